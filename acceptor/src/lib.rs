@@ -0,0 +1,175 @@
+// The listener side of a server, the part most examples start with and
+// write the same way: accepting a socket, putting the connection built
+// around it in the slab, registering it under the token it got, and
+// dropping it when the slab is full. The `Acceptor` owns the listener and
+// does all of it:
+//
+//     let acceptor = Acceptor::bind(&addr, SERVER, Connection::new as Factory<Connection>)?;
+//     acceptor.register(&mut event_loop)?;
+//
+//     // In `Handler::ready`
+//     SERVER => {
+//         let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+//
+//         if let Err(e) = res {
+//             println!("encountered error while accepting connection; err={:?}", e);
+//             event_loop.shutdown();
+//         }
+//     }
+//
+// The connections are built by a `ConnectionFactory`, which any function
+// or closure taking the socket and the token is, and say which socket to
+// register, and for what, by implementing `Connection`.
+//
+// The listener is registered as edge triggered, and every connection
+// waiting is accepted on each event: with a backlog of clients connecting
+// at once, one accept per event is a round trip through the event loop
+// for each of them. Connections are registered edge triggered and
+// oneshot, like everywhere else, and reregistered by the server from then
+// on.
+
+extern crate mio;
+
+use mio::{EventLoop, EventSet, Handler, PollOpt, Token};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::io;
+use std::net::SocketAddr;
+
+// What the acceptor needs to know of a connection, to register it
+pub trait Connection {
+    fn socket(&self) -> &TcpStream;
+
+    // What the connection is first registered for
+    fn interest(&self) -> EventSet {
+        EventSet::readable()
+    }
+}
+
+pub trait ConnectionFactory {
+    type Connection: Connection;
+
+    // Builds the connection for a socket just accepted, and the token it
+    // is going to be registered under
+    fn create(&mut self, socket: TcpStream, token: Token) -> Self::Connection;
+}
+
+impl<F, C> ConnectionFactory for F
+    where F: FnMut(TcpStream, Token) -> C,
+          C: Connection,
+{
+    type Connection = C;
+
+    fn create(&mut self, socket: TcpStream, token: Token) -> C {
+        self(socket, token)
+    }
+}
+
+// A connection's `new`, as a factory type to name in a struct field
+pub type Factory<C> = fn(TcpStream, Token) -> C;
+
+pub struct Acceptor<F> {
+    listener: TcpListener,
+    token: Token,
+    factory: F,
+    // Socket options set on every connection accepted
+    nodelay: bool,
+    keepalive: Option<u32>,
+}
+
+impl<F: ConnectionFactory> Acceptor<F> {
+    // An acceptor for a listener, to be registered under `token`
+    pub fn new(listener: TcpListener, token: Token, factory: F) -> Acceptor<F> {
+        Acceptor {
+            listener: listener,
+            token: token,
+            factory: factory,
+            nodelay: false,
+            keepalive: None,
+        }
+    }
+
+    pub fn bind(addr: &SocketAddr, token: Token, factory: F) -> io::Result<Acceptor<F>> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(Acceptor::new(listener, token, factory))
+    }
+
+    // Disables Nagle's algorithm on the connections accepted from now on
+    pub fn set_nodelay(&mut self, nodelay: bool) {
+        self.nodelay = nodelay;
+    }
+
+    // Enables TCP keepalive on the connections accepted from now on,
+    // probing after `seconds` idle
+    pub fn set_keepalive(&mut self, seconds: Option<u32>) {
+        self.keepalive = seconds;
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn register<H: Handler>(&self, event_loop: &mut EventLoop<H>) -> io::Result<()> {
+        event_loop.register_opt(&self.listener, self.token, EventSet::readable(), PollOpt::edge())
+    }
+
+    // Accepts every connection waiting, and registers them. Returns the
+    // tokens of the new connections, for servers with something to do for
+    // them right away. A client that doesn't fit in the slab is dropped.
+    //
+    // An error accepting stops the draining, and is returned along with
+    // the tokens of the connections accepted before it, which are
+    // registered all the same.
+    pub fn accept<H: Handler>(&mut self, event_loop: &mut EventLoop<H>, connections: &mut Slab<F::Connection>) -> (Vec<Token>, io::Result<()>) {
+        let mut accepted = vec![];
+
+        // The listener is registered as edge triggered, drain it
+        loop {
+            let socket = match self.listener.accept() {
+                Ok(Some(socket)) => socket,
+                Ok(None) => return (accepted, Ok(())),
+                Err(e) => return (accepted, Err(e)),
+            };
+
+            if let Err(e) = self.configure(&socket) {
+                println!("failed to set socket options, dropping client; err={:?}", e);
+                continue;
+            }
+
+            let factory = &mut self.factory;
+
+            let token = match connections.insert_with(|token| factory.create(socket, token)) {
+                Some(token) => token,
+                None => {
+                    println!("connection limit reached, dropping client");
+                    continue;
+                }
+            };
+
+            let res = {
+                let conn = &connections[token];
+                event_loop.register_opt(conn.socket(), token, conn.interest(), PollOpt::edge() | PollOpt::oneshot())
+            };
+
+            if let Err(e) = res {
+                println!("failed to register connection, dropping client; err={:?}", e);
+                connections.remove(token);
+                continue;
+            }
+
+            accepted.push(token);
+        }
+    }
+
+    fn configure(&self, socket: &TcpStream) -> io::Result<()> {
+        if self.nodelay {
+            socket.set_nodelay(true)?;
+        }
+
+        if self.keepalive.is_some() {
+            socket.set_keepalive(self.keepalive)?;
+        }
+
+        Ok(())
+    }
+}
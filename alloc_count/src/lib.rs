@@ -0,0 +1,100 @@
+// A global allocator counting the allocations it makes, and the bytes,
+// then handing them to the system allocator. A server installs it with:
+//
+//     #[global_allocator]
+//     static ALLOCATOR: alloc_count::Counting = alloc_count::Counting;
+//
+// and takes a `stats()` before and after whatever it wants to measure:
+// what happened in between is the difference. The counters are shared by
+// all threads, they are only meaningful for a single threaded event loop
+// when nothing else runs meanwhile.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ops::Sub;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static DEALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static REALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+static FREED: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        FREED.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    // A buffer growing is counted as a reallocation, its new size as
+    // allocated and its old one as freed
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        REALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(new_size as u64, Ordering::Relaxed);
+        FREED.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+// The counts since the start, or between two `stats()` once subtracted.
+// All zero if `Counting` isn't the global allocator.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Stats {
+    pub allocations: u64,
+    pub deallocations: u64,
+    pub reallocations: u64,
+    // Bytes
+    pub allocated: u64,
+    pub freed: u64,
+}
+
+impl Stats {
+    // Allocations and reallocations, what an allocation free steady state
+    // has none of
+    pub fn calls(&self) -> u64 {
+        self.allocations + self.reallocations
+    }
+
+    // Bytes allocated and not freed yet. Only meaningful since the start.
+    pub fn live(&self) -> u64 {
+        self.allocated.saturating_sub(self.freed)
+    }
+}
+
+impl Sub for Stats {
+    type Output = Stats;
+
+    fn sub(self, earlier: Stats) -> Stats {
+        Stats {
+            allocations: self.allocations - earlier.allocations,
+            deallocations: self.deallocations - earlier.deallocations,
+            reallocations: self.reallocations - earlier.reallocations,
+            allocated: self.allocated - earlier.allocated,
+            freed: self.freed - earlier.freed,
+        }
+    }
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+        reallocations: REALLOCATIONS.load(Ordering::Relaxed),
+        allocated: ALLOCATED.load(Ordering::Relaxed),
+        freed: FREED.load(Ordering::Relaxed),
+    }
+}
@@ -0,0 +1,60 @@
+// A token bucket per client address. Every request takes a token from the
+// client's bucket, and is refused when there is none left. The buckets are
+// refilled together, a token at a time, by a single timer, so a client can
+// make a burst of requests up to the bucket's size, and then keep going at
+// the rate the buckets are refilled at.
+//
+// A full bucket is the same as no bucket, it is dropped. The table only
+// holds the clients that made requests recently, however many there were
+// before.
+//
+// This does no I/O.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+pub struct Limiter {
+    buckets: HashMap<IpAddr, u32>,
+    burst: u32,
+}
+
+impl Limiter {
+    pub fn new(burst: u32) -> Limiter {
+        Limiter {
+            buckets: HashMap::new(),
+            burst: burst,
+        }
+    }
+
+    // Takes a token from the client's bucket. Returns false if it is empty.
+    pub fn take(&mut self, ip: IpAddr) -> bool {
+        let tokens = self.buckets.entry(ip).or_insert(self.burst);
+
+        if *tokens == 0 {
+            return false;
+        }
+
+        *tokens -= 1;
+        true
+    }
+
+    // The tokens left in the client's bucket
+    pub fn remaining(&self, ip: IpAddr) -> u32 {
+        self.buckets.get(&ip).cloned().unwrap_or(self.burst)
+    }
+
+    // Puts a token back in every bucket, dropping the ones that are full
+    pub fn refill(&mut self) {
+        let burst = self.burst;
+
+        self.buckets.retain(|_, tokens| {
+            *tokens += 1;
+            *tokens < burst
+        });
+    }
+
+    // The number of clients with requests counted against them
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+}
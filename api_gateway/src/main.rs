@@ -0,0 +1,525 @@
+extern crate mio;
+
+mod limiter;
+
+use limiter::Limiter;
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::{IpAddr, SocketAddr};
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Tokens `1..=MAX_CONNECTIONS` are used for client connections, and the
+// backend connection of a client is its token plus `MAX_CONNECTIONS`.
+const MAX_CONNECTIONS: usize = 1_024;
+
+// A request head larger than this is refused
+const MAX_HEAD: usize = 8 * 1_024;
+
+// Reading from one side stops while this much is waiting to be written to
+// the other
+const MAX_BUFFERED: usize = 64 * 1_024;
+
+// Every client may make a burst of this many requests, then 10 a second
+const BURST: u32 = 20;
+const REFILL_MS: u64 = 100;
+
+const STATS_MS: u64 = 10_000;
+
+// Headers about the connection between the client and the gateway, which
+// aren't passed on to the backend
+const HOP_BY_HOP: &[&str] = &["connection", "keep-alive", "proxy-connection", "upgrade", "te"];
+
+#[derive(Clone, Copy)]
+enum Timer {
+    Refill,
+    Stats,
+}
+
+// Proxies HTTP requests to a single backend, and refuses those of clients
+// making too many with a 429. There is one request per connection: the
+// gateway asks the backend to close the connection once it has answered,
+// and closes the client's connection once it has passed the answer on.
+struct Gateway {
+    server: TcpListener,
+    backend: SocketAddr,
+    proxies: Slab<Proxy>,
+    limiter: Limiter,
+    forwarded: u64,
+    limited: u64,
+}
+
+impl Gateway {
+    fn new(server: TcpListener, backend: SocketAddr) -> Gateway {
+        // Token `0` is reserved for the server socket
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS);
+
+        Gateway {
+            server: server,
+            backend: backend,
+            proxies: slab,
+            limiter: Limiter::new(BURST),
+            forwarded: 0,
+            limited: 0,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Gateway>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        let ip = match socket.peer_addr() {
+            Ok(addr) => addr.ip(),
+            Err(_) => return,
+        };
+
+        let token = match self.proxies.insert_with(|_| Proxy::new(socket, ip)) {
+            Some(token) => token,
+            None => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        event_loop.register_opt(&self.proxies[token].client, token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn client_ready(&mut self, event_loop: &mut mio::EventLoop<Gateway>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            self.proxies[token].read_client();
+        }
+
+        if self.proxies[token].state == State::Head {
+            self.route(event_loop, token);
+        }
+
+        if events.is_writable() {
+            self.proxies[token].write_client();
+        }
+
+        self.update(event_loop, token);
+    }
+
+    fn backend_ready(&mut self, event_loop: &mut mio::EventLoop<Gateway>, token: mio::Token, events: mio::EventSet) {
+        {
+            let proxy = &mut self.proxies[token];
+
+            if !proxy.connected {
+                if events.is_error() || events.is_hup() && !events.is_readable() {
+                    println!("failed to connect to the backend; err={:?}", proxy.backend.as_ref().unwrap().take_socket_error());
+                    proxy.fail();
+                } else if events.is_writable() {
+                    proxy.connected = true;
+                }
+            }
+
+            if events.is_readable() {
+                proxy.read_backend();
+            }
+
+            if proxy.connected && events.is_writable() {
+                proxy.write_backend();
+            }
+        }
+
+        self.update(event_loop, token);
+    }
+
+    // Once the request head is in, either refuses the request, or connects
+    // to the backend and passes it on
+    fn route(&mut self, event_loop: &mut mio::EventLoop<Gateway>, token: mio::Token) {
+        let head = match self.proxies[token].take_head() {
+            Ok(Some(head)) => head,
+            Ok(None) => return,
+            Err(reason) => {
+                println!("bad request; ip={}; reason={}", self.proxies[token].ip, reason);
+                return self.proxies[token].respond(400, "Bad Request", "");
+            }
+        };
+
+        let ip = self.proxies[token].ip;
+
+        if !self.limiter.take(ip) {
+            println!("rate limited; ip={}; request={}", ip, head.request_line);
+            self.limited += 1;
+
+            // A token is back within a refill
+            return self.proxies[token].respond(429, "Too Many Requests", "Retry-After: 1\r\n");
+        }
+
+        let backend = match TcpStream::connect(&self.backend) {
+            Ok(backend) => backend,
+            Err(e) => {
+                println!("failed to connect to the backend; err={:?}", e);
+                return self.proxies[token].respond(502, "Bad Gateway", "");
+            }
+        };
+
+        println!("forwarding; ip={}; request={}; remaining={}", ip, head.request_line, self.limiter.remaining(ip));
+        self.forwarded += 1;
+
+        let backend_token = mio::Token(token.as_usize() + MAX_CONNECTIONS);
+
+        // Writable once connected
+        event_loop.register_opt(&backend, backend_token, mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        self.proxies[token].forward(backend, &head);
+    }
+
+    // Reregisters both sockets of the proxy, or removes it once it is done
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Gateway>, token: mio::Token) {
+        if self.proxies[token].is_done() {
+            self.proxies.remove(token);
+            return;
+        }
+
+        let proxy = &self.proxies[token];
+
+        event_loop.reregister(&proxy.client, token, proxy.client_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        if let Some(ref backend) = proxy.backend {
+            let backend_token = mio::Token(token.as_usize() + MAX_CONNECTIONS);
+
+            event_loop.reregister(backend, backend_token, proxy.backend_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+}
+
+impl mio::Handler for Gateway {
+    type Timeout = Timer;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Gateway>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ if token.as_usize() <= MAX_CONNECTIONS => self.client_ready(event_loop, token, events),
+            _ => {
+                let token = mio::Token(token.as_usize() - MAX_CONNECTIONS);
+
+                // The backend's events may still come in after it is gone,
+                // or even the proxy
+                if self.proxies.get(token).map(|proxy| proxy.backend.is_some()).unwrap_or(false) {
+                    self.backend_ready(event_loop, token, events);
+                }
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Gateway>, timer: Timer) {
+        match timer {
+            Timer::Refill => {
+                self.limiter.refill();
+                event_loop.timeout_ms(Timer::Refill, REFILL_MS).unwrap();
+            }
+            Timer::Stats => {
+                println!("stats; forwarded={}; limited={}; clients limited={}", self.forwarded, self.limited, self.limiter.len());
+                event_loop.timeout_ms(Timer::Stats, STATS_MS).unwrap();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    // Reading the request head
+    Head,
+    // Passing the request on, and the response back
+    Forwarding,
+    // Writing a response of the gateway's own, then closing
+    Responding,
+}
+
+struct Head {
+    request_line: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Head {
+    // The head as sent to the backend, asking it to close the connection
+    // once it has answered
+    fn encode(&self, ip: IpAddr) -> Vec<u8> {
+        let mut out = format!("{}\r\n", self.request_line);
+
+        for (name, value) in &self.headers {
+            if !HOP_BY_HOP.contains(&&name.to_lowercase()[..]) {
+                out.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+
+        out.push_str(&format!("X-Forwarded-For: {}\r\nConnection: close\r\n\r\n", ip));
+        out.into_bytes()
+    }
+}
+
+struct Proxy {
+    client: TcpStream,
+    ip: IpAddr,
+    state: State,
+    backend: Option<TcpStream>,
+    connected: bool,
+    // Read from the client, the request head until it is complete, then
+    // the body to be written to the backend
+    to_backend: Vec<u8>,
+    // Read from the backend, or the gateway's own response
+    to_client: Vec<u8>,
+    // Whether the backend started answering
+    answered: bool,
+    client_eof: bool,
+    backend_eof: bool,
+    closed: bool,
+}
+
+impl Proxy {
+    fn new(client: TcpStream, ip: IpAddr) -> Proxy {
+        Proxy {
+            client: client,
+            ip: ip,
+            state: State::Head,
+            backend: None,
+            connected: false,
+            to_backend: vec![],
+            to_client: vec![],
+            answered: false,
+            client_eof: false,
+            backend_eof: false,
+            closed: false,
+        }
+    }
+
+    // Parses the request head, once it is all in
+    fn take_head(&mut self) -> Result<Option<Head>, &'static str> {
+        let end = match self.to_backend.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => end,
+            None if self.to_backend.len() > MAX_HEAD => return Err("head too large"),
+            None if self.client_eof => return Err("connection closed"),
+            None => return Ok(None),
+        };
+
+        let head: Vec<u8> = self.to_backend.drain(..end + 4).collect();
+        let head = String::from_utf8(head).map_err(|_| "head isn't UTF-8")?;
+
+        let mut lines = head[..end].split("\r\n");
+        let request_line = lines.next().unwrap().to_string();
+
+        if request_line.split(' ').count() != 3 || !request_line.ends_with("HTTP/1.1") && !request_line.ends_with("HTTP/1.0") {
+            return Err("invalid request line");
+        }
+
+        let mut headers = vec![];
+
+        for line in lines {
+            let mut parts = line.splitn(2, ':');
+
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) if !name.is_empty() => {
+                    headers.push((name.to_string(), value.trim().to_string()));
+                }
+                _ => return Err("invalid header"),
+            }
+        }
+
+        Ok(Some(Head {
+            request_line: request_line,
+            headers: headers,
+        }))
+    }
+
+    fn forward(&mut self, backend: TcpStream, head: &Head) {
+        // What was read past the head is the start of the body
+        let body = std::mem::replace(&mut self.to_backend, head.encode(self.ip));
+        self.to_backend.extend(body);
+
+        self.backend = Some(backend);
+        self.state = State::Forwarding;
+    }
+
+    fn respond(&mut self, status: u16, reason: &str, headers: &str) {
+        let body = format!("{}\n", reason);
+        let response = format!("HTTP/1.1 {} {}\r\n{}Content-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                               status, reason, headers, body.len(), body);
+
+        self.to_client = response.into_bytes();
+        self.to_backend.clear();
+        self.state = State::Responding;
+    }
+
+    // The backend is gone before it answered. If it did answer in part,
+    // there is nothing better to do than to cut the response short.
+    fn fail(&mut self) {
+        self.backend = None;
+
+        if !self.answered {
+            self.respond(502, "Bad Gateway", "");
+        } else {
+            self.backend_eof = true;
+        }
+    }
+
+    fn read_client(&mut self) {
+        let mut chunk = [0; 4_096];
+
+        // The socket is registered as edge triggered, drain it. Or at least
+        // until enough is waiting for the backend.
+        while self.to_backend.len() < MAX_BUFFERED {
+            match self.client.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.client_eof = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    // Past the request, there is nothing to read
+                    if self.state == State::Head || self.state == State::Forwarding {
+                        self.to_backend.extend(&chunk[..n]);
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read from the client; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn read_backend(&mut self) {
+        let mut chunk = [0; 4_096];
+
+        while self.to_client.len() < MAX_BUFFERED {
+            let backend = match self.backend {
+                Some(ref mut backend) => backend,
+                None => return,
+            };
+
+            match backend.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.backend_eof = true;
+                    self.backend = None;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    self.to_client.extend(&chunk[..n]);
+                    self.answered = true;
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read from the backend; err={:?}", e);
+                    self.fail();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write_client(&mut self) {
+        while !self.to_client.is_empty() {
+            match self.client.try_write(&self.to_client) {
+                Ok(Some(n)) => {
+                    self.to_client.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write to the client; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write_backend(&mut self) {
+        while !self.to_backend.is_empty() {
+            let result = match self.backend {
+                Some(ref mut backend) => backend.try_write(&self.to_backend),
+                None => return,
+            };
+
+            match result {
+                Ok(Some(n)) => {
+                    self.to_backend.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write to the backend; err={:?}", e);
+                    self.fail();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        if self.closed {
+            return true;
+        }
+
+        match self.state {
+            // A client leaving before sending the whole head
+            State::Head => self.client_eof,
+            State::Forwarding => self.backend_eof && self.to_client.is_empty(),
+            State::Responding => self.to_client.is_empty(),
+        }
+    }
+
+    fn client_interest(&self) -> mio::EventSet {
+        let mut interest = mio::EventSet::none();
+
+        if !self.client_eof && self.state != State::Responding && self.to_backend.len() < MAX_BUFFERED {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.to_client.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        interest
+    }
+
+    fn backend_interest(&self) -> mio::EventSet {
+        let mut interest = mio::EventSet::none();
+
+        if self.to_client.len() < MAX_BUFFERED {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.connected || !self.to_backend.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        interest
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:8000".to_string()).parse().unwrap();
+    let backend: SocketAddr = args.next().unwrap_or("127.0.0.1:8080".to_string()).parse().unwrap();
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+    event_loop.timeout_ms(Timer::Refill, REFILL_MS).unwrap();
+    event_loop.timeout_ms(Timer::Stats, STATS_MS).unwrap();
+
+    println!("running API gateway; addr={:?}; backend={:?}; burst={}; rate={}/s", address, backend, BURST, 1_000 / REFILL_MS);
+
+    let mut gateway = Gateway::new(server, backend);
+    event_loop.run(&mut gateway).unwrap();
+}
@@ -0,0 +1,358 @@
+extern crate mio;
+extern crate bytes;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use std::{env, process};
+
+// The receiver's listening socket, or the sender's connection
+const SERVER: mio::Token = mio::Token(0);
+const CLIENT: mio::Token = mio::Token(0);
+
+// The sender writes the same block over and over, and the receiver reads
+// into a buffer of the same size. Neither touches the bytes themselves.
+const BLOCK_SIZE: usize = 128 * 1_024;
+
+// How often the rates are reported
+const REPORT_MS: u64 = 1_000;
+
+// How long the sender runs by default
+const DEFAULT_SECS: u64 = 10;
+
+/*
+ *
+ * ===== Receiver =====
+ *
+ */
+
+struct Receiver {
+    server: TcpListener,
+    connections: Slab<Connection>,
+    buf: Vec<u8>,
+}
+
+struct Connection {
+    socket: TcpStream,
+    // Labels the connection's reports
+    token: mio::Token,
+    meter: Meter,
+}
+
+impl Receiver {
+    fn new(server: TcpListener) -> Receiver {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 128);
+
+        Receiver {
+            server: server,
+            connections: slab,
+            buf: vec![0; BLOCK_SIZE],
+        }
+    }
+
+    // Counts what has arrived, returning false once the sender is done
+    fn read(&mut self, token: mio::Token) -> bool {
+        let conn = &mut self.connections[token];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match conn.socket.try_read(&mut self.buf) {
+                Ok(Some(0)) => return false,
+                Ok(Some(n)) => conn.meter.add(n),
+                Ok(None) => return true,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+impl mio::Handler for Receiver {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Receiver>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                match self.server.accept() {
+                    Ok(Some(socket)) => {
+                        let conn = |token| Connection {
+                            socket: socket,
+                            token: token,
+                            meter: Meter::new(),
+                        };
+
+                        let token = match self.connections.insert_with(conn) {
+                            Some(token) => token,
+                            None => {
+                                println!("connection limit reached, dropping client");
+                                return;
+                            }
+                        };
+
+                        println!("[{:>3}] sender connected; addr={:?}", token.as_usize(), self.connections[token].socket.peer_addr());
+
+                        // The socket stays registered for as long as it is
+                        // open, without oneshot there is nothing to
+                        // re-register after each read.
+                        event_loop.register_opt(
+                            &self.connections[token].socket,
+                            token,
+                            mio::EventSet::readable(),
+                            mio::PollOpt::edge()).unwrap();
+                    }
+                    Ok(None) => {
+                        println!("the server socket wasn't actually ready");
+                    }
+                    Err(e) => {
+                        println!("encountered error while accepting connection; err={:?}", e);
+                        event_loop.shutdown();
+                    }
+                }
+            }
+            _ => {
+                if !self.read(token) {
+                    let mut conn = self.connections.remove(token).unwrap();
+                    conn.meter.report(token.as_usize());
+                    conn.meter.total(token.as_usize());
+                }
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Receiver>, _: ()) {
+        for conn in self.connections.iter_mut() {
+            conn.meter.report(conn.token.as_usize());
+        }
+
+        event_loop.timeout_ms((), REPORT_MS).unwrap();
+    }
+}
+
+/*
+ *
+ * ===== Sender =====
+ *
+ */
+
+enum Timeout {
+    Report,
+    Finish,
+}
+
+struct Sender {
+    socket: TcpStream,
+    block: Vec<u8>,
+    secs: u64,
+    // Started once connected
+    meter: Option<Meter>,
+}
+
+impl Sender {
+    fn new(socket: TcpStream, secs: u64) -> Sender {
+        Sender {
+            socket: socket,
+            block: vec![0; BLOCK_SIZE],
+            secs: secs,
+            meter: None,
+        }
+    }
+
+    // Keeps the socket's send buffer full. Returns false if the connection
+    // failed.
+    fn write(&mut self) -> bool {
+        let meter = self.meter.as_mut().unwrap();
+
+        // The socket is registered as edge triggered, write until it would
+        // block: the next event only comes once there is room again.
+        loop {
+            match self.socket.try_write(&self.block) {
+                Ok(Some(n)) => meter.add(n),
+                Ok(None) => return true,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+impl mio::Handler for Sender {
+    type Timeout = Timeout;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Sender>, token: mio::Token, _: mio::EventSet) {
+        assert_eq!(token, CLIENT);
+
+        if self.meter.is_none() {
+            // The connect is non-blocking, the socket becoming writable
+            // means it completed, successfully or not.
+            if let Err(e) = self.socket.take_socket_error() {
+                println!("failed to connect; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+
+            println!("connected; addr={:?}; secs={}", self.socket.peer_addr(), self.secs);
+
+            self.meter = Some(Meter::new());
+            event_loop.timeout_ms(Timeout::Report, REPORT_MS).unwrap();
+            event_loop.timeout_ms(Timeout::Finish, self.secs * 1_000).unwrap();
+        }
+
+        if !self.write() {
+            event_loop.shutdown();
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Sender>, timeout: Timeout) {
+        let meter = self.meter.as_mut().unwrap();
+
+        match timeout {
+            Timeout::Report => {
+                meter.report(0);
+                event_loop.timeout_ms(Timeout::Report, REPORT_MS).unwrap();
+            }
+            Timeout::Finish => {
+                // The last report may have only just been printed
+                if meter.interval > 0 {
+                    meter.report(0);
+                }
+
+                meter.total(0);
+
+                // The totals count what was handed to the kernel. Whatever
+                // is still in the send buffer is flushed as the socket is
+                // closed, the receiver's totals are the accurate ones.
+                event_loop.shutdown();
+            }
+        }
+    }
+}
+
+/*
+ *
+ * ===== Reporting =====
+ *
+ */
+
+// Counts the bytes moved over a connection, in total and since the last
+// report.
+struct Meter {
+    started: Instant,
+    total: u64,
+    interval_started: Instant,
+    interval: u64,
+}
+
+impl Meter {
+    fn new() -> Meter {
+        let now = Instant::now();
+
+        Meter {
+            started: now,
+            total: 0,
+            interval_started: now,
+            interval: 0,
+        }
+    }
+
+    fn add(&mut self, n: usize) {
+        self.total += n as u64;
+        self.interval += n as u64;
+    }
+
+    // Prints the rate since the last report, and starts a new interval.
+    // Timeouts don't fire exactly on time, the interval is measured rather
+    // than assumed to be a second.
+    fn report(&mut self, id: usize) {
+        let now = Instant::now();
+        let from = secs(self.interval_started - self.started);
+        let to = secs(now - self.started);
+
+        if to > from {
+            println!("[{:>3}] {:>5.1}-{:>5.1} sec  {}  {}", id, from, to, size(self.interval), rate(self.interval, to - from));
+        }
+
+        self.interval_started = now;
+        self.interval = 0;
+    }
+
+    fn total(&self, id: usize) {
+        let elapsed = secs(self.started.elapsed());
+
+        println!("[{:>3}] {:>5.1}-{:>5.1} sec  {}  {}  total", id, 0.0, elapsed, size(self.total), rate(self.total, elapsed));
+    }
+}
+
+fn secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+fn size(bytes: u64) -> String {
+    let bytes = bytes as f64;
+
+    if bytes >= 1e9 {
+        format!("{:>6.2} GBytes", bytes / 1e9)
+    } else {
+        format!("{:>6.2} MBytes", bytes / 1e6)
+    }
+}
+
+fn rate(bytes: u64, secs: f64) -> String {
+    let bits = bytes as f64 * 8.0 / secs;
+
+    if bits >= 1e9 {
+        format!("{:>6.2} Gbits/sec", bits / 1e9)
+    } else {
+        format!("{:>6.2} Mbits/sec", bits / 1e6)
+    }
+}
+
+fn usage() -> ! {
+    println!("usage: bandwidth receive [addr]");
+    println!("       bandwidth send [addr] [secs]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    match args.next().as_ref().map(|mode| &mode[..]) {
+        Some("receive") => {
+            let address: SocketAddr = args.next().unwrap_or("0.0.0.0:5201".to_string()).parse().unwrap();
+            let server = TcpListener::bind(&address).unwrap();
+
+            let mut event_loop = mio::EventLoop::new().unwrap();
+            event_loop.register(&server, SERVER).unwrap();
+            event_loop.timeout_ms((), REPORT_MS).unwrap();
+
+            println!("receiving; addr={:?}", address);
+            event_loop.run(&mut Receiver::new(server)).unwrap();
+        }
+        Some("send") => {
+            let address: SocketAddr = args.next().unwrap_or("127.0.0.1:5201".to_string()).parse().unwrap();
+            let secs: u64 = args.next().map(|s| s.parse().unwrap()).unwrap_or(DEFAULT_SECS);
+
+            let socket = TcpStream::connect(&address).unwrap();
+
+            let mut event_loop = mio::EventLoop::new().unwrap();
+
+            // The socket becomes writable once connected, and again each
+            // time the kernel has made room in the send buffer.
+            event_loop.register_opt(&socket, CLIENT, mio::EventSet::writable(), mio::PollOpt::edge()).unwrap();
+
+            event_loop.run(&mut Sender::new(socket, secs)).unwrap();
+        }
+        _ => usage(),
+    }
+}
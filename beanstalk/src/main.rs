@@ -0,0 +1,762 @@
+extern crate acceptor;
+extern crate mio;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 1_024;
+
+// Limits from beanstalkd's defaults
+const MAX_LINE: usize = 224;
+const MAX_JOB: usize = 65_535;
+const MAX_TUBE_NAME: usize = 200;
+
+const DEFAULT_TUBE: &str = "default";
+
+// A subset of the beanstalkd protocol: `put`, `reserve` and
+// `reserve-with-timeout`, `delete`, `release`, `touch`, `peek`, `use`,
+// `watch`, `ignore` and `quit`. Jobs are kept in memory.
+struct Beanstalk {
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+    jobs: HashMap<u64, Job>,
+    tubes: HashMap<String, Tube>,
+    next_id: u64,
+    // Tells the timer of a `reserve-with-timeout` from the timers of the
+    // connection's earlier ones
+    next_wait: u64,
+    // Connections that had output queued, were woken up, or were closed,
+    // while handling the current event. They are reregistered (or removed)
+    // once it is done.
+    dirty: Vec<mio::Token>,
+}
+
+#[derive(Default)]
+struct Tube {
+    // Jobs ready to be reserved, by priority then age. The most urgent
+    // priority is 0.
+    ready: BTreeSet<(u32, u64)>,
+    // Connections blocked in `reserve`, watching this tube. A connection is
+    // only removed from here when a job is looked for it, so it may have
+    // been given a job from another tube, or have gone away, since.
+    waiting: VecDeque<mio::Token>,
+}
+
+struct Job {
+    tube: String,
+    pri: u32,
+    // Time to run: how long a worker may keep the job reserved
+    ttr: u64,
+    data: Vec<u8>,
+    state: JobState,
+    // How many times it has been reserved. Tells the TTR timer of the
+    // current reservation from the timers of earlier ones.
+    reserves: u32,
+    // The TTR timer while reserved, the delay while delayed
+    timer: Option<mio::Timeout>,
+}
+
+#[derive(PartialEq, Eq)]
+enum JobState {
+    Ready,
+    // Waiting out the delay it was put or released with
+    Delayed,
+    Reserved(mio::Token),
+}
+
+#[derive(Clone, Copy)]
+enum Timer {
+    // The job's time to run is over
+    Ttr(u64, u32),
+    // The job's delay is over
+    Delay(u64),
+    // A `reserve-with-timeout` has waited long enough
+    Reserve(mio::Token, u64),
+}
+
+impl Beanstalk {
+    fn new(acceptor: Acceptor<Factory<Connection>>) -> Beanstalk {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS);
+
+        Beanstalk {
+            acceptor: acceptor,
+            connections: slab,
+            jobs: HashMap::new(),
+            tubes: HashMap::new(),
+            next_id: 1,
+            next_wait: 0,
+            dirty: vec![],
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>) {
+        let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+        if let Err(e) = res {
+            println!("encountered error while accepting connection; err={:?}", e);
+            event_loop.shutdown();
+        }
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            self.connections[token].read();
+            self.process(event_loop, token);
+        }
+
+        if events.is_writable() {
+            self.connections[token].write();
+        }
+
+        self.dirty.push(token);
+    }
+
+    // Runs the connection's state machine over its buffered input, until it
+    // needs more, or is blocked in `reserve`
+    fn process(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, token: mio::Token) {
+        loop {
+            let state = {
+                let conn = &mut self.connections[token];
+
+                if conn.quit || conn.waiting.is_some() {
+                    return;
+                }
+
+                std::mem::replace(&mut conn.state, State::Command)
+            };
+
+            let next = match state {
+                State::Command => {
+                    let line = {
+                        let conn = &mut self.connections[token];
+
+                        match conn.buf.iter().position(|b| *b == b'\n') {
+                            Some(pos) => conn.buf.drain(..pos + 1).collect::<Vec<u8>>(),
+                            None if conn.buf.len() > MAX_LINE => {
+                                conn.quit = true;
+                                return self.reply(token, "BAD_FORMAT");
+                            }
+                            None => return,
+                        }
+                    };
+
+                    self.command(event_loop, token, &String::from_utf8_lossy(&line))
+                }
+                State::Data(put) => {
+                    let data = {
+                        let conn = &mut self.connections[token];
+
+                        if conn.buf.len() < put.len + 2 {
+                            conn.state = State::Data(put);
+                            return;
+                        }
+
+                        if &conn.buf[put.len..put.len + 2] != b"\r\n" {
+                            // The client and server no longer agree on where
+                            // commands start, there is no recovering from this.
+                            conn.quit = true;
+                            return self.reply(token, "EXPECTED_CRLF");
+                        }
+
+                        conn.buf.drain(..put.len + 2).take(put.len).collect()
+                    };
+
+                    self.put(event_loop, token, put, data);
+                    State::Command
+                }
+                State::Skip(left) => {
+                    let conn = &mut self.connections[token];
+
+                    let n = left.min(conn.buf.len());
+                    conn.buf.drain(..n);
+
+                    if n < left {
+                        conn.state = State::Skip(left - n);
+                        return;
+                    }
+
+                    State::Command
+                }
+            };
+
+            self.connections[token].state = next;
+        }
+    }
+
+    // Handles a command line and returns the state to continue in
+    fn command(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, token: mio::Token, line: &str) -> State {
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match (words.first().cloned().unwrap_or(""), &words[words.len().min(1)..]) {
+            ("put", &[pri, delay, ttr, len]) => {
+                let (pri, delay, ttr, len) = match (pri.parse(), delay.parse(), ttr.parse::<u64>(), len.parse()) {
+                    (Ok(pri), Ok(delay), Ok(ttr), Ok(len)) => (pri, delay, ttr, len),
+                    _ => {
+                        self.reply(token, "BAD_FORMAT");
+                        return State::Command;
+                    }
+                };
+
+                if len > MAX_JOB {
+                    self.reply(token, "JOB_TOO_BIG");
+                    return State::Skip(len + 2);
+                }
+
+                return State::Data(Put {
+                    pri: pri,
+                    delay: delay,
+                    // beanstalkd raises a TTR of 0 to 1 second too
+                    ttr: ttr.max(1),
+                    len: len,
+                });
+            }
+            ("reserve", &[]) => self.reserve(event_loop, token, None),
+            ("reserve-with-timeout", &[secs]) => {
+                match secs.parse() {
+                    Ok(secs) => self.reserve(event_loop, token, Some(secs)),
+                    Err(_) => self.reply(token, "BAD_FORMAT"),
+                }
+            }
+            ("delete", &[id]) => self.with_id(token, id, |server, id| server.delete(event_loop, token, id)),
+            ("release", &[id, pri, delay]) => {
+                match (pri.parse(), delay.parse()) {
+                    (Ok(pri), Ok(delay)) => self.with_id(token, id, |server, id| server.release(event_loop, token, id, pri, delay)),
+                    _ => self.reply(token, "BAD_FORMAT"),
+                }
+            }
+            ("touch", &[id]) => self.with_id(token, id, |server, id| server.touch(event_loop, token, id)),
+            ("peek", &[id]) => self.with_id(token, id, |server, id| server.peek(token, id)),
+            ("use", &[tube]) if is_valid_tube(tube) => {
+                self.tubes.entry(tube.to_string()).or_default();
+                self.connections[token].using = tube.to_string();
+                self.reply(token, &format!("USING {}", tube));
+            }
+            ("watch", &[tube]) if is_valid_tube(tube) => {
+                self.tubes.entry(tube.to_string()).or_default();
+
+                let count = {
+                    let watching = &mut self.connections[token].watching;
+
+                    if !watching.iter().any(|watched| watched == tube) {
+                        watching.push(tube.to_string());
+                    }
+
+                    watching.len()
+                };
+
+                self.reply(token, &format!("WATCHING {}", count));
+            }
+            ("ignore", &[tube]) if is_valid_tube(tube) => {
+                let count = {
+                    let watching = &mut self.connections[token].watching;
+
+                    // A connection always watches at least one tube
+                    if watching.len() == 1 && watching[0] == tube {
+                        None
+                    } else {
+                        watching.retain(|watched| watched != tube);
+                        Some(watching.len())
+                    }
+                };
+
+                match count {
+                    Some(count) => self.reply(token, &format!("WATCHING {}", count)),
+                    None => self.reply(token, "NOT_IGNORED"),
+                }
+            }
+            ("quit", &[]) => self.connections[token].quit = true,
+            ("put", _) | ("reserve", _) | ("reserve-with-timeout", _) | ("delete", _) | ("release", _) |
+            ("touch", _) | ("peek", _) | ("use", _) | ("watch", _) | ("ignore", _) | ("quit", _) => {
+                self.reply(token, "BAD_FORMAT");
+            }
+            _ => self.reply(token, "UNKNOWN_COMMAND"),
+        }
+
+        State::Command
+    }
+
+    fn with_id<F>(&mut self, token: mio::Token, id: &str, f: F) where F: FnOnce(&mut Beanstalk, u64) {
+        match id.parse() {
+            Ok(id) => f(self, id),
+            Err(_) => self.reply(token, "BAD_FORMAT"),
+        }
+    }
+
+    fn put(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, token: mio::Token, put: Put, data: Vec<u8>) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let tube = self.connections[token].using.clone();
+
+        self.jobs.insert(id, Job {
+            tube: tube,
+            pri: put.pri,
+            ttr: put.ttr,
+            data: data,
+            state: JobState::Delayed,
+            reserves: 0,
+            timer: None,
+        });
+
+        self.reply(token, &format!("INSERTED {}", id));
+        self.delay(event_loop, id, put.delay);
+    }
+
+    // Makes the job ready after `secs`, or right away for 0
+    fn delay(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, id: u64, secs: u64) {
+        if secs == 0 {
+            return self.make_ready(event_loop, id);
+        }
+
+        let job = self.jobs.get_mut(&id).unwrap();
+
+        job.state = JobState::Delayed;
+        job.timer = Some(event_loop.timeout_ms(Timer::Delay(id), secs * 1_000).unwrap());
+    }
+
+    fn make_ready(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, id: u64) {
+        let tube = {
+            let job = self.jobs.get_mut(&id).unwrap();
+
+            job.state = JobState::Ready;
+            job.timer = None;
+
+            self.tubes.entry(job.tube.clone()).or_default().ready.insert((job.pri, id));
+            job.tube.clone()
+        };
+
+        self.wake(event_loop, &tube);
+    }
+
+    // Gives the tube's ready jobs to the connections blocked waiting on it
+    fn wake(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, tube: &str) {
+        loop {
+            let token = {
+                let tube = self.tubes.get_mut(tube).unwrap();
+
+                if tube.ready.is_empty() {
+                    return;
+                }
+
+                match tube.waiting.pop_front() {
+                    Some(token) => token,
+                    None => return,
+                }
+            };
+
+            let waiting = match self.connections.get(token) {
+                Some(conn) => conn.waiting.is_some() && conn.watching.iter().any(|watched| watched == tube),
+                None => false,
+            };
+
+            if !waiting {
+                continue;
+            }
+
+            // There is at least the job in this tube, but the connection may
+            // have more urgent ones in the others it watches
+            let id = self.most_urgent(token).unwrap();
+            self.reserve_job(event_loop, token, id);
+        }
+    }
+
+    // The most urgent ready job in the tubes the connection watches
+    fn most_urgent(&self, token: mio::Token) -> Option<u64> {
+        self.connections[token].watching.iter()
+            .filter_map(|tube| self.tubes.get(tube).and_then(|tube| tube.ready.iter().next()))
+            .min()
+            .map(|&(_, id)| id)
+    }
+
+    fn reserve(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, token: mio::Token, timeout: Option<u64>) {
+        if let Some(id) = self.most_urgent(token) {
+            return self.reserve_job(event_loop, token, id);
+        }
+
+        if timeout == Some(0) {
+            return self.reply(token, "TIMED_OUT");
+        }
+
+        // Blocks until a job arrives on a watched tube: the connection
+        // reads no more commands until then.
+        let seq = self.next_wait;
+        self.next_wait += 1;
+
+        let timer = timeout.map(|secs| event_loop.timeout_ms(Timer::Reserve(token, seq), secs * 1_000).unwrap());
+
+        let conn = &mut self.connections[token];
+        conn.waiting = Some(Wait { seq: seq, timer: timer });
+
+        for tube in &conn.watching {
+            let waiting = &mut self.tubes.entry(tube.clone()).or_default().waiting;
+
+            if !waiting.contains(&token) {
+                waiting.push_back(token);
+            }
+        }
+    }
+
+    fn reserve_job(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, token: mio::Token, id: u64) {
+        {
+            let job = self.jobs.get_mut(&id).unwrap();
+
+            self.tubes.get_mut(&job.tube).unwrap().ready.remove(&(job.pri, id));
+
+            job.state = JobState::Reserved(token);
+            job.reserves += 1;
+            job.timer = Some(event_loop.timeout_ms(Timer::Ttr(id, job.reserves), job.ttr * 1_000).unwrap());
+        }
+
+        let conn = &mut self.connections[token];
+        conn.reserved.insert(id);
+
+        if let Some(wait) = conn.waiting.take() {
+            if let Some(timer) = wait.timer {
+                event_loop.clear_timeout(timer);
+            }
+        }
+
+        self.reply_job(token, "RESERVED", id);
+    }
+
+    fn delete(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, token: mio::Token, id: u64) {
+        let job = match self.jobs.get(&id) {
+            // Only the connection that reserved a job may delete it
+            Some(job) if job.state == JobState::Reserved(token) || job.state == JobState::Ready || job.state == JobState::Delayed => {
+                self.jobs.remove(&id).unwrap()
+            }
+            _ => return self.reply(token, "NOT_FOUND"),
+        };
+
+        match job.state {
+            JobState::Ready => {
+                self.tubes.get_mut(&job.tube).unwrap().ready.remove(&(job.pri, id));
+            }
+            JobState::Reserved(_) => {
+                self.connections[token].reserved.remove(&id);
+            }
+            JobState::Delayed => {}
+        }
+
+        if let Some(timer) = job.timer {
+            event_loop.clear_timeout(timer);
+        }
+
+        self.reply(token, "DELETED");
+    }
+
+    fn release(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, token: mio::Token, id: u64, pri: u32, delay: u64) {
+        if !self.unreserve(event_loop, token, id) {
+            return self.reply(token, "NOT_FOUND");
+        }
+
+        self.jobs.get_mut(&id).unwrap().pri = pri;
+
+        self.reply(token, "RELEASED");
+        self.delay(event_loop, id, delay);
+    }
+
+    // Restarts the job's time to run
+    fn touch(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, token: mio::Token, id: u64) {
+        let job = match self.jobs.get_mut(&id) {
+            Some(job) if job.state == JobState::Reserved(token) => job,
+            _ => return self.reply(token, "NOT_FOUND"),
+        };
+
+        if let Some(timer) = job.timer.take() {
+            event_loop.clear_timeout(timer);
+        }
+
+        job.timer = Some(event_loop.timeout_ms(Timer::Ttr(id, job.reserves), job.ttr * 1_000).unwrap());
+
+        self.reply(token, "TOUCHED");
+    }
+
+    fn peek(&mut self, token: mio::Token, id: u64) {
+        if !self.jobs.contains_key(&id) {
+            return self.reply(token, "NOT_FOUND");
+        }
+
+        self.reply_job(token, "FOUND", id);
+    }
+
+    // Takes back a job reserved by the connection, returns false if it
+    // doesn't have it
+    fn unreserve(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, token: mio::Token, id: u64) -> bool {
+        let job = match self.jobs.get_mut(&id) {
+            Some(job) if job.state == JobState::Reserved(token) => job,
+            _ => return false,
+        };
+
+        if let Some(timer) = job.timer.take() {
+            event_loop.clear_timeout(timer);
+        }
+
+        if let Some(conn) = self.connections.get_mut(token) {
+            conn.reserved.remove(&id);
+        }
+
+        true
+    }
+
+    fn expire(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, timer: Timer) {
+        match timer {
+            Timer::Ttr(id, reserves) => {
+                let token = match self.jobs.get(&id) {
+                    Some(&Job { state: JobState::Reserved(token), reserves: current, .. }) if current == reserves => token,
+                    _ => return,
+                };
+
+                println!("time to run exceeded, job released; id={}; token={:?}", id, token);
+
+                self.unreserve(event_loop, token, id);
+                self.make_ready(event_loop, id);
+            }
+            Timer::Delay(id) => {
+                if self.jobs.get(&id).map(|job| job.state == JobState::Delayed).unwrap_or(false) {
+                    self.make_ready(event_loop, id);
+                }
+            }
+            Timer::Reserve(token, seq) => {
+                let timed_out = match self.connections.get_mut(token) {
+                    Some(conn) if conn.waiting.as_ref().map(|wait| wait.seq) == Some(seq) => {
+                        conn.waiting = None;
+                        true
+                    }
+                    _ => false,
+                };
+
+                if timed_out {
+                    self.reply(token, "TIMED_OUT");
+                }
+            }
+        }
+    }
+
+    fn reply(&mut self, token: mio::Token, line: &str) {
+        let conn = &mut self.connections[token];
+
+        conn.out.extend(line.as_bytes());
+        conn.out.extend(b"\r\n");
+
+        self.dirty.push(token);
+    }
+
+    // `RESERVED` and `FOUND` are followed by the job's data
+    fn reply_job(&mut self, token: mio::Token, name: &str, id: u64) {
+        let data = &self.jobs[&id].data;
+        let conn = &mut self.connections[token];
+
+        conn.out.extend(format!("{} {} {}\r\n", name, id, data.len()).as_bytes());
+        conn.out.extend(data);
+        conn.out.extend(b"\r\n");
+
+        self.dirty.push(token);
+    }
+
+    // Reregisters every connection touched while handling an event, and
+    // removes the ones that are done. A connection that was blocked in
+    // `reserve` may have more commands buffered, they are handled now.
+    fn flush(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>) {
+        while let Some(token) = self.dirty.pop() {
+            if !self.connections.contains(token) {
+                continue;
+            }
+
+            if !self.connections[token].buf.is_empty() {
+                self.process(event_loop, token);
+            }
+
+            let conn = &mut self.connections[token];
+            conn.write();
+
+            if conn.closed || (conn.quit && conn.out.is_empty()) {
+                self.remove(event_loop, token);
+                continue;
+            }
+
+            event_loop.reregister(&conn.socket, token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn remove(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, token: mio::Token) {
+        let conn = self.connections.remove(token).unwrap();
+
+        if let Some(timer) = conn.waiting.and_then(|wait| wait.timer) {
+            event_loop.clear_timeout(timer);
+        }
+
+        // Jobs reserved by a connection that goes away are ready again
+        for id in conn.reserved {
+            if let Some(timer) = self.jobs.get_mut(&id).and_then(|job| job.timer.take()) {
+                event_loop.clear_timeout(timer);
+            }
+
+            println!("connection closed, job released; id={}; token={:?}", id, token);
+            self.make_ready(event_loop, id);
+        }
+    }
+}
+
+impl mio::Handler for Beanstalk {
+    type Timeout = Timer;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ => self.connection_ready(event_loop, token, events),
+        }
+
+        self.flush(event_loop);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Beanstalk>, timer: Timer) {
+        self.expire(event_loop, timer);
+        self.flush(event_loop);
+    }
+}
+
+// Tube names are made of letters, digits and `-+/;.$_()`, and can't start
+// with a hyphen
+fn is_valid_tube(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_TUBE_NAME && !name.starts_with('-') &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || "-+/;.$_()".contains(c))
+}
+
+// `put` is a command line followed by the job's data, whose length is given
+// in the command line, the same way as memcached's `set`.
+enum State {
+    // Waiting for a command line
+    Command,
+    // Waiting for the data of a `put`, followed by CRLF
+    Data(Put),
+    // Throwing away the data of a job that is too big. The value is the
+    // number of bytes left to skip.
+    Skip(usize),
+}
+
+struct Put {
+    pri: u32,
+    delay: u64,
+    ttr: u64,
+    len: usize,
+}
+
+// A connection blocked in `reserve`
+struct Wait {
+    seq: u64,
+    // Set for `reserve-with-timeout`
+    timer: Option<mio::Timeout>,
+}
+
+struct Connection {
+    socket: TcpStream,
+    state: State,
+    // Bytes read but not yet processed
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // The tube `put` puts jobs in
+    using: String,
+    // The tubes `reserve` takes jobs from
+    watching: Vec<String>,
+    reserved: HashSet<u64>,
+    waiting: Option<Wait>,
+    // Set when the connection should be closed once `out` is flushed
+    quit: bool,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream) -> Connection {
+        Connection {
+            socket: socket,
+            state: State::Command,
+            buf: vec![],
+            out: vec![],
+            using: DEFAULT_TUBE.to_string(),
+            watching: vec![DEFAULT_TUBE.to_string()],
+            reserved: HashSet::new(),
+            waiting: None,
+            quit: false,
+            closed: false,
+        }
+    }
+
+    fn read(&mut self) {
+        let mut buf = [0; 4096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => self.buf.extend(&buf[..n]),
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:11300".to_string())
+        .parse().unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, (|socket, _| Connection::new(socket)) as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mut beanstalk = Beanstalk::new(acceptor);
+
+    println!("running beanstalk server; addr={:?}", address);
+    event_loop.run(&mut beanstalk).unwrap();
+}
@@ -0,0 +1,159 @@
+extern crate bridge;
+extern crate mio;
+
+use std::io::{self, BufRead};
+use std::time::Instant;
+use std::{env, process, thread};
+
+const ITEMS: mio::Token = mio::Token(0);
+
+const REPORT_MS: u64 = 1_000;
+
+// Items taken per turn of the loop, at most
+const BATCH: usize = 4_096;
+
+// An item from a producer thread: which one, and its count so far
+struct Item {
+    producer: usize,
+    seq: u64,
+}
+
+// The message the console's channel wakes the loop with
+#[derive(Clone)]
+struct Console;
+
+// Drains two bridged channels: items from producer threads, through a
+// pipe registered as `ITEMS`, and commands typed on stdin, through the
+// loop's notify channel. Every item is checked to come in order, none
+// missing, and the loop stops once it has them all.
+struct Consumer {
+    items: bridge::Receiver<Item>,
+    console: bridge::Receiver<String>,
+    // The next item expected from each producer
+    next: Vec<u64>,
+    per_producer: u64,
+    received: u64,
+    // At the last report
+    reported: u64,
+    reported_wakeups: usize,
+    started: Instant,
+}
+
+impl Consumer {
+    fn report(&mut self) {
+        let wakeups = self.items.wakeups();
+        let items = self.received - self.reported;
+        let woken = wakeups - self.reported_wakeups;
+
+        println!("items={}; wakeups={}; items per wakeup={:.1}",
+                 items, woken, if woken > 0 { items as f64 / woken as f64 } else { 0.0 });
+
+        self.reported = self.received;
+        self.reported_wakeups = wakeups;
+    }
+}
+
+impl mio::Handler for Consumer {
+    type Timeout = ();
+    type Message = Console;
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Consumer>, _: mio::Token, _: mio::EventSet) {
+        for item in self.items.drain(BATCH) {
+            if item.seq != self.next[item.producer] {
+                println!("out of order; producer={}; expected={}; got={}", item.producer, self.next[item.producer], item.seq);
+                process::exit(1);
+            }
+
+            self.next[item.producer] += 1;
+            self.received += 1;
+        }
+
+        if self.next.iter().all(|&next| next == self.per_producer) {
+            let elapsed = self.started.elapsed();
+
+            println!("done; items={}; wakeups={}; elapsed={}ms",
+                     self.received, self.items.wakeups(), elapsed.as_secs() * 1_000 + elapsed.subsec_millis() as u64);
+            event_loop.shutdown();
+        }
+    }
+
+    fn notify(&mut self, event_loop: &mut mio::EventLoop<Consumer>, _: Console) {
+        let lines: Vec<String> = self.console.drain(BATCH).collect();
+
+        for line in lines {
+            match line.trim() {
+                "stats" => {
+                    let behind: u64 = self.next.iter().map(|&next| self.per_producer - next).sum();
+                    println!("received={}; still to come={}; wakeups={}", self.received, behind, self.items.wakeups());
+                }
+                "quit" => event_loop.shutdown(),
+                "" => {}
+                other => println!("unknown command; command={:?}; expected stats or quit", other),
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Consumer>, _: ()) {
+        self.report();
+        event_loop.timeout_ms((), REPORT_MS).unwrap();
+    }
+}
+
+fn usage() -> ! {
+    println!("usage: consumer [producers] [items per producer]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let producers: usize = args.next().map(|s| s.parse().unwrap_or_else(|_| usage())).unwrap_or(4);
+    let per_producer: u64 = args.next().map(|s| s.parse().unwrap_or_else(|_| usage())).unwrap_or(5_000_000);
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+
+    let (items_tx, items) = bridge::pipe().unwrap();
+    items.register(&mut event_loop, ITEMS).unwrap();
+
+    let (console_tx, console) = bridge::notify(event_loop.channel(), Console);
+
+    for producer in 0..producers {
+        let tx = items_tx.clone();
+
+        thread::spawn(move || {
+            for seq in 0..per_producer {
+                // The loop may stop before it has everything
+                if tx.send(Item { producer: producer, seq: seq }).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    thread::spawn(move || {
+        let stdin = io::stdin();
+
+        for line in stdin.lock().lines() {
+            if console_tx.send(line.unwrap()).is_err() {
+                return;
+            }
+        }
+    });
+
+    println!("consuming; producers={}; items per producer={}", producers, per_producer);
+
+    event_loop.timeout_ms((), REPORT_MS).unwrap();
+
+    let mut consumer = Consumer {
+        items: items,
+        console: console,
+        next: vec![0; producers],
+        per_producer: per_producer,
+        received: 0,
+        reported: 0,
+        reported_wakeups: 0,
+        started: Instant::now(),
+    };
+
+    event_loop.run(&mut consumer).unwrap();
+}
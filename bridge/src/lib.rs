@@ -0,0 +1,375 @@
+// A `std::sync::mpsc` channel whose receiving end lives on an event loop.
+// Any thread can send on it, and the event loop learns of it the way it
+// learns of anything else, with no timer polling the receiver and no
+// thread blocked on it.
+//
+// The items go through the mpsc channel, unbounded. What wakes the loop
+// comes in two flavors:
+//
+// * `pipe`: a byte written to a pipe, whose read end is registered with
+//   the loop under a token of its own. The handler drains the receiver
+//   from `ready`, and the loop's message type stays free for other uses.
+// * `notify`: a message sent through the loop's own channel, one the
+//   handler picks for the purpose. It drains the receiver from `notify`,
+//   when it gets that message.
+//
+// Either way the loop is woken once per batch, not once per item. A flag
+// is set by the first send after the receiver was drained, and only that
+// send wakes the loop; the ones after it find the flag set, and just queue
+// their item. Draining clears the flag before it takes the items, so an
+// item sent during the drain either is taken by it, or wakes the loop
+// again. A drain takes so many items at most, and wakes the loop again
+// when it leaves some, so that the loop gets to its other work in
+// between.
+//
+// A wake up through `notify` waits while the loop's queue is full, from a
+// sender's thread. That can't be done from the loop's own thread, when a
+// drain leaves items behind, as it never gets to empty the queue: the
+// wake up is handed to a thread of its own there instead.
+
+extern crate mio;
+
+use mio::unix::{self, PipeReader, PipeWriter};
+use mio::TryRead;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+enum Waker {
+    Pipe(Mutex<PipeWriter>),
+    // Returns false if the loop's queue is full
+    Notify(Arc<dyn Fn() -> bool + Send + Sync>),
+}
+
+struct Wake {
+    // Set while a wake up is on its way, and the receiver not drained yet
+    pending: AtomicBool,
+    waker: Waker,
+    // How many times the loop was woken, for the curious
+    wakeups: AtomicUsize,
+}
+
+impl Wake {
+    fn new(waker: Waker) -> Arc<Wake> {
+        Arc::new(Wake {
+            pending: AtomicBool::new(false),
+            waker: waker,
+            wakeups: AtomicUsize::new(0),
+        })
+    }
+
+    // Wakes the loop, unless a wake up is on its way already. `on_loop` is
+    // set when called from the loop's thread, which mustn't wait on it.
+    fn wake(&self, on_loop: bool) {
+        if self.pending.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        self.wakeups.fetch_add(1, Ordering::Relaxed);
+
+        match self.waker {
+            // There is one byte in the pipe at most, it can't be full
+            Waker::Pipe(ref writer) => {
+                let _ = writer.lock().unwrap().write(&[1]);
+            }
+            Waker::Notify(ref notify) => {
+                if notify() {
+                    return;
+                }
+
+                // Only one wake up is on its way at a time, so there is one
+                // such thread at most
+                if on_loop {
+                    let notify = notify.clone();
+                    thread::spawn(move || retry(&*notify));
+                } else {
+                    retry(&**notify);
+                }
+            }
+        }
+    }
+}
+
+pub struct Sender<T> {
+    tx: mpsc::Sender<T>,
+    wake: Arc<Wake>,
+}
+
+impl<T> Sender<T> {
+    // Queues the item, and wakes the loop if it isn't on its way already.
+    // Fails once the receiver is gone, handing the item back.
+    pub fn send(&self, item: T) -> Result<(), SendError<T>> {
+        self.tx.send(item)?;
+        self.wake.wake(false);
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        Sender {
+            tx: self.tx.clone(),
+            wake: self.wake.clone(),
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    rx: mpsc::Receiver<T>,
+    wake: Arc<Wake>,
+    // Only for a `pipe` receiver
+    reader: Option<PipeReader>,
+}
+
+impl<T> Receiver<T> {
+    // Registers a `pipe` receiver with the event loop. It becomes readable
+    // when there is something to drain.
+    pub fn register<H: mio::Handler>(&self, event_loop: &mut mio::EventLoop<H>, token: mio::Token) -> io::Result<()> {
+        match self.reader {
+            Some(ref reader) => event_loop.register_opt(reader, token, mio::EventSet::readable(), mio::PollOpt::edge()),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "only a pipe receiver is registered")),
+        }
+    }
+
+    // The items sent so far, `max` of them at most. Producers outpacing the
+    // loop would keep it draining forever otherwise, and starve the other
+    // connections and timers. When the iterator is dropped before the
+    // channel is empty, the limit reached or not, the loop is woken again,
+    // to take the rest on its next turn.
+    pub fn drain(&mut self, max: usize) -> Drain<'_, T> {
+        if let Some(ref mut reader) = self.reader {
+            let mut buf = [0; 64];
+
+            // The pipe is registered as edge triggered, drain it too
+            while let Ok(Some(n)) = reader.try_read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+            }
+        }
+
+        // A swap rather than a store: a sender that found the flag set
+        // swapped it before this, so its item is in the channel by now
+        self.wake.pending.swap(false, Ordering::AcqRel);
+
+        Drain {
+            receiver: self,
+            left: max,
+            empty: false,
+        }
+    }
+
+    pub fn wakeups(&self) -> usize {
+        self.wake.wakeups.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Drain<'a, T: 'a> {
+    receiver: &'a mut Receiver<T>,
+    left: usize,
+    empty: bool,
+}
+
+impl<'a, T> Iterator for Drain<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.left == 0 {
+            return None;
+        }
+
+        match self.receiver.rx.try_recv() {
+            Ok(item) => {
+                self.left -= 1;
+                Some(item)
+            }
+            Err(_) => {
+                self.empty = true;
+                None
+            }
+        }
+    }
+}
+
+impl<'a, T> Drop for Drain<'a, T> {
+    fn drop(&mut self) {
+        if !self.empty {
+            self.receiver.wake.wake(true);
+        }
+    }
+}
+
+// A channel waking the loop through a pipe, the receiver is to be
+// registered with it
+pub fn pipe<T>() -> io::Result<(Sender<T>, Receiver<T>)> {
+    let (reader, writer) = unix::pipe()?;
+    let (tx, rx) = mpsc::channel();
+    let wake = Wake::new(Waker::Pipe(Mutex::new(writer)));
+
+    let sender = Sender {
+        tx: tx,
+        wake: wake.clone(),
+    };
+
+    let receiver = Receiver {
+        rx: rx,
+        wake: wake,
+        reader: Some(reader),
+    };
+
+    Ok((sender, receiver))
+}
+
+// A channel waking the loop by sending it `message` through `notify`, its
+// own channel. A full notify queue means the loop is behind, the wake up
+// waits for it.
+pub fn notify<T, M>(notify: mio::Sender<M>, message: M) -> (Sender<T>, Receiver<T>)
+    where M: Clone + Send + Sync + 'static
+{
+    let (tx, rx) = mpsc::channel();
+
+    let waker = Waker::Notify(Arc::new(move || {
+        match notify.send(message.clone()) {
+            Err(mio::NotifyError::Full(_)) => false,
+            // Any other error means the loop is gone, and so is the
+            // receiver, most likely
+            _ => true,
+        }
+    }));
+
+    let wake = Wake::new(waker);
+
+    let sender = Sender {
+        tx: tx,
+        wake: wake.clone(),
+    };
+
+    let receiver = Receiver {
+        rx: rx,
+        wake: wake,
+        reader: None,
+    };
+
+    (sender, receiver)
+}
+
+// Sends a wake up until the loop's queue has room for it
+fn retry(notify: &dyn Fn() -> bool) {
+    while !notify() {
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn drains_in_order() {
+        let (tx, mut rx) = pipe().unwrap();
+
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+
+        assert_eq!(rx.drain(10).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(rx.drain(10).count(), 0);
+    }
+
+    #[test]
+    fn drains_max_items() {
+        let (tx, mut rx) = pipe().unwrap();
+
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+
+        assert_eq!(rx.wakeups(), 1);
+        assert_eq!(rx.drain(2).collect::<Vec<_>>(), vec![0, 1]);
+        // The items left behind wake the loop again
+        assert_eq!(rx.wakeups(), 2);
+        assert_eq!(rx.drain(2).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(rx.wakeups(), 3);
+        assert_eq!(rx.drain(2).collect::<Vec<_>>(), vec![4]);
+        assert_eq!(rx.wakeups(), 3);
+    }
+
+    #[test]
+    fn wakes_once_per_batch() {
+        let (tx, mut rx) = pipe().unwrap();
+
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(rx.wakeups(), 1);
+
+        assert_eq!(rx.drain(10).count(), 2);
+        assert_eq!(rx.wakeups(), 1);
+
+        // The first send after the drain wakes the loop again
+        tx.send(3).unwrap();
+        tx.send(4).unwrap();
+        assert_eq!(rx.wakeups(), 2);
+    }
+
+    #[test]
+    fn fails_once_receiver_is_gone() {
+        let (tx, rx) = pipe().unwrap();
+
+        drop(rx);
+        assert_eq!(tx.send(1).unwrap_err().0, 1);
+    }
+
+    #[derive(Clone)]
+    enum Message {
+        Filler,
+        Wake,
+    }
+
+    struct Handler {
+        wakes: usize,
+    }
+
+    impl mio::Handler for Handler {
+        type Timeout = ();
+        type Message = Message;
+
+        fn notify(&mut self, event_loop: &mut mio::EventLoop<Handler>, message: Message) {
+            if let Message::Wake = message {
+                self.wakes += 1;
+                event_loop.shutdown();
+            }
+        }
+    }
+
+    #[test]
+    fn drain_does_not_wait_on_full_notify_queue() {
+        let config = mio::EventLoopConfig {
+            notify_capacity: 4,
+            ..mio::EventLoopConfig::default()
+        };
+
+        let mut event_loop = mio::EventLoop::configured(config).unwrap();
+        let (tx, mut rx) = notify(event_loop.channel(), Message::Wake);
+
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+
+        let mut handler = Handler { wakes: 0 };
+        event_loop.run(&mut handler).unwrap();
+        assert_eq!(handler.wakes, 1);
+
+        // Fill the loop's queue, then leave an item behind
+        let channel = event_loop.channel();
+        while channel.send(Message::Filler).is_ok() {}
+
+        assert_eq!(rx.drain(1).collect::<Vec<_>>(), vec![1]);
+
+        // The wake up gets through once the loop empties its queue
+        event_loop.run(&mut handler).unwrap();
+        assert_eq!(handler.wakes, 2);
+        assert_eq!(rx.drain(10).collect::<Vec<_>>(), vec![2]);
+    }
+}
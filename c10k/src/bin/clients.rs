@@ -0,0 +1,231 @@
+extern crate c10k;
+extern crate mio;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::{env, process};
+
+// Connections are opened this many at a time, every tick, rather than all
+// at once: a burst of thousands of SYNs overflows the server's backlog,
+// and the ones dropped are only retried a second later
+const OPEN_BATCH: usize = 100;
+const OPEN_MS: u64 = 10;
+
+// How often the connections that are due send a ping, and how often the
+// stats are printed
+const TICK_MS: u64 = 1_000;
+const REPORT_MS: u64 = 5_000;
+
+const PING: &[u8] = b"ping\n";
+
+enum Timer {
+    Open,
+    Ping,
+    Report,
+}
+
+struct Connection {
+    socket: TcpStream,
+    connected: bool,
+}
+
+#[derive(Default)]
+struct Stats {
+    connected: u64,
+    failed: u64,
+    closed: u64,
+    pings: u64,
+    // Pings not sent, the socket buffer was full
+    skipped: u64,
+    echoed: u64,
+}
+
+// Opens connections to the server, and keeps them open, each sending a
+// ping now and then. The pings are spread out: at every tick, only the
+// connections whose turn it is send one, so the server sees a steady
+// trickle rather than everyone at once.
+struct Clients {
+    addr: SocketAddr,
+    total: usize,
+    opened: usize,
+    ping_secs: u64,
+    ticks: u64,
+    connections: Slab<Connection>,
+    stats: Stats,
+}
+
+impl Clients {
+    fn open(&mut self, event_loop: &mut mio::EventLoop<Clients>) {
+        for _ in 0..OPEN_BATCH {
+            if self.opened == self.total {
+                return;
+            }
+
+            self.opened += 1;
+
+            let socket = match TcpStream::connect(&self.addr) {
+                Ok(socket) => socket,
+                Err(e) => {
+                    println!("failed to connect; err={:?}", e);
+                    self.stats.failed += 1;
+                    continue;
+                }
+            };
+
+            let token = self.connections.insert(Connection { socket: socket, connected: false }).ok().unwrap();
+
+            // Like the server, registered once, edge triggered
+            event_loop.register_opt(&self.connections[token].socket, token, mio::EventSet::readable() | mio::EventSet::writable(), mio::PollOpt::edge())
+                .unwrap();
+        }
+
+        event_loop.timeout_ms(Timer::Open, OPEN_MS).unwrap();
+    }
+
+    fn ping(&mut self) {
+        self.ticks += 1;
+
+        for i in 0..self.total {
+            if i as u64 % self.ping_secs != self.ticks % self.ping_secs {
+                continue;
+            }
+
+            let conn = match self.connections.get_mut(mio::Token(i)) {
+                Some(conn) if conn.connected => conn,
+                _ => continue,
+            };
+
+            // What isn't written at once is not worth buffering, the next
+            // ping will do
+            match conn.socket.try_write(PING) {
+                Ok(Some(n)) if n == PING.len() => self.stats.pings += 1,
+                _ => self.stats.skipped += 1,
+            }
+        }
+    }
+
+    fn report(&self) {
+        println!("open={}; connecting={}; connected={}; failed={}; closed={}; pings={}; skipped={}; echoed={}; rss={}",
+                 self.connections.count(), self.opened - self.stats.connected as usize - self.stats.failed as usize,
+                 self.stats.connected, self.stats.failed, self.stats.closed, self.stats.pings, self.stats.skipped,
+                 c10k::human(self.stats.echoed), c10k::human(c10k::rss().unwrap_or(0)));
+    }
+}
+
+impl mio::Handler for Clients {
+    type Timeout = Timer;
+    type Message = ();
+
+    fn ready(&mut self, _: &mut mio::EventLoop<Clients>, token: mio::Token, events: mio::EventSet) {
+        let mut closed = false;
+
+        {
+            let conn = &mut self.connections[token];
+
+            if !conn.connected {
+                // A failed handshake is reported as writable too, the
+                // socket's pending error tells the two apart
+                if conn.socket.take_socket_error().is_err() || events.is_error() || events.is_hup() {
+                    self.stats.failed += 1;
+                    self.connections.remove(token);
+                    return;
+                }
+
+                conn.connected = true;
+                self.stats.connected += 1;
+            }
+
+            if events.is_readable() {
+                let mut buf = [0; 4_096];
+
+                // The socket is registered as edge triggered, drain it
+                loop {
+                    match conn.socket.try_read(&mut buf) {
+                        Ok(Some(0)) | Err(_) => {
+                            closed = true;
+                            break;
+                        }
+                        Ok(Some(n)) => self.stats.echoed += n as u64,
+                        Ok(None) => break,
+                    }
+                }
+            }
+        }
+
+        if closed {
+            self.stats.closed += 1;
+            self.connections.remove(token);
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Clients>, timer: Timer) {
+        match timer {
+            Timer::Open => self.open(event_loop),
+            Timer::Ping => {
+                self.ping();
+                event_loop.timeout_ms(Timer::Ping, TICK_MS).unwrap();
+            }
+            Timer::Report => {
+                self.report();
+                event_loop.timeout_ms(Timer::Report, REPORT_MS).unwrap();
+            }
+        }
+    }
+}
+
+fn usage() -> ! {
+    println!("usage: clients <host:port> [connections] [ping secs]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let addr = match args.next().and_then(|target| target.to_socket_addrs().ok()).and_then(|mut addrs| addrs.next()) {
+        Some(addr) => addr,
+        None => usage(),
+    };
+
+    let total: usize = args.next().map(|s| s.parse().unwrap_or_else(|_| usage())).unwrap_or(10_000);
+    let ping_secs: u64 = args.next().map(|s| s.parse().unwrap_or_else(|_| usage())).unwrap_or(30);
+
+    if ping_secs == 0 {
+        usage();
+    }
+
+    // The client needs a descriptor per connection just as much
+    let fds = c10k::raise_fd_limit().unwrap();
+
+    if fds < total + 16 {
+        println!("file descriptor limit too low; limit={}; connections={}", fds, total);
+        process::exit(1);
+    }
+
+    // The default timer tick is 100ms, too coarse to pace the connects
+    let config = mio::EventLoopConfig {
+        timer_tick_ms: OPEN_MS,
+        ..mio::EventLoopConfig::default()
+    };
+
+    let mut event_loop = mio::EventLoop::configured(config).unwrap();
+
+    event_loop.timeout_ms(Timer::Open, 0).unwrap();
+    event_loop.timeout_ms(Timer::Ping, TICK_MS).unwrap();
+    event_loop.timeout_ms(Timer::Report, REPORT_MS).unwrap();
+
+    println!("opening connections; addr={:?}; connections={}; ping every {}s", addr, total, ping_secs);
+
+    let mut clients = Clients {
+        addr: addr,
+        total: total,
+        opened: 0,
+        ping_secs: ping_secs,
+        ticks: 0,
+        connections: Slab::new(total),
+        stats: Stats::default(),
+    };
+
+    event_loop.run(&mut clients).unwrap();
+}
@@ -0,0 +1,80 @@
+// The process and socket tuning shared by the server and the client in
+// `src/bin`. None of it is specific to mio: it is what any program
+// holding tens of thousands of sockets has to do first.
+
+extern crate libc;
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::io::RawFd;
+
+// Raises the limit on open file descriptors to the most the process is
+// allowed, and returns the new limit. Each connection is a descriptor,
+// and the default soft limit is usually 1024, the hard limit much higher:
+// raising the soft one doesn't take any privileges.
+pub fn raise_fd_limit() -> io::Result<usize> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    unsafe {
+        if libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        limit.rlim_cur = limit.rlim_max;
+
+        if libc::setrlimit(libc::RLIMIT_NOFILE, &limit) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(limit.rlim_cur as usize)
+}
+
+// The memory the process uses, in bytes, from `/proc/self/statm`. Linux
+// only, `None` elsewhere.
+pub fn rss() -> Option<u64> {
+    let mut statm = String::new();
+    File::open("/proc/self/statm").ok()?.read_to_string(&mut statm).ok()?;
+
+    // Sizes are in pages: total, then resident
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+
+    Some(pages * page_size)
+}
+
+// Sets the size of a socket's kernel buffers, both ways. Their memory
+// isn't the process', it doesn't show in `rss`, but with the defaults it
+// is most of what an idle connection costs once data went through it.
+pub fn set_buffer_size(fd: RawFd, size: usize) -> io::Result<()> {
+    let size = size as libc::c_int;
+
+    for &option in &[libc::SO_RCVBUF, libc::SO_SNDBUF] {
+        let ret = unsafe {
+            libc::setsockopt(fd,
+                             libc::SOL_SOCKET,
+                             option,
+                             &size as *const libc::c_int as *const libc::c_void,
+                             mem::size_of::<libc::c_int>() as libc::socklen_t)
+        };
+
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+// Bytes, for humans
+pub fn human(bytes: u64) -> String {
+    match bytes {
+        0..=9_999 => format!("{}B", bytes),
+        10_000..=9_999_999 => format!("{}KB", bytes / 1_024),
+        _ => format!("{}MB", bytes / (1_024 * 1_024)),
+    }
+}
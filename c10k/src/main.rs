@@ -0,0 +1,414 @@
+extern crate alloc_count;
+extern crate c10k;
+extern crate mio;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::time::Instant;
+use std::{env, mem, process};
+
+// Installed only with `--features count-allocations`, counting costs an
+// atomic increment per allocation
+#[cfg(feature = "count-allocations")]
+#[global_allocator]
+static ALLOCATOR: alloc_count::Counting = alloc_count::Counting;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// The most connections held, whatever the file descriptor limit allows
+const MAX_CONNECTIONS: usize = 100_000;
+
+// Descriptors kept for other uses than connections: the standard streams,
+// the listener, epoll's own...
+const RESERVED_FDS: usize = 16;
+
+// Connections waiting to be accepted, the kernel caps it at
+// `net.core.somaxconn`. The default of 1024 overflows when thousands of
+// clients connect at once: the SYNs past it are dropped, and retried
+// after a second.
+const BACKLOG: usize = 4_096;
+
+// Connections accepted per readiness event, see `accept`
+const ACCEPT_BATCH: usize = 256;
+
+// Connections not heard from in this long are closed, by default
+const IDLE_SECS: u32 = 300;
+
+// How often the idle connections are looked for, and the stats printed
+const SWEEP_MS: u64 = 5_000;
+const REPORT_MS: u64 = 10_000;
+
+// A connection stops being read from while this much of what it sent
+// hasn't been written back yet
+const MAX_PENDING: usize = 64 * 1_024;
+
+// Output buffers kept around for reuse, at most
+const POOL_SIZE: usize = 1_024;
+
+enum Timer {
+    Sweep,
+    Report,
+}
+
+// Everything the server keeps per connection. An idle connection is by
+// far the common case, and what it costs is what limits how many fit:
+// there is no read buffer, every connection shares the server's, and no
+// output buffer unless a write came up short. No mio timeout either, the
+// idle ones are found by a sweep over all of them.
+struct Connection {
+    socket: TcpStream,
+    // The server's clock at the last read, in seconds
+    active: u32,
+    // What couldn't be written yet. Empty, it has no allocation, it only
+    // gets one, from the pool, while something is waiting.
+    pending: Vec<u8>,
+}
+
+// An echo server holding as many mostly idle connections as it can. What
+// it does with each is beside the point, what it shows is what it takes
+// to hold 10,000 of them and more.
+struct Server {
+    server: TcpListener,
+    connections: Slab<Connection>,
+    // The one buffer everything is read into
+    buf: Vec<u8>,
+    // Output buffers, cleared, for the connections that need one next
+    pool: Vec<Vec<u8>>,
+    started: Instant,
+    // Seconds since the start, as of the last sweep. Coarse, but it is
+    // only compared with an idle timeout of minutes.
+    clock: u32,
+    idle_secs: u32,
+    buffer_size: Option<usize>,
+    // The memory used before any connection, what is used past it is
+    // what the connections cost
+    baseline: u64,
+    // Event loop iterations
+    turns: u64,
+    stats: Stats,
+    allocations: Allocations,
+}
+
+#[derive(Default)]
+struct Stats {
+    accepted: u64,
+    closed: u64,
+    // Of the closed ones
+    idle: u64,
+    // Connections refused for lack of room
+    refused: u64,
+    peak: usize,
+}
+
+// Zero unless counting allocations. Those made by the reports themselves
+// are left out.
+#[derive(Default)]
+struct Allocations {
+    // As of the previous report
+    reported: alloc_count::Stats,
+    turns: u64,
+    // Made while accepting and closing connections
+    lifecycle: u64,
+}
+
+impl Server {
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Server>) {
+        // The server socket is registered as level triggered, one accept
+        // per event would be enough, but there can be thousands of clients
+        // connecting at once: an event loop iteration per accept would
+        // leave the events of the connections already accepted waiting
+        // behind them
+        for _ in 0..ACCEPT_BATCH {
+            let before = alloc_count::stats();
+
+            let socket = match self.server.accept() {
+                Ok(Some(socket)) => socket,
+                Ok(None) => return,
+                Err(e) => {
+                    // Out of descriptors, or of memory for the socket: the
+                    // connection stays in the backlog, and is retried at
+                    // the next event
+                    println!("failed to accept connection; err={:?}", e);
+                    return;
+                }
+            };
+
+            if let Some(size) = self.buffer_size {
+                c10k::set_buffer_size(socket.as_raw_fd(), size).unwrap();
+            }
+
+            let conn = Connection {
+                socket: socket,
+                active: self.clock,
+                pending: vec![],
+            };
+
+            let token = match self.connections.insert(conn) {
+                Ok(token) => token,
+                Err(_) => {
+                    self.stats.refused += 1;
+                    continue;
+                }
+            };
+
+            // Registered once, for both reading and writing, as edge
+            // triggered and not oneshot: there is never a reason to
+            // reregister, and no `epoll_ctl` call per event. A writable
+            // event comes when a full socket buffer drains, which matters
+            // only when something is pending, and is cheap to ignore
+            // otherwise.
+            event_loop.register_opt(&self.connections[token].socket, token, mio::EventSet::readable() | mio::EventSet::writable(), mio::PollOpt::edge())
+                .unwrap();
+
+            self.stats.accepted += 1;
+            self.stats.peak = self.stats.peak.max(self.connections.count());
+            self.allocations.lifecycle += (alloc_count::stats() - before).calls();
+        }
+    }
+
+    fn connection_ready(&mut self, token: mio::Token, events: mio::EventSet) {
+        let closed = {
+            let Server { ref mut connections, ref mut buf, ref mut pool, clock, .. } = *self;
+            let conn = &mut connections[token];
+
+            if events.is_writable() {
+                conn.flush(pool);
+            }
+
+            // Also after a flush: it may have made room for reading what
+            // was left unread
+            conn.echo(buf, pool, clock) || events.is_error() || events.is_hup() && conn.pending.is_empty()
+        };
+
+        if closed {
+            self.close(token);
+        }
+    }
+
+    // Dropping the socket closes it, and takes it out of epoll's set
+    fn close(&mut self, token: mio::Token) {
+        let before = alloc_count::stats();
+
+        let conn = self.connections.remove(token).unwrap();
+        recycle(&mut self.pool, conn.pending);
+        self.stats.closed += 1;
+
+        self.allocations.lifecycle += (alloc_count::stats() - before).calls();
+    }
+
+    // Advances the clock and closes the idle connections. One timer for
+    // all of them, rather than one each: with tens of thousands, the
+    // timer wheel's memory, and resetting a timeout at every read, would
+    // cost more than looking at each connection every few seconds.
+    fn sweep(&mut self) {
+        self.clock = self.started.elapsed().as_secs() as u32;
+
+        let idle: Vec<mio::Token> = (1..MAX_CONNECTIONS + 1)
+            .map(mio::Token)
+            .filter(|&token| self.connections.get(token).map(|conn| self.clock - conn.active >= self.idle_secs).unwrap_or(false))
+            .collect();
+
+        for token in idle {
+            self.close(token);
+            self.stats.idle += 1;
+        }
+    }
+
+    fn report(&mut self) {
+        let allocations = alloc_count::stats();
+        let count = self.connections.count();
+        let pending = (1..MAX_CONNECTIONS + 1)
+            .filter_map(|i| self.connections.get(mio::Token(i)))
+            .filter(|conn| !conn.pending.is_empty())
+            .count();
+
+        let rss = c10k::rss().unwrap_or(0);
+        let per_connection = match count {
+            0 => "-".to_string(),
+            _ => c10k::human(rss.saturating_sub(self.baseline) / count as u64),
+        };
+
+        println!("connections={}; peak={}; accepted={}; closed={}; idle={}; refused={}; pending={}; pooled={}; rss={}; per connection={}",
+                 count, self.stats.peak, self.stats.accepted, self.stats.closed, self.stats.idle, self.stats.refused,
+                 pending, self.pool.len(), c10k::human(rss), per_connection);
+
+        if cfg!(feature = "count-allocations") {
+            self.report_allocations(allocations);
+        }
+    }
+
+    // An allocation free steady state is no allocations per turn, while
+    // there is no churn: the connections that come and go cost some
+    fn report_allocations(&mut self, now: alloc_count::Stats) {
+        let since = now - self.allocations.reported;
+        let turns = self.turns - self.allocations.turns;
+
+        println!("allocations; since last report={}; bytes={}; per turn={:.3}; per connection={:.2}; live={}",
+                 since.calls(), c10k::human(since.allocated), since.calls() as f64 / turns.max(1) as f64,
+                 self.allocations.lifecycle as f64 / self.stats.accepted.max(1) as f64, c10k::human(now.live()));
+
+        self.allocations.reported = alloc_count::stats();
+        self.allocations.turns = self.turns;
+    }
+}
+
+impl Connection {
+    // Reads what the client sent and writes it back, or keeps it pending.
+    // Returns whether the connection is closed.
+    fn echo(&mut self, buf: &mut [u8], pool: &mut Vec<Vec<u8>>, clock: u32) -> bool {
+        // The socket is registered as edge triggered, drain it, unless the
+        // client is sending faster than it reads
+        while self.pending.len() < MAX_PENDING {
+            let n = match self.socket.try_read(buf) {
+                Ok(Some(0)) => return true,
+                Ok(Some(n)) => n,
+                Ok(None) => return false,
+                Err(_) => return true,
+            };
+
+            self.active = clock;
+
+            let mut written = 0;
+
+            if self.pending.is_empty() {
+                match self.socket.try_write(&buf[..n]) {
+                    Ok(Some(w)) => written = w,
+                    Ok(None) => {}
+                    Err(_) => return true,
+                }
+            }
+
+            if written < n {
+                if self.pending.capacity() == 0 {
+                    self.pending = pool.pop().unwrap_or_default();
+                }
+
+                self.pending.extend_from_slice(&buf[written..n]);
+            }
+        }
+
+        false
+    }
+
+    fn flush(&mut self, pool: &mut Vec<Vec<u8>>) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        while !self.pending.is_empty() {
+            match self.socket.try_write(&self.pending) {
+                Ok(Some(n)) => {
+                    self.pending.drain(..n);
+                }
+                // Closing is left to the read that comes next
+                Ok(None) | Err(_) => return,
+            }
+        }
+
+        // The buffer goes back to the pool, most connections need one
+        // only now and then
+        recycle(pool, mem::take(&mut self.pending));
+    }
+}
+
+fn recycle(pool: &mut Vec<Vec<u8>>, mut buf: Vec<u8>) {
+    if buf.capacity() > 0 && pool.len() < POOL_SIZE {
+        buf.clear();
+        pool.push(buf);
+    }
+}
+
+impl mio::Handler for Server {
+    type Timeout = Timer;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ => self.connection_ready(token, events),
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Server>, timer: Timer) {
+        match timer {
+            Timer::Sweep => {
+                self.sweep();
+                event_loop.timeout_ms(Timer::Sweep, SWEEP_MS).unwrap();
+            }
+            Timer::Report => {
+                self.report();
+                event_loop.timeout_ms(Timer::Report, REPORT_MS).unwrap();
+            }
+        }
+    }
+}
+
+fn usage() -> ! {
+    println!("usage: c10k [addr] [idle secs] [socket buffer bytes]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:9500".to_string()).parse().unwrap_or_else(|_| usage());
+    let idle_secs: u32 = args.next().map(|s| s.parse().unwrap_or_else(|_| usage())).unwrap_or(IDLE_SECS);
+    let buffer_size: Option<usize> = args.next().map(|s| s.parse().unwrap_or_else(|_| usage()));
+
+    // Before anything else: the limit is what decides how many
+    // connections can be held
+    let fds = match c10k::raise_fd_limit() {
+        Ok(fds) => fds,
+        Err(e) => {
+            println!("failed to raise the file descriptor limit; err={:?}", e);
+            process::exit(1);
+        }
+    };
+
+    let max = MAX_CONNECTIONS.min(fds.saturating_sub(RESERVED_FDS));
+
+    let socket = match address {
+        SocketAddr::V4(..) => TcpSocket::v4(),
+        SocketAddr::V6(..) => TcpSocket::v6(),
+    }.unwrap();
+
+    socket.set_reuseaddr(true).unwrap();
+    socket.bind(&address).unwrap();
+
+    let server = socket.listen(BACKLOG).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+    event_loop.timeout_ms(Timer::Sweep, SWEEP_MS).unwrap();
+    event_loop.timeout_ms(Timer::Report, REPORT_MS).unwrap();
+
+    println!("running c10k server; addr={:?}; fd limit={}; max connections={}; connection size={}B; idle={}s; socket buffers={:?}",
+             address, fds, max, mem::size_of::<Connection>(), idle_secs, buffer_size);
+
+    // Token `0` is reserved for the server socket. Tokens 1+ are used for
+    // client connections. The slab's entries are allocated up front, and
+    // reused as connections come and go.
+    let mut server = Server {
+        server: server,
+        connections: Slab::new_starting_at(mio::Token(1), max),
+        buf: vec![0; 64 * 1_024],
+        pool: vec![],
+        started: Instant::now(),
+        clock: 0,
+        idle_secs: idle_secs,
+        buffer_size: buffer_size,
+        baseline: c10k::rss().unwrap_or(0),
+        turns: 0,
+        stats: Stats::default(),
+        allocations: Allocations::default(),
+    };
+
+    // Turn by turn, rather than `run`, to count them
+    loop {
+        event_loop.run_once(&mut server).unwrap();
+        server.turns += 1;
+    }
+}
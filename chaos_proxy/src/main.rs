@@ -0,0 +1,536 @@
+extern crate libc;
+extern crate mio;
+extern crate rand;
+extern crate rope;
+
+use mio::TryRead;
+use mio::tcp::*;
+use mio::util::Slab;
+use rope::Rope;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+use std::{cmp, env, mem, process};
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Tokens `1..=MAX_CONNECTIONS` are used for client connections, and the
+// connection to the upstream server for a client is its token plus
+// `MAX_CONNECTIONS`.
+const MAX_CONNECTIONS: usize = 1_024;
+
+// Reading from one side stops while this much is held back, or waiting to
+// be written, for the other
+const MAX_BUFFERED: usize = 256 * 1_024;
+
+// The faults to inject, all off by default
+#[derive(Debug)]
+struct Chaos {
+    // Added to everything relayed, client to server and back
+    up_ms: u64,
+    down_ms: u64,
+    // A random delay on top, of up to this much
+    jitter_ms: u64,
+    // The odds that a read is followed by both connections being reset
+    reset: f64,
+    // Writes are cut in random pieces of up to this many bytes, one piece
+    // at a time
+    max_write: Option<usize>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Direction {
+    // Client to server
+    Up,
+    // Server to client
+    Down,
+}
+
+// The pipe in `Direction` of the connection, that has something due
+#[derive(Copy, Clone)]
+struct Timer {
+    token: mio::Token,
+    direction: Direction,
+}
+
+// Relays TCP connections to an upstream server, and makes the network
+// between them look worse than it is. Whatever is read from one side is
+// held back for the latency of its direction before it is written to the
+// other: it is queued with the time it is due, and a timer releases it.
+// Then, a connection may be reset at random, and writes may be cut in
+// small pieces, so the other side gets what it is sent a little at a
+// time.
+struct Proxy {
+    server: TcpListener,
+    upstream: SocketAddr,
+    chaos: Chaos,
+    connections: Slab<Connection>,
+    resets: u64,
+}
+
+impl Proxy {
+    fn new(server: TcpListener, upstream: SocketAddr, chaos: Chaos) -> Proxy {
+        // Token `0` is reserved for the server socket
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS);
+
+        Proxy {
+            server: server,
+            upstream: upstream,
+            chaos: chaos,
+            connections: slab,
+            resets: 0,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Proxy>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        let upstream = match TcpStream::connect(&self.upstream) {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                println!("failed to connect upstream; err={:?}", e);
+                return;
+            }
+        };
+
+        // Small writes are the point when writes are cut in pieces, they
+        // shouldn't be put back together on the way
+        if self.chaos.max_write.is_some() {
+            let _ = socket.set_nodelay(true);
+            let _ = upstream.set_nodelay(true);
+        }
+
+        let token = match self.connections.insert_with(|token| Connection::new(socket, upstream, token)) {
+            Some(token) => token,
+            None => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        let conn = &self.connections[token];
+
+        // Nothing is read from the client until the upstream connection is
+        // up, which is when it is writable
+        event_loop.register_opt(&conn.client, token, mio::EventSet::none(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+        event_loop.register_opt(&conn.upstream, upstream_token(token), mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, direction: Direction, events: mio::EventSet) {
+        {
+            let conn = &mut self.connections[token];
+
+            if !conn.connected {
+                if events.is_error() || events.is_hup() {
+                    println!("failed to connect upstream; token={:?}; err={:?}", token, conn.upstream.take_socket_error());
+                    conn.closed = true;
+                } else if events.is_writable() {
+                    println!("connected upstream; token={:?}", token);
+                    conn.connected = true;
+                }
+            }
+        }
+
+        if self.connections[token].connected && events.is_readable() {
+            // The side the event is for is read, and what it sent is on its
+            // way the other side
+            self.read(event_loop, token, direction);
+        }
+
+        self.update(event_loop, token);
+    }
+
+    fn read(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, direction: Direction) {
+        let read = self.connections[token].read(direction, &self.chaos);
+
+        if read && self.chaos.reset > 0.0 && rand::random::<f64>() < self.chaos.reset {
+            self.resets += 1;
+            println!("resetting connection; token={:?}; resets={}", token, self.resets);
+
+            let conn = &mut self.connections[token];
+            reset(&conn.client);
+            reset(&conn.upstream);
+            conn.closed = true;
+            return;
+        }
+
+        self.schedule(event_loop, token, direction);
+    }
+
+    // Starts the timer for what is due next in the pipe, unless it is
+    // already running
+    fn schedule(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, direction: Direction) {
+        let pipe = self.connections[token].pipe(direction);
+
+        if pipe.timer.is_some() {
+            return;
+        }
+
+        let due = match pipe.queue.front() {
+            Some(&(due, _)) => due,
+            None => return,
+        };
+
+        let now = Instant::now();
+        let ms = if due > now { millis(due - now) } else { 0 };
+
+        let timer = Timer {
+            token: token,
+            direction: direction,
+        };
+
+        pipe.timer = Some(event_loop.timeout_ms(timer, ms).unwrap());
+    }
+
+    // Writes what it can, then reregisters both sockets of the connection,
+    // or removes it once it is done
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token) {
+        {
+            let conn = &mut self.connections[token];
+
+            if conn.connected && !conn.closed {
+                conn.write(Direction::Up, &self.chaos);
+                conn.write(Direction::Down, &self.chaos);
+            }
+        }
+
+        if self.connections[token].is_done() {
+            let mut conn = self.connections.remove(token).unwrap();
+
+            for &direction in &[Direction::Up, Direction::Down] {
+                if let Some(timer) = conn.pipe(direction).timer.take() {
+                    event_loop.clear_timeout(timer);
+                }
+            }
+
+            println!("connection closed; token={:?}; sent={}; received={}", token, conn.up.bytes, conn.down.bytes);
+            return;
+        }
+
+        let conn = &self.connections[token];
+
+        event_loop.reregister(&conn.client, token, conn.client_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+        event_loop.reregister(&conn.upstream, upstream_token(token), conn.upstream_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+impl mio::Handler for Proxy {
+    type Timeout = Timer;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ if token.as_usize() <= MAX_CONNECTIONS => self.connection_ready(event_loop, token, Direction::Up, events),
+            _ => {
+                let token = mio::Token(token.as_usize() - MAX_CONNECTIONS);
+
+                // The upstream socket's events may still come in after the
+                // connection is gone
+                if self.connections.contains(token) {
+                    self.connection_ready(event_loop, token, Direction::Down, events);
+                }
+            }
+        }
+    }
+
+    // Moves what is due in the pipe to be written, and starts the timer
+    // for what comes next
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Proxy>, timer: Timer) {
+        {
+            let pipe = self.connections[timer.token].pipe(timer.direction);
+            pipe.timer = None;
+            pipe.release(Instant::now());
+        }
+
+        self.schedule(event_loop, timer.token, timer.direction);
+        self.update(event_loop, timer.token);
+    }
+}
+
+fn upstream_token(token: mio::Token) -> mio::Token {
+    mio::Token(token.as_usize() + MAX_CONNECTIONS)
+}
+
+// One direction of a connection
+struct Pipe {
+    // Read, and held back until the time it is due
+    queue: VecDeque<(Instant, Vec<u8>)>,
+    // Due, and waiting to be written. The chunks are queued as they were
+    // read, and written together, without being copied into one buffer.
+    out: Rope,
+    timer: Option<mio::Timeout>,
+    // Set once the side it is read from has closed its end, and once that
+    // was passed on, after everything before it
+    eof: bool,
+    shut: bool,
+    bytes: u64,
+}
+
+impl Pipe {
+    fn new() -> Pipe {
+        Pipe {
+            queue: VecDeque::new(),
+            out: Rope::new(),
+            timer: None,
+            eof: false,
+            shut: false,
+            bytes: 0,
+        }
+    }
+
+    fn buffered(&self) -> usize {
+        self.out.len() + self.queue.iter().map(|(_, chunk)| chunk.len()).sum::<usize>()
+    }
+
+    // Holds a chunk back for `delay`. A chunk is never due before the ones
+    // read before it, whatever the jitter: TCP doesn't reorder.
+    fn hold(&mut self, chunk: Vec<u8>, delay: Duration) {
+        let mut due = Instant::now() + delay;
+
+        if let Some(&(last, _)) = self.queue.back() {
+            due = cmp::max(due, last);
+        }
+
+        self.queue.push_back((due, chunk));
+    }
+
+    fn release(&mut self, now: Instant) {
+        while self.queue.front().map(|&(due, _)| due <= now).unwrap_or(false) {
+            let (_, chunk) = self.queue.pop_front().unwrap();
+            self.out.push(chunk);
+        }
+    }
+}
+
+struct Connection {
+    client: TcpStream,
+    upstream: TcpStream,
+    token: mio::Token,
+    connected: bool,
+    // Client to server, and server to client
+    up: Pipe,
+    down: Pipe,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(client: TcpStream, upstream: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            client: client,
+            upstream: upstream,
+            token: token,
+            connected: false,
+            up: Pipe::new(),
+            down: Pipe::new(),
+            closed: false,
+        }
+    }
+
+    fn pipe(&mut self, direction: Direction) -> &mut Pipe {
+        match direction {
+            Direction::Up => &mut self.up,
+            Direction::Down => &mut self.down,
+        }
+    }
+
+    // Reads from the side `direction` starts at, holding what it read
+    // back for the latency. Returns whether anything was read.
+    fn read(&mut self, direction: Direction, chaos: &Chaos) -> bool {
+        let mut chunk = [0; 4_096];
+        let mut read = false;
+
+        let (socket, pipe, latency) = match direction {
+            Direction::Up => (&mut self.client, &mut self.up, chaos.up_ms),
+            Direction::Down => (&mut self.upstream, &mut self.down, chaos.down_ms),
+        };
+
+        // The socket is registered as edge triggered, drain it. Or at least
+        // until enough is held back for the other side.
+        while !pipe.eof && pipe.buffered() < MAX_BUFFERED {
+            match socket.try_read(&mut chunk) {
+                Ok(Some(0)) => pipe.eof = true,
+                Ok(Some(n)) => {
+                    let jitter = if chaos.jitter_ms > 0 { rand::random::<u64>() % (chaos.jitter_ms + 1) } else { 0 };
+
+                    pipe.hold(chunk[..n].to_vec(), Duration::from_millis(latency + jitter));
+                    pipe.bytes += n as u64;
+                    read = true;
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    println!("got an error trying to read; token={:?}; direction={:?}; err={:?}", self.token, direction, e);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        // Without latency, it is due right away
+        pipe.release(Instant::now());
+        read
+    }
+
+    // Writes what is due to the side `direction` ends at
+    fn write(&mut self, direction: Direction, chaos: &Chaos) {
+        let (socket, pipe) = match direction {
+            Direction::Up => (&mut self.upstream, &mut self.up),
+            Direction::Down => (&mut self.client, &mut self.down),
+        };
+
+        while !pipe.out.is_empty() {
+            // A single piece, the next one is written once the socket is
+            // reported writable again
+            let len = match chaos.max_write {
+                Some(max) => cmp::min(pipe.out.len(), rand::random::<usize>() % max + 1),
+                None => pipe.out.len(),
+            };
+
+            match pipe.out.write_prefix_to(socket, len) {
+                Ok(Some(_)) => {}
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; token={:?}; direction={:?}; err={:?}", self.token, direction, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+
+            if chaos.max_write.is_some() {
+                return;
+            }
+        }
+
+        // The side it is read from is done, and all it said was passed on
+        if pipe.eof && pipe.queue.is_empty() && !pipe.shut {
+            let _ = socket.shutdown(Shutdown::Write);
+            pipe.shut = true;
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.closed || self.up.shut && self.down.shut
+    }
+
+    fn client_interest(&self) -> mio::EventSet {
+        interest(self.connected, &self.up, &self.down)
+    }
+
+    fn upstream_interest(&self) -> mio::EventSet {
+        if !self.connected {
+            return mio::EventSet::writable();
+        }
+
+        interest(self.connected, &self.down, &self.up)
+    }
+}
+
+// The interest of a socket, given the pipe it is read into, and the pipe
+// it is written from
+fn interest(connected: bool, from: &Pipe, to: &Pipe) -> mio::EventSet {
+    let mut interest = mio::EventSet::none();
+
+    if connected && !from.eof && from.buffered() < MAX_BUFFERED {
+        interest = interest | mio::EventSet::readable();
+    }
+
+    if !to.out.is_empty() {
+        interest = interest | mio::EventSet::writable();
+    }
+
+    interest
+}
+
+// Closes the connection with a reset instead of the usual FIN: with a zero
+// linger time, whatever wasn't sent yet is thrown away, and the peer's
+// next read or write fails with "connection reset by peer"
+fn reset(socket: &TcpStream) {
+    let linger = libc::linger {
+        l_onoff: 1,
+        l_linger: 0,
+    };
+
+    unsafe {
+        libc::setsockopt(socket.as_raw_fd(),
+                         libc::SOL_SOCKET,
+                         libc::SO_LINGER,
+                         &linger as *const libc::linger as *const libc::c_void,
+                         mem::size_of::<libc::linger>() as libc::socklen_t);
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000 + duration.subsec_millis() as u64
+}
+
+fn usage() -> ! {
+    println!("usage: chaos_proxy <addr> <upstream> [up=ms] [down=ms] [jitter=ms] [reset=odds] [write=bytes]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().and_then(|addr| addr.parse().ok()).unwrap_or_else(|| usage());
+    let upstream: SocketAddr = args.next().and_then(|addr| addr.parse().ok()).unwrap_or_else(|| usage());
+
+    let mut chaos = Chaos {
+        up_ms: 0,
+        down_ms: 0,
+        jitter_ms: 0,
+        reset: 0.0,
+        max_write: None,
+    };
+
+    // The faults are given as `name=value`
+    for arg in args {
+        let mut parts = arg.splitn(2, '=');
+        let (name, value) = (parts.next().unwrap(), parts.next().unwrap_or(""));
+
+        let ok = match name {
+            "up" => value.parse().map(|ms| chaos.up_ms = ms).is_ok(),
+            "down" => value.parse().map(|ms| chaos.down_ms = ms).is_ok(),
+            "jitter" => value.parse().map(|ms| chaos.jitter_ms = ms).is_ok(),
+            "reset" => value.parse().map(|odds| chaos.reset = odds).is_ok(),
+            "write" => value.parse().ok().filter(|&max| max > 0).map(|max| chaos.max_write = Some(max)).is_some(),
+            _ => false,
+        };
+
+        if !ok {
+            println!("invalid option; option={:?}", arg);
+            usage();
+        }
+    }
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    // The default timer tick is 100ms, too coarse for latencies in the
+    // tens of milliseconds
+    let config = mio::EventLoopConfig {
+        timer_tick_ms: 10,
+        ..mio::EventLoopConfig::default()
+    };
+
+    let mut event_loop = mio::EventLoop::configured(config).unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    println!("running chaos proxy; addr={:?}; upstream={:?}; chaos={:?}", address, upstream, chaos);
+
+    let mut proxy = Proxy::new(server, upstream, chaos);
+    event_loop.run(&mut proxy).unwrap();
+}
@@ -0,0 +1,458 @@
+extern crate mio;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::collections::BTreeMap;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use std::{env, process};
+
+// Connections are opened on a timer ticking this often, as many at each
+// tick as the rate calls for by then
+const TICK_MS: u64 = 10;
+
+// How often progress is reported while the test runs
+const REPORT_MS: u64 = 1_000;
+
+// How long a connection may take, from the connect to the first bytes of
+// the answer
+const TIMEOUT_MS: u64 = 5_000;
+
+// Connections in flight at once. Past that the server isn't keeping up,
+// and the connections that can't be opened are counted as skipped.
+const MAX_CONNECTIONS: usize = 8_192;
+
+enum Timer {
+    // Time to open the connections due
+    Tick,
+    Report,
+    // The connection took too long
+    Deadline(mio::Token),
+}
+
+struct Connection {
+    socket: TcpStream,
+    started: Instant,
+    connected: bool,
+    // What is left of the request to write
+    out: Vec<u8>,
+    deadline: mio::Timeout,
+}
+
+// What a connection got to, each time it is ready
+enum Progress {
+    Pending,
+    // The handshake completed, after this many milliseconds
+    Connected(f64),
+    // The first bytes of the answer came in, the connection is done
+    Answered(f64),
+    Failed(String),
+}
+
+// Counts since the start
+#[derive(Default)]
+struct Stats {
+    opened: u64,
+    connected: u64,
+    answered: u64,
+    failed: u64,
+    skipped: u64,
+    // In milliseconds, in the order they were measured
+    connect: Vec<f64>,
+    answer: Vec<f64>,
+    errors: BTreeMap<String, u64>,
+}
+
+// Where the stats were at the previous report, each report is about what
+// happened since
+#[derive(Default)]
+struct Mark {
+    opened: u64,
+    connected: u64,
+    answered: u64,
+    failed: u64,
+    skipped: u64,
+    // The number of measures
+    connect: usize,
+    answer: usize,
+}
+
+// Opens connections at a steady rate, and closes each as soon as it is
+// done: once connected, or, with a request to send, once the first bytes
+// of the answer come in. Every connection is a new socket, accepted by
+// the server and removed from its slab, so the accept path and the
+// reuse of tokens get exercised far more than with long lived
+// connections.
+struct Churn {
+    addr: SocketAddr,
+    rate: u64,
+    duration_ms: u64,
+    request: Option<Vec<u8>>,
+    connections: Slab<Connection>,
+    started: Instant,
+    // Set once the duration is over, the connections in flight are
+    // waited for
+    stopped: bool,
+    stats: Stats,
+    reported: Mark,
+}
+
+impl Churn {
+    // Opens the connections due since the start
+    fn tick(&mut self, event_loop: &mut mio::EventLoop<Churn>) {
+        let elapsed_ms = millis(self.started.elapsed()) as u64;
+
+        if elapsed_ms >= self.duration_ms {
+            self.stopped = true;
+            return self.check_done(event_loop);
+        }
+
+        // Connections fall behind when the event loop is busy, they are
+        // caught up with at the next tick
+        let due = elapsed_ms * self.rate / 1_000 + 1;
+
+        while self.stats.opened < due {
+            self.open(event_loop);
+        }
+
+        event_loop.timeout_ms(Timer::Tick, TICK_MS).unwrap();
+    }
+
+    fn open(&mut self, event_loop: &mut mio::EventLoop<Churn>) {
+        self.stats.opened += 1;
+
+        if !self.connections.has_remaining() {
+            self.stats.skipped += 1;
+            return;
+        }
+
+        // The clock starts before the connect: the SYN is sent before the
+        // call returns
+        let started = Instant::now();
+
+        let socket = match TcpStream::connect(&self.addr) {
+            Ok(socket) => socket,
+            Err(e) => return self.stats.fail(e.to_string()),
+        };
+
+        let out = self.request.clone().unwrap_or_default();
+
+        let token = self.connections.insert_with(|token| {
+            Connection {
+                socket: socket,
+                started: started,
+                connected: false,
+                out: out,
+                deadline: event_loop.timeout_ms(Timer::Deadline(token), TIMEOUT_MS).unwrap(),
+            }
+        }).unwrap();
+
+        // The socket becomes writable once the handshake completes, or
+        // fails
+        event_loop.register_opt(&self.connections[token].socket, token, mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    // Dropping the connection closes it
+    fn close(&mut self, event_loop: &mut mio::EventLoop<Churn>, token: mio::Token) {
+        let conn = self.connections.remove(token).unwrap();
+        event_loop.clear_timeout(conn.deadline);
+
+        self.check_done(event_loop);
+    }
+
+    fn check_done(&mut self, event_loop: &mut mio::EventLoop<Churn>) {
+        if self.stopped && self.connections.count() == 0 {
+            self.report();
+            self.summary();
+            event_loop.shutdown();
+        }
+    }
+
+    fn report(&mut self) {
+        let (stats, last) = (&self.stats, &self.reported);
+
+        println!("{:>4}s; opened={}; connected={}; answered={}; failed={}; skipped={}; in flight={}; connect {}; answer {}",
+                 self.started.elapsed().as_secs(),
+                 stats.opened - last.opened,
+                 stats.connected - last.connected,
+                 stats.answered - last.answered,
+                 stats.failed - last.failed,
+                 stats.skipped - last.skipped,
+                 self.connections.count(),
+                 Percentiles(&stats.connect[last.connect..]),
+                 Percentiles(&stats.answer[last.answer..]));
+
+        self.reported = Mark {
+            opened: stats.opened,
+            connected: stats.connected,
+            answered: stats.answered,
+            failed: stats.failed,
+            skipped: stats.skipped,
+            connect: stats.connect.len(),
+            answer: stats.answer.len(),
+        };
+    }
+
+    fn summary(&self) {
+        let stats = &self.stats;
+        let secs = millis(self.started.elapsed()) / 1_000.0;
+
+        println!();
+        println!("--- {} churn statistics ---", self.addr);
+        println!("{} opened in {:.1}s, {:.0}/s; {} connected, {} answered, {} failed, {} skipped",
+                 stats.opened, secs, stats.opened as f64 / secs, stats.connected, stats.answered, stats.failed, stats.skipped);
+        println!("connect ms {}", Summary(&stats.connect));
+
+        if self.request.is_some() {
+            println!("answer ms {}", Summary(&stats.answer));
+        }
+
+        for (err, count) in &stats.errors {
+            println!("  {} x {}", count, err);
+        }
+    }
+}
+
+impl Stats {
+    fn fail(&mut self, err: String) {
+        self.failed += 1;
+        *self.errors.entry(err).or_insert(0) += 1;
+    }
+}
+
+impl mio::Handler for Churn {
+    type Timeout = Timer;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Churn>, token: mio::Token, events: mio::EventSet) {
+        let progress = match self.connections.get_mut(token) {
+            Some(conn) => ready(conn, events),
+            None => return,
+        };
+
+        match progress {
+            Progress::Pending => {}
+            Progress::Connected(ms) => {
+                self.stats.connected += 1;
+                self.stats.connect.push(ms);
+
+                // Without a request to send, the handshake was all that
+                // was needed
+                if self.request.is_none() {
+                    return self.close(event_loop, token);
+                }
+            }
+            Progress::Answered(ms) => {
+                self.stats.answered += 1;
+                self.stats.answer.push(ms);
+                return self.close(event_loop, token);
+            }
+            Progress::Failed(err) => {
+                self.stats.fail(err);
+                return self.close(event_loop, token);
+            }
+        }
+
+        let conn = &self.connections[token];
+
+        // Once the request is written, only reading is left
+        let interest = if !conn.connected || !conn.out.is_empty() {
+            mio::EventSet::writable()
+        } else {
+            mio::EventSet::readable()
+        };
+
+        event_loop.reregister(&conn.socket, token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Churn>, timer: Timer) {
+        match timer {
+            Timer::Tick => self.tick(event_loop),
+            Timer::Report => {
+                if !self.stopped {
+                    self.report();
+                    event_loop.timeout_ms(Timer::Report, REPORT_MS).unwrap();
+                }
+            }
+            Timer::Deadline(token) => {
+                let err = if self.connections[token].connected {
+                    "timed out waiting for an answer"
+                } else {
+                    "timed out connecting"
+                };
+
+                // The deadline fired, there is nothing to clear
+                self.connections.remove(token);
+                self.stats.fail(err.to_string());
+                self.check_done(event_loop);
+            }
+        }
+    }
+}
+
+// Completes the handshake, writes the request and reads the first bytes
+// of the answer, whichever is next
+fn ready(conn: &mut Connection, events: mio::EventSet) -> Progress {
+    if !conn.connected {
+        // A failed handshake is reported as writable too, the socket's
+        // pending error tells the two apart
+        if let Err(e) = conn.socket.take_socket_error() {
+            return Progress::Failed(e.to_string());
+        }
+
+        if events.is_error() || events.is_hup() {
+            return Progress::Failed("connection failed".to_string());
+        }
+
+        conn.connected = true;
+
+        // The request, if any, is written right away: the socket is
+        // writable
+        if let Some(progress) = write(conn) {
+            return progress;
+        }
+
+        return Progress::Connected(millis(conn.started.elapsed()));
+    }
+
+    if events.is_writable() {
+        if let Some(progress) = write(conn) {
+            return progress;
+        }
+    }
+
+    if events.is_readable() {
+        let mut buf = [0; 4_096];
+
+        // The first bytes are all that is waited for, what else the
+        // server sends goes away with the connection
+        return match conn.socket.try_read(&mut buf) {
+            Ok(Some(0)) => Progress::Failed("closed without an answer".to_string()),
+            Ok(Some(_)) => Progress::Answered(millis(conn.started.elapsed())),
+            Ok(None) => Progress::Pending,
+            Err(e) => Progress::Failed(e.to_string()),
+        };
+    }
+
+    Progress::Pending
+}
+
+// Returns `Some` only if writing failed
+fn write(conn: &mut Connection) -> Option<Progress> {
+    while !conn.out.is_empty() {
+        match conn.socket.try_write(&conn.out) {
+            Ok(Some(n)) => {
+                conn.out.drain(..n);
+            }
+            Ok(None) => return None,
+            Err(e) => return Some(Progress::Failed(e.to_string())),
+        }
+    }
+
+    None
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1_000.0 + duration.subsec_nanos() as f64 / 1e6
+}
+
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+fn sorted(measures: &[f64]) -> Vec<f64> {
+    let mut sorted = measures.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    sorted
+}
+
+// The median and 99th percentile, for the progress reports
+struct Percentiles<'a>(&'a [f64]);
+
+impl<'a> std::fmt::Display for Percentiles<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "p50=- p99=-");
+        }
+
+        let sorted = sorted(self.0);
+        write!(f, "p50={:.2}ms p99={:.2}ms", percentile(&sorted, 0.5), percentile(&sorted, 0.99))
+    }
+}
+
+// Of all the measures, for the summary
+struct Summary<'a>(&'a [f64]);
+
+impl<'a> std::fmt::Display for Summary<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "none measured");
+        }
+
+        let sorted = sorted(self.0);
+
+        write!(f, "min/p50/p90/p99/max = {:.3}/{:.3}/{:.3}/{:.3}/{:.3}",
+               sorted[0], percentile(&sorted, 0.5), percentile(&sorted, 0.9), percentile(&sorted, 0.99), sorted[sorted.len() - 1])
+    }
+}
+
+// `\r` and `\n` in the request argument stand for the characters, so
+// that an HTTP request can be written on the command line
+fn unescape(s: &str) -> Vec<u8> {
+    s.replace("\\r", "\r").replace("\\n", "\n").into_bytes()
+}
+
+fn usage() -> ! {
+    println!("usage: churn <host:port> [connections per second] [seconds] [request]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    // Host names are resolved once, with the blocking resolver, before the
+    // event loop starts
+    let addr = match args.next().and_then(|target| target.to_socket_addrs().ok()).and_then(|mut addrs| addrs.next()) {
+        Some(addr) => addr,
+        None => usage(),
+    };
+
+    let rate: u64 = args.next().map(|s| s.parse().unwrap_or_else(|_| usage())).unwrap_or(100);
+    let secs: u64 = args.next().map(|s| s.parse().unwrap_or_else(|_| usage())).unwrap_or(10);
+    let request = args.next().map(|s| unescape(&s));
+
+    if rate == 0 || secs == 0 {
+        usage();
+    }
+
+    // The default timer tick is 100ms, too coarse to spread the
+    // connections over each second
+    let config = mio::EventLoopConfig {
+        timer_tick_ms: TICK_MS,
+        ..mio::EventLoopConfig::default()
+    };
+
+    let mut event_loop = mio::EventLoop::configured(config).unwrap();
+
+    event_loop.timeout_ms(Timer::Tick, 0).unwrap();
+    event_loop.timeout_ms(Timer::Report, REPORT_MS).unwrap();
+
+    println!("CHURN {}; rate={}/s; duration={}s; request={}", addr, rate, secs, request.is_some());
+
+    let mut churn = Churn {
+        addr: addr,
+        rate: rate,
+        duration_ms: secs * 1_000,
+        request: request,
+        connections: Slab::new(MAX_CONNECTIONS),
+        started: Instant::now(),
+        stopped: false,
+        stats: Stats::default(),
+        reported: Mark::default(),
+    };
+
+    event_loop.run(&mut churn).unwrap();
+}
@@ -0,0 +1,132 @@
+extern crate mio;
+extern crate bytes;
+extern crate codec;
+
+use codec::connection::Connection;
+use codec::length_delimited::LengthDelimited;
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// The default for the largest frame accepted
+const MAX_FRAME: usize = 64 * 1_024;
+
+struct FrameServer {
+    server: TcpListener,
+    connections: Slab<Connection<TcpStream, LengthDelimited>>,
+    max_frame: usize,
+}
+
+impl FrameServer {
+    fn new(server: TcpListener, max_frame: usize) -> FrameServer {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        FrameServer {
+            server: server,
+            connections: slab,
+            max_frame: max_frame,
+        }
+    }
+}
+
+impl mio::Handler for FrameServer {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<FrameServer>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                match self.server.accept() {
+                    Ok(Some(socket)) => {
+                        let max_frame = self.max_frame;
+
+                        let token = match self.connections.insert_with(|token| Connection::new(socket, token, LengthDelimited::new(max_frame))) {
+                            Some(token) => token,
+                            None => {
+                                println!("connection limit reached, dropping client");
+                                return;
+                            }
+                        };
+
+                        self.connections[token].register(event_loop);
+                    }
+                    Ok(None) => {
+                        println!("the server socket wasn't actually ready");
+                    }
+                    Err(e) => {
+                        println!("encountered error while accepting connection; err={:?}", e);
+                        event_loop.shutdown();
+                    }
+                }
+            }
+            _ => {
+                let connection = &mut self.connections[token];
+
+                if events.is_readable() {
+                    for request in connection.read() {
+                        connection.send(respond(&request));
+                    }
+                }
+
+                // A frame over the maximum closes the connection. It is too
+                // large to buffer, and skipping it would still mean reading
+                // all of it.
+                connection.flush(event_loop);
+
+                if connection.closed {
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+}
+
+// Each request is a command, a space and an argument, as text. The
+// response starts with `OK` or `ERR`:
+//
+// UPPER hello    ->  OK HELLO
+// REVERSE hello  ->  OK olleh
+// LEN hello      ->  OK 5
+//
+// The frames themselves are binary, the argument may hold anything.
+fn respond(request: &[u8]) -> Vec<u8> {
+    let (command, arg) = match request.iter().position(|b| *b == b' ') {
+        Some(pos) => (&request[..pos], &request[pos + 1..]),
+        None => (request, &b""[..]),
+    };
+
+    let mut response = b"OK ".to_vec();
+
+    match command {
+        b"UPPER" => response.extend(arg.to_ascii_uppercase()),
+        b"REVERSE" => response.extend(arg.iter().rev()),
+        b"LEN" => response.extend(arg.len().to_string().as_bytes()),
+        _ => return format!("ERR unknown command {:?}", String::from_utf8_lossy(command)).into_bytes(),
+    }
+
+    response
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:9300".to_string()).parse().unwrap();
+    let max_frame: usize = args.next().map(|s| s.parse().unwrap()).unwrap_or(MAX_FRAME);
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    let mut server = FrameServer::new(server, max_frame);
+
+    println!("running frame server; addr={:?}; max frame={}", address, max_frame);
+    event_loop.run(&mut server).unwrap();
+}
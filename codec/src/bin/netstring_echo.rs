@@ -0,0 +1,186 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+extern crate codec;
+
+use acceptor::{Acceptor, Factory};
+use codec::{Decoder, Encoder};
+use codec::netstring::Netstring;
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// The longest netstring accepted
+const MAX_LEN: usize = 64 * 1_024;
+
+struct Echo {
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+}
+
+impl Echo {
+    fn new(acceptor: Acceptor<Factory<Connection>>) -> Echo {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Echo {
+            acceptor: acceptor,
+            connections: slab,
+        }
+    }
+}
+
+impl mio::Handler for Echo {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Echo>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
+                }
+            }
+            _ => {
+                self.connections[token].ready(event_loop, events);
+
+                if self.connections[token].closed {
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    codec: Netstring,
+    // Bytes read but not yet decoded
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            codec: Netstring::new(MAX_LEN),
+            buf: vec![],
+            out: vec![],
+            closed: false,
+        }
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Echo>, events: mio::EventSet) {
+        if events.is_readable() {
+            self.read();
+        }
+
+        if !self.closed {
+            self.write();
+        }
+
+        if !self.closed {
+            let interest = if self.out.is_empty() {
+                mio::EventSet::readable()
+            } else {
+                mio::EventSet::readable() | mio::EventSet::writable()
+            };
+
+            event_loop.reregister(&self.socket, self.token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn read(&mut self) {
+        let mut buf = [0; 4096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    loop {
+                        match self.codec.decode(&mut self.buf) {
+                            Ok(Some(data)) => {
+                                println!("echoing netstring; len={}; token={:?}", data.len(), self.token);
+                                self.codec.encode(data, &mut self.out);
+                            }
+                            Ok(None) => break,
+                            Err(e) => {
+                                // Netstrings have no way to report an error,
+                                // all the server can do is hang up.
+                                println!("invalid netstring, closing connection; err={:?}; token={:?}", e, self.token);
+                                self.closed = true;
+                                return;
+                            }
+                        }
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:9200".to_string())
+        .parse().unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mut echo = Echo::new(acceptor);
+
+    println!("running netstring echo server; addr={:?}; max len={}", address, MAX_LEN);
+    event_loop.run(&mut echo).unwrap();
+}
@@ -0,0 +1,135 @@
+// A framed connection: a stream socket with a codec on top of it. It holds
+// the buffers between the socket and the codec, and keeps the socket's
+// registration matching what is left to do.
+//
+// Unlike the codecs, this does I/O, but only when the event loop says the
+// socket is ready. It doesn't care what the socket is, as long as mio can
+// poll it: `TcpStream` and `UnixStream` work the same.
+
+use {Decoder, Encoder};
+use mio::{TryRead, TryWrite};
+use std::fmt;
+use std::io::{Read, Write};
+
+pub struct Connection<S, C> {
+    pub socket: S,
+    pub token: mio::Token,
+    pub codec: C,
+    // Bytes read but not yet decoded
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Set once the peer has gone away, or the stream has failed. The owner
+    // should drop the connection.
+    pub closed: bool,
+}
+
+impl<S, C> Connection<S, C>
+    where S: mio::Evented + Read + Write,
+          C: Decoder + Encoder,
+          <C as Decoder>::Error: fmt::Debug,
+{
+    pub fn new(socket: S, token: mio::Token, codec: C) -> Connection<S, C> {
+        Connection {
+            socket: socket,
+            token: token,
+            codec: codec,
+            buf: vec![],
+            out: vec![],
+            closed: false,
+        }
+    }
+
+    pub fn register<H: mio::Handler>(&self, event_loop: &mut mio::EventLoop<H>) {
+        event_loop.register_opt(&self.socket, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    // Reads what the socket has and returns the frames decoded from it. An
+    // invalid frame closes the connection, the frames before it are still
+    // returned.
+    pub fn read(&mut self) -> Vec<<C as Decoder>::Item> {
+        let mut buf = [0; 4096];
+        let mut frames = vec![];
+
+        // The socket is registered as edge triggered, drain it. What was
+        // read is decoded as it comes in, rather than once the socket is
+        // drained, so that a frame larger than the codec allows is refused
+        // before more of it is buffered.
+        while !self.closed {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(Some(n)) => self.buf.extend(&buf[..n]),
+                Ok(None) => break,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    break;
+                }
+            }
+
+            self.decode(&mut frames);
+        }
+
+        frames
+    }
+
+    // Decodes the frames that are buffered in full
+    fn decode(&mut self, frames: &mut Vec<<C as Decoder>::Item>) {
+        loop {
+            match self.codec.decode(&mut self.buf) {
+                Ok(Some(frame)) => frames.push(frame),
+                Ok(None) => break,
+                Err(e) => {
+                    // The stream is no longer at a frame boundary, there is
+                    // no way to carry on.
+                    println!("invalid frame, closing connection; err={:?}; token={:?}", e, self.token);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    // Queues a frame. It is written out by `flush`.
+    pub fn send(&mut self, frame: <C as Encoder>::Item) {
+        self.codec.encode(frame, &mut self.out);
+    }
+
+    // Writes as much of the queued frames as the socket takes, and reregisters
+    // for whatever is left to do.
+    pub fn flush<H: mio::Handler>(&mut self, event_loop: &mut mio::EventLoop<H>) {
+        while !self.closed && !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                }
+            }
+        }
+
+        if !self.closed {
+            event_loop.reregister(&self.socket, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    // Nothing left to write
+    pub fn is_flushed(&self) -> bool {
+        self.out.is_empty()
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
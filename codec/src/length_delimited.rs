@@ -0,0 +1,73 @@
+// Frames prefixed by their length as a big endian u32:
+//
+// +--------+--------+--------+--------+--------------------+
+// |          length (4 bytes)         |  payload (length)  |
+// +--------+--------+--------+--------+--------------------+
+//
+// The length doesn't include the prefix itself. This is the framing most
+// binary protocols end up with, as it is simple and the payload can be
+// anything.
+
+use {Decoder, Encoder};
+
+const PREFIX: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    // The length in the prefix is larger than the codec allows
+    TooLarge(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct LengthDelimited {
+    max_frame: usize,
+}
+
+impl LengthDelimited {
+    // Frames longer than `max_frame` are refused as soon as their prefix
+    // has been read, before any of the payload is buffered.
+    pub fn new(max_frame: usize) -> LengthDelimited {
+        LengthDelimited { max_frame: max_frame }
+    }
+}
+
+impl Decoder for LengthDelimited {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>, Error> {
+        if buf.len() < PREFIX {
+            return Ok(None);
+        }
+
+        let len = buf[..PREFIX].iter().fold(0, |n, b| n << 8 | *b as usize);
+
+        if len > self.max_frame {
+            return Err(Error::TooLarge(len));
+        }
+
+        if buf.len() < PREFIX + len {
+            return Ok(None);
+        }
+
+        let payload = buf[PREFIX..PREFIX + len].to_vec();
+        buf.drain(..PREFIX + len);
+
+        Ok(Some(payload))
+    }
+}
+
+impl Encoder for LengthDelimited {
+    type Item = Vec<u8>;
+
+    // Panics if the payload doesn't fit in the prefix. The limit applies to
+    // what is received, a peer is expected to know its own.
+    fn encode(&mut self, payload: Vec<u8>, dst: &mut Vec<u8>) {
+        assert!(payload.len() <= u32::MAX as usize, "frame too large for a u32 length");
+
+        let len = payload.len() as u32;
+
+        dst.extend(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]);
+        dst.extend(payload);
+    }
+}
@@ -0,0 +1,36 @@
+// Framing for byte streams. TCP delivers bytes, not messages: a read may
+// return half a message, or several. A codec turns the bytes read so far
+// into whole frames, and frames back into bytes.
+//
+// Like the rest of the examples' protocol code, codecs do no I/O. The
+// event loop appends whatever it reads to a buffer and hands it to the
+// decoder, and writes out whatever the encoder appends to the output
+// buffer. The servers in `src/bin` each use one of the codecs.
+//
+// `Connection` is the event loop side of it, shared by the examples that
+// exchange frames over a stream socket.
+
+extern crate mio;
+
+pub mod connection;
+pub mod length_delimited;
+pub mod line;
+pub mod netstring;
+
+pub trait Decoder {
+    type Item;
+    type Error;
+
+    // Decodes the frame at the start of `buf`, removing it from the buffer.
+    // Returns `None`, and leaves the buffer alone, if the frame hasn't been
+    // fully received. Once an error is returned, the stream can't be
+    // trusted to be at a frame boundary anymore.
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Self::Item>, Self::Error>;
+}
+
+pub trait Encoder {
+    type Item;
+
+    // Appends the encoded frame to `dst`
+    fn encode(&mut self, item: Self::Item, dst: &mut Vec<u8>);
+}
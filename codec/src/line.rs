@@ -0,0 +1,64 @@
+// Lines, as in most text protocols: SMTP, POP3, IRC, memcached... A line
+// ends with `\n`, usually preceded by `\r`, which the decoder strips as
+// well. The encoder always ends lines with `\r\n`, which is what the
+// protocols that specify an ending ask for.
+//
+// A line has no length up front, so the decoder can only tell a line is
+// too long once that much has been buffered without finding its end.
+
+use {Decoder, Encoder};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    // No line ending within the maximum length
+    TooLong,
+}
+
+#[derive(Debug, Clone)]
+pub struct Line {
+    max_len: usize,
+}
+
+impl Line {
+    // Lines longer than `max_len`, not counting the line ending, are
+    // refused
+    pub fn new(max_len: usize) -> Line {
+        Line { max_len: max_len }
+    }
+}
+
+impl Decoder for Line {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>, Error> {
+        // The `\r\n` of a line of the maximum length is two bytes past it
+        let end = match buf.iter().take(self.max_len + 2).position(|&b| b == b'\n') {
+            Some(end) => end,
+            None if buf.len() > self.max_len + 1 => return Err(Error::TooLong),
+            None => return Ok(None),
+        };
+
+        let mut line: Vec<u8> = buf.drain(..end + 1).collect();
+        line.pop();
+
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+
+        if line.len() > self.max_len {
+            return Err(Error::TooLong);
+        }
+
+        Ok(Some(line))
+    }
+}
+
+impl Encoder for Line {
+    type Item = Vec<u8>;
+
+    fn encode(&mut self, line: Vec<u8>, dst: &mut Vec<u8>) {
+        dst.extend(line);
+        dst.extend(b"\r\n");
+    }
+}
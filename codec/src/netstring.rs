@@ -0,0 +1,177 @@
+// Netstrings (http://cr.yp.to/proto/netstrings.txt) prefix the data with
+// its length in decimal, and end it with a comma:
+//
+// 5:hello,
+// 0:,
+//
+// The length comes first, so the data may contain anything, commas
+// included. The trailing comma doesn't delimit anything, it is a check
+// that the sender and receiver agree on where the frame ends.
+
+use {Decoder, Encoder};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    // The length isn't a decimal number followed by `:`, or has leading
+    // zeros, which the spec forbids.
+    InvalidLength,
+    // The length is larger than the codec allows
+    TooLarge,
+    // The data isn't followed by `,`
+    MissingComma,
+}
+
+#[derive(Debug, Clone)]
+pub struct Netstring {
+    max_len: usize,
+}
+
+impl Netstring {
+    // Frames longer than `max_len` are refused as soon as their length has
+    // been read, before any data is buffered.
+    pub fn new(max_len: usize) -> Netstring {
+        Netstring { max_len: max_len }
+    }
+
+    // Parses the length, returning it and the position of the data. The
+    // number of digits is bounded by `max_len`, so a peer can't make the
+    // decoder buffer an endless run of digits.
+    fn length(&self, buf: &[u8]) -> Result<Option<(usize, usize)>, Error> {
+        let max_digits = self.max_len.to_string().len();
+        let mut len: usize = 0;
+
+        for (i, byte) in buf.iter().enumerate() {
+            match *byte {
+                b':' if i == 0 => return Err(Error::InvalidLength),
+                b':' => return Ok(Some((len, i + 1))),
+                b'0'..=b'9' => {
+                    if i == 1 && buf[0] == b'0' {
+                        return Err(Error::InvalidLength);
+                    }
+
+                    if i == max_digits {
+                        return Err(Error::TooLarge);
+                    }
+
+                    len = len * 10 + (*byte - b'0') as usize;
+
+                    if len > self.max_len {
+                        return Err(Error::TooLarge);
+                    }
+                }
+                _ => return Err(Error::InvalidLength),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Decoder for Netstring {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>, Error> {
+        let (len, start) = match self.length(buf)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+
+        // The data, then the comma
+        if buf.len() < start + len + 1 {
+            return Ok(None);
+        }
+
+        if buf[start + len] != b',' {
+            return Err(Error::MissingComma);
+        }
+
+        let data = buf[start..start + len].to_vec();
+        buf.drain(..start + len + 1);
+
+        Ok(Some(data))
+    }
+}
+
+impl Encoder for Netstring {
+    type Item = Vec<u8>;
+
+    fn encode(&mut self, data: Vec<u8>, dst: &mut Vec<u8>) {
+        dst.extend(format!("{}:", data.len()).as_bytes());
+        dst.extend(data);
+        dst.push(b',');
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(codec: &mut Netstring, buf: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        codec.decode(&mut buf.to_vec())
+    }
+
+    #[test]
+    fn decodes_frames() {
+        let mut codec = Netstring::new(16);
+        let mut buf = b"5:hello,0:,3:a,b,".to_vec();
+
+        assert_eq!(codec.decode(&mut buf), Ok(Some(b"hello".to_vec())));
+        assert_eq!(codec.decode(&mut buf), Ok(Some(vec![])));
+        assert_eq!(codec.decode(&mut buf), Ok(Some(b"a,b".to_vec())));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn rejects_non_digit_length() {
+        let mut codec = Netstring::new(16);
+
+        assert_eq!(decode(&mut codec, b"5x:hello,"), Err(Error::InvalidLength));
+        assert_eq!(decode(&mut codec, b":hello,"), Err(Error::InvalidLength));
+        assert_eq!(decode(&mut codec, b"05:hello,"), Err(Error::InvalidLength));
+    }
+
+    #[test]
+    fn waits_for_colon() {
+        let mut codec = Netstring::new(16);
+        let mut buf = b"12".to_vec();
+
+        assert_eq!(codec.decode(&mut buf), Ok(None));
+        assert_eq!(buf, b"12");
+        assert_eq!(decode(&mut codec, b"5hello,"), Err(Error::InvalidLength));
+    }
+
+    #[test]
+    fn rejects_missing_comma() {
+        let mut codec = Netstring::new(16);
+
+        assert_eq!(decode(&mut codec, b"5:hello;"), Err(Error::MissingComma));
+        assert_eq!(decode(&mut codec, b"3:hello,"), Err(Error::MissingComma));
+    }
+
+    #[test]
+    fn rejects_length_over_max() {
+        let mut codec = Netstring::new(16);
+
+        assert_eq!(decode(&mut codec, b"16:"), Ok(None));
+        assert_eq!(decode(&mut codec, b"17:"), Err(Error::TooLarge));
+        // Refused before the colon, or any data, is there
+        assert_eq!(decode(&mut codec, b"17"), Err(Error::TooLarge));
+        assert_eq!(decode(&mut codec, b"100"), Err(Error::TooLarge));
+    }
+
+    #[test]
+    fn decodes_frame_split_across_reads() {
+        let mut codec = Netstring::new(16);
+        let mut buf = vec![];
+
+        for chunk in [&b"1"[..], b"1:hello", b" worl", b"d", b",5:"].iter() {
+            assert_eq!(codec.decode(&mut buf), Ok(None));
+            buf.extend_from_slice(chunk);
+        }
+
+        assert_eq!(codec.decode(&mut buf), Ok(Some(b"hello world".to_vec())));
+        assert_eq!(buf, b"5:");
+        assert_eq!(codec.decode(&mut buf), Ok(None));
+    }
+}
@@ -0,0 +1,724 @@
+extern crate mio;
+extern crate pump;
+extern crate resolver;
+
+mod throttle;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use pump::{Pipe, Side};
+use resolver::{Answer, Deadline, Resolve, Resolver};
+use std::cmp;
+use std::net::SocketAddr;
+use std::time::Instant;
+use std::env;
+use throttle::Budget;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Tokens `1..=MAX_TUNNELS` are used for client connections, and the
+// connection to a client's target is its token plus `MAX_TUNNELS`.
+const MAX_TUNNELS: usize = 1_024;
+
+// A request head larger than this is refused
+const MAX_HEAD: usize = 8 * 1_024;
+
+// A target not accepting the connection within this long, or whose name
+// takes this long to resolve, gets the client a 504
+const CONNECT_TIMEOUT_MS: u64 = 10_000;
+const RESOLVE_TIMEOUT_MS: u64 = 5_000;
+
+// The names of the targets are resolved on threads of their own, this
+// many, and the last ones are cached
+const RESOLVER_THREADS: usize = 4;
+const RESOLVER_CACHE: usize = 1_024;
+
+// How often the transfer budgets are released, when rates are capped
+const REFILL_MS: u64 = 100;
+
+#[derive(Clone, Copy)]
+enum Timer {
+    // The tunnel whose target is taking too long to connect
+    Connect(mio::Token),
+    // Time to release the next budgets
+    Refill,
+    // A target's name is taking too long to resolve
+    Resolve(Deadline),
+}
+
+impl From<Deadline> for Timer {
+    fn from(deadline: Deadline) -> Timer {
+        Timer::Resolve(deadline)
+    }
+}
+
+// An HTTP proxy for the CONNECT method, the way browsers reach HTTPS sites
+// through a proxy. The client asks for a tunnel to a host and port:
+//
+//     CONNECT example.com:443 HTTP/1.1
+//     Host: example.com:443
+//
+// The proxy connects to it, answers `200 Connection Established`, and from
+// then on relays bytes both ways without looking at them, whatever the
+// protocol inside the tunnel is. Each tunnel goes through four states:
+// reading the request, resolving the target's name, connecting to it, and
+// relaying. The name is resolved without blocking the event loop, by the
+// resolver's threads, which send the address back through the loop's
+// channel.
+//
+// The transfer rates can be capped, for each tunnel and for all of them
+// together, to try clients out on a slow link. Every byte read from either
+// side is taken from the budget of the tunnel and from the global one,
+// for its direction. Once either is spent, the side is left unread, not
+// registered as readable, until the timer releases the next budgets. The
+// global budget goes to whichever tunnels get to it first.
+struct Proxy {
+    server: TcpListener,
+    tunnels: Slab<Tunnel>,
+    resolver: Resolver<mio::Token>,
+    // The cap on each tunnel, in bytes per second in each direction
+    rate: Option<u64>,
+    // The global budgets, to the targets and to the clients
+    up: Budget,
+    down: Budget,
+    last_refill: Instant,
+}
+
+impl Proxy {
+    fn new(server: TcpListener, resolver: Resolver<mio::Token>, rate: Option<u64>, global_rate: Option<u64>) -> Proxy {
+        // Token `0` is reserved for the server socket
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_TUNNELS);
+
+        Proxy {
+            server: server,
+            tunnels: slab,
+            resolver: resolver,
+            rate: rate,
+            up: budget(global_rate),
+            down: budget(global_rate),
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Proxy>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        let rate = self.rate;
+
+        let token = match self.tunnels.insert_with(|token| Tunnel::new(socket, token, rate)) {
+            Some(token) => token,
+            None => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        event_loop.register_opt(self.tunnels[token].client(), token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn client_ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            self.tunnels[token].read_client(&mut self.up);
+        }
+
+        if self.tunnels[token].state == State::Request {
+            self.request(event_loop, token);
+        }
+
+        if events.is_writable() {
+            self.tunnels[token].write_client();
+        }
+
+        self.update(event_loop, token);
+    }
+
+    fn target_ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        if self.tunnels[token].state == State::Connecting {
+            self.connected(event_loop, token, events);
+        }
+
+        {
+            let tunnel = &mut self.tunnels[token];
+
+            if tunnel.state == State::Relaying {
+                if events.is_readable() {
+                    tunnel.read_target(&mut self.down);
+                }
+
+                if events.is_writable() {
+                    tunnel.write_target();
+                }
+            }
+        }
+
+        self.update(event_loop, token);
+    }
+
+    // Parses the CONNECT request, once it is all in, and starts resolving
+    // the target's name, or connecting to it right away when its address is
+    // known
+    fn request(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token) {
+        let (host, port) = match self.tunnels[token].take_request() {
+            Ok(Some(target)) => target,
+            Ok(None) => return,
+            Err((status, reason)) => {
+                println!("refusing request; token={:?}; status={}; reason={}", token, status, reason);
+                return self.tunnels[token].fail(status, reason);
+            }
+        };
+
+        self.tunnels[token].target_name = format!("{}:{}", host, port);
+
+        match self.resolver.resolve(event_loop, &host, port, RESOLVE_TIMEOUT_MS, token) {
+            Resolve::Done(token, result) => self.resolved(event_loop, token, result),
+            Resolve::Pending(query) => {
+                let tunnel = &mut self.tunnels[token];
+                tunnel.query = Some(query);
+                tunnel.state = State::Resolving;
+            }
+        }
+    }
+
+    fn resolved(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, result: Result<SocketAddr, resolver::Error>) {
+        self.tunnels[token].query = None;
+
+        let addr = match result {
+            Ok(addr) => addr,
+            Err(resolver::Error::TimedOut) => {
+                println!("target's name didn't resolve in time; token={:?}; target={}", token, self.tunnels[token].target_name);
+                return self.tunnels[token].fail(504, "Gateway Timeout");
+            }
+            Err(e) => {
+                println!("failed to resolve target; token={:?}; target={}; err={}", token, self.tunnels[token].target_name, e);
+                return self.tunnels[token].fail(502, "Bad Gateway");
+            }
+        };
+
+        self.connect(event_loop, token, addr);
+    }
+
+    fn connect(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, addr: SocketAddr) {
+        let socket = match TcpStream::connect(&addr) {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("failed to connect to target; target={}; err={:?}", self.tunnels[token].target_name, e);
+                return self.tunnels[token].fail(502, "Bad Gateway");
+            }
+        };
+
+        println!("connecting; token={:?}; target={}; addr={}", token, self.tunnels[token].target_name, addr);
+
+        // Writable once connected
+        event_loop.register_opt(&socket, target_token(token), mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        let tunnel = &mut self.tunnels[token];
+        tunnel.target = Some(socket);
+        tunnel.timeout = Some(event_loop.timeout_ms(Timer::Connect(token), CONNECT_TIMEOUT_MS).unwrap());
+        tunnel.state = State::Connecting;
+    }
+
+    fn connected(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        let tunnel = &mut self.tunnels[token];
+
+        if events.is_error() || events.is_hup() {
+            println!("failed to connect to target; token={:?}; err={:?}", token, tunnel.target().unwrap().take_socket_error());
+            event_loop.clear_timeout(tunnel.timeout.take().unwrap());
+            return tunnel.fail(502, "Bad Gateway");
+        }
+
+        if !events.is_writable() {
+            return;
+        }
+
+        println!("tunnel established; token={:?}", token);
+        event_loop.clear_timeout(tunnel.timeout.take().unwrap());
+
+        tunnel.establish();
+    }
+
+    // Writes what it can, then reregisters both sockets of the tunnel, or
+    // removes it once it is done
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token) {
+        {
+            let tunnel = &mut self.tunnels[token];
+
+            tunnel.write_client();
+
+            if tunnel.state == State::Relaying {
+                tunnel.write_target();
+            }
+        }
+
+        if self.tunnels[token].is_done() {
+            let tunnel = self.tunnels.remove(token).unwrap();
+
+            if let Some(timeout) = tunnel.timeout {
+                event_loop.clear_timeout(timeout);
+            }
+
+            // The client may leave while the name is being resolved
+            if let Some(query) = tunnel.query {
+                self.resolver.cancel(event_loop, query);
+            }
+
+            let (sent, received) = tunnel.transferred();
+            println!("tunnel closed; token={:?}; sent={}; received={}", token, sent, received);
+            return;
+        }
+
+        self.register(event_loop, token);
+    }
+
+    fn register(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token) {
+        let tunnel = &self.tunnels[token];
+
+        event_loop.reregister(tunnel.client(), token, tunnel.client_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        if let Some(target) = tunnel.target() {
+            event_loop.reregister(target, target_token(token), tunnel.target_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    // Releases the next budgets, and registers the sides left unread for
+    // lack of it as readable again. Whatever they have waiting is reported
+    // right away.
+    fn refill(&mut self, event_loop: &mut mio::EventLoop<Proxy>) {
+        let elapsed = self.last_refill.elapsed();
+        let ms = elapsed.as_secs() * 1_000 + elapsed.subsec_millis() as u64;
+        self.last_refill = Instant::now();
+
+        self.up.refill(ms);
+        self.down.refill(ms);
+
+        let mut paused = vec![];
+
+        for tunnel in self.tunnels.iter_mut() {
+            tunnel.up.refill(ms);
+            tunnel.down.refill(ms);
+
+            if tunnel.client_paused || tunnel.target_paused {
+                tunnel.client_paused = false;
+                tunnel.target_paused = false;
+                paused.push(tunnel.token);
+            }
+        }
+
+        for token in paused {
+            self.register(event_loop, token);
+        }
+    }
+}
+
+impl mio::Handler for Proxy {
+    type Timeout = Timer;
+    type Message = Answer;
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ if token.as_usize() <= MAX_TUNNELS => self.client_ready(event_loop, token, events),
+            _ => {
+                let token = mio::Token(token.as_usize() - MAX_TUNNELS);
+
+                // The target's events may still come in after it is gone,
+                // or even the tunnel
+                if self.tunnels.get(token).map(|tunnel| tunnel.target().is_some()).unwrap_or(false) {
+                    self.target_ready(event_loop, token, events);
+                }
+            }
+        }
+    }
+
+    // A name is resolved, for all the tunnels waiting for it
+    fn notify(&mut self, event_loop: &mut mio::EventLoop<Proxy>, answer: Answer) {
+        for (token, result) in self.resolver.answer(event_loop, answer) {
+            self.resolved(event_loop, token, result);
+            self.update(event_loop, token);
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Proxy>, timer: Timer) {
+        let token = match timer {
+            Timer::Connect(token) => token,
+            Timer::Refill => {
+                self.refill(event_loop);
+                event_loop.timeout_ms(Timer::Refill, REFILL_MS).unwrap();
+                return;
+            }
+            Timer::Resolve(deadline) => {
+                if let Some((token, err)) = self.resolver.expire(deadline) {
+                    self.resolved(event_loop, token, Err(err));
+                    self.update(event_loop, token);
+                }
+
+                return;
+            }
+        };
+
+        println!("target didn't accept the connection in time; token={:?}", token);
+
+        self.tunnels[token].timeout = None;
+        self.tunnels[token].fail(504, "Gateway Timeout");
+        self.update(event_loop, token);
+    }
+}
+
+fn target_token(token: mio::Token) -> mio::Token {
+    mio::Token(token.as_usize() + MAX_TUNNELS)
+}
+
+fn budget(rate: Option<u64>) -> Budget {
+    match rate {
+        Some(rate) => Budget::new(rate, REFILL_MS),
+        None => Budget::unlimited(),
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    // Reading the CONNECT request
+    Request,
+    // Waiting for the target's address
+    Resolving,
+    // Waiting for the target to accept the connection
+    Connecting,
+    // Passing bytes both ways
+    Relaying,
+    // Writing an error response, then closing
+    Failed,
+}
+
+struct Tunnel {
+    token: mio::Token,
+    state: State,
+    // `host:port`, as the client asked for it
+    target_name: String,
+    // The client, and the target once there is one, until the tunnel is
+    // established and they are handed over to the pipe
+    client: Option<TcpStream>,
+    target: Option<TcpStream>,
+    pipe: Option<Pipe>,
+    // The target's name being resolved
+    query: Option<resolver::Query>,
+    timeout: Option<mio::Timeout>,
+    // Read from the client before the tunnel is established: the request
+    // until it is complete, then anything sent without waiting for the
+    // answer, to be written to the target first
+    buf: Vec<u8>,
+    // The error response, once the tunnel failed
+    response: Vec<u8>,
+    client_eof: bool,
+    closed: bool,
+    // The tunnel's budgets, to the target and to the client, and whether
+    // either side is left unread until the next ones
+    up: Budget,
+    down: Budget,
+    client_paused: bool,
+    target_paused: bool,
+}
+
+impl Tunnel {
+    fn new(client: TcpStream, token: mio::Token, rate: Option<u64>) -> Tunnel {
+        Tunnel {
+            token: token,
+            state: State::Request,
+            target_name: String::new(),
+            client: Some(client),
+            target: None,
+            pipe: None,
+            query: None,
+            timeout: None,
+            buf: vec![],
+            response: vec![],
+            client_eof: false,
+            closed: false,
+            up: budget(rate),
+            down: budget(rate),
+            client_paused: false,
+            target_paused: false,
+        }
+    }
+
+    fn client(&self) -> &TcpStream {
+        match self.pipe {
+            Some(ref pipe) => pipe.socket(Side::A),
+            None => self.client.as_ref().unwrap(),
+        }
+    }
+
+    fn target(&self) -> Option<&TcpStream> {
+        match self.pipe {
+            Some(ref pipe) => Some(pipe.socket(Side::B)),
+            None => self.target.as_ref(),
+        }
+    }
+
+    // Returns the target's host and port, once the request head is in, or
+    // the status to refuse the request with
+    fn take_request(&mut self) -> Result<Option<(String, u16)>, (u16, &'static str)> {
+        let end = match self.buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => end,
+            None if self.buf.len() > MAX_HEAD => return Err((431, "Request Header Fields Too Large")),
+            None if self.client_eof => return Err((400, "Bad Request")),
+            None => return Ok(None),
+        };
+
+        let head: Vec<u8> = self.buf.drain(..end + 4).collect();
+        let head = String::from_utf8_lossy(&head);
+
+        let mut request_line = head.lines().next().unwrap_or("").split(' ');
+
+        let (method, target) = match (request_line.next(), request_line.next(), request_line.next()) {
+            (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => (method, target),
+            _ => return Err((400, "Bad Request")),
+        };
+
+        if method != "CONNECT" {
+            return Err((405, "Method Not Allowed"));
+        }
+
+        // The target is always a host and a port, never a URL
+        match target.rfind(':').map(|pos| target[pos + 1..].parse::<u16>()) {
+            Some(Ok(port)) if port > 0 => Ok(Some((target[..target.rfind(':').unwrap()].to_string(), port))),
+            _ => Err((400, "Bad Request")),
+        }
+    }
+
+    fn fail(&mut self, status: u16, reason: &str) {
+        let response = format!("HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status, reason);
+
+        self.response = response.into_bytes();
+        self.buf.clear();
+        self.target = None;
+        self.state = State::Failed;
+    }
+
+    // Hands both sockets over to the pipe, with the answer to the request
+    // first for the client, and what it sent since for the target
+    fn establish(&mut self) {
+        let mut pipe = Pipe::new(self.client.take().unwrap(), self.target.take().unwrap());
+
+        pipe.push(Side::A, b"HTTP/1.1 200 Connection Established\r\n\r\n");
+        pipe.push(Side::B, &self.buf);
+
+        self.buf.clear();
+        self.pipe = Some(pipe);
+        self.state = State::Relaying;
+    }
+
+    fn read_client(&mut self, global: &mut Budget) {
+        if self.state == State::Relaying {
+            return self.relay(Side::A, global);
+        }
+
+        let mut chunk = [0; 4_096];
+        let client = self.client.as_mut().unwrap();
+
+        // The socket is registered as edge triggered, drain it. Or at least
+        // until enough is waiting for the target, or the budget is spent.
+        while self.buf.len() < pump::MAX_BUFFERED {
+            let len = cmp::min(chunk.len(), cmp::min(self.up.available(), global.available()));
+
+            if len == 0 {
+                self.client_paused = true;
+                return;
+            }
+
+            match client.try_read(&mut chunk[..len]) {
+                Ok(Some(0)) => {
+                    self.client_eof = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    self.up.take(n);
+                    global.take(n);
+
+                    if self.state != State::Failed {
+                        self.buf.extend(&chunk[..n]);
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read from the client; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn read_target(&mut self, global: &mut Budget) {
+        self.relay(Side::B, global);
+    }
+
+    // Reads from a side of the established tunnel, as much as the budgets
+    // for its direction allow
+    fn relay(&mut self, side: Side, global: &mut Budget) {
+        let (budget, paused) = match side {
+            Side::A => (&mut self.up, &mut self.client_paused),
+            Side::B => (&mut self.down, &mut self.target_paused),
+        };
+
+        let max = cmp::min(budget.available(), global.available());
+
+        if max > 0 {
+            match self.pipe.as_mut().unwrap().read(side, max) {
+                Ok(n) => {
+                    budget.take(n);
+                    global.take(n);
+                }
+                Err(e) => {
+                    println!("got an error trying to read from the {}; token={:?}; err={:?}", name(side), self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        // What is left is read once the next budgets are released
+        if cmp::min(budget.available(), global.available()) == 0 {
+            *paused = true;
+        }
+    }
+
+    fn write_client(&mut self) {
+        if self.state == State::Relaying {
+            return self.write(Side::A);
+        }
+
+        let client = self.client.as_mut().unwrap();
+
+        while !self.response.is_empty() {
+            match client.try_write(&self.response) {
+                Ok(Some(n)) => {
+                    self.response.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write to the client; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write_target(&mut self) {
+        self.write(Side::B);
+    }
+
+    fn write(&mut self, side: Side) {
+        if let Err(e) = self.pipe.as_mut().unwrap().write(side) {
+            println!("got an error trying to write to the {}; token={:?}; err={:?}", name(side), self.token, e);
+            self.closed = true;
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        if self.closed {
+            return true;
+        }
+
+        match self.state {
+            State::Request => self.client_eof,
+            State::Resolving | State::Connecting => false,
+            State::Relaying => self.pipe.as_ref().unwrap().is_done(),
+            State::Failed => self.response.is_empty(),
+        }
+    }
+
+    // Bytes written to the target, and to the client
+    fn transferred(&self) -> (u64, u64) {
+        match self.pipe {
+            Some(ref pipe) => (pipe.written(Side::B), pipe.written(Side::A)),
+            None => (0, 0),
+        }
+    }
+
+    fn client_interest(&self) -> mio::EventSet {
+        if let Some(ref pipe) = self.pipe {
+            return paused(pipe.interest(Side::A), self.client_paused);
+        }
+
+        let mut interest = mio::EventSet::none();
+
+        if !self.client_eof && !self.client_paused && self.buf.len() < pump::MAX_BUFFERED {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.response.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        interest
+    }
+
+    fn target_interest(&self) -> mio::EventSet {
+        match self.pipe {
+            Some(ref pipe) => paused(pipe.interest(Side::B), self.target_paused),
+            // Writable once connected
+            None => mio::EventSet::writable(),
+        }
+    }
+}
+
+// A side left unread until the next budgets isn't registered as readable
+fn paused(interest: mio::EventSet, paused: bool) -> mio::EventSet {
+    if paused {
+        interest - mio::EventSet::readable()
+    } else {
+        interest
+    }
+}
+
+fn name(side: Side) -> &'static str {
+    match side {
+        Side::A => "client",
+        Side::B => "target",
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("127.0.0.1:3128".to_string()).parse().unwrap();
+
+    // The caps on each tunnel, and on all of them, in bytes per second in
+    // each direction. None by default.
+    let rate = args.next().map(|rate| throttle::parse_rate(&rate).expect("invalid rate")).unwrap_or(0);
+    let global_rate = args.next().map(|rate| throttle::parse_rate(&rate).expect("invalid rate")).unwrap_or(0);
+
+    let rate = if rate > 0 { Some(rate) } else { None };
+    let global_rate = if global_rate > 0 { Some(global_rate) } else { None };
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    if rate.is_some() || global_rate.is_some() {
+        event_loop.timeout_ms(Timer::Refill, REFILL_MS).unwrap();
+    }
+
+    println!("running CONNECT proxy; addr={:?}; rate={:?}; global rate={:?}", address, rate, global_rate);
+
+    let resolver = Resolver::new(RESOLVER_THREADS, event_loop.channel(), RESOLVER_CACHE);
+
+    let mut proxy = Proxy::new(server, resolver, rate, global_rate);
+    event_loop.run(&mut proxy).unwrap();
+}
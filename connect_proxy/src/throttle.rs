@@ -0,0 +1,68 @@
+// A budget of bytes, for capping a transfer rate. Every byte relayed is
+// taken from the budget, and relaying stops once it is spent, until a
+// timer releases more. The timer doesn't fire exactly on time, what it
+// releases is what the rate allows for the time that went by since the
+// last time. Unspent budget isn't carried over: an idle connection can't
+// save up for a burst.
+//
+// This does no I/O.
+
+pub struct Budget {
+    // Bytes per second, `None` for no limit
+    rate: Option<u64>,
+    available: usize,
+}
+
+impl Budget {
+    pub fn unlimited() -> Budget {
+        Budget {
+            rate: None,
+            available: usize::MAX,
+        }
+    }
+
+    // A budget for `rate` bytes per second, starting with what it allows
+    // for `ms`
+    pub fn new(rate: u64, ms: u64) -> Budget {
+        let mut budget = Budget {
+            rate: Some(rate),
+            available: 0,
+        };
+
+        budget.refill(ms);
+        budget
+    }
+
+    pub fn available(&self) -> usize {
+        self.available
+    }
+
+    pub fn take(&mut self, n: usize) {
+        if self.rate.is_some() {
+            self.available -= n;
+        }
+    }
+
+    // Releases what the rate allows for `ms` milliseconds
+    pub fn refill(&mut self, ms: u64) {
+        if let Some(rate) = self.rate {
+            self.available = (rate * ms / 1_000) as usize;
+        }
+    }
+}
+
+// Parses a rate in bytes per second, with an optional `k` or `m` suffix,
+// as in `64k`. `0` is no limit.
+pub fn parse_rate(s: &str) -> Option<u64> {
+    let s = s.to_lowercase();
+
+    let (digits, unit) = if s.ends_with('k') {
+        (&s[..s.len() - 1], 1_024)
+    } else if s.ends_with('m') {
+        (&s[..s.len() - 1], 1_024 * 1_024)
+    } else {
+        (&s[..], 1)
+    };
+
+    digits.parse::<u64>().ok().map(|n| n * unit)
+}
@@ -0,0 +1,126 @@
+// Stackful coroutines on top of the `context` crate, which switches stacks
+// with Boost.Context's assembly. A coroutine runs a closure on a stack of
+// its own, and the closure can suspend itself at any depth of calls,
+// handing a value back to whoever resumed it. Resuming it again carries
+// on from there, with a value passed in.
+//
+// That is all the event loop needs: a connection's code suspends itself
+// when the socket would block, saying what it waits on, and the loop
+// resumes it once the socket is ready.
+//
+// Values going either way are `usize`, what a context switch carries. A
+// coroutine's own value is shifted to leave room for whether it finished.
+
+use context::{Context, Transfer};
+use context::stack::ProtectedFixedSizeStack;
+use std::panic::{self, AssertUnwindSafe};
+
+const YIELDED: usize = 0;
+const FINISHED: usize = 1;
+const PANICKED: usize = 2;
+
+type Body = Box<dyn FnOnce(&mut Yielder)>;
+
+pub enum Resume {
+    // The coroutine suspended itself, with this value
+    Yielded(usize),
+    // The closure returned
+    Finished,
+    // The closure panicked. The panic was caught on the coroutine's stack,
+    // unwinding can't cross over into the loop's.
+    Panicked,
+}
+
+pub struct Coroutine {
+    // Where to switch to, to resume the coroutine. `None` once it is done.
+    context: Option<Context>,
+    // Freed with the coroutine. If it is dropped suspended, what is on the
+    // stack is never dropped: the memory it points to is leaked, whatever
+    // it was holding stays open. Resume it with a value making it return
+    // instead.
+    _stack: ProtectedFixedSizeStack,
+}
+
+// The coroutine's end of the switch, given to its closure
+pub struct Yielder {
+    // Where to switch back to, the caller of `resume`
+    context: Option<Context>,
+}
+
+impl Coroutine {
+    // Makes a coroutine to run `f` on a stack of `stack_size` bytes, plus
+    // a guard page, so overflowing it faults rather than overwriting the
+    // next. Nothing runs until the first `resume`.
+    pub fn spawn<F>(stack_size: usize, f: F) -> Coroutine
+        where F: FnOnce(&mut Yielder) + 'static
+    {
+        let stack = ProtectedFixedSizeStack::new(stack_size).unwrap();
+        let context = unsafe { Context::new(&stack, entry) };
+
+        // The closure is handed over by the first switch, which only goes
+        // as far as the start of `entry`
+        let body: Box<Body> = Box::new(Box::new(f));
+        let transfer = unsafe { context.resume(Box::into_raw(body) as usize) };
+
+        Coroutine {
+            context: Some(transfer.context),
+            _stack: stack,
+        }
+    }
+
+    // Runs the coroutine until it suspends itself or is done. `value` is
+    // what its `suspend` returns.
+    pub fn resume(&mut self, value: usize) -> Resume {
+        let context = self.context.take().expect("resumed a coroutine that is done");
+        let transfer = unsafe { context.resume(value) };
+
+        match transfer.data & 3 {
+            YIELDED => {
+                self.context = Some(transfer.context);
+                Resume::Yielded(transfer.data >> 2)
+            }
+            FINISHED => Resume::Finished,
+            _ => Resume::Panicked,
+        }
+    }
+}
+
+impl Yielder {
+    // Switches back to the caller of `resume`, which gets `value`, and
+    // returns the value the coroutine is resumed with
+    pub fn suspend(&mut self, value: usize) -> usize {
+        self.switch(value << 2 | YIELDED)
+    }
+
+    fn switch(&mut self, data: usize) -> usize {
+        let context = self.context.take().unwrap();
+        let transfer = unsafe { context.resume(data) };
+
+        self.context = Some(transfer.context);
+        transfer.data
+    }
+}
+
+extern "C" fn entry(transfer: Transfer) -> ! {
+    let body = unsafe { Box::from_raw(transfer.data as *mut Body) };
+
+    // Back to `spawn`, until the first `resume`
+    let mut yielder = Yielder { context: Some(transfer.context) };
+    yielder.switch(YIELDED);
+
+    let status = match panic::catch_unwind(AssertUnwindSafe(|| body(&mut yielder))) {
+        Ok(()) => FINISHED,
+        Err(_) => PANICKED,
+    };
+
+    // Nothing on this stack is dropped after the last switch, the stack is
+    // freed without ever coming back. Only the context is left, and it
+    // holds nothing.
+    let context = yielder.context.take().unwrap();
+
+    unsafe {
+        context.resume(status);
+    }
+
+    unreachable!("resumed a coroutine that is done");
+}
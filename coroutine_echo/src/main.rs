@@ -0,0 +1,251 @@
+extern crate context;
+extern crate mio;
+extern crate timeouts;
+
+mod coroutine;
+
+use coroutine::{Coroutine, Resume, Yielder};
+use mio::{EventLoop, Handler, TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::cell::RefCell;
+use std::env;
+use std::io;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use timeouts::Timeouts;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 4_096;
+
+// Each connection's stack. Only the pages touched are ever backed by
+// memory, this is what a coroutine can use at most, the guard page
+// after it catches an overflow.
+const STACK_SIZE: usize = 64 * 1_024;
+
+// Lines longer than this close the connection
+const MAX_LINE: usize = 16 * 1_024;
+
+// Connections not heard from in this long are closed
+const IDLE_MS: u64 = 60_000;
+
+// What a connection waits on when it suspends itself
+const READABLE: usize = 1;
+const WRITABLE: usize = 2;
+
+// Why it is resumed
+const READY: usize = 0;
+const TIMED_OUT: usize = 1;
+
+// The connection's logic, in the straight line it would have with
+// blocking sockets and a thread per connection. Every call that would
+// block suspends the coroutine instead, until the loop resumes it.
+fn echo(stream: &mut Stream) -> io::Result<u64> {
+    let mut lines = 0;
+
+    while let Some(line) = stream.read_line()? {
+        stream.write_all(&line)?;
+        lines += 1;
+    }
+
+    Ok(lines)
+}
+
+// A socket read and written as if it were blocking, from within a
+// coroutine. The loop keeps the socket to register it, the coroutine
+// borrows it for each read and write, never across a suspension.
+struct Stream<'a> {
+    socket: Rc<RefCell<TcpStream>>,
+    yielder: &'a mut Yielder,
+    // Read, and not yet returned as a line
+    buf: Vec<u8>,
+}
+
+impl<'a> Stream<'a> {
+    // The next line, with its `\n`, or `None` at the end of the stream.
+    // What comes after a last `\n` is returned as a line of its own.
+    fn read_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut chunk = [0; 4_096];
+
+        loop {
+            if let Some(i) = self.buf.iter().position(|&b| b == b'\n') {
+                return Ok(Some(self.buf.drain(..i + 1).collect()));
+            }
+
+            if self.buf.len() > MAX_LINE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+            }
+
+            let res = self.socket.borrow_mut().try_read(&mut chunk);
+
+            match res? {
+                Some(0) if self.buf.is_empty() => return Ok(None),
+                Some(0) => return Ok(Some(self.buf.split_off(0))),
+                Some(n) => self.buf.extend_from_slice(&chunk[..n]),
+                None => self.wait(READABLE)?,
+            }
+        }
+    }
+
+    fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let res = self.socket.borrow_mut().try_write(buf);
+
+            match res? {
+                Some(n) => buf = &buf[n..],
+                None => self.wait(WRITABLE)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    // Suspends the coroutine until the socket is ready. A connection
+    // timing out in the meantime gets an error, and unwinds through the
+    // `?`s like any other, so that everything on its stack is dropped.
+    fn wait(&mut self, interest: usize) -> io::Result<()> {
+        match self.yielder.suspend(interest) {
+            READY => Ok(()),
+            _ => Err(io::Error::new(io::ErrorKind::TimedOut, "idle for too long")),
+        }
+    }
+}
+
+struct Connection {
+    socket: Rc<RefCell<TcpStream>>,
+    coroutine: Coroutine,
+}
+
+// An echo server without a state machine: each connection runs `echo` as
+// a coroutine, and the event loop does nothing but switch to the
+// connection that is ready.
+struct Server {
+    server: TcpListener,
+    connections: Slab<Connection>,
+    // Each connection's idle deadline, pushed back every time it is resumed
+    idle: Timeouts,
+}
+
+impl Server {
+    fn accept(&mut self, event_loop: &mut EventLoop<Server>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        let token = match self.connections.insert_with(|token| Connection::new(socket, token)) {
+            Some(token) => token,
+            None => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        event_loop.register_opt(&*self.connections[token].socket.borrow(), token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        // Runs until its first read comes up empty
+        self.resume(event_loop, token, READY);
+    }
+
+    // Switches to the connection's coroutine, and registers for what it
+    // waits on when it is back
+    fn resume(&mut self, event_loop: &mut EventLoop<Server>, token: mio::Token, value: usize) {
+        let interest = match self.connections[token].coroutine.resume(value) {
+            Resume::Yielded(READABLE) => mio::EventSet::readable(),
+            Resume::Yielded(_) => mio::EventSet::writable(),
+            Resume::Finished | Resume::Panicked => {
+                self.remove(event_loop, token);
+                return;
+            }
+        };
+
+        self.idle.set(event_loop, token, IDLE_MS);
+
+        let conn = &self.connections[token];
+        event_loop.reregister(&*conn.socket.borrow(), token, interest | mio::EventSet::hup(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn remove(&mut self, event_loop: &mut EventLoop<Server>, token: mio::Token) {
+        // The token may be reused by the next connection, its timeout must
+        // not fire for it
+        self.idle.clear(event_loop, token);
+
+        self.connections.remove(token);
+    }
+}
+
+impl Handler for Server {
+    type Timeout = mio::Token;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut EventLoop<Server>, token: mio::Token, _: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ => self.resume(event_loop, token, READY),
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut EventLoop<Server>, token: mio::Token) {
+        // Unless the deadline was pushed back since the timeout was set
+        if self.idle.expired(event_loop, token) {
+            self.resume(event_loop, token, TIMED_OUT);
+        }
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        let socket = Rc::new(RefCell::new(socket));
+        let theirs = socket.clone();
+
+        let coroutine = Coroutine::spawn(STACK_SIZE, move |yielder| {
+            let mut stream = Stream {
+                socket: theirs,
+                yielder: yielder,
+                buf: vec![],
+            };
+
+            match echo(&mut stream) {
+                Ok(lines) => println!("connection closed; token={:?}; lines={}", token, lines),
+                Err(e) => println!("closing connection; token={:?}; err={}", token, e),
+            }
+        });
+
+        Connection {
+            socket: socket,
+            coroutine: coroutine,
+        }
+    }
+}
+
+fn main() {
+    let addr: SocketAddr = env::args().nth(1)
+        .unwrap_or_else(|| "0.0.0.0:6200".to_string())
+        .parse().unwrap();
+
+    let server = TcpListener::bind(&addr).unwrap();
+
+    let mut event_loop = EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    // Token `0` is reserved for the server socket. Tokens 1+ are used for
+    // client connections.
+    let mut server = Server {
+        server: server,
+        connections: Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS),
+        idle: Timeouts::new(),
+    };
+
+    println!("running coroutine echo server; addr={:?}", addr);
+    event_loop.run(&mut server).unwrap();
+}
@@ -0,0 +1,81 @@
+extern crate mio;
+extern crate bytes;
+
+use mio::udp::*;
+use bytes::SliceBuf;
+use std::net::SocketAddr;
+use std::{env, process};
+
+// How often the service is announced. The interval is part of the beacon,
+// listeners use it to tell when an announcer has gone away.
+const INTERVAL_MS: u64 = 3_000;
+
+// Announces a service on the local network. Every few seconds, a beacon is
+// broadcast to the listeners' port:
+//
+//     BEACON <name> <service addr> <interval ms>
+//
+// Nothing is sent when the announcer stops, listeners notice the beacons
+// stopping.
+struct Announcer {
+    socket: UdpSocket,
+    beacon: String,
+    broadcast: SocketAddr,
+}
+
+impl Announcer {
+    fn announce(&mut self, event_loop: &mut mio::EventLoop<Announcer>) {
+        match self.socket.send_to(&mut SliceBuf::wrap(self.beacon.as_bytes()), &self.broadcast) {
+            Ok(Some(())) => {}
+            Ok(None) => println!("the socket wasn't actually ready, skipping beacon"),
+            Err(e) => println!("failed to send beacon; addr={:?}; err={:?}", self.broadcast, e),
+        }
+
+        event_loop.timeout_ms((), INTERVAL_MS).unwrap();
+    }
+}
+
+impl mio::Handler for Announcer {
+    type Timeout = ();
+    type Message = ();
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Announcer>, _: ()) {
+        self.announce(event_loop);
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let (name, service) = match (args.next(), args.next().and_then(|addr| addr.parse::<SocketAddr>().ok())) {
+        // The name is a single word, the rest of the beacon follows it
+        (Some(ref name), Some(service)) if !name.contains(char::is_whitespace) => (name.clone(), service),
+        _ => {
+            println!("usage: announcer <name> <service addr> [broadcast addr]");
+            process::exit(1);
+        }
+    };
+
+    // The limited broadcast address reaches every host on the local network.
+    // A subnet's broadcast address, like `192.168.1.255:9800`, picks the
+    // network to announce on.
+    let broadcast: SocketAddr = args.next().unwrap_or("255.255.255.255:9800".to_string()).parse().unwrap();
+
+    let socket = UdpSocket::bound(&"0.0.0.0:0".parse().unwrap()).unwrap();
+
+    // Sending to a broadcast address fails unless it is allowed explicitly
+    socket.set_broadcast(true).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+
+    let mut announcer = Announcer {
+        socket: socket,
+        beacon: format!("BEACON {} {} {}", name, service, INTERVAL_MS),
+        broadcast: broadcast,
+    };
+
+    println!("announcing service; name={}; service={}; broadcast={}", name, service, broadcast);
+    announcer.announce(&mut event_loop);
+
+    event_loop.run(&mut announcer).unwrap();
+}
@@ -0,0 +1,171 @@
+extern crate mio;
+extern crate bytes;
+extern crate libc;
+
+use mio::udp::*;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::time::Instant;
+use std::{env, io, mem};
+
+const SOCKET: mio::Token = mio::Token(0);
+
+const MAX_DATAGRAM: usize = 512;
+
+// A peer is considered gone once this many of its beacons in a row have
+// been missed. Broadcasts are unreliable, a single lost one is no reason
+// to drop it.
+const MISSED_BEACONS: u64 = 3;
+
+// A service seen on the network, keyed by its name
+struct Peer {
+    service: SocketAddr,
+    // Where the beacons come from
+    src: SocketAddr,
+    first_seen: Instant,
+    // Fires if no beacon arrives in time. It is replaced by every beacon.
+    expiry: mio::Timeout,
+}
+
+struct Listener {
+    socket: UdpSocket,
+    peers: HashMap<String, Peer>,
+}
+
+impl Listener {
+    fn receive(&mut self, event_loop: &mut mio::EventLoop<Listener>) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event.
+        loop {
+            let mut buf = Vec::with_capacity(MAX_DATAGRAM);
+
+            let src = match self.socket.recv_from(&mut buf) {
+                Ok(Some(src)) => src,
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            let beacon = String::from_utf8_lossy(&buf).into_owned();
+            let mut parts = beacon.split_whitespace();
+
+            match (parts.next(), parts.next(), parts.next().and_then(|s| s.parse().ok()), parts.next().and_then(|s| s.parse().ok())) {
+                (Some("BEACON"), Some(name), Some(service), Some(interval)) => {
+                    self.beacon(event_loop, name, service, interval, src);
+                }
+                _ => println!("ignoring invalid beacon; src={:?}", src),
+            }
+        }
+    }
+
+    fn beacon(&mut self, event_loop: &mut mio::EventLoop<Listener>, name: &str, service: SocketAddr, interval: u64, src: SocketAddr) {
+        // The announcer says how often it sends beacons, so each peer gets
+        // its own deadline.
+        let expiry = event_loop.timeout_ms(name.to_string(), interval * MISSED_BEACONS).unwrap();
+
+        match self.peers.get_mut(name) {
+            Some(peer) => {
+                event_loop.clear_timeout(peer.expiry);
+                peer.expiry = expiry;
+
+                if peer.service == service && peer.src == src {
+                    return;
+                }
+
+                println!("peer changed; name={}; service={}; src={}", name, service, src);
+
+                peer.service = service;
+                peer.src = src;
+            }
+            None => {
+                println!("peer up; name={}; service={}; src={}", name, service, src);
+
+                self.peers.insert(name.to_string(), Peer {
+                    service: service,
+                    src: src,
+                    first_seen: Instant::now(),
+                    expiry: expiry,
+                });
+            }
+        }
+
+        self.print_table();
+    }
+
+    fn print_table(&self) {
+        let mut names: Vec<&String> = self.peers.keys().collect();
+        names.sort();
+
+        println!("{} live peer(s):", names.len());
+
+        for name in names {
+            let peer = &self.peers[name];
+            println!("  {:<16} {:<22} up {}s", name, peer.service.to_string(), peer.first_seen.elapsed().as_secs());
+        }
+    }
+}
+
+impl mio::Handler for Listener {
+    // The peer that missed its deadline
+    type Timeout = String;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Listener>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, SOCKET);
+        assert!(events.is_readable(), "unexpected events; events={:?}", events);
+
+        self.receive(event_loop);
+    }
+
+    fn timeout(&mut self, _: &mut mio::EventLoop<Listener>, name: String) {
+        if let Some(peer) = self.peers.remove(&name) {
+            println!("peer expired; name={}; service={}", name, peer.service);
+            self.print_table();
+        }
+    }
+}
+
+// Lets several listeners on this host bind the beacon port. Each one gets
+// its own copy of every broadcast.
+fn bind_shared(addr: &SocketAddr) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::v4()?;
+
+    let on: libc::c_int = 1;
+
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_REUSEADDR,
+            &on as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    socket.bind(addr)?;
+    Ok(socket)
+}
+
+fn main() {
+    // Broadcasts are only delivered to sockets bound to the wildcard
+    // address, or to the broadcast address itself.
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:9800".to_string())
+        .parse().unwrap();
+
+    let socket = bind_shared(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&socket, SOCKET, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+
+    let mut listener = Listener {
+        socket: socket,
+        peers: HashMap::new(),
+    };
+
+    println!("listening for beacons; addr={:?}", address);
+    event_loop.run(&mut listener).unwrap();
+}
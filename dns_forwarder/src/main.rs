@@ -0,0 +1,343 @@
+extern crate mio;
+extern crate bytes;
+extern crate dns_wire;
+
+use mio::udp::*;
+use bytes::SliceBuf;
+use dns_wire::{read_name, read_u16, read_u32, write_u16};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+const UPSTREAM: mio::Token = mio::Token(1);
+
+// DNS over UDP limits messages to 512 bytes unless EDNS is used
+const MAX_MESSAGE: usize = 512;
+
+// How long to wait for the upstream resolver before forgetting about a
+// forwarded query. The client will retry on its own.
+const FORWARD_TIMEOUT_MS: u64 = 5_000;
+
+// Answers are never cached for longer than this, regardless of their TTL
+const MAX_TTL: u32 = 3_600;
+
+// Queries forwarded upstream at once, at most, well under the 65,536 IDs
+// there are for them. Past this, or when the timer is full, queries are
+// answered with a SERVFAIL.
+const MAX_PENDING: usize = 4_096;
+
+// The question being asked: name, type and class. Answers are cached by the
+// question they answer.
+type Question = (String, u16, u16);
+
+enum Timeout {
+    // A cache entry has reached the end of its TTL
+    Expire(Question),
+    // A forwarded query has not received a response in time
+    Forward(u16),
+}
+
+struct Forwarder {
+    server: UdpSocket,
+    upstream: UdpSocket,
+    upstream_addr: SocketAddr,
+    cache: HashMap<Question, Entry>,
+    // Queries forwarded upstream and waiting for a response, keyed by the ID
+    // used on the upstream socket
+    pending: HashMap<u16, Pending>,
+    next_id: u16,
+}
+
+struct Entry {
+    packet: Vec<u8>,
+    timeout: mio::Timeout,
+}
+
+struct Pending {
+    client: SocketAddr,
+    client_id: u16,
+    question: Question,
+    timeout: mio::Timeout,
+}
+
+impl Forwarder {
+    fn new(server: UdpSocket, upstream: UdpSocket, upstream_addr: SocketAddr) -> Forwarder {
+        Forwarder {
+            server: server,
+            upstream: upstream,
+            upstream_addr: upstream_addr,
+            cache: HashMap::new(),
+            pending: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    fn client_query(&mut self, event_loop: &mut mio::EventLoop<Forwarder>, client: SocketAddr, mut packet: Vec<u8>) {
+        let question = match parse_question(&packet) {
+            Some(question) => question,
+            None => {
+                println!("ignoring malformed query; client={:?}", client);
+                return;
+            }
+        };
+
+        let client_id = read_u16(&packet, 0).unwrap();
+
+        if let Some(entry) = self.cache.get_mut(&question) {
+            println!("cache hit; name={}; client={:?}", question.0, client);
+
+            // The cached response carries the ID of the query that populated
+            // the cache, give it the ID the client is expecting.
+            write_u16(&mut entry.packet, 0, client_id);
+            send(&self.server, &entry.packet, &client);
+            return;
+        }
+
+        println!("cache miss; name={}; client={:?}", question.0, client);
+
+        // Upstream IDs are allocated by the forwarder, since IDs chosen by
+        // different clients may collide.
+        if self.pending.len() >= MAX_PENDING {
+            println!("too many queries pending, refusing query; name={}; client={:?}", question.0, client);
+            return self.refuse(&packet, &client);
+        }
+
+        let id = self.next_upstream_id();
+
+        let timeout = match event_loop.timeout_ms(Timeout::Forward(id), FORWARD_TIMEOUT_MS) {
+            Ok(timeout) => timeout,
+            Err(_) => {
+                println!("timer full, refusing query; name={}; client={:?}", question.0, client);
+                return self.refuse(&packet, &client);
+            }
+        };
+
+        write_u16(&mut packet, 0, id);
+
+        self.pending.insert(id, Pending {
+            client: client,
+            client_id: client_id,
+            question: question,
+            timeout: timeout,
+        });
+
+        send(&self.upstream, &packet, &self.upstream_addr);
+    }
+
+    fn upstream_response(&mut self, event_loop: &mut mio::EventLoop<Forwarder>, mut packet: Vec<u8>) {
+        let id = match read_u16(&packet, 0) {
+            Some(id) => id,
+            None => {
+                println!("ignoring malformed response");
+                return;
+            }
+        };
+
+        let pending = match self.pending.remove(&id) {
+            Some(pending) => pending,
+            None => {
+                println!("ignoring response for unknown query; id={}", id);
+                return;
+            }
+        };
+
+        event_loop.clear_timeout(pending.timeout);
+
+        // Restore the ID the client used before relaying the response
+        write_u16(&mut packet, 0, pending.client_id);
+        send(&self.server, &packet, &pending.client);
+
+        if let Some(ttl) = min_ttl(&packet) {
+            let ttl = std::cmp::min(ttl, MAX_TTL);
+
+            println!("caching response; name={}; ttl={}", pending.question.0, ttl);
+
+            let timeout = match event_loop.timeout_ms(Timeout::Expire(pending.question.clone()), ttl as u64 * 1_000) {
+                Ok(timeout) => timeout,
+                Err(_) => {
+                    println!("timer full, not caching response; name={}", pending.question.0);
+                    return;
+                }
+            };
+
+            let entry = Entry {
+                packet: packet,
+                timeout: timeout,
+            };
+
+            // Two clients may have raced on the same question, in which case
+            // the older entry's timer must not expire the newer entry.
+            if let Some(old) = self.cache.insert(pending.question, entry) {
+                event_loop.clear_timeout(old.timeout);
+            }
+        }
+    }
+
+    // Answers a query with a SERVFAIL, for the client to try again later
+    fn refuse(&self, query: &[u8], client: &SocketAddr) {
+        if let Some(packet) = servfail(query) {
+            send(&self.server, &packet, client);
+        }
+    }
+
+    // There are always IDs left, with at most `MAX_PENDING` of them taken
+    fn next_upstream_id(&mut self) -> u16 {
+        loop {
+            self.next_id = self.next_id.wrapping_add(1);
+
+            if !self.pending.contains_key(&self.next_id) {
+                return self.next_id;
+            }
+        }
+    }
+}
+
+impl mio::Handler for Forwarder {
+    type Timeout = Timeout;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Forwarder>, token: mio::Token, events: mio::EventSet) {
+        assert!(events.is_readable(), "unexpected events; events={:?}", events);
+
+        // Both sockets are registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event.
+        loop {
+            let mut buf = Vec::with_capacity(MAX_MESSAGE);
+
+            match token {
+                SERVER => {
+                    match self.server.recv_from(&mut buf) {
+                        Ok(Some(addr)) => self.client_query(event_loop, addr, buf),
+                        Ok(None) => return,
+                        Err(e) => panic!("got an error trying to receive; err={:?}", e),
+                    }
+                }
+                UPSTREAM => {
+                    match self.upstream.recv_from(&mut buf) {
+                        Ok(Some(addr)) if addr == self.upstream_addr => {
+                            self.upstream_response(event_loop, buf);
+                        }
+                        Ok(Some(addr)) => {
+                            println!("ignoring datagram from unexpected peer; addr={:?}", addr);
+                        }
+                        Ok(None) => return,
+                        Err(e) => panic!("got an error trying to receive; err={:?}", e),
+                    }
+                }
+                _ => panic!("unexpected token"),
+            }
+        }
+    }
+
+    fn timeout(&mut self, _: &mut mio::EventLoop<Forwarder>, timeout: Timeout) {
+        match timeout {
+            Timeout::Expire(question) => {
+                println!("cache entry expired; name={}", question.0);
+                self.cache.remove(&question);
+            }
+            Timeout::Forward(id) => {
+                if let Some(pending) = self.pending.remove(&id) {
+                    println!("upstream did not respond; name={}", pending.question.0);
+                }
+            }
+        }
+    }
+}
+
+// Sends a datagram. If the socket isn't ready the datagram is dropped, DNS
+// clients are expected to retransmit lost queries.
+fn send(socket: &UdpSocket, packet: &[u8], addr: &SocketAddr) {
+    match socket.send_to(&mut SliceBuf::wrap(packet), addr) {
+        Ok(Some(())) => {}
+        Ok(None) => println!("the socket wasn't actually ready, dropping datagram; addr={:?}", addr),
+        Err(e) => println!("failed to send datagram; addr={:?}; err={:?}", addr, e),
+    }
+}
+
+/*
+ *
+ * ===== DNS messages =====
+ *
+ */
+
+// Returns the single question of a query.
+fn parse_question(packet: &[u8]) -> Option<Question> {
+    if read_u16(packet, 4)? != 1 {
+        return None;
+    }
+
+    let (name, pos) = read_name(packet, 12)?;
+    let qtype = read_u16(packet, pos)?;
+    let qclass = read_u16(packet, pos + 2)?;
+
+    Some((name.to_lowercase(), qtype, qclass))
+}
+
+// Builds a SERVFAIL response to a query: its header and question, with
+// the opcode and the recursion desired flag kept, and no records.
+fn servfail(query: &[u8]) -> Option<Vec<u8>> {
+    let (_, pos) = read_name(query, 12)?;
+    let mut packet = query.get(..pos + 4)?.to_vec();
+
+    let flags = read_u16(&packet, 2)?;
+    write_u16(&mut packet, 2, flags & 0x7900 | 0x8082);
+
+    for count in &[6, 8, 10] {
+        write_u16(&mut packet, *count, 0);
+    }
+
+    Some(packet)
+}
+
+// Returns the smallest TTL of all the answer records in a successful
+// response, or `None` if the response should not be cached.
+fn min_ttl(packet: &[u8]) -> Option<u32> {
+    let flags = read_u16(packet, 2)?;
+    let qdcount = read_u16(packet, 4)?;
+    let ancount = read_u16(packet, 6)?;
+
+    // Don't cache errors or truncated responses
+    if flags & 0x000f != 0 || flags & 0x0200 != 0 || ancount == 0 {
+        return None;
+    }
+
+    let mut pos = 12;
+
+    for _ in 0..qdcount {
+        let (_, next) = read_name(packet, pos)?;
+        pos = next + 4;
+    }
+
+    let mut min = None;
+
+    for _ in 0..ancount {
+        let (_, next) = read_name(packet, pos)?;
+        let ttl = read_u32(packet, next + 4)?;
+        let rdlen = read_u16(packet, next + 8)? as usize;
+
+        min = Some(std::cmp::min(ttl, min.unwrap_or(ttl)));
+        pos = next + 10 + rdlen;
+    }
+
+    min
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let listen: SocketAddr = args.next().unwrap_or("0.0.0.0:5300".to_string()).parse().unwrap();
+    let upstream_addr: SocketAddr = args.next().unwrap_or("8.8.8.8:53".to_string()).parse().unwrap();
+
+    let server = UdpSocket::bound(&listen).unwrap();
+    let upstream = UdpSocket::bound(&"0.0.0.0:0".parse().unwrap()).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&server, SERVER, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+    event_loop.register_opt(&upstream, UPSTREAM, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+
+    let mut forwarder = Forwarder::new(server, upstream, upstream_addr);
+
+    println!("running dns forwarder; addr={:?}; upstream={:?}", listen, upstream_addr);
+    event_loop.run(&mut forwarder).unwrap();
+}
@@ -0,0 +1,316 @@
+extern crate mio;
+extern crate bytes;
+extern crate dns_wire;
+
+use mio::udp::*;
+use bytes::SliceBuf;
+use dns_wire::{push_u16, read_name, read_u16, read_u32};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::{env, process};
+
+const SOCKET: mio::Token = mio::Token(0);
+
+// How long to wait for a response before sending the query again
+const RETRANSMIT_MS: u64 = 1_000;
+
+// How many times a query is sent before giving up on it
+const MAX_ATTEMPTS: usize = 3;
+
+// DNS over UDP limits messages to 512 bytes unless EDNS is used
+const MAX_MESSAGE: usize = 512;
+
+struct Resolver {
+    socket: UdpSocket,
+    resolver: SocketAddr,
+    // In flight queries, keyed by the DNS query ID. The ID is echoed back by
+    // the resolver which is how responses are matched with queries.
+    queries: HashMap<u16, Query>,
+}
+
+struct Query {
+    name: String,
+    packet: Vec<u8>,
+    attempts: usize,
+    timeout: Option<mio::Timeout>,
+}
+
+impl Resolver {
+    fn new(socket: UdpSocket, resolver: SocketAddr) -> Resolver {
+        Resolver {
+            socket: socket,
+            resolver: resolver,
+            queries: HashMap::new(),
+        }
+    }
+
+    fn query(&mut self, event_loop: &mut mio::EventLoop<Resolver>, id: u16, name: &str) {
+        let query = Query {
+            name: name.to_string(),
+            packet: build_query(id, name),
+            attempts: 0,
+            timeout: None,
+        };
+
+        self.queries.insert(id, query);
+        self.send(event_loop, id);
+    }
+
+    // Sends (or re-sends) the query and arms the retransmission timer.
+    fn send(&mut self, event_loop: &mut mio::EventLoop<Resolver>, id: u16) {
+        let query = self.queries.get_mut(&id).unwrap();
+        query.attempts += 1;
+
+        println!("sending query; id={}; name={}; attempt={}", id, query.name, query.attempts);
+
+        match self.socket.send_to(&mut SliceBuf::wrap(&query.packet), &self.resolver) {
+            Ok(Some(())) => {}
+            Ok(None) => {
+                // The socket's send buffer is full. There is no need to wait
+                // for a writable event, the retransmission timer will try
+                // again shortly.
+                println!("the socket wasn't actually ready; id={}", id);
+            }
+            Err(e) => panic!("got an error trying to send; err={:?}", e),
+        }
+
+        query.timeout = Some(event_loop.timeout_ms(id, RETRANSMIT_MS).unwrap());
+    }
+
+    fn receive(&mut self, event_loop: &mut mio::EventLoop<Resolver>) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event.
+        loop {
+            let mut buf = Vec::with_capacity(MAX_MESSAGE);
+
+            let addr = match self.socket.recv_from(&mut buf) {
+                Ok(Some(addr)) => addr,
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            if addr != self.resolver {
+                println!("ignoring datagram from unexpected peer; addr={:?}", addr);
+                continue;
+            }
+
+            let response = match parse_response(&buf) {
+                Some(response) => response,
+                None => {
+                    println!("ignoring malformed response; len={}", buf.len());
+                    continue;
+                }
+            };
+
+            // Correlate the response with the query that caused it. Late
+            // duplicates of an already answered query end up here as well.
+            let query = match self.queries.remove(&response.id) {
+                Some(query) => query,
+                None => {
+                    println!("ignoring response for unknown query; id={}", response.id);
+                    continue;
+                }
+            };
+
+            if let Some(timeout) = query.timeout {
+                event_loop.clear_timeout(timeout);
+            }
+
+            response.print(&query.name);
+
+            if self.queries.is_empty() {
+                event_loop.shutdown();
+            }
+        }
+    }
+}
+
+impl mio::Handler for Resolver {
+    type Timeout = u16;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Resolver>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, SOCKET);
+        assert!(events.is_readable(), "unexpected events; events={:?}", events);
+
+        self.receive(event_loop);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Resolver>, id: u16) {
+        let attempts = match self.queries.get(&id) {
+            Some(query) => query.attempts,
+            // The response arrived at the same time the timer fired
+            None => return,
+        };
+
+        if attempts < MAX_ATTEMPTS {
+            self.send(event_loop, id);
+            return;
+        }
+
+        let query = self.queries.remove(&id).unwrap();
+        println!("{}: no response after {} attempts", query.name, query.attempts);
+
+        if self.queries.is_empty() {
+            event_loop.shutdown();
+        }
+    }
+}
+
+/*
+ *
+ * ===== DNS messages =====
+ *
+ */
+
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const CLASS_IN: u16 = 1;
+
+// Builds a standard query for the A record of `name` with the "recursion
+// desired" bit set.
+fn build_query(id: u16, name: &str) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(MAX_MESSAGE);
+
+    push_u16(&mut packet, id);
+    push_u16(&mut packet, 0x0100); // flags: RD
+    push_u16(&mut packet, 1);      // QDCOUNT
+    push_u16(&mut packet, 0);      // ANCOUNT
+    push_u16(&mut packet, 0);      // NSCOUNT
+    push_u16(&mut packet, 0);      // ARCOUNT
+
+    // The name is encoded as a sequence of length prefixed labels terminated
+    // by the empty root label.
+    for label in name.trim_end_matches('.').split('.') {
+        packet.push(label.len() as u8);
+        packet.extend(label.as_bytes());
+    }
+
+    packet.push(0);
+
+    push_u16(&mut packet, TYPE_A);
+    push_u16(&mut packet, CLASS_IN);
+
+    packet
+}
+
+struct Response {
+    id: u16,
+    rcode: u8,
+    answers: Vec<Answer>,
+}
+
+enum Answer {
+    A(String, Ipv4Addr, u32),
+    Cname(String, String, u32),
+    Other(String, u16),
+}
+
+impl Response {
+    fn print(&self, name: &str) {
+        if self.rcode != 0 {
+            println!("{}: query failed; rcode={}", name, self.rcode);
+            return;
+        }
+
+        if self.answers.is_empty() {
+            println!("{}: no records", name);
+        }
+
+        for answer in &self.answers {
+            match *answer {
+                Answer::A(ref owner, ref addr, ttl) => println!("{}: A {} (ttl={})", owner, addr, ttl),
+                Answer::Cname(ref owner, ref target, ttl) => println!("{}: CNAME {} (ttl={})", owner, target, ttl),
+                Answer::Other(ref owner, rtype) => println!("{}: unhandled record type {}", owner, rtype),
+            }
+        }
+    }
+}
+
+// Returns `None` if the packet is not a well formed response.
+fn parse_response(packet: &[u8]) -> Option<Response> {
+    let id = read_u16(packet, 0)?;
+    let flags = read_u16(packet, 2)?;
+    let qdcount = read_u16(packet, 4)?;
+    let ancount = read_u16(packet, 6)?;
+
+    // The QR bit must be set for responses
+    if flags & 0x8000 == 0 {
+        return None;
+    }
+
+    let mut pos = 12;
+
+    // Skip the question section, which repeats the query
+    for _ in 0..qdcount {
+        let (_, next) = read_name(packet, pos)?;
+        pos = next + 4;
+    }
+
+    let mut answers = vec![];
+
+    for _ in 0..ancount {
+        let (owner, next) = read_name(packet, pos)?;
+        let rtype = read_u16(packet, next)?;
+        let ttl = read_u32(packet, next + 4)?;
+        let rdlen = read_u16(packet, next + 8)? as usize;
+        let rdata = next + 10;
+
+        if rdata + rdlen > packet.len() {
+            return None;
+        }
+
+        let answer = match rtype {
+            TYPE_A if rdlen == 4 => {
+                let r = &packet[rdata..rdata + 4];
+                Answer::A(owner, Ipv4Addr::new(r[0], r[1], r[2], r[3]), ttl)
+            }
+            TYPE_CNAME => {
+                let (target, _) = read_name(packet, rdata)?;
+                Answer::Cname(owner, target, ttl)
+            }
+            _ => Answer::Other(owner, rtype),
+        };
+
+        answers.push(answer);
+        pos = rdata + rdlen;
+    }
+
+    Some(Response {
+        id: id,
+        rcode: (flags & 0x000f) as u8,
+        answers: answers,
+    })
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let names: Vec<String> = match args.next() {
+        Some(names) => names.split(',').map(|s| s.to_string()).collect(),
+        None => {
+            println!("usage: dns_resolver <name>[,<name>...] [resolver]");
+            process::exit(1);
+        }
+    };
+
+    let resolver = args.next().unwrap_or("8.8.8.8:53".to_string());
+    let resolver: SocketAddr = resolver.parse().unwrap();
+
+    let socket = UdpSocket::bound(&"0.0.0.0:0".parse().unwrap()).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&socket, SOCKET, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+
+    let mut resolver = Resolver::new(socket, resolver);
+
+    // DNS query IDs should be unpredictable to make spoofing responses
+    // harder. Deriving them from the process ID is good enough for a demo.
+    let base = process::id() as u16;
+
+    for (i, name) in names.iter().enumerate() {
+        resolver.query(&mut event_loop, base.wrapping_add(i as u16), name);
+    }
+
+    event_loop.run(&mut resolver).unwrap();
+}
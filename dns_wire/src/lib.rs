@@ -0,0 +1,141 @@
+// Reading and writing the parts of a DNS message (RFC 1035) the DNS
+// examples have in common. A name is a sequence of labels, each preceded
+// by its length, ending with an empty label:
+//
+// +---+---------+---+-----+---+
+// | 7 | example | 3 | com | 0 |
+// +---+---------+---+-----+---+
+//
+// In a message, a name may end with a pointer instead, two bytes whose
+// top bits are set, giving the offset of the rest of the name. Integers
+// are big endian. The readers return `None` when the message is too short
+// for what they read.
+
+// How many labels and pointers a name is followed through. A name fits in
+// 255 bytes, so no valid one has more, and a pointer loop stops here.
+const MAX_LABELS: usize = 128;
+
+// Reads a possibly compressed name starting at `pos`. Returns the name and
+// the position of the first byte following it.
+pub fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+
+    for _ in 0..MAX_LABELS {
+        let len = *packet.get(pos)? as usize;
+
+        if len == 0 {
+            return Some((name, end.unwrap_or(pos + 1)));
+        }
+
+        if len & 0xc0 == 0xc0 {
+            // Compression pointer, the rest of the name lives at the offset
+            let offset = read_u16(packet, pos)? as usize & 0x3fff;
+            end = end.or(Some(pos + 2));
+            pos = offset;
+            continue;
+        }
+
+        let label = packet.get(pos + 1..pos + 1 + len)?;
+
+        if !name.is_empty() {
+            name.push('.');
+        }
+
+        name.push_str(&String::from_utf8_lossy(label));
+        pos += 1 + len;
+    }
+
+    None
+}
+
+pub fn read_u16(packet: &[u8], pos: usize) -> Option<u16> {
+    let b = packet.get(pos..pos + 2)?;
+    Some((b[0] as u16) << 8 | b[1] as u16)
+}
+
+pub fn read_u32(packet: &[u8], pos: usize) -> Option<u32> {
+    let hi = read_u16(packet, pos)? as u32;
+    let lo = read_u16(packet, pos + 2)? as u32;
+    Some(hi << 16 | lo)
+}
+
+// Appends a name, uncompressed. A trailing dot, for the root, is optional.
+pub fn push_name(packet: &mut Vec<u8>, name: &str) {
+    for label in name.trim_end_matches('.').split('.') {
+        packet.push(label.len() as u8);
+        packet.extend(label.as_bytes());
+    }
+
+    packet.push(0);
+}
+
+pub fn push_u16(packet: &mut Vec<u8>, val: u16) {
+    packet.push((val >> 8) as u8);
+    packet.push(val as u8);
+}
+
+// Overwrites the integer at `pos`, which has to be in the packet already
+pub fn write_u16(packet: &mut [u8], pos: usize, val: u16) {
+    packet[pos] = (val >> 8) as u8;
+    packet[pos + 1] = val as u8;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_what_it_writes() {
+        let mut packet = vec![];
+        push_name(&mut packet, "www.example.com.");
+        push_u16(&mut packet, 28);
+
+        assert_eq!(read_name(&packet, 0), Some(("www.example.com".to_string(), 17)));
+        assert_eq!(read_u16(&packet, 17), Some(28));
+    }
+
+    #[test]
+    fn follows_pointers() {
+        let mut packet = vec![];
+        push_name(&mut packet, "example.com");
+        // "www" then a pointer to the name above
+        packet.extend(&[3, b'w', b'w', b'w', 0xc0, 0]);
+
+        assert_eq!(read_name(&packet, 13), Some(("www.example.com".to_string(), 19)));
+    }
+
+    #[test]
+    fn rejects_truncated_names() {
+        let mut packet = vec![];
+        push_name(&mut packet, "example.com");
+
+        for len in 0..packet.len() {
+            assert_eq!(read_name(&packet[..len], 0), None);
+        }
+
+        // A pointer missing its second byte, and one past the end
+        assert_eq!(read_name(&[0xc0], 0), None);
+        assert_eq!(read_name(&[0xc0, 9], 0), None);
+    }
+
+    #[test]
+    fn rejects_pointer_loops() {
+        assert_eq!(read_name(&[0xc0, 0], 0), None);
+        assert_eq!(read_name(&[1, b'a', 0xc0, 0], 0), None);
+    }
+
+    #[test]
+    fn reads_integers() {
+        let packet = [0x12, 0x34, 0x56, 0x78];
+
+        assert_eq!(read_u16(&packet, 2), Some(0x5678));
+        assert_eq!(read_u32(&packet, 0), Some(0x1234_5678));
+        assert_eq!(read_u16(&packet, 3), None);
+        assert_eq!(read_u32(&packet, 1), None);
+
+        let mut packet = packet.to_vec();
+        write_u16(&mut packet, 1, 0xabcd);
+        assert_eq!(packet, [0x12, 0xab, 0xcd, 0x78]);
+    }
+}
@@ -0,0 +1,386 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+extern crate libc;
+extern crate root_dir;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use root_dir::resolve;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// The request is a single short line
+const MAX_LINE: usize = 1_024;
+
+// The read-then-write path copies the file in chunks of this size
+const CHUNK: usize = 64 * 1_024;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Mode {
+    // The kernel copies the file from the page cache straight to the socket
+    Sendfile,
+    // The file is read into a buffer, which is then written to the socket
+    Copy,
+}
+
+struct Downloads {
+    acceptor: Acceptor<Factory<Connection>>,
+    root: PathBuf,
+    connections: Slab<Connection>,
+}
+
+impl Downloads {
+    fn new(acceptor: Acceptor<Factory<Connection>>, root: PathBuf) -> Downloads {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Downloads {
+            acceptor: acceptor,
+            root: root,
+            connections: slab,
+        }
+    }
+}
+
+impl mio::Handler for Downloads {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Downloads>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
+                }
+            }
+            _ => {
+                self.connections[token].ready(event_loop, events, &self.root);
+
+                if self.connections[token].closed {
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+}
+
+// A file being sent to the client
+struct Download {
+    file: File,
+    name: String,
+    mode: Mode,
+    // How much of the file has been written to the socket. `sendfile` is
+    // given the offset explicitly and leaves the file's position alone.
+    offset: u64,
+    len: u64,
+    started: Instant,
+    // Read from the file but not yet written, in copy mode
+    buf: Vec<u8>,
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Bytes of the request read so far
+    buf: Vec<u8>,
+    download: Option<Download>,
+    // An error reply, the connection is closed once it is written
+    out: Vec<u8>,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            buf: vec![],
+            download: None,
+            out: vec![],
+            closed: false,
+        }
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Downloads>, events: mio::EventSet, root: &Path) {
+        if events.is_readable() && self.download.is_none() && self.out.is_empty() {
+            self.read(root);
+        }
+
+        if self.download.is_some() && !self.closed {
+            self.send();
+        }
+
+        if !self.out.is_empty() && !self.closed {
+            self.write();
+        }
+
+        if !self.closed {
+            let interest = if self.download.is_some() || !self.out.is_empty() {
+                mio::EventSet::writable()
+            } else {
+                mio::EventSet::readable()
+            };
+
+            event_loop.reregister(&self.socket, self.token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn read(&mut self, root: &Path) {
+        let mut buf = [0; 1024];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    if let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+                        let line = String::from_utf8_lossy(&self.buf[..pos]).trim().to_string();
+                        return self.request(&line, root);
+                    }
+
+                    if self.buf.len() > MAX_LINE {
+                        println!("line too long, closing connection");
+                        self.closed = true;
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    // Either `SENDFILE <name>` or `COPY <name>`
+    fn request(&mut self, line: &str, root: &Path) {
+        let mut parts = line.splitn(2, ' ');
+
+        let mode = match &parts.next().unwrap_or("").to_uppercase()[..] {
+            "SENDFILE" => Mode::Sendfile,
+            "COPY" => Mode::Copy,
+            _ => return self.fail("ERR unknown command"),
+        };
+
+        let name = parts.next().unwrap_or("").trim().to_string();
+
+        let file = match resolve(root, &name).map(open) {
+            Some(Ok(file)) => file,
+            _ => return self.fail("ERR file not found"),
+        };
+
+        let len = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+
+        println!("starting download; file={:?}; mode={:?}; bytes={}", name, mode, len);
+
+        self.download = Some(Download {
+            file: file,
+            name: name,
+            mode: mode,
+            offset: 0,
+            len: len,
+            started: Instant::now(),
+            buf: vec![],
+        });
+    }
+
+    // Sends the file until the socket's buffer is full or the whole file
+    // has been sent.
+    fn send(&mut self) {
+        loop {
+            let download = self.download.as_mut().unwrap();
+
+            if download.offset == download.len {
+                break;
+            }
+
+            let res = match download.mode {
+                Mode::Sendfile => sendfile(&self.socket, download),
+                Mode::Copy => copy(&mut self.socket, download),
+            };
+
+            match res {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(ref e) if download.mode == Mode::Sendfile && unsupported(e) => {
+                    // Not every file can be sent by the kernel, fall back to
+                    // copying it from where `sendfile` left off.
+                    println!("sendfile not supported, copying instead; err={:?}", e);
+
+                    if let Err(e) = download.file.seek(SeekFrom::Start(download.offset)) {
+                        println!("failed to seek; err={:?}", e);
+                        self.closed = true;
+                        return;
+                    }
+
+                    download.mode = Mode::Copy;
+                }
+                Err(e) => {
+                    println!("download failed; file={:?}; err={:?}", download.name, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        let download = self.download.take().unwrap();
+        let elapsed = download.started.elapsed();
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+
+        println!("download complete; file={:?}; mode={:?}; bytes={}; elapsed_ms={}; MB/s={:.1}",
+                 download.name,
+                 download.mode,
+                 download.len,
+                 (secs * 1_000.0) as u64,
+                 download.len as f64 / secs / 1e6);
+
+        self.closed = true;
+    }
+
+    fn fail(&mut self, line: &str) {
+        self.out.extend(line.as_bytes());
+        self.out.push(b'\n');
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        self.closed = true;
+    }
+}
+
+// Writes the next part of the file to the socket without copying it
+// through user space. Returns false if the socket's buffer is full.
+#[cfg(target_os = "linux")]
+fn sendfile(socket: &TcpStream, download: &mut Download) -> io::Result<bool> {
+    let mut offset = download.offset as libc::off_t;
+
+    // Large counts are fine, the call returns once the socket is full. The
+    // cap keeps the count within what `ssize_t` can report back.
+    let count = std::cmp::min(download.len - download.offset, 1 << 30) as usize;
+
+    let ret = unsafe {
+        libc::sendfile(socket.as_raw_fd(), download.file.as_raw_fd(), &mut offset, count)
+    };
+
+    if ret < 0 {
+        let err = io::Error::last_os_error();
+
+        if err.kind() == io::ErrorKind::WouldBlock {
+            return Ok(false);
+        }
+
+        return Err(err);
+    }
+
+    if ret == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file was truncated"));
+    }
+
+    download.offset = offset as u64;
+    Ok(true)
+}
+
+// The BSDs and macOS have a `sendfile` with a different signature. The
+// example only uses the Linux one, other systems always copy.
+#[cfg(not(target_os = "linux"))]
+fn sendfile(_: &TcpStream, _: &mut Download) -> io::Result<bool> {
+    Err(io::Error::from_raw_os_error(libc::ENOSYS))
+}
+
+// Whether `sendfile` failed because it can't be used for this file, as
+// opposed to the transfer failing.
+fn unsupported(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::EINVAL) | Some(libc::ENOSYS))
+}
+
+// Reads the next chunk of the file if the previous one was written, then
+// writes as much of it as the socket takes. Returns false if the socket's
+// buffer is full.
+fn copy(socket: &mut TcpStream, download: &mut Download) -> io::Result<bool> {
+    if download.buf.is_empty() {
+        let n = (&mut download.file).take(CHUNK as u64).read_to_end(&mut download.buf)?;
+
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file was truncated"));
+        }
+    }
+
+    match socket.try_write(&download.buf)? {
+        Some(n) => {
+            download.buf.drain(..n);
+            download.offset += n as u64;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+fn open(path: PathBuf) -> io::Result<File> {
+    if !fs::metadata(&path)?.is_file() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "not a file"));
+    }
+
+    File::open(path)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let root = PathBuf::from(args.next().unwrap_or("files".to_string()));
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:9500".to_string()).parse().unwrap();
+
+    fs::create_dir_all(&root).unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mut downloads = Downloads::new(acceptor, root);
+
+    println!("running download server; addr={:?}; root={:?}", address, downloads.root);
+    event_loop.run(&mut downloads).unwrap();
+}
@@ -0,0 +1,170 @@
+extern crate bytes;
+extern crate failure_detector;
+extern crate mio;
+
+use bytes::SliceBuf;
+use failure_detector::Detector;
+use mio::udp::*;
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::time::Instant;
+use std::{env, process};
+
+const SOCKET: mio::Token = mio::Token(0);
+
+const MAX_DATAGRAM: usize = 512;
+
+const HEARTBEAT_MS: u64 = 500;
+
+// A failure is noticed on the first check after phi goes over the
+// threshold, checking more often than the timer's 100ms tick is pointless
+const CHECK_MS: u64 = 100;
+
+const THRESHOLD: f64 = 8.0;
+
+const STATUS_MS: u64 = 5_000;
+
+#[derive(Clone, Copy)]
+enum Timer {
+    Heartbeat,
+    Check,
+    Status,
+}
+
+// Sends a heartbeat, its id, to every peer, and watches for theirs. A peer
+// doesn't need to be listed to be watched, any node sending heartbeats is
+// one.
+struct Node {
+    id: String,
+    socket: UdpSocket,
+    peers: Vec<SocketAddr>,
+    detector: Detector<String>,
+    // Every node that sent a heartbeat, up or down, for the status
+    known: BTreeSet<String>,
+    started: Instant,
+}
+
+impl Node {
+    // Milliseconds since the node started, the detector's clock
+    fn now(&self) -> u64 {
+        let elapsed = self.started.elapsed();
+        elapsed.as_secs() * 1_000 + elapsed.subsec_nanos() as u64 / 1_000_000
+    }
+
+    fn send_heartbeats(&self) {
+        for peer in &self.peers {
+            // A lost heartbeat is what the detector is there to put up with
+            match self.socket.send_to(&mut SliceBuf::wrap(self.id.as_bytes()), peer) {
+                Ok(Some(())) => {}
+                Ok(None) => println!("the socket wasn't actually ready, dropping heartbeat; peer={}", peer),
+                Err(e) => println!("failed to send heartbeat; peer={}; err={:?}", peer, e),
+            }
+        }
+    }
+
+    fn receive(&mut self) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event.
+        loop {
+            let mut buf = Vec::with_capacity(MAX_DATAGRAM);
+
+            let src = match self.socket.recv_from(&mut buf) {
+                Ok(Some(src)) => src,
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            let id = String::from_utf8_lossy(&buf).trim().to_string();
+
+            if id.is_empty() || id == self.id {
+                continue;
+            }
+
+            let now = self.now();
+
+            if self.detector.heartbeat(id.clone(), now) {
+                println!("peer up; id={}; addr={}", id, src);
+            }
+
+            self.known.insert(id);
+        }
+    }
+
+    fn check(&mut self) {
+        let now = self.now();
+
+        for id in self.detector.check(now) {
+            println!("peer down; id={}; phi={:.1}", id, self.detector.phi(&id, now).unwrap());
+        }
+    }
+
+    fn status(&self) {
+        let now = self.now();
+
+        let peers: Vec<String> = self.known.iter().map(|id| {
+            let state = if self.detector.is_up(id) { "up" } else { "down" };
+            format!("{}={} (phi {:.2})", id, state, self.detector.phi(id, now).unwrap())
+        }).collect();
+
+        println!("status; id={}; peers=[{}]", self.id, peers.join(", "));
+    }
+}
+
+impl mio::Handler for Node {
+    type Timeout = Timer;
+    type Message = ();
+
+    fn ready(&mut self, _: &mut mio::EventLoop<Node>, _: mio::Token, _: mio::EventSet) {
+        self.receive();
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Node>, timer: Timer) {
+        match timer {
+            Timer::Heartbeat => {
+                self.send_heartbeats();
+                event_loop.timeout_ms(Timer::Heartbeat, HEARTBEAT_MS).unwrap();
+            }
+            Timer::Check => {
+                self.check();
+                event_loop.timeout_ms(Timer::Check, CHECK_MS).unwrap();
+            }
+            Timer::Status => {
+                self.status();
+                event_loop.timeout_ms(Timer::Status, STATUS_MS).unwrap();
+            }
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.len() < 2 || args[0].contains(char::is_whitespace) {
+        println!("usage: heartbeat <node id> <addr> [<peer addr> ...]");
+        process::exit(1);
+    }
+
+    let addr: SocketAddr = args[1].parse().unwrap();
+    let peers: Vec<SocketAddr> = args[2..].iter().map(|peer| peer.parse().unwrap()).collect();
+
+    let socket = UdpSocket::bound(&addr).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&socket, SOCKET, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+    event_loop.timeout_ms(Timer::Heartbeat, HEARTBEAT_MS).unwrap();
+    event_loop.timeout_ms(Timer::Check, CHECK_MS).unwrap();
+    event_loop.timeout_ms(Timer::Status, STATUS_MS).unwrap();
+
+    println!("running node; id={}; addr={}; peers={:?}", args[0], addr, peers);
+
+    let mut node = Node {
+        id: args[0].clone(),
+        socket: socket,
+        peers: peers,
+        detector: Detector::new(THRESHOLD, HEARTBEAT_MS),
+        known: BTreeSet::new(),
+        started: Instant::now(),
+    };
+
+    event_loop.run(&mut node).unwrap();
+}
@@ -0,0 +1,145 @@
+// A phi accrual failure detector, for telling which peers are up from the
+// heartbeats they send.
+//
+// A fixed timeout has to be picked for the worst network the peers will
+// ever be on: too short and a slow network looks like dead peers, too long
+// and dead peers go unnoticed for a while. Instead, the detector keeps the
+// intervals between the last heartbeats of every peer, and from their mean
+// and standard deviation, tells how unlikely it is that the next heartbeat
+// is still on its way. That is phi:
+//
+//     phi = -log10(probability of a heartbeat arriving this late or later)
+//
+// A phi of 1 means a 10% chance of being wrong when saying the peer is
+// down, 2 a 1% chance, 3 a 0.1% chance, and so on. The peer is down once
+// phi goes over the threshold, and back up on its next heartbeat. A peer
+// on a jittery network gets more slack than one on a steady network, with
+// the same threshold.
+//
+// Like the rest of the examples' protocol code, the detector does no I/O
+// and doesn't look at the clock. Times are milliseconds, from whenever the
+// caller likes, as long as they don't go backwards.
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+// The number of intervals kept per peer
+const WINDOW: usize = 100;
+
+// Heartbeats arriving like clockwork would make the smallest delay look
+// like a failure, the standard deviation is never taken to be less than
+// this
+const MIN_STD_DEV_MS: f64 = 100.0;
+
+struct Peer {
+    last: u64,
+    intervals: VecDeque<u64>,
+    up: bool,
+}
+
+pub struct Detector<K> {
+    peers: HashMap<K, Peer>,
+    threshold: f64,
+    // How often heartbeats are sent, standing in for the intervals of a
+    // peer until there are some
+    expected_ms: u64,
+}
+
+impl<K: Hash + Eq + Clone> Detector<K> {
+    // `threshold` is the phi past which a peer is considered down, 8 is a
+    // common choice. `expected_ms` is the interval peers send heartbeats
+    // at.
+    pub fn new(threshold: f64, expected_ms: u64) -> Detector<K> {
+        Detector {
+            peers: HashMap::new(),
+            threshold: threshold,
+            expected_ms: expected_ms,
+        }
+    }
+
+    // Records a heartbeat from the peer. Returns true if it is up again, or
+    // for the first time.
+    pub fn heartbeat(&mut self, peer: K, now: u64) -> bool {
+        let expected_ms = self.expected_ms;
+
+        let peer = self.peers.entry(peer).or_insert_with(|| {
+            Peer {
+                last: now,
+                intervals: vec![expected_ms].into_iter().collect(),
+                up: false,
+            }
+        });
+
+        // The silence of a peer that was down isn't an interval between
+        // heartbeats, it would only make the next failure slower to notice
+        if peer.up {
+            if peer.intervals.len() == WINDOW {
+                peer.intervals.pop_front();
+            }
+
+            peer.intervals.push_back(now.saturating_sub(peer.last));
+        }
+
+        peer.last = now;
+
+        let came_up = !peer.up;
+        peer.up = true;
+
+        came_up
+    }
+
+    // Marks down the peers whose phi went over the threshold, and returns
+    // them. Meant to be called on a timer, often enough for failures to be
+    // noticed in time.
+    pub fn check(&mut self, now: u64) -> Vec<K> {
+        let threshold = self.threshold;
+        let mut down = vec![];
+
+        for (key, peer) in self.peers.iter_mut() {
+            if peer.up && peer.phi(now) > threshold {
+                peer.up = false;
+                down.push(key.clone());
+            }
+        }
+
+        down
+    }
+
+    // `None` if the peer never sent a heartbeat
+    pub fn phi(&self, peer: &K, now: u64) -> Option<f64> {
+        self.peers.get(peer).map(|peer| peer.phi(now))
+    }
+
+    pub fn is_up(&self, peer: &K) -> bool {
+        self.peers.get(peer).map(|peer| peer.up).unwrap_or(false)
+    }
+
+    // Forgets a peer, it is new again on its next heartbeat
+    pub fn remove(&mut self, peer: &K) {
+        self.peers.remove(peer);
+    }
+}
+
+impl Peer {
+    fn phi(&self, now: u64) -> f64 {
+        let n = self.intervals.len() as f64;
+        let mean = self.intervals.iter().sum::<u64>() as f64 / n;
+        let variance = self.intervals.iter().map(|&i| (i as f64 - mean).powi(2)).sum::<f64>() / n;
+        let std_dev = variance.sqrt().max(MIN_STD_DEV_MS);
+
+        let elapsed = now.saturating_sub(self.last) as f64;
+
+        // The normal distribution's tail, with a logistic approximation of
+        // its cumulative distribution function. Close enough, and cheap.
+        // Both branches are the same value, written so that neither
+        // overflows into a NaN far from the mean.
+        let y = (elapsed - mean) / std_dev;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+
+        if elapsed > mean {
+            -(e / (1.0 + e)).log10()
+        } else {
+            -(1.0 - 1.0 / (1.0 + e)).log10()
+        }
+    }
+}
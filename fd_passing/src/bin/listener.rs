@@ -0,0 +1,192 @@
+extern crate mio;
+extern crate fd_passing;
+
+use fd_passing::DEFAULT_PATH;
+use mio::TryRead;
+use mio::tcp::*;
+use mio::unix::*;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::{env, fs, io};
+
+const SERVER: mio::Token = mio::Token(0);
+const CHANNEL: mio::Token = mio::Token(1);
+
+// Accepts TCP connections, but doesn't serve them. Each one is handed to a
+// worker process, in turn, over the Unix socket the worker connected to the
+// listener with. Once passed, the listener closes its own copy of the
+// connection, the worker's is the only one left.
+struct Listener {
+    server: TcpListener,
+    channel: UnixListener,
+    workers: Vec<Worker>,
+    // The worker the next connection goes to
+    next: usize,
+    // Tokens 2+ are used for workers, they are not reused
+    next_token: usize,
+}
+
+struct Worker {
+    socket: UnixStream,
+    token: mio::Token,
+    // Connections passed to it so far
+    passed: u64,
+}
+
+impl Listener {
+    fn accept_connection(&mut self, event_loop: &mut mio::EventLoop<Listener>) {
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => {
+                println!("the server socket wasn't actually ready");
+                return;
+            }
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        // A worker with a full socket buffer is skipped, the connection goes
+        // to the next one.
+        for _ in 0..self.workers.len() {
+            let i = self.next % self.workers.len();
+            self.next = i + 1;
+
+            let worker = &mut self.workers[i];
+
+            match fd_passing::send_fd(worker.socket.as_raw_fd(), socket.as_raw_fd()) {
+                Ok(Some(())) => {
+                    worker.passed += 1;
+                    println!("passed connection; peer={:?}; worker={:?}; passed={}", socket.peer_addr().ok(), worker.token, worker.passed);
+                    return;
+                }
+                Ok(None) => println!("worker is backed up; worker={:?}", worker.token),
+                Err(e) => println!("failed to pass connection; worker={:?}; err={:?}", worker.token, e),
+            }
+        }
+
+        // Dropping the socket closes the connection
+        println!("no worker available, dropping connection; peer={:?}", socket.peer_addr().ok());
+    }
+
+    fn accept_worker(&mut self, event_loop: &mut mio::EventLoop<Listener>) {
+        match self.channel.accept() {
+            Ok(Some(socket)) => {
+                let token = mio::Token(self.next_token);
+                self.next_token += 1;
+
+                // Workers never send anything. The socket only becomes
+                // readable when the worker goes away.
+                event_loop.register_opt(&socket, token, mio::EventSet::readable() | mio::EventSet::hup(), mio::PollOpt::edge())
+                    .unwrap();
+
+                self.workers.push(Worker {
+                    socket: socket,
+                    token: token,
+                    passed: 0,
+                });
+
+                println!("worker connected; worker={:?}; workers={}", token, self.workers.len());
+            }
+            Ok(None) => {
+                println!("the channel socket wasn't actually ready");
+            }
+            Err(e) => {
+                println!("encountered error while accepting worker; err={:?}", e);
+                event_loop.shutdown();
+            }
+        }
+    }
+
+    fn worker_ready(&mut self, event_loop: &mut mio::EventLoop<Listener>, token: mio::Token) {
+        let i = match self.workers.iter().position(|worker| worker.token == token) {
+            Some(i) => i,
+            None => return,
+        };
+
+        let mut buf = [0; 64];
+
+        // The socket is registered as edge triggered, drain it
+        let gone = loop {
+            match self.workers[i].socket.try_read(&mut buf) {
+                Ok(Some(0)) => break true,
+                Ok(Some(_)) => {}
+                Ok(None) => break false,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    break true;
+                }
+            }
+        };
+
+        if gone {
+            let worker = self.workers.remove(i);
+            event_loop.deregister(&worker.socket).unwrap();
+
+            println!("worker disconnected; worker={:?}; passed={}; workers={}", token, worker.passed, self.workers.len());
+        }
+    }
+}
+
+impl mio::Handler for Listener {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Listener>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+                self.accept_connection(event_loop);
+            }
+            CHANNEL => {
+                assert!(events.is_readable());
+                self.accept_worker(event_loop);
+            }
+            _ => self.worker_ready(event_loop, token),
+        }
+    }
+}
+
+// A socket file left behind by a listener that didn't shut down cleanly
+// makes the bind fail. If connecting to it is refused, no one is listening
+// on it anymore, and it is removed.
+fn bind_channel(path: &str) -> io::Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AddrInUse, "another listener is using the path"));
+    }
+
+    match fs::remove_file(path) {
+        Ok(()) => println!("removed stale socket; path={:?}", path),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    UnixListener::bind(path)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:9400".to_string()).parse().unwrap();
+    let path = args.next().unwrap_or(DEFAULT_PATH.to_string());
+
+    let server = TcpListener::bind(&address).unwrap();
+    let channel = bind_channel(&path).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+    event_loop.register(&channel, CHANNEL).unwrap();
+
+    let mut listener = Listener {
+        server: server,
+        channel: channel,
+        workers: vec![],
+        next: 0,
+        next_token: 2,
+    };
+
+    println!("running listener; addr={:?}; path={:?}", address, path);
+    event_loop.run(&mut listener).unwrap();
+}
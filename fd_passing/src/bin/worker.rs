@@ -0,0 +1,195 @@
+extern crate mio;
+extern crate fd_passing;
+
+use fd_passing::{DEFAULT_PATH, Received};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::unix::*;
+use mio::util::Slab;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::{env, process};
+
+const CHANNEL: mio::Token = mio::Token(0);
+
+// Stop reading from a client whose echo hasn't been written out yet
+const MAX_BUFFERED: usize = 64 * 1_024;
+
+// Serves the connections the listener passes to it: an echo service that
+// first tells the client which process it is talking to. The connections
+// were accepted by another process, but once received they are registered
+// with this worker's event loop like any other socket.
+struct Worker {
+    channel: UnixStream,
+    connections: Slab<Connection>,
+}
+
+impl Worker {
+    fn new(channel: UnixStream) -> Worker {
+        // Token `0` is reserved for the channel to the listener. Tokens 1+
+        // are used for client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Worker {
+            channel: channel,
+            connections: slab,
+        }
+    }
+
+    fn receive(&mut self, event_loop: &mut mio::EventLoop<Worker>) {
+        // The socket is registered as edge triggered, drain it
+        loop {
+            let fd = match fd_passing::recv_fd(self.channel.as_raw_fd()) {
+                Ok(Received::Fd(fd)) => fd,
+                Ok(Received::WouldBlock) => return,
+                Ok(Received::Closed) => {
+                    println!("the listener went away, shutting down");
+                    event_loop.shutdown();
+                    return;
+                }
+                Err(e) => {
+                    println!("got an error trying to receive a connection; err={:?}", e);
+                    event_loop.shutdown();
+                    return;
+                }
+            };
+
+            // The descriptor is ours now. It is still non-blocking, the
+            // flag belongs to the connection, not the descriptor.
+            let socket = unsafe { TcpStream::from_raw_fd(fd) };
+
+            println!("received connection; peer={:?}", socket.peer_addr().ok());
+
+            let token = match self.connections.insert_with(|token| Connection::new(socket, token)) {
+                Some(token) => token,
+                None => {
+                    println!("connection limit reached, dropping client");
+                    continue;
+                }
+            };
+
+            event_loop.register_opt(
+                &self.connections[token].socket,
+                token,
+                self.connections[token].interest(),
+                mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+        }
+    }
+}
+
+impl mio::Handler for Worker {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Worker>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            CHANNEL => self.receive(event_loop),
+            _ => {
+                self.connections[token].ready(event_loop, events);
+
+                if self.connections[token].closed {
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Read but not yet echoed back
+    buf: Vec<u8>,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            buf: format!("hello from worker {}\r\n", process::id()).into_bytes(),
+            closed: false,
+        }
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Worker>, events: mio::EventSet) {
+        if events.is_readable() {
+            self.read();
+        }
+
+        if !self.closed {
+            self.write();
+        }
+
+        if !self.closed {
+            event_loop.reregister(&self.socket, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn read(&mut self) {
+        let mut buf = [0; 1024];
+
+        // The socket is registered as edge triggered, keep reading until
+        // the socket is drained or there is no room left to buffer data.
+        while self.buf.len() < MAX_BUFFERED {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => self.buf.extend(&buf[..n]),
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.buf.is_empty() {
+            match self.socket.try_write(&self.buf) {
+                Ok(Some(n)) => {
+                    self.buf.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        let mut interest = mio::EventSet::none();
+
+        if self.buf.len() < MAX_BUFFERED {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.buf.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        interest
+    }
+}
+
+fn main() {
+    let path = env::args().nth(1).unwrap_or(DEFAULT_PATH.to_string());
+
+    let channel = UnixStream::connect(&path).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&channel, CHANNEL, mio::EventSet::readable() | mio::EventSet::hup(), mio::PollOpt::edge()).unwrap();
+
+    let mut worker = Worker::new(channel);
+
+    println!("running worker; pid={}; path={:?}", process::id(), path);
+    event_loop.run(&mut worker).unwrap();
+}
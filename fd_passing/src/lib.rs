@@ -0,0 +1,124 @@
+// Passing file descriptors between processes over a Unix domain socket.
+//
+// A descriptor is sent as `SCM_RIGHTS` ancillary data, alongside a regular
+// message. The kernel installs a new descriptor in the receiving process,
+// referring to the same open file: for a socket, the same connection, with
+// the same flags. A socket that was non-blocking in the sender is
+// non-blocking in the receiver too.
+//
+// Ancillary data can't be sent on its own over a stream socket, every
+// descriptor goes with a single byte of regular data. The receiver reads
+// one byte at a time, the kernel never merges two messages carrying
+// ancillary data into one read.
+//
+// The listener and the worker in `src/bin` share these.
+
+extern crate libc;
+
+use std::os::unix::io::RawFd;
+use std::{io, mem, ptr};
+
+pub const DEFAULT_PATH: &str = "/tmp/mio-fd-passing.sock";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Received {
+    Fd(RawFd),
+    // Nothing to read right now
+    WouldBlock,
+    // The peer closed the socket
+    Closed,
+}
+
+// Sends `fd` over `socket`. Returns `None` if the socket's buffer is full,
+// in which case nothing was sent. The caller still owns its copy of the
+// descriptor and should close it once it's sent.
+pub fn send_fd(socket: RawFd, fd: RawFd) -> io::Result<Option<()>> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: 1,
+    };
+
+    // Room for the ancillary data, aligned for its header
+    let mut control = [0u64; 8];
+    let space = unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) } as usize;
+    assert!(space <= mem::size_of_val(&control));
+
+    let ret = unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = space as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+
+        // A peer that went away must not kill the process with SIGPIPE
+        libc::sendmsg(socket, &msg, libc::MSG_NOSIGNAL)
+    };
+
+    if ret < 0 {
+        let err = io::Error::last_os_error();
+
+        if err.kind() == io::ErrorKind::WouldBlock {
+            return Ok(None);
+        }
+
+        return Err(err);
+    }
+
+    Ok(Some(()))
+}
+
+// Receives a descriptor sent with `send_fd`. The new descriptor belongs to
+// the caller, and is closed on exec.
+pub fn recv_fd(socket: RawFd) -> io::Result<Received> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: 1,
+    };
+
+    let mut control = [0u64; 8];
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let ret = unsafe { libc::recvmsg(socket, &mut msg, libc::MSG_CMSG_CLOEXEC) };
+
+    if ret < 0 {
+        let err = io::Error::last_os_error();
+
+        if err.kind() == io::ErrorKind::WouldBlock {
+            return Ok(Received::WouldBlock);
+        }
+
+        return Err(err);
+    }
+
+    if ret == 0 {
+        return Ok(Received::Closed);
+    }
+
+    // Descriptors that didn't fit in the buffer are closed by the kernel
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "ancillary data truncated"));
+    }
+
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+
+        if cmsg.is_null() || (*cmsg).cmsg_level != libc::SOL_SOCKET || (*cmsg).cmsg_type != libc::SCM_RIGHTS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "message without a descriptor"));
+        }
+
+        Ok(Received::Fd(ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RawFd)))
+    }
+}
@@ -0,0 +1,314 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use bytes::Buf;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::net::SocketAddr;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// RFC 1288 doesn't limit the query length, but nothing legitimate comes
+// close to this.
+const MAX_QUERY: usize = 512;
+
+// Login name -> lines of user information
+type Users = BTreeMap<String, Vec<String>>;
+
+struct Finger {
+    acceptor: Acceptor<Factory<Connection>>,
+    users: Users,
+    connections: Slab<Connection>,
+}
+
+impl Finger {
+    fn new(acceptor: Acceptor<Factory<Connection>>, users: Users) -> Finger {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Finger {
+            acceptor: acceptor,
+            users: users,
+            connections: slab,
+        }
+    }
+}
+
+impl mio::Handler for Finger {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Finger>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
+                }
+            }
+            _ => {
+                self.connections[token].ready(event_loop, events, &self.users);
+
+                if self.connections[token].is_closed() {
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    state: State,
+}
+
+// A finger connection goes through each state exactly once: read the query
+// line, write the response, close.
+#[derive(Debug)]
+enum State {
+    Reading(Vec<u8>),
+    Writing(Cursor<Vec<u8>>),
+    Closed,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            state: State::Reading(Vec::with_capacity(MAX_QUERY)),
+        }
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Finger>, events: mio::EventSet, users: &Users) {
+        match self.state {
+            State::Reading(..) => {
+                assert!(events.is_readable(), "unexpected events; events={:?}", events);
+                self.read(event_loop, users);
+            }
+            State::Writing(..) => {
+                assert!(events.is_writable(), "unexpected events; events={:?}", events);
+                self.write(event_loop);
+            }
+            State::Closed => unreachable!(),
+        }
+    }
+
+    fn read(&mut self, event_loop: &mut mio::EventLoop<Finger>, users: &Users) {
+        let res = match self.state {
+            State::Reading(ref mut buf) => self.socket.try_read_buf(buf),
+            _ => unreachable!(),
+        };
+
+        match res {
+            Ok(Some(0)) => {
+                self.state = State::Closed;
+            }
+            Ok(Some(_)) => {
+                let line = match self.state {
+                    State::Reading(ref buf) => {
+                        if buf.len() > MAX_QUERY {
+                            println!("query too long, closing connection");
+                            self.state = State::Closed;
+                            return;
+                        }
+
+                        buf.iter().position(|b| *b == b'\n').map(|pos| buf[..pos].to_vec())
+                    }
+                    _ => unreachable!(),
+                };
+
+                // Once the full query line has arrived, the response is
+                // generated in one go and the connection switches to writing.
+                if let Some(line) = line {
+                    let response = respond(&String::from_utf8_lossy(&line), users);
+                    self.state = State::Writing(Cursor::new(response.into_bytes()));
+                }
+
+                self.reregister(event_loop);
+            }
+            Ok(None) => {
+                self.reregister(event_loop);
+            }
+            Err(e) => {
+                println!("got an error trying to read; err={:?}", e);
+                self.state = State::Closed;
+            }
+        }
+    }
+
+    fn write(&mut self, event_loop: &mut mio::EventLoop<Finger>) {
+        let res = match self.state {
+            State::Writing(ref mut buf) => self.socket.try_write_buf(buf),
+            _ => unreachable!(),
+        };
+
+        match res {
+            Ok(Some(_)) => {
+                let done = match self.state {
+                    State::Writing(ref buf) => !buf.has_remaining(),
+                    _ => unreachable!(),
+                };
+
+                // The server closes the connection to signal the end of the
+                // response.
+                if done {
+                    self.state = State::Closed;
+                    return;
+                }
+
+                self.reregister(event_loop);
+            }
+            Ok(None) => {
+                self.reregister(event_loop);
+            }
+            Err(e) => {
+                println!("got an error trying to write; err={:?}", e);
+                self.state = State::Closed;
+            }
+        }
+    }
+
+    fn reregister(&self, event_loop: &mut mio::EventLoop<Finger>) {
+        let interest = match self.state {
+            State::Reading(..) => mio::EventSet::readable(),
+            State::Writing(..) => mio::EventSet::writable(),
+            State::Closed => return,
+        };
+
+        event_loop.reregister(&self.socket, self.token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn is_closed(&self) -> bool {
+        match self.state {
+            State::Closed => true,
+            _ => false,
+        }
+    }
+}
+
+// Generates the response to a query line, as described by RFC 1288:
+//
+// ""          list all users
+// "user"      details about "user"
+// "user@host" forward the query to "host"
+//
+// Any query may be prefixed by "/W" to request verbose output, which is
+// accepted and ignored.
+fn respond(line: &str, users: &Users) -> String {
+    let mut query = line.trim();
+
+    if query.starts_with("/W") {
+        query = query[2..].trim_start();
+    }
+
+    println!("received query; query={:?}", query);
+
+    if query.contains('@') {
+        // Forwarding queries turns the server into an anonymous relay, most
+        // servers refuse to do it.
+        return "Finger forwarding service denied.\r\n".to_string();
+    }
+
+    if query.is_empty() {
+        let mut response = String::from("Login      Name\r\n");
+
+        for (login, info) in users {
+            let name = info.iter()
+                .find(|line| line.starts_with("Name:"))
+                .map(|line| line[5..].trim())
+                .unwrap_or("");
+
+            response.push_str(&format!("{:<10} {}\r\n", login, name));
+        }
+
+        return response;
+    }
+
+    match users.get(query) {
+        Some(info) => {
+            let mut response = format!("Login: {}\r\n", query);
+
+            for line in info {
+                response.push_str(line);
+                response.push_str("\r\n");
+            }
+
+            response
+        }
+        None => format!("finger: {}: no such user.\r\n", query),
+    }
+}
+
+// Parses the user database. Each user starts with a `[login]` line and
+// every line after it, up to the next user, is returned as is. Blank lines
+// and `#` comments between users are skipped.
+fn load_users(path: &str) -> Users {
+    let mut contents = String::new();
+    File::open(path).unwrap().read_to_string(&mut contents).unwrap();
+
+    let mut users = Users::new();
+    let mut current: Option<(String, Vec<String>)> = None;
+
+    for line in contents.lines() {
+        if line.starts_with('[') && line.ends_with(']') {
+            if let Some((login, info)) = current.take() {
+                users.insert(login, info);
+            }
+
+            current = Some((line[1..line.len() - 1].to_string(), vec![]));
+            continue;
+        }
+
+        if let Some((_, ref mut info)) = current {
+            if !line.trim().is_empty() && !line.starts_with('#') {
+                info.push(line.to_string());
+            }
+        }
+    }
+
+    if let Some((login, info)) = current {
+        users.insert(login, info);
+    }
+
+    users
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let path = args.next().unwrap_or("users.conf".to_string());
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:7979".to_string()).parse().unwrap();
+
+    let users = load_users(&path);
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mut finger = Finger::new(acceptor, users);
+
+    println!("running finger server; addr={:?}; users={}", address, finger.users.len());
+    event_loop.run(&mut finger).unwrap();
+}
@@ -0,0 +1,717 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+extern crate root_dir;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use root_dir::resolve_flat;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Tokens `1..=MAX_SESSIONS` are used for control connections, the tokens
+// after that for data channels.
+const MAX_SESSIONS: usize = 1_024;
+const MAX_CHANNELS: usize = 1_024;
+
+// Commands are short, anything longer is a confused or hostile client
+const MAX_LINE: usize = 1_024;
+
+// Files are sent and received in chunks of this size
+const CHUNK: usize = 8 * 1_024;
+
+// How long a passive listener waits for the client to connect
+const ACCEPT_TIMEOUT_MS: u64 = 30_000;
+
+struct Config {
+    root: PathBuf,
+    user: String,
+    pass: String,
+}
+
+struct Ftp {
+    acceptor: Acceptor<Factory<Session>>,
+    config: Config,
+    sessions: Slab<Session>,
+    // Data channels come and go with each transfer. Each has a token of its
+    // own, which is freed, and may be reused, once the channel is closed.
+    channels: Slab<Channel>,
+}
+
+impl Ftp {
+    fn new(acceptor: Acceptor<Factory<Session>>, config: Config) -> Ftp {
+        // Token `0` is reserved for the server socket
+        let sessions = Slab::new_starting_at(mio::Token(1), MAX_SESSIONS);
+        let channels = Slab::new_starting_at(mio::Token(1 + MAX_SESSIONS), MAX_CHANNELS);
+
+        Ftp {
+            acceptor: acceptor,
+            config: config,
+            sessions: sessions,
+            channels: channels,
+        }
+    }
+
+    fn session_ready(&mut self, event_loop: &mut mio::EventLoop<Ftp>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            self.sessions[token].read();
+        }
+
+        while let Some(line) = self.sessions[token].next_line() {
+            self.command(event_loop, token, &line);
+        }
+
+        self.sessions[token].write();
+
+        if self.sessions[token].closed {
+            let session = self.sessions.remove(token).unwrap();
+
+            if let Some(channel) = session.channel {
+                self.close_channel(event_loop, channel);
+            }
+        } else {
+            self.sessions[token].reregister(event_loop);
+        }
+    }
+
+    fn command(&mut self, event_loop: &mut mio::EventLoop<Ftp>, token: mio::Token, line: &str) {
+        let mut parts = line.splitn(2, ' ');
+        let cmd = parts.next().unwrap_or("").to_uppercase();
+        let arg = parts.next().unwrap_or("").trim();
+
+        // Don't log passwords
+        if cmd == "PASS" {
+            println!("received command; line=\"PASS ***\"; token={:?}", token);
+        } else {
+            println!("received command; line={:?}; token={:?}", line, token);
+        }
+
+        let logged_in = self.sessions[token].logged_in;
+
+        let reply = match &cmd[..] {
+            "QUIT" => {
+                self.sessions[token].quit = true;
+                "221 Goodbye".to_string()
+            }
+            "USER" => {
+                let session = &mut self.sessions[token];
+                session.user = Some(arg.to_string());
+                session.logged_in = false;
+                "331 Password required".to_string()
+            }
+            "PASS" => {
+                let session = &mut self.sessions[token];
+
+                if session.user.take().as_ref() == Some(&self.config.user) && arg == self.config.pass {
+                    session.logged_in = true;
+                    "230 Logged in".to_string()
+                } else {
+                    "530 Invalid username or password".to_string()
+                }
+            }
+            _ if !logged_in => "530 Not logged in".to_string(),
+            "SYST" => "215 UNIX Type: L8".to_string(),
+            "PWD" => "257 \"/\" is the only directory".to_string(),
+            // Every transfer is binary, ASCII mode would mean translating
+            // line endings.
+            "TYPE" => "200 Type set".to_string(),
+            "NOOP" => "200 OK".to_string(),
+            "PASV" => self.passive(event_loop, token),
+            "LIST" | "RETR" | "STOR" if self.idle_channel(token).is_none() => "425 Use PASV first".to_string(),
+            "LIST" => {
+                match listing(&self.config.root) {
+                    Ok(listing) => self.transfer(event_loop, token, Job::Send(Box::new(Cursor::new(listing)))),
+                    Err(_) => "450 Unable to list files".to_string(),
+                }
+            }
+            "RETR" => {
+                match resolve_flat(&self.config.root, arg).map(File::open) {
+                    Some(Ok(file)) => self.transfer(event_loop, token, Job::Send(Box::new(file))),
+                    _ => "550 File not found".to_string(),
+                }
+            }
+            "STOR" => {
+                match resolve_flat(&self.config.root, arg).map(File::create) {
+                    Some(Ok(file)) => self.transfer(event_loop, token, Job::Receive(file)),
+                    _ => "553 File name not allowed".to_string(),
+                }
+            }
+            _ => "502 Command not implemented".to_string(),
+        };
+
+        self.sessions[token].reply(&reply);
+    }
+
+    // Opens a listener on a port picked by the kernel, for the next
+    // transfer's data connection.
+    fn passive(&mut self, event_loop: &mut mio::EventLoop<Ftp>, token: mio::Token) -> String {
+        // A session has one data channel at most, a new PASV replaces it
+        if let Some(channel) = self.sessions[token].channel {
+            self.close_channel(event_loop, channel);
+        }
+
+        // The listener is bound to the address the client connected to, the
+        // one address it is known to be able to reach.
+        let (local, peer) = {
+            let socket = &self.sessions[token].socket;
+
+            match (socket.local_addr(), socket.peer_addr()) {
+                (Ok(SocketAddr::V4(local)), Ok(peer)) => (*local.ip(), peer.ip()),
+                _ => return "425 Passive mode requires IPv4".to_string(),
+            }
+        };
+
+        let listener = match TcpListener::bind(&SocketAddr::new(IpAddr::V4(local), 0)) {
+            Ok(listener) => listener,
+            Err(e) => {
+                println!("failed to open passive listener; err={:?}", e);
+                return "425 Can't open data connection".to_string();
+            }
+        };
+
+        let port = listener.local_addr().unwrap().port();
+
+        let channel = match self.channels.insert_with(|channel| Channel::new(listener, channel, token, peer)) {
+            Some(channel) => channel,
+            None => return "425 Too many data connections".to_string(),
+        };
+
+        event_loop.register_opt(
+            self.channels[channel].listener().unwrap(),
+            channel,
+            mio::EventSet::readable(),
+            mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+
+        self.channels[channel].timeout = Some(event_loop.timeout_ms(channel, ACCEPT_TIMEOUT_MS).unwrap());
+        self.sessions[token].channel = Some(channel);
+
+        println!("opened passive listener; port={}; channel={:?}; token={:?}", port, channel, token);
+
+        let ip = local.octets();
+        format!("227 Entering Passive Mode ({},{},{},{},{},{})", ip[0], ip[1], ip[2], ip[3], port >> 8, port & 0xff)
+    }
+
+    // The session's data channel, if it has one that isn't used yet. Every
+    // transfer needs a PASV of its own.
+    fn idle_channel(&self, token: mio::Token) -> Option<mio::Token> {
+        self.sessions[token].channel.filter(|channel| self.channels[*channel].job.is_none())
+    }
+
+    // Hands a transfer to the session's data channel. It starts once the
+    // client has connected, which may already be the case.
+    fn transfer(&mut self, event_loop: &mut mio::EventLoop<Ftp>, token: mio::Token, job: Job) -> String {
+        let channel = self.idle_channel(token).unwrap();
+
+        self.channels[channel].start(event_loop, job);
+        "150 Opening data connection".to_string()
+    }
+
+    fn channel_ready(&mut self, event_loop: &mut mio::EventLoop<Ftp>, token: mio::Token, events: mio::EventSet) {
+        self.channels[token].ready(event_loop, events);
+
+        if self.channels[token].closed {
+            self.close_channel(event_loop, token);
+        }
+    }
+
+    // Tears down a data channel, freeing its token. The outcome of the
+    // transfer, if there was one, is reported on the control connection.
+    fn close_channel(&mut self, event_loop: &mut mio::EventLoop<Ftp>, token: mio::Token) {
+        let channel = self.channels.remove(token).unwrap();
+
+        println!("closing data channel; channel={:?}", token);
+
+        if let Some(timeout) = channel.timeout {
+            event_loop.clear_timeout(timeout);
+        }
+
+        // The control connection is gone when it is the one closing
+        if let Some(session) = self.sessions.get_mut(channel.session) {
+            session.channel = None;
+
+            if let Some(reply) = channel.reply {
+                session.reply(reply);
+                session.reregister(event_loop);
+            }
+        }
+    }
+}
+
+impl mio::Handler for Ftp {
+    // The data channel whose listener should stop waiting for the client
+    type Timeout = mio::Token;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Ftp>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                let (tokens, res) = self.acceptor.accept(event_loop, &mut self.sessions);
+
+                for token in tokens {
+                    println!("accepted a new client socket; token={:?}", token);
+                }
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
+                }
+            }
+            _ if token.as_usize() <= MAX_SESSIONS => self.session_ready(event_loop, token, events),
+            _ => self.channel_ready(event_loop, token, events),
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Ftp>, token: mio::Token) {
+        println!("client never opened the data connection; channel={:?}", token);
+
+        let channel = &mut self.channels[token];
+        channel.timeout = None;
+
+        if channel.job.is_some() {
+            channel.reply = Some("425 Can't open data connection");
+        }
+
+        self.close_channel(event_loop, token);
+    }
+}
+
+/*
+ *
+ * ===== Control connections =====
+ *
+ */
+
+struct Session {
+    socket: TcpStream,
+    token: mio::Token,
+    // The user name given with USER, waiting for PASS
+    user: Option<String>,
+    logged_in: bool,
+    // The data channel opened by the last PASV
+    channel: Option<mio::Token>,
+    // Bytes read but not yet processed
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Set when the connection should be closed once `out` is flushed
+    quit: bool,
+    closed: bool,
+}
+
+impl acceptor::Connection for Session {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        Session::interest(self)
+    }
+}
+
+impl Session {
+    fn new(socket: TcpStream, token: mio::Token) -> Session {
+        let mut session = Session {
+            socket: socket,
+            token: token,
+            user: None,
+            logged_in: false,
+            channel: None,
+            buf: vec![],
+            out: vec![],
+            quit: false,
+            closed: false,
+        };
+
+        session.reply("220 mio FTP server ready");
+        session
+    }
+
+    fn read(&mut self) {
+        let mut buf = [0; 1024];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    if self.buf.len() > MAX_LINE && !self.buf[..MAX_LINE].contains(&b'\n') {
+                        println!("line too long, closing connection");
+                        self.closed = true;
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    // Takes the next complete command out of the read buffer
+    fn next_line(&mut self) -> Option<String> {
+        if self.quit || self.closed {
+            return None;
+        }
+
+        let pos = self.buf.iter().position(|b| *b == b'\n')?;
+        let line = self.buf.drain(..pos + 1).collect::<Vec<u8>>();
+
+        Some(String::from_utf8_lossy(&line).trim_end().to_string())
+    }
+
+    fn reply(&mut self, line: &str) {
+        self.out.extend(line.as_bytes());
+        self.out.extend(b"\r\n");
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        if self.quit {
+            self.closed = true;
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+
+    fn reregister(&self, event_loop: &mut mio::EventLoop<Ftp>) {
+        event_loop.reregister(&self.socket, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+/*
+ *
+ * ===== Data channels =====
+ *
+ */
+
+enum Socket {
+    // Waiting for the client to connect
+    Listening(TcpListener),
+    Connected(TcpStream),
+}
+
+enum Job {
+    // Sends a listing or a file, until the end
+    Send(Box<dyn Read>),
+    // Writes what the client sends to a file, until the client closes the
+    // connection.
+    Receive(File),
+}
+
+struct Channel {
+    socket: Socket,
+    token: mio::Token,
+    // The control connection the channel belongs to
+    session: mio::Token,
+    // Only the client of the control connection may connect
+    peer: IpAddr,
+    // Set by the transfer command, which may arrive before or after the
+    // client connects.
+    job: Option<Job>,
+    // Bytes read from the file but not yet written
+    out: Vec<u8>,
+    // Runs until the client connects
+    timeout: Option<mio::Timeout>,
+    // The line to reply on the control connection once closed
+    reply: Option<&'static str>,
+    closed: bool,
+}
+
+impl Channel {
+    fn new(listener: TcpListener, token: mio::Token, session: mio::Token, peer: IpAddr) -> Channel {
+        Channel {
+            socket: Socket::Listening(listener),
+            token: token,
+            session: session,
+            peer: peer,
+            job: None,
+            out: vec![],
+            timeout: None,
+            reply: None,
+            closed: false,
+        }
+    }
+
+    fn listener(&self) -> Option<&TcpListener> {
+        match self.socket {
+            Socket::Listening(ref listener) => Some(listener),
+            Socket::Connected(_) => None,
+        }
+    }
+
+    fn start(&mut self, event_loop: &mut mio::EventLoop<Ftp>, job: Job) {
+        self.job = Some(job);
+
+        // Until now the connection was registered with no interest
+        if let Socket::Connected(ref stream) = self.socket {
+            event_loop.reregister(stream, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Ftp>, events: mio::EventSet) {
+        let stream = match self.socket {
+            Socket::Listening(ref listener) => {
+                match listener.accept() {
+                    Ok(Some(stream)) => Some(stream),
+                    Ok(None) => {
+                        println!("the data socket wasn't actually ready");
+                        None
+                    }
+                    Err(e) => {
+                        println!("encountered error while accepting data connection; err={:?}", e);
+                        self.close(Some("425 Can't open data connection"));
+                        return;
+                    }
+                }
+            }
+            Socket::Connected(_) => return self.transfer(event_loop, events),
+        };
+
+        if let Some(stream) = stream {
+            // Otherwise anyone could connect first and grab the file
+            let peer = stream.peer_addr().map(|addr| addr.ip()).ok();
+
+            if peer == Some(self.peer) {
+                println!("data connection opened; channel={:?}", self.token);
+
+                if let Some(timeout) = self.timeout.take() {
+                    event_loop.clear_timeout(timeout);
+                }
+
+                // Dropping the listener closes it, the port is only used for
+                // this one connection.
+                event_loop.register_opt(&stream, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                    .unwrap();
+
+                self.socket = Socket::Connected(stream);
+                return;
+            }
+
+            println!("refusing data connection from another host; peer={:?}", peer);
+        }
+
+        event_loop.reregister(self.listener().unwrap(), self.token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn transfer(&mut self, event_loop: &mut mio::EventLoop<Ftp>, events: mio::EventSet) {
+        let done = {
+            let stream = match self.socket {
+                Socket::Connected(ref mut stream) => stream,
+                Socket::Listening(_) => unreachable!(),
+            };
+
+            match self.job {
+                Some(Job::Send(ref mut src)) => send(stream, src, &mut self.out),
+                Some(Job::Receive(ref mut file)) => receive(stream, file),
+                None => {
+                    // The connection was registered with no interest, the
+                    // client hung up before sending a transfer command.
+                    if events.is_hup() || events.is_error() {
+                        self.closed = true;
+                    }
+
+                    None
+                }
+            }
+        };
+
+        match done {
+            Some(reply) => self.close(Some(reply)),
+            None if !self.closed => {
+                if let Socket::Connected(ref stream) = self.socket {
+                    event_loop.reregister(stream, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                        .unwrap();
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn close(&mut self, reply: Option<&'static str>) {
+        self.reply = reply;
+        self.closed = true;
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        match self.job {
+            Some(Job::Send(_)) => mio::EventSet::writable(),
+            Some(Job::Receive(_)) => mio::EventSet::readable(),
+            None => mio::EventSet::none(),
+        }
+    }
+}
+
+// Writes `src` to the data connection until the socket's buffer is full.
+// Returns the reply for the control connection once done.
+fn send(stream: &mut TcpStream, src: &mut Box<dyn Read>, out: &mut Vec<u8>) -> Option<&'static str> {
+    loop {
+        if out.is_empty() {
+            let mut chunk = [0; CHUNK];
+
+            // Reading from a regular file blocks, there is no readiness to
+            // wait on. A single chunk from a local disk is fast enough.
+            match src.read(&mut chunk) {
+                Ok(0) => return Some("226 Transfer complete"),
+                Ok(n) => out.extend(&chunk[..n]),
+                Err(e) => {
+                    println!("failed to read file; err={:?}", e);
+                    return Some("451 Local error reading file");
+                }
+            }
+        }
+
+        match stream.try_write(out) {
+            Ok(Some(n)) => {
+                out.drain(..n);
+            }
+            Ok(None) => return None,
+            Err(e) => {
+                println!("got an error trying to write; err={:?}", e);
+                return Some("426 Connection closed; transfer aborted");
+            }
+        }
+    }
+}
+
+// Writes what the client sent to the file. The end of the file is the end
+// of the connection.
+fn receive(stream: &mut TcpStream, file: &mut File) -> Option<&'static str> {
+    let mut buf = [0; CHUNK];
+
+    // The socket is registered as edge triggered, drain it
+    loop {
+        match stream.try_read(&mut buf) {
+            Ok(Some(0)) => return Some("226 Transfer complete"),
+            Ok(Some(n)) => {
+                if let Err(e) = file.write_all(&buf[..n]) {
+                    println!("failed to write file; err={:?}", e);
+                    return Some("451 Local error writing file");
+                }
+            }
+            Ok(None) => return None,
+            Err(e) => {
+                println!("got an error trying to read; err={:?}", e);
+                return Some("426 Connection closed; transfer aborted");
+            }
+        }
+    }
+}
+
+/*
+ *
+ * ===== Files =====
+ *
+ */
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// Lists the files of the root in the format of `ls -l`. RFC 959 leaves the
+// format of LIST up to the server, but that is the one clients can parse.
+// Subdirectories are left out, there is no CWD to enter them with.
+fn listing(root: &Path) -> io::Result<Vec<u8>> {
+    let mut files = vec![];
+
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+
+        if meta.is_file() {
+            let modified = meta.modified()?.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            files.push((entry.file_name().to_string_lossy().into_owned(), meta.len(), modified));
+        }
+    }
+
+    files.sort();
+
+    let mut listing = vec![];
+
+    for (name, size, modified) in files {
+        let line = format!("-rw-r--r-- 1 ftp ftp {:>12} {} {}\r\n", size, date(modified), name);
+        listing.extend(line.as_bytes());
+    }
+
+    Ok(listing)
+}
+
+// Formats seconds since the epoch as "Oct 11  2015"
+fn date(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+
+    // Converts days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{} {:>2} {:>5}", MONTHS[month as usize - 1], day, year)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let root = PathBuf::from(args.next().unwrap_or("files".to_string()));
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:2121".to_string()).parse().unwrap();
+
+    fs::create_dir_all(&root).unwrap();
+
+    let config = Config {
+        root: root,
+        user: env::var("FTP_USER").unwrap_or("user".to_string()),
+        pass: env::var("FTP_PASS").unwrap_or("pass".to_string()),
+    };
+
+    let acceptor = Acceptor::bind(&address, SERVER, Session::new as Factory<Session>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mut ftp = Ftp::new(acceptor, config);
+
+    println!("running FTP server; addr={:?}; root={:?}", address, ftp.config.root);
+    event_loop.run(&mut ftp).unwrap();
+}
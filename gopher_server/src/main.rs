@@ -0,0 +1,362 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+extern crate root_dir;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use root_dir::resolve;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_SELECTOR: usize = 1_024;
+
+// Files are streamed to the client in chunks of this size
+const CHUNK: usize = 8 * 1_024;
+
+struct Config {
+    root: PathBuf,
+    // The host and port are embedded in every menu line so that the client
+    // knows where to send the next request.
+    host: String,
+    port: u16,
+}
+
+struct Gopher {
+    acceptor: Acceptor<Factory<Connection>>,
+    config: Config,
+    connections: Slab<Connection>,
+}
+
+impl Gopher {
+    fn new(acceptor: Acceptor<Factory<Connection>>, config: Config) -> Gopher {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Gopher {
+            acceptor: acceptor,
+            config: config,
+            connections: slab,
+        }
+    }
+}
+
+impl mio::Handler for Gopher {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Gopher>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
+                }
+            }
+            _ => {
+                self.connections[token].ready(event_loop, events, &self.config);
+
+                if self.connections[token].is_closed() {
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    state: State,
+}
+
+#[derive(Debug)]
+enum State {
+    // Waiting for the selector line
+    Reading(Vec<u8>),
+    // Sending the response
+    Writing(Response),
+    Closed,
+}
+
+#[derive(Debug)]
+struct Response {
+    buf: Vec<u8>,
+    pos: usize,
+    // When serving a file, the rest of its contents. The buffer is refilled
+    // from the file every time it has been fully written, so a large file
+    // only ever occupies `CHUNK` bytes of memory.
+    file: Option<File>,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            state: State::Reading(Vec::with_capacity(MAX_SELECTOR)),
+        }
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Gopher>, events: mio::EventSet, config: &Config) {
+        match self.state {
+            State::Reading(..) => {
+                assert!(events.is_readable(), "unexpected events; events={:?}", events);
+                self.read(config);
+            }
+            State::Writing(..) => {
+                assert!(events.is_writable(), "unexpected events; events={:?}", events);
+                self.write();
+            }
+            State::Closed => unreachable!(),
+        }
+
+        self.reregister(event_loop);
+    }
+
+    fn read(&mut self, config: &Config) {
+        let selector = match self.state {
+            State::Reading(ref mut buf) => {
+                match self.socket.try_read_buf(buf) {
+                    Ok(Some(0)) => None,
+                    Ok(Some(_)) => {
+                        match buf.iter().position(|b| *b == b'\n') {
+                            Some(pos) => Some(String::from_utf8_lossy(&buf[..pos]).trim().to_string()),
+                            // Keep waiting for the rest of the line
+                            None if buf.len() < MAX_SELECTOR => return,
+                            None => None,
+                        }
+                    }
+                    Ok(None) => return,
+                    Err(e) => {
+                        println!("got an error trying to read; err={:?}", e);
+                        None
+                    }
+                }
+            }
+            _ => unreachable!(),
+        };
+
+        self.state = match selector {
+            Some(selector) => {
+                println!("received request; selector={:?}", selector);
+                State::Writing(respond(&selector, config))
+            }
+            None => State::Closed,
+        };
+    }
+
+    fn write(&mut self) {
+        let done = match self.state {
+            State::Writing(ref mut res) => {
+                // The socket is registered as edge triggered, keep writing
+                // until the socket's buffer is full or the response is done.
+                loop {
+                    if res.pos == res.buf.len() && !res.refill() {
+                        break true;
+                    }
+
+                    match self.socket.try_write(&res.buf[res.pos..]) {
+                        Ok(Some(n)) => res.pos += n,
+                        Ok(None) => break false,
+                        Err(e) => {
+                            println!("got an error trying to write; err={:?}", e);
+                            break true;
+                        }
+                    }
+                }
+            }
+            _ => unreachable!(),
+        };
+
+        // The end of the response is signaled by closing the connection
+        if done {
+            self.state = State::Closed;
+        }
+    }
+
+    fn reregister(&self, event_loop: &mut mio::EventLoop<Gopher>) {
+        let interest = match self.state {
+            State::Reading(..) => mio::EventSet::readable(),
+            State::Writing(..) => mio::EventSet::writable(),
+            State::Closed => return,
+        };
+
+        event_loop.reregister(&self.socket, self.token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn is_closed(&self) -> bool {
+        match self.state {
+            State::Closed => true,
+            _ => false,
+        }
+    }
+}
+
+impl Response {
+    fn text(text: String) -> Response {
+        Response {
+            buf: text.into_bytes(),
+            pos: 0,
+            file: None,
+        }
+    }
+
+    fn file(file: File) -> Response {
+        Response {
+            buf: vec![],
+            pos: 0,
+            file: Some(file),
+        }
+    }
+
+    // Reads the next chunk of the file into the buffer. Returns false when
+    // there is nothing left to send.
+    //
+    // Reading from a regular file blocks, there is no readiness to wait on.
+    // Local disk reads of a single chunk are fast enough for this example,
+    // but a slow disk or network filesystem would stall the whole loop.
+    fn refill(&mut self) -> bool {
+        let file = match self.file {
+            Some(ref mut file) => file,
+            None => return false,
+        };
+
+        self.buf.resize(CHUNK, 0);
+        self.pos = 0;
+
+        match file.read(&mut self.buf) {
+            Ok(0) | Err(_) => {
+                self.buf.clear();
+                false
+            }
+            Ok(n) => {
+                self.buf.truncate(n);
+                true
+            }
+        }
+    }
+}
+
+fn respond(selector: &str, config: &Config) -> Response {
+    // The empty selector is the root menu, which names no file
+    let path = match resolve(&config.root, selector) {
+        Some(path) => path,
+        None if selector.trim_start_matches('/').is_empty() => config.root.clone(),
+        None => return error("invalid selector", config),
+    };
+
+    if path.is_dir() {
+        // An unreadable directory is an error item, like a missing file
+        return match menu(&path, selector, config) {
+            Ok(menu) => Response::text(menu),
+            Err(e) => {
+                println!("failed to list directory; path={:?}; err={:?}", path, e);
+                error("cannot list directory", config)
+            }
+        };
+    }
+
+    match File::open(&path) {
+        Ok(file) => Response::file(file),
+        Err(_) => error("not found", config),
+    }
+}
+
+// Builds a gopher menu listing a directory. Each line is:
+//
+// <type><display name> TAB <selector> TAB <host> TAB <port> CRLF
+//
+// and the menu ends with a line containing a single period.
+fn menu(dir: &Path, selector: &str, config: &Config) -> io::Result<String> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .collect();
+
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let base = selector.trim_end_matches('/');
+    let mut menu = String::new();
+
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+
+        if name.starts_with('.') {
+            continue;
+        }
+
+        let kind = if entry.path().is_dir() {
+            '1'
+        } else if is_text(&entry.path()) {
+            '0'
+        } else {
+            '9'
+        };
+
+        menu.push_str(&format!("{}{}\t{}/{}\t{}\t{}\r\n", kind, name, base, name, config.host, config.port));
+    }
+
+    menu.push_str(".\r\n");
+    Ok(menu)
+}
+
+// Guesses whether a file is text by checking that its first block is
+// valid UTF-8 with no NUL bytes.
+fn is_text(path: &Path) -> bool {
+    let mut buf = [0; 512];
+
+    let n = match File::open(path).and_then(|mut file| file.read(&mut buf)) {
+        Ok(n) => n,
+        Err(_) => return false,
+    };
+
+    !buf[..n].contains(&0) && std::str::from_utf8(&buf[..n]).is_ok()
+}
+
+fn error(msg: &str, config: &Config) -> Response {
+    Response::text(format!("3{}\t\t{}\t{}\r\n.\r\n", msg, config.host, config.port))
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let root = PathBuf::from(args.next().unwrap_or("public".to_string()));
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:7070".to_string()).parse().unwrap();
+    let host = args.next().unwrap_or("localhost".to_string());
+
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let config = Config {
+        root: root,
+        host: host,
+        port: address.port(),
+    };
+
+    let mut gopher = Gopher::new(acceptor, config);
+
+    println!("running gopher server; addr={:?}; root={:?}", address, gopher.config.root);
+    event_loop.run(&mut gopher).unwrap();
+}
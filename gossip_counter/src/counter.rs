@@ -0,0 +1,101 @@
+// A grow-only counter, the simplest CRDT (conflict-free replicated data
+// type). Every node keeps a count per node, and only ever increments its
+// own. The counter's value is the sum of them all.
+//
+// Two copies are merged by taking the larger count for each node. Merging
+// is commutative, associative and idempotent: copies can be merged in any
+// order, any number of times, and once every node has seen every other
+// node's state, directly or through others, they all have the same value.
+// There is nothing to coordinate, losing or duplicating gossip only slows
+// down convergence.
+//
+// The state is sent as a single line, the id of the node sending it and
+// its counts:
+//
+//     <sender> <node>=<count> <node>=<count> ...
+//
+// This does no I/O.
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GCounter {
+    counts: BTreeMap<String, u64>,
+}
+
+impl GCounter {
+    pub fn new() -> GCounter {
+        GCounter::default()
+    }
+
+    pub fn increment(&mut self, node: &str, n: u64) {
+        *self.counts.entry(node.to_string()).or_insert(0) += n;
+    }
+
+    pub fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    // Returns true if anything changed
+    pub fn merge(&mut self, other: &GCounter) -> bool {
+        let mut changed = false;
+
+        for (node, &count) in &other.counts {
+            let mine = self.counts.entry(node.clone()).or_insert(0);
+
+            if count > *mine {
+                *mine = count;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    // True if this copy has counts `other` doesn't have yet
+    pub fn is_ahead_of(&self, other: &GCounter) -> bool {
+        self.counts.iter().any(|(node, &count)| count > other.counts.get(node).cloned().unwrap_or(0))
+    }
+
+    pub fn encode(&self, sender: &str) -> String {
+        let mut line = sender.to_string();
+
+        for (node, count) in &self.counts {
+            line.push_str(&format!(" {}={}", node, count));
+        }
+
+        line
+    }
+
+    // Returns the sender and its state, `None` if the line is malformed
+    pub fn decode(line: &str) -> Option<(&str, GCounter)> {
+        let mut parts = line.trim_end().split(' ');
+        let sender = parts.next().filter(|sender| is_valid_id(sender))?;
+
+        let mut counter = GCounter::new();
+
+        for part in parts {
+            let mut kv = part.splitn(2, '=');
+
+            match (kv.next(), kv.next().map(|count| count.parse::<u64>())) {
+                (Some(node), Some(Ok(count))) if is_valid_id(node) => {
+                    counter.counts.insert(node.to_string(), count);
+                }
+                _ => return None,
+            }
+        }
+
+        Some((sender, counter))
+    }
+
+    // `node=count` pairs, for showing the state
+    pub fn describe(&self) -> String {
+        let counts: Vec<String> = self.counts.iter().map(|(node, count)| format!("{}={}", node, count)).collect();
+        counts.join(" ")
+    }
+}
+
+// Node ids end up in the encoding, they can't have spaces or `=`
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && !id.contains([' ', '=', '\n'])
+}
@@ -0,0 +1,304 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+extern crate rand;
+
+mod counter;
+
+use acceptor::{Acceptor, Factory};
+use bytes::SliceBuf;
+use counter::GCounter;
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::udp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::{env, process};
+
+const GOSSIP: mio::Token = mio::Token(0);
+const SERVER: mio::Token = mio::Token(1);
+
+const MAX_CONNECTIONS: usize = 128;
+
+// Large enough for any datagram that isn't fragmented on a typical network,
+// and for the state of a few dozen nodes
+const MAX_DATAGRAM: usize = 1_500;
+
+const MAX_LINE: usize = 1_024;
+
+// Every round, the state is sent to this many peers picked at random
+const GOSSIP_MS: u64 = 1_000;
+const FANOUT: usize = 2;
+
+// Each node keeps a copy of the counter. Increments are made locally, over
+// TCP, and spread to the other nodes by gossip over UDP: every round, a
+// node sends its whole state to a few random peers, and merges the states
+// it receives. A node that gets a state older than its own answers with
+// its own, so both ends of an exchange come out up to date.
+struct Node {
+    id: String,
+    counter: GCounter,
+    gossip: UdpSocket,
+    peers: Vec<SocketAddr>,
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+}
+
+impl Node {
+    fn round(&mut self) {
+        let line = self.counter.encode(&self.id);
+
+        // The first ones of a partial shuffle. With fewer peers than the
+        // fanout, all of them.
+        let mut peers = self.peers.clone();
+
+        for i in 0..FANOUT.min(peers.len()) {
+            let j = i + rand::random::<usize>() % (peers.len() - i);
+            peers.swap(i, j);
+
+            self.send(&line, &peers[i]);
+        }
+    }
+
+    fn send(&self, line: &str, peer: &SocketAddr) {
+        // Gossip is best effort, the next round makes up for a lost datagram
+        match self.gossip.send_to(&mut SliceBuf::wrap(line.as_bytes()), peer) {
+            Ok(Some(())) => {}
+            Ok(None) => println!("the socket wasn't actually ready, dropping gossip; peer={}", peer),
+            Err(e) => println!("failed to send gossip; peer={}; err={:?}", peer, e),
+        }
+    }
+
+    fn receive(&mut self) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event.
+        loop {
+            let mut buf = Vec::with_capacity(MAX_DATAGRAM);
+
+            let src = match self.gossip.recv_from(&mut buf) {
+                Ok(Some(src)) => src,
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            let line = String::from_utf8_lossy(&buf).into_owned();
+
+            let (sender, state) = match GCounter::decode(&line) {
+                Some(decoded) => decoded,
+                None => {
+                    println!("ignoring invalid gossip; src={}", src);
+                    continue;
+                }
+            };
+
+            if self.counter.merge(&state) {
+                println!("merged gossip; from={}; value={}; state={}", sender, self.counter.value(), self.counter.describe());
+            }
+
+            // The sender is behind, catch it up without waiting for it to
+            // be picked in a round
+            if self.counter.is_ahead_of(&state) {
+                let line = self.counter.encode(&self.id);
+                self.send(&line, &src);
+            }
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Node>) {
+        let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+        if let Err(e) = res {
+            println!("encountered error while accepting connection; err={:?}", e);
+            event_loop.shutdown();
+        }
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<Node>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            for line in self.connections[token].read() {
+                let reply = self.execute(&line);
+                self.connections[token].reply(&reply);
+            }
+        }
+
+        let conn = &mut self.connections[token];
+        conn.write();
+
+        if conn.closed {
+            self.connections.remove(token);
+            return;
+        }
+
+        event_loop.reregister(&conn.socket, token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    // Runs a command and returns the reply:
+    //
+    //     GET          -> the counter's value
+    //     INCR [<n>]   -> the value after adding n, 1 by default
+    //     STATE        -> the count of every node
+    fn execute(&mut self, line: &str) -> String {
+        let mut parts = line.split_whitespace();
+
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("GET"), None, None) => self.counter.value().to_string(),
+            (Some("INCR"), n, None) => {
+                match n.map(|n| n.parse::<u64>()).unwrap_or(Ok(1)) {
+                    Ok(n) => {
+                        self.counter.increment(&self.id, n);
+                        self.counter.value().to_string()
+                    }
+                    Err(_) => "ERR invalid number".to_string(),
+                }
+            }
+            (Some("STATE"), None, None) => self.counter.describe(),
+            _ => "ERR unknown command".to_string(),
+        }
+    }
+}
+
+impl mio::Handler for Node {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Node>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            GOSSIP => self.receive(),
+            SERVER => self.accept(event_loop),
+            _ => self.connection_ready(event_loop, token, events),
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Node>, _: ()) {
+        self.round();
+        event_loop.timeout_ms((), GOSSIP_MS).unwrap();
+    }
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Bytes of an incomplete line
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            buf: vec![],
+            out: vec![],
+            closed: false,
+        }
+    }
+
+    // Returns the complete lines read
+    fn read(&mut self) -> Vec<String> {
+        let mut chunk = [0; 1_024];
+        let mut lines = vec![];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(Some(n)) => self.buf.extend(&chunk[..n]),
+                Ok(None) => break,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..pos + 1).take(pos).collect();
+            lines.push(String::from_utf8_lossy(&line).into_owned());
+        }
+
+        if self.buf.len() > MAX_LINE {
+            println!("line too long, closing connection; token={:?}", self.token);
+            self.closed = true;
+        }
+
+        lines
+    }
+
+    fn reply(&mut self, line: &str) {
+        self.out.extend(line.as_bytes());
+        self.out.push(b'\n');
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.len() < 3 || !counter::is_valid_id(&args[0]) {
+        println!("usage: gossip_counter <node id> <gossip addr> <tcp addr> [<peer gossip addr> ...]");
+        process::exit(1);
+    }
+
+    let gossip_addr: SocketAddr = args[1].parse().unwrap();
+    let tcp_addr: SocketAddr = args[2].parse().unwrap();
+    let peers: Vec<SocketAddr> = args[3..].iter().map(|peer| peer.parse().unwrap()).collect();
+
+    let gossip = UdpSocket::bound(&gossip_addr).unwrap();
+    let acceptor = Acceptor::bind(&tcp_addr, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&gossip, GOSSIP, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+    event_loop.timeout_ms((), GOSSIP_MS).unwrap();
+
+    println!("running gossip node; id={}; gossip={}; tcp={}; peers={:?}", args[0], gossip_addr, tcp_addr, peers);
+
+    // Tokens `0` and `1` are reserved for the gossip and server sockets.
+    // Tokens 2+ are used for client connections.
+    let mut node = Node {
+        id: args[0].clone(),
+        counter: GCounter::new(),
+        gossip: gossip,
+        peers: peers,
+        acceptor: acceptor,
+        connections: Slab::new_starting_at(mio::Token(2), MAX_CONNECTIONS),
+    };
+
+    event_loop.run(&mut node).unwrap();
+}
@@ -0,0 +1,269 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+
+use acceptor::{Acceptor, Factory};
+use mio::TryRead;
+use mio::tcp::*;
+use mio::util::Slab;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_LINE: usize = 1_024;
+
+// How often buffered points are written to disk
+const FLUSH_MS: u64 = 1_000;
+
+// If this much is buffered before the timer fires, it is written right
+// away, so a burst of points can't use up an unbounded amount of memory.
+const MAX_PENDING: usize = 1_024 * 1_024;
+
+// Buffers points in memory and appends them to one file per metric.
+//
+// Writing to a file blocks the event loop, there is no readiness to wait
+// on. Writing every point as it arrives would mean a system call (and
+// possibly a disk write) per line, with every sender waiting on it.
+// Buffering turns that into one write per metric per flush interval.
+struct Writer {
+    dir: PathBuf,
+    // Metric name -> lines not yet written
+    pending: HashMap<String, Vec<u8>>,
+    pending_bytes: usize,
+}
+
+impl Writer {
+    fn new(dir: PathBuf) -> Writer {
+        Writer {
+            dir: dir,
+            pending: HashMap::new(),
+            pending_bytes: 0,
+        }
+    }
+
+    fn push(&mut self, name: &str, line: &str) {
+        let buf = self.pending.entry(name.to_string()).or_default();
+
+        buf.extend(line.as_bytes());
+        self.pending_bytes += line.len();
+
+        if self.pending_bytes >= MAX_PENDING {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let metrics = self.pending.len();
+
+        for (name, buf) in self.pending.drain() {
+            let path = self.dir.join(format!("{}.txt", name));
+
+            let res = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| file.write_all(&buf));
+
+            if let Err(e) = res {
+                println!("failed to write metric; path={:?}; err={:?}", path, e);
+            }
+        }
+
+        println!("flushed; metrics={}; bytes={}", metrics, self.pending_bytes);
+        self.pending_bytes = 0;
+    }
+}
+
+struct Carbon {
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+    writer: Writer,
+}
+
+impl Carbon {
+    fn new(acceptor: Acceptor<Factory<Connection>>, writer: Writer) -> Carbon {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Carbon {
+            acceptor: acceptor,
+            connections: slab,
+            writer: writer,
+        }
+    }
+}
+
+impl mio::Handler for Carbon {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Carbon>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                let (tokens, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+                for token in tokens {
+                    println!("accepted a new sender; token={:?}", token);
+                }
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
+                }
+            }
+            _ => {
+                assert!(events.is_readable(), "unexpected events; events={:?}", events);
+
+                let conn = &mut self.connections[token];
+                conn.read(&mut self.writer);
+
+                if conn.closed {
+                    let _ = self.connections.remove(token);
+                } else {
+                    event_loop.reregister(&conn.socket, token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                        .unwrap();
+                }
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Carbon>, _: ()) {
+        self.writer.flush();
+        event_loop.timeout_ms((), FLUSH_MS).unwrap();
+    }
+}
+
+// Senders only ever send, the connection is never written to
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Bytes of an incomplete line
+    buf: Vec<u8>,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            buf: Vec::with_capacity(MAX_LINE),
+            closed: false,
+        }
+    }
+
+    fn read(&mut self, writer: &mut Writer) {
+        let mut buf = [0; 8 * 1_024];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+                        let line: Vec<u8> = self.buf.drain(..pos + 1).collect();
+
+                        if let Err(e) = record(&String::from_utf8_lossy(&line), writer) {
+                            println!("ignoring invalid line; err={}; line={:?}", e, String::from_utf8_lossy(&line).trim());
+                        }
+                    }
+
+                    if self.buf.len() > MAX_LINE {
+                        println!("line too long, closing connection; token={:?}", self.token);
+                        self.closed = true;
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Parses a line of the plaintext protocol and buffers the point:
+//
+// <metric path> <value> <timestamp>
+//
+// The point is stored as `<timestamp> <value>` in the metric's file.
+fn record(line: &str, writer: &mut Writer) -> Result<(), &'static str> {
+    let mut fields = line.split_whitespace();
+
+    let (name, value, timestamp) = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+        (Some(name), Some(value), Some(timestamp), None) => (name, value, timestamp),
+        _ => return Err("expected 3 fields"),
+    };
+
+    // The name becomes a file name, so it must not be able to point
+    // anywhere else.
+    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-') &&
+        !name.starts_with('.') && !name.contains("..");
+
+    if !valid {
+        return Err("invalid metric name");
+    }
+
+    let value: f64 = match value.parse() {
+        Ok(value) => value,
+        Err(_) => return Err("invalid value"),
+    };
+
+    // A timestamp of -1 means "now"
+    let timestamp: i64 = match timestamp.parse() {
+        Ok(-1) => SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
+        Ok(timestamp) => timestamp,
+        Err(_) => return Err("invalid timestamp"),
+    };
+
+    writer.push(name, &format!("{} {}\n", timestamp, value));
+    Ok(())
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let dir = PathBuf::from(args.next().unwrap_or("metrics".to_string()));
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:2003".to_string()).parse().unwrap();
+
+    fs::create_dir_all(&dir).unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+    event_loop.timeout_ms((), FLUSH_MS).unwrap();
+
+    let mut carbon = Carbon::new(acceptor, Writer::new(dir));
+
+    println!("running graphite receiver; addr={:?}; dir={:?}", address, carbon.writer.dir);
+    event_loop.run(&mut carbon).unwrap();
+}
@@ -0,0 +1,296 @@
+extern crate mio;
+extern crate bytes;
+
+use mio::udp::*;
+use bytes::SliceBuf;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Instant;
+use std::{env, process};
+
+const SOCKET: mio::Token = mio::Token(0);
+
+const MAX_DATAGRAM: usize = 512;
+
+// How often to register with the rendezvous server while waiting for the
+// other peer
+const REGISTER_MS: u64 = 1_000;
+
+// How often to send a punch, and how many to send before giving up
+const PUNCH_MS: u64 = 500;
+const MAX_PUNCHES: usize = 20;
+
+// NATs forget idle UDP mappings, often after as little as 30 seconds. A
+// ping every few seconds keeps the hole open.
+const KEEPALIVE_MS: u64 = 5_000;
+
+// How many pings may go unanswered before the peer is considered gone
+const MAX_MISSED: usize = 3;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State {
+    // Waiting for the server to introduce the other peer
+    Registering,
+    // Sending to the other peer's public endpoint. The first datagrams are
+    // usually dropped by the other peer's NAT, until its own outgoing
+    // datagrams have opened a mapping that lets ours in.
+    Punching(SocketAddr),
+    // Datagrams have made it through in both directions
+    Connected(SocketAddr),
+}
+
+enum Timeout {
+    Register,
+    Punch,
+    Keepalive,
+}
+
+struct Peer {
+    // The same socket talks to the server and to the other peer. The NAT
+    // mapping the server observed belongs to this socket, using another
+    // one would get a different mapping.
+    socket: UdpSocket,
+    server: SocketAddr,
+    session: String,
+    state: State,
+    timeout: Option<mio::Timeout>,
+    punches: usize,
+    // The last ping sent, and when
+    seq: u64,
+    pinged: Option<Instant>,
+    missed: usize,
+}
+
+impl Peer {
+    fn new(socket: UdpSocket, server: SocketAddr, session: String) -> Peer {
+        Peer {
+            socket: socket,
+            server: server,
+            session: session,
+            state: State::Registering,
+            timeout: None,
+            punches: 0,
+            seq: 0,
+            pinged: None,
+            missed: 0,
+        }
+    }
+
+    fn register(&mut self, event_loop: &mut mio::EventLoop<Peer>) {
+        let line = format!("REGISTER {}", self.session);
+        let server = self.server;
+
+        self.send(&line, server);
+        self.schedule(event_loop, Timeout::Register, REGISTER_MS);
+    }
+
+    fn punch(&mut self, event_loop: &mut mio::EventLoop<Peer>, peer: SocketAddr) {
+        if self.punches == MAX_PUNCHES {
+            println!("no response from peer after {} punches, its NAT probably maps every destination to a different port",
+                     self.punches);
+            event_loop.shutdown();
+            return;
+        }
+
+        self.punches += 1;
+
+        let line = format!("PUNCH {}", self.session);
+        self.send(&line, peer);
+        self.schedule(event_loop, Timeout::Punch, PUNCH_MS);
+    }
+
+    fn keepalive(&mut self, event_loop: &mut mio::EventLoop<Peer>, peer: SocketAddr) {
+        if self.pinged.is_some() {
+            self.missed += 1;
+
+            if self.missed == MAX_MISSED {
+                println!("peer stopped responding; peer={:?}", peer);
+                event_loop.shutdown();
+                return;
+            }
+        }
+
+        self.seq += 1;
+        self.pinged = Some(Instant::now());
+
+        let line = format!("PING {}", self.seq);
+        self.send(&line, peer);
+        self.schedule(event_loop, Timeout::Keepalive, KEEPALIVE_MS);
+    }
+
+    fn connect(&mut self, event_loop: &mut mio::EventLoop<Peer>, peer: SocketAddr) {
+        println!("hole punched, connected to peer; peer={:?}; punches={}", peer, self.punches);
+
+        self.state = State::Connected(peer);
+        self.keepalive(event_loop, peer);
+    }
+
+    fn receive(&mut self, event_loop: &mut mio::EventLoop<Peer>) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event.
+        loop {
+            let mut buf = Vec::with_capacity(MAX_DATAGRAM);
+
+            let addr = match self.socket.recv_from(&mut buf) {
+                Ok(Some(addr)) => addr,
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            let line = String::from_utf8_lossy(&buf).into_owned();
+
+            if addr == self.server {
+                self.server_message(event_loop, &line);
+            } else {
+                self.peer_message(event_loop, &line, addr);
+            }
+        }
+    }
+
+    fn server_message(&mut self, event_loop: &mut mio::EventLoop<Peer>, line: &str) {
+        // The server keeps answering registrations sent before the
+        // introduction, only the first one matters.
+        if self.state != State::Registering {
+            return;
+        }
+
+        let mut parts = line.split_whitespace();
+
+        match parts.next() {
+            Some("WAITING") => println!("registered, waiting for the other peer"),
+            Some("PEER") => {
+                let peer: SocketAddr = match parts.next().and_then(|addr| addr.parse().ok()) {
+                    Some(peer) => peer,
+                    None => return println!("invalid response from server; line={:?}", line),
+                };
+
+                println!("got peer's endpoint, punching; peer={:?}", peer);
+
+                self.state = State::Punching(peer);
+                self.punch(event_loop, peer);
+            }
+            _ => {
+                println!("server refused registration; line={:?}", line);
+                event_loop.shutdown();
+            }
+        }
+    }
+
+    fn peer_message(&mut self, event_loop: &mut mio::EventLoop<Peer>, line: &str, addr: SocketAddr) {
+        let peer = match self.state {
+            State::Punching(peer) | State::Connected(peer) if peer == addr => peer,
+            _ => {
+                // Possibly the other peer's punch, arriving before the
+                // server's introduction did. It punches again later.
+                println!("ignoring datagram from unknown address; addr={:?}", addr);
+                return;
+            }
+        };
+
+        let mut parts = line.split_whitespace();
+
+        match (parts.next(), parts.next()) {
+            // The other peer is still punching: acknowledge it, so that it
+            // also knows the hole is open.
+            (Some("PUNCH"), Some(session)) if session == self.session => {
+                self.send("ACK", peer);
+
+                if self.state == State::Punching(peer) {
+                    self.connect(event_loop, peer);
+                }
+            }
+            (Some("ACK"), None) => {
+                if self.state == State::Punching(peer) {
+                    self.connect(event_loop, peer);
+                }
+            }
+            (Some("PING"), Some(seq)) => {
+                let line = format!("PONG {}", seq);
+                self.send(&line, peer);
+            }
+            (Some("PONG"), Some(seq)) => {
+                if seq.parse() == Ok(self.seq) {
+                    if let Some(pinged) = self.pinged.take() {
+                        let elapsed = pinged.elapsed();
+                        let ms = elapsed.as_secs() as f64 * 1_000.0 + elapsed.subsec_nanos() as f64 / 1e6;
+
+                        println!("pong from peer; seq={}; time={:.3} ms", seq, ms);
+                        self.missed = 0;
+                    }
+                }
+            }
+            _ => println!("ignoring unknown message from peer; line={:?}", line),
+        }
+    }
+
+    fn send(&mut self, line: &str, addr: SocketAddr) {
+        match self.socket.send_to(&mut SliceBuf::wrap(line.as_bytes()), &addr) {
+            Ok(Some(())) => {}
+            Ok(None) => println!("the socket wasn't actually ready, will retry"),
+            Err(e) => println!("failed to send datagram; addr={:?}; err={:?}", addr, e),
+        }
+    }
+
+    // Only one timer is ever pending, the one for the current state
+    fn schedule(&mut self, event_loop: &mut mio::EventLoop<Peer>, timeout: Timeout, ms: u64) {
+        if let Some(timeout) = self.timeout.take() {
+            event_loop.clear_timeout(timeout);
+        }
+
+        self.timeout = Some(event_loop.timeout_ms(timeout, ms).unwrap());
+    }
+}
+
+impl mio::Handler for Peer {
+    type Timeout = Timeout;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Peer>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, SOCKET);
+        assert!(events.is_readable(), "unexpected events; events={:?}", events);
+
+        self.receive(event_loop);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Peer>, timeout: Timeout) {
+        self.timeout = None;
+
+        match (timeout, self.state) {
+            (Timeout::Register, State::Registering) => self.register(event_loop),
+            (Timeout::Punch, State::Punching(peer)) => self.punch(event_loop, peer),
+            (Timeout::Keepalive, State::Connected(peer)) => self.keepalive(event_loop, peer),
+            _ => {}
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let session = match args.next() {
+        Some(session) => session,
+        None => {
+            println!("usage: peer <session> [server]");
+            process::exit(1);
+        }
+    };
+
+    let server = args.next().unwrap_or("127.0.0.1:9600".to_string());
+
+    // Resolving the hostname blocks, which is fine since the event loop
+    // isn't running yet.
+    let server = server.to_socket_addrs().unwrap()
+        .find(|addr| addr.is_ipv4())
+        .expect("no IPv4 address for server");
+
+    let socket = UdpSocket::bound(&"0.0.0.0:0".parse().unwrap()).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&socket, SOCKET, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+
+    let mut peer = Peer::new(socket, server, session);
+
+    println!("registering; server={:?}; session={:?}", server, peer.session);
+    peer.register(&mut event_loop);
+
+    event_loop.run(&mut peer).unwrap();
+}
@@ -0,0 +1,147 @@
+extern crate mio;
+extern crate bytes;
+
+use mio::udp::*;
+use bytes::SliceBuf;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_DATAGRAM: usize = 512;
+
+// Peers keep registering while they wait for each other. A session nobody
+// has registered with for this long is forgotten.
+const SESSION_TTL_SECS: u64 = 60;
+const SWEEP_MS: u64 = 10_000;
+
+// Two peers registering with the same session name are introduced to each
+// other.
+struct Session {
+    peers: Vec<SocketAddr>,
+    seen: Instant,
+}
+
+struct Rendezvous {
+    socket: UdpSocket,
+    sessions: HashMap<String, Session>,
+}
+
+impl Rendezvous {
+    fn receive(&mut self) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event.
+        loop {
+            let mut buf = Vec::with_capacity(MAX_DATAGRAM);
+
+            let addr = match self.socket.recv_from(&mut buf) {
+                Ok(Some(addr)) => addr,
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            let line = String::from_utf8_lossy(&buf).into_owned();
+            let mut parts = line.split_whitespace();
+
+            let reply = match (parts.next(), parts.next()) {
+                (Some("REGISTER"), Some(name)) => self.register(name, addr),
+                _ => {
+                    println!("ignoring unknown request; addr={:?}", addr);
+                    continue;
+                }
+            };
+
+            self.send(&reply, addr);
+        }
+    }
+
+    // The address the request came from is the peer's public endpoint: the
+    // one its NAT mapped the socket to. The other peer has to send to that
+    // address to reach it.
+    fn register(&mut self, name: &str, addr: SocketAddr) -> String {
+        let session = self.sessions.entry(name.to_string()).or_insert_with(|| {
+            Session {
+                peers: vec![],
+                seen: Instant::now(),
+            }
+        });
+
+        session.seen = Instant::now();
+
+        if !session.peers.contains(&addr) {
+            if session.peers.len() == 2 {
+                return "ERR session full".to_string();
+            }
+
+            println!("peer registered; session={:?}; addr={:?}", name, addr);
+            session.peers.push(addr);
+        }
+
+        // Either peer may have missed the introduction, it is repeated for
+        // as long as they keep registering.
+        match session.peers.iter().find(|peer| **peer != addr) {
+            Some(other) => {
+                println!("introducing peers; session={:?}; addr={:?}; peer={:?}", name, addr, other);
+                format!("PEER {}", other)
+            }
+            None => "WAITING".to_string(),
+        }
+    }
+
+    fn send(&mut self, line: &str, addr: SocketAddr) {
+        match self.socket.send_to(&mut SliceBuf::wrap(line.as_bytes()), &addr) {
+            Ok(Some(())) => {}
+            Ok(None) => println!("the socket wasn't actually ready, dropping reply"),
+            Err(e) => println!("failed to send reply; addr={:?}; err={:?}", addr, e),
+        }
+    }
+}
+
+impl mio::Handler for Rendezvous {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, _: &mut mio::EventLoop<Rendezvous>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, SERVER);
+        assert!(events.is_readable());
+
+        self.receive();
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Rendezvous>, _: ()) {
+        let ttl = Duration::from_secs(SESSION_TTL_SECS);
+
+        self.sessions.retain(|name, session| {
+            if session.seen.elapsed() < ttl {
+                return true;
+            }
+
+            println!("session expired; session={:?}", name);
+            false
+        });
+
+        event_loop.timeout_ms((), SWEEP_MS).unwrap();
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:9600".to_string())
+        .parse().unwrap();
+
+    let socket = UdpSocket::bound(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&socket, SERVER, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+    event_loop.timeout_ms((), SWEEP_MS).unwrap();
+
+    let mut rendezvous = Rendezvous {
+        socket: socket,
+        sessions: HashMap::new(),
+    };
+
+    println!("running rendezvous server; addr={:?}", address);
+    event_loop.run(&mut rendezvous).unwrap();
+}
@@ -0,0 +1,340 @@
+extern crate mio;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::{env, process};
+
+// Connections are closed this long after they were accepted, whatever
+// they are doing
+const TIMEOUT_MS: u64 = 10_000;
+
+// At most this many connections are accepted per event, see
+// `Honeypot::accept`
+const ACCEPT_BATCH: usize = 64;
+
+const MAX_CONNECTIONS: usize = 4_096;
+
+// What a listener pretends to be, by the banner it sends when a client
+// connects. Clients of protocols where the server speaks first, which is
+// most of what scanners look for, wait for it before they say anything.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Banner {
+    Ssh,
+    Ftp,
+    Smtp,
+    Telnet,
+}
+
+impl Banner {
+    fn parse(s: &str) -> Option<Banner> {
+        match s {
+            "ssh" => Some(Banner::Ssh),
+            "ftp" => Some(Banner::Ftp),
+            "smtp" => Some(Banner::Smtp),
+            "telnet" => Some(Banner::Telnet),
+            _ => None,
+        }
+    }
+
+    fn bytes(&self) -> &'static [u8] {
+        match *self {
+            Banner::Ssh => b"SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.6\r\n",
+            Banner::Ftp => b"220 (vsFTPd 3.0.5)\r\n",
+            Banner::Smtp => b"220 mail.example.com ESMTP Postfix (Ubuntu)\r\n",
+            Banner::Telnet => b"\r\nUbuntu 22.04.4 LTS\r\nlogin: ",
+        }
+    }
+}
+
+struct Listener {
+    socket: TcpListener,
+    port: u16,
+    banner: Option<Banner>,
+}
+
+// Listens on many ports, and logs whoever connects: when, from where, and
+// the first bytes they send. Nothing is ever answered beyond the banner.
+// A connection is closed once it sent enough to be recorded, when the
+// client hangs up, or after `TIMEOUT_MS`, so connections are short lived,
+// and a busy honeypot spends most of its time accepting.
+struct Honeypot {
+    // A listener's token is its index in this Vec, like in inetd
+    listeners: Vec<Listener>,
+    connections: Slab<Connection>,
+    // Bytes recorded per connection
+    max_bytes: usize,
+    accepted: u64,
+}
+
+impl Honeypot {
+    fn new(listeners: Vec<Listener>, max_bytes: usize) -> Honeypot {
+        // Tokens `0..listeners.len()` are reserved for the listeners.
+        // Connections use the tokens after that.
+        let slab = Slab::new_starting_at(mio::Token(listeners.len()), MAX_CONNECTIONS);
+
+        Honeypot {
+            listeners: listeners,
+            connections: slab,
+            max_bytes: max_bytes,
+            accepted: 0,
+        }
+    }
+
+    // The listeners are registered as level triggered, so one accept per
+    // event would do. But with connections coming in faster than the time
+    // between two polls, that is a full trip through the event loop per
+    // connection, and the backlog fills up. The listener is drained
+    // instead, a batch at a time: the next batch comes with the next poll,
+    // so that a flood on one port doesn't hold up the others.
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Honeypot>, token: mio::Token) {
+        let (port, banner) = {
+            let listener = &self.listeners[token.as_usize()];
+            (listener.port, listener.banner)
+        };
+
+        for _ in 0..ACCEPT_BATCH {
+            let socket = match self.listeners[token.as_usize()].socket.accept() {
+                Ok(Some(socket)) => socket,
+                Ok(None) => return,
+                Err(e) => {
+                    println!("encountered error while accepting connection; port={}; err={:?}", port, e);
+                    return;
+                }
+            };
+
+            let peer = match socket.peer_addr() {
+                Ok(peer) => peer,
+                // Gone already
+                Err(_) => continue,
+            };
+
+            self.accepted += 1;
+            let id = self.accepted;
+
+            println!("{} connect; id={}; port={}; peer={}", timestamp(), id, port, peer);
+
+            let conn = Connection::new(socket, id, port, peer, banner);
+
+            // The client is dropped without a word, but it was logged
+            let token = match self.connections.insert(conn) {
+                Ok(token) => token,
+                Err(_) => {
+                    println!("connection limit reached, dropping client; id={}", id);
+                    continue;
+                }
+            };
+
+            let conn = &mut self.connections[token];
+            conn.timeout = Some(event_loop.timeout_ms(token, TIMEOUT_MS).unwrap());
+
+            event_loop.register_opt(&conn.socket, token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn close(&mut self, event_loop: &mut mio::EventLoop<Honeypot>, token: mio::Token, reason: &str) {
+        let conn = self.connections.remove(token).unwrap();
+
+        if let Some(timeout) = conn.timeout {
+            event_loop.clear_timeout(timeout);
+        }
+
+        println!("{} close; id={}; port={}; peer={}; reason={}; duration_ms={}; bytes={}; data=\"{}\"",
+                 timestamp(), conn.id, conn.port, conn.peer, reason, millis(conn.accepted), conn.data.len(), conn.data.escape_ascii());
+    }
+}
+
+impl mio::Handler for Honeypot {
+    type Timeout = mio::Token;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Honeypot>, token: mio::Token, events: mio::EventSet) {
+        if token.as_usize() < self.listeners.len() {
+            self.accept(event_loop, token);
+            return;
+        }
+
+        let max_bytes = self.max_bytes;
+
+        let closed = {
+            let conn = &mut self.connections[token];
+
+            let mut closed = None;
+
+            if events.is_readable() {
+                closed = conn.read(max_bytes);
+            }
+
+            if closed.is_none() && events.is_writable() {
+                closed = conn.write();
+            }
+
+            closed
+        };
+
+        match closed {
+            Some(reason) => self.close(event_loop, token, reason),
+            None => {
+                let conn = &self.connections[token];
+
+                event_loop.reregister(&conn.socket, token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                    .unwrap();
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Honeypot>, token: mio::Token) {
+        self.connections[token].timeout = None;
+        self.close(event_loop, token, "timeout");
+    }
+}
+
+struct Connection {
+    socket: TcpStream,
+    id: u64,
+    port: u16,
+    peer: SocketAddr,
+    accepted: Instant,
+    // What is left of the banner to write
+    banner: &'static [u8],
+    // What the client sent, up to the maximum
+    data: Vec<u8>,
+    timeout: Option<mio::Timeout>,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, id: u64, port: u16, peer: SocketAddr, banner: Option<Banner>) -> Connection {
+        Connection {
+            socket: socket,
+            id: id,
+            port: port,
+            peer: peer,
+            accepted: Instant::now(),
+            banner: banner.map(|banner| banner.bytes()).unwrap_or(b""),
+            data: vec![],
+            timeout: None,
+        }
+    }
+
+    // Records what the client sent. Returns why the connection is to be
+    // closed, if it is.
+    fn read(&mut self, max_bytes: usize) -> Option<&'static str> {
+        let mut buf = [0; 1_024];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => return Some("client closed"),
+                Ok(Some(n)) => {
+                    let len = n.min(max_bytes - self.data.len());
+                    self.data.extend_from_slice(&buf[..len]);
+
+                    if self.data.len() == max_bytes {
+                        return Some("recorded enough");
+                    }
+                }
+                Ok(None) => return None,
+                // Scanners often reset the connection as soon as they know
+                // the port is open
+                Err(_) => return Some("reset"),
+            }
+        }
+    }
+
+    fn write(&mut self) -> Option<&'static str> {
+        while !self.banner.is_empty() {
+            match self.socket.try_write(self.banner) {
+                Ok(Some(n)) => self.banner = &self.banner[n..],
+                Ok(None) => return None,
+                Err(_) => return Some("reset"),
+            }
+        }
+
+        None
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.banner.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+fn millis(since: Instant) -> u64 {
+    let elapsed = since.elapsed();
+    elapsed.as_secs() * 1_000 + elapsed.subsec_millis() as u64
+}
+
+// The current UTC time, in RFC 3339 format with milliseconds, e.g.
+// "2016-02-22T17:37:43.123Z"
+fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let secs = now.as_secs() as i64;
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+
+    // Converts days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            year, month, day, rem / 3_600, rem % 3_600 / 60, rem % 60, now.subsec_millis())
+}
+
+fn usage() -> ! {
+    println!("usage: honeypot <ip> <bytes> <port[:banner]>...; banners are ssh, ftp, smtp and telnet");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let ip: IpAddr = args.next().map(|ip| ip.parse().unwrap_or_else(|_| usage())).unwrap_or_else(|| "0.0.0.0".parse().unwrap());
+    let max_bytes: usize = args.next().map(|n| n.parse().unwrap_or_else(|_| usage())).unwrap_or(512);
+
+    let mut ports: Vec<String> = args.collect();
+
+    // The usual suspects, on unprivileged ports
+    if ports.is_empty() {
+        ports = ["2121:ftp", "2222:ssh", "2323:telnet", "2525:smtp", "3306", "8888"].iter().map(|s| s.to_string()).collect();
+    }
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    let mut listeners = vec![];
+
+    for (i, spec) in ports.iter().enumerate() {
+        let mut parts = spec.splitn(2, ':');
+
+        let port: u16 = parts.next().unwrap().parse().unwrap_or_else(|_| usage());
+        let banner = parts.next().map(|name| Banner::parse(name).unwrap_or_else(|| usage()));
+
+        let socket = TcpListener::bind(&SocketAddr::new(ip, port)).unwrap();
+        event_loop.register(&socket, mio::Token(i)).unwrap();
+
+        println!("listening; port={}; banner={:?}", port, banner);
+
+        listeners.push(Listener {
+            socket: socket,
+            port: port,
+            banner: banner,
+        });
+    }
+
+    let mut honeypot = Honeypot::new(listeners, max_bytes);
+
+    println!("running honeypot; ip={}; bytes={}", ip, max_bytes);
+    event_loop.run(&mut honeypot).unwrap();
+}
@@ -0,0 +1,566 @@
+extern crate http_client;
+extern crate mio;
+extern crate resolver;
+
+use http_client::links;
+use http_client::pool::{Checkout, Pool};
+use http_client::response::{Parser, Response};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use resolver::{Answer, Deadline, Resolve, Resolver};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use std::{env, process};
+
+const MAX_CONNECTIONS: usize = 1_024;
+
+// How long a connection is kept idle before it is closed, and how often
+// the pool is checked for those
+const IDLE_SECS: u64 = 5;
+const SWEEP_MS: u64 = 1_000;
+
+// Hostnames are resolved on threads of their own, and cached: most links
+// are to the hosts crawled already
+const RESOLVER_THREADS: usize = 4;
+const RESOLVER_CACHE: usize = 1_024;
+const RESOLVE_TIMEOUT_MS: u64 = 5_000;
+
+enum Timer {
+    // The host's politeness delay is over
+    Host(String),
+    Sweep,
+    // A hostname is taking too long to resolve
+    Resolve(Deadline),
+}
+
+impl From<Deadline> for Timer {
+    fn from(deadline: Deadline) -> Timer {
+        Timer::Resolve(deadline)
+    }
+}
+
+// A page to fetch, and how many links away from the seed it was found
+struct Page {
+    url: String,
+    // `host:port`, and the path with the query
+    host: String,
+    path: String,
+    // The host to resolve, and the port to connect to
+    name: String,
+    port: u16,
+    depth: usize,
+    // A request sent on a connection the server had closed in the
+    // meantime is sent again, once
+    retried: bool,
+}
+
+impl Page {
+    // Returns `None` if the URL's host or port is malformed
+    fn new(url: String, depth: usize) -> Option<Page> {
+        let (host, name, port, path) = {
+            let rest = &url["http://".len()..];
+            let slash = rest.find('/').unwrap_or(rest.len());
+            let (name, port) = links::host_port(&rest[..slash])?;
+
+            (format!("{}:{}", name, port), name.to_string(), port, rest[slash..].to_string())
+        };
+
+        Some(Page {
+            host: host,
+            path: path,
+            name: name,
+            port: port,
+            url: url,
+            depth: depth,
+            retried: false,
+        })
+    }
+}
+
+// What is known of a host being crawled
+struct Host {
+    // Whether a request to it is in flight
+    busy: bool,
+    // When the next request may start, the politeness delay after the end
+    // of the last one
+    ready_at: Instant,
+    // Whether a timer is set for `ready_at`
+    timer: bool,
+}
+
+// Crawls the web breadth first from a seed URL, fetching every page it
+// finds a link to, once, up to a number of pages. The pages found, the
+// frontier, are fetched in the order they were found, with a few rules:
+//
+// * at most `concurrency` requests are in flight at once;
+// * a host gets one request at a time, then isn't asked again before the
+//   politeness delay is over, which a timer per host waits for. A page on
+//   a host that isn't ready is skipped over for the next one;
+// * connections are kept alive, in a pool, for the next page on the same
+//   host.
+struct Crawler {
+    pool: Pool,
+    // The pages waiting for their host's address
+    resolver: Resolver<Page>,
+    connections: Slab<Connection>,
+    frontier: VecDeque<Page>,
+    hosts: HashMap<String, Host>,
+    // Every URL ever queued, fetched or not
+    seen: HashSet<String>,
+    concurrency: usize,
+    delay: Duration,
+    max_pages: usize,
+    in_flight: usize,
+    fetched: u64,
+    failed: u64,
+    started: Instant,
+}
+
+impl Crawler {
+    // Queues a URL, unless it was seen already or the crawl is big enough
+    fn queue(&mut self, url: String, depth: usize) {
+        if self.seen.len() >= self.max_pages || self.seen.contains(&url) {
+            return;
+        }
+
+        self.seen.insert(url.clone());
+
+        match Page::new(url.clone(), depth) {
+            Some(page) => self.frontier.push_back(page),
+            None => {
+                self.failed += 1;
+                println!("{} failed; depth={}; err=invalid host or port", url, depth);
+            }
+        }
+    }
+
+    // Starts requests for as many pages as the limits allow, first in the
+    // frontier first
+    fn schedule(&mut self, event_loop: &mut mio::EventLoop<Crawler>) {
+        let now = Instant::now();
+        let mut i = 0;
+
+        while self.in_flight < self.concurrency && i < self.frontier.len() {
+            let ready = {
+                let host = self.hosts.entry(self.frontier[i].host.clone()).or_insert_with(|| {
+                    Host {
+                        busy: false,
+                        ready_at: now,
+                        timer: false,
+                    }
+                });
+
+                if !host.busy && host.ready_at > now && !host.timer {
+                    let ms = millis(host.ready_at - now);
+                    event_loop.timeout_ms(Timer::Host(self.frontier[i].host.clone()), ms).unwrap();
+                    host.timer = true;
+                }
+
+                !host.busy && host.ready_at <= now
+            };
+
+            if !ready {
+                i += 1;
+                continue;
+            }
+
+            let page = self.frontier.remove(i).unwrap();
+            self.fetch(event_loop, page);
+        }
+
+        // Done once there is nothing left to fetch, or waiting for a host
+        if self.frontier.is_empty() && self.in_flight == 0 {
+            println!("done; fetched={}; failed={}; seen={}; elapsed={}ms; cache hits={}; cache misses={}",
+                     self.fetched, self.failed, self.seen.len(), millis(self.started.elapsed()), self.resolver.hits(), self.resolver.misses());
+            event_loop.shutdown();
+        }
+    }
+
+    fn fetch(&mut self, event_loop: &mut mio::EventLoop<Crawler>, page: Page) {
+        self.in_flight += 1;
+        self.hosts.get_mut(&page.host).unwrap().busy = true;
+
+        match self.pool.checkout(&page.host) {
+            Checkout::Idle(token) => {
+                let conn = &mut self.connections[token];
+                conn.start(page, true);
+
+                if conn.write() {
+                    conn.reregister(event_loop);
+                } else {
+                    self.closed(event_loop, token, "failed to write");
+                }
+            }
+            Checkout::Connect => self.connect(event_loop, page),
+            // A host has only one request in flight, and so at most one
+            // connection in use
+            Checkout::Full => unreachable!(),
+        }
+    }
+
+    // Resolves the page's host, then connects to it. The request counts as
+    // in flight meanwhile.
+    fn connect(&mut self, event_loop: &mut mio::EventLoop<Crawler>, page: Page) {
+        let (host, port) = (page.name.clone(), page.port);
+
+        // A pending page comes back in `notify`, or `timeout`
+        if let Resolve::Done(page, result) = self.resolver.resolve(event_loop, &host, port, RESOLVE_TIMEOUT_MS, page) {
+            self.resolved(event_loop, page, result);
+        }
+    }
+
+    fn resolved(&mut self, event_loop: &mut mio::EventLoop<Crawler>, page: Page, result: Result<SocketAddr, resolver::Error>) {
+        let socket = match result.map(|addr| TcpStream::connect(&addr)) {
+            Ok(Ok(socket)) => socket,
+            Ok(Err(e)) => {
+                self.pool.release(&page.host);
+                return self.done(page, Err(&format!("failed to connect; err={}", e)));
+            }
+            Err(e) => {
+                self.pool.release(&page.host);
+                return self.done(page, Err(&format!("failed to resolve host; err={}", e)));
+            }
+        };
+
+        let token = match self.connections.insert_with(|token| Connection::new(socket, token, page.host.clone())) {
+            Some(token) => token,
+            None => {
+                self.pool.release(&page.host);
+                return self.done(page, Err("connection limit reached"));
+            }
+        };
+
+        // The connect is non-blocking. The socket becomes writable once the
+        // connection is established, or has failed, and the request is
+        // written then.
+        self.connections[token].start(page, false);
+        self.connections[token].register(event_loop);
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<Crawler>, token: mio::Token, events: mio::EventSet) {
+        // The connection may have been closed earlier in the same turn of
+        // the event loop
+        if !self.connections.contains(token) {
+            return;
+        }
+
+        match self.connections[token].ready(events) {
+            Ok(Some(response)) => {
+                let page = self.connections[token].page.take().unwrap();
+                let keep_alive = response.keep_alive();
+
+                if keep_alive {
+                    let host = self.connections[token].host.clone();
+                    self.pool.checkin(&host, token, Instant::now());
+                    self.connections[token].reregister(event_loop);
+                } else {
+                    self.closed(event_loop, token, "closed after the response");
+                }
+
+                self.done(page, Ok(&response));
+            }
+            Ok(None) => self.connections[token].reregister(event_loop),
+            Err(e) => self.closed(event_loop, token, e),
+        }
+
+        self.schedule(event_loop);
+    }
+
+    // Drops a connection that is gone, failing its request if it had one
+    fn closed(&mut self, _: &mut mio::EventLoop<Crawler>, token: mio::Token, err: &str) {
+        // Dropping the socket closes it, which also removes it from the
+        // event loop
+        let conn = self.connections.remove(token).unwrap();
+        self.pool.closed(&conn.host, token);
+
+        let mut page = match conn.page {
+            Some(page) => page,
+            None => return,
+        };
+
+        // A connection sitting in the pool can be closed by the server at
+        // any time, even right as a request is sent on it. If nothing came
+        // back, the page goes back to the front of the frontier.
+        if conn.reused && !conn.parser.is_started() && !page.retried {
+            page.retried = true;
+            self.in_flight -= 1;
+            self.hosts.get_mut(&page.host).unwrap().busy = false;
+            self.frontier.push_front(page);
+            return;
+        }
+
+        self.done(page, Err(err));
+    }
+
+    // The request for the page is over. The host's politeness delay starts
+    // now, and whatever the page links to is queued.
+    fn done(&mut self, page: Page, result: Result<&Response, &str>) {
+        self.in_flight -= 1;
+
+        {
+            let host = self.hosts.get_mut(&page.host).unwrap();
+            host.busy = false;
+            host.ready_at = Instant::now() + self.delay;
+        }
+
+        let response = match result {
+            Ok(response) => response,
+            Err(err) => {
+                self.failed += 1;
+                println!("{} failed; depth={}; err={}", page.url, page.depth, err);
+                return;
+            }
+        };
+
+        self.fetched += 1;
+
+        let mut found = vec![];
+
+        // A redirect is a link like any other, at the same depth
+        if response.status / 100 == 3 {
+            if let Some(url) = response.header("Location").and_then(|location| links::resolve(&page.url, location)) {
+                found.push((url, page.depth));
+            }
+        }
+
+        let html = response.header("Content-Type").map(|value| value.contains("text/html")).unwrap_or(false);
+
+        if response.status == 200 && html {
+            for href in links::extract(&response.body) {
+                if let Some(url) = links::resolve(&page.url, &href) {
+                    found.push((url, page.depth + 1));
+                }
+            }
+        }
+
+        println!("{} {}; depth={}; bytes={}; links={}", page.url, response.status, page.depth, response.body.len(), found.len());
+
+        for (url, depth) in found {
+            self.queue(url, depth);
+        }
+    }
+
+    fn sweep(&mut self, event_loop: &mut mio::EventLoop<Crawler>) {
+        for token in self.pool.expire(Instant::now(), Duration::from_secs(IDLE_SECS)) {
+            self.connections.remove(token);
+        }
+
+        event_loop.timeout_ms(Timer::Sweep, SWEEP_MS).unwrap();
+    }
+}
+
+impl mio::Handler for Crawler {
+    type Timeout = Timer;
+    type Message = Answer;
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Crawler>, token: mio::Token, events: mio::EventSet) {
+        self.connection_ready(event_loop, token, events);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Crawler>, timer: Timer) {
+        match timer {
+            Timer::Host(host) => {
+                if let Some(host) = self.hosts.get_mut(&host) {
+                    host.timer = false;
+                }
+
+                self.schedule(event_loop);
+            }
+            Timer::Sweep => self.sweep(event_loop),
+            Timer::Resolve(deadline) => {
+                if let Some((page, err)) = self.resolver.expire(deadline) {
+                    self.resolved(event_loop, page, Err(err));
+                    self.schedule(event_loop);
+                }
+            }
+        }
+    }
+
+    // A hostname is resolved, for all the pages on the host waiting for it
+    fn notify(&mut self, event_loop: &mut mio::EventLoop<Crawler>, answer: Answer) {
+        for (page, result) in self.resolver.answer(event_loop, answer) {
+            self.resolved(event_loop, page, result);
+        }
+
+        self.schedule(event_loop);
+    }
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Where it is connected to, as `host:port`
+    host: String,
+    connected: bool,
+    // The page being fetched, `None` while in the pool
+    page: Option<Page>,
+    // Whether the request in flight isn't the first one on the connection
+    reused: bool,
+    parser: Parser,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token, host: String) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            host: host,
+            connected: false,
+            page: None,
+            reused: false,
+            parser: Parser::new(),
+            buf: vec![],
+            out: vec![],
+        }
+    }
+
+    fn start(&mut self, page: Page, reused: bool) {
+        let head = format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: mio-examples\r\n\r\n", page.path, self.host);
+
+        self.out.extend(head.as_bytes());
+        self.page = Some(page);
+        self.reused = reused;
+        self.parser = Parser::new();
+    }
+
+    // Returns the response once it is complete. An idle connection should
+    // hear nothing from the server but it closing the connection, which is
+    // reported as an error.
+    fn ready(&mut self, events: mio::EventSet) -> Result<Option<Response>, &'static str> {
+        if !self.connected {
+            if events.is_error() || events.is_hup() {
+                return Err("failed to connect");
+            }
+
+            if events.is_writable() {
+                self.connected = true;
+            }
+        }
+
+        if events.is_writable() && !self.write() {
+            return Err("failed to write");
+        }
+
+        if !events.is_readable() {
+            return Ok(None);
+        }
+
+        let mut chunk = [0; 4_096];
+        let mut eof = false;
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    eof = true;
+                    break;
+                }
+                Ok(Some(n)) => self.buf.extend(&chunk[..n]),
+                Ok(None) => break,
+                Err(_) => return Err("failed to read"),
+            }
+        }
+
+        if self.page.is_none() {
+            return if eof || !self.buf.is_empty() { Err("unexpected data") } else { Ok(None) };
+        }
+
+        if let Some(response) = self.parser.parse(&mut self.buf)? {
+            return Ok(Some(response));
+        }
+
+        if eof {
+            return match self.parser.eof()? {
+                Some(response) => Ok(Some(response)),
+                None => Err("connection closed"),
+            };
+        }
+
+        Ok(None)
+    }
+
+    fn write(&mut self) -> bool {
+        if !self.connected {
+            return true;
+        }
+
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return true,
+                Err(_) => return false,
+            }
+        }
+
+        true
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if !self.connected || !self.out.is_empty() {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        } else {
+            mio::EventSet::readable()
+        }
+    }
+
+    fn register(&self, event_loop: &mut mio::EventLoop<Crawler>) {
+        event_loop.register_opt(&self.socket, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn reregister(&self, event_loop: &mut mio::EventLoop<Crawler>) {
+        event_loop.reregister(&self.socket, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000 + duration.subsec_millis() as u64
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let seed = args.next().and_then(|url| links::normalize(&url)).unwrap_or_else(|| {
+        println!("usage: crawl http://host[:port]/path [concurrency] [delay ms] [max pages]");
+        process::exit(1);
+    });
+
+    let concurrency = args.next().map(|s| s.parse().unwrap()).unwrap_or(8);
+    let delay_ms = args.next().map(|s| s.parse().unwrap()).unwrap_or(500);
+    let max_pages = args.next().map(|s| s.parse().unwrap()).unwrap_or(100);
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+
+    let mut crawler = Crawler {
+        // One connection per host is all it ever gets, it gets one request
+        // at a time
+        pool: Pool::new(1),
+        resolver: Resolver::new(RESOLVER_THREADS, event_loop.channel(), RESOLVER_CACHE),
+        connections: Slab::new(MAX_CONNECTIONS),
+        frontier: VecDeque::new(),
+        hosts: HashMap::new(),
+        seen: HashSet::new(),
+        concurrency: concurrency,
+        delay: Duration::from_millis(delay_ms),
+        max_pages: max_pages,
+        in_flight: 0,
+        fetched: 0,
+        failed: 0,
+        started: Instant::now(),
+    };
+
+    println!("crawling; seed={}; concurrency={}; delay={}ms; max pages={}", seed, concurrency, delay_ms, max_pages);
+
+    crawler.queue(seed, 0);
+    crawler.schedule(&mut event_loop);
+
+    event_loop.timeout_ms(Timer::Sweep, SWEEP_MS).unwrap();
+    event_loop.run(&mut crawler).unwrap();
+}
@@ -0,0 +1,331 @@
+extern crate http_client;
+extern crate mio;
+extern crate openssl;
+
+use http_client::response::{Parser, Response};
+use mio::tcp::*;
+use openssl::ssl::{self, ErrorCode, HandshakeError, MidHandshakeSslStream, SslConnector, SslMethod, SslStream};
+use openssl::x509::X509VerifyResult;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::{env, mem, process};
+
+const CLIENT: mio::Token = mio::Token(0);
+
+// The fetch is given up on if it takes longer than this, connecting and
+// the handshake included
+const TIMEOUT_MS: u64 = 30_000;
+
+// Fetches an HTTPS URL and prints the response. The connection goes
+// through three stages, each waiting on the socket without blocking:
+// connecting, the TLS handshake, then writing the request and reading the
+// response through OpenSSL.
+//
+// OpenSSL reads and writes the socket itself, and says what it would
+// need to go on when it can't: the socket to be readable, or writable.
+// That isn't always the obvious one, a handshake step writes as well as
+// reads, and a read may need to write. Whatever it asks for is what the
+// socket is registered for.
+struct Fetch {
+    url: Url,
+    connector: SslConnector,
+    tls: Tls,
+    // What OpenSSL waits for on the socket
+    wants: mio::EventSet,
+    // The request, until it is written
+    out: Vec<u8>,
+    // Decrypted, and not yet parsed
+    buf: Vec<u8>,
+    parser: Parser,
+    failed: bool,
+}
+
+impl Fetch {
+    // Returns the response once it is complete
+    fn ready(&mut self, events: mio::EventSet) -> Result<Option<Response>, String> {
+        match mem::replace(&mut self.tls, Tls::Closed) {
+            Tls::Connecting(socket) => {
+                if events.is_error() || events.is_hup() {
+                    return Err(format!("failed to connect; err={:?}", socket.take_socket_error()));
+                }
+
+                if !events.is_writable() {
+                    self.tls = Tls::Connecting(socket);
+                    return Ok(None);
+                }
+
+                // Sends the host name for the server to pick its
+                // certificate, and checks the one it gets against it
+                let result = self.connector.connect(&self.url.hostname, socket);
+                self.handshake(result)?;
+            }
+            Tls::Handshaking(stream) => {
+                let result = stream.handshake();
+                self.handshake(result)?;
+            }
+            tls => self.tls = tls,
+        }
+
+        // Both are tried whatever the socket is ready for, either may have
+        // been waiting for the other readiness. Right after the handshake,
+        // the request goes out.
+        self.write()?;
+        self.read()
+    }
+
+    fn handshake(&mut self, result: Result<SslStream<TcpStream>, HandshakeError<TcpStream>>) -> Result<(), String> {
+        match result {
+            Ok(stream) => {
+                let cipher = stream.ssl().current_cipher().map(|cipher| cipher.name()).unwrap_or("none");
+                println!("handshake done; version={}; cipher={}", stream.ssl().version_str(), cipher);
+
+                self.tls = Tls::Established(stream);
+                self.wants = mio::EventSet::writable();
+                Ok(())
+            }
+            Err(HandshakeError::WouldBlock(stream)) => {
+                self.wants = wants(stream.error());
+                self.tls = Tls::Handshaking(stream);
+                Ok(())
+            }
+            Err(HandshakeError::Failure(stream)) => {
+                // A certificate that doesn't check out fails the handshake,
+                // OpenSSL keeps why
+                let verify = stream.ssl().verify_result();
+
+                if verify != X509VerifyResult::OK {
+                    return Err(format!("certificate verification failed; err={}", verify.error_string()));
+                }
+
+                Err(format!("handshake failed; err={}", stream.error()))
+            }
+            Err(HandshakeError::SetupFailure(e)) => Err(format!("failed to set up the handshake; err={}", e)),
+        }
+    }
+
+    fn write(&mut self) -> Result<(), String> {
+        let stream = match self.tls {
+            Tls::Established(ref mut stream) => stream,
+            _ => return Ok(()),
+        };
+
+        // A write that can't go on has to be retried with the same data,
+        // which it is, as nothing is taken off `out` until written
+        while !self.out.is_empty() {
+            match stream.ssl_write(&self.out) {
+                Ok(n) => {
+                    self.out.drain(..n);
+                }
+                Err(ref e) if e.code() == ErrorCode::WANT_READ || e.code() == ErrorCode::WANT_WRITE => {
+                    self.wants = wants(e);
+                    return Ok(());
+                }
+                Err(e) => return Err(format!("failed to write; err={}", e)),
+            }
+        }
+
+        self.wants = mio::EventSet::readable();
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<Option<Response>, String> {
+        let stream = match self.tls {
+            Tls::Established(ref mut stream) => stream,
+            _ => return Ok(None),
+        };
+
+        if !self.out.is_empty() {
+            return Ok(None);
+        }
+
+        // A TLS record holds up to 16KB
+        let mut chunk = [0; 16 * 1_024];
+
+        // The socket is registered as edge triggered, drain it, until
+        // OpenSSL needs more from the socket
+        loop {
+            match stream.ssl_read(&mut chunk) {
+                Ok(n) => self.buf.extend(&chunk[..n]),
+                // The server sent its close_notify
+                Err(ref e) if e.code() == ErrorCode::ZERO_RETURN => {
+                    if let Some(response) = self.parser.parse(&mut self.buf)? {
+                        return Ok(Some(response));
+                    }
+
+                    return match self.parser.eof()? {
+                        Some(response) => Ok(Some(response)),
+                        None => Err("connection closed".to_string()),
+                    };
+                }
+                Err(ref e) if e.code() == ErrorCode::WANT_READ || e.code() == ErrorCode::WANT_WRITE => {
+                    self.wants = wants(e);
+                    break;
+                }
+                // Closing the connection without a close_notify is an error
+                // too: the response may have been cut short by an attacker
+                Err(e) => return Err(format!("failed to read; err={}", e)),
+            }
+        }
+
+        Ok(self.parser.parse(&mut self.buf)?)
+    }
+}
+
+impl mio::Handler for Fetch {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Fetch>, _: mio::Token, events: mio::EventSet) {
+        match Fetch::ready(self, events) {
+            Ok(None) => {
+                event_loop.reregister(self.tls.socket(), CLIENT, self.wants, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                    .unwrap();
+            }
+            Ok(Some(response)) => {
+                println!("{} {} {}; bytes={}", self.url, response.status, response.reason, response.body.len());
+                println!();
+                println!("{}", String::from_utf8_lossy(&response.body));
+
+                event_loop.shutdown();
+            }
+            Err(err) => {
+                println!("{} failed; {}", self.url, err);
+
+                self.failed = true;
+                event_loop.shutdown();
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Fetch>, _: ()) {
+        println!("{} failed; timed out", self.url);
+
+        self.failed = true;
+        event_loop.shutdown();
+    }
+}
+
+// The connection, as far as it went
+enum Tls {
+    Connecting(TcpStream),
+    Handshaking(MidHandshakeSslStream<TcpStream>),
+    Established(SslStream<TcpStream>),
+    // Only while a stage hands over to the next, or once it failed
+    Closed,
+}
+
+impl Tls {
+    fn socket(&self) -> &TcpStream {
+        match *self {
+            Tls::Connecting(ref socket) => socket,
+            Tls::Handshaking(ref stream) => stream.get_ref(),
+            Tls::Established(ref stream) => stream.get_ref(),
+            Tls::Closed => panic!("the connection is closed"),
+        }
+    }
+}
+
+// The readiness OpenSSL is waiting for, when it can't go on
+fn wants(e: &ssl::Error) -> mio::EventSet {
+    if e.code() == ErrorCode::WANT_WRITE {
+        mio::EventSet::writable()
+    } else {
+        mio::EventSet::readable()
+    }
+}
+
+struct Url {
+    hostname: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    fn parse(s: &str) -> Option<Url> {
+        let rest = s.strip_prefix("https://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+
+        let (hostname, port) = match authority.rfind(':') {
+            Some(pos) => (&authority[..pos], authority[pos + 1..].parse().ok()?),
+            None => (authority, 443),
+        };
+
+        if hostname.is_empty() {
+            return None;
+        }
+
+        Some(Url {
+            hostname: hostname.to_string(),
+            port: port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for Url {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "https://{}:{}{}", self.hostname, self.port, self.path)
+    }
+}
+
+fn main() {
+    let url = env::args().nth(1).unwrap_or("https://www.rust-lang.org/".to_string());
+
+    let url = match Url::parse(&url) {
+        Some(url) => url,
+        None => {
+            println!("invalid URL, expected https://host[:port]/path; url={:?}", url);
+            process::exit(1);
+        }
+    };
+
+    // Certificates are checked against the system's trusted roots, and
+    // against the one in the file given, if any, which is how a self-signed
+    // certificate can be trusted
+    let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+
+    if let Some(ca) = env::args().nth(2) {
+        connector.set_ca_file(&ca).unwrap();
+    }
+
+    // Resolving the name blocks, before the event loop even starts
+    let addr: SocketAddr = match (&url.hostname[..], url.port).to_socket_addrs().ok().and_then(|mut addrs| addrs.next()) {
+        Some(addr) => addr,
+        None => {
+            println!("failed to resolve host; host={}", url.hostname);
+            process::exit(1);
+        }
+    };
+
+    let socket = TcpStream::connect(&addr).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+
+    // Writable once connected
+    event_loop.register_opt(&socket, CLIENT, mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+        .unwrap();
+    event_loop.timeout_ms((), TIMEOUT_MS).unwrap();
+
+    println!("fetching; url={}; addr={}", url, addr);
+
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: mio-examples\r\nConnection: close\r\n\r\n", url.path, url.hostname);
+
+    let mut fetch = Fetch {
+        url: url,
+        connector: connector.build(),
+        tls: Tls::Connecting(socket),
+        wants: mio::EventSet::writable(),
+        out: request.into_bytes(),
+        buf: vec![],
+        parser: Parser::new(),
+        failed: false,
+    };
+
+    event_loop.run(&mut fetch).unwrap();
+
+    if fetch.failed {
+        process::exit(1);
+    }
+}
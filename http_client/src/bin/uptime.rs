@@ -0,0 +1,403 @@
+extern crate http_client;
+extern crate mio;
+
+use http_client::response::Parser;
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use std::{env, fmt, process};
+
+// How often each URL is checked, and how long a check may take, by
+// default
+const INTERVAL_MS: u64 = 30_000;
+const CHECK_TIMEOUT_MS: u64 = 5_000;
+
+// A URL is reported down after this many failed checks in a row, so that
+// a single lost connection doesn't raise an alert
+const FAILURES_BEFORE_DOWN: u32 = 2;
+
+// How often the state of all URLs is summed up
+const SUMMARY_MS: u64 = 60_000;
+
+enum Timer {
+    // Time to check the URL again
+    Check(usize),
+    // The check in flight took too long
+    Deadline(usize),
+    Summary,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State {
+    // Not checked yet
+    Unknown,
+    Up,
+    Down,
+}
+
+// A request in flight. Every check uses a new connection, registered with
+// the URL's token: the previous one is closed before the next one is
+// opened.
+struct Request {
+    socket: TcpStream,
+    // What is left of the request to write
+    out: Vec<u8>,
+    buf: Vec<u8>,
+    parser: Parser,
+    started: Instant,
+    deadline: mio::Timeout,
+}
+
+struct Check {
+    url: Url,
+    addr: SocketAddr,
+    state: State,
+    since: Instant,
+    request: Option<Request>,
+    // Failed checks in a row
+    failures: u32,
+    // Of the successful checks
+    checks: u64,
+    total_ms: u64,
+}
+
+// Checks a list of URLs, each on its own timer, and reports when one goes
+// down or comes back up. A URL is up when it answers with a 2xx or 3xx
+// status within the timeout. All checks run in the one event loop, the
+// URL's index in the list being the token of its connection.
+struct Monitor {
+    checks: Vec<Check>,
+    interval_ms: u64,
+    timeout_ms: u64,
+}
+
+impl Monitor {
+    fn start(&mut self, event_loop: &mut mio::EventLoop<Monitor>, i: usize) {
+        let socket = match TcpStream::connect(&self.checks[i].addr) {
+            Ok(socket) => socket,
+            Err(e) => return self.finish(event_loop, i, Err(e.to_string())),
+        };
+
+        let check = &mut self.checks[i];
+
+        // The socket becomes writable once connected, and the request is
+        // written then
+        event_loop.register_opt(&socket, mio::Token(i), mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        let out = format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: mio-examples\r\nConnection: close\r\n\r\n",
+                          check.url.path, check.url.hostname);
+
+        check.request = Some(Request {
+            socket: socket,
+            out: out.into_bytes(),
+            buf: vec![],
+            parser: Parser::new(),
+            started: Instant::now(),
+            deadline: event_loop.timeout_ms(Timer::Deadline(i), self.timeout_ms).unwrap(),
+        });
+    }
+
+    // Records the outcome of a check, the status and latency or why it
+    // failed, and schedules the next one
+    fn finish(&mut self, event_loop: &mut mio::EventLoop<Monitor>, i: usize, result: Result<(u16, u64), String>) {
+        let check = &mut self.checks[i];
+
+        // Dropping the request closes its connection
+        if let Some(request) = check.request.take() {
+            event_loop.clear_timeout(request.deadline);
+        }
+
+        let result = match result {
+            Ok((status, _)) if status >= 400 => Err(format!("status {}", status)),
+            result => result,
+        };
+
+        match result {
+            Ok((status, ms)) => {
+                check.failures = 0;
+                check.checks += 1;
+                check.total_ms += ms;
+
+                if check.state != State::Up {
+                    match check.state {
+                        State::Down => println!("UP   {}; status={}; latency={}ms; down for {}", check.url, status, ms, Elapsed(check.since)),
+                        _ => println!("UP   {}; status={}; latency={}ms", check.url, status, ms),
+                    }
+
+                    check.state = State::Up;
+                    check.since = Instant::now();
+                }
+            }
+            Err(err) => {
+                check.failures += 1;
+
+                if check.state != State::Down && (check.failures >= FAILURES_BEFORE_DOWN || check.state == State::Unknown) {
+                    match check.state {
+                        State::Up => println!("DOWN {}; err={}; up for {}", check.url, err, Elapsed(check.since)),
+                        _ => println!("DOWN {}; err={}", check.url, err),
+                    }
+
+                    check.state = State::Down;
+                    check.since = Instant::now();
+                } else if check.state == State::Up {
+                    println!("failed check; url={}; err={}; failures={}", check.url, err, check.failures);
+                }
+            }
+        }
+
+        event_loop.timeout_ms(Timer::Check(i), self.interval_ms).unwrap();
+    }
+
+    fn summary(&self) {
+        let up = self.checks.iter().filter(|check| check.state == State::Up).count();
+        let down = self.checks.iter().filter(|check| check.state == State::Down).count();
+
+        // Of all the successful checks so far
+        let checks: u64 = self.checks.iter().map(|check| check.checks).sum();
+        let total_ms: u64 = self.checks.iter().map(|check| check.total_ms).sum();
+        let average = total_ms.checked_div(checks).unwrap_or(0);
+
+        println!("summary; urls={}; up={}; down={}; average latency={}ms", self.checks.len(), up, down, average);
+
+        for check in self.checks.iter().filter(|check| check.state == State::Down) {
+            println!("  down {}; for {}", check.url, Elapsed(check.since));
+        }
+    }
+}
+
+impl mio::Handler for Monitor {
+    type Timeout = Timer;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Monitor>, token: mio::Token, events: mio::EventSet) {
+        let i = token.as_usize();
+
+        let result = {
+            let request = match self.checks[i].request.as_mut() {
+                Some(request) => request,
+                None => return,
+            };
+
+            ready(request, events)
+        };
+
+        match result {
+            Some(result) => self.finish(event_loop, i, result),
+            None => {
+                let request = self.checks[i].request.as_ref().unwrap();
+
+                // Once the request is written, only reading is left
+                let interest = if request.out.is_empty() {
+                    mio::EventSet::readable()
+                } else {
+                    mio::EventSet::writable()
+                };
+
+                event_loop.reregister(&request.socket, token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                    .unwrap();
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Monitor>, timer: Timer) {
+        match timer {
+            Timer::Check(i) => self.start(event_loop, i),
+            Timer::Deadline(i) => {
+                let timeout_ms = self.timeout_ms;
+                self.finish(event_loop, i, Err(format!("timed out after {}ms", timeout_ms)));
+            }
+            Timer::Summary => {
+                self.summary();
+                event_loop.timeout_ms(Timer::Summary, SUMMARY_MS).unwrap();
+            }
+        }
+    }
+}
+
+// Writes the request, reads the response. Returns the outcome once there
+// is one: the status and the latency, or why the check failed.
+fn ready(request: &mut Request, events: mio::EventSet) -> Option<Result<(u16, u64), String>> {
+    if events.is_error() || events.is_hup() && !request.out.is_empty() {
+        let err = request.socket.take_socket_error().err().map(|e| e.to_string());
+        return Some(Err(err.unwrap_or_else(|| "connection failed".to_string())));
+    }
+
+    if events.is_writable() {
+        while !request.out.is_empty() {
+            match request.socket.try_write(&request.out) {
+                Ok(Some(n)) => {
+                    request.out.drain(..n);
+                }
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e.to_string())),
+            }
+        }
+    }
+
+    if events.is_readable() {
+        let mut chunk = [0; 4_096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            let response = match request.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => match request.parser.eof() {
+                    Ok(Some(response)) => response,
+                    Ok(None) => return Some(Err("connection closed without a response".to_string())),
+                    Err(e) => return Some(Err(e.to_string())),
+                },
+                Ok(Some(n)) => {
+                    request.buf.extend_from_slice(&chunk[..n]);
+
+                    match request.parser.parse(&mut request.buf) {
+                        Ok(Some(response)) => response,
+                        Ok(None) => continue,
+                        Err(e) => return Some(Err(e.to_string())),
+                    }
+                }
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e.to_string())),
+            };
+
+            return Some(Ok((response.status, millis(request.started.elapsed()))));
+        }
+    }
+
+    None
+}
+
+struct Url {
+    hostname: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    fn parse(s: &str) -> Option<Url> {
+        let rest = s.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+
+        let (hostname, port) = match authority.rfind(':') {
+            Some(pos) => (&authority[..pos], authority[pos + 1..].parse().ok()?),
+            None => (authority, 80),
+        };
+
+        if hostname.is_empty() {
+            return None;
+        }
+
+        Some(Url {
+            hostname: hostname.to_string(),
+            port: port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.hostname, self.port, self.path)
+    }
+}
+
+// How long ago, for humans
+struct Elapsed(Instant);
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.0.elapsed().as_secs();
+
+        match secs {
+            0..=59 => write!(f, "{}s", secs),
+            60..=3_599 => write!(f, "{}m{}s", secs / 60, secs % 60),
+            _ => write!(f, "{}h{}m", secs / 3_600, secs % 3_600 / 60),
+        }
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000 + duration.subsec_millis() as u64
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let path = args.next().unwrap_or_else(|| "uptime.conf".to_string());
+    let interval_ms = args.next().map(|s| s.parse::<u64>().unwrap() * 1_000).unwrap_or(INTERVAL_MS);
+    let timeout_ms = args.next().map(|s| s.parse().unwrap()).unwrap_or(CHECK_TIMEOUT_MS);
+
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("failed to open the URL list; path={:?}; err={}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let mut checks = vec![];
+
+    // A URL per line, `#` starts a comment
+    for line in BufReader::new(file).lines() {
+        let line = line.unwrap();
+        let line = line.split('#').next().unwrap().trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let url = match Url::parse(line) {
+            Some(url) => url,
+            None => {
+                println!("invalid URL, expected http://host[:port]/path; url={:?}", line);
+                process::exit(1);
+            }
+        };
+
+        // Resolved once, up front: resolving blocks
+        let addr = match (&url.hostname[..], url.port).to_socket_addrs().ok().and_then(|mut addrs| addrs.next()) {
+            Some(addr) => addr,
+            None => {
+                println!("failed to resolve host; url={}", url);
+                process::exit(1);
+            }
+        };
+
+        checks.push(Check {
+            url: url,
+            addr: addr,
+            state: State::Unknown,
+            since: Instant::now(),
+            request: None,
+            failures: 0,
+            checks: 0,
+            total_ms: 0,
+        });
+    }
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+
+    // The first checks are spread over the interval, rather than all
+    // started at once, and so are the ones after
+    for i in 0..checks.len() {
+        let delay = interval_ms * i as u64 / checks.len() as u64;
+        event_loop.timeout_ms(Timer::Check(i), delay).unwrap();
+    }
+
+    event_loop.timeout_ms(Timer::Summary, SUMMARY_MS).unwrap();
+
+    println!("monitoring; urls={}; interval={}ms; timeout={}ms", checks.len(), interval_ms, timeout_ms);
+
+    let mut monitor = Monitor {
+        checks: checks,
+        interval_ms: interval_ms,
+        timeout_ms: timeout_ms,
+    };
+
+    event_loop.run(&mut monitor).unwrap();
+}
@@ -0,0 +1,9 @@
+// The HTTP/1.x response parser and the connection pool, shared by the
+// client, and the HTTPS fetcher, uptime monitor and crawler in `src/bin`.
+// The crawler also extracts links from the pages it fetches.
+
+extern crate mio;
+
+pub mod links;
+pub mod pool;
+pub mod response;
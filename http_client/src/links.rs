@@ -0,0 +1,188 @@
+// Extracts the links from an HTML page, and resolves them against the
+// page's URL. There is no HTML parser here: a link is the value of any
+// `href` attribute, which is what matters for crawling, comments and
+// scripts are rare enough places for one to turn up.
+//
+// Only `http://` links are kept, there is no TLS. URLs come out
+// normalized, so that the same page linked to in different ways is
+// crawled once: the host is lowercased, the default port, the fragment
+// and dot segments are dropped.
+//
+// Like the rest of the examples' protocol code, this does no I/O.
+
+use std::net::Ipv6Addr;
+
+// The `href` values in the page, as they are written, with `&amp;`
+// decoded
+pub fn extract(html: &[u8]) -> Vec<String> {
+    let lower = html.to_ascii_lowercase();
+    let mut links = vec![];
+    let mut pos = 0;
+
+    while let Some(found) = find(&lower[pos..], b"href") {
+        pos += found + 4;
+
+        // The attribute name may be part of another word
+        if pos >= 5 && lower[pos - 5].is_ascii_alphanumeric() {
+            continue;
+        }
+
+        let rest = &html[pos..];
+        let mut i = skip_spaces(rest, 0);
+
+        if rest.get(i) != Some(&b'=') {
+            continue;
+        }
+
+        i = skip_spaces(rest, i + 1);
+
+        let value = match rest.get(i) {
+            Some(&quote) if quote == b'"' || quote == b'\'' => {
+                let value = &rest[i + 1..];
+                &value[..value.iter().position(|&b| b == quote).unwrap_or(value.len())]
+            }
+            Some(_) => {
+                let value = &rest[i..];
+                &value[..value.iter().position(|&b| b.is_ascii_whitespace() || b == b'>').unwrap_or(value.len())]
+            }
+            None => break,
+        };
+
+        links.push(String::from_utf8_lossy(value).replace("&amp;", "&"));
+    }
+
+    links
+}
+
+// Resolves `href` against `base`, an `http://` URL. Returns `None` for
+// links to anything but `http://` URLs, and links within the page.
+pub fn resolve(base: &str, href: &str) -> Option<String> {
+    let href = href.trim();
+    let href = &href[..href.find('#').unwrap_or(href.len())];
+
+    if href.is_empty() {
+        return None;
+    }
+
+    let (origin, path) = split(base)?;
+
+    let url = if href.starts_with("http://") {
+        href.to_string()
+    } else if href.starts_with("//") {
+        format!("http:{}", href)
+    } else if href.find(':').map(|i| !href[..i].contains('/')).unwrap_or(false) {
+        // Another scheme: https, mailto, javascript...
+        return None;
+    } else if href.starts_with('/') {
+        format!("{}{}", origin, href)
+    } else if href.starts_with('?') {
+        format!("{}{}{}", origin, &path[..path.find('?').unwrap_or(path.len())], href)
+    } else {
+        // Relative to the directory of the base's path
+        let path = &path[..path.find('?').unwrap_or(path.len())];
+        format!("{}{}{}", origin, &path[..path.rfind('/').unwrap() + 1], href)
+    };
+
+    normalize(&url)
+}
+
+// Lowercases the host, drops the default port, and resolves `.` and `..`
+// in the path
+pub fn normalize(url: &str) -> Option<String> {
+    let (origin, path) = split(url)?;
+
+    let authority = origin["http://".len()..].to_ascii_lowercase();
+    let authority = authority.trim_end_matches(":80");
+
+    // A link with a port that isn't one, or a mangled IPv6 address, goes
+    // no further than here
+    host_port(authority)?;
+
+    let (path, query) = match path.find('?') {
+        Some(i) => path.split_at(i),
+        None => (path, ""),
+    };
+
+    let mut segments: Vec<&str> = vec![];
+
+    for segment in path.split('/').skip(1) {
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    // `a/.` and `a/..` are directories
+    if path.ends_with("/.") || path.ends_with("/..") {
+        segments.push("");
+    }
+
+    Some(format!("http://{}/{}{}", authority, segments.join("/"), query))
+}
+
+// Splits a URL's authority into the host, IPv6 addresses still in their
+// brackets, and the port, 80 if there is none. Returns `None` if either is
+// malformed.
+pub fn host_port(authority: &str) -> Option<(&str, u16)> {
+    let (host, port) = if authority.starts_with('[') {
+        let end = authority.find(']')? + 1;
+
+        if authority[1..end - 1].parse::<Ipv6Addr>().is_err() {
+            return None;
+        }
+
+        match &authority[end..] {
+            "" => (&authority[..end], None),
+            rest if rest.starts_with(':') => (&authority[..end], Some(&rest[1..])),
+            _ => return None,
+        }
+    } else {
+        match authority.find(':') {
+            Some(i) => (&authority[..i], Some(&authority[i + 1..])),
+            None => (authority, None),
+        }
+    };
+
+    if host.is_empty() {
+        return None;
+    }
+
+    match port {
+        None => Some((host, 80)),
+        // `parse` takes a sign too
+        Some(port) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+            port.parse().ok().map(|port| (host, port))
+        }
+        Some(_) => None,
+    }
+}
+
+// Splits an `http://` URL into its origin and path, `/` if it has none
+fn split(url: &str) -> Option<(&str, &str)> {
+    if !url.starts_with("http://") {
+        return None;
+    }
+
+    let end = url["http://".len()..].find(['/', '?']).map(|i| i + "http://".len()).unwrap_or(url.len());
+
+    match &url[end..] {
+        "" => Some((&url[..end], "/")),
+        path if path.starts_with('?') => None,
+        path => Some((&url[..end], path)),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn skip_spaces(buf: &[u8], mut i: usize) -> usize {
+    while buf.get(i).map(|b| b.is_ascii_whitespace()).unwrap_or(false) {
+        i += 1;
+    }
+
+    i
+}
@@ -0,0 +1,518 @@
+extern crate http_client;
+extern crate mio;
+
+use http_client::pool::{Checkout, Pool};
+use http_client::response::{Parser, Response};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::collections::{HashMap, VecDeque};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use std::{env, fmt};
+
+const STDIN: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 1_024;
+
+// How long a connection is kept idle before it is closed
+const IDLE_SECS: u64 = 5;
+
+// How often the pool is checked for expired connections
+const SWEEP_MS: u64 = 1_000;
+
+// Reads URLs from stdin, one per line, and fetches them. Requests are
+// started as soon as they are read, through a pool of keep-alive
+// connections per host.
+struct Fetch {
+    pool: Pool,
+    connections: Slab<Connection>,
+    // Requests waiting for a connection to their host
+    waiting: HashMap<String, VecDeque<Request>>,
+    stdin: mio::Io,
+    // Typed but not yet terminated by a newline
+    line: Vec<u8>,
+    stdin_closed: bool,
+    next_id: u64,
+    // Requests not yet completed, or failed
+    outstanding: usize,
+    requests: u64,
+    opened: u64,
+}
+
+struct Request {
+    id: u64,
+    url: Url,
+    started: Instant,
+    // A request sent on a connection the server had closed in the meantime
+    // is sent again, once.
+    retried: bool,
+}
+
+impl Fetch {
+    fn read_stdin(&mut self, event_loop: &mut mio::EventLoop<Fetch>) {
+        let mut buf = [0; 1_024];
+
+        match self.stdin.try_read(&mut buf) {
+            Ok(Some(0)) => self.stdin_closed = true,
+            Ok(Some(n)) => self.line.extend(&buf[..n]),
+            Ok(None) => {}
+            Err(e) => {
+                println!("got an error trying to read stdin; err={:?}", e);
+                self.stdin_closed = true;
+            }
+        }
+
+        while let Some(pos) = self.line.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.line.drain(..pos + 1).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let url = match Url::parse(line) {
+                Some(url) => url,
+                None => {
+                    println!("invalid URL, expected http://host[:port]/path; url={:?}", line);
+                    continue;
+                }
+            };
+
+            self.next_id += 1;
+            self.outstanding += 1;
+
+            let request = Request {
+                id: self.next_id,
+                url: url,
+                started: Instant::now(),
+                retried: false,
+            };
+
+            self.submit(event_loop, request);
+        }
+    }
+
+    // Finds a connection for the request: an idle one, a new one, or the
+    // next one to free up.
+    fn submit(&mut self, event_loop: &mut mio::EventLoop<Fetch>, request: Request) {
+        let host = request.url.host();
+
+        match self.pool.checkout(&host) {
+            Checkout::Idle(token) => self.send(event_loop, token, request),
+            Checkout::Connect => self.connect(event_loop, request),
+            Checkout::Full => {
+                println!("waiting for a connection; id={}; host={}", request.id, host);
+                self.waiting.entry(host).or_default().push_back(request);
+            }
+        }
+    }
+
+    fn connect(&mut self, event_loop: &mut mio::EventLoop<Fetch>, request: Request) {
+        let host = request.url.host();
+
+        // Resolving the hostname blocks the event loop. Fine for an example,
+        // a real client would resolve asynchronously, the way the crawler
+        // does.
+        let socket = match resolve(&host).map(|addr| TcpStream::connect(&addr)) {
+            Some(Ok(socket)) => socket,
+            Some(Err(e)) => {
+                self.fail(request, &format!("failed to connect; err={:?}", e));
+                self.pool.release(&host);
+                self.dispatch(event_loop, &host);
+                return;
+            }
+            None => {
+                self.fail(request, "failed to resolve host");
+                self.pool.release(&host);
+                self.dispatch(event_loop, &host);
+                return;
+            }
+        };
+
+        let token = self.connections.insert_with(|token| Connection::new(socket, token, host)).unwrap();
+
+        self.opened += 1;
+        println!("connecting; host={}; token={:?}", self.connections[token].host, token);
+
+        // The connect is non-blocking. The socket becomes writable once the
+        // connection is established (or has failed), and the request is
+        // written then.
+        self.connections[token].start(request, false);
+        self.connections[token].register(event_loop);
+    }
+
+    // Puts the request on a connection already established
+    fn send(&mut self, event_loop: &mut mio::EventLoop<Fetch>, token: mio::Token, request: Request) {
+        let conn = &mut self.connections[token];
+
+        conn.start(request, true);
+
+        if conn.write() {
+            conn.reregister(event_loop);
+        } else {
+            self.closed(event_loop, token);
+        }
+    }
+
+    // Hands a connection freed up, or the room for one, to the next request
+    // waiting for the host.
+    fn dispatch(&mut self, event_loop: &mut mio::EventLoop<Fetch>, host: &str) {
+        let request = match self.waiting.get_mut(host).and_then(|waiting| waiting.pop_front()) {
+            Some(request) => request,
+            None => return,
+        };
+
+        if self.waiting[host].is_empty() {
+            self.waiting.remove(host);
+        }
+
+        self.submit(event_loop, request);
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<Fetch>, token: mio::Token, events: mio::EventSet) {
+        // The connection may have been closed earlier in the same turn of
+        // the event loop
+        if !self.connections.contains(token) {
+            return;
+        }
+
+        let result = self.connections[token].ready(events);
+
+        match result {
+            Ok(Some(response)) => {
+                let (request, keep_alive) = {
+                    let conn = &mut self.connections[token];
+                    (conn.request.take().unwrap(), response.keep_alive())
+                };
+
+                self.complete(request, &response, self.connections[token].reused);
+
+                if keep_alive {
+                    let host = self.connections[token].host.clone();
+                    self.pool.checkin(&host, token, Instant::now());
+                    self.connections[token].reregister(event_loop);
+                    self.dispatch(event_loop, &host);
+                } else {
+                    self.closed(event_loop, token);
+                }
+            }
+            Ok(None) => self.connections[token].reregister(event_loop),
+            Err(e) => {
+                let conn = &mut self.connections[token];
+
+                // A connection sitting in the pool can be closed by the
+                // server at any time, even right as a request is sent on it.
+                // If nothing came back, the request is sent again on another
+                // connection.
+                if let Some(mut request) = conn.request.take() {
+                    if conn.reused && !conn.parser.is_started() && !request.retried {
+                        println!("pooled connection was closed, retrying; id={}", request.id);
+                        request.retried = true;
+                        self.closed(event_loop, token);
+                        self.submit(event_loop, request);
+                        return;
+                    }
+
+                    self.fail(request, e);
+                } else {
+                    println!("idle connection closed; host={}; token={:?}", conn.host, token);
+                }
+
+                self.closed(event_loop, token);
+            }
+        }
+    }
+
+    // Drops a connection that is gone, making room for a waiting request
+    fn closed(&mut self, event_loop: &mut mio::EventLoop<Fetch>, token: mio::Token) {
+        // Dropping the socket closes it, which also removes it from the
+        // event loop.
+        let conn = self.connections.remove(token).unwrap();
+
+        if let Some(request) = conn.request {
+            self.fail(request, "connection closed");
+        }
+
+        self.pool.closed(&conn.host, token);
+        self.dispatch(event_loop, &conn.host);
+    }
+
+    fn complete(&mut self, request: Request, response: &Response, reused: bool) {
+        self.outstanding -= 1;
+        self.requests += 1;
+
+        println!("{} {} {}; id={}; bytes={}; reused={}; elapsed={}ms",
+                 request.url, response.status, response.reason,
+                 request.id, response.body.len(), reused, millis(request.started.elapsed()));
+    }
+
+    fn fail(&mut self, request: Request, err: &str) {
+        self.outstanding -= 1;
+        println!("{} failed; id={}; err={}", request.url, request.id, err);
+    }
+
+    fn sweep(&mut self, event_loop: &mut mio::EventLoop<Fetch>) {
+        for token in self.pool.expire(Instant::now(), Duration::from_secs(IDLE_SECS)) {
+            let conn = self.connections.remove(token).unwrap();
+            println!("closing idle connection; host={}; token={:?}", conn.host, token);
+        }
+
+        event_loop.timeout_ms((), SWEEP_MS).unwrap();
+    }
+}
+
+impl mio::Handler for Fetch {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Fetch>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            STDIN => {
+                // Stdin is registered as level triggered, one read per event
+                // is enough.
+                self.read_stdin(event_loop);
+
+                if self.stdin_closed {
+                    event_loop.deregister(&self.stdin).unwrap();
+                }
+            }
+            _ => self.connection_ready(event_loop, token, events),
+        }
+
+        // Done once every request has completed
+        if self.stdin_closed && self.outstanding == 0 {
+            println!("done; requests={}; connections opened={}; open={}; idle={}",
+                     self.requests, self.opened, self.pool.open(), self.pool.idle());
+            event_loop.shutdown();
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Fetch>, _: ()) {
+        self.sweep(event_loop);
+    }
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Where it is connected to, as `host:port`
+    host: String,
+    connected: bool,
+    // The request in flight, `None` while in the pool
+    request: Option<Request>,
+    // Whether the request in flight isn't the first one on the connection
+    reused: bool,
+    parser: Parser,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token, host: String) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            host: host,
+            connected: false,
+            request: None,
+            reused: false,
+            parser: Parser::new(),
+            buf: vec![],
+            out: vec![],
+        }
+    }
+
+    fn start(&mut self, request: Request, reused: bool) {
+        let head = format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: mio-examples\r\n\r\n", request.url.path, self.host);
+
+        self.out.extend(head.as_bytes());
+        self.request = Some(request);
+        self.reused = reused;
+        self.parser = Parser::new();
+    }
+
+    // Returns the response once it is complete. An idle connection should
+    // hear nothing from the server but it closing the connection, which is
+    // reported as an error.
+    fn ready(&mut self, events: mio::EventSet) -> Result<Option<Response>, &'static str> {
+        if !self.connected {
+            if events.is_error() || events.is_hup() {
+                println!("connection failed; err={:?}", self.socket.take_socket_error());
+                return Err("failed to connect");
+            }
+
+            if events.is_writable() {
+                self.connected = true;
+            }
+        }
+
+        if events.is_writable() && !self.write() {
+            return Err("failed to write");
+        }
+
+        if !events.is_readable() {
+            return Ok(None);
+        }
+
+        let mut chunk = [0; 4_096];
+        let mut eof = false;
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    eof = true;
+                    break;
+                }
+                Ok(Some(n)) => self.buf.extend(&chunk[..n]),
+                Ok(None) => break,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    return Err("failed to read");
+                }
+            }
+        }
+
+        if self.request.is_none() {
+            return if eof || !self.buf.is_empty() { Err("unexpected data") } else { Ok(None) };
+        }
+
+        if let Some(response) = self.parser.parse(&mut self.buf)? {
+            // The server shouldn't send anything past the response it was
+            // asked for
+            if !self.buf.is_empty() {
+                return Err("unexpected data after the response");
+            }
+
+            return Ok(Some(response));
+        }
+
+        if eof {
+            return match self.parser.eof()? {
+                Some(response) => Ok(Some(response)),
+                None => Err("connection closed"),
+            };
+        }
+
+        Ok(None)
+    }
+
+    fn write(&mut self) -> bool {
+        if !self.connected {
+            return true;
+        }
+
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return true,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if !self.connected || !self.out.is_empty() {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        } else {
+            mio::EventSet::readable()
+        }
+    }
+
+    fn register(&self, event_loop: &mut mio::EventLoop<Fetch>) {
+        event_loop.register_opt(&self.socket, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn reregister(&self, event_loop: &mut mio::EventLoop<Fetch>) {
+        event_loop.reregister(&self.socket, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+// An `http://` URL. There is no TLS, so no `https://`.
+struct Url {
+    hostname: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    fn parse(s: &str) -> Option<Url> {
+        let rest = s.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+
+        let (hostname, port) = match authority.rfind(':') {
+            Some(pos) => (&authority[..pos], authority[pos + 1..].parse().ok()?),
+            None => (authority, 80),
+        };
+
+        if hostname.is_empty() {
+            return None;
+        }
+
+        Some(Url {
+            hostname: hostname.to_string(),
+            port: port,
+            path: path.to_string(),
+        })
+    }
+
+    // Connections are pooled by host and port
+    fn host(&self) -> String {
+        format!("{}:{}", self.hostname, self.port)
+    }
+
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "http://{}{}", self.host(), self.path)
+    }
+}
+
+fn resolve(host: &str) -> Option<SocketAddr> {
+    host.to_socket_addrs().ok().and_then(|mut addrs| addrs.next())
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000 + duration.subsec_millis() as u64
+}
+
+fn main() {
+    let max_per_host: usize = env::args().nth(1).map(|s| s.parse().unwrap()).unwrap_or(4);
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+
+    // Token `0` is reserved for stdin. Tokens 1+ are used for connections.
+    let mut fetch = Fetch {
+        pool: Pool::new(max_per_host),
+        connections: Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS),
+        waiting: HashMap::new(),
+        stdin: mio::Io::from_raw_fd(0),
+        line: vec![],
+        stdin_closed: false,
+        next_id: 0,
+        outstanding: 0,
+        requests: 0,
+        opened: 0,
+    };
+
+    event_loop.register_opt(&fetch.stdin, STDIN, mio::EventSet::readable(), mio::PollOpt::level()).unwrap();
+    event_loop.timeout_ms((), SWEEP_MS).unwrap();
+
+    println!("fetching URLs from stdin; max per host={}; idle timeout={}s", max_per_host, IDLE_SECS);
+    event_loop.run(&mut fetch).unwrap();
+}
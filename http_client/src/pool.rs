@@ -0,0 +1,131 @@
+// Keeps track of the connections open to each host, and of the idle ones
+// that can take another request. Opening a connection costs a round trip,
+// more with TLS, so a connection is kept once its response is in, and the
+// next request to the same host goes on it.
+//
+// A host gets at most `max_per_host` connections at once. Requests beyond
+// that wait their turn, rather than piling up connections on the server.
+// Idle connections are closed after a while, servers close them on their
+// own anyway, and keeping them open forever holds on to resources on both
+// ends.
+//
+// The pool only deals in tokens. It does no I/O: the caller opens and
+// closes the connections, and tells the pool about it.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Checkout {
+    // An idle connection, now in use
+    Idle(mio::Token),
+    // There is room for another connection. The caller should open one and
+    // `checkin` or `closed` it once done with it.
+    Connect,
+    // The host has as many connections as it can get, all in use
+    Full,
+}
+
+struct Host {
+    // Most recently checked in last, with the time it was. Those are the
+    // ones handed out first: the server is least likely to have closed
+    // them, and the others are left to expire.
+    idle: Vec<(mio::Token, Instant)>,
+    open: usize,
+}
+
+pub struct Pool {
+    max_per_host: usize,
+    hosts: HashMap<String, Host>,
+}
+
+impl Pool {
+    pub fn new(max_per_host: usize) -> Pool {
+        Pool {
+            max_per_host: max_per_host,
+            hosts: HashMap::new(),
+        }
+    }
+
+    pub fn checkout(&mut self, host: &str) -> Checkout {
+        let max = self.max_per_host;
+
+        let host = self.hosts.entry(host.to_string()).or_insert_with(|| {
+            Host {
+                idle: vec![],
+                open: 0,
+            }
+        });
+
+        if let Some((token, _)) = host.idle.pop() {
+            return Checkout::Idle(token);
+        }
+
+        if host.open < max {
+            host.open += 1;
+            return Checkout::Connect;
+        }
+
+        Checkout::Full
+    }
+
+    // The connection is done with its request, and can take another one
+    pub fn checkin(&mut self, host: &str, token: mio::Token, now: Instant) {
+        if let Some(host) = self.hosts.get_mut(host) {
+            host.idle.push((token, now));
+        }
+    }
+
+    // The connection is gone, making room for another one
+    pub fn closed(&mut self, host: &str, token: mio::Token) {
+        if let Some(host) = self.hosts.get_mut(host) {
+            host.idle.retain(|&(t, _)| t != token);
+        }
+
+        self.release(host);
+    }
+
+    // The room made for a connection is given back, the connection couldn't
+    // be opened or is gone. Once a host has no connection left, it is
+    // forgotten.
+    pub fn release(&mut self, host: &str) {
+        let empty = match self.hosts.get_mut(host) {
+            Some(host) => {
+                host.open -= 1;
+                host.open == 0
+            }
+            None => false,
+        };
+
+        if empty {
+            self.hosts.remove(host);
+        }
+    }
+
+    // Removes the connections idle for longer than `timeout`. The caller
+    // should close them.
+    pub fn expire(&mut self, now: Instant, timeout: Duration) -> Vec<mio::Token> {
+        let mut expired = vec![];
+
+        for host in self.hosts.values_mut() {
+            // The oldest are first
+            let n = host.idle.iter().take_while(|&&(_, since)| now - since >= timeout).count();
+
+            expired.extend(host.idle.drain(..n).map(|(token, _)| token));
+            host.open -= n;
+        }
+
+        self.hosts.retain(|_, host| host.open > 0);
+
+        expired
+    }
+
+    // The number of connections open, and of those, idle, over all hosts
+    pub fn open(&self) -> usize {
+        self.hosts.values().map(|host| host.open).sum()
+    }
+
+    pub fn idle(&self) -> usize {
+        self.hosts.values().map(|host| host.idle.len()).sum()
+    }
+}
@@ -0,0 +1,283 @@
+// Parses HTTP/1.x responses as their bytes arrive. How the body ends is
+// known from the head: after `Content-Length` bytes, after the last chunk
+// of a chunked body, or, without either, when the server closes the
+// connection. Only the first two leave the connection usable for another
+// request.
+//
+// Like the rest of the examples' protocol code, the parser does no I/O.
+
+use std::str;
+
+// Responses with a larger head are rejected
+pub const MAX_HEAD: usize = 8 * 1_024;
+
+// Or a larger body
+pub const MAX_BODY: usize = 16 * 1_024 * 1_024;
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    pub body: Vec<u8>,
+    // HTTP/1.1 or later
+    http11: bool,
+    headers: Vec<(String, String)>,
+    // Whether the body ended on its own, rather than with the connection
+    delimited: bool,
+}
+
+impl Response {
+    // Returns the value of a header. Header names are case insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &value[..])
+    }
+
+    // Whether the connection can be used for another request. HTTP/1.1
+    // connections stay open unless either side says otherwise, HTTP/1.0
+    // ones are closed unless the server says otherwise.
+    pub fn keep_alive(&self) -> bool {
+        let connection = |token: &str| {
+            self.header("Connection")
+                .map(|value| value.split(',').any(|v| v.trim().eq_ignore_ascii_case(token)))
+                .unwrap_or(false)
+        };
+
+        let open = if self.http11 { !connection("close") } else { connection("keep-alive") };
+
+        self.delimited && open
+    }
+}
+
+enum Body {
+    Length(usize),
+    // The size line of the next chunk
+    ChunkSize,
+    // What's left of the current chunk
+    ChunkData(usize),
+    // The CRLF following a chunk's data
+    ChunkEnd,
+    // Trailer headers, up to an empty line
+    Trailer,
+    // Until the connection is closed
+    Close,
+}
+
+pub struct Parser {
+    response: Option<Response>,
+    body: Body,
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser {
+            response: None,
+            body: Body::Close,
+        }
+    }
+
+    // Consumes what it can of `buf`. Returns the response once it is
+    // complete, ready for the next one.
+    pub fn parse(&mut self, buf: &mut Vec<u8>) -> Result<Option<Response>, &'static str> {
+        if self.response.is_none() {
+            match parse_head(buf)? {
+                Some((response, body, len)) => {
+                    buf.drain(..len);
+                    self.response = Some(response);
+                    self.body = body;
+                }
+                None => return Ok(None),
+            }
+        }
+
+        loop {
+            let response = self.response.as_mut().unwrap();
+
+            match self.body {
+                Body::Length(n) => {
+                    let len = n - response.body.len();
+                    let len = if len < buf.len() { len } else { buf.len() };
+                    response.body.extend(buf.drain(..len));
+
+                    if response.body.len() < n {
+                        return Ok(None);
+                    }
+
+                    break;
+                }
+                Body::ChunkSize => {
+                    let line = match take_line(buf)? {
+                        Some(line) => line,
+                        None => return Ok(None),
+                    };
+
+                    // Chunk extensions follow a `;`, they are ignored
+                    let size = line.split(';').next().unwrap_or("").trim();
+
+                    let size = match usize::from_str_radix(size, 16) {
+                        Ok(size) if size <= MAX_BODY - response.body.len() => size,
+                        Ok(_) => return Err("response too large"),
+                        Err(_) => return Err("malformed chunk size"),
+                    };
+
+                    self.body = if size == 0 { Body::Trailer } else { Body::ChunkData(size) };
+                }
+                Body::ChunkData(n) => {
+                    let len = if n < buf.len() { n } else { buf.len() };
+                    response.body.extend(buf.drain(..len));
+
+                    if len < n {
+                        self.body = Body::ChunkData(n - len);
+                        return Ok(None);
+                    }
+
+                    self.body = Body::ChunkEnd;
+                }
+                Body::ChunkEnd => {
+                    if buf.len() < 2 {
+                        return Ok(None);
+                    }
+
+                    if &buf[..2] != b"\r\n" {
+                        return Err("malformed chunk");
+                    }
+
+                    buf.drain(..2);
+                    self.body = Body::ChunkSize;
+                }
+                Body::Trailer => {
+                    match take_line(buf)? {
+                        Some(ref line) if line.is_empty() => break,
+                        Some(_) => {}
+                        None => return Ok(None),
+                    }
+                }
+                Body::Close => {
+                    if response.body.len() + buf.len() > MAX_BODY {
+                        return Err("response too large");
+                    }
+
+                    response.body.append(buf);
+                    return Ok(None);
+                }
+            }
+        }
+
+        let mut response = self.response.take().unwrap();
+        response.delimited = true;
+
+        Ok(Some(response))
+    }
+
+    // The connection was closed. Completes a body read until then, anything
+    // else cut short is an error.
+    pub fn eof(&mut self) -> Result<Option<Response>, &'static str> {
+        match (self.response.take(), &self.body) {
+            (Some(response), &Body::Close) => Ok(Some(response)),
+            (Some(_), _) => Err("connection closed in the middle of a response"),
+            (None, _) => Ok(None),
+        }
+    }
+
+    // Whether part of a response has been received
+    pub fn is_started(&self) -> bool {
+        self.response.is_some()
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
+    }
+}
+
+// Parses a response head from the start of `buf`. Returns the response, how
+// its body ends and the number of bytes it used, or `None` if the head isn't
+// complete yet.
+fn parse_head(buf: &[u8]) -> Result<Option<(Response, Body, usize)>, &'static str> {
+    let end = match buf.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => pos + 4,
+        None if buf.len() > MAX_HEAD => return Err("response head too large"),
+        None => return Ok(None),
+    };
+
+    let head = match str::from_utf8(&buf[..end]) {
+        Ok(head) => head,
+        Err(_) => return Err("response head is not valid UTF-8"),
+    };
+
+    let mut lines = head.split("\r\n");
+
+    // HTTP/1.1 200 OK
+    let mut status_line = lines.next().unwrap_or("").splitn(3, ' ');
+
+    let (version, status, reason) = match (status_line.next(), status_line.next(), status_line.next()) {
+        (Some(version), Some(status), reason) => (version, status, reason.unwrap_or("")),
+        _ => return Err("malformed status line"),
+    };
+
+    let http11 = match version {
+        "HTTP/1.0" => false,
+        _ if version.starts_with("HTTP/1.") => true,
+        _ => return Err("unsupported HTTP version"),
+    };
+
+    let status: u16 = match status.parse() {
+        Ok(status) if (100..600).contains(&status) => status,
+        _ => return Err("malformed status code"),
+    };
+
+    let mut headers = vec![];
+
+    for line in lines.filter(|line| !line.is_empty()) {
+        match line.find(':') {
+            Some(pos) => headers.push((line[..pos].trim().to_string(), line[pos + 1..].trim().to_string())),
+            None => return Err("malformed header"),
+        }
+    }
+
+    let response = Response {
+        status: status,
+        reason: reason.to_string(),
+        body: vec![],
+        http11: http11,
+        headers: headers,
+        delimited: false,
+    };
+
+    let chunked = response.header("Transfer-Encoding")
+        .map(|value| value.split(',').any(|v| v.trim().eq_ignore_ascii_case("chunked")))
+        .unwrap_or(false);
+
+    // Responses to GET requests have a body, except for these. The example
+    // doesn't send any other kind of request.
+    let body = if status < 200 || status == 204 || status == 304 {
+        Body::Length(0)
+    } else if chunked {
+        Body::ChunkSize
+    } else if let Some(len) = response.header("Content-Length") {
+        match len.parse() {
+            Ok(len) if len <= MAX_BODY => Body::Length(len),
+            Ok(_) => return Err("response too large"),
+            Err(_) => return Err("malformed content length"),
+        }
+    } else {
+        Body::Close
+    };
+
+    Ok(Some((response, body, end)))
+}
+
+// Takes a CRLF terminated line off the start of `buf`
+fn take_line(buf: &mut Vec<u8>) -> Result<Option<String>, &'static str> {
+    match buf.windows(2).position(|w| w == b"\r\n") {
+        Some(pos) => {
+            let line: Vec<u8> = buf.drain(..pos + 2).take(pos).collect();
+
+            String::from_utf8(line).map(Some).map_err(|_| "malformed chunk")
+        }
+        None if buf.len() > MAX_HEAD => Err("line too long"),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,361 @@
+extern crate acceptor;
+extern crate http_server;
+extern crate mio;
+
+use acceptor::{Acceptor, Factory};
+use http_server::request;
+use http_server::Response;
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// The page served on `/`, subscribing to the events
+const PAGE: &str = include_str!("events.html");
+
+// Room for 10k subscribers and then some. The process needs as many file
+// descriptors, see `ulimit -n`.
+const MAX_CONNECTIONS: usize = 16_384;
+
+// A subscriber that falls this far behind is disconnected
+const MAX_QUEUED: usize = 64 * 1_024;
+
+// Kept for subscribers reconnecting with the id of the last event they
+// got, so they get the ones they missed
+const HISTORY: usize = 100;
+
+// Intervals kept short for the demo. Proxies tend to close connections
+// idle for a minute or so, a heartbeat every 15 to 30 seconds is usual.
+const EVENT_MS: u64 = 10_000;
+const HEARTBEAT_MS: u64 = 4_000;
+const HEARTBEAT_CHECK_MS: u64 = 1_000;
+
+// How long the browser waits before reconnecting, sent to every subscriber
+const RETRY_MS: u64 = 3_000;
+
+#[derive(Clone, Copy)]
+enum Timer {
+    Event,
+    Heartbeat,
+}
+
+// Serves a stream of Server-Sent Events on `/events`. An event is made by
+// a timer, and written to every subscriber. Between events, the
+// connections are idle, apart from a heartbeat comment written to the ones
+// that have seen nothing for a while, so that proxies and load balancers
+// don't take them for dead.
+struct Events {
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+    // The last events, encoded, with their id
+    history: VecDeque<(u64, String)>,
+    next_id: u64,
+}
+
+impl Events {
+    fn new(acceptor: Acceptor<Factory<Connection>>) -> Events {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS);
+
+        Events {
+            acceptor: acceptor,
+            connections: slab,
+            history: VecDeque::new(),
+            next_id: 1,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Events>) {
+        let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+        if let Err(e) = res {
+            println!("encountered error while accepting connection; err={:?}", e);
+            event_loop.shutdown();
+        }
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<Events>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            self.connections[token].read();
+
+            if self.connections[token].state == State::Request {
+                self.route(token);
+            }
+        }
+
+        self.update(event_loop, token);
+    }
+
+    fn route(&mut self, token: mio::Token) {
+        let conn = &mut self.connections[token];
+
+        let request = match request::parse(&conn.buf) {
+            Ok(Some((request, _))) => request,
+            Ok(None) => return,
+            Err(reason) => {
+                return conn.respond(Response::text(400, reason));
+            }
+        };
+
+        conn.buf.clear();
+
+        match (&request.method[..], &request.path[..]) {
+            ("GET", "/events") => {}
+            ("GET", "/") => {
+                let page = Response::new(200)
+                    .header("Content-Type", "text/html; charset=utf-8")
+                    .body(PAGE.as_bytes().to_vec());
+
+                return conn.respond(page);
+            }
+            (_, "/") | (_, "/events") => return conn.respond(Response::text(405, "Method Not Allowed").header("Allow", "GET")),
+            _ => return conn.respond(Response::text(404, "Not Found")),
+        }
+
+        // There is no `Content-Length`, the body is the stream of events,
+        // and it ends when the connection does
+        conn.out.extend(b"HTTP/1.1 200 OK\r\n\
+                          Content-Type: text/event-stream\r\n\
+                          Cache-Control: no-cache\r\n\
+                          X-Accel-Buffering: no\r\n\r\n");
+        conn.out.extend(format!("retry: {}\n\n", RETRY_MS).as_bytes());
+
+        // A browser reconnecting says which event it got last
+        let last = request.header("Last-Event-ID").and_then(|id| id.parse::<u64>().ok());
+
+        if let Some(last) = last {
+            let missed: Vec<&(u64, String)> = self.history.iter().filter(|&&(id, _)| id > last).collect();
+
+            println!("subscriber resumed; token={:?}; last event={}; missed={}", token, last, missed.len());
+
+            for (_, event) in missed {
+                conn.out.extend(event.as_bytes());
+            }
+        } else {
+            println!("subscriber connected; token={:?}", token);
+        }
+
+        conn.state = State::Streaming;
+        conn.last_write = Instant::now();
+    }
+
+    // Makes an event, and writes it to every subscriber
+    fn publish(&mut self, event_loop: &mut mio::EventLoop<Events>) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let event = format!("id: {}\ndata: {{\"tick\":{},\"time\":{}}}\n\n", id, id, now);
+
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+
+        self.history.push_back((id, event.clone()));
+
+        let subscribers = self.send_all(event_loop, &event);
+
+        println!("published event; id={}; subscribers={}", id, subscribers);
+    }
+
+    // A comment, ignored by the browser, to the subscribers that have seen
+    // nothing for a while
+    fn heartbeat(&mut self, event_loop: &mut mio::EventLoop<Events>) {
+        let idle: Vec<mio::Token> = self.connections.iter()
+            .filter(|conn| conn.state == State::Streaming && conn.last_write.elapsed().as_secs() * 1_000 >= HEARTBEAT_MS)
+            .map(|conn| conn.token)
+            .collect();
+
+        for token in idle {
+            self.connections[token].out.extend(b": heartbeat\n\n");
+            self.update(event_loop, token);
+        }
+    }
+
+    // Returns the number of subscribers written to
+    fn send_all(&mut self, event_loop: &mut mio::EventLoop<Events>, event: &str) -> usize {
+        let subscribers: Vec<mio::Token> = self.connections.iter()
+            .filter(|conn| conn.state == State::Streaming)
+            .map(|conn| conn.token)
+            .collect();
+
+        for &token in &subscribers {
+            self.connections[token].out.extend(event.as_bytes());
+            self.update(event_loop, token);
+        }
+
+        subscribers.len()
+    }
+
+    // Writes what it can, then reregisters the connection, or removes it
+    // once it is done
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Events>, token: mio::Token) {
+        let conn = &mut self.connections[token];
+        conn.write();
+
+        if conn.out.len() > MAX_QUEUED {
+            println!("subscriber is not keeping up, disconnecting; token={:?}", token);
+            conn.closed = true;
+        }
+
+        if conn.closed || conn.state == State::Responding && conn.out.is_empty() {
+            if conn.state == State::Streaming {
+                println!("subscriber disconnected; token={:?}", token);
+            }
+
+            self.connections.remove(token);
+            return;
+        }
+
+        event_loop.reregister(&conn.socket, token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+impl mio::Handler for Events {
+    type Timeout = Timer;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Events>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ => self.connection_ready(event_loop, token, events),
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Events>, timer: Timer) {
+        match timer {
+            Timer::Event => {
+                self.publish(event_loop);
+                event_loop.timeout_ms(Timer::Event, EVENT_MS).unwrap();
+            }
+            Timer::Heartbeat => {
+                self.heartbeat(event_loop);
+                event_loop.timeout_ms(Timer::Heartbeat, HEARTBEAT_CHECK_MS).unwrap();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    // Reading the request
+    Request,
+    // Writing events, until the client goes away
+    Streaming,
+    // Writing a response other than the stream, then closing
+    Responding,
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    state: State,
+    // The request, until it is complete
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // The last time anything was written, to tell when a heartbeat is due
+    last_write: Instant,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            state: State::Request,
+            buf: vec![],
+            out: vec![],
+            last_write: Instant::now(),
+            closed: false,
+        }
+    }
+
+    fn respond(&mut self, response: Response) {
+        response.encode(false, &mut self.out);
+        self.state = State::Responding;
+    }
+
+    fn read(&mut self) {
+        let mut chunk = [0; 4_096];
+
+        // The socket is registered as edge triggered, drain it. Once the
+        // request is in, there is nothing more to read but the end of the
+        // stream, which is how a subscriber going away is noticed.
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    if self.state == State::Request {
+                        self.buf.extend(&chunk[..n]);
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        if !self.out.is_empty() {
+            self.last_write = Instant::now();
+        }
+
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1).unwrap_or("0.0.0.0:8081".to_string()).parse().unwrap();
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+    event_loop.timeout_ms(Timer::Event, EVENT_MS).unwrap();
+    event_loop.timeout_ms(Timer::Heartbeat, HEARTBEAT_CHECK_MS).unwrap();
+
+    println!("running event stream; addr={:?}", address);
+
+    let mut events = Events::new(acceptor);
+    event_loop.run(&mut events).unwrap();
+}
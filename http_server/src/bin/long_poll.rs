@@ -0,0 +1,350 @@
+extern crate acceptor;
+extern crate http_server;
+extern crate mio;
+
+use acceptor::{Acceptor, Factory};
+use http_server::request::{self, Request};
+use http_server::Response;
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 16_384;
+
+// Kept for the clients asking for what was published since the last one
+// they got
+const HISTORY: usize = 100;
+
+// A poll finding nothing waits this long before being answered with a 204,
+// and the client polls again. Proxies may not wait for a response much
+// longer than this.
+const POLL_TIMEOUT_MS: u64 = 30_000;
+
+const MAX_MESSAGE: usize = 1_024;
+
+// Clients poll for notifications, and whoever publishes them posts them:
+//
+//     GET /poll?since=<id>     -> the notifications after that id, or
+//                                 nothing yet
+//     POST /publish            -> the id of the notification, the body
+//
+// A poll with nothing to answer yet is parked: the request is read, but
+// its response is held back, and the connection left alone, until there
+// is something to answer. Publishing completes every parked poll at once.
+// Connections are kept open between requests, so a client polls again on
+// the same one.
+struct Notifier {
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+    // The last notifications, with their id
+    history: VecDeque<(u64, String)>,
+    // The id the next notification gets
+    next_id: u64,
+}
+
+impl Notifier {
+    fn new(acceptor: Acceptor<Factory<Connection>>) -> Notifier {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS);
+
+        Notifier {
+            acceptor: acceptor,
+            connections: slab,
+            history: VecDeque::new(),
+            next_id: 1,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Notifier>) {
+        let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+        if let Err(e) = res {
+            println!("encountered error while accepting connection; err={:?}", e);
+            event_loop.shutdown();
+        }
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<Notifier>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            self.connections[token].read();
+        }
+
+        self.process(event_loop, token);
+        self.update(event_loop, token);
+    }
+
+    // Answers the complete requests read so far, until one is parked. The
+    // ones after it wait for it to be answered.
+    fn process(&mut self, event_loop: &mut mio::EventLoop<Notifier>, token: mio::Token) {
+        while !self.connections[token].closing && self.connections[token].parked.is_none() {
+            let request = match request::parse(&self.connections[token].buf) {
+                Ok(Some((request, len))) => {
+                    self.connections[token].buf.drain(..len);
+                    request
+                }
+                Ok(None) => return,
+                Err(reason) => {
+                    let conn = &mut self.connections[token];
+                    Response::text(400, reason).encode(false, &mut conn.out);
+                    conn.closing = true;
+                    return;
+                }
+            };
+
+            match (&request.method[..], &request.path[..]) {
+                ("GET", "/poll") => self.poll(event_loop, token, request),
+                ("POST", "/publish") => self.publish(event_loop, token, &request),
+                (_, "/poll") => self.respond(token, &request, Response::text(405, "Method Not Allowed").header("Allow", "GET")),
+                (_, "/publish") => self.respond(token, &request, Response::text(405, "Method Not Allowed").header("Allow", "POST")),
+                _ => self.respond(token, &request, Response::text(404, "Not Found")),
+            }
+        }
+    }
+
+    fn poll(&mut self, event_loop: &mut mio::EventLoop<Notifier>, token: mio::Token, request: Request) {
+        // Without an id, whatever comes next
+        let since = match since(&request) {
+            Ok(since) => since.unwrap_or(self.next_id - 1),
+            Err(reason) => return self.respond(token, &request, Response::text(400, reason)),
+        };
+
+        if since < self.next_id - 1 {
+            let response = self.notifications(since);
+            return self.respond(token, &request, response);
+        }
+
+        let timeout = event_loop.timeout_ms(token, POLL_TIMEOUT_MS).unwrap();
+
+        self.connections[token].parked = Some(Parked {
+            request: request,
+            timeout: timeout,
+        });
+    }
+
+    fn publish(&mut self, event_loop: &mut mio::EventLoop<Notifier>, token: mio::Token, request: &Request) {
+        let message = String::from_utf8_lossy(&request.body).trim().to_string();
+
+        if message.is_empty() || message.len() > MAX_MESSAGE || message.contains('\n') {
+            return self.respond(token, request, Response::text(400, "expected a single line message"));
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+
+        self.history.push_back((id, message));
+
+        // Every parked poll is waiting for exactly this
+        let parked: Vec<mio::Token> = self.connections.iter()
+            .filter(|conn| conn.parked.is_some())
+            .map(|conn| conn.token)
+            .collect();
+
+        for &parked in &parked {
+            let Parked { request, timeout } = self.connections[parked].parked.take().unwrap();
+            event_loop.clear_timeout(timeout);
+
+            let response = self.notifications(id - 1);
+            self.respond(parked, &request, response);
+
+            // The next request may have been waiting behind the poll
+            self.process(event_loop, parked);
+            self.update(event_loop, parked);
+        }
+
+        println!("published; id={}; completed polls={}", id, parked.len());
+
+        self.respond(token, request, Response::text(200, &id.to_string()));
+    }
+
+    // The notifications after `since`, one per line, prefixed by their id.
+    // A client that missed more than there is history for gets what there
+    // is.
+    fn notifications(&self, since: u64) -> Response {
+        let lines: Vec<String> = self.history.iter()
+            .filter(|&&(id, _)| id > since)
+            .map(|(id, message)| format!("{} {}", id, message))
+            .collect();
+
+        Response::text(200, &lines.join("\n"))
+    }
+
+    fn respond(&mut self, token: mio::Token, request: &Request, response: Response) {
+        let conn = &mut self.connections[token];
+        let keep_alive = request.keep_alive();
+
+        response.encode(keep_alive, &mut conn.out);
+        conn.closing = !keep_alive;
+    }
+
+    // Writes what it can, then reregisters the connection, or removes it
+    // once it is done
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Notifier>, token: mio::Token) {
+        let conn = &mut self.connections[token];
+        conn.write();
+
+        if conn.closed || conn.closing && conn.out.is_empty() {
+            // A client may give up on a poll before it is answered
+            if let Some(parked) = conn.parked.take() {
+                event_loop.clear_timeout(parked.timeout);
+            }
+
+            self.connections.remove(token);
+            return;
+        }
+
+        event_loop.reregister(&conn.socket, token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+impl mio::Handler for Notifier {
+    // The connection whose poll waited long enough
+    type Timeout = mio::Token;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Notifier>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ => self.connection_ready(event_loop, token, events),
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Notifier>, token: mio::Token) {
+        let parked = match self.connections.get_mut(token).and_then(|conn| conn.parked.take()) {
+            Some(parked) => parked,
+            None => return,
+        };
+
+        self.respond(token, &parked.request, Response::new(204));
+        self.process(event_loop, token);
+        self.update(event_loop, token);
+    }
+}
+
+// A poll waiting for a notification
+struct Parked {
+    request: Request,
+    timeout: mio::Timeout,
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Read, and not yet parsed into requests
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    parked: Option<Parked>,
+    // Set once the connection should be closed after writing `out`
+    closing: bool,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            buf: vec![],
+            out: vec![],
+            parked: None,
+            closing: false,
+            closed: false,
+        }
+    }
+
+    fn read(&mut self) {
+        let mut chunk = [0; 4_096];
+
+        // The socket is registered as edge triggered, drain it. A parked
+        // connection is still read from, to notice the client going away.
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => self.buf.extend(&chunk[..n]),
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+// The `since` query parameter
+fn since(request: &Request) -> Result<Option<u64>, &'static str> {
+    let query = match request.query {
+        Some(ref query) => query,
+        None => return Ok(None),
+    };
+
+    for pair in query.split('&') {
+        let mut kv = pair.splitn(2, '=');
+
+        if kv.next() == Some("since") {
+            return match kv.next().map(|id| id.parse::<u64>()) {
+                Some(Ok(id)) => Ok(Some(id)),
+                _ => Err("invalid since"),
+            };
+        }
+    }
+
+    Ok(None)
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1).unwrap_or("0.0.0.0:8082".to_string()).parse().unwrap();
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    println!("running notifier; addr={:?}", address);
+
+    let mut notifier = Notifier::new(acceptor);
+    event_loop.run(&mut notifier).unwrap();
+}
@@ -0,0 +1,105 @@
+extern crate http_server;
+
+use http_server::{Params, Request, Response, Router};
+use std::collections::BTreeMap;
+use std::env;
+use std::net::SocketAddr;
+
+// The state shared by the handlers. With a single event loop thread, they
+// can't run at the same time, they get it mutably without any locking.
+struct Users {
+    users: BTreeMap<u64, String>,
+    next_id: u64,
+}
+
+fn index(_: &mut Users, _: &Request, _: &Params) -> Response {
+    Response::text(200, "GET /users\nPOST /users\nGET /users/:id\nPUT /users/:id\nDELETE /users/:id\nGET /hello/:name")
+}
+
+fn hello(_: &mut Users, _: &Request, params: &Params) -> Response {
+    Response::text(200, &format!("Hello, {}!", params.get("name").unwrap()))
+}
+
+fn list(users: &mut Users, _: &Request, _: &Params) -> Response {
+    let lines: Vec<String> = users.users.iter().map(|(id, name)| format!("{} {}", id, name)).collect();
+    Response::text(200, &lines.join("\n"))
+}
+
+// The body is the user's name
+fn create(users: &mut Users, request: &Request, _: &Params) -> Response {
+    let name = match name(request) {
+        Some(name) => name,
+        None => return Response::text(400, "expected a name"),
+    };
+
+    let id = users.next_id;
+    users.next_id += 1;
+    users.users.insert(id, name);
+
+    Response::text(201, &id.to_string()).header("Location", &format!("/users/{}", id))
+}
+
+fn show(users: &mut Users, _: &Request, params: &Params) -> Response {
+    match id(params).and_then(|id| users.users.get(&id)) {
+        Some(name) => Response::text(200, name),
+        None => Response::text(404, "no such user"),
+    }
+}
+
+fn update(users: &mut Users, request: &Request, params: &Params) -> Response {
+    let name = match name(request) {
+        Some(name) => name,
+        None => return Response::text(400, "expected a name"),
+    };
+
+    match id(params).and_then(|id| users.users.get_mut(&id)) {
+        Some(user) => {
+            *user = name;
+            Response::new(204)
+        }
+        None => Response::text(404, "no such user"),
+    }
+}
+
+fn remove(users: &mut Users, _: &Request, params: &Params) -> Response {
+    match id(params).and_then(|id| users.users.remove(&id)) {
+        Some(_) => Response::new(204),
+        None => Response::text(404, "no such user"),
+    }
+}
+
+// The `:id` of the route. One that isn't a number is a user that doesn't
+// exist.
+fn id(params: &Params) -> Option<u64> {
+    params.get("id").and_then(|id| id.parse().ok())
+}
+
+fn name(request: &Request) -> Option<String> {
+    let name = String::from_utf8_lossy(&request.body).trim().to_string();
+
+    if name.is_empty() || name.contains('\n') {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1).unwrap_or("0.0.0.0:8080".to_string()).parse().unwrap();
+
+    let router = Router::new()
+        .get("/", index)
+        .get("/hello/:name", hello)
+        .get("/users", list)
+        .post("/users", create)
+        .get("/users/:id", show)
+        .put("/users/:id", update)
+        .delete("/users/:id", remove);
+
+    let users = Users {
+        users: BTreeMap::new(),
+        next_id: 1,
+    };
+
+    http_server::server::serve(&address, router, users);
+}
@@ -0,0 +1,18 @@
+// A small HTTP/1.1 server framework: requests are matched against a table
+// of routes, by method and path pattern, and handed to plain functions
+// returning the response.
+//
+// The request parser, the response encoder and the router do no I/O, they
+// work on buffers filled and drained by the event loop. `server` is the
+// event loop side of it, shared by the servers in `src/bin`.
+
+extern crate mio;
+
+pub mod request;
+pub mod response;
+pub mod router;
+pub mod server;
+
+pub use request::Request;
+pub use response::Response;
+pub use router::{Params, Router};
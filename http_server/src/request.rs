@@ -0,0 +1,126 @@
+use std::str;
+
+// Requests with a larger head are rejected
+pub const MAX_HEAD: usize = 8 * 1_024;
+
+// Requests with a larger body are rejected
+pub const MAX_BODY: usize = 1_024 * 1_024;
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    // Without the query string
+    pub path: String,
+    pub query: Option<String>,
+    pub version: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    // Returns the value of a header. Header names are case insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &value[..])
+    }
+
+    // Whether the connection stays open after the response. It does by
+    // default in HTTP/1.1, and doesn't in HTTP/1.0.
+    pub fn keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.header("Connection")
+                .map(|value| value.split(',').any(|v| v.trim().eq_ignore_ascii_case(token)))
+                .unwrap_or(false)
+        };
+
+        if self.version == "HTTP/1.0" {
+            has_token("keep-alive")
+        } else {
+            !has_token("close")
+        }
+    }
+}
+
+// Whether `buf` starts with a complete request head. The body may still be
+// missing.
+pub fn has_head(buf: &[u8]) -> bool {
+    buf.windows(4).any(|w| w == b"\r\n\r\n")
+}
+
+// Parses a request from the start of `buf`, head and body. Returns the
+// request and the number of bytes it used, or `None` if it isn't complete
+// yet. Bodies are only delimited by `Content-Length`, chunked bodies
+// aren't supported.
+pub fn parse(buf: &[u8]) -> Result<Option<(Request, usize)>, &'static str> {
+    let end = match buf.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => pos + 4,
+        None if buf.len() > MAX_HEAD => return Err("request head too large"),
+        None => return Ok(None),
+    };
+
+    let head = match str::from_utf8(&buf[..end]) {
+        Ok(head) => head,
+        Err(_) => return Err("request is not valid UTF-8"),
+    };
+
+    let mut lines = head.split("\r\n");
+
+    // GET /path?query HTTP/1.1
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+
+    let (method, target, version) = match (request_line.next(), request_line.next(), request_line.next(), request_line.next()) {
+        (Some(method), Some(target), Some(version), None) => (method, target, version),
+        _ => return Err("malformed request line"),
+    };
+
+    if version != "HTTP/1.1" && version != "HTTP/1.0" {
+        return Err("unsupported HTTP version");
+    }
+
+    if !target.starts_with('/') {
+        return Err("malformed request target");
+    }
+
+    let mut headers = vec![];
+
+    for line in lines.filter(|line| !line.is_empty()) {
+        match line.find(':') {
+            Some(pos) => headers.push((line[..pos].trim().to_string(), line[pos + 1..].trim().to_string())),
+            None => return Err("malformed header"),
+        }
+    }
+
+    let (path, query) = match target.find('?') {
+        Some(pos) => (&target[..pos], Some(target[pos + 1..].to_string())),
+        None => (target, None),
+    };
+
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query,
+        version: version.to_string(),
+        headers: headers,
+        body: vec![],
+    };
+
+    if request.header("Transfer-Encoding").is_some() {
+        return Err("chunked bodies are not supported");
+    }
+
+    let len = match request.header("Content-Length").map(|len| len.parse::<usize>()) {
+        Some(Ok(len)) if len > MAX_BODY => return Err("request body too large"),
+        Some(Ok(len)) => len,
+        Some(Err(_)) => return Err("malformed Content-Length"),
+        None => 0,
+    };
+
+    if buf.len() < end + len {
+        return Ok(None);
+    }
+
+    request.body = buf[end..end + len].to_vec();
+
+    Ok(Some((request, end + len)))
+}
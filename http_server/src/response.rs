@@ -0,0 +1,74 @@
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status: status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    // A `text/plain` response. The text gets a trailing newline, for the
+    // benefit of whoever is reading it in a terminal.
+    pub fn text(status: u16, text: &str) -> Response {
+        Response::new(status)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(format!("{}\n", text).into_bytes())
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Response {
+        self.body = body;
+        self
+    }
+
+    // Appends the response to `dst`. `Content-Length` and `Connection` are
+    // set here, from the body and whether the connection is kept open.
+    pub fn encode(&self, keep_alive: bool, dst: &mut Vec<u8>) {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+
+        if !keep_alive {
+            head.push_str("Connection: close\r\n");
+        }
+
+        head.push_str("\r\n");
+
+        dst.extend(head.as_bytes());
+        dst.extend(&self.body);
+    }
+}
+
+// The reason phrase of the status codes used by the examples
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,175 @@
+// Matches requests against a table of routes, each a method, a path
+// pattern and the function handling the requests matching both. A
+// pattern is a path whose segments are either literal, or start with `:`
+// and match any segment, which is captured under that name:
+//
+//     GET /users/:id/posts/:post
+//
+// Routes are tried in the order they were added, the first one matching
+// wins. A path matching some route, but not with the request's method, is
+// a `405 Method Not Allowed`, listing the methods that would do. A path
+// matching none is a `404 Not Found`.
+
+use request::Request;
+use response::Response;
+
+// Handlers get the server's state, shared by all of them, the request and
+// what the pattern captured
+pub type Handler<S> = fn(&mut S, &Request, &Params) -> Response;
+
+enum Segment {
+    Literal(String),
+    Param(String),
+}
+
+struct Route<S> {
+    method: String,
+    pattern: Vec<Segment>,
+    handler: Handler<S>,
+}
+
+pub struct Router<S> {
+    routes: Vec<Route<S>>,
+}
+
+// The path segments captured by a pattern's parameters, percent-decoded
+#[derive(Debug, Default)]
+pub struct Params {
+    params: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| &value[..])
+    }
+}
+
+impl<S> Router<S> {
+    pub fn new() -> Router<S> {
+        Router { routes: vec![] }
+    }
+
+    pub fn route(mut self, method: &str, pattern: &str, handler: Handler<S>) -> Router<S> {
+        let pattern = segments(pattern).map(|segment| {
+            if let Some(name) = segment.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else {
+                Segment::Literal(segment.to_string())
+            }
+        }).collect();
+
+        self.routes.push(Route {
+            method: method.to_string(),
+            pattern: pattern,
+            handler: handler,
+        });
+
+        self
+    }
+
+    pub fn get(self, pattern: &str, handler: Handler<S>) -> Router<S> {
+        self.route("GET", pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: Handler<S>) -> Router<S> {
+        self.route("POST", pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: Handler<S>) -> Router<S> {
+        self.route("PUT", pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: Handler<S>) -> Router<S> {
+        self.route("DELETE", pattern, handler)
+    }
+
+    // Hands the request to the handler of the first route matching it
+    pub fn dispatch(&self, state: &mut S, request: &Request) -> Response {
+        let path: Vec<&str> = segments(&request.path).collect();
+        let mut allowed: Vec<&str> = vec![];
+
+        for route in &self.routes {
+            let params = match route.matches(&path) {
+                Some(params) => params,
+                None => continue,
+            };
+
+            if route.method == request.method {
+                return (route.handler)(state, request, &params);
+            }
+
+            if !allowed.contains(&&route.method[..]) {
+                allowed.push(&route.method);
+            }
+        }
+
+        if allowed.is_empty() {
+            Response::text(404, "Not Found")
+        } else {
+            Response::text(405, "Method Not Allowed").header("Allow", &allowed.join(", "))
+        }
+    }
+}
+
+impl<S> Default for Router<S> {
+    fn default() -> Router<S> {
+        Router::new()
+    }
+}
+
+impl<S> Route<S> {
+    fn matches(&self, path: &[&str]) -> Option<Params> {
+        if path.len() != self.pattern.len() {
+            return None;
+        }
+
+        let mut params = Params::default();
+
+        for (segment, part) in self.pattern.iter().zip(path) {
+            match *segment {
+                Segment::Literal(ref literal) if literal == part => {}
+                Segment::Param(ref name) => {
+                    params.params.push((name.clone(), decode(part)));
+                }
+                _ => return None,
+            }
+        }
+
+        Some(params)
+    }
+}
+
+// The segments of a path, `/` being none. Empty segments are skipped, so
+// a trailing slash doesn't make a difference.
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.trim_start_matches('/').split('/').filter(|segment| !segment.is_empty())
+}
+
+// Decodes `%XX` escapes. A segment that doesn't decode to UTF-8 is kept as
+// it is.
+fn decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).unwrap_or_else(|_| segment.to_string())
+}
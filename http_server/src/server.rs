@@ -0,0 +1,329 @@
+// The event loop side of the server: accepts connections, reads requests
+// off them, and writes back what the router answers. Connections are kept
+// open between requests unless the client asks otherwise, and requests
+// pipelined on a connection are answered in order.
+//
+// A client has a deadline to send the head of each request, counted from
+// when the server starts waiting for it, and trickling bytes in doesn't
+// push it back. That is the defence against slowloris: clients opening
+// many connections and sending their heads a byte at a time, to tie up
+// the server's connections without costing themselves anything. The
+// number of connections waiting on a head is capped too, so that slow
+// clients can't take all of them before their deadlines come.
+
+use mio::{self, EventLoop, Handler, TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use request;
+use response::Response;
+use router::Router;
+use std::net::SocketAddr;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 1_024;
+
+// Pipelined requests are left unread while this much output is waiting to
+// be written, a client sending requests without reading the responses
+// can't make the server buffer them without bound
+const MAX_OUT: usize = 64 * 1_024;
+
+// How long a client has to send a request head. An idle keep-alive
+// connection is waiting for the next one, and is closed after as long.
+const HEAD_TIMEOUT_MS: u64 = 10_000;
+
+// New connections are refused while this many are waiting on a head
+const MAX_INCOMPLETE: usize = 256;
+
+// How often the stats are printed
+const STATS_MS: u64 = 60_000;
+
+pub enum Timer {
+    // The connection didn't send a request head in time
+    Head(mio::Token),
+    Stats,
+}
+
+struct Stats {
+    // Connections waiting on a request head
+    incomplete: usize,
+    // Connections closed for not sending a head in time
+    timed_out: u64,
+    // Connections refused because too many were incomplete
+    refused: u64,
+}
+
+pub struct Server<S> {
+    server: TcpListener,
+    connections: Slab<Connection>,
+    router: Router<S>,
+    state: S,
+    stats: Stats,
+}
+
+// Runs the server until the process exits
+pub fn serve<S>(addr: &SocketAddr, router: Router<S>, state: S) {
+    let server = TcpListener::bind(addr).unwrap();
+
+    let mut event_loop = EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+    event_loop.timeout_ms(Timer::Stats, STATS_MS).unwrap();
+
+    // Token `0` is reserved for the server socket. Tokens 1+ are used for
+    // client connections.
+    let mut server = Server {
+        server: server,
+        connections: Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS),
+        router: router,
+        state: state,
+        stats: Stats {
+            incomplete: 0,
+            timed_out: 0,
+            refused: 0,
+        },
+    };
+
+    println!("running HTTP server; addr={:?}", addr);
+    event_loop.run(&mut server).unwrap();
+}
+
+impl<S> Server<S> {
+    fn accept(&mut self, event_loop: &mut EventLoop<Server<S>>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        if self.stats.incomplete >= MAX_INCOMPLETE {
+            self.stats.refused += 1;
+            println!("too many incomplete requests, dropping client; incomplete={}", self.stats.incomplete);
+            return;
+        }
+
+        let token = match self.connections.insert_with(|token| Connection::new(socket, token)) {
+            Some(token) => token,
+            None => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        event_loop.register_opt(&self.connections[token].socket, token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        self.update_deadline(event_loop, token);
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut EventLoop<Server<S>>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            self.connections[token].read();
+        }
+
+        self.process(token);
+
+        self.connections[token].write();
+
+        if self.connections[token].is_done() {
+            self.remove(event_loop, token);
+            return;
+        }
+
+        self.update_deadline(event_loop, token);
+
+        let conn = &self.connections[token];
+        event_loop.reregister(&conn.socket, token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    // Starts the head deadline when the connection starts waiting on the
+    // client for one, and clears it once the head is in. Bytes arriving
+    // in between leave it as is.
+    fn update_deadline(&mut self, event_loop: &mut EventLoop<Server<S>>, token: mio::Token) {
+        let conn = &mut self.connections[token];
+
+        match (conn.waiting_for_head(), conn.deadline.is_some()) {
+            (true, false) => {
+                conn.deadline = Some(event_loop.timeout_ms(Timer::Head(token), HEAD_TIMEOUT_MS).unwrap());
+                self.stats.incomplete += 1;
+            }
+            (false, true) => {
+                event_loop.clear_timeout(conn.deadline.take().unwrap());
+                self.stats.incomplete -= 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn remove(&mut self, event_loop: &mut EventLoop<Server<S>>, token: mio::Token) {
+        // The token may be reused by the next connection, its deadline
+        // must not fire for it
+        if let Some(deadline) = self.connections[token].deadline.take() {
+            event_loop.clear_timeout(deadline);
+            self.stats.incomplete -= 1;
+        }
+
+        self.connections.remove(token);
+    }
+
+    // Answers the complete requests read so far
+    fn process(&mut self, token: mio::Token) {
+        let conn = &mut self.connections[token];
+
+        while !conn.closing && conn.out.len() < MAX_OUT {
+            let (request, len) = match request::parse(&conn.buf) {
+                Ok(Some(parsed)) => parsed,
+                Ok(None) => return,
+                Err(reason) => {
+                    println!("bad request; token={:?}; reason={}", token, reason);
+                    Response::text(400, reason).encode(false, &mut conn.out);
+                    conn.closing = true;
+                    return;
+                }
+            };
+
+            conn.buf.drain(..len);
+
+            let response = self.router.dispatch(&mut self.state, &request);
+            let keep_alive = request.keep_alive();
+
+            println!("{} {}; status={}", request.method, request.path, response.status);
+
+            response.encode(keep_alive, &mut conn.out);
+            conn.closing = !keep_alive;
+        }
+    }
+}
+
+impl<S> Handler for Server<S> {
+    type Timeout = Timer;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut EventLoop<Server<S>>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ => self.connection_ready(event_loop, token, events),
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut EventLoop<Server<S>>, timer: Timer) {
+        match timer {
+            Timer::Head(token) => {
+                // The deadline fired, there is nothing to clear
+                self.connections[token].deadline = None;
+                self.stats.incomplete -= 1;
+                self.stats.timed_out += 1;
+
+                println!("request head timed out, closing connection; token={:?}; read={}", token, self.connections[token].buf.len());
+                self.connections.remove(token);
+            }
+            Timer::Stats => {
+                println!("stats; connections={}; incomplete={}; timed out={}; refused={}",
+                         self.connections.count(), self.stats.incomplete, self.stats.timed_out, self.stats.refused);
+                event_loop.timeout_ms(Timer::Stats, STATS_MS).unwrap();
+            }
+        }
+    }
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Read, and not yet parsed into requests
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Set once the connection should be closed after writing `out`
+    closing: bool,
+    // The client is done sending requests, the ones it sent are still
+    // answered
+    eof: bool,
+    closed: bool,
+    // Set while waiting on the client for a request head
+    deadline: Option<mio::Timeout>,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            buf: vec![],
+            out: vec![],
+            closing: false,
+            eof: false,
+            closed: false,
+            deadline: None,
+        }
+    }
+
+    fn read(&mut self) {
+        let mut chunk = [0; 4_096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.eof = true;
+                    return;
+                }
+                Ok(Some(n)) => self.buf.extend(&chunk[..n]),
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        // At the end of the stream, once the output is written: what is
+        // left to read isn't a complete request, or it would have been
+        // answered
+        self.closed || (self.closing || self.eof) && self.out.is_empty()
+    }
+
+    // Whether the server is waiting on the client for a request head: it
+    // isn't while the previous response is being written, only once the
+    // ball is in the client's court
+    fn waiting_for_head(&self) -> bool {
+        !self.closing && !self.eof && self.out.is_empty() && !request::has_head(&self.buf)
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        let mut interest = mio::EventSet::none();
+
+        if !self.closing && !self.eof && self.out.len() < MAX_OUT {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.out.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        interest
+    }
+}
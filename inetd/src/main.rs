@@ -0,0 +1,333 @@
+extern crate mio;
+extern crate bytes;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::env;
+
+// Echo data is buffered up to this amount before the server stops reading
+// and waits for the client to catch up.
+const MAX_BUFFERED: usize = 4_096;
+
+const QUOTES: &[&str] = &[
+    "The best way to predict the future is to invent it. -- Alan Kay",
+    "Simplicity is prerequisite for reliability. -- Edsger W. Dijkstra",
+    "Premature optimization is the root of all evil. -- Donald Knuth",
+    "Talk is cheap. Show me the code. -- Linus Torvalds",
+];
+
+/// The protocols served. Each listener is bound to exactly one of them and
+/// every connection it accepts behaves accordingly.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Service {
+    // RFC 862: send back everything received
+    Echo,
+    // RFC 863: throw away everything received
+    Discard,
+    // RFC 867: send the date and time as text, then close
+    Daytime,
+    // RFC 865: send a quote, then close
+    Qotd,
+    // RFC 864: send a rotating character pattern until the client goes away
+    Chargen,
+}
+
+impl Service {
+    fn port(&self) -> u16 {
+        match *self {
+            Service::Echo => 7,
+            Service::Discard => 9,
+            Service::Daytime => 13,
+            Service::Qotd => 17,
+            Service::Chargen => 19,
+        }
+    }
+
+    // Services that don't care what the client sends respond immediately
+    // with a single message and close the connection.
+    fn greeting(&self, conn_count: usize) -> Option<Vec<u8>> {
+        match *self {
+            Service::Daytime => Some(daytime().into_bytes()),
+            Service::Qotd => Some(format!("{}\r\n", QUOTES[conn_count % QUOTES.len()]).into_bytes()),
+            _ => None,
+        }
+    }
+}
+
+struct Listener {
+    socket: TcpListener,
+    service: Service,
+}
+
+struct Inetd {
+    // The service registry. A listener's token is its index in this Vec,
+    // which is how a readiness event is mapped back to the service to run.
+    listeners: Vec<Listener>,
+    connections: Slab<Connection>,
+    accepted: usize,
+}
+
+impl Inetd {
+    fn new(listeners: Vec<Listener>) -> Inetd {
+        // Tokens `0..listeners.len()` are reserved for the listeners.
+        // Connections use the tokens after that.
+        let slab = Slab::new_starting_at(mio::Token(listeners.len()), 4096);
+
+        Inetd {
+            listeners: listeners,
+            connections: slab,
+            accepted: 0,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Inetd>, token: mio::Token) {
+        let service = self.listeners[token.as_usize()].service;
+
+        let socket = match self.listeners[token.as_usize()].socket.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => {
+                println!("the server socket wasn't actually ready");
+                return;
+            }
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                return;
+            }
+        };
+
+        println!("accepted a new client socket; service={:?}", service);
+
+        self.accepted += 1;
+        let conn = Connection::new(socket, service, self.accepted);
+
+        let token = match self.connections.insert(conn) {
+            Ok(token) => token,
+            Err(_) => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        let conn = &self.connections[token];
+
+        event_loop.register_opt(
+            &conn.socket,
+            token,
+            conn.interest(),
+            mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+    }
+}
+
+impl mio::Handler for Inetd {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Inetd>, token: mio::Token, events: mio::EventSet) {
+        if token.as_usize() < self.listeners.len() {
+            assert!(events.is_readable());
+            self.accept(event_loop, token);
+            return;
+        }
+
+        self.connections[token].ready(event_loop, token, events);
+
+        // If handling the event resulted in a closed socket, then remove the
+        // socket from the Slab. This will result in all resources being
+        // freed.
+        if self.connections[token].closed {
+            let _ = self.connections.remove(token);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    service: Service,
+    // Data waiting to be written to the client
+    buf: Vec<u8>,
+    // Set when the connection should be closed once `buf` is flushed
+    close_after_write: bool,
+    // Position in the chargen pattern of the next line
+    chargen_pos: usize,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, service: Service, count: usize) -> Connection {
+        let (buf, close_after_write) = match service.greeting(count) {
+            Some(buf) => (buf, true),
+            None => (vec![], false),
+        };
+
+        Connection {
+            socket: socket,
+            service: service,
+            buf: buf,
+            close_after_write: close_after_write,
+            chargen_pos: 0,
+            closed: false,
+        }
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Inetd>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            self.read();
+        }
+
+        if events.is_writable() && !self.closed {
+            if self.service == Service::Chargen && self.buf.is_empty() {
+                self.generate();
+            }
+
+            self.write();
+        }
+
+        if !self.closed {
+            event_loop.reregister(&self.socket, token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn read(&mut self) {
+        let mut buf = [0; 1024];
+
+        // The socket is registered as edge triggered, keep reading until
+        // the socket is drained or there is no room left to buffer data.
+        while self.buf.len() < MAX_BUFFERED {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    // Chargen and discard ignore anything the client sends,
+                    // they only read to notice when the client hangs up.
+                    if self.service == Service::Echo {
+                        self.buf.extend(&buf[..n]);
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.buf.is_empty() {
+            match self.socket.try_write(&self.buf) {
+                Ok(Some(n)) => {
+                    self.buf.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        if self.close_after_write {
+            self.closed = true;
+        }
+    }
+
+    // Fills the write buffer with the next lines of the chargen pattern: 72
+    // printable ASCII characters per line, each line starting one character
+    // further along than the one before it.
+    fn generate(&mut self) {
+        for _ in 0..32 {
+            for i in 0..72 {
+                self.buf.push(b' ' + ((self.chargen_pos + i) % 95) as u8);
+            }
+
+            self.buf.extend(b"\r\n");
+            self.chargen_pos = (self.chargen_pos + 1) % 95;
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        let mut interest = mio::EventSet::none();
+
+        // Daytime and QOTD don't read at all, they write and close
+        if !self.close_after_write && self.buf.len() < MAX_BUFFERED {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.buf.is_empty() || self.service == Service::Chargen {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        interest
+    }
+}
+
+// Formats the current UTC time, e.g. "Tuesday, February 22, 1982 17:37:43-UTC".
+// RFC 867 doesn't mandate a format, this is the one used by the RFC's own
+// example.
+fn daytime() -> String {
+    const DAYS: [&str; 7] = ["Thursday", "Friday", "Saturday", "Sunday", "Monday", "Tuesday", "Wednesday"];
+    const MONTHS: [&str; 12] = [
+        "January", "February", "March", "April", "May", "June", "July",
+        "August", "September", "October", "November", "December"];
+
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+
+    // Converts days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{}, {} {}, {} {:02}:{:02}:{:02}-UTC\r\n",
+            DAYS[(days % 7) as usize], MONTHS[month as usize - 1], day, year,
+            rem / 3_600, rem % 3_600 / 60, rem % 60)
+}
+
+fn main() {
+    // The classic ports are all below 1024 and require root. By default
+    // they are offset, e.g. echo is served on port 10007.
+    let offset: u16 = env::args().nth(1)
+        .map(|s| s.parse().unwrap())
+        .unwrap_or(10_000);
+
+    let services = [Service::Echo, Service::Discard, Service::Daytime, Service::Qotd, Service::Chargen];
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    let mut listeners = vec![];
+
+    for (i, service) in services.iter().enumerate() {
+        let address: SocketAddr = format!("0.0.0.0:{}", offset + service.port()).parse().unwrap();
+        let socket = TcpListener::bind(&address).unwrap();
+
+        event_loop.register(&socket, mio::Token(i)).unwrap();
+
+        println!("serving {:?}; port={}", service, address.port());
+
+        listeners.push(Listener {
+            socket: socket,
+            service: *service,
+        });
+    }
+
+    let mut inetd = Inetd::new(listeners);
+
+    println!("running inetd");
+    event_loop.run(&mut inetd).unwrap();
+}
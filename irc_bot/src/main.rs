@@ -0,0 +1,333 @@
+extern crate mio;
+extern crate bytes;
+extern crate reconnect;
+
+use reconnect::{Backoff, Client, Event};
+use std::time::{Duration, Instant};
+use std::env;
+
+const CLIENT: mio::Token = mio::Token(0);
+
+// IRC limits messages to 512 bytes including the trailing CRLF
+const MAX_LINE: usize = 512;
+
+// How long to wait before reconnecting after the connection is lost, the
+// first time, and at most. Servers don't like clients that reconnect too
+// eagerly, and may ban them for a while.
+const INITIAL_BACKOFF_MS: u64 = 1_000;
+const MAX_BACKOFF_MS: u64 = 300_000;
+
+// Replies kept while disconnected, to send once back on the channel
+const MAX_PENDING: usize = 100;
+
+// How often the connection is checked for activity
+const KEEPALIVE_MS: u64 = 30_000;
+
+// If nothing is heard from the server for this long, the bot sends its own
+// PING. If that goes unanswered for another interval, the connection is
+// considered dead.
+const IDLE_SECS: u64 = 60;
+
+#[derive(Debug, Clone)]
+enum Timeout {
+    Reconnect,
+    Keepalive,
+}
+
+struct Config {
+    server: String,
+    nick: String,
+    channel: String,
+}
+
+struct Bot {
+    config: Config,
+    // The nick currently in use, which may differ from the configured one if
+    // the server reported it as taken.
+    nick: String,
+    client: Client<Timeout>,
+    lines: LineCodec,
+    last_read: Instant,
+    ping_sent: bool,
+}
+
+impl Bot {
+    fn new(config: Config) -> Bot {
+        let nick = config.nick.clone();
+        let backoff = Backoff::new(INITIAL_BACKOFF_MS, MAX_BACKOFF_MS);
+        let client = Client::new(&config.server, CLIENT, Timeout::Reconnect, backoff, MAX_PENDING);
+
+        Bot {
+            config: config,
+            nick: nick,
+            client: client,
+            lines: LineCodec::new(),
+            last_read: Instant::now(),
+            ping_sent: false,
+        }
+    }
+
+    // A new connection starts from scratch, registering again
+    fn connected(&mut self) {
+        self.lines = LineCodec::new();
+        self.last_read = Instant::now();
+        self.ping_sent = false;
+
+        self.nick = self.config.nick.clone();
+        let nick = format!("NICK {}", self.nick);
+        let user = format!("USER {} 0 * :mio example bot", self.nick);
+        self.send(&nick);
+        self.send(&user);
+    }
+
+    // Sends a line on the current connection
+    fn send(&mut self, line: &str) {
+        println!("-> {}", line);
+
+        let mut buf = vec![];
+        LineCodec::encode(line, &mut buf);
+        self.client.send(&buf);
+    }
+
+    // Sends a line once registered, even if it takes reconnecting
+    fn queue(&mut self, line: &str) {
+        if !self.client.is_connected() {
+            println!("queued; line={}", line);
+        }
+
+        let mut buf = vec![];
+        LineCodec::encode(line, &mut buf);
+        self.client.queue(buf);
+    }
+
+    fn keepalive(&mut self, event_loop: &mut mio::EventLoop<Bot>) {
+        event_loop.timeout_ms(Timeout::Keepalive, KEEPALIVE_MS).unwrap();
+
+        if !self.client.is_connected() {
+            return;
+        }
+
+        if self.last_read.elapsed() < Duration::from_secs(IDLE_SECS) {
+            return;
+        }
+
+        if self.ping_sent {
+            println!("server stopped responding");
+            self.client.disconnect(event_loop);
+            return;
+        }
+
+        self.ping_sent = true;
+        self.send("PING :keepalive");
+        self.client.flush(event_loop);
+    }
+
+    // Reacts to a single line received from the server.
+    fn handle(&mut self, line: &str) {
+        let msg = Message::parse(line);
+
+        match msg.command {
+            "PING" => {
+                self.send(&format!("PONG :{}", msg.trailing()));
+            }
+            // RPL_WELCOME, registration is complete
+            "001" => {
+                println!("registered; nick={}", self.nick);
+                let join = format!("JOIN {}", self.config.channel);
+                self.send(&join);
+
+                // Replies that couldn't be sent before the connection was
+                // lost go out now, after the JOIN.
+                self.client.replay();
+            }
+            // ERR_NICKNAMEINUSE
+            "433" => {
+                self.nick.push('_');
+                let nick = format!("NICK {}", self.nick);
+                self.send(&nick);
+            }
+            "PRIVMSG" => {
+                let target = msg.params.first().cloned().unwrap_or("");
+                let text = msg.trailing();
+                let sender = msg.nick();
+
+                // Respond to messages addressed to the bot, either on the
+                // channel ("nick: hello") or privately.
+                let mention = format!("{}:", self.nick);
+
+                if target == self.nick {
+                    self.queue(&format!("PRIVMSG {} :{}", sender, text));
+                } else if text.starts_with(&mention) {
+                    let text = text[mention.len()..].trim();
+                    self.queue(&format!("PRIVMSG {} :{}: {}", target, sender, text));
+                }
+            }
+            "ERROR" => {
+                println!("server error; msg={}", msg.trailing());
+            }
+            _ => {}
+        }
+    }
+
+    fn socket_ready(&mut self, event_loop: &mut mio::EventLoop<Bot>, events: mio::EventSet) {
+        let mut buf = vec![];
+
+        if let Some(Event::Connected) = self.client.ready(event_loop, events, &mut buf) {
+            self.connected();
+        }
+
+        if !buf.is_empty() {
+            self.last_read = Instant::now();
+            self.ping_sent = false;
+        }
+
+        let mut lines = vec![];
+        let ok = self.lines.decode(&buf, &mut lines);
+
+        // Lines read before the connection was lost are still handled,
+        // replies to them are queued for the next one.
+        for line in lines {
+            self.handle(&line);
+        }
+
+        if !ok {
+            println!("line too long");
+            self.client.disconnect(event_loop);
+            return;
+        }
+
+        // Handling lines may have queued more data to write
+        self.client.flush(event_loop);
+    }
+}
+
+impl mio::Handler for Bot {
+    type Timeout = Timeout;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Bot>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, CLIENT);
+        self.socket_ready(event_loop, events);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Bot>, timeout: Timeout) {
+        match timeout {
+            Timeout::Reconnect => self.client.connect(event_loop),
+            Timeout::Keepalive => self.keepalive(event_loop),
+        }
+    }
+}
+
+/*
+ *
+ * ===== Line codec =====
+ *
+ */
+
+// Splits a byte stream into CRLF (or bare LF) terminated lines. Bytes
+// belonging to an incomplete line are kept until the rest arrives.
+struct LineCodec {
+    buf: Vec<u8>,
+}
+
+impl LineCodec {
+    fn new() -> LineCodec {
+        LineCodec { buf: Vec::with_capacity(MAX_LINE) }
+    }
+
+    // Returns false if a line exceeds the maximum length.
+    fn decode(&mut self, data: &[u8], lines: &mut Vec<String>) -> bool {
+        self.buf.extend(data);
+
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..pos + 1).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            println!("<- {}", line);
+            lines.push(line.to_string());
+        }
+
+        self.buf.len() <= MAX_LINE
+    }
+
+    fn encode(line: &str, dst: &mut Vec<u8>) {
+        dst.extend(line.as_bytes());
+        dst.extend(b"\r\n");
+    }
+}
+
+/*
+ *
+ * ===== IRC messages =====
+ *
+ */
+
+// A parsed IRC message:
+//
+// [":" prefix " "] command {" " param} [" :" trailing]
+struct Message<'a> {
+    prefix: Option<&'a str>,
+    command: &'a str,
+    params: Vec<&'a str>,
+}
+
+impl<'a> Message<'a> {
+    fn parse(line: &'a str) -> Message<'a> {
+        let mut rest = line;
+        let mut prefix = None;
+
+        if rest.starts_with(':') {
+            let end = rest.find(' ').unwrap_or(rest.len());
+            prefix = Some(&rest[1..end]);
+            rest = rest[end..].trim_start();
+        }
+
+        let (head, trailing) = match rest.find(" :") {
+            Some(pos) => (&rest[..pos], Some(&rest[pos + 2..])),
+            None => (rest, None),
+        };
+
+        let mut parts = head.split(' ').filter(|s| !s.is_empty());
+        let command = parts.next().unwrap_or("");
+        let mut params: Vec<&str> = parts.collect();
+
+        if let Some(trailing) = trailing {
+            params.push(trailing);
+        }
+
+        Message {
+            prefix: prefix,
+            command: command,
+            params: params,
+        }
+    }
+
+    fn trailing(&self) -> &'a str {
+        self.params.last().cloned().unwrap_or("")
+    }
+
+    // The nick of the sender, taken from a "nick!user@host" prefix
+    fn nick(&self) -> &'a str {
+        let prefix = self.prefix.unwrap_or("");
+        prefix.split('!').next().unwrap_or(prefix)
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let config = Config {
+        server: args.next().unwrap_or("irc.libera.chat:6667".to_string()),
+        nick: args.next().unwrap_or("miobot".to_string()),
+        channel: args.next().unwrap_or("#mio-examples".to_string()),
+    };
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    let mut bot = Bot::new(config);
+
+    bot.client.connect(&mut event_loop);
+    event_loop.timeout_ms(Timeout::Keepalive, KEEPALIVE_MS).unwrap();
+
+    event_loop.run(&mut bot).unwrap();
+}
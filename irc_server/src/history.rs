@@ -0,0 +1,160 @@
+// The messages sent to every channel, appended to a log file per channel,
+// and read back to replay the last ones to clients joining the channel.
+//
+// The log holds the lines as they were sent to the members, CRLF and all,
+// so a replay is a copy of the end of the file. It is read a chunk at a
+// time, as the client's socket takes it, rather than all at once, so a
+// long history doesn't hold up the event loop, or sit in memory.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+const CHUNK: usize = 4_096;
+
+pub struct History {
+    dir: PathBuf,
+    // Open for appending, for channels with members
+    logs: HashMap<String, File>,
+}
+
+// The part of a channel's log still to be sent to a client
+#[derive(Debug)]
+pub struct Replay {
+    file: File,
+    remaining: u64,
+}
+
+impl History {
+    pub fn new(dir: PathBuf) -> io::Result<History> {
+        fs::create_dir_all(&dir)?;
+
+        Ok(History {
+            dir: dir,
+            logs: HashMap::new(),
+        })
+    }
+
+    // Appends a line, without its CRLF, to the channel's log
+    pub fn append(&mut self, channel: &str, line: &str) -> io::Result<()> {
+        if !self.logs.contains_key(channel) {
+            let file = OpenOptions::new().create(true).append(true).open(self.path(channel))?;
+            self.logs.insert(channel.to_string(), file);
+        }
+
+        let log = self.logs.get_mut(channel).unwrap();
+
+        log.write_all(format!("{}\r\n", line).as_bytes())
+    }
+
+    // Closes the channel's log, once the channel is gone. The file stays.
+    pub fn close(&mut self, channel: &str) {
+        self.logs.remove(channel);
+    }
+
+    // The last `lines` lines of the channel's log, and how many there are.
+    // Lines appended from now on aren't part of it.
+    pub fn replay(&self, channel: &str, lines: usize) -> io::Result<Option<(Replay, usize)>> {
+        let mut file = match File::open(self.path(channel)) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let end = file.seek(SeekFrom::End(0))?;
+        let (start, count) = tail(&mut file, end, lines)?;
+
+        if count == 0 {
+            return Ok(None);
+        }
+
+        file.seek(SeekFrom::Start(start))?;
+
+        let replay = Replay {
+            file: file,
+            remaining: end - start,
+        };
+
+        Ok(Some((replay, count)))
+    }
+
+    // Channel names may hold characters that don't belong in a file name,
+    // anything but letters, digits, `-` and `_` is escaped like in a URL.
+    fn path(&self, channel: &str) -> PathBuf {
+        let mut name = String::new();
+
+        for b in channel.bytes() {
+            if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+                name.push(b as char);
+            } else {
+                name.push_str(&format!("%{:02X}", b));
+            }
+        }
+
+        name.push_str(".log");
+        self.dir.join(name)
+    }
+}
+
+impl Replay {
+    // Reads the next chunk onto `out`. Returns true once it is all read.
+    pub fn read(&mut self, out: &mut Vec<u8>) -> bool {
+        let mut chunk = [0; CHUNK];
+        let len = std::cmp::min(self.remaining, CHUNK as u64) as usize;
+
+        match self.file.read(&mut chunk[..len]) {
+            Ok(0) => self.remaining = 0,
+            Ok(n) => {
+                out.extend(&chunk[..n]);
+                self.remaining -= n as u64;
+            }
+            Err(e) => {
+                println!("failed to read history, cutting the replay short; err={:?}", e);
+                self.remaining = 0;
+            }
+        }
+
+        self.remaining == 0
+    }
+}
+
+// Finds where the last `lines` lines start, by reading the file backwards a
+// chunk at a time. Returns the offset, and the number of lines found, fewer
+// if the file doesn't have that many.
+fn tail(file: &mut File, end: u64, lines: usize) -> io::Result<(u64, usize)> {
+    let mut chunk = [0; CHUNK];
+    let mut pos = end;
+    let mut newlines = 0;
+
+    while pos > 0 {
+        let len = std::cmp::min(pos, CHUNK as u64) as usize;
+        pos -= len as u64;
+
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut chunk[..len])?;
+
+        for i in (0..len).rev() {
+            if chunk[i] != b'\n' {
+                continue;
+            }
+
+            // The newline ending the last line doesn't start one
+            if pos + i as u64 + 1 == end {
+                continue;
+            }
+
+            newlines += 1;
+
+            if newlines == lines {
+                return Ok((pos + i as u64 + 1, lines));
+            }
+        }
+    }
+
+    // The whole file. A file with anything in it has one more line than it
+    // has newlines in between.
+    let count = if end > 0 { newlines + 1 } else { 0 };
+
+    Ok((0, std::cmp::min(count, lines)))
+}
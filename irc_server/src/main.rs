@@ -0,0 +1,691 @@
+extern crate mio;
+extern crate bytes;
+
+mod history;
+
+use history::{History, Replay};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::{env, mem};
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Used as the prefix of messages originating from the server itself
+const SERVER_NAME: &str = "mio.irc";
+
+// IRC limits messages to 512 bytes including the trailing CRLF
+const MAX_LINE: usize = 512;
+
+// A client that doesn't read fast enough is disconnected once this much
+// output is queued for it, rather than letting its queue grow forever.
+const MAX_QUEUED: usize = 64 * 1_024;
+
+// The number of messages replayed to a client joining a channel
+const REPLAY_LINES: usize = 100;
+
+// A replay is read from the log only while the client has less than this
+// much output queued
+const REPLAY_LOW_WATER: usize = 16 * 1_024;
+
+struct Irc {
+    server: TcpListener,
+    clients: Slab<Client>,
+    // Lowercased nick -> client using it
+    nicks: HashMap<String, mio::Token>,
+    // Channel name -> members. A channel exists as long as it has members.
+    channels: HashMap<String, HashSet<mio::Token>>,
+    history: History,
+    // Clients that had output queued, or were closed, while handling the
+    // current event. They are reregistered (or removed) once it is done.
+    dirty: Vec<mio::Token>,
+}
+
+impl Irc {
+    fn new(server: TcpListener, history: History) -> Irc {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Irc {
+            server: server,
+            clients: slab,
+            nicks: HashMap::new(),
+            channels: HashMap::new(),
+            history: history,
+            dirty: vec![],
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Irc>) {
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => {
+                println!("the server socket wasn't actually ready");
+                return;
+            }
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        let host = match socket.peer_addr() {
+            Ok(addr) => addr.ip().to_string(),
+            Err(_) => "unknown".to_string(),
+        };
+
+        println!("accepted a new client socket; host={}", host);
+
+        let token = match self.clients.insert_with(|token| Client::new(socket, token, host)) {
+            Some(token) => token,
+            None => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        event_loop.register_opt(
+            &self.clients[token].socket,
+            token,
+            mio::EventSet::readable(),
+            mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+    }
+
+    fn client_ready(&mut self, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            match self.clients[token].read() {
+                Some(lines) => {
+                    for line in lines {
+                        // Anything sent after QUIT is ignored
+                        if self.clients[token].quit {
+                            break;
+                        }
+
+                        self.handle(token, &line);
+                    }
+                }
+                None => self.clients[token].closed = true,
+            }
+        }
+
+        if events.is_writable() {
+            self.clients[token].write();
+        }
+
+        self.dirty.push(token);
+    }
+
+    // Reregisters every client touched while handling an event, and
+    // removes the ones that are done.
+    fn flush(&mut self, event_loop: &mut mio::EventLoop<Irc>) {
+        while let Some(token) = self.dirty.pop() {
+            let (closed, overflow) = match self.clients.get(token) {
+                Some(client) => (client.closed, client.queued() > MAX_QUEUED),
+                None => continue,
+            };
+
+            if overflow && !closed {
+                println!("client is not keeping up, disconnecting; token={:?}", token);
+                self.clients[token].out.clear();
+                self.clients[token].replays.clear();
+                self.clients[token].closed = true;
+            }
+
+            if self.clients[token].closed {
+                self.part_all(token, "Connection closed");
+                let _ = self.clients.remove(token);
+                continue;
+            }
+
+            let client = &mut self.clients[token];
+            client.fill();
+
+            event_loop.reregister(&client.socket, token, client.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    // Reacts to a single line received from a client.
+    fn handle(&mut self, token: mio::Token, line: &str) {
+        let msg = Message::parse(line);
+        let command = msg.command.to_uppercase();
+
+        println!("received command; token={:?}; line={:?}", token, line);
+
+        if !self.clients[token].registered {
+            match &command[..] {
+                "NICK" | "USER" | "QUIT" | "PING" | "PONG" => {}
+                "" => return,
+                _ => return self.reply(token, "451", ":You have not registered"),
+            }
+        }
+
+        match &command[..] {
+            "NICK" => self.nick(token, &msg),
+            "USER" => self.user(token, &msg),
+            "JOIN" => self.join(token, &msg),
+            "PRIVMSG" => self.privmsg(token, &msg),
+            "QUIT" => {
+                let reason = format!("Quit: {}", msg.params.first().cloned().unwrap_or("leaving"));
+                self.part_all(token, &reason);
+                self.send(token, "ERROR :Closing link");
+                self.clients[token].quit = true;
+            }
+            "PING" => {
+                let param = msg.params.first().cloned().unwrap_or(SERVER_NAME);
+                self.send(token, &format!(":{} PONG {} :{}", SERVER_NAME, SERVER_NAME, param));
+            }
+            "PONG" | "" => {}
+            _ => self.reply(token, "421", &format!("{} :Unknown command", msg.command)),
+        }
+    }
+
+    fn nick(&mut self, token: mio::Token, msg: &Message) {
+        let nick = match msg.params.first() {
+            Some(nick) => *nick,
+            None => return self.reply(token, "431", ":No nickname given"),
+        };
+
+        if !is_valid_nick(nick) {
+            return self.reply(token, "432", &format!("{} :Erroneous nickname", nick));
+        }
+
+        // Nicks are case insensitive. A client may change the case of its
+        // own nick, but not take one that another client is using.
+        let key = nick.to_lowercase();
+
+        if let Some(&owner) = self.nicks.get(&key) {
+            if owner != token {
+                return self.reply(token, "433", &format!("{} :Nickname is already in use", nick));
+            }
+        }
+
+        if let Some(old) = self.clients[token].nick.take() {
+            self.nicks.remove(&old.to_lowercase());
+
+            // Everyone who can see the client is told about the change
+            if self.clients[token].registered {
+                let prefix = format!("{}!{}@{}", old, self.clients[token].user, self.clients[token].host);
+                let line = format!(":{} NICK :{}", prefix, nick);
+
+                for peer in self.peers(token) {
+                    self.send(peer, &line);
+                }
+
+                self.send(token, &line);
+            }
+        }
+
+        self.nicks.insert(key, token);
+        self.clients[token].nick = Some(nick.to_string());
+        self.try_register(token);
+    }
+
+    fn user(&mut self, token: mio::Token, msg: &Message) {
+        if self.clients[token].registered {
+            return self.reply(token, "462", ":You may not reregister");
+        }
+
+        // USER <username> <mode> <unused> :<realname>
+        if msg.params.len() < 4 {
+            return self.reply(token, "461", "USER :Not enough parameters");
+        }
+
+        self.clients[token].user = msg.params[0].to_string();
+        self.try_register(token);
+    }
+
+    // Registration completes once both NICK and USER have been received, in
+    // either order.
+    fn try_register(&mut self, token: mio::Token) {
+        {
+            let client = &mut self.clients[token];
+
+            if client.registered || client.nick.is_none() || client.user.is_empty() {
+                return;
+            }
+
+            client.registered = true;
+        }
+
+        let prefix = self.clients[token].prefix();
+
+        println!("client registered; token={:?}; prefix={}", token, prefix);
+
+        self.reply(token, "001", &format!(":Welcome to the mio IRC server {}", prefix));
+        self.reply(token, "422", ":MOTD File is missing");
+    }
+
+    fn join(&mut self, token: mio::Token, msg: &Message) {
+        let names = match msg.params.first() {
+            Some(names) => *names,
+            None => return self.reply(token, "461", "JOIN :Not enough parameters"),
+        };
+
+        // Several channels can be joined at once: JOIN #a,#b
+        for name in names.split(',') {
+            if !name.starts_with('#') || name.len() < 2 {
+                self.reply(token, "403", &format!("{} :No such channel", name));
+                continue;
+            }
+
+            if !self.clients[token].channels.insert(name.to_string()) {
+                // Already a member
+                continue;
+            }
+
+            self.channels.entry(name.to_string()).or_default().insert(token);
+
+            // The JOIN is echoed to every member, including the one joining
+            let line = format!(":{} JOIN {}", self.clients[token].prefix(), name);
+
+            for member in self.members(name) {
+                self.send(member, &line);
+            }
+
+            let nicks: Vec<String> = self.members(name).iter()
+                .filter_map(|member| self.clients[*member].nick.clone())
+                .collect();
+
+            self.reply(token, "353", &format!("= {} :{}", name, nicks.join(" ")));
+            self.reply(token, "366", &format!("{} :End of /NAMES list", name));
+
+            self.replay(token, name);
+        }
+    }
+
+    // Starts replaying the last messages of the channel to a client that
+    // just joined it. What is sent to the client from now on waits for the
+    // replay to be done, so it comes after the history.
+    fn replay(&mut self, token: mio::Token, name: &str) {
+        let (replay, count) = match self.history.replay(name, REPLAY_LINES) {
+            Ok(Some(replay)) => replay,
+            Ok(None) => return,
+            Err(e) => {
+                println!("failed to open history; channel={}; err={:?}", name, e);
+                return;
+            }
+        };
+
+        self.send(token, &format!(":{} NOTICE {} :Replaying the last {} messages", SERVER_NAME, name, count));
+
+        let client = &mut self.clients[token];
+
+        client.replays.push_back(Pending {
+            replay: replay,
+            then: vec![],
+        });
+
+        client.queue(&format!(":{} NOTICE {} :End of replay", SERVER_NAME, name));
+    }
+
+    fn privmsg(&mut self, token: mio::Token, msg: &Message) {
+        let target = match msg.params.first() {
+            Some(target) => *target,
+            None => return self.reply(token, "411", ":No recipient given (PRIVMSG)"),
+        };
+
+        let text = match msg.params.get(1) {
+            Some(text) => *text,
+            None => return self.reply(token, "412", ":No text to send"),
+        };
+
+        let line = format!(":{} PRIVMSG {} :{}", self.clients[token].prefix(), target, text);
+
+        if target.starts_with('#') {
+            // Only members may talk on a channel. The message is fanned out
+            // to every member but the sender.
+            if !self.clients[token].channels.contains(target) {
+                return self.reply(token, "404", &format!("{} :Cannot send to channel", target));
+            }
+
+            // The message is still delivered if it can't be kept
+            if let Err(e) = self.history.append(target, &line) {
+                println!("failed to append to history; channel={}; err={:?}", target, e);
+            }
+
+            for member in self.members(target) {
+                if member != token {
+                    self.send(member, &line);
+                }
+            }
+
+            return;
+        }
+
+        match self.nicks.get(&target.to_lowercase()).cloned() {
+            Some(peer) if self.clients[peer].registered => self.send(peer, &line),
+            _ => self.reply(token, "401", &format!("{} :No such nick/channel", target)),
+        }
+    }
+
+    // Removes the client from every channel and releases its nick. The
+    // other members of its channels are sent a QUIT. This runs both for an
+    // explicit QUIT and when the connection goes away, but only does
+    // anything the first time.
+    fn part_all(&mut self, token: mio::Token, reason: &str) {
+        let peers = self.peers(token);
+
+        let (prefix, channels) = {
+            let client = &mut self.clients[token];
+
+            // By the second time around, another client may have taken
+            // the nick.
+            if let Some(ref nick) = client.nick {
+                if self.nicks.get(&nick.to_lowercase()) == Some(&token) {
+                    self.nicks.remove(&nick.to_lowercase());
+                }
+            }
+
+            (client.prefix(), mem::take(&mut client.channels))
+        };
+
+        for name in channels {
+            let empty = match self.channels.get_mut(&name) {
+                Some(members) => {
+                    members.remove(&token);
+                    members.is_empty()
+                }
+                None => false,
+            };
+
+            if empty {
+                self.channels.remove(&name);
+                self.history.close(&name);
+            }
+        }
+
+        let line = format!(":{} QUIT :{}", prefix, reason);
+
+        for peer in peers {
+            self.send(peer, &line);
+        }
+    }
+
+    // The members of a channel
+    fn members(&self, name: &str) -> Vec<mio::Token> {
+        match self.channels.get(name) {
+            Some(members) => members.iter().cloned().collect(),
+            None => vec![],
+        }
+    }
+
+    // Every other client sharing at least one channel with the given one.
+    // A client is only listed once, no matter how many channels it shares.
+    fn peers(&self, token: mio::Token) -> HashSet<mio::Token> {
+        let mut peers = HashSet::new();
+
+        for name in &self.clients[token].channels {
+            peers.extend(self.members(name));
+        }
+
+        peers.remove(&token);
+        peers
+    }
+
+    fn send(&mut self, token: mio::Token, line: &str) {
+        self.clients[token].queue(line);
+        self.dirty.push(token);
+    }
+
+    // Sends a numeric reply. Replies are addressed to the client's nick, or
+    // `*` if it doesn't have one yet.
+    fn reply(&mut self, token: mio::Token, code: &str, text: &str) {
+        let line = {
+            let nick = self.clients[token].nick.as_ref().map(|nick| &nick[..]).unwrap_or("*");
+            format!(":{} {} {} {}", SERVER_NAME, code, nick, text)
+        };
+
+        self.send(token, &line);
+    }
+}
+
+impl mio::Handler for Irc {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Irc>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+                self.accept(event_loop);
+            }
+            _ => {
+                self.client_ready(token, events);
+                self.flush(event_loop);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Client {
+    socket: TcpStream,
+    token: mio::Token,
+    host: String,
+    // Bytes of an incomplete line
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Channel histories being sent, one after the other
+    replays: VecDeque<Pending>,
+    nick: Option<String>,
+    user: String,
+    registered: bool,
+    channels: HashSet<String>,
+    // Set once the client has sent QUIT. The connection is closed after the
+    // remaining output is flushed.
+    quit: bool,
+    closed: bool,
+}
+
+impl Client {
+    fn new(socket: TcpStream, token: mio::Token, host: String) -> Client {
+        Client {
+            socket: socket,
+            token: token,
+            host: host,
+            buf: Vec::with_capacity(MAX_LINE),
+            out: vec![],
+            replays: VecDeque::new(),
+            nick: None,
+            user: String::new(),
+            registered: false,
+            channels: HashSet::new(),
+            quit: false,
+            closed: false,
+        }
+    }
+
+    // The source of messages sent on behalf of the client: nick!user@host
+    fn prefix(&self) -> String {
+        let nick = self.nick.as_ref().map(|nick| &nick[..]).unwrap_or("*");
+        format!("{}!{}@{}", nick, self.user, self.host)
+    }
+
+    // Queues a line, after the replays in progress if there are any
+    fn queue(&mut self, line: &str) {
+        let out = match self.replays.back_mut() {
+            Some(pending) => &mut pending.then,
+            None => &mut self.out,
+        };
+
+        out.extend(line.as_bytes());
+        out.extend(b"\r\n");
+    }
+
+    // Moves replayed history onto the output, until there is enough of it
+    // queued for the socket to be busy for a while. The output held back
+    // by a replay follows it once it is done.
+    fn fill(&mut self) {
+        while self.out.len() < REPLAY_LOW_WATER {
+            let done = match self.replays.front_mut() {
+                Some(pending) => pending.replay.read(&mut self.out),
+                None => return,
+            };
+
+            if done {
+                let pending = self.replays.pop_front().unwrap();
+                self.out.extend(pending.then);
+            }
+        }
+    }
+
+    // Output waiting to be written, not counting the history still to be
+    // read from the logs
+    fn queued(&self) -> usize {
+        self.out.len() + self.replays.iter().map(|pending| pending.then.len()).sum::<usize>()
+    }
+
+    // Returns the complete lines read, or None if the connection is closed.
+    fn read(&mut self) -> Option<Vec<String>> {
+        let mut buf = [0; 4096];
+        let mut lines = vec![];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => return None,
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+                        let line: Vec<u8> = self.buf.drain(..pos + 1).collect();
+                        let line = String::from_utf8_lossy(&line);
+
+                        lines.push(line.trim_end_matches(['\r', '\n']).to_string());
+                    }
+
+                    if self.buf.len() > MAX_LINE {
+                        println!("line too long, closing connection; token={:?}", self.token);
+                        return None;
+                    }
+                }
+                Ok(None) => return Some(lines),
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        if self.quit {
+            self.closed = true;
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        let mut interest = mio::EventSet::none();
+
+        if !self.quit {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.out.is_empty() || !self.replays.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        interest
+    }
+}
+
+// A replay, and the output queued while it is in progress
+#[derive(Debug)]
+struct Pending {
+    replay: Replay,
+    then: Vec<u8>,
+}
+
+/*
+ *
+ * ===== IRC messages =====
+ *
+ */
+
+// A parsed IRC message:
+//
+// [":" prefix " "] command {" " param} [" :" trailing]
+//
+// Clients don't send a prefix, and if they do it is ignored.
+struct Message<'a> {
+    command: &'a str,
+    params: Vec<&'a str>,
+}
+
+impl<'a> Message<'a> {
+    fn parse(line: &'a str) -> Message<'a> {
+        let mut rest = line;
+
+        if rest.starts_with(':') {
+            let end = rest.find(' ').unwrap_or(rest.len());
+            rest = rest[end..].trim_start();
+        }
+
+        let (head, trailing) = match rest.find(" :") {
+            Some(pos) => (&rest[..pos], Some(&rest[pos + 2..])),
+            None => (rest, None),
+        };
+
+        let mut parts = head.split(' ').filter(|s| !s.is_empty());
+        let command = parts.next().unwrap_or("");
+        let mut params: Vec<&str> = parts.collect();
+
+        if let Some(trailing) = trailing {
+            params.push(trailing);
+        }
+
+        Message {
+            command: command,
+            params: params,
+        }
+    }
+}
+
+// Nicks start with a letter and contain letters, digits and a few special
+// characters.
+fn is_valid_nick(nick: &str) -> bool {
+    let special = |c: char| "-_[]\\`^{}|".contains(c);
+
+    nick.len() <= 16 &&
+        nick.chars().next().map(|c| c.is_ascii_alphabetic() || special(c) && c != '-').unwrap_or(false) &&
+        nick.chars().all(|c| c.is_ascii_alphanumeric() || special(c))
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:6667".to_string())
+        .parse().unwrap();
+
+    let dir = env::args().nth(2).unwrap_or("history".to_string());
+    let history = History::new(PathBuf::from(&dir)).unwrap();
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    let mut irc = Irc::new(server, history);
+
+    println!("running IRC server; addr={:?}; history={}", address, dir);
+    event_loop.run(&mut irc).unwrap();
+}
@@ -0,0 +1,467 @@
+extern crate acceptor;
+extern crate mio;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 1_024;
+
+// Longer lines are refused
+const MAX_LINE: usize = 64 * 1_024;
+
+// How long a worker has to finish a job, or TOUCH it, before the job is
+// given to another worker
+const VISIBILITY_MS: u64 = 30_000;
+
+// A job failing, or timing out, this many times is given up on
+const MAX_ATTEMPTS: u32 = 3;
+
+// Submitters queue jobs, workers take them one at a time, and the result
+// goes back to the submitter. Both roles speak the same line protocol over
+// the same port, a connection can be both.
+struct Server {
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+    jobs: HashMap<u64, Job>,
+    // Jobs waiting for a worker, oldest first
+    ready: VecDeque<u64>,
+    // Workers waiting for a job, a worker appearing once per FETCH
+    idle: VecDeque<mio::Token>,
+    next_id: u64,
+    // Connections that had output queued, or were closed, while handling the
+    // current event. They are reregistered (or removed) once it is done.
+    dirty: Vec<mio::Token>,
+}
+
+struct Job {
+    payload: String,
+    submitter: mio::Token,
+    attempts: u32,
+    lease: Option<Lease>,
+}
+
+// A job handed out to a worker
+struct Lease {
+    worker: mio::Token,
+    timeout: mio::Timeout,
+}
+
+// The timer set for a lease. The attempt tells an expired timer from one
+// that belongs to an earlier lease of the same job.
+#[derive(Clone, Copy)]
+struct Expiry {
+    id: u64,
+    attempt: u32,
+}
+
+enum Command<'a> {
+    // Submitters
+    Submit(&'a str),
+    // Workers
+    Fetch,
+    Ack(u64, &'a str),
+    Nack(u64),
+    Touch(u64),
+}
+
+fn parse(line: &str) -> Result<Command<'_>, &'static str> {
+    let mut parts = line.splitn(2, ' ');
+    let name = parts.next().unwrap_or("");
+    let rest = parts.next().unwrap_or("");
+
+    let mut args = rest.splitn(2, ' ');
+    let id = args.next().unwrap_or("").parse::<u64>();
+    let result = args.next().unwrap_or("");
+
+    match (name, id) {
+        ("SUBMIT", _) => Ok(Command::Submit(rest)),
+        ("FETCH", _) => Ok(Command::Fetch),
+        ("ACK", Ok(id)) => Ok(Command::Ack(id, result)),
+        ("NACK", Ok(id)) => Ok(Command::Nack(id)),
+        ("TOUCH", Ok(id)) => Ok(Command::Touch(id)),
+        ("ACK", _) | ("NACK", _) | ("TOUCH", _) => Err("invalid job id"),
+        _ => Err("unknown command"),
+    }
+}
+
+impl Server {
+    fn new(acceptor: Acceptor<Factory<Connection>>) -> Server {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS);
+
+        Server {
+            acceptor: acceptor,
+            connections: slab,
+            jobs: HashMap::new(),
+            ready: VecDeque::new(),
+            idle: VecDeque::new(),
+            next_id: 1,
+            dirty: vec![],
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Server>) {
+        let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+        if let Err(e) = res {
+            println!("encountered error while accepting connection; err={:?}", e);
+            event_loop.shutdown();
+        }
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            for line in self.connections[token].read() {
+                match parse(&line) {
+                    Ok(command) => self.handle(event_loop, token, command),
+                    Err(reason) => self.reply(token, &format!("ERR {}", reason)),
+                }
+            }
+        }
+
+        if events.is_writable() {
+            self.connections[token].write();
+        }
+
+        self.dirty.push(token);
+    }
+
+    fn handle(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, command: Command) {
+        match command {
+            Command::Submit(payload) => {
+                let id = self.next_id;
+                self.next_id += 1;
+
+                self.jobs.insert(id, Job {
+                    payload: payload.to_string(),
+                    submitter: token,
+                    attempts: 0,
+                    lease: None,
+                });
+
+                self.connections[token].submitted.insert(id);
+                self.reply(token, &format!("QUEUED {}", id));
+
+                self.ready.push_back(id);
+                self.dispatch(event_loop);
+            }
+            Command::Fetch => {
+                self.idle.push_back(token);
+                self.dispatch(event_loop);
+            }
+            Command::Ack(id, result) => {
+                if let Err(reason) = self.release(event_loop, token, id) {
+                    return self.reply(token, reason);
+                }
+
+                let job = self.jobs.remove(&id).unwrap();
+                self.reply(token, "OK");
+                self.finish(id, &job, &format!("DONE {} {}", id, result));
+            }
+            Command::Nack(id) => {
+                if let Err(reason) = self.release(event_loop, token, id) {
+                    return self.reply(token, reason);
+                }
+
+                self.reply(token, "OK");
+                self.retry(event_loop, id);
+            }
+            Command::Touch(id) => {
+                if let Err(reason) = self.release(event_loop, token, id) {
+                    return self.reply(token, reason);
+                }
+
+                // Leased again, to the same worker, for another full timeout
+                self.lease(event_loop, id, token);
+                self.reply(token, "OK");
+            }
+        }
+    }
+
+    // Hands out ready jobs to idle workers, as many as there are of both
+    fn dispatch(&mut self, event_loop: &mut mio::EventLoop<Server>) {
+        while !self.ready.is_empty() {
+            let worker = match self.idle.pop_front() {
+                Some(worker) => worker,
+                None => return,
+            };
+
+            // The worker may be disconnecting
+            if self.connections[worker].closed {
+                continue;
+            }
+
+            let id = self.ready.pop_front().unwrap();
+            self.jobs.get_mut(&id).unwrap().attempts += 1;
+            self.lease(event_loop, id, worker);
+
+            let line = format!("JOB {} {}", id, self.jobs[&id].payload);
+            self.reply(worker, &line);
+        }
+    }
+
+    fn lease(&mut self, event_loop: &mut mio::EventLoop<Server>, id: u64, worker: mio::Token) {
+        let job = self.jobs.get_mut(&id).unwrap();
+
+        let expiry = Expiry { id: id, attempt: job.attempts };
+        let timeout = event_loop.timeout_ms(expiry, VISIBILITY_MS).unwrap();
+
+        job.lease = Some(Lease {
+            worker: worker,
+            timeout: timeout,
+        });
+
+        self.connections[worker].leased.insert(id);
+    }
+
+    // Takes the lease back from the worker reporting on the job. Fails if
+    // the job isn't leased to it, because it has timed out and been given to
+    // someone else, for one.
+    fn release(&mut self, event_loop: &mut mio::EventLoop<Server>, worker: mio::Token, id: u64) -> Result<(), &'static str> {
+        let job = match self.jobs.get_mut(&id) {
+            Some(job) => job,
+            None => return Err("ERR unknown job"),
+        };
+
+        match job.lease {
+            Some(ref lease) if lease.worker == worker => {
+                event_loop.clear_timeout(lease.timeout);
+            }
+            _ => return Err("ERR job not leased to you"),
+        }
+
+        job.lease = None;
+        self.connections[worker].leased.remove(&id);
+
+        Ok(())
+    }
+
+    // Puts a job that didn't complete back at the front of the queue, unless
+    // it has run out of attempts
+    fn retry(&mut self, event_loop: &mut mio::EventLoop<Server>, id: u64) {
+        if self.jobs[&id].attempts >= MAX_ATTEMPTS {
+            let job = self.jobs.remove(&id).unwrap();
+            println!("job failed; id={}; attempts={}", id, job.attempts);
+            self.finish(id, &job, &format!("FAILED {} too many attempts", id));
+            return;
+        }
+
+        self.ready.push_front(id);
+        self.dispatch(event_loop);
+    }
+
+    // Tells the submitter how its job ended, if it is still there to hear it
+    fn finish(&mut self, id: u64, job: &Job, line: &str) {
+        let live = match self.connections.get_mut(job.submitter) {
+            Some(conn) => conn.submitted.remove(&id),
+            None => false,
+        };
+
+        if live {
+            self.reply(job.submitter, line);
+        }
+    }
+
+    fn expire(&mut self, event_loop: &mut mio::EventLoop<Server>, expiry: Expiry) {
+        let worker = match self.jobs.get_mut(&expiry.id) {
+            Some(job) if job.attempts == expiry.attempt => {
+                match job.lease.take() {
+                    Some(lease) => lease.worker,
+                    None => return,
+                }
+            }
+            _ => return,
+        };
+
+        println!("lease expired; id={}; worker={:?}", expiry.id, worker);
+
+        if let Some(conn) = self.connections.get_mut(worker) {
+            conn.leased.remove(&expiry.id);
+        }
+
+        self.retry(event_loop, expiry.id);
+    }
+
+    fn reply(&mut self, token: mio::Token, line: &str) {
+        let conn = &mut self.connections[token];
+
+        conn.out.extend(line.as_bytes());
+        conn.out.push(b'\n');
+
+        self.dirty.push(token);
+    }
+
+    // Reregisters every connection touched while handling an event, and
+    // removes the ones that are done.
+    fn flush(&mut self, event_loop: &mut mio::EventLoop<Server>) {
+        while let Some(token) = self.dirty.pop() {
+            let closed = match self.connections.get(token) {
+                Some(conn) => conn.closed,
+                None => continue,
+            };
+
+            if closed {
+                self.remove(event_loop, token);
+                continue;
+            }
+
+            let conn = &self.connections[token];
+
+            event_loop.reregister(&conn.socket, token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn remove(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token) {
+        let conn = self.connections.remove(token).unwrap();
+
+        // Jobs submitted on the connection still run, nobody is told how
+        // they went. The token may be reused, but the next connection's
+        // `submitted` won't have these jobs.
+        self.idle.retain(|worker| *worker != token);
+
+        // A worker going away won't finish its jobs, no need to wait for
+        // their leases to expire
+        for id in conn.leased {
+            if let Some(lease) = self.jobs.get_mut(&id).and_then(|job| job.lease.take()) {
+                event_loop.clear_timeout(lease.timeout);
+            }
+
+            println!("worker disconnected, retrying job; id={}", id);
+            self.retry(event_loop, id);
+        }
+    }
+}
+
+impl mio::Handler for Server {
+    type Timeout = Expiry;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ => self.connection_ready(event_loop, token, events),
+        }
+
+        self.flush(event_loop);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Server>, expiry: Expiry) {
+        self.expire(event_loop, expiry);
+        self.flush(event_loop);
+    }
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Bytes of an incomplete line
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Jobs submitted on this connection that haven't finished
+    submitted: HashSet<u64>,
+    // Jobs leased to this connection
+    leased: HashSet<u64>,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            buf: vec![],
+            out: vec![],
+            submitted: HashSet::new(),
+            leased: HashSet::new(),
+            closed: false,
+        }
+    }
+
+    // Returns the complete lines read
+    fn read(&mut self) -> Vec<String> {
+        let mut chunk = [0; 4_096];
+        let mut lines = vec![];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(Some(n)) => self.buf.extend(&chunk[..n]),
+                Ok(None) => break,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..pos + 1).take(pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            lines.push(line.trim_end_matches('\r').to_string());
+        }
+
+        if self.buf.len() > MAX_LINE {
+            println!("line too long, closing connection; token={:?}", self.token);
+            self.closed = true;
+        }
+
+        lines
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1).unwrap_or("0.0.0.0:9800".to_string()).parse().unwrap();
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    println!("running job queue; addr={:?}", address);
+
+    let mut server = Server::new(acceptor);
+    event_loop.run(&mut server).unwrap();
+}
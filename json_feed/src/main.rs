@@ -0,0 +1,253 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+extern crate rand;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// How often an event is generated
+const TICK_MS: u64 = 100;
+
+// Once this much output is queued for a subscriber, new events are skipped
+// for it until it catches up.
+const MAX_QUEUED: usize = 64 * 1_024;
+
+// A subscriber that has missed this many events in a row isn't coming
+// back, and is disconnected.
+const MAX_SKIPPED: usize = 100;
+
+const SYMBOLS: [&str; 4] = ["MIO", "RUST", "EPOLL", "KQUEUE"];
+
+struct Feed {
+    acceptor: Acceptor<Factory<Subscriber>>,
+    subscribers: Slab<Subscriber>,
+    // The sequence number of the last event
+    seq: u64,
+    // The current price of each symbol
+    prices: Vec<f64>,
+}
+
+impl Feed {
+    fn new(acceptor: Acceptor<Factory<Subscriber>>) -> Feed {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // subscriber connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Feed {
+            acceptor: acceptor,
+            subscribers: slab,
+            seq: 0,
+            prices: vec![100.0; SYMBOLS.len()],
+        }
+    }
+
+    // Makes up the next event: one of the prices takes a small random step.
+    fn next_event(&mut self) -> String {
+        let i = rand::random::<usize>() % SYMBOLS.len();
+        let step = (rand::random::<f64>() - 0.5) * 2.0;
+
+        self.prices[i] = (self.prices[i] + step).max(1.0);
+        self.seq += 1;
+
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let ms = time.as_secs() * 1_000 + time.subsec_nanos() as u64 / 1_000_000;
+
+        format!("{{\"seq\":{},\"time\":{},\"symbol\":\"{}\",\"price\":{:.2}}}\n",
+                self.seq, ms, SYMBOLS[i], self.prices[i])
+    }
+
+    // Queues an event for every subscriber and writes as much as each one
+    // will take.
+    fn publish(&mut self, event_loop: &mut mio::EventLoop<Feed>, event: &str) {
+        let mut closed = vec![];
+
+        for sub in self.subscribers.iter_mut() {
+            sub.push(event);
+            sub.write();
+
+            if sub.closed {
+                closed.push(sub.token);
+            } else {
+                sub.reregister(event_loop);
+            }
+        }
+
+        for token in closed {
+            let _ = self.subscribers.remove(token);
+        }
+    }
+}
+
+impl mio::Handler for Feed {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Feed>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                let (tokens, res) = self.acceptor.accept(event_loop, &mut self.subscribers);
+
+                for token in tokens {
+                    println!("subscriber connected; token={:?}; seq={}", token, self.seq);
+                }
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
+                }
+            }
+            _ => {
+                let sub = &mut self.subscribers[token];
+
+                if events.is_readable() {
+                    sub.read();
+                }
+
+                if events.is_writable() {
+                    sub.write();
+                }
+
+                if sub.closed {
+                    println!("subscriber disconnected; token={:?}", token);
+                    let _ = self.subscribers.remove(token);
+                } else {
+                    sub.reregister(event_loop);
+                }
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Feed>, _: ()) {
+        let event = self.next_event();
+        self.publish(event_loop, &event);
+
+        event_loop.timeout_ms((), TICK_MS).unwrap();
+    }
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    socket: TcpStream,
+    token: mio::Token,
+    out: Vec<u8>,
+    // Events missed in a row because the queue was full
+    skipped: usize,
+    closed: bool,
+}
+
+impl acceptor::Connection for Subscriber {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Subscriber {
+    fn new(socket: TcpStream, token: mio::Token) -> Subscriber {
+        Subscriber {
+            socket: socket,
+            token: token,
+            out: vec![],
+            skipped: 0,
+            closed: false,
+        }
+    }
+
+    // Queues an event, unless the subscriber is too far behind. Each event
+    // carries a sequence number, so a subscriber can tell from the gap how
+    // many it missed.
+    fn push(&mut self, event: &str) {
+        if self.out.len() < MAX_QUEUED {
+            self.out.extend(event.as_bytes());
+            self.skipped = 0;
+            return;
+        }
+
+        self.skipped += 1;
+
+        if self.skipped == 1 {
+            println!("subscriber is falling behind, skipping events; token={:?}", self.token);
+        }
+
+        if self.skipped >= MAX_SKIPPED {
+            println!("subscriber is not keeping up, disconnecting; token={:?}", self.token);
+            self.closed = true;
+        }
+    }
+
+    // Subscribers have nothing to say, the socket is only read to find out
+    // when the connection is closed. Anything sent is thrown away.
+    fn read(&mut self) {
+        let mut buf = [0; 1024];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn reregister(&self, event_loop: &mut mio::EventLoop<Feed>) {
+        let interest = if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        };
+
+        event_loop.reregister(&self.socket, self.token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:7000".to_string())
+        .parse().unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, Subscriber::new as Factory<Subscriber>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+    event_loop.timeout_ms((), TICK_MS).unwrap();
+
+    let mut feed = Feed::new(acceptor);
+
+    println!("running JSON feed; addr={:?}; tick={}ms", address, TICK_MS);
+    event_loop.run(&mut feed).unwrap();
+}
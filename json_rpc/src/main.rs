@@ -0,0 +1,504 @@
+extern crate mio;
+extern crate bytes;
+extern crate rustc_serialize;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use rustc_serialize::json::{Json, Object};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Requests are one per line, a batch included
+const MAX_LINE: usize = 64 * 1_024;
+
+// The longest `sleep` allowed
+const MAX_SLEEP_MS: u64 = 60_000;
+
+// Error codes defined by the JSON-RPC 2.0 spec
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+#[derive(Debug)]
+struct Error {
+    code: i64,
+    message: String,
+}
+
+impl Error {
+    fn new(code: i64, message: &str) -> Error {
+        Error {
+            code: code,
+            message: message.to_string(),
+        }
+    }
+}
+
+// What a method handler produces. Most methods have their result right
+// away, others only after some time has passed. The event loop can't
+// block, so those hand back the result along with how long to hold on to
+// it, and a timer completes the call.
+enum Reply {
+    Now(Result<Json, Error>),
+    After(u64, Result<Json, Error>),
+}
+
+// Method handlers are given the request's `params`, or `Null` if there
+// were none.
+type Method = fn(&Json) -> Reply;
+
+// A call that completes when its timer fires
+struct Deferred {
+    token: mio::Token,
+    // Tokens are reused once a connection is closed, this makes sure the
+    // response only goes to the connection that made the call.
+    conn_id: u64,
+    // The batch the call is part of, if any
+    batch: Option<u64>,
+    response: Json,
+}
+
+// The responses for a batch are sent together, as an array, once every
+// call in it has completed.
+#[derive(Debug)]
+struct Batch {
+    responses: Vec<Json>,
+    pending: usize,
+}
+
+struct Rpc {
+    server: TcpListener,
+    connections: Slab<Connection>,
+    methods: HashMap<&'static str, Method>,
+    accepted: u64,
+}
+
+impl Rpc {
+    fn new(server: TcpListener) -> Rpc {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Rpc {
+            server: server,
+            connections: slab,
+            methods: HashMap::new(),
+            accepted: 0,
+        }
+    }
+
+    fn register(&mut self, name: &'static str, method: Method) {
+        self.methods.insert(name, method);
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Rpc>) {
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => {
+                println!("the server socket wasn't actually ready");
+                return;
+            }
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        self.accepted += 1;
+        let conn = Connection::new(socket, self.accepted);
+
+        let token = match self.connections.insert(conn) {
+            Ok(token) => token,
+            Err(_) => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        event_loop.register_opt(
+            &self.connections[token].socket,
+            token,
+            mio::EventSet::readable(),
+            mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+    }
+
+    fn conn_ready(&mut self, event_loop: &mut mio::EventLoop<Rpc>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            match self.connections[token].read() {
+                Some(lines) => {
+                    for line in lines {
+                        self.handle(event_loop, token, &line);
+                    }
+                }
+                None => self.connections[token].closed = true,
+            }
+        }
+
+        self.reregister(event_loop, token);
+    }
+
+    // Flushes what it can of the connection's output, then either waits
+    // for more events or, if it is done, removes it.
+    fn reregister(&mut self, event_loop: &mut mio::EventLoop<Rpc>, token: mio::Token) {
+        let conn = &mut self.connections[token];
+
+        conn.write();
+
+        if conn.closed {
+            // Calls still waiting on a timer are dropped when it fires
+            let _ = self.connections.remove(token);
+            return;
+        }
+
+        let interest = if conn.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        };
+
+        event_loop.reregister(&conn.socket, token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    // Handles a line from the client, which is either a single request or
+    // a batch of them.
+    fn handle(&mut self, event_loop: &mut mio::EventLoop<Rpc>, token: mio::Token, line: &str) {
+        if line.trim().is_empty() {
+            return;
+        }
+
+        let requests = match Json::from_str(line) {
+            Ok(Json::Array(requests)) => requests,
+            Ok(request) => {
+                if let Some(response) = self.call(event_loop, token, &request, None) {
+                    self.connections[token].send(&response);
+                }
+
+                return;
+            }
+            Err(_) => {
+                let response = response(Json::Null, Err(Error::new(PARSE_ERROR, "Parse error")));
+                return self.connections[token].send(&response);
+            }
+        };
+
+        if requests.is_empty() {
+            let response = response(Json::Null, Err(Error::new(INVALID_REQUEST, "Invalid Request")));
+            return self.connections[token].send(&response);
+        }
+
+        let id = self.connections[token].next_batch;
+        self.connections[token].next_batch += 1;
+
+        self.connections[token].batches.insert(id, Batch {
+            responses: vec![],
+            pending: 0,
+        });
+
+        for request in &requests {
+            if let Some(response) = self.call(event_loop, token, request, Some(id)) {
+                self.connections[token].batches.get_mut(&id).unwrap().responses.push(response);
+            }
+        }
+
+        self.connections[token].complete(id);
+    }
+
+    // Dispatches a request to its method. Returns the response if there is
+    // one to send right away. There isn't if the request is a notification,
+    // or if the method completes later.
+    fn call(&mut self, event_loop: &mut mio::EventLoop<Rpc>, token: mio::Token, request: &Json, batch: Option<u64>) -> Option<Json> {
+        let (id, method, params) = match parse(request) {
+            Ok(parsed) => parsed,
+            Err(e) => return Some(response(Json::Null, Err(e))),
+        };
+
+        println!("call; method={:?}; id={:?}; token={:?}", method, id, token);
+
+        let reply = match self.methods.get(&method[..]) {
+            Some(method) => method(&params),
+            None => Reply::Now(Err(Error::new(METHOD_NOT_FOUND, "Method not found"))),
+        };
+
+        // A request without an id is a notification, it never gets a
+        // response, not even an error. Its timer isn't worth setting.
+        let id = id?;
+
+        match reply {
+            Reply::Now(result) => Some(response(id, result)),
+            Reply::After(ms, result) => {
+                let conn = &mut self.connections[token];
+
+                if let Some(batch) = batch {
+                    conn.batches.get_mut(&batch).unwrap().pending += 1;
+                }
+
+                let deferred = Deferred {
+                    token: token,
+                    conn_id: conn.id,
+                    batch: batch,
+                    response: response(id, result),
+                };
+
+                event_loop.timeout_ms(deferred, ms).unwrap();
+                None
+            }
+        }
+    }
+}
+
+impl mio::Handler for Rpc {
+    type Timeout = Deferred;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Rpc>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+                self.accept(event_loop);
+            }
+            _ => self.conn_ready(event_loop, token, events),
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Rpc>, deferred: Deferred) {
+        match self.connections.get_mut(deferred.token) {
+            Some(conn) if conn.id == deferred.conn_id => {
+                match deferred.batch {
+                    Some(batch) => {
+                        {
+                            let batch = conn.batches.get_mut(&batch).unwrap();
+                            batch.responses.push(deferred.response);
+                            batch.pending -= 1;
+                        }
+
+                        conn.complete(batch);
+                    }
+                    None => conn.send(&deferred.response),
+                }
+            }
+            // The connection that made the call is gone
+            _ => return,
+        }
+
+        self.reregister(event_loop, deferred.token);
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    // Unique for the life of the server, unlike the token
+    id: u64,
+    // Bytes of an incomplete line
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Batches with calls still in progress
+    batches: HashMap<u64, Batch>,
+    next_batch: u64,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, id: u64) -> Connection {
+        Connection {
+            socket: socket,
+            id: id,
+            buf: vec![],
+            out: vec![],
+            batches: HashMap::new(),
+            next_batch: 0,
+            closed: false,
+        }
+    }
+
+    // Returns the complete lines read, or None if the connection is closed.
+    fn read(&mut self) -> Option<Vec<String>> {
+        let mut buf = [0; 4096];
+        let mut lines = vec![];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => return None,
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+                        let line: Vec<u8> = self.buf.drain(..pos + 1).collect();
+                        lines.push(String::from_utf8_lossy(&line).into_owned());
+                    }
+
+                    if self.buf.len() > MAX_LINE {
+                        println!("line too long, closing connection; id={}", self.id);
+                        return None;
+                    }
+                }
+                Ok(None) => return Some(lines),
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    return None;
+                }
+            }
+        }
+    }
+
+    // Sends the batch's responses if none are still pending. A batch of
+    // only notifications has no responses, and nothing is sent.
+    fn complete(&mut self, id: u64) {
+        if self.batches[&id].pending > 0 {
+            return;
+        }
+
+        let batch = self.batches.remove(&id).unwrap();
+
+        if !batch.responses.is_empty() {
+            self.send(&Json::Array(batch.responses));
+        }
+    }
+
+    fn send(&mut self, response: &Json) {
+        self.out.extend(response.to_string().as_bytes());
+        self.out.push(b'\n');
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/*
+ *
+ * ===== JSON-RPC messages =====
+ *
+ */
+
+// Checks that a request is well formed and pulls out its id, method and
+// params. The id is None for a notification.
+fn parse(request: &Json) -> Result<(Option<Json>, String, Json), Error> {
+    let invalid = || Error::new(INVALID_REQUEST, "Invalid Request");
+
+    if request.find("jsonrpc").and_then(|v| v.as_string()) != Some("2.0") {
+        return Err(invalid());
+    }
+
+    let id = match request.find("id") {
+        Some(id) if id.is_string() || id.is_number() || id.is_null() => Some(id.clone()),
+        Some(_) => return Err(invalid()),
+        None => None,
+    };
+
+    let method = match request.find("method").and_then(|v| v.as_string()) {
+        Some(method) => method.to_string(),
+        None => return Err(invalid()),
+    };
+
+    // Params are passed either by position or by name
+    let params = match request.find("params") {
+        Some(params) if params.is_array() || params.is_object() => params.clone(),
+        Some(_) => return Err(invalid()),
+        None => Json::Null,
+    };
+
+    Ok((id, method, params))
+}
+
+fn response(id: Json, result: Result<Json, Error>) -> Json {
+    let mut obj = Object::new();
+
+    obj.insert("jsonrpc".to_string(), Json::String("2.0".to_string()));
+    obj.insert("id".to_string(), id);
+
+    match result {
+        Ok(value) => {
+            obj.insert("result".to_string(), value);
+        }
+        Err(e) => {
+            let mut error = Object::new();
+            error.insert("code".to_string(), Json::I64(e.code));
+            error.insert("message".to_string(), Json::String(e.message));
+
+            obj.insert("error".to_string(), Json::Object(error));
+        }
+    }
+
+    Json::Object(obj)
+}
+
+/*
+ *
+ * ===== Methods =====
+ *
+ */
+
+// Returns the params as given
+fn echo(params: &Json) -> Reply {
+    Reply::Now(Ok(params.clone()))
+}
+
+// Adds up a list of integers
+fn add(params: &Json) -> Reply {
+    let sum = params.as_array()
+        .and_then(|values| values.iter().map(|v| v.as_i64()).collect::<Option<Vec<i64>>>())
+        .map(|values| values.iter().fold(0i64, |sum, v| sum.wrapping_add(*v)));
+
+    match sum {
+        Some(sum) => Reply::Now(Ok(Json::I64(sum))),
+        None => Reply::Now(Err(Error::new(INVALID_PARAMS, "expected an array of integers"))),
+    }
+}
+
+// Responds after the given number of milliseconds, passed as `[ms]` or
+// `{"ms": ms}`. This stands in for any method that has to wait on
+// something, like a database or another service. Calls made after it
+// complete first.
+fn sleep(params: &Json) -> Reply {
+    let ms = match *params {
+        Json::Array(ref values) if values.len() == 1 => values[0].as_u64(),
+        Json::Object(_) => params.find("ms").and_then(|v| v.as_u64()),
+        _ => None,
+    };
+
+    match ms {
+        Some(ms) if ms <= MAX_SLEEP_MS => Reply::After(ms, Ok(Json::U64(ms))),
+        _ => Reply::Now(Err(Error::new(INVALID_PARAMS, "expected a number of milliseconds"))),
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:4444".to_string())
+        .parse().unwrap();
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    let mut rpc = Rpc::new(server);
+
+    rpc.register("echo", echo);
+    rpc.register("add", add);
+    rpc.register("sleep", sleep);
+
+    println!("running JSON-RPC server; addr={:?}", address);
+    event_loop.run(&mut rpc).unwrap();
+}
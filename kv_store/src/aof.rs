@@ -0,0 +1,234 @@
+// The append-only file: every change to the store, one line each,
+//
+//     SET <key> <value>
+//     DEL <key>
+//
+// in the order they were made. Replaying the file from the start rebuilds
+// the store.
+//
+// The file only grows, a key set a thousand times is in it a thousand
+// times. A rewrite replaces it with one `SET` per key, written a slice at a
+// time so the server keeps serving in between. Changes made while the
+// rewrite runs go to the old file as usual, and to a buffer appended at the
+// end of the new file, so the new file misses nothing. A key written by the
+// rewrite and changed afterwards is in the new file twice, the later line
+// winning: `SET` and `DEL` don't depend on the value they replace, so
+// replaying a change on top of a state that already has it is harmless.
+
+use std::collections::BTreeMap;
+use std::collections::Bound::{Excluded, Unbounded};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+pub enum Op<'a> {
+    Set(&'a str, &'a str),
+    Del(&'a str),
+}
+
+pub struct Aof {
+    path: PathBuf,
+    file: File,
+    // Appended but not yet written
+    buf: Vec<u8>,
+    size: u64,
+    rewrite: Option<Rewrite>,
+}
+
+struct Rewrite {
+    path: PathBuf,
+    file: File,
+    // The last key written, the next slice starts after it. `None` until
+    // the first slice.
+    cursor: Option<String>,
+    // Changes made since the rewrite started
+    diff: Vec<u8>,
+    size: u64,
+}
+
+impl Aof {
+    // Opens the file, creating it if needed, and replays it into `store`.
+    // A last line cut short by a crash in the middle of writing it is
+    // dropped.
+    pub fn open(path: &Path, store: &mut BTreeMap<String, String>) -> io::Result<Aof> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+
+        let mut data = vec![];
+        file.read_to_end(&mut data)?;
+
+        let complete = data.iter().rposition(|b| *b == b'\n').map(|pos| pos + 1).unwrap_or(0);
+
+        for line in data[..complete].split(|b| *b == b'\n') {
+            let line = String::from_utf8_lossy(line);
+
+            match parse(&line) {
+                Some(Op::Set(key, value)) => {
+                    store.insert(key.to_string(), value.to_string());
+                }
+                Some(Op::Del(key)) => {
+                    store.remove(key);
+                }
+                None if line.is_empty() => {}
+                None => println!("skipping invalid line; line={:?}", line),
+            }
+        }
+
+        if complete < data.len() {
+            println!("dropping incomplete line; bytes={}", data.len() - complete);
+            file.set_len(complete as u64)?;
+        }
+
+        Ok(Aof {
+            path: path.to_path_buf(),
+            file: file,
+            buf: vec![],
+            size: complete as u64,
+            rewrite: None,
+        })
+    }
+
+    pub fn append(&mut self, op: &Op) {
+        let start = self.buf.len();
+        encode(op, &mut self.buf);
+
+        if let Some(ref mut rewrite) = self.rewrite {
+            rewrite.diff.extend(&self.buf[start..]);
+        }
+    }
+
+    // Writes out the changes appended. They are in the page cache
+    // afterwards, safe from the process crashing, but not yet from the
+    // machine crashing: that takes `sync`.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        self.file.write_all(&self.buf)?;
+        self.size += self.buf.len() as u64;
+        self.buf.clear();
+
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_rewriting(&self) -> bool {
+        self.rewrite.is_some()
+    }
+
+    // Returns false if a rewrite is already running
+    pub fn start_rewrite(&mut self) -> io::Result<bool> {
+        if self.rewrite.is_some() {
+            return Ok(false);
+        }
+
+        let path = self.path.with_extension("rewrite");
+        let file = File::create(&path)?;
+
+        self.rewrite = Some(Rewrite {
+            path: path,
+            file: file,
+            cursor: None,
+            diff: vec![],
+            size: 0,
+        });
+
+        Ok(true)
+    }
+
+    // Writes the next `keys` keys of the store to the new file. Once every
+    // key is written, the new file replaces the old one, and true is
+    // returned.
+    pub fn rewrite_slice(&mut self, store: &BTreeMap<String, String>, keys: usize) -> io::Result<bool> {
+        let done = {
+            let rewrite = match self.rewrite {
+                Some(ref mut rewrite) => rewrite,
+                None => return Ok(true),
+            };
+
+            let slice: Vec<(&String, &String)> = match rewrite.cursor {
+                Some(ref cursor) => store.range::<String, _>((Excluded(cursor), Unbounded)).take(keys).collect(),
+                None => store.iter().take(keys).collect(),
+            };
+
+            let mut out = vec![];
+
+            for &(key, value) in &slice {
+                encode(&Op::Set(key, value), &mut out);
+            }
+
+            rewrite.file.write_all(&out)?;
+            rewrite.size += out.len() as u64;
+
+            match slice.last() {
+                Some(&(key, _)) if slice.len() == keys => {
+                    rewrite.cursor = Some(key.clone());
+                    false
+                }
+                _ => true,
+            }
+        };
+
+        if done {
+            self.finish_rewrite()?;
+        }
+
+        Ok(done)
+    }
+
+    fn finish_rewrite(&mut self) -> io::Result<()> {
+        // Changes not yet written to the old file are in the diff already,
+        // and go to the new file with it
+        self.buf.clear();
+
+        let mut rewrite = self.rewrite.take().unwrap();
+
+        rewrite.file.write_all(&rewrite.diff)?;
+        rewrite.file.sync_all()?;
+
+        // The new file has to be complete on disk before it takes the old
+        // one's place, and the rename has to be on disk before the old
+        // file's contents are gone for good
+        fs::rename(&rewrite.path, &self.path)?;
+
+        if let Some(dir) = self.path.parent() {
+            let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+            File::open(dir)?.sync_all()?;
+        }
+
+        let before = self.size;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.size = rewrite.size + rewrite.diff.len() as u64;
+
+        println!("rewrite done; before={}; after={}", before, self.size);
+
+        Ok(())
+    }
+}
+
+// Keys can't have spaces, and neither can have newlines, the protocol being
+// line based
+fn encode(op: &Op, out: &mut Vec<u8>) {
+    match *op {
+        Op::Set(key, value) => out.extend(format!("SET {} {}\n", key, value).as_bytes()),
+        Op::Del(key) => out.extend(format!("DEL {}\n", key).as_bytes()),
+    }
+}
+
+fn parse(line: &str) -> Option<Op<'_>> {
+    let mut parts = line.splitn(3, ' ');
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("SET"), Some(key), Some(value)) => Some(Op::Set(key, value)),
+        (Some("DEL"), Some(key), None) => Some(Op::Del(key)),
+        _ => None,
+    }
+}
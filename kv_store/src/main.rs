@@ -0,0 +1,281 @@
+extern crate acceptor;
+extern crate mio;
+
+mod aof;
+
+use acceptor::{Acceptor, Factory};
+use aof::{Aof, Op};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 1_024;
+
+// Longer lines are refused
+const MAX_LINE: usize = 64 * 1_024;
+
+// How often the append-only file is synced to disk. A machine crash loses
+// at most this much of the latest changes, like Redis' `everysec`.
+const SYNC_MS: u64 = 1_000;
+
+// A rewrite writes this many keys per slice, and gives the event loop back
+// in between
+const REWRITE_SLICE_KEYS: usize = 10_000;
+const REWRITE_SLICE_MS: u64 = 1;
+
+#[derive(Clone, Copy)]
+enum Timer {
+    Sync,
+    Rewrite,
+}
+
+struct KvStore {
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+    store: BTreeMap<String, String>,
+    aof: Aof,
+}
+
+impl KvStore {
+    fn new(acceptor: Acceptor<Factory<Connection>>, store: BTreeMap<String, String>, aof: Aof) -> KvStore {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS);
+
+        KvStore {
+            acceptor: acceptor,
+            connections: slab,
+            store: store,
+            aof: aof,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<KvStore>) {
+        let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+        if let Err(e) = res {
+            println!("encountered error while accepting connection; err={:?}", e);
+            event_loop.shutdown();
+        }
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<KvStore>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            for line in self.connections[token].read() {
+                let reply = self.execute(event_loop, &line);
+                self.connections[token].reply(&reply);
+            }
+
+            // The changes are in the file before they are acknowledged. One
+            // write however many commands came in.
+            self.aof.flush().unwrap();
+        }
+
+        let conn = &mut self.connections[token];
+        conn.write();
+
+        if conn.closed {
+            self.connections.remove(token);
+            return;
+        }
+
+        event_loop.reregister(&conn.socket, token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    // Runs a command and returns the reply
+    fn execute(&mut self, event_loop: &mut mio::EventLoop<KvStore>, line: &str) -> String {
+        let mut parts = line.splitn(3, ' ');
+
+        match (parts.next().unwrap_or(""), parts.next(), parts.next()) {
+            // SET <key> <value>, the value being the rest of the line
+            ("SET", Some(key), Some(value)) if !key.is_empty() => {
+                self.aof.append(&Op::Set(key, value));
+                self.store.insert(key.to_string(), value.to_string());
+                "OK".to_string()
+            }
+            ("GET", Some(key), None) => {
+                match self.store.get(key) {
+                    Some(value) => format!("VALUE {}", value),
+                    None => "NOT_FOUND".to_string(),
+                }
+            }
+            ("DEL", Some(key), None) => {
+                match self.store.remove(key) {
+                    Some(_) => {
+                        self.aof.append(&Op::Del(key));
+                        "DELETED".to_string()
+                    }
+                    None => "NOT_FOUND".to_string(),
+                }
+            }
+            // Compacts the append-only file, in the background
+            ("BGREWRITE", None, None) => {
+                if !self.aof.start_rewrite().unwrap() {
+                    return "ERR rewrite already in progress".to_string();
+                }
+
+                println!("rewrite started; keys={}; size={}", self.store.len(), self.aof.size());
+
+                event_loop.timeout_ms(Timer::Rewrite, REWRITE_SLICE_MS).unwrap();
+                "OK rewrite started".to_string()
+            }
+            ("INFO", None, None) => {
+                format!("INFO keys={} aof_bytes={} rewriting={}", self.store.len(), self.aof.size(), self.aof.is_rewriting())
+            }
+            ("SET", _, _) | ("GET", _, _) | ("DEL", _, _) | ("BGREWRITE", _, _) | ("INFO", _, _) => {
+                "ERR wrong number of arguments".to_string()
+            }
+            _ => "ERR unknown command".to_string(),
+        }
+    }
+}
+
+impl mio::Handler for KvStore {
+    type Timeout = Timer;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<KvStore>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ => self.connection_ready(event_loop, token, events),
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<KvStore>, timer: Timer) {
+        match timer {
+            Timer::Sync => {
+                self.aof.sync().unwrap();
+                event_loop.timeout_ms(Timer::Sync, SYNC_MS).unwrap();
+            }
+            Timer::Rewrite => {
+                // Not done, the next slice is scheduled: the connections
+                // that are ready get served in between
+                if !self.aof.rewrite_slice(&self.store, REWRITE_SLICE_KEYS).unwrap() {
+                    event_loop.timeout_ms(Timer::Rewrite, REWRITE_SLICE_MS).unwrap();
+                }
+            }
+        }
+    }
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Bytes of an incomplete line
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            buf: vec![],
+            out: vec![],
+            closed: false,
+        }
+    }
+
+    // Returns the complete lines read
+    fn read(&mut self) -> Vec<String> {
+        let mut chunk = [0; 4_096];
+        let mut lines = vec![];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(Some(n)) => self.buf.extend(&chunk[..n]),
+                Ok(None) => break,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..pos + 1).take(pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            lines.push(line.trim_end_matches('\r').to_string());
+        }
+
+        if self.buf.len() > MAX_LINE {
+            println!("line too long, closing connection; token={:?}", self.token);
+            self.closed = true;
+        }
+
+        lines
+    }
+
+    fn reply(&mut self, line: &str) {
+        self.out.extend(line.as_bytes());
+        self.out.push(b'\n');
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:7400".to_string()).parse().unwrap();
+    let path = args.next().unwrap_or("appendonly.aof".to_string());
+
+    let mut store = BTreeMap::new();
+    let aof = Aof::open(Path::new(&path), &mut store).unwrap();
+
+    println!("replayed append-only file; path={:?}; keys={}; size={}", path, store.len(), aof.size());
+
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+    event_loop.timeout_ms(Timer::Sync, SYNC_MS).unwrap();
+
+    println!("running kv store; addr={:?}", address);
+
+    let mut kv = KvStore::new(acceptor, store, aof);
+    event_loop.run(&mut kv).unwrap();
+}
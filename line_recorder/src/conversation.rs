@@ -0,0 +1,111 @@
+// Pairs the lines of a conversation into exchanges: a request from the
+// client, and the lines the server answered it with. Nothing in the lines
+// says where a response ends, SMTP's are several lines long, POP3's end
+// with a dot, and so on. So the pairing is a guess that holds for
+// protocols where the server answers requests in order:
+//
+// * the lines from the server are the response to the oldest request
+//   still waiting for one;
+// * a request's response is complete when the server starts answering
+//   the next request, or when the connection closes;
+// * lines from the server while no request is waiting, like a greeting,
+//   are an exchange of their own, without a request.
+//
+// This does no I/O.
+
+use rustc_serialize::json::{Json, Object, ToJson};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct Exchange {
+    pub request: Option<String>,
+    // Milliseconds since the connection was accepted, until the request
+    // was read, and until the first and last lines of the response were
+    pub at_ms: f64,
+    pub first_ms: Option<f64>,
+    pub last_ms: Option<f64>,
+    pub response: Vec<String>,
+}
+
+impl Exchange {
+    fn new(request: Option<String>, at: Duration) -> Exchange {
+        Exchange {
+            request: request,
+            at_ms: millis(at),
+            first_ms: None,
+            last_ms: None,
+            response: vec![],
+        }
+    }
+}
+
+impl ToJson for Exchange {
+    fn to_json(&self) -> Json {
+        let mut obj = Object::new();
+
+        obj.insert("request".to_string(), self.request.to_json());
+        obj.insert("at_ms".to_string(), self.at_ms.to_json());
+        obj.insert("first_ms".to_string(), self.first_ms.to_json());
+        obj.insert("last_ms".to_string(), self.last_ms.to_json());
+        obj.insert("response".to_string(), self.response.to_json());
+
+        Json::Object(obj)
+    }
+}
+
+pub struct Conversation {
+    // Requests waiting for a response, or still receiving one, oldest
+    // first
+    pending: VecDeque<Exchange>,
+}
+
+impl Conversation {
+    pub fn new() -> Conversation {
+        Conversation { pending: VecDeque::new() }
+    }
+
+    pub fn request(&mut self, line: &[u8], at: Duration) {
+        self.pending.push_back(Exchange::new(Some(text(line)), at));
+    }
+
+    // Adds a line of response, returning the exchanges it completes
+    pub fn response(&mut self, line: &[u8], at: Duration) -> Vec<Exchange> {
+        let mut done = vec![];
+
+        // The oldest request has its response: if a newer one is waiting,
+        // the line is for that one
+        while self.pending.len() > 1 && !self.pending[0].response.is_empty() {
+            done.push(self.pending.pop_front().unwrap());
+        }
+
+        if self.pending.is_empty() {
+            self.pending.push_back(Exchange::new(None, at));
+        }
+
+        let exchange = &mut self.pending[0];
+
+        if exchange.first_ms.is_none() {
+            exchange.first_ms = Some(millis(at));
+        }
+
+        exchange.last_ms = Some(millis(at));
+        exchange.response.push(text(line));
+
+        done
+    }
+
+    // The connection is closed, whatever is pending is all there will be
+    pub fn finish(&mut self) -> Vec<Exchange> {
+        self.pending.drain(..).collect()
+    }
+}
+
+// Lines are logged as text, whatever isn't UTF-8 in them replaced
+fn text(line: &[u8]) -> String {
+    String::from_utf8_lossy(line).into_owned()
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1_000.0 + duration.subsec_micros() as f64 / 1_000.0
+}
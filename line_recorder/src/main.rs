@@ -0,0 +1,455 @@
+extern crate codec;
+extern crate mio;
+extern crate rustc_serialize;
+
+mod conversation;
+
+use codec::Decoder;
+use codec::line::Line;
+use conversation::{Conversation, Exchange};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use rustc_serialize::json::ToJson;
+use std::fs::{self, File};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Instant;
+use std::{env, process};
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Tokens `1..=MAX_CONNECTIONS` are used for client connections, and the
+// connection to the upstream server for a client is its token plus
+// `MAX_CONNECTIONS`.
+const MAX_CONNECTIONS: usize = 1_024;
+
+// Reading from one side stops while this much waits to be written to the
+// other
+const MAX_BUFFERED: usize = 64 * 1_024;
+
+// Lines longer than this aren't recorded, see `Connection::decode`
+const MAX_LINE: usize = 8 * 1_024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Direction {
+    // Client to server
+    Up,
+    // Server to client
+    Down,
+}
+
+// Relays TCP connections to the server of a line based protocol, and
+// records the conversations: each request from the client, the lines the
+// server answered it with, and how long they took, as a JSON object per
+// exchange, in a file per connection. See `conversation` for how
+// requests and responses are paired.
+//
+// The bytes are relayed as they are read, the lines are decoded from a
+// copy. Neither side can tell the recorder is there, or has to be
+// changed for it.
+struct Recorder {
+    server: TcpListener,
+    upstream: SocketAddr,
+    dir: PathBuf,
+    connections: Slab<Connection>,
+    next_id: u64,
+}
+
+impl Recorder {
+    fn new(server: TcpListener, upstream: SocketAddr, dir: PathBuf) -> Recorder {
+        // Token `0` is reserved for the server socket
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS);
+
+        Recorder {
+            server: server,
+            upstream: upstream,
+            dir: dir,
+            connections: slab,
+            next_id: 1,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Recorder>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        let upstream = match TcpStream::connect(&self.upstream) {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                println!("failed to connect upstream; err={:?}", e);
+                return;
+            }
+        };
+
+        // Ids, unlike tokens, aren't reused: they name the files
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let path = self.dir.join(format!("{}.jsonl", id));
+
+        let file = match File::create(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                println!("failed to create conversation file; path={:?}; err={:?}", path, e);
+                return;
+            }
+        };
+
+        let token = match self.connections.insert_with(|token| Connection::new(socket, upstream, token, id, file)) {
+            Some(token) => token,
+            None => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        println!("recording connection; id={}; path={:?}", id, path);
+
+        let conn = &self.connections[token];
+
+        // Nothing is read from the client until the upstream connection is
+        // up, which is when it is writable
+        event_loop.register_opt(&conn.client, token, mio::EventSet::none(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+        event_loop.register_opt(&conn.upstream, upstream_token(token), mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<Recorder>, token: mio::Token, direction: Direction, events: mio::EventSet) {
+        {
+            let conn = &mut self.connections[token];
+
+            if !conn.connected {
+                if events.is_error() || events.is_hup() {
+                    println!("failed to connect upstream; id={}; err={:?}", conn.id, conn.upstream.take_socket_error());
+                    conn.closed = true;
+                } else if events.is_writable() {
+                    conn.connected = true;
+                }
+            }
+
+            if conn.connected && events.is_readable() {
+                conn.read(direction);
+            }
+        }
+
+        self.update(event_loop, token);
+    }
+
+    // Writes what it can, then reregisters both sockets of the connection,
+    // or removes it once it is done
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Recorder>, token: mio::Token) {
+        {
+            let conn = &mut self.connections[token];
+
+            if conn.connected && !conn.closed {
+                conn.write(Direction::Up);
+                conn.write(Direction::Down);
+            }
+        }
+
+        if self.connections[token].is_done() {
+            let mut conn = self.connections.remove(token).unwrap();
+            let exchanges = conn.conversation.finish();
+
+            conn.record(exchanges);
+            println!("connection closed; id={}; exchanges={}", conn.id, conn.exchanges);
+            return;
+        }
+
+        let conn = &self.connections[token];
+
+        event_loop.reregister(&conn.client, token, conn.client_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+        event_loop.reregister(&conn.upstream, upstream_token(token), conn.upstream_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+impl mio::Handler for Recorder {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Recorder>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ if token.as_usize() <= MAX_CONNECTIONS => self.connection_ready(event_loop, token, Direction::Up, events),
+            _ => {
+                let token = mio::Token(token.as_usize() - MAX_CONNECTIONS);
+
+                // The upstream socket's events may still come in after the
+                // connection is gone
+                if self.connections.contains(token) {
+                    self.connection_ready(event_loop, token, Direction::Down, events);
+                }
+            }
+        }
+    }
+}
+
+fn upstream_token(token: mio::Token) -> mio::Token {
+    mio::Token(token.as_usize() + MAX_CONNECTIONS)
+}
+
+// One direction of a connection
+struct Pipe {
+    // To be written to the other side
+    buf: Vec<u8>,
+    // A copy, for the decoder. `None` once a line was too long to be
+    // recorded.
+    lines: Option<Vec<u8>>,
+    // Set once the side it is read from has closed its end, and once that
+    // was passed on
+    eof: bool,
+    shut: bool,
+}
+
+impl Pipe {
+    fn new() -> Pipe {
+        Pipe {
+            buf: vec![],
+            lines: Some(vec![]),
+            eof: false,
+            shut: false,
+        }
+    }
+}
+
+struct Connection {
+    client: TcpStream,
+    upstream: TcpStream,
+    token: mio::Token,
+    id: u64,
+    file: Option<File>,
+    accepted: Instant,
+    connected: bool,
+    // Client to server, and server to client
+    up: Pipe,
+    down: Pipe,
+    codec: Line,
+    conversation: Conversation,
+    exchanges: u64,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(client: TcpStream, upstream: TcpStream, token: mio::Token, id: u64, file: File) -> Connection {
+        Connection {
+            client: client,
+            upstream: upstream,
+            token: token,
+            id: id,
+            file: Some(file),
+            accepted: Instant::now(),
+            connected: false,
+            up: Pipe::new(),
+            down: Pipe::new(),
+            codec: Line::new(MAX_LINE),
+            conversation: Conversation::new(),
+            exchanges: 0,
+            closed: false,
+        }
+    }
+
+    // Reads from the side `direction` starts at, then records the lines
+    // that came in
+    fn read(&mut self, direction: Direction) {
+        let mut chunk = [0; 4_096];
+
+        {
+            let (socket, pipe) = match direction {
+                Direction::Up => (&mut self.client, &mut self.up),
+                Direction::Down => (&mut self.upstream, &mut self.down),
+            };
+
+            // The socket is registered as edge triggered, drain it. Or at
+            // least until the other side has enough to write.
+            while !pipe.eof && pipe.buf.len() < MAX_BUFFERED {
+                match socket.try_read(&mut chunk) {
+                    Ok(Some(0)) => pipe.eof = true,
+                    Ok(Some(n)) => {
+                        pipe.buf.extend_from_slice(&chunk[..n]);
+
+                        if let Some(ref mut lines) = pipe.lines {
+                            lines.extend_from_slice(&chunk[..n]);
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        println!("got an error trying to read; token={:?}; direction={:?}; err={:?}", self.token, direction, e);
+                        self.closed = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.decode(direction);
+    }
+
+    // Passes the complete lines read from the side `direction` starts at
+    // to the conversation, and records the exchanges they complete. A
+    // line too long means the protocol isn't one of lines after all, or
+    // not at the moment, as when a client sends a file: recording that
+    // direction stops, relaying goes on.
+    fn decode(&mut self, direction: Direction) {
+        let at = self.accepted.elapsed();
+        let mut done = vec![];
+
+        {
+            let pipe = match direction {
+                Direction::Up => &mut self.up,
+                Direction::Down => &mut self.down,
+            };
+
+            let mut lines = match pipe.lines.take() {
+                Some(lines) => lines,
+                None => return,
+            };
+
+            loop {
+                match self.codec.decode(&mut lines) {
+                    Ok(Some(line)) => {
+                        match direction {
+                            Direction::Up => self.conversation.request(&line, at),
+                            Direction::Down => done.extend(self.conversation.response(&line, at)),
+                        }
+                    }
+                    Ok(None) => {
+                        pipe.lines = Some(lines);
+                        break;
+                    }
+                    Err(e) => {
+                        println!("not recording anymore; id={}; direction={:?}; err={:?}", self.id, direction, e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.record(done);
+    }
+
+    // Appends the exchanges to the connection's file, a JSON object a line
+    fn record(&mut self, exchanges: Vec<Exchange>) {
+        for exchange in exchanges {
+            self.exchanges += 1;
+
+            // How long the server took to start answering
+            let latency = match exchange.first_ms {
+                Some(ms) if exchange.request.is_some() => format!("{:.3}ms", ms - exchange.at_ms),
+                _ => "-".to_string(),
+            };
+
+            println!("id={}; request={:?}; response={:?}; latency={}", self.id, exchange.request, exchange.response, latency);
+
+            if let Some(ref mut file) = self.file {
+                let line = exchange.to_json().to_string() + "\n";
+
+                // Losing the recording is no reason to drop the connection,
+                // but it is no use going on writing to it
+                if let Err(e) = file.write_all(line.as_bytes()) {
+                    println!("failed to write conversation file; id={}; err={:?}", self.id, e);
+                    self.file = None;
+                }
+            }
+        }
+    }
+
+    // Writes what was read from the other side to the side `direction`
+    // ends at
+    fn write(&mut self, direction: Direction) {
+        let (socket, pipe) = match direction {
+            Direction::Up => (&mut self.upstream, &mut self.up),
+            Direction::Down => (&mut self.client, &mut self.down),
+        };
+
+        while !pipe.buf.is_empty() {
+            match socket.try_write(&pipe.buf) {
+                Ok(Some(n)) => {
+                    pipe.buf.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; token={:?}; direction={:?}; err={:?}", self.token, direction, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        // The side it is read from is done, and all it said was passed on
+        if pipe.eof && !pipe.shut {
+            let _ = socket.shutdown(Shutdown::Write);
+            pipe.shut = true;
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.closed || self.up.shut && self.down.shut
+    }
+
+    fn client_interest(&self) -> mio::EventSet {
+        interest(self.connected, &self.up, &self.down)
+    }
+
+    fn upstream_interest(&self) -> mio::EventSet {
+        if !self.connected {
+            return mio::EventSet::writable();
+        }
+
+        interest(self.connected, &self.down, &self.up)
+    }
+}
+
+// The interest of a socket, given the pipe it is read into, and the pipe
+// it is written from
+fn interest(connected: bool, from: &Pipe, to: &Pipe) -> mio::EventSet {
+    let mut interest = mio::EventSet::none();
+
+    if connected && !from.eof && from.buf.len() < MAX_BUFFERED {
+        interest = interest | mio::EventSet::readable();
+    }
+
+    if !to.buf.is_empty() {
+        interest = interest | mio::EventSet::writable();
+    }
+
+    interest
+}
+
+fn usage() -> ! {
+    println!("usage: line_recorder <addr> <upstream> [dir]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().and_then(|addr| addr.parse().ok()).unwrap_or_else(|| usage());
+    let upstream: SocketAddr = args.next().and_then(|addr| addr.parse().ok()).unwrap_or_else(|| usage());
+    let dir = PathBuf::from(args.next().unwrap_or_else(|| "conversations".to_string()));
+
+    fs::create_dir_all(&dir).unwrap();
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    println!("running line recorder; addr={:?}; upstream={:?}; dir={:?}", address, upstream, dir);
+
+    let mut recorder = Recorder::new(server, upstream, dir);
+    event_loop.run(&mut recorder).unwrap();
+}
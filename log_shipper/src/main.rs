@@ -0,0 +1,183 @@
+extern crate mio;
+extern crate reconnect;
+
+use mio::TryRead;
+use reconnect::{Backoff, Client, Event};
+use std::env;
+
+const COLLECTOR: mio::Token = mio::Token(0);
+const STDIN: mio::Token = mio::Token(1);
+
+// How long to wait before reconnecting after the connection is lost, the
+// first time, and at most
+const INITIAL_BACKOFF_MS: u64 = 500;
+const MAX_BACKOFF_MS: u64 = 30_000;
+
+// Lines queued, while the collector is away or slow. Past that, stdin isn't
+// read until some are written out: the shipper shouldn't run out of memory
+// because the collector did, nor drop lines to keep up with it.
+const MAX_PENDING: usize = 100_000;
+
+// The syslog priority the lines are sent with, user.info
+const PRIORITY: u32 = 14;
+
+// Reads lines from stdin and ships them to a collector over TCP, framed the
+// way syslog over TCP is, terminated by a newline. While the collector is
+// unreachable, lines keep being read and are queued. They are sent once it
+// is back.
+//
+// The queue is what pushes back on stdin. When it is full, stdin is
+// deregistered, and whatever writes to it blocks, until the collector has
+// taken enough lines.
+struct Shipper {
+    client: Client<()>,
+    tag: String,
+    stdin: mio::Io,
+    // Read but not yet terminated by a newline
+    line: Vec<u8>,
+    stdin_closed: bool,
+    // Whether stdin is deregistered for the queue being full
+    paused: bool,
+    lines: u64,
+}
+
+impl Shipper {
+    fn read_stdin(&mut self) {
+        let mut buf = [0; 4_096];
+
+        match self.stdin.try_read(&mut buf) {
+            Ok(Some(0)) => self.stdin_closed = true,
+            Ok(Some(n)) => self.line.extend(&buf[..n]),
+            Ok(None) => {}
+            Err(e) => {
+                println!("got an error trying to read stdin; err={:?}", e);
+                self.stdin_closed = true;
+            }
+        }
+
+        self.ship_lines();
+    }
+
+    // Queues the complete lines read, as many as there is room for. The
+    // others wait in `line` for the queue to drain.
+    fn ship_lines(&mut self) {
+        while self.client.pending() < MAX_PENDING {
+            let pos = match self.line.iter().position(|b| *b == b'\n') {
+                Some(pos) => pos,
+                None => break,
+            };
+
+            let line: Vec<u8> = self.line.drain(..pos + 1).take(pos).collect();
+            self.ship(&line);
+        }
+
+        // The last line may not end with a newline
+        if self.stdin_closed && !self.line.is_empty() && self.client.pending() < MAX_PENDING {
+            let line = std::mem::take(&mut self.line);
+            self.ship(&line);
+        }
+    }
+
+    // Stops reading stdin while the queue is full, and starts again once
+    // there is room in it
+    fn throttle(&mut self, event_loop: &mut mio::EventLoop<Shipper>) {
+        if self.stdin_closed {
+            return;
+        }
+
+        let full = self.client.pending() >= MAX_PENDING;
+
+        if full && !self.paused {
+            println!("queue full, pausing stdin; pending={}", self.client.pending());
+            event_loop.deregister(&self.stdin).unwrap();
+            self.paused = true;
+        } else if !full && self.paused {
+            println!("queue drained, resuming stdin; pending={}", self.client.pending());
+            event_loop.register_opt(&self.stdin, STDIN, mio::EventSet::readable(), mio::PollOpt::level()).unwrap();
+            self.paused = false;
+        }
+    }
+
+    fn ship(&mut self, line: &[u8]) {
+        let mut msg = format!("<{}>{}: ", PRIORITY, self.tag).into_bytes();
+        msg.extend(line);
+        msg.push(b'\n');
+
+        self.lines += 1;
+        self.client.queue(msg);
+    }
+}
+
+impl mio::Handler for Shipper {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Shipper>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            COLLECTOR => {
+                // The collector has nothing to say, what it sends is only
+                // read to notice it closing the connection.
+                let mut buf = vec![];
+
+                // There is no handshake, queued lines can go right away
+                if let Some(Event::Connected) = self.client.ready(event_loop, events, &mut buf) {
+                    self.client.replay();
+                }
+            }
+            STDIN => {
+                // Stdin is registered as level triggered, one read per event
+                // is enough.
+                self.read_stdin();
+
+                if self.stdin_closed {
+                    event_loop.deregister(&self.stdin).unwrap();
+                }
+            }
+            _ => panic!("unexpected token"),
+        }
+
+        self.client.flush(event_loop);
+
+        // What was written made room for the lines held back
+        self.ship_lines();
+        self.client.flush(event_loop);
+        self.throttle(event_loop);
+
+        // Done once every line has been written out, however many
+        // reconnects it takes
+        if self.stdin_closed && self.line.is_empty() && self.client.pending() == 0 && self.client.is_flushed() {
+            println!("shipped all lines; lines={}", self.lines);
+            event_loop.shutdown();
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Shipper>, _: ()) {
+        self.client.connect(event_loop);
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    // The syslog receiver's default address
+    let addr = args.next().unwrap_or("127.0.0.1:5514".to_string());
+    let tag = args.next().unwrap_or("log_shipper".to_string());
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    let backoff = Backoff::new(INITIAL_BACKOFF_MS, MAX_BACKOFF_MS);
+
+    let mut shipper = Shipper {
+        client: Client::new(&addr, COLLECTOR, (), backoff, MAX_PENDING),
+        tag: tag,
+        stdin: mio::Io::from_raw_fd(0),
+        line: vec![],
+        stdin_closed: false,
+        paused: false,
+        lines: 0,
+    };
+
+    event_loop.register_opt(&shipper.stdin, STDIN, mio::EventSet::readable(), mio::PollOpt::level()).unwrap();
+    shipper.client.connect(&mut event_loop);
+
+    event_loop.run(&mut shipper).unwrap();
+}
@@ -0,0 +1,311 @@
+extern crate mio;
+extern crate bytes;
+extern crate dns_wire;
+extern crate libc;
+
+use mio::{IpAddr, TryRead};
+use mio::udp::*;
+use bytes::SliceBuf;
+use dns_wire::{push_name, push_u16, read_name, read_u16};
+use std::net::{self, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::unix::io::AsRawFd;
+use std::{env, io, mem};
+
+const SOCKET: mio::Token = mio::Token(0);
+const STDIN: mio::Token = mio::Token(1);
+
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+
+// How long other hosts may cache the record
+const RECORD_TTL: u32 = 120;
+
+// After the initial announcements, the record is announced again well within
+// its TTL so that caches on the network stay fresh.
+const ANNOUNCE_INTERVAL_MS: u64 = 60_000;
+
+const MAX_MESSAGE: usize = 9_000;
+
+#[derive(Debug)]
+enum Timeout {
+    Announce,
+}
+
+struct Responder {
+    socket: UdpSocket,
+    stdin: mio::Io,
+    hostname: String,
+    addr: Ipv4Addr,
+    // The number of announcements sent so far
+    announcements: usize,
+}
+
+impl Responder {
+    fn new(socket: UdpSocket, stdin: mio::Io, hostname: String, addr: Ipv4Addr) -> Responder {
+        Responder {
+            socket: socket,
+            stdin: stdin,
+            hostname: hostname,
+            addr: addr,
+            announcements: 0,
+        }
+    }
+
+    fn announce(&mut self, event_loop: &mut mio::EventLoop<Responder>) {
+        println!("announcing; hostname={}; addr={}", self.hostname, self.addr);
+
+        let packet = build_response(0, None, &self.hostname, self.addr, RECORD_TTL);
+        self.send(&packet, &group_addr());
+
+        self.announcements += 1;
+
+        // RFC 6762 asks for at least two announcements one second apart at
+        // startup, after which the interval is relaxed.
+        let delay = if self.announcements < 2 { 1_000 } else { ANNOUNCE_INTERVAL_MS };
+        event_loop.timeout_ms(Timeout::Announce, delay).unwrap();
+    }
+
+    fn receive(&mut self) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event.
+        loop {
+            let mut buf = Vec::with_capacity(MAX_MESSAGE);
+
+            let src = match self.socket.recv_from(&mut buf) {
+                Ok(Some(src)) => src,
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            let question = match find_question(&buf, &self.hostname) {
+                Some(question) => question,
+                None => continue,
+            };
+
+            println!("received query; hostname={}; src={:?}", self.hostname, src);
+
+            let id = read_u16(&buf, 0).unwrap();
+
+            if src.port() != MDNS_PORT {
+                // A "legacy" unicast query from a plain DNS resolver. It
+                // expects a conventional response: sent directly back, with
+                // the query ID and question echoed.
+                let packet = build_response(id, Some(&question), &self.hostname, self.addr, 10);
+                self.send(&packet, &src);
+            } else {
+                let packet = build_response(0, None, &self.hostname, self.addr, RECORD_TTL);
+                self.send(&packet, &group_addr());
+            }
+        }
+    }
+
+    // Sends a goodbye (a record with a TTL of zero), leaves the multicast
+    // group and stops the event loop.
+    fn goodbye(&mut self, event_loop: &mut mio::EventLoop<Responder>) {
+        println!("sending goodbye");
+
+        let packet = build_response(0, None, &self.hostname, self.addr, 0);
+        self.send(&packet, &group_addr());
+
+        self.socket.leave_multicast(&IpAddr::V4(MDNS_GROUP)).unwrap();
+        event_loop.shutdown();
+    }
+
+    fn send(&self, packet: &[u8], addr: &SocketAddr) {
+        match self.socket.send_to(&mut SliceBuf::wrap(packet), addr) {
+            Ok(Some(())) => {}
+            Ok(None) => println!("the socket wasn't actually ready, dropping datagram"),
+            Err(e) => println!("failed to send datagram; addr={:?}; err={:?}", addr, e),
+        }
+    }
+}
+
+impl mio::Handler for Responder {
+    type Timeout = Timeout;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Responder>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SOCKET => {
+                assert!(events.is_readable(), "unexpected events; events={:?}", events);
+                self.receive();
+            }
+            STDIN => {
+                // Any input (or closing stdin) is the signal to shut down
+                let mut buf = [0; 128];
+                let _ = self.stdin.try_read(&mut buf);
+
+                self.goodbye(event_loop);
+            }
+            _ => panic!("unexpected token"),
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Responder>, timeout: Timeout) {
+        match timeout {
+            Timeout::Announce => self.announce(event_loop),
+        }
+    }
+}
+
+/*
+ *
+ * ===== DNS messages =====
+ *
+ */
+
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+// In mDNS the top bit of the class is repurposed. In responses it is the
+// "cache flush" bit, telling other hosts that this record replaces any they
+// had cached for the name.
+const CACHE_FLUSH: u16 = 0x8000;
+
+// Returns the raw bytes of the first question in a query that asks for our
+// hostname's A record.
+fn find_question(packet: &[u8], hostname: &str) -> Option<Vec<u8>> {
+    let flags = read_u16(packet, 2)?;
+    let qdcount = read_u16(packet, 4)?;
+
+    // Ignore responses, including our own announcements looped back to us
+    if flags & 0x8000 != 0 {
+        return None;
+    }
+
+    let mut pos = 12;
+
+    for _ in 0..qdcount {
+        let (name, next) = read_name(packet, pos)?;
+        let qtype = read_u16(packet, next)?;
+        // The top bit is the "unicast response" bit, mask it off
+        let qclass = read_u16(packet, next + 2)? & 0x7fff;
+
+        if name.eq_ignore_ascii_case(hostname) && qclass == CLASS_IN && (qtype == TYPE_A || qtype == TYPE_ANY) {
+            let mut question = Vec::new();
+            push_name(&mut question, hostname);
+            push_u16(&mut question, TYPE_A);
+            push_u16(&mut question, CLASS_IN);
+            return Some(question);
+        }
+
+        pos = next + 4;
+    }
+
+    None
+}
+
+fn build_response(id: u16, question: Option<&[u8]>, hostname: &str, addr: Ipv4Addr, ttl: u32) -> Vec<u8> {
+    let mut packet = Vec::new();
+
+    push_u16(&mut packet, id);
+    push_u16(&mut packet, 0x8400); // flags: QR, AA
+    push_u16(&mut packet, if question.is_some() { 1 } else { 0 });
+    push_u16(&mut packet, 1);      // ANCOUNT
+    push_u16(&mut packet, 0);      // NSCOUNT
+    push_u16(&mut packet, 0);      // ARCOUNT
+
+    if let Some(question) = question {
+        packet.extend(question);
+    }
+
+    push_name(&mut packet, hostname);
+    push_u16(&mut packet, TYPE_A);
+
+    // Legacy unicast responses must not set the cache flush bit
+    if question.is_some() {
+        push_u16(&mut packet, CLASS_IN);
+    } else {
+        push_u16(&mut packet, CLASS_IN | CACHE_FLUSH);
+    }
+
+    push_u16(&mut packet, (ttl >> 16) as u16);
+    push_u16(&mut packet, ttl as u16);
+    push_u16(&mut packet, 4);
+    packet.extend(&addr.octets());
+
+    packet
+}
+
+fn group_addr() -> SocketAddr {
+    SocketAddr::V4(SocketAddrV4::new(MDNS_GROUP, MDNS_PORT))
+}
+
+// Other mDNS responders (avahi, mDNSResponder) are most likely already bound
+// to port 5353. Setting SO_REUSEADDR before binding lets us share it.
+fn bind_shared(port: u16) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::v4()?;
+
+    setsockopt(&socket, libc::SOL_SOCKET, libc::SO_REUSEADDR, 1)?;
+
+    socket.bind(&SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port)))?;
+    Ok(socket)
+}
+
+fn setsockopt(socket: &UdpSocket, level: libc::c_int, name: libc::c_int, val: libc::c_int) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &val as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+// Finds the address of the interface used to reach the multicast group by
+// "connecting" a UDP socket, which sends no packets.
+fn local_addr() -> Ipv4Addr {
+    let socket = net::UdpSocket::bind("0.0.0.0:0").unwrap();
+    socket.connect(group_addr()).unwrap();
+
+    match socket.local_addr().unwrap() {
+        SocketAddr::V4(addr) => *addr.ip(),
+        _ => unreachable!(),
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let hostname = args.next().unwrap_or("mio-example.local".to_string());
+    let addr = match args.next() {
+        Some(addr) => addr.parse().unwrap(),
+        None => local_addr(),
+    };
+
+    let socket = bind_shared(MDNS_PORT).unwrap();
+
+    socket.join_multicast(&IpAddr::V4(MDNS_GROUP)).unwrap();
+
+    // mDNS packets must never leave the local link. Receivers check for a
+    // TTL of 255 as proof that a packet was not routed.
+    //
+    // `UdpSocket::set_multicast_time_to_live` passes the kernel a one byte
+    // value with a four byte length, which Linux rejects, so the option is
+    // set directly.
+    setsockopt(&socket, libc::IPPROTO_IP, libc::IP_MULTICAST_TTL, 255).unwrap();
+
+    // Loop our own packets back so that other applications on this host
+    // see the announcements.
+    socket.set_multicast_loop(true).unwrap();
+
+    let stdin = mio::Io::from_raw_fd(0);
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&socket, SOCKET, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+    event_loop.register(&stdin, STDIN).unwrap();
+
+    let mut responder = Responder::new(socket, stdin, hostname, addr);
+    responder.announce(&mut event_loop);
+
+    println!("running mdns responder; press enter to stop");
+    event_loop.run(&mut responder).unwrap();
+}
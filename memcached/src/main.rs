@@ -0,0 +1,419 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Limits from the memcached protocol
+const MAX_KEY: usize = 250;
+const MAX_LINE: usize = 2_048;
+const MAX_VALUE: usize = 1_024 * 1_024;
+
+// Expiration times up to 30 days are relative to now, anything larger is
+// an absolute unix timestamp.
+const MAX_RELATIVE_EXPIRY: u64 = 60 * 60 * 24 * 30;
+
+struct Item {
+    flags: u32,
+    data: Vec<u8>,
+    expires: Option<Instant>,
+}
+
+// Expired items are not removed by a timer, they are dropped when a `get`
+// finds them, which is what memcached itself does.
+struct Store {
+    items: HashMap<String, Item>,
+}
+
+impl Store {
+    fn get(&mut self, key: &str) -> Option<&Item> {
+        let expired = match self.items.get(key) {
+            Some(item) => item.expires.map(|at| at <= Instant::now()).unwrap_or(false),
+            None => return None,
+        };
+
+        if expired {
+            self.items.remove(key);
+            return None;
+        }
+
+        self.items.get(key)
+    }
+}
+
+struct Memcached {
+    acceptor: Acceptor<Factory<Connection>>,
+    store: Store,
+    connections: Slab<Connection>,
+}
+
+impl Memcached {
+    fn new(acceptor: Acceptor<Factory<Connection>>) -> Memcached {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Memcached {
+            acceptor: acceptor,
+            store: Store { items: HashMap::new() },
+            connections: slab,
+        }
+    }
+}
+
+impl mio::Handler for Memcached {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Memcached>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
+                }
+            }
+            _ => {
+                self.connections[token].ready(event_loop, events, &mut self.store);
+
+                if self.connections[token].closed {
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+}
+
+// Most commands are a single line, but `set` is a command line followed by
+// a data block whose length is given in the command line. The data block is
+// binary, it may contain CRLF, so it can't be found by looking for the end
+// of a line, only by counting bytes.
+#[derive(Debug)]
+enum State {
+    // Waiting for a command line
+    Command,
+    // Waiting for the data block of a `set`, followed by CRLF
+    Data(Set),
+    // Throwing away the data block of a rejected `set`. The value is the
+    // number of bytes left to skip.
+    Skip(usize),
+}
+
+#[derive(Debug)]
+struct Set {
+    key: String,
+    flags: u32,
+    expires: Option<Instant>,
+    len: usize,
+    noreply: bool,
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    state: State,
+    // Bytes read but not yet processed
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Set when the connection should be closed once `out` is flushed
+    quit: bool,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            state: State::Command,
+            buf: vec![],
+            out: vec![],
+            quit: false,
+            closed: false,
+        }
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Memcached>, events: mio::EventSet, store: &mut Store) {
+        if events.is_readable() {
+            self.read(store);
+        }
+
+        if events.is_writable() || !self.out.is_empty() {
+            self.write();
+        }
+
+        if !self.closed {
+            let interest = if self.out.is_empty() {
+                mio::EventSet::readable()
+            } else {
+                mio::EventSet::readable() | mio::EventSet::writable()
+            };
+
+            event_loop.reregister(&self.socket, self.token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn read(&mut self, store: &mut Store) {
+        let mut buf = [0; 4096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    if self.quit {
+                        continue;
+                    }
+
+                    self.buf.extend(&buf[..n]);
+                    self.process(store);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    // Runs the state machine over the buffered input until it needs more
+    fn process(&mut self, store: &mut Store) {
+        while !self.quit {
+            let state = std::mem::replace(&mut self.state, State::Command);
+
+            self.state = match state {
+                State::Command => {
+                    let line = match self.buf.iter().position(|b| *b == b'\n') {
+                        Some(pos) => self.buf.drain(..pos + 1).collect::<Vec<u8>>(),
+                        None if self.buf.len() > MAX_LINE => {
+                            self.reply("CLIENT_ERROR line too long");
+                            self.quit = true;
+                            return;
+                        }
+                        None => return,
+                    };
+
+                    self.command(&String::from_utf8_lossy(&line), store)
+                }
+                State::Data(set) => {
+                    if self.buf.len() < set.len + 2 {
+                        self.state = State::Data(set);
+                        return;
+                    }
+
+                    if &self.buf[set.len..set.len + 2] != b"\r\n" {
+                        // The client and server no longer agree on where
+                        // commands start, there is no recovering from this.
+                        self.reply("CLIENT_ERROR bad data chunk");
+                        self.quit = true;
+                        return;
+                    }
+
+                    let data: Vec<u8> = self.buf.drain(..set.len + 2).take(set.len).collect();
+
+                    println!("stored; key={:?}; len={}", set.key, data.len());
+
+                    store.items.insert(set.key, Item {
+                        flags: set.flags,
+                        data: data,
+                        expires: set.expires,
+                    });
+
+                    if !set.noreply {
+                        self.reply("STORED");
+                    }
+
+                    State::Command
+                }
+                State::Skip(left) => {
+                    let n = left.min(self.buf.len());
+                    self.buf.drain(..n);
+
+                    if n < left {
+                        self.state = State::Skip(left - n);
+                        return;
+                    }
+
+                    State::Command
+                }
+            };
+        }
+    }
+
+    // Handles a command line and returns the state to continue in
+    fn command(&mut self, line: &str, store: &mut Store) -> State {
+        let mut words = line.split_whitespace();
+
+        match words.next() {
+            Some("get") => {
+                for key in words {
+                    if let Some(item) = store.get(key) {
+                        let header = format!("VALUE {} {} {}\r\n", key, item.flags, item.data.len());
+
+                        self.out.extend(header.as_bytes());
+                        self.out.extend(&item.data);
+                        self.out.extend(b"\r\n");
+                    }
+                }
+
+                self.reply("END");
+            }
+            Some("set") => {
+                // set <key> <flags> <exptime> <bytes> [noreply]
+                let args: Vec<&str> = words.collect();
+
+                let parsed = match (args.first(), args.get(1), args.get(2), args.get(3)) {
+                    (Some(key), Some(flags), Some(exptime), Some(len)) => {
+                        match (flags.parse::<u32>(), exptime.parse::<i64>(), len.parse::<usize>()) {
+                            (Ok(flags), Ok(exptime), Ok(len)) if key.len() <= MAX_KEY => {
+                                Some((key.to_string(), flags, exptime, len))
+                            }
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+
+                let (key, flags, exptime, len) = match parsed {
+                    Some(parsed) => parsed,
+                    None => {
+                        self.reply("CLIENT_ERROR bad command line format");
+                        return State::Command;
+                    }
+                };
+
+                let noreply = args.get(4) == Some(&"noreply");
+
+                if len > MAX_VALUE {
+                    self.reply("SERVER_ERROR object too large for cache");
+                    return State::Skip(len + 2);
+                }
+
+                return State::Data(Set {
+                    key: key,
+                    flags: flags,
+                    expires: expires(exptime),
+                    len: len,
+                    noreply: noreply,
+                });
+            }
+            Some("delete") => {
+                let key = match words.next() {
+                    Some(key) => key,
+                    None => {
+                        self.reply("ERROR");
+                        return State::Command;
+                    }
+                };
+
+                let noreply = words.next() == Some("noreply");
+
+                let reply = match store.items.remove(key) {
+                    Some(_) => "DELETED",
+                    None => "NOT_FOUND",
+                };
+
+                if !noreply {
+                    self.reply(reply);
+                }
+            }
+            Some("quit") => {
+                self.quit = true;
+            }
+            Some(_) => self.reply("ERROR"),
+            None => {}
+        }
+
+        State::Command
+    }
+
+    fn reply(&mut self, line: &str) {
+        self.out.extend(line.as_bytes());
+        self.out.extend(b"\r\n");
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        if self.quit {
+            self.closed = true;
+        }
+    }
+}
+
+// Converts an expiration time from a `set` command. 0 means never, and a
+// negative time means the item expires immediately.
+fn expires(exptime: i64) -> Option<Instant> {
+    if exptime == 0 {
+        return None;
+    }
+
+    if exptime < 0 {
+        return Some(Instant::now());
+    }
+
+    let exptime = exptime as u64;
+
+    let secs = if exptime <= MAX_RELATIVE_EXPIRY {
+        exptime
+    } else {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        exptime.saturating_sub(now)
+    };
+
+    Some(Instant::now() + Duration::from_secs(secs))
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:11211".to_string())
+        .parse().unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mut memcached = Memcached::new(acceptor);
+
+    println!("running memcached server; addr={:?}", address);
+    event_loop.run(&mut memcached).unwrap();
+}
@@ -0,0 +1,236 @@
+// An append-only log of messages, split into segment files. Every message
+// gets the next offset, starting at 0. A segment holds the messages from
+// the offset it is named after, `00000000000000001000.log` starts with
+// message 1000, and once it reaches its size limit, a new one is started.
+//
+// A record is the message's length as 4 bytes, big endian, followed by the
+// message. The offsets of a segment's records are not stored anywhere, they
+// are rebuilt by reading the segment when the log is opened.
+//
+// Appends are buffered, and written out and synced to disk together by
+// `commit`. Only committed messages can be read: once a producer is told
+// its message is in, it stays in, whatever happens to the process or the
+// machine.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+// Larger messages are refused
+pub const MAX_MESSAGE: usize = 64 * 1_024;
+
+struct Segment {
+    // The offset of the first message
+    base: u64,
+    path: PathBuf,
+    file: File,
+    // Where each record starts in the file
+    positions: Vec<u64>,
+    size: u64,
+}
+
+impl Segment {
+    fn end(&self) -> u64 {
+        self.base + self.positions.len() as u64
+    }
+}
+
+pub struct Log {
+    dir: PathBuf,
+    segment_bytes: u64,
+    // Oldest first. The last one is being appended to.
+    segments: Vec<Segment>,
+    // Records appended but not yet committed, and where they will start in
+    // the last segment
+    buf: Vec<u8>,
+    pending: Vec<u64>,
+}
+
+impl Log {
+    // Opens the log in `dir`, creating it if needed. A record cut short by a
+    // crash in the middle of writing it was never committed, it is dropped.
+    pub fn open(dir: &Path, segment_bytes: u64) -> io::Result<Log> {
+        fs::create_dir_all(dir)?;
+
+        let mut bases = vec![];
+
+        for entry in fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+
+            if let Some(base) = name.strip_suffix(".log").and_then(|base| base.parse::<u64>().ok()) {
+                bases.push(base);
+            }
+        }
+
+        bases.sort();
+
+        let mut log = Log {
+            dir: dir.to_path_buf(),
+            segment_bytes: segment_bytes,
+            segments: vec![],
+            buf: vec![],
+            pending: vec![],
+        };
+
+        for base in bases {
+            let segment = log.load(base)?;
+            log.segments.push(segment);
+        }
+
+        if log.segments.is_empty() {
+            log.roll(0)?;
+        }
+
+        Ok(log)
+    }
+
+    // Returns the offset the message will have once committed
+    pub fn append(&mut self, msg: &[u8]) -> u64 {
+        let segment = self.segments.last().unwrap();
+        let offset = segment.end() + self.pending.len() as u64;
+
+        self.pending.push(segment.size + self.buf.len() as u64);
+        self.buf.extend(&[(msg.len() >> 24) as u8, (msg.len() >> 16) as u8, (msg.len() >> 8) as u8, msg.len() as u8]);
+        self.buf.extend(msg);
+
+        offset
+    }
+
+    // Writes the appended messages, and waits for the disk to have them.
+    // This blocks the event loop, for as long as the disk takes.
+    pub fn commit(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        {
+            let segment = self.segments.last_mut().unwrap();
+
+            segment.file.write_all(&self.buf)?;
+            segment.file.sync_data()?;
+
+            segment.size += self.buf.len() as u64;
+            segment.positions.append(&mut self.pending);
+            self.buf.clear();
+        }
+
+        let (size, end) = {
+            let segment = self.segments.last().unwrap();
+            (segment.size, segment.end())
+        };
+
+        if size >= self.segment_bytes {
+            self.roll(end)?;
+        }
+
+        Ok(())
+    }
+
+    // The oldest message still in the log
+    pub fn start(&self) -> u64 {
+        self.segments[0].base
+    }
+
+    // The offset the next committed message will have
+    pub fn end(&self) -> u64 {
+        self.segments.last().unwrap().end()
+    }
+
+    // Reads a committed message. Reading blocks the event loop too, but the
+    // recently written segments are most likely in the page cache.
+    pub fn read(&self, offset: u64) -> io::Result<Vec<u8>> {
+        let segment = match self.segments.iter().rev().find(|segment| segment.base <= offset) {
+            Some(segment) if offset < segment.end() => segment,
+            _ => return Err(io::Error::new(io::ErrorKind::NotFound, "no such offset")),
+        };
+
+        let position = segment.positions[(offset - segment.base) as usize];
+
+        let mut len = [0; 4];
+        segment.file.read_exact_at(&mut len, position)?;
+
+        let len = ((len[0] as usize) << 24) | ((len[1] as usize) << 16) | ((len[2] as usize) << 8) | len[3] as usize;
+
+        let mut msg = vec![0; len];
+        segment.file.read_exact_at(&mut msg, position + 4)?;
+
+        Ok(msg)
+    }
+
+    // Deletes the segments holding only messages before `offset`. The last
+    // segment is always kept, it is the one being appended to.
+    pub fn retain(&mut self, offset: u64) -> io::Result<()> {
+        while self.segments.len() > 1 && self.segments[0].end() <= offset {
+            let segment = self.segments.remove(0);
+            fs::remove_file(&segment.path)?;
+
+            println!("deleted segment; base={}; messages={}", segment.base, segment.positions.len());
+        }
+
+        Ok(())
+    }
+
+    fn load(&self, base: u64) -> io::Result<Segment> {
+        let path = self.path(base);
+        let mut file = OpenOptions::new().read(true).append(true).open(&path)?;
+
+        let mut data = vec![];
+        file.read_to_end(&mut data)?;
+
+        let mut positions = vec![];
+        let mut pos = 0;
+
+        while pos + 4 <= data.len() {
+            let len = ((data[pos] as usize) << 24) | ((data[pos + 1] as usize) << 16) | ((data[pos + 2] as usize) << 8) | data[pos + 3] as usize;
+
+            if pos + 4 + len > data.len() {
+                break;
+            }
+
+            positions.push(pos as u64);
+            pos += 4 + len;
+        }
+
+        if pos < data.len() {
+            println!("dropping incomplete record; segment={:?}; bytes={}", path, data.len() - pos);
+            file.set_len(pos as u64)?;
+        }
+
+        println!("loaded segment; base={}; messages={}", base, positions.len());
+
+        Ok(Segment {
+            base: base,
+            path: path,
+            file: file,
+            positions: positions,
+            size: pos as u64,
+        })
+    }
+
+    // Starts a new segment
+    fn roll(&mut self, base: u64) -> io::Result<()> {
+        let path = self.path(base);
+        let file = OpenOptions::new().read(true).append(true).create(true).open(&path)?;
+
+        // The new file's directory entry has to be on disk too, or the
+        // segment could be gone after a crash, along with what is
+        // committed to it.
+        File::open(&self.dir)?.sync_all()?;
+
+        self.segments.push(Segment {
+            base: base,
+            path: path,
+            file: file,
+            positions: vec![],
+            size: 0,
+        });
+
+        Ok(())
+    }
+
+    fn path(&self, base: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.log", base))
+    }
+}
@@ -0,0 +1,434 @@
+extern crate acceptor;
+extern crate mio;
+
+mod log;
+mod offsets;
+
+use acceptor::{Acceptor, Factory};
+use log::{Log, MAX_MESSAGE};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use offsets::Offsets;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 1_024;
+
+// A new segment is started once the current one reaches this size
+const SEGMENT_BYTES: u64 = 1_024 * 1_024;
+
+// Messages sent to a consumer and not yet acknowledged. Once it has this
+// many, it gets no more until it acknowledges some.
+const MAX_IN_FLIGHT: u64 = 100;
+
+// Past this much output queued for a consumer, messages wait in the log
+const MAX_OUT: usize = 256 * 1_024;
+
+// How often acknowledged offsets are saved, and fully acknowledged
+// segments deleted
+const SAVE_MS: u64 = 1_000;
+
+// Producers append messages to the log, and consumers read them from the
+// offset they have acknowledged up to. The log is on disk, and so are the
+// offsets: messages survive restarts, and consumers pick up where they left
+// off.
+struct Queue {
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+    log: Log,
+    offsets: Offsets,
+    // Producers waiting to hear that their message is committed, and its
+    // offset
+    uncommitted: Vec<(mio::Token, u64)>,
+    // Consumer name -> the connection it is using
+    consumers: HashMap<String, mio::Token>,
+    // Connections that had output queued, or were closed, while handling the
+    // current event. They are reregistered (or removed) once it is done.
+    dirty: Vec<mio::Token>,
+}
+
+impl Queue {
+    fn new(acceptor: Acceptor<Factory<Connection>>, log: Log, offsets: Offsets) -> Queue {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS);
+
+        Queue {
+            acceptor: acceptor,
+            connections: slab,
+            log: log,
+            offsets: offsets,
+            uncommitted: vec![],
+            consumers: HashMap::new(),
+            dirty: vec![],
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Queue>) {
+        let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+        if let Err(e) = res {
+            println!("encountered error while accepting connection; err={:?}", e);
+            event_loop.shutdown();
+        }
+    }
+
+    fn connection_ready(&mut self, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            for line in self.connections[token].read() {
+                self.handle(token, &line);
+            }
+        }
+
+        if events.is_writable() {
+            self.connections[token].write();
+        }
+
+        self.commit();
+
+        // Acknowledgements and writes make room for more messages
+        self.pump(token);
+        self.dirty.push(token);
+    }
+
+    fn handle(&mut self, token: mio::Token, line: &str) {
+        let mut parts = line.splitn(2, ' ');
+        let command = parts.next().unwrap_or("");
+        let rest = parts.next().unwrap_or("");
+
+        match command {
+            // PUT <message>
+            "PUT" => {
+                if rest.len() > MAX_MESSAGE {
+                    return self.reply(token, "ERR message too large");
+                }
+
+                let offset = self.log.append(rest.as_bytes());
+                self.uncommitted.push((token, offset));
+            }
+            // SUB <consumer> [<offset>]
+            "SUB" => {
+                let mut parts = rest.split(' ');
+                let name = parts.next().unwrap_or("");
+                let offset = parts.next().map(|offset| offset.parse::<u64>());
+
+                match offset {
+                    _ if name.is_empty() => self.reply(token, "ERR expected a consumer name"),
+                    Some(Err(_)) => self.reply(token, "ERR invalid offset"),
+                    Some(Ok(offset)) => self.subscribe(token, name, Some(offset)),
+                    None => self.subscribe(token, name, None),
+                }
+            }
+            // ACK <offset>, acknowledges every message up to this one
+            "ACK" => {
+                match rest.parse() {
+                    Ok(offset) => self.ack(token, offset),
+                    Err(_) => self.reply(token, "ERR invalid offset"),
+                }
+            }
+            _ => self.reply(token, "ERR unknown command"),
+        }
+    }
+
+    fn subscribe(&mut self, token: mio::Token, name: &str, offset: Option<u64>) {
+        if self.connections[token].consumer.is_some() {
+            return self.reply(token, "ERR already subscribed");
+        }
+
+        if self.consumers.contains_key(name) {
+            return self.reply(token, "ERR consumer already connected");
+        }
+
+        // Without an offset, a consumer resumes from where it left off. A new
+        // one starts with the oldest message in the log.
+        let start = offset
+            .or_else(|| self.offsets.get(name))
+            .unwrap_or_else(|| self.log.start());
+
+        // Deleted messages can't be read, and messages not yet written can't
+        // be acknowledged
+        let start = start.max(self.log.start()).min(self.log.end());
+
+        println!("consumer subscribed; name={}; offset={}; token={:?}", name, start, token);
+
+        self.consumers.insert(name.to_string(), token);
+        self.offsets.set(name, start);
+
+        self.connections[token].consumer = Some(Consumer {
+            name: name.to_string(),
+            next: start,
+            acked: start,
+        });
+
+        self.reply(token, &format!("OK {}", start));
+    }
+
+    fn ack(&mut self, token: mio::Token, offset: u64) {
+        let name = match self.connections[token].consumer {
+            // Only messages sent can be acknowledged
+            Some(ref mut consumer) if offset < consumer.next => {
+                if offset >= consumer.acked {
+                    consumer.acked = offset + 1;
+                }
+
+                consumer.name.clone()
+            }
+            Some(_) => return self.reply(token, "ERR offset not delivered"),
+            None => return self.reply(token, "ERR not subscribed"),
+        };
+
+        let acked = self.connections[token].consumer.as_ref().unwrap().acked;
+        self.offsets.set(&name, acked);
+    }
+
+    // Writes out the messages appended while handling the event, with a
+    // single sync however many there are, and tells their producers.
+    fn commit(&mut self) {
+        if self.uncommitted.is_empty() {
+            return;
+        }
+
+        // Without the disk, the queue can't keep its promises
+        self.log.commit().unwrap();
+
+        for (token, offset) in std::mem::take(&mut self.uncommitted) {
+            // The producer may be gone already
+            if self.connections.contains(token) {
+                self.reply(token, &format!("OK {}", offset));
+            }
+        }
+
+        // Only committed messages are delivered
+        let consumers: Vec<mio::Token> = self.consumers.values().cloned().collect();
+
+        for token in consumers {
+            self.pump(token);
+            self.dirty.push(token);
+        }
+    }
+
+    // Sends a consumer the messages it is ready for
+    fn pump(&mut self, token: mio::Token) {
+        let end = self.log.end();
+
+        let conn = match self.connections.get_mut(token) {
+            Some(conn) => conn,
+            None => return,
+        };
+
+        let consumer = match conn.consumer {
+            Some(ref mut consumer) => consumer,
+            None => return,
+        };
+
+        while consumer.next < end && consumer.next - consumer.acked < MAX_IN_FLIGHT && conn.out.len() < MAX_OUT {
+            let msg = self.log.read(consumer.next).unwrap();
+
+            conn.out.extend(format!("MSG {} ", consumer.next).as_bytes());
+            conn.out.extend(&msg);
+            conn.out.push(b'\n');
+
+            consumer.next += 1;
+        }
+    }
+
+    fn reply(&mut self, token: mio::Token, line: &str) {
+        let conn = &mut self.connections[token];
+
+        conn.out.extend(line.as_bytes());
+        conn.out.push(b'\n');
+
+        self.dirty.push(token);
+    }
+
+    // Reregisters every connection touched while handling an event, and
+    // removes the ones that are done.
+    fn flush(&mut self, event_loop: &mut mio::EventLoop<Queue>) {
+        while let Some(token) = self.dirty.pop() {
+            let closed = match self.connections.get(token) {
+                Some(conn) => conn.closed,
+                None => continue,
+            };
+
+            if closed {
+                self.remove(token);
+                continue;
+            }
+
+            let conn = &self.connections[token];
+
+            event_loop.reregister(&conn.socket, token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn remove(&mut self, token: mio::Token) {
+        let conn = self.connections.remove(token).unwrap();
+
+        // Messages sent but not acknowledged are sent again to the next
+        // connection using the consumer's name.
+        if let Some(consumer) = conn.consumer {
+            println!("consumer disconnected; name={}; acked={}; in flight={}",
+                     consumer.name, consumer.acked, consumer.next - consumer.acked);
+
+            self.consumers.remove(&consumer.name);
+        }
+    }
+
+    // Saves the offsets, and deletes the segments every consumer is done
+    // with. Only saved offsets count: a segment is never deleted while a
+    // crash could take a consumer back into it.
+    fn save(&mut self, event_loop: &mut mio::EventLoop<Queue>) {
+        self.offsets.save().unwrap();
+
+        if let Some(min) = self.offsets.min() {
+            self.log.retain(min).unwrap();
+        }
+
+        event_loop.timeout_ms((), SAVE_MS).unwrap();
+    }
+}
+
+impl mio::Handler for Queue {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Queue>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ => {
+                self.connection_ready(token, events);
+                self.flush(event_loop);
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Queue>, _: ()) {
+        self.save(event_loop);
+    }
+}
+
+struct Consumer {
+    name: String,
+    // The next message to send
+    next: u64,
+    // The first message not yet acknowledged
+    acked: u64,
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Bytes of an incomplete line
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Set by SUB
+    consumer: Option<Consumer>,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            buf: vec![],
+            out: vec![],
+            consumer: None,
+            closed: false,
+        }
+    }
+
+    // Returns the complete lines read
+    fn read(&mut self) -> Vec<String> {
+        let mut chunk = [0; 4_096];
+        let mut lines = vec![];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(Some(n)) => self.buf.extend(&chunk[..n]),
+                Ok(None) => break,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..pos + 1).take(pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            lines.push(line.trim_end_matches('\r').to_string());
+        }
+
+        // Room for the command and a message of the largest size
+        if self.buf.len() > MAX_MESSAGE + 16 {
+            println!("line too long, closing connection; token={:?}", self.token);
+            self.closed = true;
+        }
+
+        lines
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:9700".to_string()).parse().unwrap();
+    let dir = args.next().unwrap_or("queue-data".to_string());
+
+    let log = Log::open(Path::new(&dir), SEGMENT_BYTES).unwrap();
+    let offsets = Offsets::load(Path::new(&dir)).unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+    event_loop.timeout_ms((), SAVE_MS).unwrap();
+
+    println!("running message queue; addr={:?}; dir={:?}; messages={}..{}", address, dir, log.start(), log.end());
+
+    let mut queue = Queue::new(acceptor, log, offsets);
+    event_loop.run(&mut queue).unwrap();
+}
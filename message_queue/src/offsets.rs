@@ -0,0 +1,88 @@
+// The offset each consumer has acknowledged up to, kept in a file with a
+// line per consumer:
+//
+//     <consumer> <offset of the next message it hasn't acknowledged>
+//
+// The file is replaced as a whole: written to a temporary file first, which
+// is then renamed over it, so a crash leaves either the old or the new
+// version, never half of one. Acknowledgements arriving after the last save
+// are lost in a crash, and those messages are delivered again: consumers
+// see every message at least once, and may see some twice.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+pub struct Offsets {
+    path: PathBuf,
+    offsets: BTreeMap<String, u64>,
+    // Changed since the last save
+    dirty: bool,
+}
+
+impl Offsets {
+    pub fn load(dir: &Path) -> io::Result<Offsets> {
+        let path = dir.join("offsets");
+        let mut offsets = BTreeMap::new();
+
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    let mut parts = line.split(' ');
+
+                    if let (Some(name), Some(Ok(offset))) = (parts.next(), parts.next().map(str::parse)) {
+                        offsets.insert(name.to_string(), offset);
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(Offsets {
+            path: path,
+            offsets: offsets,
+            dirty: false,
+        })
+    }
+
+    pub fn get(&self, consumer: &str) -> Option<u64> {
+        self.offsets.get(consumer).cloned()
+    }
+
+    pub fn set(&mut self, consumer: &str, offset: u64) {
+        self.offsets.insert(consumer.to_string(), offset);
+        self.dirty = true;
+    }
+
+    // The lowest offset of all consumers, the messages before it have been
+    // acknowledged by every one of them. `None` if there are no consumers.
+    pub fn min(&self) -> Option<u64> {
+        self.offsets.values().cloned().min()
+    }
+
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let tmp = self.path.with_extension("tmp");
+
+        {
+            let mut file = File::create(&tmp)?;
+
+            for (name, offset) in &self.offsets {
+                writeln!(file, "{} {}", name, offset)?;
+            }
+
+            file.sync_all()?;
+        }
+
+        fs::rename(&tmp, &self.path)?;
+        self.dirty = false;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,426 @@
+extern crate mio;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::{env, process, thread};
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Per loop
+const MAX_CONNECTIONS: usize = 4_096;
+
+// Lines longer than this close the connection
+const MAX_LINE: usize = 1_024;
+
+// How often the loads of the loops are compared
+const REBALANCE_MS: u64 = 5_000;
+
+enum Msg {
+    // A connection moving to this loop
+    Adopt(Session),
+    // Give one of this loop's connections to another
+    Give(usize),
+}
+
+// A connection, and everything known about it: what it sent that isn't
+// handled yet, what is waiting to be written to it, and the session's own
+// state. All of it moves with the connection.
+struct Session {
+    socket: TcpStream,
+    id: u64,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    eof: bool,
+    lines: u64,
+    moves: u32,
+}
+
+// A worker event loop, on its own thread. Sessions move from one loop to
+// another at runtime without the client noticing, other than by the loop
+// it is told it is on: the socket is deregistered from the old loop, sent
+// through the new loop's channel, and registered with it, which reports
+// right away whatever the socket got meanwhile.
+struct Worker {
+    id: usize,
+    connections: Slab<Session>,
+    // The channels of all the loops, this one's included
+    peers: Vec<mio::Sender<Msg>>,
+    // The number of connections of each loop, for the dispatcher
+    counts: Arc<Vec<AtomicUsize>>,
+}
+
+impl Worker {
+    fn adopt(&mut self, event_loop: &mut mio::EventLoop<Worker>, session: Session) {
+        let token = match self.connections.insert(session) {
+            Ok(token) => token,
+            Err(_) => {
+                println!("connection limit reached, dropping client; loop={}", self.id);
+                return;
+            }
+        };
+
+        self.counts[self.id].store(self.connections.count(), Ordering::Relaxed);
+
+        // What came in the same read as the move is handled here, and what
+        // the old loop didn't get to write is written from here
+        self.update(event_loop, token, true);
+    }
+
+    // Answers the complete lines, writes, then registers the socket again,
+    // or moves it, or closes it
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Worker>, token: mio::Token, first: bool) {
+        let to = self.process(token);
+
+        let session = &mut self.connections[token];
+        let closed = session.write();
+
+        // A session that just arrived isn't registered with this loop yet
+        if let Some(to) = to {
+            return self.migrate(event_loop, token, to, !first);
+        }
+
+        if closed || session.buf.len() > MAX_LINE || session.eof && session.out.is_empty() {
+            self.connections.remove(token);
+            self.counts[self.id].store(self.connections.count(), Ordering::Relaxed);
+            return;
+        }
+
+        let mut interest = mio::EventSet::none();
+
+        if !session.eof {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !session.out.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        let opts = mio::PollOpt::edge() | mio::PollOpt::oneshot();
+
+        if first {
+            event_loop.register_opt(&session.socket, token, interest, opts).unwrap();
+        } else {
+            event_loop.reregister(&session.socket, token, interest, opts).unwrap();
+        }
+    }
+
+    // Answers the lines read so far, up to a `move`: the lines after it are
+    // for the loop the session moves to. Returns that loop.
+    fn process(&mut self, token: mio::Token) -> Option<usize> {
+        let session = &mut self.connections[token];
+
+        while let Some(pos) = session.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = session.buf.drain(..pos + 1).collect();
+            let line = String::from_utf8_lossy(&line).trim().to_string();
+
+            session.lines += 1;
+
+            let mut words = line.split_whitespace();
+
+            match (words.next(), words.next().map(|to| to.parse::<usize>())) {
+                (Some("move"), Some(Ok(to))) if to == self.id => {
+                    session.reply(&format!("already on loop {}", to));
+                }
+                (Some("move"), Some(Ok(to))) if to < self.peers.len() => {
+                    session.reply(&format!("moving; from={}; to={}", self.id, to));
+                    return Some(to);
+                }
+                (Some("move"), _) => {
+                    session.reply(&format!("usage: move <loop>, 0 to {}", self.peers.len() - 1));
+                }
+                _ => {
+                    let reply = format!("loop={}; session={}; lines={}; moves={}; {}", self.id, session.id, session.lines, session.moves, line);
+                    session.reply(&reply);
+                }
+            }
+        }
+
+        None
+    }
+
+    // Moves a session to another loop, deregistering its socket first if it
+    // is registered with this one
+    fn migrate(&mut self, event_loop: &mut mio::EventLoop<Worker>, token: mio::Token, to: usize, registered: bool) {
+        let mut session = self.connections.remove(token).unwrap();
+        self.counts[self.id].store(self.connections.count(), Ordering::Relaxed);
+
+        // Once deregistered, this loop hears nothing more of the socket,
+        // whatever comes in waits for the new loop
+        if registered {
+            event_loop.deregister(&session.socket).unwrap();
+        }
+
+        session.moves += 1;
+
+        println!("moving session; session={}; from={}; to={}", session.id, self.id, to);
+
+        match self.peers[to].send(Msg::Adopt(session)) {
+            Ok(()) => {}
+            // The other loop's queue is full, the session stays
+            Err(mio::NotifyError::Full(Msg::Adopt(mut session))) => {
+                session.moves -= 1;
+                session.reply("move failed, try again later");
+                self.adopt(event_loop, session);
+            }
+            // The other loop is gone, the session stays for good
+            Err(mio::NotifyError::Closed(Some(Msg::Adopt(mut session)))) => {
+                println!("loop is gone; loop={}", to);
+                session.moves -= 1;
+                session.reply("move failed, the loop is gone");
+                self.adopt(event_loop, session);
+            }
+            Err(e) => println!("failed to move session, dropping client; to={}; err={:?}", to, e),
+        }
+    }
+
+    // Moves some session to the other loop, if there is any
+    fn give(&mut self, event_loop: &mut mio::EventLoop<Worker>, to: usize) {
+        let token = (0..MAX_CONNECTIONS).map(mio::Token).find(|&token| self.connections.contains(token));
+
+        if let Some(token) = token {
+            let from = self.id;
+            self.connections[token].reply(&format!("moved by the rebalancer; from={}; to={}", from, to));
+            self.migrate(event_loop, token, to, true);
+        }
+    }
+}
+
+impl mio::Handler for Worker {
+    type Timeout = ();
+    type Message = Msg;
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Worker>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            self.connections[token].read();
+        }
+
+        self.update(event_loop, token, false);
+    }
+
+    fn notify(&mut self, event_loop: &mut mio::EventLoop<Worker>, msg: Msg) {
+        match msg {
+            Msg::Adopt(session) => self.adopt(event_loop, session),
+            Msg::Give(to) => self.give(event_loop, to),
+        }
+    }
+}
+
+impl Session {
+    fn reply(&mut self, line: &str) {
+        self.out.extend_from_slice(line.as_bytes());
+        self.out.extend_from_slice(b"\r\n");
+    }
+
+    fn read(&mut self) {
+        let mut chunk = [0; 4_096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.eof = true;
+                    return;
+                }
+                Ok(Some(n)) => self.buf.extend_from_slice(&chunk[..n]),
+                Ok(None) | Err(_) => return,
+            }
+        }
+    }
+
+    // Returns whether the connection failed
+    fn write(&mut self) -> bool {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return false,
+                Err(_) => return true,
+            }
+        }
+
+        false
+    }
+}
+
+// Accepts the connections and hands them to the loops round robin. Every
+// few seconds, it moves a session from the busiest loop to the least
+// busy one, if they are two or more apart: connections last, and as some
+// close the loops drift out of balance. A loop whose channel is closed is
+// gone, and left out from then on.
+struct Dispatcher {
+    server: TcpListener,
+    loops: Vec<mio::Sender<Msg>>,
+    dead: Vec<bool>,
+    counts: Arc<Vec<AtomicUsize>>,
+    next: usize,
+    sessions: u64,
+}
+
+impl Dispatcher {
+    // Hands a session to the next loop still running
+    fn assign(&mut self, event_loop: &mut mio::EventLoop<Dispatcher>, mut session: Session) {
+        while let Some(to) = self.next_loop() {
+            session.reply(&format!("session {}, on loop {}; `move <loop>` moves it", session.id, to));
+
+            match self.loops[to].send(Msg::Adopt(session)) {
+                Ok(()) => return,
+                Err(mio::NotifyError::Full(_)) => {
+                    println!("loop queue full, dropping client; loop={}", to);
+                    return;
+                }
+                Err(mio::NotifyError::Closed(Some(Msg::Adopt(rejected)))) => {
+                    println!("loop is gone; loop={}", to);
+                    self.dead[to] = true;
+                    session = rejected;
+                    session.out.clear();
+                }
+                Err(e) => {
+                    println!("failed to hand over client, dropping it; loop={}; err={:?}", to, e);
+                    return;
+                }
+            }
+        }
+
+        println!("all loops are gone, shutting down");
+        event_loop.shutdown();
+    }
+
+    fn next_loop(&mut self) -> Option<usize> {
+        for _ in 0..self.loops.len() {
+            let next = self.next;
+            self.next = (self.next + 1) % self.loops.len();
+
+            if !self.dead[next] {
+                return Some(next);
+            }
+        }
+
+        None
+    }
+}
+
+impl mio::Handler for Dispatcher {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Dispatcher>, token: mio::Token, _: mio::EventSet) {
+        assert_eq!(token, SERVER);
+
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("failed to accept connection; err={:?}", e);
+                return;
+            }
+        };
+
+        self.sessions += 1;
+
+        let session = Session {
+            socket: socket,
+            id: self.sessions,
+            buf: vec![],
+            out: vec![],
+            eof: false,
+            lines: 0,
+            moves: 0,
+        };
+
+        self.assign(event_loop, session);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Dispatcher>, _: ()) {
+        let counts: Vec<usize> = self.counts.iter().map(|count| count.load(Ordering::Relaxed)).collect();
+        let live: Vec<usize> = (0..counts.len()).filter(|&i| !self.dead[i]).collect();
+
+        let busiest = live.iter().cloned().max_by_key(|&i| counts[i]);
+        let idlest = live.iter().cloned().min_by_key(|&i| counts[i]);
+
+        if let (Some(busiest), Some(idlest)) = (busiest, idlest) {
+            if counts[busiest] >= counts[idlest] + 2 {
+                println!("rebalancing; from={}; to={}; counts={:?}", busiest, idlest, counts);
+                let _ = self.loops[busiest].send(Msg::Give(idlest));
+            }
+        }
+
+        event_loop.timeout_ms((), REBALANCE_MS).unwrap();
+    }
+}
+
+fn usage() -> ! {
+    println!("usage: migrate [addr] [loops]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:9700".to_string()).parse().unwrap_or_else(|_| usage());
+    let count: usize = args.next().map(|s| s.parse().unwrap_or_else(|_| usage())).unwrap_or(3);
+
+    if count == 0 {
+        usage();
+    }
+
+    let counts: Arc<Vec<AtomicUsize>> = Arc::new((0..count).map(|_| AtomicUsize::new(0)).collect());
+
+    // Each loop needs the channels of all the others: the loops are
+    // started first, and get the channels once they all exist
+    let mut loops = vec![];
+    let mut starts = vec![];
+
+    for id in 0..count {
+        let (channel_tx, channel_rx) = mpsc::channel();
+        let (peers_tx, peers_rx) = mpsc::channel();
+        let counts = counts.clone();
+
+        thread::spawn(move || {
+            let mut event_loop = mio::EventLoop::new().unwrap();
+            channel_tx.send(event_loop.channel()).unwrap();
+
+            let mut worker = Worker {
+                id: id,
+                connections: Slab::new(MAX_CONNECTIONS),
+                peers: peers_rx.recv().unwrap(),
+                counts: counts,
+            };
+
+            event_loop.run(&mut worker).unwrap();
+        });
+
+        loops.push(channel_rx.recv().unwrap());
+        starts.push(peers_tx);
+    }
+
+    for start in starts {
+        start.send(loops.clone()).unwrap();
+    }
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+    event_loop.timeout_ms((), REBALANCE_MS).unwrap();
+
+    println!("running migrating server; addr={:?}; loops={}", address, count);
+
+    let mut dispatcher = Dispatcher {
+        server: server,
+        dead: vec![false; count],
+        loops: loops,
+        counts: counts,
+        next: 0,
+        sessions: 0,
+    };
+
+    event_loop.run(&mut dispatcher).unwrap();
+}
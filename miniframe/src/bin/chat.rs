@@ -0,0 +1,203 @@
+// The WebSocket chat, on miniframe. The codec speaks HTTP until the
+// client asks to upgrade, WebSocket frames after that; the service serves
+// the page and relays what every member says to all of them.
+extern crate codec;
+extern crate miniframe;
+extern crate websocket;
+
+use codec::{Decoder, Encoder};
+use miniframe::{Context, Id, Reactor, Service};
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use websocket::frame::{self, Message, Opcode};
+use websocket::handshake;
+
+// The page served to browsers that request it over plain HTTP
+const PAGE: &str = include_str!("../../../websocket/src/bin/chat.html");
+
+const MAX_TEXT: usize = 4 * 1_024;
+
+enum Incoming {
+    Request(handshake::Request),
+    Message(Message),
+}
+
+enum Outgoing {
+    // An HTTP response, or the handshake's
+    Raw(Vec<u8>),
+    Text(String),
+    Pong(Vec<u8>),
+    Close(u16),
+}
+
+#[derive(Debug)]
+enum Error {
+    // The request head, before the upgrade
+    Http,
+    Frame(frame::Error),
+}
+
+#[derive(Clone)]
+struct WebSocket {
+    // Past the upgrade request, the rest is frames
+    upgraded: bool,
+    reader: frame::Reader,
+}
+
+impl Decoder for WebSocket {
+    type Item = Incoming;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Incoming>, Error> {
+        if !self.upgraded {
+            return match handshake::parse(buf).map_err(|_| Error::Http)? {
+                Some((request, len)) => {
+                    buf.drain(..len);
+                    self.upgraded = request.is_upgrade();
+                    Ok(Some(Incoming::Request(request)))
+                }
+                None => Ok(None),
+            };
+        }
+
+        while let Some(frame) = frame::decode(buf).map_err(Error::Frame)? {
+            if let Some(msg) = self.reader.push(frame).map_err(Error::Frame)? {
+                return Ok(Some(Incoming::Message(msg)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Encoder for WebSocket {
+    type Item = Outgoing;
+
+    fn encode(&mut self, out: Outgoing, dst: &mut Vec<u8>) {
+        match out {
+            Outgoing::Raw(bytes) => dst.extend(bytes),
+            Outgoing::Text(text) => frame::encode(Opcode::Text, text.as_bytes(), dst),
+            Outgoing::Pong(data) => frame::encode(Opcode::Pong, &data, dst),
+            Outgoing::Close(code) => frame::encode_close(Some(code), dst),
+        }
+    }
+}
+
+struct Chat {
+    // The connections past the handshake, and their names
+    members: HashMap<Id, String>,
+}
+
+impl Chat {
+    fn broadcast(&self, ctx: &mut Context<Outgoing>, text: String) {
+        println!("broadcast; msg={:?}", text);
+
+        for &id in self.members.keys() {
+            ctx.send(id, Outgoing::Text(text.clone()));
+        }
+    }
+
+    // Sends a plain HTTP response, then closes the connection
+    fn respond(&self, ctx: &mut Context<Outgoing>, response: Vec<u8>) {
+        ctx.reply(Outgoing::Raw(response));
+        ctx.close();
+    }
+
+    // Sends a close frame, then closes the connection
+    fn fail(&self, ctx: &mut Context<Outgoing>, code: u16) {
+        ctx.reply(Outgoing::Close(code));
+        ctx.close();
+    }
+}
+
+impl Service for Chat {
+    type Request = Incoming;
+    type Response = Outgoing;
+    type Error = Error;
+
+    fn call(&mut self, ctx: &mut Context<Outgoing>, incoming: Incoming) {
+        let msg = match incoming {
+            Incoming::Request(request) => {
+                println!("received request; method={}; path={}; upgrade={}", request.method, request.path, request.is_upgrade());
+
+                // The page and the WebSocket are served on the same port,
+                // one connection at a time is either a browser fetching the
+                // page or the page's script connecting back.
+                if !request.is_upgrade() {
+                    if request.method == "GET" && request.path == "/" {
+                        return self.respond(ctx, page());
+                    }
+
+                    return self.respond(ctx, b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec());
+                }
+
+                match handshake::accept(&request) {
+                    Some(response) => ctx.reply(Outgoing::Raw(response)),
+                    None => return self.respond(ctx, handshake::bad_request()),
+                }
+
+                let name = format!("guest{}", ctx.id());
+
+                self.members.insert(ctx.id(), name.clone());
+                self.broadcast(ctx, format!("* {} joined", name));
+                return;
+            }
+            Incoming::Message(msg) => msg,
+        };
+
+        match msg {
+            Message::Text(ref text) if text.len() > MAX_TEXT => self.fail(ctx, 1009),
+            Message::Text(text) => {
+                let text = format!("{}: {}", self.members[&ctx.id()], text);
+                self.broadcast(ctx, text);
+            }
+            // 1003: the chat only deals in text
+            Message::Binary(..) => self.fail(ctx, 1003),
+            Message::Ping(data) => ctx.reply(Outgoing::Pong(data)),
+            Message::Pong(..) => {}
+            Message::Close(code) => self.fail(ctx, code.unwrap_or(1000)),
+        }
+    }
+
+    fn invalid(&mut self, ctx: &mut Context<Outgoing>, err: Error) {
+        match err {
+            Error::Http => self.respond(ctx, handshake::bad_request()),
+            Error::Frame(e) => self.fail(ctx, e.close_code()),
+        }
+    }
+
+    // A client that left after joining the chat is announced to the others
+    fn disconnected(&mut self, ctx: &mut Context<Outgoing>) {
+        if let Some(name) = self.members.remove(&ctx.id()) {
+            self.broadcast(ctx, format!("* {} left", name));
+        }
+    }
+}
+
+fn page() -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/html; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        PAGE.len()).into_bytes();
+
+    response.extend(PAGE.as_bytes());
+    response
+}
+
+fn main() {
+    let addr: SocketAddr = env::args().nth(1)
+        .unwrap_or_else(|| "0.0.0.0:6301".to_string())
+        .parse().unwrap();
+
+    let codec = WebSocket {
+        upgraded: false,
+        reader: frame::Reader::new(),
+    };
+
+    let chat = Chat { members: HashMap::new() };
+
+    Reactor::serve(&addr, codec, chat);
+}
@@ -0,0 +1,30 @@
+// The Ping Pong server, on miniframe: every line is written back
+extern crate codec;
+extern crate miniframe;
+
+use codec::line::{self, Line};
+use miniframe::{Context, Reactor, Service};
+use std::env;
+use std::net::SocketAddr;
+
+const MAX_LINE: usize = 1_024;
+
+struct Echo;
+
+impl Service for Echo {
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+    type Error = line::Error;
+
+    fn call(&mut self, ctx: &mut Context<Vec<u8>>, line: Vec<u8>) {
+        ctx.reply(line);
+    }
+}
+
+fn main() {
+    let addr: SocketAddr = env::args().nth(1)
+        .unwrap_or_else(|| "0.0.0.0:6300".to_string())
+        .parse().unwrap();
+
+    Reactor::serve(&addr, Line::new(MAX_LINE), Echo);
+}
@@ -0,0 +1,175 @@
+// The Users API of the HTTP server, on miniframe. The codec parses
+// requests and encodes responses with the HTTP server's own code, and the
+// service dispatches them through its router; the handlers are the
+// same. The connection handling its `server` module does is the
+// framework's, minus the deadlines on request heads: miniframe has no
+// timers.
+extern crate codec;
+extern crate http_server;
+extern crate miniframe;
+
+use codec::{Decoder, Encoder};
+use http_server::{request, Params, Request, Response, Router};
+use miniframe::{Context, Reactor, Service};
+use std::collections::BTreeMap;
+use std::env;
+use std::net::SocketAddr;
+
+#[derive(Clone)]
+struct Http;
+
+impl Decoder for Http {
+    type Item = Request;
+    type Error = &'static str;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Request>, &'static str> {
+        match request::parse(buf)? {
+            Some((request, len)) => {
+                buf.drain(..len);
+                Ok(Some(request))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder for Http {
+    // With whether the connection is kept open
+    type Item = (Response, bool);
+
+    fn encode(&mut self, (response, keep_alive): (Response, bool), dst: &mut Vec<u8>) {
+        response.encode(keep_alive, dst);
+    }
+}
+
+struct Api {
+    router: Router<Users>,
+    users: Users,
+}
+
+impl Service for Api {
+    type Request = Request;
+    type Response = (Response, bool);
+    type Error = &'static str;
+
+    fn call(&mut self, ctx: &mut Context<(Response, bool)>, request: Request) {
+        let response = self.router.dispatch(&mut self.users, &request);
+        let keep_alive = request.keep_alive();
+
+        println!("{} {}; status={}", request.method, request.path, response.status);
+
+        ctx.reply((response, keep_alive));
+
+        if !keep_alive {
+            ctx.close();
+        }
+    }
+
+    fn invalid(&mut self, ctx: &mut Context<(Response, bool)>, reason: &'static str) {
+        ctx.reply((Response::text(400, reason), false));
+    }
+}
+
+// The state shared by the handlers. With a single event loop thread, they
+// can't run at the same time, they get it mutably without any locking.
+struct Users {
+    users: BTreeMap<u64, String>,
+    next_id: u64,
+}
+
+fn index(_: &mut Users, _: &Request, _: &Params) -> Response {
+    Response::text(200, "GET /users\nPOST /users\nGET /users/:id\nPUT /users/:id\nDELETE /users/:id\nGET /hello/:name")
+}
+
+fn hello(_: &mut Users, _: &Request, params: &Params) -> Response {
+    Response::text(200, &format!("Hello, {}!", params.get("name").unwrap()))
+}
+
+fn list(users: &mut Users, _: &Request, _: &Params) -> Response {
+    let lines: Vec<String> = users.users.iter().map(|(id, name)| format!("{} {}", id, name)).collect();
+    Response::text(200, &lines.join("\n"))
+}
+
+// The body is the user's name
+fn create(users: &mut Users, request: &Request, _: &Params) -> Response {
+    let name = match name(request) {
+        Some(name) => name,
+        None => return Response::text(400, "expected a name"),
+    };
+
+    let id = users.next_id;
+    users.next_id += 1;
+    users.users.insert(id, name);
+
+    Response::text(201, &id.to_string()).header("Location", &format!("/users/{}", id))
+}
+
+fn show(users: &mut Users, _: &Request, params: &Params) -> Response {
+    match id(params).and_then(|id| users.users.get(&id)) {
+        Some(name) => Response::text(200, name),
+        None => Response::text(404, "no such user"),
+    }
+}
+
+fn update(users: &mut Users, request: &Request, params: &Params) -> Response {
+    let name = match name(request) {
+        Some(name) => name,
+        None => return Response::text(400, "expected a name"),
+    };
+
+    match id(params).and_then(|id| users.users.get_mut(&id)) {
+        Some(user) => {
+            *user = name;
+            Response::new(204)
+        }
+        None => Response::text(404, "no such user"),
+    }
+}
+
+fn remove(users: &mut Users, _: &Request, params: &Params) -> Response {
+    match id(params).and_then(|id| users.users.remove(&id)) {
+        Some(_) => Response::new(204),
+        None => Response::text(404, "no such user"),
+    }
+}
+
+// The `:id` of the route. One that isn't a number is a user that doesn't
+// exist.
+fn id(params: &Params) -> Option<u64> {
+    params.get("id").and_then(|id| id.parse().ok())
+}
+
+fn name(request: &Request) -> Option<String> {
+    let name = String::from_utf8_lossy(&request.body).trim().to_string();
+
+    if name.is_empty() || name.contains('\n') {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+fn main() {
+    let addr: SocketAddr = env::args().nth(1)
+        .unwrap_or_else(|| "0.0.0.0:6302".to_string())
+        .parse().unwrap();
+
+    let router = Router::new()
+        .get("/", index)
+        .get("/hello/:name", hello)
+        .get("/users", list)
+        .post("/users", create)
+        .get("/users/:id", show)
+        .put("/users/:id", update)
+        .delete("/users/:id", remove);
+
+    let api = Api {
+        router: router,
+        users: Users {
+            users: BTreeMap::new(),
+            next_id: 1,
+        },
+    };
+
+    Reactor::serve(&addr, Http, api);
+}
@@ -0,0 +1,103 @@
+// A tiny framework over mio, for servers speaking a framed protocol over
+// TCP. Every example in this repository does the same bookkeeping around
+// its protocol: a listener on token 0, a slab of connections, read and
+// write buffers, reregistering each socket for what is left to do, and
+// removing the connection once it is done. Here that is written once,
+// in the `Reactor`, and a server is a codec and a service:
+//
+//     Reactor::serve(&addr, Line::new(1_024), Echo);
+//
+// The codec, one of those in the `codec` crate or any other implementing
+// its traits, turns the bytes read into requests, and the responses into
+// bytes. Each connection gets its own, cloned from the one passed in, so
+// it can keep state, such as which protocol the connection speaks.
+//
+// The service gets the requests, with a `Context` to answer them. It can
+// reply, send to any other connection by its id, and close the
+// connection. Nothing it does is I/O: what it sends is encoded into the
+// connections' buffers, and written once it returns.
+//
+// What the framework leaves out is what makes the examples interesting:
+// timers, other kinds of sockets, talking to threads. It fits servers
+// answering requests, pushing to each other's connections at most.
+
+extern crate codec;
+extern crate mio;
+
+mod reactor;
+
+pub use reactor::Reactor;
+
+use std::fmt;
+
+// A connection, as the service knows it. Ids are reused once their
+// connection is gone, `Service::disconnected` says when.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Id(usize);
+
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+pub trait Service {
+    type Request;
+    type Response;
+    // What the codec fails with
+    type Error;
+
+    // A new connection, before anything is read from it
+    fn connected(&mut self, _: &mut Context<Self::Response>) {}
+
+    fn call(&mut self, ctx: &mut Context<Self::Response>, request: Self::Request);
+
+    // The codec failed: the stream can't be trusted to be at a frame
+    // boundary anymore, and nothing more is read. What the service sends
+    // in answer is written, then the connection is closed.
+    fn invalid(&mut self, _: &mut Context<Self::Response>, _: Self::Error) {}
+
+    // The connection is gone. It can't be sent to anymore, the others
+    // can.
+    fn disconnected(&mut self, _: &mut Context<Self::Response>) {}
+}
+
+// What the service does with the connections, while handling an event of
+// one of them. It is all carried out once the service returns.
+pub struct Context<R> {
+    id: Id,
+    sends: Vec<(Id, R)>,
+    close: bool,
+}
+
+impl<R> Context<R> {
+    fn new(id: Id) -> Context<R> {
+        Context {
+            id: id,
+            sends: vec![],
+            close: false,
+        }
+    }
+
+    // The connection the event is for
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    pub fn reply(&mut self, response: R) {
+        let id = self.id;
+        self.send(id, response);
+    }
+
+    // Queues a response on any connection. One that is gone, or closing, is
+    // skipped.
+    pub fn send(&mut self, to: Id, response: R) {
+        self.sends.push((to, response));
+    }
+
+    // Closes the connection once what was sent to it is written. Nothing
+    // more is read from it, or sent to it.
+    pub fn close(&mut self) {
+        self.close = true;
+    }
+}
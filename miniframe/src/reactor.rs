@@ -0,0 +1,329 @@
+// The event loop side: accepts connections, reads and decodes requests
+// for the service, and writes out what it sends. Connections are
+// registered edge triggered and oneshot, and reregistered after every
+// event for what is left to do, like in the examples written directly on
+// mio.
+
+use codec::{Decoder, Encoder};
+use mio::{self, EventLoop, Handler, TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::fmt;
+use std::net::SocketAddr;
+use {Context, Id, Service};
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 1_024;
+
+// A connection stops being read from while this much output is waiting
+// to be written, a client sending requests without reading the responses
+// can't make the server buffer them without bound
+const MAX_OUT: usize = 64 * 1_024;
+
+// A connection with this much output waiting is disconnected. Only others
+// sending to it can take it past `MAX_OUT`: a chat member that stopped
+// reading, say.
+const MAX_PENDING: usize = 1_024 * 1_024;
+
+// The most read from a connection per event. What is left stays in the
+// socket until the requests read are answered, so a client sending faster
+// than it reads backs up into its own socket, not the read buffer.
+const MAX_READ: usize = 64 * 1_024;
+
+pub struct Reactor<C, S> {
+    server: TcpListener,
+    connections: Slab<Connection<C>>,
+    // Cloned for each new connection
+    codec: C,
+    service: S,
+}
+
+impl<C, S> Reactor<C, S>
+    where C: Decoder + Encoder + Clone,
+          <C as Decoder>::Error: fmt::Debug,
+          S: Service<Request = <C as Decoder>::Item, Response = <C as Encoder>::Item, Error = <C as Decoder>::Error>,
+{
+    // Runs the service on `addr` until the process exits
+    pub fn serve(addr: &SocketAddr, codec: C, service: S) {
+        let server = TcpListener::bind(addr).unwrap();
+
+        let mut event_loop = EventLoop::new().unwrap();
+        event_loop.register(&server, SERVER).unwrap();
+
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let mut reactor = Reactor {
+            server: server,
+            connections: Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS),
+            codec: codec,
+            service: service,
+        };
+
+        println!("running server; addr={:?}", addr);
+        event_loop.run(&mut reactor).unwrap();
+    }
+
+    fn accept(&mut self, event_loop: &mut EventLoop<Reactor<C, S>>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        let codec = self.codec.clone();
+
+        let token = match self.connections.insert_with(|_| Connection::new(socket, codec)) {
+            Some(token) => token,
+            None => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        event_loop.register_opt(&self.connections[token].socket, token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        let mut ctx = Context::new(Id(token.as_usize()));
+        self.service.connected(&mut ctx);
+
+        let mut touched = self.apply(ctx);
+        touched.push(token);
+
+        self.settle(event_loop, touched);
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut EventLoop<Reactor<C, S>>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            self.connections[token].read();
+        }
+
+        let touched = self.process(token);
+        self.settle(event_loop, touched);
+    }
+
+    // Hands the requests read so far to the service, as long as there is
+    // room for the responses. Returns the connections it sent to.
+    fn process(&mut self, token: mio::Token) -> Vec<mio::Token> {
+        let mut touched = vec![token];
+
+        loop {
+            let res = {
+                let conn = &mut self.connections[token];
+
+                if conn.closed || !conn.has_room() {
+                    break;
+                }
+
+                conn.codec.decode(&mut conn.buf)
+            };
+
+            let mut ctx = Context::new(Id(token.as_usize()));
+
+            match res {
+                Ok(Some(request)) => self.service.call(&mut ctx, request),
+                Ok(None) => break,
+                Err(e) => {
+                    println!("invalid frame, closing connection; err={:?}; token={:?}", e, token);
+                    self.service.invalid(&mut ctx, e);
+                    ctx.close();
+                }
+            }
+
+            touched.extend(self.apply(ctx));
+        }
+
+        touched
+    }
+
+    // Encodes what the service sent into the connections' buffers. Returns
+    // the connections to write to.
+    fn apply(&mut self, ctx: Context<<C as Encoder>::Item>) -> Vec<mio::Token> {
+        let mut touched = vec![];
+
+        for (Id(to), response) in ctx.sends {
+            let token = mio::Token(to);
+
+            let conn = match self.connections.get_mut(token) {
+                Some(conn) if !conn.closing && !conn.closed => conn,
+                _ => continue,
+            };
+
+            conn.codec.encode(response, &mut conn.out);
+
+            if conn.out.len() > MAX_PENDING {
+                println!("client is not keeping up, disconnecting; token={:?}", token);
+                conn.closed = true;
+            }
+
+            touched.push(token);
+        }
+
+        if ctx.close {
+            let Id(id) = ctx.id;
+
+            if let Some(conn) = self.connections.get_mut(mio::Token(id)) {
+                conn.closing = true;
+            }
+        }
+
+        touched
+    }
+
+    // Writes out what the connections have waiting, and reregisters them.
+    // Removing one tells the service, which may send to others in turn,
+    // and writing responses out may make room for more requests already
+    // read: both go on until there is nothing left to do.
+    fn settle(&mut self, event_loop: &mut EventLoop<Reactor<C, S>>, mut touched: Vec<mio::Token>) {
+        while let Some(token) = touched.pop() {
+            if !self.connections.contains(token) {
+                continue;
+            }
+
+            self.connections[token].write();
+
+            if self.connections[token].has_room() && !self.connections[token].buf.is_empty() {
+                let more = self.process(token);
+
+                // Unless nothing more could be decoded, the new responses
+                // need writing
+                if more.len() > 1 {
+                    touched.extend(more);
+                    continue;
+                }
+            }
+
+            if self.connections[token].is_done() {
+                self.connections.remove(token);
+
+                let mut ctx = Context::new(Id(token.as_usize()));
+                self.service.disconnected(&mut ctx);
+
+                touched.extend(self.apply(ctx));
+                continue;
+            }
+
+            let conn = &self.connections[token];
+            event_loop.reregister(&conn.socket, token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+}
+
+impl<C, S> Handler for Reactor<C, S>
+    where C: Decoder + Encoder + Clone,
+          <C as Decoder>::Error: fmt::Debug,
+          S: Service<Request = <C as Decoder>::Item, Response = <C as Encoder>::Item, Error = <C as Decoder>::Error>,
+{
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut EventLoop<Reactor<C, S>>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            // The connection may have been removed while handling an event
+            // for another one earlier in this iteration.
+            _ if self.connections.contains(token) => self.connection_ready(event_loop, token, events),
+            _ => {}
+        }
+    }
+}
+
+struct Connection<C> {
+    socket: TcpStream,
+    codec: C,
+    // Read, and not yet decoded
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Set once the connection should be closed after writing `out`
+    closing: bool,
+    // The client is done sending, what it sent is still answered
+    eof: bool,
+    closed: bool,
+}
+
+impl<C> Connection<C> {
+    fn new(socket: TcpStream, codec: C) -> Connection<C> {
+        Connection {
+            socket: socket,
+            codec: codec,
+            buf: vec![],
+            out: vec![],
+            closing: false,
+            eof: false,
+            closed: false,
+        }
+    }
+
+    fn read(&mut self) {
+        let mut chunk = [0; 4_096];
+        let mut read = 0;
+
+        // The socket is registered as edge triggered, but oneshot too: what
+        // is left past `MAX_READ` makes it ready again once reregistered
+        while read < MAX_READ {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.eof = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    self.buf.extend(&chunk[..n]);
+                    read += n;
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.closed && !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                }
+            }
+        }
+    }
+
+    fn has_room(&self) -> bool {
+        !self.closing && self.out.len() < MAX_OUT
+    }
+
+    fn is_done(&self) -> bool {
+        // At the end of the stream, once the output is written: what is
+        // left to read isn't a complete request, or it would have been
+        // answered
+        self.closed || (self.closing || self.eof) && self.out.is_empty()
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        let mut interest = mio::EventSet::none();
+
+        if !self.eof && self.has_room() {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.out.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        interest
+    }
+}
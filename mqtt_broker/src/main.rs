@@ -0,0 +1,780 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::{env, mem};
+
+const SERVER: mio::Token = mio::Token(0);
+
+// The largest packet accepted from a client. The protocol allows up to
+// 256MB, which is far more than a toy broker should buffer.
+const MAX_PACKET: usize = 256 * 1_024;
+
+// A client that doesn't read fast enough is disconnected once this much
+// output is queued for it, rather than letting its queue grow forever.
+const MAX_QUEUED: usize = 1_024 * 1_024;
+
+// How long a new connection has to send CONNECT
+const CONNECT_TIMEOUT_MS: u64 = 10_000;
+
+// Packet types, the high nibble of the first byte of every packet
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+// CONNACK return codes
+const ACCEPTED: u8 = 0;
+const UNACCEPTABLE_PROTOCOL_VERSION: u8 = 1;
+const IDENTIFIER_REJECTED: u8 = 2;
+
+struct Broker {
+    acceptor: Acceptor<Factory<Client>>,
+    clients: Slab<Client>,
+    // Client identifier -> connection using it
+    ids: HashMap<String, mio::Token>,
+    // Topic filter -> clients subscribed with it. A filter is present as
+    // long as it has subscribers.
+    subscriptions: HashMap<String, HashSet<mio::Token>>,
+    // Clients that had output queued, or were closed, while handling the
+    // current event. They are reregistered (or removed) once it is done.
+    dirty: Vec<mio::Token>,
+    // Used to make up identifiers for clients that don't send one
+    next_id: usize,
+}
+
+impl Broker {
+    fn new(acceptor: Acceptor<Factory<Client>>) -> Broker {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Broker {
+            acceptor: acceptor,
+            clients: slab,
+            ids: HashMap::new(),
+            subscriptions: HashMap::new(),
+            dirty: vec![],
+            next_id: 0,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Broker>) {
+        let (tokens, res) = self.acceptor.accept(event_loop, &mut self.clients);
+
+        for token in tokens {
+            self.arm(event_loop, token);
+        }
+
+        if let Err(e) = res {
+            println!("encountered error while accepting connection; err={:?}", e);
+            event_loop.shutdown();
+        }
+    }
+
+    fn client_ready(&mut self, event_loop: &mut mio::EventLoop<Broker>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            match self.clients[token].read() {
+                Some(packets) => {
+                    let received = !packets.is_empty();
+
+                    for packet in packets {
+                        if self.clients[token].quit || self.clients[token].closed {
+                            break;
+                        }
+
+                        self.handle(token, packet);
+                    }
+
+                    // Any packet counts as a sign of life
+                    if received && !self.clients[token].closed {
+                        self.arm(event_loop, token);
+                    }
+                }
+                None => self.clients[token].closed = true,
+            }
+        }
+
+        if events.is_writable() {
+            self.clients[token].write();
+        }
+
+        self.dirty.push(token);
+    }
+
+    // (Re)starts the timer that disconnects the client if it goes quiet.
+    // Before CONNECT, the client has `CONNECT_TIMEOUT_MS` to send it. After,
+    // the client has one and a half times the keep alive it asked for to
+    // send its next packet. A keep alive of zero turns the timer off.
+    fn arm(&mut self, event_loop: &mut mio::EventLoop<Broker>, token: mio::Token) {
+        let client = &mut self.clients[token];
+
+        if let Some(timeout) = client.timer.take() {
+            event_loop.clear_timeout(timeout);
+        }
+
+        let ms = match client.id {
+            Some(_) => client.keep_alive,
+            None => Some(CONNECT_TIMEOUT_MS),
+        };
+
+        if let Some(ms) = ms {
+            client.timer = Some(event_loop.timeout_ms(token, ms).unwrap());
+        }
+    }
+
+    // Reregisters every client touched while handling an event, and
+    // removes the ones that are done.
+    fn flush(&mut self, event_loop: &mut mio::EventLoop<Broker>) {
+        while let Some(token) = self.dirty.pop() {
+            let (closed, overflow) = match self.clients.get(token) {
+                Some(client) => (client.closed, client.out.len() > MAX_QUEUED),
+                None => continue,
+            };
+
+            if overflow && !closed {
+                println!("client is not keeping up, disconnecting; token={:?}", token);
+                self.clients[token].out.clear();
+                self.clients[token].closed = true;
+            }
+
+            if self.clients[token].closed {
+                self.remove(event_loop, token);
+                continue;
+            }
+
+            let client = &self.clients[token];
+
+            event_loop.reregister(&client.socket, token, client.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn remove(&mut self, event_loop: &mut mio::EventLoop<Broker>, token: mio::Token) {
+        let mut client = self.clients.remove(token).unwrap();
+
+        if let Some(timeout) = client.timer.take() {
+            event_loop.clear_timeout(timeout);
+        }
+
+        // Sessions aren't kept, the subscriptions go away with the
+        // connection.
+        for filter in mem::take(&mut client.filters) {
+            let empty = match self.subscriptions.get_mut(&filter) {
+                Some(subscribers) => {
+                    subscribers.remove(&token);
+                    subscribers.is_empty()
+                }
+                None => false,
+            };
+
+            if empty {
+                self.subscriptions.remove(&filter);
+            }
+        }
+
+        // The identifier may already have been taken over by another
+        // connection.
+        if let Some(id) = client.id {
+            if self.ids.get(&id) == Some(&token) {
+                self.ids.remove(&id);
+            }
+
+            println!("client disconnected; id={:?}; token={:?}", id, token);
+        }
+    }
+
+    // Reacts to a single packet received from a client
+    fn handle(&mut self, token: mio::Token, packet: Packet) {
+        // CONNECT must be the first packet, and sent only once
+        match (&packet, self.clients[token].id.is_some()) {
+            (&Packet::Connect { .. }, false) => {}
+            (_, false) => return self.violation(token, "expected CONNECT"),
+            (&Packet::Connect { .. }, true) => return self.violation(token, "duplicate CONNECT"),
+            _ => {}
+        }
+
+        match packet {
+            Packet::Connect { level, client_id, clean_session, keep_alive } => {
+                self.connect(token, level, client_id, clean_session, keep_alive);
+            }
+            Packet::Publish { topic, payload, qos } => {
+                // Only QoS 0 is supported. Anything higher needs the broker
+                // to acknowledge the message, and to keep it until the
+                // subscribers acknowledge it in turn.
+                if qos > 0 {
+                    return self.violation(token, "QoS 1 and 2 are not supported");
+                }
+
+                self.publish(&topic, &payload);
+            }
+            Packet::Subscribe { id, filters } => self.subscribe(token, id, filters),
+            Packet::PingReq => {
+                let mut packet = vec![];
+                encode(PINGRESP << 4, &[], &mut packet);
+                self.send(token, &packet);
+            }
+            Packet::Disconnect => {
+                self.clients[token].closed = true;
+                self.dirty.push(token);
+            }
+        }
+    }
+
+    fn connect(&mut self, token: mio::Token, level: u8, client_id: String, clean_session: bool, keep_alive: u16) {
+        // 4 is MQTT 3.1.1
+        if level != 4 {
+            return self.refuse(token, UNACCEPTABLE_PROTOCOL_VERSION);
+        }
+
+        // A client may leave the identifier empty and have the server make
+        // one up, but only for a clean session, since it could never get
+        // back to a stored one.
+        let id = if client_id.is_empty() {
+            if !clean_session {
+                return self.refuse(token, IDENTIFIER_REJECTED);
+            }
+
+            self.next_id += 1;
+            format!("mio-{}", self.next_id)
+        } else {
+            client_id
+        };
+
+        // A second connection with the same identifier replaces the first
+        if let Some(old) = self.ids.insert(id.clone(), token) {
+            println!("client identifier taken over, disconnecting previous connection; id={:?}; token={:?}", id, old);
+            self.clients[old].closed = true;
+            self.dirty.push(old);
+        }
+
+        println!("client connected; id={:?}; keep_alive={}s; token={:?}", id, keep_alive, token);
+
+        {
+            let client = &mut self.clients[token];
+
+            client.id = Some(id);
+
+            if keep_alive > 0 {
+                client.keep_alive = Some(keep_alive as u64 * 1_500);
+            }
+        }
+
+        // Sessions are never stored, so there is never one present. The
+        // client learns from this that its subscriptions are gone.
+        self.send(token, &[CONNACK << 4, 2, 0, ACCEPTED]);
+    }
+
+    // Rejects a CONNECT. The connection is closed once the CONNACK is sent.
+    fn refuse(&mut self, token: mio::Token, code: u8) {
+        println!("refusing connection; code={}; token={:?}", code, token);
+
+        self.send(token, &[CONNACK << 4, 2, 0, code]);
+        self.clients[token].quit = true;
+    }
+
+    // The protocol has no error packets, a misbehaving client is simply
+    // disconnected.
+    fn violation(&mut self, token: mio::Token, err: &str) {
+        println!("protocol violation, closing connection; err={}; token={:?}", err, token);
+
+        self.clients[token].closed = true;
+        self.dirty.push(token);
+    }
+
+    fn publish(&mut self, topic: &str, payload: &[u8]) {
+        // A client gets a message once, however many of its filters match
+        let mut targets = HashSet::new();
+
+        for (filter, subscribers) in &self.subscriptions {
+            if matches(filter, topic) {
+                targets.extend(subscribers.iter().cloned());
+            }
+        }
+
+        println!("publish; topic={:?}; len={}; subscribers={}", topic, payload.len(), targets.len());
+
+        if targets.is_empty() {
+            return;
+        }
+
+        // The packet is the same for every subscriber, encode it once
+        let mut body = vec![];
+        put_string(topic, &mut body);
+        body.extend(payload);
+
+        let mut packet = vec![];
+        encode(PUBLISH << 4, &body, &mut packet);
+
+        for target in targets {
+            self.send(target, &packet);
+        }
+    }
+
+    fn subscribe(&mut self, token: mio::Token, id: u16, filters: Vec<String>) {
+        let mut body = vec![(id >> 8) as u8, id as u8];
+
+        for filter in filters {
+            if !is_valid_filter(&filter) {
+                body.push(0x80);
+                continue;
+            }
+
+            println!("subscribe; filter={:?}; token={:?}", filter, token);
+
+            self.subscriptions.entry(filter.clone()).or_default().insert(token);
+            self.clients[token].filters.insert(filter);
+
+            // Whatever QoS was asked for, 0 is the most that is granted
+            body.push(0);
+        }
+
+        let mut packet = vec![];
+        encode(SUBACK << 4, &body, &mut packet);
+        self.send(token, &packet);
+    }
+
+    fn send(&mut self, token: mio::Token, packet: &[u8]) {
+        self.clients[token].out.extend(packet);
+        self.dirty.push(token);
+    }
+}
+
+impl mio::Handler for Broker {
+    type Timeout = mio::Token;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Broker>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+                self.accept(event_loop);
+            }
+            _ => {
+                self.client_ready(event_loop, token, events);
+                self.flush(event_loop);
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Broker>, token: mio::Token) {
+        // Timers are cleared when a client is removed, so the token still
+        // refers to the client the timer was set for.
+        let client = &mut self.clients[token];
+
+        println!("client went quiet, disconnecting; id={:?}; token={:?}", client.id, token);
+
+        client.timer = None;
+        client.closed = true;
+
+        self.dirty.push(token);
+        self.flush(event_loop);
+    }
+}
+
+struct Client {
+    socket: TcpStream,
+    token: mio::Token,
+    // Bytes of an incomplete packet
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Set by CONNECT
+    id: Option<String>,
+    // How long the client may go without sending anything, in ms
+    keep_alive: Option<u64>,
+    timer: Option<mio::Timeout>,
+    // The topic filters the client is subscribed with
+    filters: HashSet<String>,
+    // Set once a CONNECT has been refused. The connection is closed after
+    // the CONNACK is flushed.
+    quit: bool,
+    closed: bool,
+}
+
+impl acceptor::Connection for Client {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Client {
+    fn new(socket: TcpStream, token: mio::Token) -> Client {
+        Client {
+            socket: socket,
+            token: token,
+            buf: vec![],
+            out: vec![],
+            id: None,
+            keep_alive: None,
+            timer: None,
+            filters: HashSet::new(),
+            quit: false,
+            closed: false,
+        }
+    }
+
+    // Returns the complete packets read, or None if the connection is
+    // closed.
+    fn read(&mut self) -> Option<Vec<Packet>> {
+        let mut buf = [0; 4096];
+        let mut packets = vec![];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => return None,
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    loop {
+                        match decode(&self.buf) {
+                            Ok(Some((packet, len))) => {
+                                self.buf.drain(..len);
+                                packets.push(packet);
+                            }
+                            Ok(None) => break,
+                            Err(e) => {
+                                println!("malformed packet, closing connection; err={}; token={:?}", e, self.token);
+                                return None;
+                            }
+                        }
+                    }
+                }
+                Ok(None) => return Some(packets),
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        if self.quit {
+            self.closed = true;
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        let mut interest = mio::EventSet::none();
+
+        if !self.quit {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.out.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        interest
+    }
+}
+
+/*
+ *
+ * ===== MQTT packets =====
+ *
+ */
+
+// The packets a client may send that the broker understands
+#[derive(Debug)]
+enum Packet {
+    Connect {
+        // The protocol level, 4 for MQTT 3.1.1
+        level: u8,
+        client_id: String,
+        clean_session: bool,
+        // In seconds, 0 means none
+        keep_alive: u16,
+    },
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        qos: u8,
+    },
+    Subscribe {
+        id: u16,
+        filters: Vec<String>,
+    },
+    PingReq,
+    Disconnect,
+}
+
+// Decodes the packet at the start of `buf`. Returns the packet and the
+// number of bytes it used, or None if the packet is not complete yet.
+//
+// Every packet starts with a fixed header: one byte holding the type and
+// flags, then the length of the rest of the packet. The length is encoded
+// in 1 to 4 bytes, 7 bits at a time, least significant first, with the
+// high bit set on all but the last byte.
+fn decode(buf: &[u8]) -> Result<Option<(Packet, usize)>, &'static str> {
+    if buf.is_empty() {
+        return Ok(None);
+    }
+
+    let kind = buf[0] >> 4;
+    let flags = buf[0] & 0x0f;
+
+    let mut len = 0;
+    let mut pos = 1;
+
+    loop {
+        if pos == 5 {
+            return Err("malformed remaining length");
+        }
+
+        let byte = match buf.get(pos) {
+            Some(byte) => *byte,
+            None => return Ok(None),
+        };
+
+        len |= ((byte & 0x7f) as usize) << (7 * (pos - 1));
+        pos += 1;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    if len > MAX_PACKET {
+        return Err("packet too large");
+    }
+
+    if buf.len() < pos + len {
+        return Ok(None);
+    }
+
+    let mut body = Fields { buf: &buf[pos..pos + len] };
+
+    // The flags are fixed for every packet but PUBLISH
+    match (kind, flags) {
+        (PUBLISH, _) | (SUBSCRIBE, 0b0010) => {}
+        (SUBSCRIBE, _) => return Err("invalid flags"),
+        (_, 0) => {}
+        _ => return Err("invalid flags"),
+    }
+
+    let packet = match kind {
+        CONNECT => {
+            if body.string()? != "MQTT" {
+                return Err("unknown protocol name");
+            }
+
+            let level = body.u8()?;
+            let connect_flags = body.u8()?;
+            let keep_alive = body.u16()?;
+
+            // The payload also holds the will and the credentials, which
+            // the broker has no use for.
+            let client_id = body.string()?;
+
+            Packet::Connect {
+                level: level,
+                client_id: client_id,
+                clean_session: connect_flags & 0b10 != 0,
+                keep_alive: keep_alive,
+            }
+        }
+        PUBLISH => {
+            // The lowest flag bit asks for the message to be retained.
+            // Retained messages aren't stored, so it is ignored.
+            let qos = (flags >> 1) & 0b11;
+            let topic = body.string()?;
+
+            if qos == 3 {
+                return Err("invalid QoS");
+            }
+
+            if topic.is_empty() || topic.contains(['+', '#']) {
+                return Err("invalid topic name");
+            }
+
+            // Only messages with a QoS above 0 have an identifier
+            if qos > 0 {
+                body.u16()?;
+            }
+
+            Packet::Publish {
+                topic: topic,
+                payload: body.buf.to_vec(),
+                qos: qos,
+            }
+        }
+        SUBSCRIBE => {
+            let id = body.u16()?;
+            let mut filters = vec![];
+
+            // Each filter is followed by the QoS asked for
+            while !body.buf.is_empty() {
+                filters.push(body.string()?);
+                body.u8()?;
+            }
+
+            if filters.is_empty() {
+                return Err("SUBSCRIBE without filters");
+            }
+
+            Packet::Subscribe {
+                id: id,
+                filters: filters,
+            }
+        }
+        PINGREQ => Packet::PingReq,
+        DISCONNECT => Packet::Disconnect,
+        _ => return Err("unsupported packet type"),
+    };
+
+    Ok(Some((packet, pos + len)))
+}
+
+// Reads the fields of a packet body
+struct Fields<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn u8(&mut self) -> Result<u8, &'static str> {
+        if self.buf.is_empty() {
+            return Err("packet too short");
+        }
+
+        let byte = self.buf[0];
+        self.buf = &self.buf[1..];
+        Ok(byte)
+    }
+
+    fn u16(&mut self) -> Result<u16, &'static str> {
+        let hi = self.u8()? as u16;
+        let lo = self.u8()? as u16;
+        Ok(hi << 8 | lo)
+    }
+
+    // Strings are UTF-8, prefixed by their length as a u16
+    fn string(&mut self) -> Result<String, &'static str> {
+        let len = self.u16()? as usize;
+
+        if self.buf.len() < len {
+            return Err("packet too short");
+        }
+
+        let s = match std::str::from_utf8(&self.buf[..len]) {
+            Ok(s) => s.to_string(),
+            Err(_) => return Err("invalid UTF-8"),
+        };
+
+        self.buf = &self.buf[len..];
+        Ok(s)
+    }
+}
+
+// Writes a packet with the given first byte and body to `dst`
+fn encode(header: u8, body: &[u8], dst: &mut Vec<u8>) {
+    let mut len = body.len();
+
+    dst.push(header);
+
+    loop {
+        let mut byte = (len & 0x7f) as u8;
+        len >>= 7;
+
+        if len > 0 {
+            byte |= 0x80;
+        }
+
+        dst.push(byte);
+
+        if len == 0 {
+            break;
+        }
+    }
+
+    dst.extend(body);
+}
+
+fn put_string(s: &str, dst: &mut Vec<u8>) {
+    dst.push((s.len() >> 8) as u8);
+    dst.push(s.len() as u8);
+    dst.extend(s.as_bytes());
+}
+
+/*
+ *
+ * ===== Topics =====
+ *
+ */
+
+// Topics are split into levels by `/`. In a filter, `+` matches any one
+// level and `#` (only allowed as the last level) matches any number of
+// them, including none.
+fn is_valid_filter(filter: &str) -> bool {
+    let levels: Vec<&str> = filter.split('/').collect();
+
+    !filter.is_empty() && levels.iter().enumerate().all(|(i, level)| {
+        match *level {
+            "+" => true,
+            "#" => i == levels.len() - 1,
+            level => !level.contains(['+', '#']),
+        }
+    })
+}
+
+fn matches(filter: &str, topic: &str) -> bool {
+    // Topics starting with `$` are reserved for the server, wildcards at the
+    // first level don't match them.
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+
+    let mut filter = filter.split('/');
+    let mut topic = topic.split('/');
+
+    loop {
+        match (filter.next(), topic.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(f), Some(t)) if f == t => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:1883".to_string())
+        .parse().unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, Client::new as Factory<Client>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mut broker = Broker::new(acceptor);
+
+    println!("running MQTT broker; addr={:?}", address);
+    event_loop.run(&mut broker).unwrap();
+}
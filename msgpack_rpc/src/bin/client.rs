@@ -0,0 +1,236 @@
+extern crate mio;
+extern crate bytes;
+extern crate msgpack_rpc;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use msgpack_rpc::msgpack::Value;
+use msgpack_rpc::rpc::{self, Message};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Instant;
+use std::env;
+
+const CLIENT: mio::Token = mio::Token(0);
+
+// A request that has been sent and is waiting for its response
+#[derive(Debug)]
+struct Call {
+    method: String,
+    sent: Instant,
+}
+
+struct Client {
+    socket: TcpStream,
+    connected: bool,
+    // Bytes read but not yet decoded
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Calls waiting for a response, by msgid. Unlike with Redis, responses
+    // don't come back in the order the requests were sent, so they are
+    // found by id.
+    calls: HashMap<u32, Call>,
+    next_id: u32,
+}
+
+impl Client {
+    fn new(socket: TcpStream) -> Client {
+        Client {
+            socket: socket,
+            connected: false,
+            buf: vec![],
+            out: vec![],
+            calls: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    fn call(&mut self, method: &str, params: Vec<Value>) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        println!("request; id={}; method={:?}; params={:?}", id, method, params);
+
+        Message::Request {
+            id: id,
+            method: method.to_string(),
+            params: params,
+        }.encode(&mut self.out);
+
+        self.calls.insert(id, Call {
+            method: method.to_string(),
+            sent: Instant::now(),
+        });
+    }
+
+    fn notify(&mut self, method: &str, params: Vec<Value>) {
+        println!("notification; method={:?}; params={:?}", method, params);
+
+        Message::Notification {
+            method: method.to_string(),
+            params: params,
+        }.encode(&mut self.out);
+    }
+
+    // Sends every request at once, without waiting for any response. The
+    // slow calls go first, so the faster ones overtake them.
+    fn send_requests(&mut self) {
+        self.call("sleep", vec![Value::Int(500)]);
+        self.call("sleep", vec![Value::Int(100)]);
+        self.call("add", vec![Value::Int(1), Value::Int(2), Value::Int(3)]);
+        self.call("echo", vec![Value::str("hello")]);
+        self.call("echo", vec![Value::Bin(vec![0, 1, 2, 255])]);
+        self.call("add", vec![Value::str("one")]);
+        self.call("missing", vec![]);
+        self.notify("log", vec![Value::str("requests sent")]);
+    }
+
+    // Returns false if the connection is closed or failed
+    fn read(&mut self) -> bool {
+        let mut buf = [0; 4096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    println!("server closed the connection");
+                    return false;
+                }
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    if !self.decode() {
+                        return false;
+                    }
+                }
+                Ok(None) => return true,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    // Matches every complete response in the buffer with its call
+    fn decode(&mut self) -> bool {
+        loop {
+            let (message, len) = match rpc::decode(&self.buf) {
+                Ok(Some(message)) => message,
+                Ok(None) => return true,
+                Err(e) => {
+                    println!("invalid message; err={:?}", e);
+                    return false;
+                }
+            };
+
+            self.buf.drain(..len);
+
+            let (id, result) = match message {
+                Message::Response { id, result } => (id, result),
+                message => {
+                    println!("unexpected message; message={:?}", message);
+                    return false;
+                }
+            };
+
+            let call = match self.calls.remove(&id) {
+                Some(call) => call,
+                None => {
+                    println!("response to an unknown request; id={}", id);
+                    return false;
+                }
+            };
+
+            println!("response; id={}; method={:?}; result={:?}; latency={:?}",
+                     id, call.method, result, call.sent.elapsed());
+        }
+    }
+
+    fn write(&mut self) -> bool {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return true,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+impl mio::Handler for Client {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Client>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, CLIENT);
+
+        if !self.connected {
+            // The connect is non-blocking, the socket becoming writable
+            // means it completed, successfully or not.
+            if let Err(e) = self.socket.take_socket_error() {
+                println!("failed to connect; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+
+            self.connected = true;
+            self.send_requests();
+        }
+
+        if events.is_readable() && !self.read() {
+            event_loop.shutdown();
+            return;
+        }
+
+        if !self.write() {
+            event_loop.shutdown();
+            return;
+        }
+
+        if self.calls.is_empty() {
+            println!("all calls completed");
+            event_loop.shutdown();
+            return;
+        }
+
+        event_loop.reregister(&self.socket, CLIENT, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("127.0.0.1:18800".to_string())
+        .parse().unwrap();
+
+    let socket = TcpStream::connect(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+
+    // The socket becomes writable once connected
+    event_loop.register_opt(
+        &socket,
+        CLIENT,
+        mio::EventSet::writable(),
+        mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+
+    let mut client = Client::new(socket);
+
+    event_loop.run(&mut client).unwrap();
+}
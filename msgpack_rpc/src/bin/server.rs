@@ -0,0 +1,336 @@
+extern crate mio;
+extern crate bytes;
+extern crate msgpack_rpc;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use msgpack_rpc::msgpack::Value;
+use msgpack_rpc::rpc::{self, Message};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// The longest `sleep` allowed
+const MAX_SLEEP_MS: u64 = 60_000;
+
+// What a method handler produces. Most methods have their result right
+// away, others only after some time has passed. The event loop can't
+// block, so those hand back the result along with how long to hold on to
+// it, and a timer completes the call.
+enum Reply {
+    Now(Result<Value, Value>),
+    After(u64, Result<Value, Value>),
+}
+
+type Method = fn(&[Value]) -> Reply;
+
+// A response to send when its timer fires
+struct Deferred {
+    token: mio::Token,
+    // Tokens are reused once a connection is closed, this makes sure the
+    // response only goes to the connection that made the call.
+    conn_id: u64,
+    response: Message,
+}
+
+struct Server {
+    server: TcpListener,
+    connections: Slab<Connection>,
+    methods: HashMap<&'static str, Method>,
+    accepted: u64,
+}
+
+impl Server {
+    fn new(server: TcpListener) -> Server {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Server {
+            server: server,
+            connections: slab,
+            methods: HashMap::new(),
+            accepted: 0,
+        }
+    }
+
+    fn register(&mut self, name: &'static str, method: Method) {
+        self.methods.insert(name, method);
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Server>) {
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => {
+                println!("the server socket wasn't actually ready");
+                return;
+            }
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        self.accepted += 1;
+        let conn = Connection::new(socket, self.accepted);
+
+        let token = match self.connections.insert(conn) {
+            Ok(token) => token,
+            Err(_) => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        event_loop.register_opt(
+            &self.connections[token].socket,
+            token,
+            mio::EventSet::readable(),
+            mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+    }
+
+    fn conn_ready(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            match self.connections[token].read() {
+                Some(messages) => {
+                    for message in messages {
+                        self.handle(event_loop, token, message);
+                    }
+                }
+                None => self.connections[token].closed = true,
+            }
+        }
+
+        self.reregister(event_loop, token);
+    }
+
+    // Flushes what it can of the connection's output, then either waits
+    // for more events or, if it is done, removes it.
+    fn reregister(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token) {
+        let conn = &mut self.connections[token];
+
+        conn.write();
+
+        if conn.closed {
+            // Calls still waiting on a timer are dropped when it fires
+            let _ = self.connections.remove(token);
+            return;
+        }
+
+        let interest = if conn.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        };
+
+        event_loop.reregister(&conn.socket, token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn handle(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, message: Message) {
+        let (id, method, params) = match message {
+            Message::Request { id, method, params } => (Some(id), method, params),
+            Message::Notification { method, params } => (None, method, params),
+            Message::Response { .. } => {
+                println!("unexpected response, closing connection; token={:?}", token);
+                self.connections[token].closed = true;
+                return;
+            }
+        };
+
+        println!("call; method={:?}; id={:?}; token={:?}", method, id, token);
+
+        let reply = match self.methods.get(&method[..]) {
+            Some(method) => method(&params),
+            None => Reply::Now(Err(Value::Str(format!("unknown method '{}'", method)))),
+        };
+
+        // Notifications never get a response, not even an error
+        let id = match id {
+            Some(id) => id,
+            None => return,
+        };
+
+        match reply {
+            Reply::Now(result) => {
+                self.connections[token].send(&Message::Response { id: id, result: result });
+            }
+            Reply::After(ms, result) => {
+                let deferred = Deferred {
+                    token: token,
+                    conn_id: self.connections[token].id,
+                    response: Message::Response { id: id, result: result },
+                };
+
+                event_loop.timeout_ms(deferred, ms).unwrap();
+            }
+        }
+    }
+}
+
+impl mio::Handler for Server {
+    type Timeout = Deferred;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+                self.accept(event_loop);
+            }
+            _ => self.conn_ready(event_loop, token, events),
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Server>, deferred: Deferred) {
+        match self.connections.get_mut(deferred.token) {
+            Some(conn) if conn.id == deferred.conn_id => conn.send(&deferred.response),
+            // The connection that made the call is gone
+            _ => return,
+        }
+
+        self.reregister(event_loop, deferred.token);
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    // Unique for the life of the server, unlike the token
+    id: u64,
+    // Bytes read but not yet decoded
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, id: u64) -> Connection {
+        Connection {
+            socket: socket,
+            id: id,
+            buf: vec![],
+            out: vec![],
+            closed: false,
+        }
+    }
+
+    // Returns the complete messages read, or None if the connection is
+    // closed or the client sent something that isn't a message.
+    fn read(&mut self) -> Option<Vec<Message>> {
+        let mut buf = [0; 4096];
+        let mut messages = vec![];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => return None,
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    loop {
+                        match rpc::decode(&self.buf) {
+                            Ok(Some((message, len))) => {
+                                self.buf.drain(..len);
+                                messages.push(message);
+                            }
+                            Ok(None) => break,
+                            Err(e) => {
+                                println!("invalid message, closing connection; err={:?}; id={}", e, self.id);
+                                return None;
+                            }
+                        }
+                    }
+                }
+                Ok(None) => return Some(messages),
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, message: &Message) {
+        message.encode(&mut self.out);
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/*
+ *
+ * ===== Methods =====
+ *
+ */
+
+// Returns the first param as given
+fn echo(params: &[Value]) -> Reply {
+    Reply::Now(Ok(params.first().cloned().unwrap_or(Value::Nil)))
+}
+
+// Adds up the params, which must be integers
+fn add(params: &[Value]) -> Reply {
+    let sum = params.iter()
+        .try_fold(0i64, |sum, v| v.as_int().map(|v| sum.wrapping_add(v)));
+
+    match sum {
+        Some(sum) => Reply::Now(Ok(Value::Int(sum))),
+        None => Reply::Now(Err(Value::str("expected integers"))),
+    }
+}
+
+// Responds after the given number of milliseconds. This stands in for any
+// method that has to wait on something, like a database or another
+// service. Calls made after it complete first.
+fn sleep(params: &[Value]) -> Reply {
+    match params.first().and_then(|v| v.as_int()) {
+        Some(ms) if ms >= 0 && ms as u64 <= MAX_SLEEP_MS => Reply::After(ms as u64, Ok(Value::Int(ms))),
+        _ => Reply::Now(Err(Value::str("expected a number of milliseconds"))),
+    }
+}
+
+// Prints its params. Meant to be sent as a notification.
+fn log(params: &[Value]) -> Reply {
+    println!("log; params={:?}", params);
+    Reply::Now(Ok(Value::Nil))
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:18800".to_string())
+        .parse().unwrap();
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    let mut server = Server::new(server);
+
+    server.register("echo", echo);
+    server.register("add", add);
+    server.register("sleep", sleep);
+    server.register("log", log);
+
+    println!("running msgpack-rpc server; addr={:?}", address);
+    event_loop.run(&mut server).unwrap();
+}
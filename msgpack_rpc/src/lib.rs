@@ -0,0 +1,7 @@
+// MessagePack, and the MessagePack-RPC messages built from it, shared by
+// the server and the client in `src/bin`. Like the rest of the examples'
+// protocol code, it does no I/O and works on buffers filled and drained by
+// the event loop.
+
+pub mod msgpack;
+pub mod rpc;
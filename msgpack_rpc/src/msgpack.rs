@@ -0,0 +1,420 @@
+// MessagePack encodes each value as a marker byte, saying what type it is,
+// followed by its data. Small values fit in the marker itself:
+//
+// 0x00 - 0x7f   positive integer up to 127
+// 0x80 - 0x8f   map of up to 15 pairs, followed by the keys and values
+// 0x90 - 0x9f   array of up to 15 values, followed by the values
+// 0xa0 - 0xbf   string of up to 31 bytes, followed by the bytes
+// 0xe0 - 0xff   negative integer down to -32
+//
+// Larger values have a marker of their own, followed by the length or the
+// value in big endian. Extension types are not supported.
+//
+// A marker is a single byte, and an array of one element is one more level
+// of nesting, so the decoder refuses arrays and maps nested too deep before
+// recursing into them, and values still incomplete once too much of them
+// is buffered.
+
+use std::str;
+
+// Strings, binary data, arrays and maps larger than this are refused
+pub const MAX_LEN: usize = 16 * 1_024 * 1_024;
+
+// Arrays and maps nested deeper than this are refused
+pub const MAX_DEPTH: usize = 32;
+
+// A value still incomplete once this much of it is buffered is refused
+pub const MAX_VALUE: usize = 2 * MAX_LEN;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    // Unsigned integers larger than `i64::MAX` are refused
+    Int(i64),
+    // 32 bit floats are widened
+    Float(f64),
+    Str(String),
+    Bin(Vec<u8>),
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+}
+
+impl Value {
+    pub fn str(s: &str) -> Value {
+        Value::Str(s.to_string())
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            Value::Int(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::Str(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    // Every value is encoded in its smallest form
+    pub fn encode(&self, dst: &mut Vec<u8>) {
+        match *self {
+            Value::Nil => dst.push(0xc0),
+            Value::Bool(false) => dst.push(0xc2),
+            Value::Bool(true) => dst.push(0xc3),
+            Value::Int(n) if (0..128).contains(&n) => dst.push(n as u8),
+            Value::Int(n) if (-32..0).contains(&n) => dst.push(n as u8),
+            Value::Int(n) if n >= 0 => {
+                let n = n as u64;
+
+                if n <= 0xff {
+                    put(dst, 0xcc, n, 1);
+                } else if n <= 0xffff {
+                    put(dst, 0xcd, n, 2);
+                } else if n <= 0xffff_ffff {
+                    put(dst, 0xce, n, 4);
+                } else {
+                    put(dst, 0xcf, n, 8);
+                }
+            }
+            Value::Int(n) => {
+                if n >= -0x80 {
+                    put(dst, 0xd0, n as u64, 1);
+                } else if n >= -0x8000 {
+                    put(dst, 0xd1, n as u64, 2);
+                } else if n >= -0x8000_0000 {
+                    put(dst, 0xd2, n as u64, 4);
+                } else {
+                    put(dst, 0xd3, n as u64, 8);
+                }
+            }
+            Value::Float(n) => put(dst, 0xcb, n.to_bits(), 8),
+            Value::Str(ref s) => {
+                let len = s.len() as u64;
+
+                if len < 32 {
+                    dst.push(0xa0 | len as u8);
+                } else if len <= 0xff {
+                    put(dst, 0xd9, len, 1);
+                } else if len <= 0xffff {
+                    put(dst, 0xda, len, 2);
+                } else {
+                    put(dst, 0xdb, len, 4);
+                }
+
+                dst.extend(s.as_bytes());
+            }
+            Value::Bin(ref data) => {
+                let len = data.len() as u64;
+
+                if len <= 0xff {
+                    put(dst, 0xc4, len, 1);
+                } else if len <= 0xffff {
+                    put(dst, 0xc5, len, 2);
+                } else {
+                    put(dst, 0xc6, len, 4);
+                }
+
+                dst.extend(data);
+            }
+            Value::Array(ref values) => {
+                let len = values.len() as u64;
+
+                if len < 16 {
+                    dst.push(0x90 | len as u8);
+                } else if len <= 0xffff {
+                    put(dst, 0xdc, len, 2);
+                } else {
+                    put(dst, 0xdd, len, 4);
+                }
+
+                for value in values {
+                    value.encode(dst);
+                }
+            }
+            Value::Map(ref pairs) => {
+                let len = pairs.len() as u64;
+
+                if len < 16 {
+                    dst.push(0x80 | len as u8);
+                } else if len <= 0xffff {
+                    put(dst, 0xde, len, 2);
+                } else {
+                    put(dst, 0xdf, len, 4);
+                }
+
+                for (key, value) in pairs {
+                    key.encode(dst);
+                    value.encode(dst);
+                }
+            }
+        }
+    }
+}
+
+// Writes a marker followed by the low `size` bytes of `n`, big endian
+fn put(dst: &mut Vec<u8>, marker: u8, n: u64, size: usize) {
+    dst.push(marker);
+
+    for i in (0..size).rev() {
+        dst.push((n >> (8 * i)) as u8);
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Error(pub &'static str);
+
+// Decodes a value from the start of `buf`. Returns the value and the number
+// of bytes it used, or `None` if the value hasn't been fully received.
+pub fn decode(buf: &[u8]) -> Result<Option<(Value, usize)>, Error> {
+    match decode_at(buf, 0, 0)? {
+        None if buf.len() > MAX_VALUE => Err(Error("value too large")),
+        decoded => Ok(decoded),
+    }
+}
+
+// Decodes the value at `start`, inside `depth` arrays or maps
+fn decode_at(buf: &[u8], start: usize, depth: usize) -> Result<Option<(Value, usize)>, Error> {
+    let marker = match buf.get(start) {
+        Some(marker) => *marker,
+        None => return Ok(None),
+    };
+
+    let pos = start + 1;
+
+    // The size of what follows the marker, before the data: the value for
+    // numbers, the length for everything else.
+    let size = match marker {
+        0xcc | 0xd0 | 0xc4 | 0xd9 => 1,
+        0xcd | 0xd1 | 0xc5 | 0xda | 0xdc | 0xde => 2,
+        0xce | 0xd2 | 0xca | 0xc6 | 0xdb | 0xdd | 0xdf => 4,
+        0xcf | 0xd3 | 0xcb => 8,
+        _ => 0,
+    };
+
+    let n = match read(buf, pos, size) {
+        Some(n) => n,
+        None => return Ok(None),
+    };
+
+    let pos = pos + size;
+
+    let value = match marker {
+        0x00..=0x7f => Value::Int(marker as i64),
+        0xe0..=0xff => Value::Int(marker as i8 as i64),
+        0xc0 => Value::Nil,
+        0xc2 => Value::Bool(false),
+        0xc3 => Value::Bool(true),
+        0xcc..=0xcf => {
+            if n > i64::MAX as u64 {
+                return Err(Error("integer out of range"));
+            }
+
+            Value::Int(n as i64)
+        }
+        0xd0 => Value::Int(n as u8 as i8 as i64),
+        0xd1 => Value::Int(n as u16 as i16 as i64),
+        0xd2 => Value::Int(n as u32 as i32 as i64),
+        0xd3 => Value::Int(n as i64),
+        0xca => Value::Float(f32::from_bits(n as u32) as f64),
+        0xcb => Value::Float(f64::from_bits(n)),
+        0xa0..=0xbf | 0xd9 | 0xda | 0xdb => {
+            let len = if marker <= 0xbf { (marker & 0x1f) as u64 } else { n };
+
+            return match bytes(buf, pos, len)? {
+                Some((data, pos)) => match str::from_utf8(data) {
+                    Ok(s) => Ok(Some((Value::Str(s.to_string()), pos))),
+                    Err(_) => Err(Error("invalid UTF-8")),
+                },
+                None => Ok(None),
+            };
+        }
+        0xc4..=0xc6 => {
+            return match bytes(buf, pos, n)? {
+                Some((data, pos)) => Ok(Some((Value::Bin(data.to_vec()), pos))),
+                None => Ok(None),
+            };
+        }
+        0x90..=0x9f | 0xdc | 0xdd => {
+            let len = if marker <= 0x9f { (marker & 0x0f) as u64 } else { n };
+            let mut values = vec![];
+            let mut pos = pos;
+
+            check_len(len)?;
+            check_depth(depth)?;
+
+            for _ in 0..len {
+                match decode_at(buf, pos, depth + 1)? {
+                    Some((value, next)) => {
+                        values.push(value);
+                        pos = next;
+                    }
+                    None => return Ok(None),
+                }
+            }
+
+            return Ok(Some((Value::Array(values), pos)));
+        }
+        0x80..=0x8f | 0xde | 0xdf => {
+            let len = if marker <= 0x8f { (marker & 0x0f) as u64 } else { n };
+            let mut pairs = vec![];
+            let mut pos = pos;
+
+            check_len(len)?;
+            check_depth(depth)?;
+
+            for _ in 0..len {
+                let (key, next) = match decode_at(buf, pos, depth + 1)? {
+                    Some(key) => key,
+                    None => return Ok(None),
+                };
+
+                let (value, next) = match decode_at(buf, next, depth + 1)? {
+                    Some(value) => value,
+                    None => return Ok(None),
+                };
+
+                pairs.push((key, value));
+                pos = next;
+            }
+
+            return Ok(Some((Value::Map(pairs), pos)));
+        }
+        _ => return Err(Error("unsupported type")),
+    };
+
+    Ok(Some((value, pos)))
+}
+
+// Reads a `size` byte big endian number at `pos`
+fn read(buf: &[u8], pos: usize, size: usize) -> Option<u64> {
+    if buf.len() < pos + size {
+        return None;
+    }
+
+    Some(buf[pos..pos + size].iter().fold(0, |n, b| n << 8 | *b as u64))
+}
+
+// Returns the `len` bytes at `pos`, and the position following them
+fn bytes(buf: &[u8], pos: usize, len: u64) -> Result<Option<(&[u8], usize)>, Error> {
+    check_len(len)?;
+
+    let len = len as usize;
+
+    if buf.len() < pos + len {
+        return Ok(None);
+    }
+
+    Ok(Some((&buf[pos..pos + len], pos + len)))
+}
+
+fn check_len(len: u64) -> Result<(), Error> {
+    if len > MAX_LEN as u64 {
+        return Err(Error("value too large"));
+    }
+
+    Ok(())
+}
+
+fn check_depth(depth: usize) -> Result<(), Error> {
+    if depth == MAX_DEPTH {
+        return Err(Error("nested too deep"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encoded(value: &Value) -> Vec<u8> {
+        let mut buf = vec![];
+        value.encode(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let values = vec![
+            Value::Nil,
+            Value::Bool(true),
+            Value::Int(0),
+            Value::Int(-32),
+            Value::Int(-33),
+            Value::Int(300),
+            Value::Int(i64::MAX),
+            Value::Int(i64::MIN),
+            Value::Float(1.5),
+            Value::str("hello"),
+            Value::str(&"x".repeat(300)),
+            Value::Bin(vec![0, 1, 2]),
+            Value::Array(vec![Value::Int(1), Value::Array(vec![])]),
+            Value::Map(vec![(Value::str("k"), Value::Nil)]),
+        ];
+
+        for value in values {
+            let buf = encoded(&value);
+            assert_eq!(decode(&buf), Ok(Some((value, buf.len()))));
+        }
+    }
+
+    #[test]
+    fn waits_for_truncated_values() {
+        let value = Value::Array(vec![Value::Int(100_000), Value::str("hello"), Value::Map(vec![(Value::Nil, Value::Nil)])]);
+        let buf = encoded(&value);
+
+        for len in 0..buf.len() {
+            assert_eq!(decode(&buf[..len]), Ok(None), "len={}", len);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_values() {
+        assert_eq!(decode(&[0xc1]), Err(Error("unsupported type")));
+        assert_eq!(decode(&[0xd4, 0, 0]), Err(Error("unsupported type")));
+        assert_eq!(decode(&[0xa2, 0xff, 0xfe]), Err(Error("invalid UTF-8")));
+        assert_eq!(decode(&[0xcf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]), Err(Error("integer out of range")));
+    }
+
+    #[test]
+    fn rejects_oversize_lengths() {
+        // Refused from the length alone, before any data is there
+        assert_eq!(decode(&[0xdb, 0x01, 0x00, 0x00, 0x01]), Err(Error("value too large")));
+        assert_eq!(decode(&[0xc6, 0xff, 0xff, 0xff, 0xff]), Err(Error("value too large")));
+        assert_eq!(decode(&[0xdd, 0xff, 0xff, 0xff, 0xff]), Err(Error("value too large")));
+        assert_eq!(decode(&[0xdf, 0x01, 0x00, 0x00, 0x01]), Err(Error("value too large")));
+        // The largest array allowed only takes what its elements use
+        assert_eq!(decode(&[0xdd, 0x01, 0x00, 0x00, 0x00, 0xc0]), Ok(None));
+    }
+
+    #[test]
+    fn rejects_incomplete_value_over_max() {
+        // Each binary is within the limit, the array isn't
+        let header = [0xc6, (MAX_LEN >> 24) as u8, (MAX_LEN >> 16) as u8, (MAX_LEN >> 8) as u8, MAX_LEN as u8];
+        let mut buf = vec![0x93];
+
+        buf.extend_from_slice(&header);
+        buf.resize(buf.len() + MAX_LEN, 0);
+        buf.extend_from_slice(&header);
+        buf.resize(MAX_VALUE, 0);
+
+        assert_eq!(decode(&buf), Ok(None));
+        buf.push(0);
+        assert_eq!(decode(&buf), Err(Error("value too large")));
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let mut buf = vec![0x91; MAX_DEPTH];
+        buf.push(0xc0);
+        assert!(decode(&buf).unwrap().is_some());
+
+        // Refused without recursing any deeper, however deep it goes
+        assert_eq!(decode(&[0x91; 100_000]), Err(Error("nested too deep")));
+        assert_eq!(decode(&[0x81; 100_000]), Err(Error("nested too deep")));
+    }
+}
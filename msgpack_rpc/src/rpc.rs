@@ -0,0 +1,122 @@
+// MessagePack-RPC messages are MessagePack arrays whose first element is
+// the message type:
+//
+// [0, msgid, method, params]    request
+// [1, msgid, error, result]     response, `error` is nil on success
+// [2, method, params]           notification, which gets no response
+//
+// Responses may come back in any order. The client picks the msgid of each
+// request, and the server copies it into the response, which is how the
+// client matches them up.
+
+use msgpack::{self, Error, Value};
+
+const REQUEST: i64 = 0;
+const RESPONSE: i64 = 1;
+const NOTIFICATION: i64 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Request {
+        id: u32,
+        method: String,
+        params: Vec<Value>,
+    },
+    Response {
+        id: u32,
+        // The result of a failed call is nil, and the error is usually a
+        // string.
+        result: Result<Value, Value>,
+    },
+    Notification {
+        method: String,
+        params: Vec<Value>,
+    },
+}
+
+impl Message {
+    pub fn encode(&self, dst: &mut Vec<u8>) {
+        let fields = match *self {
+            Message::Request { id, ref method, ref params } => {
+                vec![Value::Int(REQUEST), Value::Int(id as i64), Value::str(method), Value::Array(params.clone())]
+            }
+            Message::Response { id, ref result } => {
+                let (error, result) = match *result {
+                    Ok(ref result) => (Value::Nil, result.clone()),
+                    Err(ref error) => (error.clone(), Value::Nil),
+                };
+
+                vec![Value::Int(RESPONSE), Value::Int(id as i64), error, result]
+            }
+            Message::Notification { ref method, ref params } => {
+                vec![Value::Int(NOTIFICATION), Value::str(method), Value::Array(params.clone())]
+            }
+        };
+
+        Value::Array(fields).encode(dst);
+    }
+
+    pub fn from_value(value: Value) -> Result<Message, Error> {
+        let mut fields = match value {
+            Value::Array(fields) => fields.into_iter(),
+            _ => return Err(Error("message is not an array")),
+        };
+
+        let kind = fields.next().and_then(|v| v.as_int());
+
+        let message = match (kind, fields.len()) {
+            (Some(REQUEST), 3) => Message::Request {
+                id: msgid(fields.next().unwrap())?,
+                method: method(fields.next().unwrap())?,
+                params: params(fields.next().unwrap())?,
+            },
+            (Some(RESPONSE), 3) => {
+                let id = msgid(fields.next().unwrap())?;
+                let error = fields.next().unwrap();
+                let result = fields.next().unwrap();
+
+                Message::Response {
+                    id: id,
+                    result: if error == Value::Nil { Ok(result) } else { Err(error) },
+                }
+            }
+            (Some(NOTIFICATION), 2) => Message::Notification {
+                method: method(fields.next().unwrap())?,
+                params: params(fields.next().unwrap())?,
+            },
+            _ => return Err(Error("unknown message type")),
+        };
+
+        Ok(message)
+    }
+}
+
+// Decodes a message from the start of `buf`. Returns the message and the
+// number of bytes it used, or `None` if it hasn't been fully received.
+pub fn decode(buf: &[u8]) -> Result<Option<(Message, usize)>, Error> {
+    match msgpack::decode(buf)? {
+        Some((value, len)) => Ok(Some((Message::from_value(value)?, len))),
+        None => Ok(None),
+    }
+}
+
+fn msgid(value: Value) -> Result<u32, Error> {
+    match value.as_int() {
+        Some(id) if id >= 0 && id <= u32::MAX as i64 => Ok(id as u32),
+        _ => Err(Error("invalid msgid")),
+    }
+}
+
+fn method(value: Value) -> Result<String, Error> {
+    match value {
+        Value::Str(method) => Ok(method),
+        _ => Err(Error("invalid method")),
+    }
+}
+
+fn params(value: Value) -> Result<Vec<Value>, Error> {
+    match value {
+        Value::Array(params) => Ok(params),
+        _ => Err(Error("params is not an array")),
+    }
+}
@@ -0,0 +1,269 @@
+extern crate mio;
+extern crate bytes;
+extern crate libc;
+extern crate rand;
+
+use mio::{IpAddr, TryRead};
+use mio::udp::*;
+use bytes::SliceBuf;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::unix::io::AsRawFd;
+use std::{env, io, mem, process};
+
+const SOCKET: mio::Token = mio::Token(0);
+const STDIN: mio::Token = mio::Token(1);
+
+// An administratively scoped group, meant for use within an organization
+const DEFAULT_GROUP: &str = "239.255.42.99:4242";
+
+const MAX_DATAGRAM: usize = 1_024;
+
+// Every message is a single line:
+//
+//     JOIN <id> <nick>
+//     MSG <id> <nick> <text>
+//     LEAVE <id> <nick>
+//
+// The id is picked at random by each instance. With multicast loopback
+// turned on, an instance receives its own messages too and uses the id to
+// recognize them.
+struct Chat {
+    socket: UdpSocket,
+    stdin: mio::Io,
+    group: SocketAddr,
+    id: u32,
+    nick: String,
+    // Typed but not yet terminated by a newline
+    line: Vec<u8>,
+}
+
+impl Chat {
+    fn new(socket: UdpSocket, stdin: mio::Io, group: SocketAddr, nick: String) -> Chat {
+        Chat {
+            socket: socket,
+            stdin: stdin,
+            group: group,
+            id: rand::random(),
+            nick: nick,
+            line: vec![],
+        }
+    }
+
+    fn send(&mut self, kind: &str, text: &str) {
+        let mut message = format!("{} {} {}", kind, self.id, self.nick);
+
+        if !text.is_empty() {
+            message.push(' ');
+            message.push_str(text);
+        }
+
+        // Datagrams are never split, long lines are cut down to fit, without
+        // cutting a character in half.
+        let mut len = std::cmp::min(MAX_DATAGRAM, message.len());
+
+        while !message.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        message.truncate(len);
+
+        match self.socket.send_to(&mut SliceBuf::wrap(message.as_bytes()), &self.group) {
+            Ok(Some(())) => {}
+            Ok(None) => println!("the socket wasn't actually ready, dropping message"),
+            Err(e) => println!("failed to send message; err={:?}", e),
+        }
+    }
+
+    fn receive(&mut self) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event.
+        loop {
+            let mut buf = Vec::with_capacity(MAX_DATAGRAM);
+
+            let src = match self.socket.recv_from(&mut buf) {
+                Ok(Some(src)) => src,
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            let message = String::from_utf8_lossy(&buf).into_owned();
+            let mut parts = message.splitn(4, ' ');
+
+            let (kind, id, nick) = match (parts.next(), parts.next().and_then(|id| id.parse::<u32>().ok()), parts.next()) {
+                (Some(kind), Some(id), Some(nick)) => (kind, id, nick),
+                _ => {
+                    println!("ignoring invalid message; src={:?}", src);
+                    continue;
+                }
+            };
+
+            // Looped back, it was already shown when it was typed
+            if id == self.id {
+                continue;
+            }
+
+            match kind {
+                "JOIN" => println!("* {} joined from {}", nick, src),
+                "MSG" => println!("<{}> {}", nick, parts.next().unwrap_or("").trim_end()),
+                "LEAVE" => println!("* {} left", nick),
+                _ => println!("ignoring unknown message; src={:?}; kind={:?}", src, kind),
+            }
+        }
+    }
+
+    // Sends every complete line typed so far. Returns false once stdin is
+    // closed.
+    fn read_stdin(&mut self) -> bool {
+        let mut buf = [0; 1_024];
+
+        let open = match self.stdin.try_read(&mut buf) {
+            Ok(Some(0)) => false,
+            Ok(Some(n)) => {
+                self.line.extend(&buf[..n]);
+                true
+            }
+            Ok(None) => true,
+            Err(e) => {
+                println!("got an error trying to read stdin; err={:?}", e);
+                false
+            }
+        };
+
+        while let Some(pos) = self.line.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.line.drain(..pos + 1).collect();
+            let text = String::from_utf8_lossy(&line).trim().to_string();
+
+            if !text.is_empty() {
+                self.send("MSG", &text);
+            }
+        }
+
+        open
+    }
+}
+
+impl mio::Handler for Chat {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Chat>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SOCKET => {
+                assert!(events.is_readable(), "unexpected events; events={:?}", events);
+                self.receive();
+            }
+            STDIN => {
+                // Stdin is registered as level triggered, one read per event
+                // is enough.
+                if !self.read_stdin() {
+                    self.send("LEAVE", "");
+                    event_loop.shutdown();
+                }
+            }
+            _ => panic!("unexpected token"),
+        }
+    }
+}
+
+// Lets every instance on this host bind the group's port. Each one then
+// gets its own copy of the datagrams sent to the group.
+fn bind_shared(port: u16) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::v4()?;
+
+    setsockopt(&socket, libc::SOL_SOCKET, libc::SO_REUSEADDR, &(1 as libc::c_int))?;
+
+    socket.bind(&SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port)))?;
+    Ok(socket)
+}
+
+// `UdpSocket::join_multicast` lets the kernel pick the interface, by way of
+// the routing table. Joining on a specific interface takes the option
+// with the interface's address in it.
+fn join(socket: &UdpSocket, group: Ipv4Addr, interface: Ipv4Addr) -> io::Result<()> {
+    if interface.is_unspecified() {
+        return socket.join_multicast(&IpAddr::V4(group));
+    }
+
+    let mreq = libc::ip_mreq {
+        imr_multiaddr: in_addr(group),
+        imr_interface: in_addr(interface),
+    };
+
+    setsockopt(socket, libc::IPPROTO_IP, libc::IP_ADD_MEMBERSHIP, &mreq)?;
+
+    // Joining only covers receiving. Datagrams sent to the group go out the
+    // interface picked here.
+    setsockopt(socket, libc::IPPROTO_IP, libc::IP_MULTICAST_IF, &in_addr(interface))
+}
+
+fn in_addr(addr: Ipv4Addr) -> libc::in_addr {
+    libc::in_addr { s_addr: u32::from(addr).to_be() }
+}
+
+fn setsockopt<T>(socket: &UdpSocket, level: libc::c_int, name: libc::c_int, val: &T) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            val as *const _ as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t)
+    };
+
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let nick = match args.next() {
+        // The nick is a single word, it is followed by the text
+        Some(nick) if !nick.contains(char::is_whitespace) => nick,
+        _ => {
+            println!("usage: multicast_chat <nick> [group:port] [interface] [on|off]");
+            process::exit(1);
+        }
+    };
+
+    let group: SocketAddr = args.next().unwrap_or(DEFAULT_GROUP.to_string()).parse().unwrap();
+    let interface: Ipv4Addr = args.next().unwrap_or("0.0.0.0".to_string()).parse().unwrap();
+
+    // Whether datagrams sent to the group are also delivered to this host.
+    // Needed to chat with other instances on the same host.
+    let multicast_loop = args.next().map(|arg| arg != "off").unwrap_or(true);
+
+    let group_ip = match group {
+        SocketAddr::V4(addr) if addr.ip().is_multicast() => *addr.ip(),
+        _ => {
+            println!("not an IPv4 multicast group; group={}", group);
+            process::exit(1);
+        }
+    };
+
+    let socket = bind_shared(group.port()).unwrap();
+
+    join(&socket, group_ip, interface).unwrap();
+    socket.set_multicast_loop(multicast_loop).unwrap();
+
+    // Keep the chat on the local network. `set_multicast_time_to_live` passes
+    // the kernel a one byte value with a four byte length, which Linux
+    // rejects, so the option is set directly.
+    setsockopt(&socket, libc::IPPROTO_IP, libc::IP_MULTICAST_TTL, &(1 as libc::c_int)).unwrap();
+
+    let stdin = mio::Io::from_raw_fd(0);
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&socket, SOCKET, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+    event_loop.register_opt(&stdin, STDIN, mio::EventSet::readable(), mio::PollOpt::level()).unwrap();
+
+    let mut chat = Chat::new(socket, stdin, group, nick);
+
+    println!("joined group; group={}; interface={}; loop={}; type to chat, ctrl-d to leave", group, interface, multicast_loop);
+    chat.send("JOIN", "");
+
+    event_loop.run(&mut chat).unwrap();
+}
@@ -0,0 +1,359 @@
+extern crate mio;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::io::{self, BufRead};
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::{env, thread};
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 1_024;
+
+// A connection this far behind on its output is closed rather than
+// buffered for without bound
+const MAX_OUT: usize = 64 * 1_024;
+
+// How often the ticker thread sends a broadcast
+const TICK_SECS: u64 = 10;
+
+// What the other threads send the event loop. Everything the handler
+// owns, the connections included, is only ever touched from the loop's
+// thread: the others ask for what they want done, and the handler does
+// it in `notify`, between handling events.
+enum Message {
+    // Text for every connection
+    Broadcast(String),
+    // Close a connection, by its token
+    Close(mio::Token),
+    // Replaces the configuration
+    Config(Config),
+    // Prints the connections
+    List,
+}
+
+#[derive(Clone, Debug)]
+struct Config {
+    // Put in front of every broadcast
+    prefix: String,
+    // Whether the connections are told of the others coming and going
+    announce: bool,
+}
+
+struct Connection {
+    socket: TcpStream,
+    addr: SocketAddr,
+    out: Vec<u8>,
+    closed: bool,
+}
+
+// A server only ever writing to its connections, what it writes coming
+// from other threads: a ticker, and an admin console on stdin.
+struct Server {
+    server: TcpListener,
+    connections: Slab<Connection>,
+    config: Config,
+}
+
+impl Server {
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Server>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("failed to accept connection; err={:?}", e);
+                return;
+            }
+        };
+
+        let addr = match socket.peer_addr() {
+            Ok(addr) => addr,
+            Err(_) => return,
+        };
+
+        let conn = Connection {
+            socket: socket,
+            addr: addr,
+            out: vec![],
+            closed: false,
+        };
+
+        let token = match self.connections.insert(conn) {
+            Ok(token) => token,
+            Err(_) => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        event_loop.register_opt(&self.connections[token].socket, token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        println!("connected; token={:?}; addr={}", token, addr);
+
+        if self.config.announce {
+            self.broadcast(event_loop, &format!("{} joined", addr));
+        }
+
+        self.send(event_loop, token, &format!("welcome, you are {:?}", token));
+    }
+
+    fn broadcast(&mut self, event_loop: &mut mio::EventLoop<Server>, text: &str) {
+        let tokens: Vec<mio::Token> = (1..MAX_CONNECTIONS + 1).map(mio::Token).filter(|&token| self.connections.contains(token)).collect();
+        let line = format!("{}{}", self.config.prefix, text);
+
+        for token in tokens {
+            // Sending to one connection may have closed another, too far
+            // behind, with its departure announced
+            if self.connections.contains(token) {
+                self.send(event_loop, token, &line);
+            }
+        }
+    }
+
+    fn send(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, line: &str) {
+        {
+            let conn = &mut self.connections[token];
+            conn.out.extend_from_slice(line.as_bytes());
+            conn.out.extend_from_slice(b"\r\n");
+        }
+
+        self.update(event_loop, token);
+    }
+
+    // Writes what it can, then registers the socket again, or closes it
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token) {
+        let closed = {
+            let conn = &mut self.connections[token];
+            conn.write();
+
+            if !conn.closed && conn.out.len() > MAX_OUT {
+                println!("connection too far behind, closing; token={:?}", token);
+                conn.closed = true;
+            }
+
+            if !conn.closed {
+                let mut interest = mio::EventSet::readable();
+
+                if !conn.out.is_empty() {
+                    interest = interest | mio::EventSet::writable();
+                }
+
+                event_loop.reregister(&conn.socket, token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                    .unwrap();
+            }
+
+            conn.closed
+        };
+
+        if closed {
+            self.close(event_loop, token);
+        }
+    }
+
+    fn close(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token) {
+        let conn = self.connections.remove(token).unwrap();
+        println!("closed; token={:?}; addr={}", token, conn.addr);
+
+        if self.config.announce {
+            self.broadcast(event_loop, &format!("{} left", conn.addr));
+        }
+    }
+}
+
+impl mio::Handler for Server {
+    type Timeout = ();
+    type Message = Message;
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, events: mio::EventSet) {
+        if token == SERVER {
+            return self.accept(event_loop);
+        }
+
+        // Closed by a message while the event was pending
+        if !self.connections.contains(token) {
+            return;
+        }
+
+        if events.is_readable() {
+            self.connections[token].read();
+        }
+
+        self.update(event_loop, token);
+    }
+
+    fn notify(&mut self, event_loop: &mut mio::EventLoop<Server>, msg: Message) {
+        match msg {
+            Message::Broadcast(text) => self.broadcast(event_loop, &text),
+            Message::Close(token) => {
+                if token != SERVER && self.connections.contains(token) {
+                    self.send(event_loop, token, "closed by the admin");
+
+                    // What couldn't be written goes away with the socket
+                    if self.connections.contains(token) {
+                        self.close(event_loop, token);
+                    }
+                } else {
+                    println!("no such connection; token={:?}", token);
+                }
+            }
+            Message::Config(config) => {
+                println!("config updated; config={:?}", config);
+                self.config = config;
+            }
+            Message::List => {
+                println!("{} connections", self.connections.count());
+
+                for token in (1..MAX_CONNECTIONS + 1).map(mio::Token) {
+                    if let Some(conn) = self.connections.get(token) {
+                        println!("  {}: {}; {} bytes pending", token.as_usize(), conn.addr, conn.out.len());
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Connection {
+    // What the client sends is only read to notice it going away
+    fn read(&mut self) {
+        let mut chunk = [0; 4_096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) | Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => return,
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Sends a message to the loop. The channel is bounded: when it is full,
+// the loop is behind, and the sender waits for it to catch up rather than
+// dropping the message. Returns false once the loop is gone.
+fn send(channel: &mio::Sender<Message>, mut msg: Message) -> bool {
+    loop {
+        match channel.send(msg) {
+            Ok(()) => return true,
+            Err(mio::NotifyError::Full(returned)) => {
+                msg = returned;
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(_) => return false,
+        }
+    }
+}
+
+fn ticker(channel: mio::Sender<Message>) {
+    for tick in 1.. {
+        thread::sleep(Duration::from_secs(TICK_SECS));
+
+        if !send(&channel, Message::Broadcast(format!("tick {}", tick))) {
+            return;
+        }
+    }
+}
+
+// Reads commands on stdin, and sends them to the loop as messages
+fn console(channel: mio::Sender<Message>, mut config: Config) {
+    let stdin = io::stdin();
+
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+
+        // The prefix is taken as it is, trailing spaces included
+        let line = line.trim_start();
+        let (command, raw) = match line.find(' ') {
+            Some(pos) => (&line[..pos], &line[pos + 1..]),
+            None => (line.trim_end(), ""),
+        };
+
+        let arg = raw.trim();
+
+        // The console keeps a copy of the configuration, to change a field
+        // of it and send the loop the whole of it
+        let msg = match (command, arg.parse::<usize>()) {
+            ("", _) => continue,
+            ("broadcast", _) if !arg.is_empty() => Message::Broadcast(arg.to_string()),
+            ("close", Ok(token)) => Message::Close(mio::Token(token)),
+            ("prefix", _) => {
+                config.prefix = raw.to_string();
+                Message::Config(config.clone())
+            }
+            ("announce", _) if arg == "on" || arg == "off" => {
+                config.announce = arg == "on";
+                Message::Config(config.clone())
+            }
+            ("list", _) => Message::List,
+            _ => {
+                println!("commands: broadcast <text> | close <token> | prefix [text] | announce <on|off> | list");
+                continue;
+            }
+        };
+
+        if !send(&channel, msg) {
+            return;
+        }
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1).unwrap_or("0.0.0.0:9900".to_string()).parse().unwrap();
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    let config = Config {
+        prefix: "* ".to_string(),
+        announce: true,
+    };
+
+    // Each thread gets a `Sender` of its own, a clone of the loop's
+    let channel = event_loop.channel();
+    thread::spawn(move || ticker(channel));
+
+    let channel = event_loop.channel();
+    let console_config = config.clone();
+    thread::spawn(move || console(channel, console_config));
+
+    println!("running notify server; addr={:?}", address);
+
+    // Token `0` is reserved for the server socket. Tokens 1+ are used for
+    // client connections.
+    let mut server = Server {
+        server: server,
+        connections: Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS),
+        config: config,
+    };
+
+    event_loop.run(&mut server).unwrap();
+}
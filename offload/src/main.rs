@@ -0,0 +1,325 @@
+extern crate mio;
+extern crate promise;
+extern crate sha1;
+
+mod pool;
+mod work;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use pool::{Done, Pool};
+use promise::{Callback, Canceled};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::{env, process};
+use work::Work;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 1_024;
+
+// Jobs queued or running at once, past that new ones are refused: the
+// pool can't keep up, and queueing more only makes every answer later
+const MAX_JOBS: usize = 256;
+
+// A waiting connection isn't read from past this much, what it sends
+// waits in the kernel
+const MAX_BUF: usize = 16 * 1_024;
+
+struct Connection {
+    socket: TcpStream,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // The job the connection is parked on: its answer comes before those of
+    // the lines after it
+    waiting: Option<u64>,
+    eof: bool,
+    closed: bool,
+}
+
+// A line protocol server doing the slow work on a pool of threads:
+//
+//     sha1 <path>              -> the SHA-1 of the file, and its size
+//     stretch <rounds> <text>  -> the text hashed, rounds times over
+//     ping                     -> pong, from the event loop itself
+//
+// A command for the pool parks the connection: the job is sent to the
+// pool, and the loop goes on with the other connections. What to do with
+// the result is said right there, with a callback on the job's future,
+// which runs on the loop once the result is in, and the connection picks
+// up where it left off.
+struct Server {
+    server: TcpListener,
+    connections: Slab<Connection>,
+    pool: Pool<Server>,
+    // Jobs submitted so far, the id of the next one
+    jobs: u64,
+    // Submitted and not done yet
+    running: usize,
+}
+
+impl Server {
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Server>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("failed to accept connection; err={:?}", e);
+                return;
+            }
+        };
+
+        let conn = Connection {
+            socket: socket,
+            buf: vec![],
+            out: vec![],
+            waiting: None,
+            eof: false,
+            closed: false,
+        };
+
+        let token = match self.connections.insert(conn) {
+            Ok(token) => token,
+            Err(_) => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        event_loop.register_opt(&self.connections[token].socket, token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    // Answers the lines read so far, until one parks the connection
+    fn process(&mut self, token: mio::Token) {
+        loop {
+            let line = {
+                let conn = &mut self.connections[token];
+
+                if conn.waiting.is_some() {
+                    return;
+                }
+
+                let pos = match conn.buf.iter().position(|&b| b == b'\n') {
+                    Some(pos) => pos,
+                    // Nothing more is read past a full buffer, and there is
+                    // no line in it to answer. Nothing more is read at all:
+                    // the connection is closed once the error is written.
+                    None if conn.buf.len() >= MAX_BUF => {
+                        println!("line too long, closing connection; token={:?}", token);
+                        conn.reply("ERR line too long");
+                        conn.buf.clear();
+                        conn.eof = true;
+                        return;
+                    }
+                    None => return,
+                };
+
+                let line: Vec<u8> = conn.buf.drain(..pos + 1).collect();
+                String::from_utf8_lossy(&line).trim().to_string()
+            };
+
+            let answer = match parse(&line) {
+                Ok(Some(work)) => {
+                    if self.running == MAX_JOBS {
+                        "ERR busy, try again later".to_string()
+                    } else {
+                        self.jobs += 1;
+                        self.running += 1;
+
+                        let id = self.jobs;
+                        self.connections[token].waiting = Some(id);
+
+                        self.pool.submit(work).then(move |server, event_loop, result| {
+                            server.done(event_loop, token, id, result);
+                        });
+
+                        continue;
+                    }
+                }
+                Ok(None) => "pong".to_string(),
+                Err(usage) => format!("ERR {}", usage),
+            };
+
+            self.connections[token].reply(&answer);
+        }
+    }
+
+    // A job is done
+    fn done(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, id: u64, result: Result<Done, Canceled>) {
+        self.running -= 1;
+
+        // A thread panicking drops the job's promise
+        let done = result.unwrap_or_else(|_| {
+            Done {
+                answer: "ERR the job failed".to_string(),
+                ms: 0,
+            }
+        });
+
+        // The connection may have closed meanwhile, and its token gone to
+        // another one
+        match self.connections.get_mut(token) {
+            Some(conn) if conn.waiting == Some(id) => {
+                conn.waiting = None;
+                conn.reply(&done.answer);
+            }
+            _ => return,
+        }
+
+        println!("job done; token={:?}; id={}; time={}ms", token, id, done.ms);
+
+        // The lines that came in while parked
+        self.process(token);
+        self.update(event_loop, token);
+    }
+
+    // Writes what it can, then registers the socket again, or closes it
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token) {
+        let conn = &mut self.connections[token];
+        conn.write();
+
+        // A connection gone away is closed even with a job running, its
+        // result is thrown away when it comes
+        if conn.closed || conn.eof && conn.waiting.is_none() && conn.out.is_empty() && !conn.buf.contains(&b'\n') {
+            self.connections.remove(token);
+            return;
+        }
+
+        let mut interest = mio::EventSet::none();
+
+        if !conn.eof && conn.buf.len() < MAX_BUF {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !conn.out.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        event_loop.reregister(&conn.socket, token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+impl mio::Handler for Server {
+    type Timeout = ();
+    type Message = Callback<Server>;
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, events: mio::EventSet) {
+        if token == SERVER {
+            return self.accept(event_loop);
+        }
+
+        if events.is_readable() {
+            self.connections[token].read();
+        }
+
+        self.process(token);
+        self.update(event_loop, token);
+    }
+
+    // A job's callback
+    fn notify(&mut self, event_loop: &mut mio::EventLoop<Server>, callback: Callback<Server>) {
+        callback.run(self, event_loop);
+    }
+}
+
+impl Connection {
+    fn reply(&mut self, line: &str) {
+        self.out.extend_from_slice(line.as_bytes());
+        self.out.extend_from_slice(b"\r\n");
+    }
+
+    fn read(&mut self) {
+        let mut chunk = [0; 4_096];
+
+        // The socket is registered as edge triggered, drain it, up to the
+        // limit
+        while self.buf.len() < MAX_BUF {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.eof = true;
+                    return;
+                }
+                Ok(Some(n)) => self.buf.extend_from_slice(&chunk[..n]),
+                Ok(None) => return,
+                Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// The work a line asks for, `None` for a ping
+fn parse(line: &str) -> Result<Option<Work>, &'static str> {
+    let mut words = line.splitn(3, ' ');
+
+    match (words.next(), words.next(), words.next()) {
+        (Some("ping"), None, None) => Ok(None),
+        (Some("sha1"), Some(path), None) => match work::safe(path) {
+            Some(path) => Ok(Some(Work::Checksum(path))),
+            None => Err("the path must be relative, without `..`"),
+        },
+        (Some("stretch"), Some(rounds), Some(text)) => match rounds.parse() {
+            Ok(rounds) if rounds > 0 && rounds <= work::MAX_ROUNDS => Ok(Some(Work::Stretch(rounds, text.to_string()))),
+            _ => Err("rounds must be from 1 to 10000000"),
+        },
+        _ => Err("usage: sha1 <path> | stretch <rounds> <text> | ping"),
+    }
+}
+
+fn usage() -> ! {
+    println!("usage: offload [addr] [root dir] [threads]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:6100".to_string()).parse().unwrap_or_else(|_| usage());
+    let root = PathBuf::from(args.next().unwrap_or(".".to_string()));
+    let threads: usize = args.next().map(|s| s.parse().unwrap_or_else(|_| usage())).unwrap_or(4);
+
+    if threads == 0 {
+        usage();
+    }
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    println!("running offload server; addr={:?}; root={:?}; threads={}", address, root, threads);
+
+    // Token `0` is reserved for the server socket. Tokens 1+ are used for
+    // client connections.
+    let mut server = Server {
+        server: server,
+        connections: Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS),
+        pool: Pool::new(threads, root, event_loop.channel()),
+        jobs: 0,
+        running: 0,
+    };
+
+    event_loop.run(&mut server).unwrap();
+}
@@ -0,0 +1,77 @@
+// A fixed pool of threads doing the blocking work. Jobs come in through
+// a channel shared by all the threads, whichever is free takes the next
+// one. Each job comes with a promise, which the thread completes with the
+// result: that sends the loop the callback waiting for it.
+
+use mio::{self, Handler};
+use promise::{self, Callback, Future, Promise};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+use work::Work;
+
+pub struct Done {
+    pub answer: String,
+    // How long the job ran, not counting its wait in the queue
+    pub ms: u64,
+}
+
+struct Job<H: Handler + 'static> {
+    work: Work,
+    promise: Promise<Done, H>,
+}
+
+pub struct Pool<H: Handler + 'static> {
+    jobs: Sender<Job<H>>,
+    notify: mio::Sender<Callback<H>>,
+}
+
+impl<H: Handler + 'static> Pool<H> {
+    pub fn new(threads: usize, root: PathBuf, notify: mio::Sender<Callback<H>>) -> Pool<H> {
+        let (tx, rx) = mpsc::channel();
+        let rx = Arc::new(Mutex::new(rx));
+
+        for _ in 0..threads {
+            let rx = rx.clone();
+            let root = root.clone();
+
+            thread::spawn(move || work(&rx, &root));
+        }
+
+        Pool {
+            jobs: tx,
+            notify: notify,
+        }
+    }
+
+    // Queues the work, the future is for its result
+    pub fn submit(&self, work: Work) -> Future<Done, H> {
+        let (promise, future) = promise::pair(self.notify.clone());
+
+        self.jobs.send(Job { work: work, promise: promise }).unwrap();
+
+        future
+    }
+}
+
+fn work<H: Handler + 'static>(jobs: &Mutex<Receiver<Job<H>>>, root: &Path) {
+    loop {
+        // The lock is only held while waiting for a job, not while running
+        // it
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+
+        let started = Instant::now();
+        let answer = job.work.run(root);
+        let elapsed = started.elapsed();
+
+        job.promise.complete(Done {
+            answer: answer,
+            ms: elapsed.as_secs() * 1_000 + elapsed.subsec_millis() as u64,
+        });
+    }
+}
@@ -0,0 +1,80 @@
+// The work too slow for the event loop: reading a whole file from disk,
+// or burning CPU on purpose to make a password hash slow to brute force.
+// This is what runs on the pool's threads, and it blocks.
+
+use sha1::Sha1;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+// More rounds than this are refused, a job shouldn't take minutes
+pub const MAX_ROUNDS: u32 = 10_000_000;
+
+pub enum Work {
+    // The SHA-1 of a file, its path relative to the root
+    Checksum(PathBuf),
+    // The text hashed, then the hash hashed again, `rounds` times. Real
+    // key stretching uses bcrypt or scrypt, but the point is the same:
+    // each guess costs an attacker as much.
+    Stretch(u32, String),
+}
+
+impl Work {
+    // Returns the answer, a line
+    pub fn run(&self, root: &Path) -> String {
+        match *self {
+            Work::Checksum(ref path) => match checksum(&root.join(path)) {
+                Ok((digest, len)) => format!("{} {} {}", digest, len, path.display()),
+                Err(e) => format!("ERR {}", e),
+            },
+            Work::Stretch(rounds, ref text) => {
+                let mut sha1 = Sha1::new();
+                sha1.update(text.as_bytes());
+                let mut digest = sha1.digest().bytes();
+
+                for _ in 1..rounds {
+                    sha1.reset();
+                    sha1.update(&digest);
+                    digest = sha1.digest().bytes();
+                }
+
+                hex(&digest)
+            }
+        }
+    }
+}
+
+// A path that stays under the root: relative, and without `..`
+pub fn safe(path: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(path);
+
+    if path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn checksum(path: &Path) -> Result<(String, u64), String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let mut sha1 = Sha1::new();
+    let mut buf = [0; 64 * 1_024];
+    let mut len = 0;
+
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                sha1.update(&buf[..n]);
+                len += n as u64;
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+
+    Ok((sha1.digest().to_string(), len))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
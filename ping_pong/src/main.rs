@@ -1,31 +1,60 @@
+extern crate acceptor;
 extern crate mio;
 extern crate bytes;
+extern crate libc;
+#[macro_use]
+extern crate state_machine;
 
+mod signal;
+
+use acceptor::{Acceptor, Factory};
 use mio::{TryRead, TryWrite};
 use mio::tcp::*;
 use mio::util::Slab;
 use bytes::{Buf, Take};
-use std::mem;
+use signal::Signals;
 use std::io::Cursor;
 
 const SERVER: mio::Token = mio::Token(0);
+const SIGNALS: mio::Token = mio::Token(1);
 const MAX_LINE: usize = 128;
 
 struct Pong {
-    server: TcpListener,
+    acceptor: Acceptor<Factory<Connection>>,
     connections: Slab<Connection>,
+    signals: Signals,
 }
 
 impl Pong {
-    fn new(server: TcpListener) -> Pong {
-        // Token `0` is reserved for the server socket. Tokens 1+ are used for
-        // client connections. The slab is initialized to return Tokens
-        // starting at 1.
-        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+    fn new(acceptor: Acceptor<Factory<Connection>>, signals: Signals) -> Pong {
+        // Token `0` is reserved for the server socket and token `1` for the
+        // signal pipe. Tokens 2+ are used for client connections. The slab
+        // is initialized to return Tokens starting at 2.
+        let slab = Slab::new_starting_at(mio::Token(2), 1024);
 
         Pong {
-            server: server,
+            acceptor: acceptor,
             connections: slab,
+            signals: signals,
+        }
+    }
+
+    // SIGINT and SIGTERM stop the server. The connections are closed when
+    // the event loop returns and `Pong` is dropped. SIGHUP, which usually
+    // asks a server to reload its configuration, reports the server's state
+    // instead, there being no configuration to reload.
+    fn signaled(&mut self, event_loop: &mut mio::EventLoop<Pong>) {
+        for signal in self.signals.pending() {
+            match signal {
+                libc::SIGINT | libc::SIGTERM => {
+                    println!("shutting down; signal={}; connections={}", signal::name(signal), self.connections.count());
+                    event_loop.shutdown();
+                }
+                libc::SIGHUP => {
+                    println!("still running; signal={}; connections={}", signal::name(signal), self.connections.count());
+                }
+                _ => println!("ignoring signal; signal={}", signal),
+            }
         }
     }
 }
@@ -41,31 +70,18 @@ impl mio::Handler for Pong {
                 assert!(events.is_readable());
 
                 println!("the server socket is ready to accept a connection");
-                match self.server.accept() {
-                    Ok(Some(socket)) => {
-                        println!("accepted a new client socket");
-
-                        // This will fail when the connection cap is reached
-                        let token = self.connections
-                            .insert_with(|token| Connection::new(socket, token))
-                            .unwrap();
-
-                        // Register the connection with the event loop.
-                        event_loop.register_opt(
-                            &self.connections[token].socket,
-                            token,
-                            mio::EventSet::readable(),
-                            mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
-                    }
-                    Ok(None) => {
-                        println!("the server socket wasn't actually ready");
-                    }
-                    Err(e) => {
-                        println!("encountered error while accepting connection; err={:?}", e);
-                        event_loop.shutdown();
-                    }
+                let (tokens, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+                for token in tokens {
+                    println!("accepted a new client socket; token={:?}", token);
+                }
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
                 }
             }
+            SIGNALS => self.signaled(event_loop),
             _ => {
                 self.connections[token].ready(event_loop, events);
 
@@ -87,6 +103,12 @@ struct Connection {
     state: State,
 }
 
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
 impl Connection {
     fn new(socket: TcpStream, token: mio::Token) -> Connection {
         Connection {
@@ -111,16 +133,16 @@ impl Connection {
     }
 
     fn read(&mut self, event_loop: &mut mio::EventLoop<Pong>) {
-        match self.socket.try_read_buf(self.state.mut_read_buf()) {
+        match self.socket.try_read_buf(self.state.read_buf()) {
             Ok(Some(0)) => {
-                self.state = State::Closed;
+                self.state.eof();
             }
             Ok(Some(n)) => {
                 println!("read {} bytes", n);
 
                 // Look for a new line. If a new line is received, then the
                 // state is transitioned from `Reading` to `Writing`.
-                self.state.try_transition_to_writing();
+                self.state.read();
 
                 // Re-register the socket with the event loop. The current
                 // state is used to determine whether we are currently reading
@@ -138,11 +160,11 @@ impl Connection {
 
     fn write(&mut self, event_loop: &mut mio::EventLoop<Pong>) {
         // TODO: handle error
-        match self.socket.try_write_buf(self.state.mut_write_buf()) {
+        match self.socket.try_write_buf(self.state.write_buf()) {
             Ok(Some(_)) => {
                 // If the entire line has been written, transition back to the
                 // reading state
-                self.state.try_transition_to_reading();
+                self.state.written();
 
                 // Re-register the socket with the event loop.
                 self.reregister(event_loop);
@@ -159,130 +181,81 @@ impl Connection {
     }
 
     fn reregister(&self, event_loop: &mut mio::EventLoop<Pong>) {
-        event_loop.reregister(&self.socket, self.token, self.state.event_set(), mio::PollOpt::oneshot())
+        event_loop.reregister(&self.socket, self.token, self.state.interest(), mio::PollOpt::oneshot())
             .unwrap();
     }
 
     fn is_closed(&self) -> bool {
-        match self.state {
-            State::Closed => true,
-            _ => false,
-        }
+        matches!(self.state, State::Closed)
     }
 }
 
-#[derive(Debug)]
-enum State {
-    Reading(Vec<u8>),
-    Writing(Take<Cursor<Vec<u8>>>),
-    Closed,
-}
-
-impl State {
-    fn mut_read_buf(&mut self) -> &mut Vec<u8> {
-        match *self {
-            State::Reading(ref mut buf) => buf,
-            _ => panic!("connection not in reading state"),
-        }
-    }
-
-    fn read_buf(&self) -> &[u8] {
-        match *self {
-            State::Reading(ref buf) => buf,
-            _ => panic!("connection not in reading state"),
-        }
+state_machine! {
+    #[derive(Debug)]
+    enum State {
+        Reading(Vec<u8>) as read_buf: readable,
+        Writing(Take<Cursor<Vec<u8>>>) as write_buf: writable,
+        Closed: none,
     }
 
-    fn write_buf(&self) -> &Take<Cursor<Vec<u8>>> {
-        match *self {
-            State::Writing(ref buf) => buf,
-            _ => panic!("connection not in writing state"),
+    placeholder Closed;
+
+    events {
+        // Looks for a new line, if there is one the state is transitioned to
+        // writing
+        fn read(&mut self) {
+            State::Reading(buf) => match buf.iter().position(|b| *b == b'\n') {
+                // Transition the state to `Writing`, limiting the buffer to
+                // the new line (inclusive). `Cursor` allows Vec<u8> to act as
+                // a readable buffer.
+                Some(pos) => State::Writing(Take::new(Cursor::new(buf), pos + 1)),
+                None => State::Reading(buf),
+            },
         }
-    }
 
-    fn mut_write_buf(&mut self) -> &mut Take<Cursor<Vec<u8>>> {
-        match *self {
-            State::Writing(ref mut buf) => buf,
-            _ => panic!("connection not in writing state"),
+        // If the buffer being written back to the client has been consumed,
+        // switch back to the reading state. However, there already might be
+        // another line in the read buffer, so the new state is checked for
+        // one as a final step.
+        fn written(&mut self) {
+            State::Writing(buf) if buf.has_remaining() => State::Writing(buf),
+            State::Writing(buf) => {
+                let cursor = buf.into_inner();
+                let pos = cursor.position();
+                let mut buf = cursor.into_inner();
+
+                // Drop all data that has been written to the client
+                drain_to(&mut buf, pos as usize);
+
+                let mut state = State::Reading(buf);
+                state.read();
+                state
+            },
         }
-    }
 
-    // Looks for a new line, if there is one the state is transitioned to
-    // writing
-    fn try_transition_to_writing(&mut self) {
-        if let Some(pos) = self.read_buf().iter().position(|b| *b == b'\n') {
-            // First, remove the current read buffer, replacing it with an
-            // empty Vec<u8>.
-            let buf = mem::replace(self, State::Closed)
-                .unwrap_read_buf();
-
-            // Wrap in `Cursor`, this allows Vec<u8> to act as a readable
-            // buffer
-            let buf = Cursor::new(buf);
-
-            // Transition the state to `Writing`, limiting the buffer to the
-            // new line (inclusive).
-            *self = State::Writing(Take::new(buf, pos + 1));
-        }
-    }
-
-    // If the buffer being written back to the client has been consumed, switch
-    // back to the reading state. However, there already might be another line
-    // in the read buffer, so `try_transition_to_writing` is called as a final
-    // step.
-    fn try_transition_to_reading(&mut self) {
-        if !self.write_buf().has_remaining() {
-            let cursor = mem::replace(self, State::Closed)
-                .unwrap_write_buf()
-                .into_inner();
-
-            let pos = cursor.position();
-            let mut buf = cursor.into_inner();
-
-            // Drop all data that has been written to the client
-            drain_to(&mut buf, pos as usize);
-
-            *self = State::Reading(buf);
-
-            // Check for any new lines that have already been read.
-            self.try_transition_to_writing();
-        }
-    }
-
-    fn event_set(&self) -> mio::EventSet {
-        match *self {
-            State::Reading(..) => mio::EventSet::readable(),
-            State::Writing(..) => mio::EventSet::writable(),
-            _ => mio::EventSet::none(),
-        }
-    }
-
-    fn unwrap_read_buf(self) -> Vec<u8> {
-        match self {
-            State::Reading(buf) => buf,
-            _ => panic!("connection not in reading state"),
-        }
-    }
-
-    fn unwrap_write_buf(self) -> Take<Cursor<Vec<u8>>> {
-        match self {
-            State::Writing(buf) => buf,
-            _ => panic!("connection not in writing state"),
+        // The client closed its end
+        fn eof(&mut self) {
+            State::Reading(_) => State::Closed,
         }
     }
 }
 
 fn main() {
     let address = "0.0.0.0:6567".parse().unwrap();
-    let server = TcpListener::bind(&address).unwrap();
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let signals = Signals::new(&[libc::SIGINT, libc::SIGTERM, libc::SIGHUP]).unwrap();
 
     let mut event_loop = mio::EventLoop::new().unwrap();
-    event_loop.register(&server, SERVER).unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+    signals.register(&mut event_loop, SIGNALS).unwrap();
 
-    let mut pong = Pong::new(server);
+    let mut pong = Pong::new(acceptor, signals);
 
     println!("running pingpong server; port=6567");
     event_loop.run(&mut pong).unwrap();
+
+    println!("server stopped");
 }
 
 fn drain_to(vec: &mut Vec<u8>, count: usize) {
@@ -0,0 +1,159 @@
+// Unix signals delivered as readiness events, using the self-pipe trick.
+//
+// A signal handler interrupts whatever the process was doing, and can do
+// very little safely: no allocating, no locking, no printing. All it does
+// here is write the signal's number to a pipe. The read end of the pipe is
+// registered with the event loop, so the handler learns about the signal
+// like it learns about anything else, and deals with it from `ready`.
+
+use libc;
+use mio::TryRead;
+use mio::unix::{self, PipeReader, PipeWriter};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::{io, mem, ptr};
+
+// The write end of the pipe, for the signal handler. There is only one set
+// of signal handlers per process, and so only one `Signals` at a time.
+static WRITER: AtomicI32 = AtomicI32::new(-1);
+
+pub struct Signals {
+    reader: PipeReader,
+    // Kept open for the signal handler
+    _writer: PipeWriter,
+    // The actions the signals had before, put back on drop
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+impl Signals {
+    // Starts catching the given signals. Until `Signals` is dropped, they
+    // no longer have their default effect, like terminating the process.
+    pub fn new(signals: &[libc::c_int]) -> io::Result<Signals> {
+        let (reader, writer) = unix::pipe()?;
+
+        if WRITER.compare_exchange(-1, writer.as_raw_fd(), Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "signals are already being caught"));
+        }
+
+        let mut previous = vec![];
+
+        for &signal in signals {
+            match catch(signal) {
+                Ok(action) => previous.push((signal, action)),
+                Err(e) => {
+                    restore(&previous);
+                    WRITER.store(-1, Ordering::SeqCst);
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(Signals {
+            reader: reader,
+            _writer: writer,
+            previous: previous,
+        })
+    }
+
+    pub fn register<H: mio::Handler>(&self, event_loop: &mut mio::EventLoop<H>, token: mio::Token) -> io::Result<()> {
+        event_loop.register_opt(&self.reader, token, mio::EventSet::readable(), mio::PollOpt::edge())
+    }
+
+    // The signals received since the last call, in order
+    pub fn pending(&mut self) -> Vec<libc::c_int> {
+        let mut buf = [0; 64];
+        let mut signals = vec![];
+
+        // The pipe is registered as edge triggered, drain it
+        loop {
+            match self.reader.try_read(&mut buf) {
+                Ok(Some(0)) | Ok(None) => return signals,
+                Ok(Some(n)) => signals.extend(buf[..n].iter().map(|b| *b as libc::c_int)),
+                Err(e) => panic!("got an error trying to read signals; err={:?}", e),
+            }
+        }
+    }
+}
+
+impl Drop for Signals {
+    fn drop(&mut self) {
+        // A signal coming in between the two finds no pipe to write to, it
+        // is dropped
+        restore(&self.previous);
+        WRITER.store(-1, Ordering::SeqCst);
+    }
+}
+
+// Installs the handler for a signal, returning the action it replaced
+fn catch(signal: libc::c_int) -> io::Result<libc::sigaction> {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle as *const () as libc::sighandler_t;
+        // System calls interrupted by the signal are restarted, instead
+        // of failing with `EINTR`
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        let mut previous: libc::sigaction = mem::zeroed();
+
+        if libc::sigaction(signal, &action, &mut previous) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(previous)
+    }
+}
+
+fn restore(previous: &[(libc::c_int, libc::sigaction)]) {
+    for &(signal, ref action) in previous {
+        unsafe {
+            libc::sigaction(signal, action, ptr::null_mut());
+        }
+    }
+}
+
+pub fn name(signal: libc::c_int) -> &'static str {
+    match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGTERM => "SIGTERM",
+        _ => "unknown",
+    }
+}
+
+extern "C" fn handle(signal: libc::c_int) {
+    let fd = WRITER.load(Ordering::SeqCst);
+
+    if fd < 0 {
+        return;
+    }
+
+    // `write` sets errno when it fails, which would clobber the value the
+    // interrupted code is about to look at.
+    let errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+
+    // The pipe is non-blocking. If it is full, the event loop hasn't caught
+    // up with the signals already in it, and this one is dropped.
+    let byte = signal as u8;
+
+    unsafe {
+        libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+        *errno_location() = errno;
+    }
+}
+
+// Where errno lives, which every libc names differently
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno_location()
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "dragonfly"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__error()
+}
+
+#[cfg(any(target_os = "openbsd", target_os = "netbsd"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno()
+}
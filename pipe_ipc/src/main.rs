@@ -0,0 +1,386 @@
+extern crate mio;
+extern crate libc;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::unix::*;
+use mio::util::Slab;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::{env, thread};
+
+const SERVER: mio::Token = mio::Token(0);
+const JOBS: mio::Token = mio::Token(1);
+const RESULTS: mio::Token = mio::Token(2);
+
+// Stop reading from a client that has this much waiting to be handled
+const MAX_BUFFERED: usize = 64 * 1_024;
+
+// Counting primes takes a while for large numbers, which is the point: the
+// event loop keeps serving other clients while the worker is busy. Larger
+// numbers are refused, a job shouldn't take minutes.
+const MAX_N: u64 = 10_000_000;
+
+// A TCP server handing the work to a thread, over a pair of pipes. Each
+// line a client sends is a job, written to the jobs pipe:
+//
+//     <token> <id> <line>
+//
+// The worker reads the jobs, one at a time, and writes the results to the
+// results pipe, prefixed the same way. The event loop polls the pipes just
+// like the sockets: the results pipe for readability, and the jobs pipe for
+// writability while jobs are waiting for room in it.
+//
+// The id tells apart connections that got the same token. A result for a
+// connection that has gone away is dropped.
+struct Server {
+    server: TcpListener,
+    connections: Slab<Connection>,
+    jobs: PipeWriter,
+    // Jobs waiting for room in the jobs pipe
+    queued: Vec<u8>,
+    results: PipeReader,
+    // Read from the results pipe but not yet terminated by a newline
+    partial: Vec<u8>,
+    next_id: u64,
+}
+
+impl Server {
+    fn new(server: TcpListener, jobs: PipeWriter, results: PipeReader) -> Server {
+        // Tokens `0` to `2` are reserved for the server socket and the two
+        // pipes. Tokens 3+ are used for client connections.
+        let slab = Slab::new_starting_at(mio::Token(3), 1024);
+
+        Server {
+            server: server,
+            connections: slab,
+            jobs: jobs,
+            queued: vec![],
+            results: results,
+            partial: vec![],
+            next_id: 0,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Server>) {
+        match self.server.accept() {
+            Ok(Some(socket)) => {
+                let id = self.next_id;
+                self.next_id += 1;
+
+                let token = match self.connections.insert_with(|token| Connection::new(socket, token, id)) {
+                    Some(token) => token,
+                    None => {
+                        println!("connection limit reached, dropping client");
+                        return;
+                    }
+                };
+
+                event_loop.register_opt(
+                    &self.connections[token].socket,
+                    token,
+                    mio::EventSet::readable(),
+                    mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+            }
+            Ok(None) => {
+                println!("the server socket wasn't actually ready");
+            }
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+            }
+        }
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, events: mio::EventSet) {
+        let had_queued = !self.queued.is_empty();
+
+        {
+            let connection = &mut self.connections[token];
+
+            if events.is_readable() {
+                for line in connection.read() {
+                    self.queued.extend(format!("{} {} {}\n", token.as_usize(), connection.id, line).as_bytes());
+                    connection.waiting += 1;
+                }
+            }
+
+            connection.write();
+        }
+
+        if self.connections[token].closed {
+            let _ = self.connections.remove(token);
+        } else {
+            self.connections[token].reregister(event_loop);
+        }
+
+        // Otherwise, the jobs pipe is still registered, waiting for room
+        if !had_queued {
+            self.write_jobs(event_loop);
+        }
+    }
+
+    fn write_jobs(&mut self, event_loop: &mut mio::EventLoop<Server>) {
+        while !self.queued.is_empty() {
+            match self.jobs.try_write(&self.queued) {
+                Ok(Some(n)) => {
+                    self.queued.drain(..n);
+                }
+                Ok(None) => break,
+                Err(e) => panic!("got an error trying to write a job; err={:?}", e),
+            }
+        }
+
+        // The pipe is full, wait until the worker makes room in it
+        if !self.queued.is_empty() {
+            event_loop.reregister(&self.jobs, JOBS, mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn read_results(&mut self, event_loop: &mut mio::EventLoop<Server>) {
+        let mut buf = [0; 4096];
+
+        // The pipe is registered as edge triggered, drain it
+        loop {
+            match self.results.try_read(&mut buf) {
+                Ok(Some(0)) => panic!("the worker went away"),
+                Ok(Some(n)) => self.partial.extend(&buf[..n]),
+                Ok(None) => break,
+                Err(e) => panic!("got an error trying to read results; err={:?}", e),
+            }
+        }
+
+        while let Some(pos) = self.partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..pos + 1).collect();
+            let line = String::from_utf8_lossy(&line);
+            let mut parts = line.trim_end().splitn(3, ' ');
+
+            let (token, id, result) = match (parts.next().and_then(|s| s.parse().ok()), parts.next().and_then(|s| s.parse::<u64>().ok()), parts.next()) {
+                (Some(token), Some(id), Some(result)) => (mio::Token(token), id, result),
+                _ => panic!("invalid result from the worker; line={:?}", line),
+            };
+
+            let connection = match self.connections.get_mut(token) {
+                Some(connection) if connection.id == id => connection,
+                _ => {
+                    println!("dropping result for a closed connection; id={}", id);
+                    continue;
+                }
+            };
+
+            connection.out.extend(result.as_bytes());
+            connection.out.extend(b"\r\n");
+            connection.waiting -= 1;
+            connection.write();
+
+            if connection.closed {
+                let _ = self.connections.remove(token);
+            } else {
+                connection.reregister(event_loop);
+            }
+        }
+    }
+}
+
+impl mio::Handler for Server {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+                self.accept(event_loop);
+            }
+            JOBS => self.write_jobs(event_loop),
+            RESULTS => self.read_results(event_loop),
+            _ => self.connection_ready(event_loop, token, events),
+        }
+    }
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    id: u64,
+    // Read but not yet terminated by a newline
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Jobs sent to the worker whose result hasn't come back yet
+    waiting: usize,
+    // Set once the client has sent everything
+    eof: bool,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token, id: u64) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            id: id,
+            buf: vec![],
+            out: vec![],
+            waiting: 0,
+            eof: false,
+            closed: false,
+        }
+    }
+
+    // Returns the complete lines read so far
+    fn read(&mut self) -> Vec<String> {
+        let mut buf = [0; 1024];
+
+        // The socket is registered as edge triggered, keep reading until
+        // the socket is drained or there is no room left to buffer data.
+        while self.buf.len() < MAX_BUFFERED {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(Some(n)) => self.buf.extend(&buf[..n]),
+                Ok(None) => break,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        let mut lines = vec![];
+
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..pos + 1).collect();
+            let line = String::from_utf8_lossy(&line).trim().to_string();
+
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        }
+
+        lines
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        // A client that is done sending still gets the results of the
+        // jobs it sent.
+        if self.eof && self.waiting == 0 {
+            self.closed = true;
+        }
+    }
+
+    fn reregister(&self, event_loop: &mut mio::EventLoop<Server>) {
+        let mut interest = mio::EventSet::none();
+
+        if !self.eof && self.buf.len() < MAX_BUFFERED {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.out.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        event_loop.reregister(&self.socket, self.token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+/*
+ *
+ * ===== Worker =====
+ *
+ */
+
+// Runs on its own thread with nothing else to wait for, so it uses its ends
+// of the pipes with plain blocking I/O.
+fn work(jobs: PipeReader, mut results: PipeWriter) {
+    set_blocking(&jobs).unwrap();
+    set_blocking(&results).unwrap();
+
+    for line in BufReader::new(jobs).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => panic!("got an error trying to read a job; err={:?}", e),
+        };
+
+        let mut parts = line.splitn(3, ' ');
+
+        let (token, id, job) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(token), Some(id), Some(job)) => (token, id, job),
+            _ => panic!("invalid job; line={:?}", line),
+        };
+
+        let result = match job.parse::<u64>() {
+            Ok(n) if n <= MAX_N => format!("{} primes up to {}", count_primes(n), n),
+            Ok(_) => format!("ERR at most {}", MAX_N),
+            Err(_) => format!("ERR not a number: {:?}", job),
+        };
+
+        // The event loop has gone away when this fails
+        if writeln!(results, "{} {} {}", token, id, result).is_err() {
+            return;
+        }
+    }
+}
+
+fn count_primes(n: u64) -> u64 {
+    (2..n + 1).filter(|&i| (2..).take_while(|d| d * d <= i).all(|d| i % d != 0)).count() as u64
+}
+
+// `mio::unix::pipe` makes both ends non-blocking, the flag is cleared for
+// the worker's ends.
+fn set_blocking<T: AsRawFd>(fd: &T) -> io::Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd.as_raw_fd(), libc::F_GETFL);
+
+        if flags < 0 || libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags & !libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:9500".to_string())
+        .parse().unwrap();
+
+    let (jobs_reader, jobs_writer) = pipe().unwrap();
+    let (results_reader, results_writer) = pipe().unwrap();
+
+    thread::spawn(move || work(jobs_reader, results_writer));
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    // The jobs pipe is empty, this fires once right away with nothing to
+    // write. After that, `write_jobs` only rearms it when the pipe is full.
+    event_loop.register_opt(&jobs_writer, JOBS, mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+    event_loop.register_opt(&results_reader, RESULTS, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+
+    let mut server = Server::new(server, jobs_writer, results_reader);
+
+    println!("running pipe server; addr={:?}", address);
+    event_loop.run(&mut server).unwrap();
+}
@@ -0,0 +1,421 @@
+extern crate mio;
+
+use mio::{EventSet, PollOpt, TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use std::{env, fmt, process, thread};
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 4_096;
+
+// How a server registers its connections
+#[derive(Copy, Clone)]
+struct Strategy {
+    edge: bool,
+    oneshot: bool,
+}
+
+const STRATEGIES: [Strategy; 4] = [
+    Strategy { edge: true, oneshot: true },
+    Strategy { edge: true, oneshot: false },
+    Strategy { edge: false, oneshot: true },
+    Strategy { edge: false, oneshot: false },
+];
+
+impl Strategy {
+    fn opts(&self) -> PollOpt {
+        let trigger = if self.edge { PollOpt::edge() } else { PollOpt::level() };
+
+        if self.oneshot {
+            trigger | PollOpt::oneshot()
+        } else {
+            trigger
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = format!("{}, {}", if self.edge { "edge" } else { "level" }, if self.oneshot { "oneshot" } else { "persistent" });
+        f.pad(&name)
+    }
+}
+
+// The calls into the kernel the server made, counted by the server itself
+#[derive(Default, Debug)]
+struct Syscalls {
+    // `epoll_wait`, once per event loop iteration
+    polls: u64,
+    events: u64,
+    reads: u64,
+    writes: u64,
+    // `epoll_ctl`, registering and reregistering
+    ctls: u64,
+}
+
+struct Connection {
+    socket: TcpStream,
+    // What was read and couldn't be written back yet
+    pending: Vec<u8>,
+    // What the socket is registered for
+    interest: EventSet,
+}
+
+// An echo server, registering its connections the way its strategy says.
+// Everything else is the same for all strategies, except for reading:
+// edge triggered, a connection must be read until it would block, or the
+// rest of what it sent is never heard of again. Level triggered, one
+// read per event is enough, the event comes again if there is more.
+struct EchoServer {
+    listener: TcpListener,
+    connections: Slab<Connection>,
+    strategy: Strategy,
+    buf: Vec<u8>,
+    syscalls: Syscalls,
+    done: bool,
+}
+
+impl EchoServer {
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<EchoServer>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.listener.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => panic!("failed to accept connection; err={:?}", e),
+        };
+
+        socket.set_nodelay(true).unwrap();
+
+        // A persistent edge triggered registration is for both ways, once
+        // and for all: an edge only comes when something changes, a
+        // writable socket doesn't keep waking the loop up
+        let interest = if self.strategy.edge && !self.strategy.oneshot {
+            EventSet::readable() | EventSet::writable()
+        } else {
+            EventSet::readable()
+        };
+
+        let conn = Connection {
+            socket: socket,
+            pending: vec![],
+            interest: interest,
+        };
+
+        let token = self.connections.insert(conn).ok().expect("connection limit reached");
+
+        event_loop.register_opt(&self.connections[token].socket, token, interest, self.strategy.opts())
+            .unwrap();
+        self.syscalls.ctls += 1;
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<EchoServer>, token: mio::Token, events: EventSet) {
+        let strategy = self.strategy;
+        let closed = {
+            let EchoServer { ref mut connections, ref mut buf, ref mut syscalls, .. } = *self;
+            connections[token].ready(events, strategy, buf, syscalls)
+        };
+
+        if closed {
+            self.connections.remove(token);
+            return;
+        }
+
+        let conn = &mut self.connections[token];
+
+        if strategy.edge && !strategy.oneshot {
+            return;
+        }
+
+        let mut interest = EventSet::readable();
+
+        if !conn.pending.is_empty() {
+            interest = interest | EventSet::writable();
+        }
+
+        // Oneshot, the registration is spent and must be renewed after
+        // every event. Persistent and level triggered, it changes only to
+        // wait for writability while something is pending: a writable
+        // interest left on would wake the loop up all the time.
+        if strategy.oneshot || interest != conn.interest {
+            event_loop.reregister(&conn.socket, token, interest, strategy.opts())
+                .unwrap();
+            conn.interest = interest;
+            self.syscalls.ctls += 1;
+        }
+    }
+}
+
+impl Connection {
+    // Returns whether the connection is closed
+    fn ready(&mut self, events: EventSet, strategy: Strategy, buf: &mut [u8], syscalls: &mut Syscalls) -> bool {
+        if events.is_writable() && !self.pending.is_empty() {
+            syscalls.writes += 1;
+
+            match self.socket.try_write(&self.pending) {
+                Ok(Some(n)) => {
+                    self.pending.drain(..n);
+                }
+                Ok(None) => {}
+                Err(_) => return true,
+            }
+        }
+
+        if !events.is_readable() {
+            return events.is_hup() || events.is_error();
+        }
+
+        loop {
+            syscalls.reads += 1;
+
+            let n = match self.socket.try_read(buf) {
+                Ok(Some(0)) | Err(_) => return true,
+                Ok(Some(n)) => n,
+                Ok(None) => return false,
+            };
+
+            if self.pending.is_empty() {
+                syscalls.writes += 1;
+
+                match self.socket.try_write(&buf[..n]) {
+                    Ok(Some(w)) => self.pending.extend_from_slice(&buf[w..n]),
+                    Ok(None) => self.pending.extend_from_slice(&buf[..n]),
+                    Err(_) => return true,
+                }
+            } else {
+                self.pending.extend_from_slice(&buf[..n]);
+            }
+
+            if !strategy.edge {
+                return false;
+            }
+        }
+    }
+}
+
+impl mio::Handler for EchoServer {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<EchoServer>, token: mio::Token, events: EventSet) {
+        self.syscalls.events += 1;
+
+        match token {
+            SERVER => self.accept(event_loop),
+            _ => self.connection_ready(event_loop, token, events),
+        }
+    }
+
+    // The load generator is done
+    fn notify(&mut self, _: &mut mio::EventLoop<EchoServer>, _: ()) {
+        self.done = true;
+    }
+}
+
+// The load generator, the same for every strategy being compared. Every
+// connection sends a message, waits for all of it to come back, and
+// sends it again, as fast as the server answers.
+struct Load {
+    connections: Slab<Client>,
+    message: Vec<u8>,
+    // Round trip times, in microseconds
+    latencies: Vec<u32>,
+}
+
+struct Client {
+    socket: TcpStream,
+    connected: bool,
+    // What is left of the message to write
+    out: Vec<u8>,
+    // Bytes of the message that came back so far
+    received: usize,
+    sent: Instant,
+}
+
+impl Client {
+    fn send(&mut self, message: &[u8]) {
+        self.out.extend_from_slice(message);
+        self.received = 0;
+        self.sent = Instant::now();
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => panic!("failed to write; err={:?}", e),
+            }
+        }
+    }
+}
+
+impl mio::Handler for Load {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, _: &mut mio::EventLoop<Load>, token: mio::Token, events: EventSet) {
+        let Load { ref mut connections, ref message, ref mut latencies } = *self;
+        let client = &mut connections[token];
+
+        if events.is_writable() {
+            if !client.connected {
+                client.connected = true;
+                client.send(message);
+            } else {
+                client.flush();
+            }
+        }
+
+        if events.is_readable() {
+            let mut buf = [0; 16 * 1_024];
+
+            // The socket is registered as edge triggered, drain it
+            loop {
+                match client.socket.try_read(&mut buf) {
+                    Ok(Some(0)) => panic!("the server closed the connection"),
+                    Ok(Some(n)) => client.received += n,
+                    Ok(None) => break,
+                    Err(e) => panic!("failed to read; err={:?}", e),
+                }
+            }
+
+            if client.received == message.len() {
+                latencies.push(micros(client.sent.elapsed()));
+                client.send(message);
+            }
+        }
+    }
+
+    // The run is over
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Load>, _: ()) {
+        event_loop.shutdown();
+    }
+}
+
+// Runs the load against a server using the strategy, and returns the
+// round trip times and what the server counted
+fn run(strategy: Strategy, connections: usize, size: usize, duration_ms: u64) -> (Vec<u32>, Syscalls) {
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+
+    let server = thread::spawn(move || {
+        let mut event_loop = mio::EventLoop::new().unwrap();
+        event_loop.register(&listener, SERVER).unwrap();
+        tx.send(event_loop.channel()).unwrap();
+
+        // Token `0` is reserved for the server socket. Tokens 1+ are used
+        // for client connections.
+        let mut server = EchoServer {
+            listener: listener,
+            connections: Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS),
+            strategy: strategy,
+            buf: vec![0; 16 * 1_024],
+            syscalls: Syscalls::default(),
+            done: false,
+        };
+
+        // Iteration by iteration, rather than `run`, to count the polls
+        while !server.done {
+            event_loop.run_once(&mut server).unwrap();
+            server.syscalls.polls += 1;
+        }
+
+        server.syscalls
+    });
+
+    let stop = rx.recv().unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    let mut load = Load {
+        connections: Slab::new(connections),
+        message: vec![b'x'; size],
+        latencies: vec![],
+    };
+
+    for _ in 0..connections {
+        let socket = TcpStream::connect(&addr).unwrap();
+        socket.set_nodelay(true).unwrap();
+
+        let client = Client {
+            socket: socket,
+            connected: false,
+            out: vec![],
+            received: 0,
+            sent: Instant::now(),
+        };
+
+        let token = load.connections.insert(client).ok().unwrap();
+
+        // The socket becomes writable once connected, and the first
+        // message is sent then
+        event_loop.register_opt(&load.connections[token].socket, token, EventSet::readable() | EventSet::writable(), PollOpt::edge())
+            .unwrap();
+    }
+
+    event_loop.timeout_ms((), duration_ms).unwrap();
+    event_loop.run(&mut load).unwrap();
+
+    stop.send(()).unwrap();
+
+    (load.latencies, server.join().unwrap())
+}
+
+fn micros(duration: Duration) -> u32 {
+    duration.as_secs() as u32 * 1_000_000 + duration.subsec_micros()
+}
+
+fn percentile(sorted: &[u32], p: f64) -> f64 {
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize] as f64 / 1_000.0
+}
+
+fn usage() -> ! {
+    println!("usage: poll_bench [connections] [message bytes] [seconds per strategy]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let connections: usize = args.next().map(|s| s.parse().unwrap_or_else(|_| usage())).unwrap_or(64);
+    let size: usize = args.next().map(|s| s.parse().unwrap_or_else(|_| usage())).unwrap_or(64);
+    let secs: u64 = args.next().map(|s| s.parse().unwrap_or_else(|_| usage())).unwrap_or(3);
+
+    if connections == 0 || connections > MAX_CONNECTIONS || size == 0 || secs == 0 {
+        usage();
+    }
+
+    println!("echo round trips; connections={}; message={}B; {}s per strategy", connections, size, secs);
+    println!();
+    println!("{:<20} {:>14} {:>8} {:>8} {:>9} {:>10} {:>9}",
+             "strategy", "round trips/s", "p50 ms", "p99 ms", "polls/rt", "events/rt", "calls/rt");
+
+    for &strategy in STRATEGIES.iter() {
+        let (mut latencies, syscalls) = run(strategy, connections, size, secs * 1_000);
+
+        if latencies.is_empty() {
+            println!("{:<20} no round trips completed", strategy);
+            continue;
+        }
+
+        latencies.sort();
+
+        let trips = latencies.len() as f64;
+        let calls = syscalls.polls + syscalls.reads + syscalls.writes + syscalls.ctls;
+
+        println!("{:<20} {:>14.0} {:>8.3} {:>8.3} {:>9.2} {:>10.2} {:>9.2}",
+                 strategy, trips / secs as f64, percentile(&latencies, 0.5), percentile(&latencies, 0.99),
+                 syscalls.polls as f64 / trips, syscalls.events as f64 / trips, calls as f64 / trips);
+        println!("{:<20} reads={}; writes={}; epoll_ctl={}", "", syscalls.reads, syscalls.writes, syscalls.ctls);
+    }
+}
@@ -0,0 +1,519 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::fs::{self, File};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// RFC 1939 limits commands to 255 bytes, responses to 512
+const MAX_LINE: usize = 512;
+
+// Message bodies are streamed to the client in chunks of this size
+const CHUNK: usize = 8 * 1_024;
+
+struct Config {
+    maildir: PathBuf,
+    user: String,
+    pass: String,
+}
+
+struct Pop3 {
+    acceptor: Acceptor<Factory<Connection>>,
+    config: Config,
+    connections: Slab<Connection>,
+}
+
+impl Pop3 {
+    fn new(acceptor: Acceptor<Factory<Connection>>, config: Config) -> Pop3 {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Pop3 {
+            acceptor: acceptor,
+            config: config,
+            connections: slab,
+        }
+    }
+}
+
+impl mio::Handler for Pop3 {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Pop3>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                let (tokens, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+                for token in tokens {
+                    println!("accepted a new client socket; token={:?}", token);
+                }
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
+                }
+            }
+            _ => {
+                self.connections[token].ready(event_loop, events, &self.config);
+
+                if self.connections[token].closed {
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Message {
+    path: PathBuf,
+    // The size reported to the client. This is the size of the file, which
+    // is a little less than the size on the wire if the file has LF line
+    // endings. Clients only use it as an estimate.
+    size: u64,
+    deleted: bool,
+}
+
+// The POP3 session states (RFC 1939). There is no separate update state,
+// the maildrop is read-only and nothing happens on QUIT.
+#[derive(Debug)]
+enum Session {
+    // Waiting for USER and PASS
+    Authorization(Option<String>),
+    // Logged in, with a snapshot of the maildrop taken at login
+    Transaction(Vec<Message>),
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    session: Session,
+    // Bytes read but not yet processed
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // The message being sent in response to RETR. While it is set, no more
+    // commands are processed, so that responses don't interleave.
+    body: Option<Body>,
+    // Set when the connection should be closed once `out` is flushed
+    quit: bool,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        Connection::interest(self)
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        let mut conn = Connection {
+            socket: socket,
+            token: token,
+            session: Session::Authorization(None),
+            buf: vec![],
+            out: vec![],
+            body: None,
+            quit: false,
+            closed: false,
+        };
+
+        conn.reply("+OK mio POP3 server ready");
+        conn
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Pop3>, events: mio::EventSet, config: &Config) {
+        if events.is_readable() {
+            self.read();
+        }
+
+        self.process(config);
+
+        if events.is_writable() || !self.out.is_empty() {
+            self.write();
+
+            // Finishing a message may leave pipelined commands to run
+            if self.body.is_none() {
+                self.process(config);
+                self.write();
+            }
+        }
+
+        if !self.closed {
+            event_loop.reregister(&self.socket, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn read(&mut self) {
+        let mut buf = [0; 1024];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    if self.buf.len() > MAX_LINE && !self.buf[..MAX_LINE].contains(&b'\n') {
+                        println!("line too long, closing connection");
+                        self.closed = true;
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    // Runs the complete commands in the read buffer
+    fn process(&mut self, config: &Config) {
+        while self.body.is_none() && !self.quit {
+            let line = match self.buf.iter().position(|b| *b == b'\n') {
+                Some(pos) => self.buf.drain(..pos + 1).collect::<Vec<u8>>(),
+                None => return,
+            };
+
+            let line = String::from_utf8_lossy(&line).trim_end().to_string();
+            self.command(&line, config);
+        }
+    }
+
+    fn command(&mut self, line: &str, config: &Config) {
+        let mut parts = line.splitn(2, ' ');
+        let cmd = parts.next().unwrap_or("").to_uppercase();
+        let arg = parts.next().unwrap_or("").trim();
+
+        // Don't log passwords
+        if cmd == "PASS" {
+            println!("received command; line=\"PASS ***\"");
+        } else {
+            println!("received command; line={:?}", line);
+        }
+
+        if cmd == "QUIT" {
+            self.quit = true;
+            return self.reply("+OK mio POP3 server signing off");
+        }
+
+        let reply = match self.session {
+            Session::Authorization(ref mut user) => {
+                match &cmd[..] {
+                    "USER" => {
+                        *user = Some(arg.to_string());
+                        "+OK".to_string()
+                    }
+                    "PASS" => {
+                        if user.take().as_ref() == Some(&config.user) && arg == config.pass {
+                            let messages = load(config);
+                            let reply = format!("+OK maildrop has {} messages", messages.len());
+                            self.session = Session::Transaction(messages);
+                            reply
+                        } else {
+                            "-ERR invalid username or password".to_string()
+                        }
+                    }
+                    _ => "-ERR command not valid before login".to_string(),
+                }
+            }
+            Session::Transaction(ref mut messages) => {
+                match &cmd[..] {
+                    "STAT" => {
+                        let (count, size) = messages.iter()
+                            .filter(|msg| !msg.deleted)
+                            .fold((0, 0), |(count, size), msg| (count + 1, size + msg.size));
+
+                        format!("+OK {} {}", count, size)
+                    }
+                    "LIST" | "UIDL" if arg.is_empty() => {
+                        let mut reply = String::from("+OK\r\n");
+
+                        for (i, msg) in messages.iter().enumerate().filter(|&(_, msg)| !msg.deleted) {
+                            if cmd == "LIST" {
+                                reply.push_str(&format!("{} {}\r\n", i + 1, msg.size));
+                            } else {
+                                reply.push_str(&format!("{} {}\r\n", i + 1, msg.uid()));
+                            }
+                        }
+
+                        reply.push('.');
+                        reply
+                    }
+                    "LIST" => {
+                        match lookup(messages, arg) {
+                            Ok(i) => format!("+OK {} {}", i + 1, messages[i].size),
+                            Err(e) => e.to_string(),
+                        }
+                    }
+                    "UIDL" => {
+                        match lookup(messages, arg) {
+                            Ok(i) => format!("+OK {} {}", i + 1, messages[i].uid()),
+                            Err(e) => e.to_string(),
+                        }
+                    }
+                    "RETR" => {
+                        let i = match lookup(messages, arg) {
+                            Ok(i) => i,
+                            Err(e) => return self.reply(e),
+                        };
+
+                        match File::open(&messages[i].path) {
+                            Ok(file) => {
+                                // The status line is sent now, the message
+                                // itself as the socket becomes writable.
+                                self.body = Some(Body::new(file));
+                                format!("+OK {} octets", messages[i].size)
+                            }
+                            Err(_) => "-ERR unable to read message".to_string(),
+                        }
+                    }
+                    "DELE" => {
+                        // Deleted messages are hidden for the rest of the
+                        // session, the files are left alone.
+                        match lookup(messages, arg) {
+                            Ok(i) => {
+                                messages[i].deleted = true;
+                                format!("+OK message {} deleted", i + 1)
+                            }
+                            Err(e) => e.to_string(),
+                        }
+                    }
+                    "RSET" => {
+                        for msg in messages.iter_mut() {
+                            msg.deleted = false;
+                        }
+
+                        "+OK".to_string()
+                    }
+                    "NOOP" => "+OK".to_string(),
+                    _ => "-ERR unknown command".to_string(),
+                }
+            }
+        };
+
+        self.reply(&reply);
+    }
+
+    fn reply(&mut self, line: &str) {
+        self.out.extend(line.as_bytes());
+        self.out.extend(b"\r\n");
+    }
+
+    fn write(&mut self) {
+        // The socket is registered as edge triggered, keep writing until the
+        // socket's buffer is full or there is nothing left to send.
+        loop {
+            if self.out.is_empty() {
+                let done = match self.body {
+                    Some(ref mut body) => !body.fill(&mut self.out),
+                    None => break,
+                };
+
+                if done {
+                    self.body = None;
+                }
+
+                continue;
+            }
+
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        if self.quit {
+            self.closed = true;
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        // While a message is being sent, commands are left unread in the
+        // socket rather than buffered.
+        if self.body.is_some() {
+            return mio::EventSet::writable();
+        }
+
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+// A message being streamed to the client. Multi-line responses end with a
+// line containing a single period, so any line of the message that starts
+// with a period gets another one added ("dot-stuffing"). Bare LF line
+// endings are converted to CRLF on the way.
+#[derive(Debug)]
+struct Body {
+    file: File,
+    // Whether the last byte sent ended a line
+    line_start: bool,
+    prev_cr: bool,
+}
+
+impl Body {
+    fn new(file: File) -> Body {
+        Body {
+            file: file,
+            line_start: true,
+            prev_cr: false,
+        }
+    }
+
+    // Appends the next chunk of the message to `dst`. Returns false once the
+    // whole message, including the terminating line, has been appended.
+    //
+    // Reading from a regular file blocks, there is no readiness to wait on.
+    // A single chunk from a local disk is fast enough for this example.
+    fn fill(&mut self, dst: &mut Vec<u8>) -> bool {
+        let mut chunk = [0; CHUNK];
+
+        let n = match self.file.read(&mut chunk) {
+            Ok(n) => n,
+            Err(e) => {
+                println!("failed to read message; err={:?}", e);
+                0
+            }
+        };
+
+        if n == 0 {
+            if !self.line_start {
+                dst.extend(b"\r\n");
+            }
+
+            dst.extend(b".\r\n");
+            return false;
+        }
+
+        for &b in &chunk[..n] {
+            if self.line_start && b == b'.' {
+                dst.push(b'.');
+            }
+
+            if b == b'\n' && !self.prev_cr {
+                dst.push(b'\r');
+            }
+
+            dst.push(b);
+
+            self.line_start = b == b'\n';
+            self.prev_cr = b == b'\r';
+        }
+
+        true
+    }
+}
+
+impl Message {
+    // The unique id of a maildir message is its file name, minus any flags
+    fn uid(&self) -> String {
+        let name = self.path.file_name().unwrap().to_string_lossy();
+        name.split(':').next().unwrap().to_string()
+    }
+}
+
+// Finds a message by its number, as given by the client
+fn lookup(messages: &[Message], arg: &str) -> Result<usize, &'static str> {
+    let i = match arg.parse::<usize>() {
+        Ok(n) if n >= 1 && n <= messages.len() => n - 1,
+        Ok(_) => return Err("-ERR no such message"),
+        Err(_) => return Err("-ERR invalid message number"),
+    };
+
+    if messages[i].deleted {
+        return Err("-ERR message is deleted");
+    }
+
+    Ok(i)
+}
+
+// Lists the messages in the maildir. Both `new` (unseen) and `cur` (seen)
+// messages are served, oldest first. Maildir file names start with the
+// delivery time, so sorting by name sorts by age.
+fn load(config: &Config) -> Vec<Message> {
+    let mut messages = vec![];
+
+    for sub in &["new", "cur"] {
+        let entries = match fs::read_dir(config.maildir.join(sub)) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let meta = match entry.metadata() {
+                Ok(meta) => meta,
+                Err(_) => continue,
+            };
+
+            if meta.is_file() && !entry.file_name().to_string_lossy().starts_with('.') {
+                messages.push(Message {
+                    path: entry.path(),
+                    size: meta.len(),
+                    deleted: false,
+                });
+            }
+        }
+    }
+
+    messages.sort_by_key(|msg| msg.path.file_name().map(|name| name.to_os_string()));
+    messages
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let maildir = PathBuf::from(args.next().unwrap_or("maildir".to_string()));
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:1110".to_string()).parse().unwrap();
+
+    let config = Config {
+        maildir: maildir,
+        user: env::var("POP3_USER").unwrap_or("user".to_string()),
+        pass: env::var("POP3_PASS").unwrap_or("pass".to_string()),
+    };
+
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mut pop3 = Pop3::new(acceptor, config);
+
+    println!("running POP3 server; addr={:?}; maildir={:?}", address, pop3.config.maildir);
+    event_loop.run(&mut pop3).unwrap();
+}
@@ -0,0 +1,451 @@
+extern crate bytes;
+extern crate mio;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::udp::*;
+use mio::util::Slab;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::{env, process};
+
+// A knock sequence has to be completed within this long of its first
+// knock
+const WINDOW_MS: u64 = 10_000;
+
+// How long the service stays open to a client that knocked right
+const OPEN_MS: u64 = 30_000;
+
+// Echo data is buffered up to this amount before the service stops
+// reading and waits for the client to catch up
+const MAX_BUFFERED: usize = 4_096;
+
+const MAX_CONNECTIONS: usize = 1_024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Knock {
+    // A connection to the port, accepted and closed right away
+    Tcp(u16),
+    // A datagram to the port, whatever is in it
+    Udp(u16),
+}
+
+impl Knock {
+    fn parse(s: &str) -> Option<Knock> {
+        let mut parts = s.splitn(2, ':');
+
+        match (parts.next(), parts.next().and_then(|port| port.parse().ok())) {
+            (Some("tcp"), Some(port)) => Some(Knock::Tcp(port)),
+            (Some("udp"), Some(port)) => Some(Knock::Udp(port)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Knock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Knock::Tcp(port) => write!(f, "tcp:{}", port),
+            Knock::Udp(port) => write!(f, "udp:{}", port),
+        }
+    }
+}
+
+enum Socket {
+    Tcp(TcpListener),
+    Udp(UdpSocket),
+}
+
+// A port knocked on. There is a socket for each, even when the sequence
+// knocks on it more than once.
+struct KnockPort {
+    knock: Knock,
+    socket: Socket,
+}
+
+enum Timer {
+    // The client's knock window is over
+    Window(IpAddr),
+    // The service is closed to the client again
+    Close(IpAddr),
+}
+
+// How far a client is in the sequence
+struct Progress {
+    // The index of the next knock expected
+    next: usize,
+    window: mio::Timeout,
+}
+
+// Keeps a service closed, until a client knocks on the right ports, in the
+// right order, within `WINDOW_MS`. Then the service is opened for that
+// client, and that client only, for `OPEN_MS`. Connections accepted while
+// it is open are left alone when it closes.
+//
+// The service itself is only listening while it is open to someone:
+// otherwise the port is closed, really closed, and a connection attempt
+// is refused like on any port nothing listens on. While it is open to a
+// client, connections from anyone else are accepted, and dropped right
+// away. The service is an echo server, standing in for whatever would be
+// protected: the point is the knocking.
+//
+// The knock ports can't be closed, they have to be listened on to notice
+// the knocks, but knocking is all they are good for.
+struct Daemon {
+    knock_ports: Vec<KnockPort>,
+    sequence: Vec<Knock>,
+    service_addr: SocketAddr,
+    service_token: mio::Token,
+    // `Some` while the service is open to at least one client
+    service: Option<TcpListener>,
+    progress: HashMap<IpAddr, Progress>,
+    open: HashMap<IpAddr, mio::Timeout>,
+    connections: Slab<Connection>,
+}
+
+impl Daemon {
+    fn new(knock_ports: Vec<KnockPort>, sequence: Vec<Knock>, service_addr: SocketAddr) -> Daemon {
+        // Tokens `0..knock_ports.len()` are reserved for the knock ports,
+        // the next one for the service, and connections use the tokens
+        // after that
+        let service_token = mio::Token(knock_ports.len());
+        let slab = Slab::new_starting_at(mio::Token(service_token.as_usize() + 1), MAX_CONNECTIONS);
+
+        Daemon {
+            knock_ports: knock_ports,
+            sequence: sequence,
+            service_addr: service_addr,
+            service_token: service_token,
+            service: None,
+            progress: HashMap::new(),
+            open: HashMap::new(),
+            connections: slab,
+        }
+    }
+
+    // Reads the knocks that came in on a knock port
+    fn knocked(&mut self, event_loop: &mut mio::EventLoop<Daemon>, token: mio::Token) {
+        let knock = self.knock_ports[token.as_usize()].knock;
+
+        // The sockets are registered as level triggered, whatever is left
+        // is picked up on the next event
+        let peer = match self.knock_ports[token.as_usize()].socket {
+            Socket::Tcp(ref listener) => {
+                match listener.accept() {
+                    // Dropped right away, the connection is all there is to
+                    // a TCP knock
+                    Ok(Some(socket)) => socket.peer_addr().ok(),
+                    Ok(None) => None,
+                    Err(e) => {
+                        println!("encountered error while accepting connection; knock={}; err={:?}", knock, e);
+                        None
+                    }
+                }
+            }
+            Socket::Udp(ref socket) => {
+                let mut buf = Vec::with_capacity(512);
+
+                match socket.recv_from(&mut buf) {
+                    Ok(addr) => addr,
+                    Err(e) => {
+                        println!("got an error trying to receive; knock={}; err={:?}", knock, e);
+                        None
+                    }
+                }
+            }
+        };
+
+        if let Some(peer) = peer {
+            self.knock(event_loop, peer.ip(), knock);
+        }
+    }
+
+    // Moves the client along the sequence, or sends it back to the start
+    fn knock(&mut self, event_loop: &mut mio::EventLoop<Daemon>, ip: IpAddr, knock: Knock) {
+        let next = self.progress.get(&ip).map(|progress| progress.next).unwrap_or(0);
+
+        let next = if self.sequence[next] == knock {
+            next + 1
+        } else if self.sequence[0] == knock {
+            // A wrong knock fails the sequence, but it may be the start of
+            // a new attempt
+            1
+        } else {
+            0
+        };
+
+        println!("knock; ip={}; knock={}; progress={}/{}", ip, knock, next, self.sequence.len());
+
+        // A new attempt gets a new window, which a wrong knock cancels
+        if next <= 1 {
+            if let Some(progress) = self.progress.remove(&ip) {
+                event_loop.clear_timeout(progress.window);
+            }
+        }
+
+        if next == 0 {
+            return;
+        }
+
+        if next == self.sequence.len() {
+            if let Some(progress) = self.progress.remove(&ip) {
+                event_loop.clear_timeout(progress.window);
+            }
+
+            self.open(event_loop, ip);
+            return;
+        }
+
+        match self.progress.get_mut(&ip) {
+            Some(progress) => progress.next = next,
+            None => {
+                let window = event_loop.timeout_ms(Timer::Window(ip), WINDOW_MS).unwrap();
+
+                self.progress.insert(ip, Progress {
+                    next: next,
+                    window: window,
+                });
+            }
+        }
+    }
+
+    fn open(&mut self, event_loop: &mut mio::EventLoop<Daemon>, ip: IpAddr) {
+        // Knocking again while it is open starts the time over
+        if let Some(timeout) = self.open.remove(&ip) {
+            event_loop.clear_timeout(timeout);
+        }
+
+        let timeout = event_loop.timeout_ms(Timer::Close(ip), OPEN_MS).unwrap();
+        self.open.insert(ip, timeout);
+
+        println!("service open; ip={}; ms={}", ip, OPEN_MS);
+
+        if self.service.is_none() {
+            match TcpListener::bind(&self.service_addr) {
+                Ok(listener) => {
+                    event_loop.register(&listener, self.service_token).unwrap();
+                    self.service = Some(listener);
+                }
+                Err(e) => println!("failed to listen on the service port; addr={:?}; err={:?}", self.service_addr, e),
+            }
+        }
+    }
+
+    fn close(&mut self, event_loop: &mut mio::EventLoop<Daemon>, ip: IpAddr) {
+        self.open.remove(&ip);
+
+        println!("service closed; ip={}", ip);
+
+        // Nobody left to serve, the port is closed again. Whatever is in
+        // the backlog is reset.
+        if self.open.is_empty() {
+            if let Some(listener) = self.service.take() {
+                event_loop.deregister(&listener).unwrap();
+            }
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Daemon>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.service.as_ref().map(|listener| listener.accept()) {
+            Some(Ok(Some(socket))) => socket,
+            Some(Ok(None)) | None => return,
+            Some(Err(e)) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                return;
+            }
+        };
+
+        let ip = match socket.peer_addr() {
+            Ok(peer) => peer.ip(),
+            Err(_) => return,
+        };
+
+        if !self.open.contains_key(&ip) {
+            println!("dropping connection, no knock; ip={}", ip);
+            return;
+        }
+
+        println!("accepted connection; ip={}", ip);
+
+        let token = match self.connections.insert(Connection::new(socket)) {
+            Ok(token) => token,
+            Err(_) => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        event_loop.register_opt(&self.connections[token].socket, token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+impl mio::Handler for Daemon {
+    type Timeout = Timer;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Daemon>, token: mio::Token, events: mio::EventSet) {
+        if token.as_usize() < self.knock_ports.len() {
+            self.knocked(event_loop, token);
+            return;
+        }
+
+        if token == self.service_token {
+            self.accept(event_loop);
+            return;
+        }
+
+        self.connections[token].ready(event_loop, token, events);
+
+        if self.connections[token].closed {
+            let _ = self.connections.remove(token);
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Daemon>, timer: Timer) {
+        match timer {
+            Timer::Window(ip) => {
+                println!("knock window over; ip={}", ip);
+                self.progress.remove(&ip);
+            }
+            Timer::Close(ip) => self.close(event_loop, ip),
+        }
+    }
+}
+
+// A connection to the protected service, which echoes
+struct Connection {
+    socket: TcpStream,
+    buf: Vec<u8>,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(socket: TcpStream) -> Connection {
+        Connection {
+            socket: socket,
+            buf: vec![],
+            closed: false,
+        }
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Daemon>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            self.read();
+        }
+
+        if !self.closed {
+            self.write();
+        }
+
+        if !self.closed {
+            event_loop.reregister(&self.socket, token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn read(&mut self) {
+        let mut buf = [0; 1_024];
+
+        // The socket is registered as edge triggered, keep reading until
+        // the socket is drained or there is no room left to buffer data.
+        while self.buf.len() < MAX_BUFFERED {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => self.buf.extend(&buf[..n]),
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.buf.is_empty() {
+            match self.socket.try_write(&self.buf) {
+                Ok(Some(n)) => {
+                    self.buf.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        let mut interest = mio::EventSet::none();
+
+        if self.buf.len() < MAX_BUFFERED {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.buf.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        interest
+    }
+}
+
+fn usage() -> ! {
+    println!("usage: port_knock <ip> <service port> <tcp:port|udp:port>...");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let ip: IpAddr = args.next().map(|ip| ip.parse().unwrap_or_else(|_| usage())).unwrap_or_else(|| "0.0.0.0".parse().unwrap());
+    let service_port: u16 = args.next().map(|port| port.parse().unwrap_or_else(|_| usage())).unwrap_or(2222);
+
+    let mut sequence: Vec<Knock> = args.map(|knock| Knock::parse(&knock).unwrap_or_else(|| usage())).collect();
+
+    if sequence.is_empty() {
+        sequence = vec![Knock::Tcp(7000), Knock::Udp(8000), Knock::Tcp(9000)];
+    }
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    let mut knock_ports: Vec<KnockPort> = vec![];
+
+    for &knock in &sequence {
+        if knock_ports.iter().any(|port| port.knock == knock) {
+            continue;
+        }
+
+        let socket = match knock {
+            Knock::Tcp(port) => Socket::Tcp(TcpListener::bind(&SocketAddr::new(ip, port)).unwrap()),
+            Knock::Udp(port) => Socket::Udp(UdpSocket::bound(&SocketAddr::new(ip, port)).unwrap()),
+        };
+
+        let token = mio::Token(knock_ports.len());
+
+        match socket {
+            Socket::Tcp(ref listener) => event_loop.register(listener, token).unwrap(),
+            Socket::Udp(ref socket) => event_loop.register(socket, token).unwrap(),
+        }
+
+        knock_ports.push(KnockPort {
+            knock: knock,
+            socket: socket,
+        });
+    }
+
+    let sequence_str: Vec<String> = sequence.iter().map(|knock| knock.to_string()).collect();
+    println!("running port knocking daemon; service={}; sequence={}", service_port, sequence_str.join(","));
+
+    let mut daemon = Daemon::new(knock_ports, sequence, SocketAddr::new(ip, service_port));
+    event_loop.run(&mut daemon).unwrap();
+}
@@ -0,0 +1,233 @@
+extern crate mio;
+extern crate bytes;
+
+use mio::tcp::*;
+use mio::util::Slab;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+use std::{env, process};
+
+// How many connects are in flight at once. Each one is a file descriptor,
+// so this has to stay below the process's limit, usually 1024.
+const CONCURRENCY: usize = 500;
+
+// A port that neither accepts nor refuses the connection in this time is
+// considered filtered.
+const CONNECT_TIMEOUT_MS: u64 = 1_500;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State {
+    // The connection was accepted
+    Open,
+    // The host refused the connection, nothing listens on the port
+    Closed,
+    // No answer, or the host was reported unreachable. A firewall is
+    // usually dropping the packets.
+    Filtered,
+    // The connect couldn't be attempted, for example because the process
+    // ran out of file descriptors or local ports.
+    Failed,
+}
+
+struct Attempt {
+    socket: TcpStream,
+    port: u16,
+    timeout: mio::Timeout,
+}
+
+struct Scanner {
+    target: IpAddr,
+    // The next port to try, and the last one. A u32, so the range can end
+    // at port 65535.
+    next: u32,
+    last: u32,
+    // Connects in flight, the slab's capacity is the concurrency limit
+    attempts: Slab<Attempt>,
+    open: Vec<u16>,
+    closed: usize,
+    filtered: usize,
+    failed: usize,
+    started: Instant,
+}
+
+impl Scanner {
+    fn new(target: IpAddr, first: u16, last: u16) -> Scanner {
+        Scanner {
+            target: target,
+            next: first as u32,
+            last: last as u32,
+            attempts: Slab::new(CONCURRENCY),
+            open: vec![],
+            closed: 0,
+            filtered: 0,
+            failed: 0,
+            started: Instant::now(),
+        }
+    }
+
+    // Starts connects until the concurrency limit is reached or every port
+    // has been tried. Shuts the event loop down once the scan is complete.
+    fn launch(&mut self, event_loop: &mut mio::EventLoop<Scanner>) {
+        while self.attempts.has_remaining() && self.next <= self.last {
+            let port = self.next as u16;
+            self.next += 1;
+
+            // The connect is non-blocking, it has only been started when
+            // this returns. A refusal from the local host may already be
+            // known though.
+            let socket = match TcpStream::connect(&SocketAddr::new(self.target, port)) {
+                Ok(socket) => socket,
+                Err(e) => {
+                    self.record(port, classify(&e));
+                    continue;
+                }
+            };
+
+            let token = self.attempts.insert_with(|token| {
+                Attempt {
+                    socket: socket,
+                    port: port,
+                    timeout: event_loop.timeout_ms(token, CONNECT_TIMEOUT_MS).unwrap(),
+                }
+            }).unwrap();
+
+            // The socket becomes writable once the connect completes, either
+            // way.
+            event_loop.register_opt(
+                &self.attempts[token].socket,
+                token,
+                mio::EventSet::writable(),
+                mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+        }
+
+        if self.attempts.is_empty() {
+            self.summary();
+            event_loop.shutdown();
+        }
+    }
+
+    fn record(&mut self, port: u16, state: State) {
+        match state {
+            State::Open => {
+                println!("open; port={}", port);
+                self.open.push(port);
+            }
+            State::Closed => self.closed += 1,
+            State::Filtered => self.filtered += 1,
+            State::Failed => self.failed += 1,
+        }
+    }
+
+    fn summary(&mut self) {
+        let elapsed = self.started.elapsed();
+        let ports = self.open.len() + self.closed + self.filtered + self.failed;
+
+        self.open.sort();
+
+        println!("scanned {} ports on {} in {}.{:03}s",
+                 ports, self.target, elapsed.as_secs(), elapsed.subsec_millis());
+        println!("open:     {}", self.open.len());
+        println!("closed:   {}", self.closed);
+        println!("filtered: {}", self.filtered);
+
+        if self.failed > 0 {
+            println!("failed:   {}", self.failed);
+        }
+
+        if !self.open.is_empty() {
+            let ports: Vec<String> = self.open.iter().map(|port| port.to_string()).collect();
+            println!("open ports: {}", ports.join(", "));
+        }
+    }
+}
+
+impl mio::Handler for Scanner {
+    // The attempt that ran out of time
+    type Timeout = mio::Token;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Scanner>, token: mio::Token, events: mio::EventSet) {
+        let attempt = self.attempts.remove(token).unwrap();
+
+        // The timeout has to go before the token can be reused
+        event_loop.clear_timeout(attempt.timeout);
+
+        // A failed connect is reported as writable too, the socket's pending
+        // error tells the two apart.
+        let state = match attempt.socket.take_socket_error() {
+            Ok(()) if events.is_writable() && !events.is_hup() => State::Open,
+            Ok(()) => State::Closed,
+            Err(e) => classify(&e),
+        };
+
+        self.record(attempt.port, state);
+
+        // Dropping the socket closes it, freeing a file descriptor for the
+        // next port.
+        drop(attempt);
+        self.launch(event_loop);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Scanner>, token: mio::Token) {
+        let attempt = self.attempts.remove(token).unwrap();
+
+        self.record(attempt.port, State::Filtered);
+
+        drop(attempt);
+        self.launch(event_loop);
+    }
+}
+
+fn classify(err: &io::Error) -> State {
+    match err.kind() {
+        io::ErrorKind::ConnectionRefused => State::Closed,
+        io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable | io::ErrorKind::TimedOut => State::Filtered,
+        _ => {
+            println!("failed to connect; err={:?}", err);
+            State::Failed
+        }
+    }
+}
+
+// Parses "80" or "1-1024"
+fn parse_range(range: &str) -> Option<(u16, u16)> {
+    let mut parts = range.splitn(2, '-');
+    let first = parts.next()?.parse().ok()?;
+    let last = match parts.next() {
+        Some(last) => last.parse().ok()?,
+        None => first,
+    };
+
+    if first == 0 || first > last {
+        return None;
+    }
+
+    Some((first, last))
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let target: IpAddr = args.next().unwrap_or("127.0.0.1".to_string()).parse().unwrap();
+
+    let (first, last) = match parse_range(&args.next().unwrap_or("1-1024".to_string())) {
+        Some(range) => range,
+        None => {
+            println!("usage: port_scanner [target] [first-last]");
+            process::exit(1);
+        }
+    };
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    let mut scanner = Scanner::new(target, first, last);
+
+    println!("scanning; target={}; ports={}-{}; concurrency={}", target, first, last, CONCURRENCY);
+
+    scanner.launch(&mut event_loop);
+
+    // Every connect may have failed right away, leaving nothing to wait for
+    if !scanner.attempts.is_empty() {
+        event_loop.run(&mut scanner).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+// A value to come, from another thread, and what the event loop does with
+// it once it is in. `pair` makes the two ends:
+//
+// * the `Promise` goes to whoever computes the value, a thread pool say,
+//   and is completed from there;
+// * the `Future` stays on the loop, which says with `then` what to do
+//   with the value: a callback, getting the handler and the event loop,
+//   like the handler's own methods do.
+//
+// The callback always runs on the loop, from `Handler::notify`: it is sent
+// through the loop's channel, as a `Callback`, by whichever of `complete`
+// and `then` comes last, and the handler runs the callbacks it gets. It
+// never runs from within `then`, even with the value in already, so the
+// code after `then` doesn't have to care which came first.
+//
+// A promise dropped without being completed, by a thread that panicked for
+// instance, completes its future with `Canceled`, rather than leave the
+// loop waiting forever.
+//
+// That is far from the composable futures of later versions of Rust, but
+// it is the start of them: the code waiting for the value is written where
+// the value is asked for, rather than in a `notify` matching on message
+// types, and `map` chains a step onto a future.
+
+extern crate mio;
+
+use mio::{EventLoop, Handler};
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// The promise was dropped without being completed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Canceled;
+
+impl fmt::Display for Canceled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the promise was dropped without a value")
+    }
+}
+
+type Run<H> = Box<dyn FnOnce(&mut H, &mut EventLoop<H>) + Send>;
+
+// What the handler's `notify` gets, and runs
+pub struct Callback<H: Handler>(Run<H>);
+
+impl<H: Handler> Callback<H> {
+    pub fn run(self, handler: &mut H, event_loop: &mut EventLoop<H>) {
+        (self.0)(handler, event_loop)
+    }
+}
+
+type Then<T, H> = Box<dyn FnOnce(&mut H, &mut EventLoop<H>, Result<T, Canceled>) + Send>;
+
+enum State<T, H: Handler> {
+    // Neither end has had its say
+    Empty,
+    // The value came first, the callback is to come
+    Value(Result<T, Canceled>),
+    // The callback came first, the value is to come
+    Waiting(Then<T, H>),
+    // The callback is on its way to the loop
+    Sent,
+}
+
+struct Inner<T, H: Handler> {
+    state: Mutex<State<T, H>>,
+    notify: mio::Sender<Callback<H>>,
+}
+
+pub struct Promise<T: Send + 'static, H: Handler + 'static> {
+    // Taken once completed
+    inner: Option<Arc<Inner<T, H>>>,
+}
+
+pub struct Future<T, H: Handler> {
+    inner: Arc<Inner<T, H>>,
+}
+
+pub fn pair<T, H>(notify: mio::Sender<Callback<H>>) -> (Promise<T, H>, Future<T, H>)
+    where T: Send + 'static,
+          H: Handler + 'static
+{
+    let inner = Arc::new(Inner {
+        state: Mutex::new(State::Empty),
+        notify: notify,
+    });
+
+    (Promise { inner: Some(inner.clone()) }, Future { inner: inner })
+}
+
+impl<T: Send + 'static, H: Handler + 'static> Promise<T, H> {
+    pub fn complete(mut self, value: T) {
+        complete(&self.inner.take().unwrap(), Ok(value));
+    }
+}
+
+impl<T: Send + 'static, H: Handler + 'static> Drop for Promise<T, H> {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            complete(&inner, Err(Canceled));
+        }
+    }
+}
+
+impl<T: Send + 'static, H: Handler + 'static> Future<T, H> {
+    // Runs `callback` on the loop once the value is in
+    pub fn then<F>(self, callback: F)
+        where F: FnOnce(&mut H, &mut EventLoop<H>, Result<T, Canceled>) + Send + 'static
+    {
+        let result = {
+            let mut state = self.inner.state.lock().unwrap();
+
+            match mem::replace(&mut *state, State::Sent) {
+                State::Empty => {
+                    *state = State::Waiting(Box::new(callback));
+                    return;
+                }
+                State::Value(result) => result,
+                _ => unreachable!(),
+            }
+        };
+
+        send(&self.inner.notify, Box::new(callback), result);
+    }
+
+    // A future for the value passed through `f`. The step runs on the loop
+    // along with the callback it follows, and so does the next callback if
+    // it is waiting already: there is no more trip through the loop's
+    // channel.
+    pub fn map<U, F>(self, f: F) -> Future<U, H>
+        where U: Send + 'static,
+              F: FnOnce(T) -> U + Send + 'static
+    {
+        let inner = Arc::new(Inner {
+            state: Mutex::new(State::Empty),
+            notify: self.inner.notify.clone(),
+        });
+
+        let next = inner.clone();
+
+        self.then(move |handler, event_loop, result| {
+            // A canceled future cancels the next one too
+            if let Some((callback, result)) = settle(&next, result.map(f)) {
+                callback(handler, event_loop, result);
+            }
+        });
+
+        Future { inner: inner }
+    }
+}
+
+// Completes a future from the promise's end, sending the callback to the
+// loop if it came first
+fn complete<T: Send + 'static, H: Handler + 'static>(inner: &Inner<T, H>, result: Result<T, Canceled>) {
+    if let Some((callback, result)) = settle(inner, result) {
+        send(&inner.notify, callback, result);
+    }
+}
+
+// Keeps the value for the callback to come, or returns the callback that
+// came first, to be run with it. The lock is released by then: the
+// callback may take a while, and sending it may have to wait.
+fn settle<T, H: Handler>(inner: &Inner<T, H>, result: Result<T, Canceled>) -> Option<(Then<T, H>, Result<T, Canceled>)> {
+    let mut state = inner.state.lock().unwrap();
+
+    match mem::replace(&mut *state, State::Sent) {
+        State::Empty => {
+            *state = State::Value(result);
+            None
+        }
+        State::Waiting(callback) => Some((callback, result)),
+        _ => unreachable!(),
+    }
+}
+
+fn send<T: Send + 'static, H: Handler + 'static>(notify: &mio::Sender<Callback<H>>, callback: Then<T, H>, result: Result<T, Canceled>) {
+    let message = Callback(Box::new(move |handler: &mut H, event_loop: &mut EventLoop<H>| callback(handler, event_loop, result)));
+
+    // A full channel means the loop is behind, the callback waits for it on
+    // a thread of its own. The loop's own thread would wait forever, and
+    // `then` is called from it, as a promise may be completed from it.
+    if let Err(mio::NotifyError::Full(message)) = notify.send(message) {
+        let notify = notify.clone();
+        thread::spawn(move || retry(&notify, message));
+    }
+
+    // Any other error means the loop is gone, there is no one left to care
+}
+
+fn retry<H: Handler + 'static>(notify: &mio::Sender<Callback<H>>, mut message: Callback<H>) {
+    loop {
+        match notify.send(message) {
+            Err(mio::NotifyError::Full(returned)) => {
+                message = returned;
+                thread::sleep(Duration::from_millis(1));
+            }
+            _ => return,
+        }
+    }
+}
@@ -0,0 +1,204 @@
+extern crate mio;
+extern crate bytes;
+extern crate protobuf_framing;
+extern crate rand;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use protobuf_framing::framing;
+use protobuf_framing::messages::{Ack, Reading};
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::env;
+
+const CLIENT: mio::Token = mio::Token(0);
+
+// How often a piece of the pending output is written
+const CHUNK_MS: u64 = 10;
+
+// The most bytes written at a time. Frames are a few dozen bytes, so
+// almost every one is split, often within the length prefix.
+const MAX_CHUNK: usize = 8;
+
+struct Client {
+    socket: TcpStream,
+    connected: bool,
+    // Bytes read but not yet decoded
+    buf: Vec<u8>,
+    // Every frame, encoded up front and written out a few bytes at a time
+    out: Vec<u8>,
+    total: u64,
+    acked: u64,
+}
+
+impl Client {
+    fn new(socket: TcpStream, total: u64) -> Client {
+        let mut out = vec![];
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+        for i in 0..total {
+            let reading = Reading {
+                sensor: format!("sensor-{}", i % 3),
+                timestamp: timestamp + i,
+                // Swings both ways, to exercise the zigzag encoding
+                value: (i as i64 - total as i64 / 2) * 150,
+                tags: if i % 2 == 0 { vec!["even".to_string()] } else { vec![] },
+            };
+
+            let mut message = vec![];
+            reading.encode(&mut message);
+            framing::encode(&message, &mut out);
+        }
+
+        Client {
+            socket: socket,
+            connected: false,
+            buf: vec![],
+            out: out,
+            total: total,
+            acked: 0,
+        }
+    }
+
+    // Writes the next chunk of output, of a random size
+    fn write_chunk(&mut self) -> bool {
+        let len = (rand::random::<usize>() % MAX_CHUNK + 1).min(self.out.len());
+
+        match self.socket.try_write(&self.out[..len]) {
+            Ok(Some(n)) => {
+                self.out.drain(..n);
+                true
+            }
+            Ok(None) => true,
+            Err(e) => {
+                println!("got an error trying to write; err={:?}", e);
+                false
+            }
+        }
+    }
+
+    // Returns false if the connection is closed or failed
+    fn read(&mut self) -> bool {
+        let mut buf = [0; 4096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    println!("server closed the connection");
+                    return false;
+                }
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    if let Err(e) = self.decode() {
+                        println!("invalid ack; err={:?}", e);
+                        return false;
+                    }
+                }
+                Ok(None) => return true,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    fn decode(&mut self) -> Result<(), protobuf_framing::wire::Error> {
+        let mut pos = 0;
+
+        while let Some((message, len)) = framing::decode(&self.buf[pos..])? {
+            let ack = Ack::decode(message)?;
+            pos += len;
+
+            println!("ack; count={}", ack.count);
+            self.acked = ack.count;
+        }
+
+        self.buf.drain(..pos);
+        Ok(())
+    }
+}
+
+impl mio::Handler for Client {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Client>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, CLIENT);
+
+        if !self.connected {
+            // The connect is non-blocking, the socket becoming writable
+            // means it completed, successfully or not.
+            if let Err(e) = self.socket.take_socket_error() {
+                println!("failed to connect; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+
+            println!("connected; readings={}; bytes={}", self.total, self.out.len());
+
+            // Otherwise the kernel would gather the small writes back up
+            // into larger segments.
+            self.socket.set_nodelay(true).unwrap();
+
+            self.connected = true;
+            event_loop.timeout_ms((), CHUNK_MS).unwrap();
+        }
+
+        if events.is_readable() && !self.read() {
+            event_loop.shutdown();
+            return;
+        }
+
+        if self.acked == self.total {
+            println!("every reading was acknowledged");
+            event_loop.shutdown();
+            return;
+        }
+
+        // Writes are driven by the timer, the socket is only read
+        event_loop.reregister(&self.socket, CLIENT, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Client>, _: ()) {
+        if !self.write_chunk() {
+            event_loop.shutdown();
+            return;
+        }
+
+        if !self.out.is_empty() {
+            event_loop.timeout_ms((), CHUNK_MS).unwrap();
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("127.0.0.1:9100".to_string()).parse().unwrap();
+    let total: u64 = args.next().map(|s| s.parse().unwrap()).unwrap_or(10);
+
+    let socket = TcpStream::connect(&address).unwrap();
+
+    // The default timer tick is 100ms, too coarse for writing every 10ms
+    let config = mio::EventLoopConfig {
+        timer_tick_ms: CHUNK_MS,
+        ..mio::EventLoopConfig::default()
+    };
+
+    let mut event_loop = mio::EventLoop::configured(config).unwrap();
+
+    // The socket becomes writable once connected
+    event_loop.register_opt(
+        &socket,
+        CLIENT,
+        mio::EventSet::writable(),
+        mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+
+    let mut client = Client::new(socket, total);
+
+    event_loop.run(&mut client).unwrap();
+}
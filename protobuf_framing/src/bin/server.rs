@@ -0,0 +1,200 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+extern crate protobuf_framing;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use protobuf_framing::framing;
+use protobuf_framing::messages::{Ack, Reading};
+use std::net::SocketAddr;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+struct Server {
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+}
+
+impl Server {
+    fn new(acceptor: Acceptor<Factory<Connection>>) -> Server {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Server {
+            acceptor: acceptor,
+            connections: slab,
+        }
+    }
+}
+
+impl mio::Handler for Server {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
+                }
+            }
+            _ => {
+                self.connections[token].ready(event_loop, events);
+
+                if self.connections[token].closed {
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Bytes read but not yet decoded
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // The number of reads and of messages so far. With a client sending
+    // a few bytes at a time, there are many reads per message.
+    reads: usize,
+    count: u64,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            buf: vec![],
+            out: vec![],
+            reads: 0,
+            count: 0,
+            closed: false,
+        }
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Server>, events: mio::EventSet) {
+        if events.is_readable() {
+            self.read();
+        }
+
+        if !self.closed {
+            self.write();
+        }
+
+        if !self.closed {
+            let interest = if self.out.is_empty() {
+                mio::EventSet::readable()
+            } else {
+                mio::EventSet::readable() | mio::EventSet::writable()
+            };
+
+            event_loop.reregister(&self.socket, self.token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn read(&mut self) {
+        let mut buf = [0; 4096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    self.reads += 1;
+                    self.buf.extend(&buf[..n]);
+
+                    if let Err(e) = self.process() {
+                        println!("invalid frame, closing connection; err={:?}; token={:?}", e, self.token);
+                        self.closed = true;
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    // Handles every complete frame in the buffer. Each one holds a reading,
+    // which is acknowledged.
+    fn process(&mut self) -> Result<(), protobuf_framing::wire::Error> {
+        let mut pos = 0;
+
+        while let Some((message, len)) = framing::decode(&self.buf[pos..])? {
+            let reading = Reading::decode(message)?;
+            pos += len;
+
+            self.count += 1;
+
+            println!("reading; sensor={:?}; timestamp={}; value={}; tags={:?}; reads={}",
+                     reading.sensor, reading.timestamp, reading.value, reading.tags, self.reads);
+
+            let mut ack = vec![];
+            Ack { count: self.count }.encode(&mut ack);
+            framing::encode(&ack, &mut self.out);
+        }
+
+        self.buf.drain(..pos);
+        Ok(())
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:9100".to_string())
+        .parse().unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mut server = Server::new(acceptor);
+
+    println!("running protobuf server; addr={:?}", address);
+    event_loop.run(&mut server).unwrap();
+}
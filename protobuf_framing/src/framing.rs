@@ -0,0 +1,41 @@
+// Protobuf messages don't say where they end: a message is just fields,
+// and the end of the buffer is the end of the message. On a stream, each
+// message is prefixed by its length as a varint, which is what the
+// protobuf libraries' `writeDelimitedTo` and `parseDelimitedFrom` do.
+//
+// The decoder is handed whatever has been read so far. The length prefix
+// and the message can be split across reads at any point, even in the
+// middle of the prefix, and the decoder waits until all of it is there.
+
+use wire::{self, Error};
+
+// Frames larger than this are refused, before any of the message is read.
+// Without a limit, a corrupt prefix could announce gigabytes.
+pub const MAX_FRAME: usize = 64 * 1_024;
+
+// Appends `message`, prefixed by its length, to `dst`
+pub fn encode(message: &[u8], dst: &mut Vec<u8>) {
+    wire::put_varint(message.len() as u64, dst);
+    dst.extend(message);
+}
+
+// Finds the frame at the start of `buf`. Returns the message and the number
+// of bytes the frame used, or `None` if the frame isn't complete.
+pub fn decode(buf: &[u8]) -> Result<Option<(&[u8], usize)>, Error> {
+    let (len, prefix) = match wire::get_varint(buf)? {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+
+    if len > MAX_FRAME as u64 {
+        return Err(Error("frame too large"));
+    }
+
+    let end = prefix + len as usize;
+
+    if buf.len() < end {
+        return Ok(None);
+    }
+
+    Ok(Some((&buf[prefix..end], end)))
+}
@@ -0,0 +1,8 @@
+// The protobuf wire format, the messages exchanged by the server and the
+// client in `src/bin`, and the length prefix that frames them on the
+// stream. Like the rest of the examples' protocol code, it does no I/O and
+// works on buffers filled and drained by the event loop.
+
+pub mod wire;
+pub mod messages;
+pub mod framing;
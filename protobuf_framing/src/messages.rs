@@ -0,0 +1,89 @@
+// The messages from `messages.proto`, with the code protoc would generate
+// written out by hand. In proto3, a field set to its default value (zero,
+// or empty) is not sent at all.
+
+use std::str;
+use wire::{self, Error, Field, Reader};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reading {
+    pub sensor: String,
+    pub timestamp: u64,
+    pub value: i64,
+    pub tags: Vec<String>,
+}
+
+impl Reading {
+    pub fn encode(&self, dst: &mut Vec<u8>) {
+        if !self.sensor.is_empty() {
+            wire::put_bytes(1, self.sensor.as_bytes(), dst);
+        }
+
+        if self.timestamp != 0 {
+            wire::put_uint(2, self.timestamp, dst);
+        }
+
+        if self.value != 0 {
+            wire::put_uint(3, wire::zigzag(self.value), dst);
+        }
+
+        // Each element of a repeated string is a field of its own
+        for tag in &self.tags {
+            wire::put_bytes(4, tag.as_bytes(), dst);
+        }
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Reading, Error> {
+        let mut reading = Reading::default();
+        let mut fields = Reader::new(buf);
+
+        while let Some((number, field)) = fields.next_field()? {
+            match (number, field) {
+                (1, Field::Bytes(data)) => reading.sensor = string(data)?,
+                (2, Field::Varint(n)) => reading.timestamp = n,
+                (3, Field::Varint(n)) => reading.value = wire::unzigzag(n),
+                (4, Field::Bytes(data)) => reading.tags.push(string(data)?),
+                (1..=4, _) => return Err(Error("unexpected wire type")),
+                // Added to the schema after this was written
+                _ => {}
+            }
+        }
+
+        Ok(reading)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ack {
+    pub count: u64,
+}
+
+impl Ack {
+    pub fn encode(&self, dst: &mut Vec<u8>) {
+        if self.count != 0 {
+            wire::put_uint(1, self.count, dst);
+        }
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Ack, Error> {
+        let mut ack = Ack::default();
+        let mut fields = Reader::new(buf);
+
+        while let Some((number, field)) = fields.next_field()? {
+            match (number, field) {
+                (1, Field::Varint(n)) => ack.count = n,
+                (1, _) => return Err(Error("unexpected wire type")),
+                _ => {}
+            }
+        }
+
+        Ok(ack)
+    }
+}
+
+fn string(data: &[u8]) -> Result<String, Error> {
+    match str::from_utf8(data) {
+        Ok(s) => Ok(s.to_string()),
+        Err(_) => Err(Error("invalid UTF-8")),
+    }
+}
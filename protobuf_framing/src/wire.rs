@@ -0,0 +1,159 @@
+// A protobuf message is a sequence of fields. Each field starts with a key,
+// a varint holding the field number and the wire type, `number << 3 |
+// type`. The wire type says how to find the end of the value:
+//
+// 0   varint
+// 1   8 bytes, little endian
+// 2   varint length, then that many bytes (strings, bytes, messages)
+// 5   4 bytes, little endian
+//
+// A varint is 7 bits per byte, least significant first, with the high bit
+// set on every byte but the last. A u64 takes at most 10 bytes.
+//
+// Fields may come in any order, and unknown fields are skipped, which is
+// what lets a schema grow without breaking older readers.
+
+pub const VARINT: u8 = 0;
+pub const FIXED64: u8 = 1;
+pub const LENGTH_DELIMITED: u8 = 2;
+pub const FIXED32: u8 = 5;
+
+const MAX_VARINT: usize = 10;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Error(pub &'static str);
+
+pub fn put_varint(mut n: u64, dst: &mut Vec<u8>) {
+    while n >= 0x80 {
+        dst.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+
+    dst.push(n as u8);
+}
+
+// Decodes a varint from the start of `buf`. Returns the value and the
+// number of bytes it used, or `None` if the varint isn't complete.
+pub fn get_varint(buf: &[u8]) -> Result<Option<(u64, usize)>, Error> {
+    let mut n = 0;
+
+    for (i, byte) in buf.iter().enumerate() {
+        if i == MAX_VARINT {
+            return Err(Error("varint too long"));
+        }
+
+        n |= ((byte & 0x7f) as u64) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            return Ok(Some((n, i + 1)));
+        }
+    }
+
+    if buf.len() >= MAX_VARINT {
+        return Err(Error("varint too long"));
+    }
+
+    Ok(None)
+}
+
+// `sint64` fields are zigzag encoded, so that small negative numbers are
+// small varints too: 0 -> 0, -1 -> 1, 1 -> 2, -2 -> 3, ...
+pub fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+pub fn unzigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+pub fn put_key(field: u32, wire_type: u8, dst: &mut Vec<u8>) {
+    put_varint((field as u64) << 3 | wire_type as u64, dst);
+}
+
+pub fn put_uint(field: u32, n: u64, dst: &mut Vec<u8>) {
+    put_key(field, VARINT, dst);
+    put_varint(n, dst);
+}
+
+pub fn put_bytes(field: u32, data: &[u8], dst: &mut Vec<u8>) {
+    put_key(field, LENGTH_DELIMITED, dst);
+    put_varint(data.len() as u64, dst);
+    dst.extend(data);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Field<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+// Iterates over the fields of a complete message
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Reader<'a> {
+        Reader { buf: buf }
+    }
+
+    // Returns the next field number and value, or `None` at the end of the
+    // message.
+    pub fn next_field(&mut self) -> Result<Option<(u32, Field<'a>)>, Error> {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+
+        let key = self.varint()?;
+        let field = (key >> 3) as u32;
+
+        if field == 0 {
+            return Err(Error("invalid field number"));
+        }
+
+        let value = match (key & 0x7) as u8 {
+            VARINT => Field::Varint(self.varint()?),
+            FIXED64 => Field::Fixed64(self.fixed(8)?),
+            LENGTH_DELIMITED => {
+                let len = self.varint()?;
+
+                if len > self.buf.len() as u64 {
+                    return Err(Error("truncated message"));
+                }
+
+                let (data, rest) = self.buf.split_at(len as usize);
+                self.buf = rest;
+
+                Field::Bytes(data)
+            }
+            FIXED32 => Field::Fixed32(self.fixed(4)? as u32),
+            _ => return Err(Error("unsupported wire type")),
+        };
+
+        Ok(Some((field, value)))
+    }
+
+    // The message is complete, so a varint that isn't is an error
+    fn varint(&mut self) -> Result<u64, Error> {
+        match get_varint(self.buf)? {
+            Some((n, len)) => {
+                self.buf = &self.buf[len..];
+                Ok(n)
+            }
+            None => Err(Error("truncated message")),
+        }
+    }
+
+    fn fixed(&mut self, size: usize) -> Result<u64, Error> {
+        if self.buf.len() < size {
+            return Err(Error("truncated message"));
+        }
+
+        let (data, rest) = self.buf.split_at(size);
+        self.buf = rest;
+
+        Ok(data.iter().rev().fold(0, |n, b| n << 8 | *b as u64))
+    }
+}
@@ -0,0 +1,269 @@
+extern crate mio;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::time::Instant;
+use std::env;
+
+const REPORT_MS: u64 = 1_000;
+
+// Messages sent but not yet received by every subscriber. The bench reads
+// for all the subscribers on one thread, so without a limit the publisher
+// would run ahead until the broker evicted them all as slow.
+const WINDOW: usize = 1_000;
+
+const TOPIC: &str = "bench.messages";
+const FILTER: &str = "bench.>";
+
+// Measures fan-out: one publisher sends as fast as it can to a topic with
+// many subscribers, and the time until every subscriber has every message
+// is reported. Slow subscribers subscribe too, but never read, to watch the
+// broker evict them without slowing down the others.
+struct Bench {
+    peers: Slab<Peer>,
+    subscribers: usize,
+    messages: usize,
+    size: usize,
+    address: SocketAddr,
+    // Subscribers whose `SUB` has been answered
+    subscribed: usize,
+    // Messages received, over all subscribers
+    received: usize,
+    // Messages published so far
+    sent: usize,
+    publisher: Option<mio::Token>,
+    started: Option<Instant>,
+}
+
+enum Kind {
+    Subscriber,
+    Slow,
+    Publisher,
+}
+
+struct Peer {
+    socket: TcpStream,
+    token: mio::Token,
+    kind: Kind,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl Bench {
+    fn connect(&mut self, event_loop: &mut mio::EventLoop<Bench>, kind: Kind, out: Vec<u8>) -> mio::Token {
+        let socket = TcpStream::connect(&self.address).unwrap();
+
+        let token = self.peers.insert_with(|token| {
+            Peer {
+                socket: socket,
+                token: token,
+                kind: kind,
+                buf: vec![],
+                out: out,
+            }
+        }).unwrap();
+
+        let peer = &self.peers[token];
+
+        // Slow subscribers are only written to, once
+        let interest = match peer.kind {
+            Kind::Slow => mio::EventSet::writable(),
+            _ => mio::EventSet::readable() | mio::EventSet::writable(),
+        };
+
+        event_loop.register_opt(&peer.socket, token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+
+        token
+    }
+
+    // Every subscriber is ready, start publishing
+    fn publish(&mut self, event_loop: &mut mio::EventLoop<Bench>) {
+        println!("subscribed; subscribers={}; publishing; messages={}; size={}", self.subscribers, self.messages, self.size);
+
+        self.started = Some(Instant::now());
+        self.publisher = Some(self.connect(event_loop, Kind::Publisher, vec![]));
+        self.fill();
+    }
+
+    // Queues messages for the publisher to send, up to the window
+    fn fill(&mut self) {
+        let token = match self.publisher {
+            Some(token) => token,
+            None => return,
+        };
+
+        let delivered = self.received / self.subscribers;
+
+        while self.sent < self.messages && self.sent - delivered < WINDOW {
+            let payload = format!("{:0width$}", self.sent, width = self.size);
+            self.peers[token].out.extend(format!("PUB {} {}\n", TOPIC, payload).as_bytes());
+            self.sent += 1;
+        }
+    }
+
+    fn peer_ready(&mut self, event_loop: &mut mio::EventLoop<Bench>, token: mio::Token, events: mio::EventSet) {
+        let (lines, closed) = self.peers[token].ready(events);
+
+        for line in lines {
+            if line == "OK" {
+                self.subscribed += 1;
+
+                if self.subscribed == self.subscribers {
+                    self.publish(event_loop);
+                }
+            } else if line.starts_with("MSG ") {
+                self.received += 1;
+            } else {
+                println!("unexpected line; line={:?}", line);
+            }
+        }
+
+        if closed {
+            println!("the broker closed the connection; token={:?}", token);
+            self.peers.remove(token);
+        } else {
+            self.peers[token].reregister(event_loop);
+        }
+
+        // The subscribers caught up some, the publisher may send more
+        if let Some(publisher) = self.publisher {
+            let idle = self.peers[publisher].out.is_empty();
+            self.fill();
+
+            if idle && !self.peers[publisher].out.is_empty() {
+                self.peers[publisher].reregister(event_loop);
+            }
+        }
+
+        if self.received == self.subscribers * self.messages {
+            self.report();
+            event_loop.shutdown();
+        }
+    }
+
+    fn report(&self) {
+        let elapsed = match self.started {
+            Some(started) => started.elapsed(),
+            None => return,
+        };
+
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+
+        println!("received={}; elapsed={:.3}s; delivered={:.0} msgs/s",
+                 self.received, secs, self.received as f64 / secs);
+    }
+}
+
+impl Peer {
+    // Returns the lines read, and whether the connection is closed
+    fn ready(&mut self, events: mio::EventSet) -> (Vec<String>, bool) {
+        let mut lines = vec![];
+
+        if events.is_writable() {
+            while !self.out.is_empty() {
+                match self.socket.try_write(&self.out) {
+                    Ok(Some(n)) => {
+                        self.out.drain(..n);
+                    }
+                    Ok(None) => break,
+                    Err(_) => return (lines, true),
+                }
+            }
+        }
+
+        if !events.is_readable() {
+            return (lines, false);
+        }
+
+        let mut chunk = [0; 16 * 1_024];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) | Err(_) => return (lines, true),
+                Ok(Some(n)) => self.buf.extend(&chunk[..n]),
+                Ok(None) => break,
+            }
+        }
+
+        let mut start = 0;
+
+        while let Some(pos) = self.buf[start..].iter().position(|b| *b == b'\n') {
+            lines.push(String::from_utf8_lossy(&self.buf[start..start + pos]).into_owned());
+            start += pos + 1;
+        }
+
+        self.buf.drain(..start);
+
+        (lines, false)
+    }
+
+    fn reregister(&self, event_loop: &mut mio::EventLoop<Bench>) {
+        let interest = match (&self.kind, self.out.is_empty()) {
+            (&Kind::Slow, true) => return,
+            (&Kind::Slow, false) => mio::EventSet::writable(),
+            (_, true) => mio::EventSet::readable(),
+            (_, false) => mio::EventSet::readable() | mio::EventSet::writable(),
+        };
+
+        event_loop.reregister(&self.socket, self.token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+impl mio::Handler for Bench {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Bench>, token: mio::Token, events: mio::EventSet) {
+        self.peer_ready(event_loop, token, events);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Bench>, _: ()) {
+        println!("received={} of {}", self.received, self.subscribers * self.messages);
+        event_loop.timeout_ms((), REPORT_MS).unwrap();
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("127.0.0.1:4222".to_string()).parse().unwrap();
+    let subscribers: usize = args.next().map(|s| s.parse().unwrap()).unwrap_or(100);
+    let messages: usize = args.next().map(|s| s.parse().unwrap()).unwrap_or(10_000);
+    let size: usize = args.next().map(|s| s.parse().unwrap()).unwrap_or(64);
+    let slow: usize = args.next().map(|s| s.parse().unwrap()).unwrap_or(0);
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+
+    let mut bench = Bench {
+        peers: Slab::new(subscribers + slow + 1),
+        subscribers: subscribers,
+        messages: messages,
+        size: size,
+        address: address,
+        subscribed: 0,
+        received: 0,
+        sent: 0,
+        publisher: None,
+        started: None,
+    };
+
+    let sub = format!("SUB {}\n", FILTER).into_bytes();
+
+    // The slow ones first, their `SUB` is never answered, as they don't
+    // read. Subscribing before the others, they are subscribed by the time
+    // publishing starts.
+    for _ in 0..slow {
+        bench.connect(&mut event_loop, Kind::Slow, sub.clone());
+    }
+
+    for _ in 0..subscribers {
+        bench.connect(&mut event_loop, Kind::Subscriber, sub.clone());
+    }
+
+    event_loop.timeout_ms((), REPORT_MS).unwrap();
+    event_loop.run(&mut bench).unwrap();
+}
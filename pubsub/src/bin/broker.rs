@@ -0,0 +1,393 @@
+extern crate acceptor;
+extern crate mio;
+extern crate pubsub;
+extern crate rope;
+
+use acceptor::{Acceptor, Factory};
+use mio::TryRead;
+use mio::tcp::*;
+use mio::util::Slab;
+use pubsub::{Command, Trie, MAX_LINE};
+use rope::{Chunk, Rope};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use std::{env, mem};
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 16_384;
+
+// A subscriber is evicted once this much is queued for it...
+const MAX_QUEUED: usize = 1_024 * 1_024;
+
+// ...or once it has had something queued for this long without the socket
+// taking any of it.
+const STALL_SECS: u64 = 5;
+
+// How often subscribers are checked for stalls
+const SWEEP_MS: u64 = 1_000;
+
+// Routes published messages to the subscribers of their topic. A message
+// is never copied: it is made of the bytes of the `PUB` line as they were
+// read, between a `MSG ` and a newline shared by all messages, and the
+// same chunks are queued for every subscriber getting it. Publishing to a
+// thousand subscribers costs a thousand ropes of three pointers, not a
+// thousand copies, or even one.
+struct Broker {
+    acceptor: Acceptor<Factory<Client>>,
+    clients: Slab<Client>,
+    subscriptions: Trie<mio::Token>,
+    // Clients that had output queued, or were closed, while handling the
+    // current event. They are reregistered (or removed) once it is done.
+    dirty: Vec<mio::Token>,
+    // What goes around the topic and payload of every message
+    prefix: Chunk,
+    newline: Chunk,
+    published: u64,
+    delivered: u64,
+    evicted: u64,
+    // Published when the counts were last printed
+    reported: u64,
+}
+
+impl Broker {
+    fn new(acceptor: Acceptor<Factory<Client>>) -> Broker {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS);
+
+        Broker {
+            acceptor: acceptor,
+            clients: slab,
+            subscriptions: Trie::new(),
+            dirty: vec![],
+            prefix: Chunk::from(&b"MSG "[..]),
+            newline: Chunk::from(&b"\n"[..]),
+            published: 0,
+            delivered: 0,
+            evicted: 0,
+            reported: 0,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Broker>) {
+        let (_, res) = self.acceptor.accept(event_loop, &mut self.clients);
+
+        if let Err(e) = res {
+            println!("encountered error while accepting connection; err={:?}", e);
+            event_loop.shutdown();
+        }
+    }
+
+    fn client_ready(&mut self, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            let lines = self.clients[token].read();
+
+            // Lines read before the client closed the connection are still
+            // handled, a publisher may be done and gone already
+            for line in lines {
+                self.handle(token, line);
+            }
+        }
+
+        if events.is_writable() {
+            self.clients[token].write();
+        }
+
+        self.dirty.push(token);
+    }
+
+    fn handle(&mut self, token: mio::Token, line: Chunk) {
+        let text = String::from_utf8_lossy(&line);
+
+        match pubsub::parse(&text) {
+            Ok(Command::Sub(filter)) => {
+                if self.clients[token].filters.insert(filter.to_string()) {
+                    self.subscriptions.insert(filter, token);
+                }
+
+                self.reply(token, "OK");
+            }
+            Ok(Command::Unsub(filter)) => {
+                if self.clients[token].filters.remove(filter) {
+                    self.subscriptions.remove(filter, token);
+                }
+
+                self.reply(token, "OK");
+            }
+            Ok(Command::Pub(topic, payload)) => {
+                // The line is `PUB <topic> <payload>`, the message is the
+                // same past the command. The few lines that aren't valid
+                // UTF-8, or have no payload to share, are formatted anew.
+                let msg = if matches!(text, Cow::Borrowed(_)) && !payload.is_empty() {
+                    let mut msg = Rope::from(self.prefix.clone());
+                    msg.push(line.slice(4, line.len()));
+                    msg.push(self.newline.clone());
+                    msg
+                } else {
+                    Rope::from(format!("MSG {} {}\n", topic, payload).into_bytes())
+                };
+
+                self.publish(topic, msg);
+            }
+            Ok(Command::Ping) => self.reply(token, "PONG"),
+            Err(e) => self.reply(token, &format!("ERR {}", e)),
+        }
+    }
+
+    fn publish(&mut self, topic: &str, msg: Rope) {
+        let mut targets = HashSet::new();
+        self.subscriptions.matches(topic, &mut targets);
+
+        self.published += 1;
+
+        if targets.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+
+        for target in targets {
+            let client = &mut self.clients[target];
+
+            // Already being evicted
+            if client.closed {
+                continue;
+            }
+
+            client.push(msg.clone(), now);
+            self.delivered += 1;
+
+            if client.out.len() > MAX_QUEUED {
+                println!("subscriber is not keeping up, evicting; queued={}; token={:?}", client.out.len(), target);
+                self.evict(target);
+            }
+
+            self.dirty.push(target);
+        }
+    }
+
+    fn reply(&mut self, token: mio::Token, line: &str) {
+        let line = Rope::from(format!("{}\n", line).into_bytes());
+        self.clients[token].push(line, Instant::now());
+        self.dirty.push(token);
+    }
+
+    // What is queued is thrown away, the subscriber isn't reading it
+    fn evict(&mut self, token: mio::Token) {
+        let client = &mut self.clients[token];
+
+        client.out = Rope::new();
+        client.closed = true;
+
+        self.evicted += 1;
+    }
+
+    // Reregisters every client touched while handling an event, and
+    // removes the ones that are done.
+    fn flush(&mut self, event_loop: &mut mio::EventLoop<Broker>) {
+        while let Some(token) = self.dirty.pop() {
+            let closed = match self.clients.get(token) {
+                Some(client) => client.closed,
+                None => continue,
+            };
+
+            if closed {
+                self.remove(token);
+                continue;
+            }
+
+            let client = &self.clients[token];
+
+            event_loop.reregister(&client.socket, token, client.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn remove(&mut self, token: mio::Token) {
+        let mut client = self.clients.remove(token).unwrap();
+
+        for filter in mem::take(&mut client.filters) {
+            self.subscriptions.remove(&filter, token);
+        }
+    }
+
+    // Evicts the subscribers that have stopped reading, even if not enough
+    // is queued for them yet to notice otherwise.
+    fn sweep(&mut self, event_loop: &mut mio::EventLoop<Broker>) {
+        let now = Instant::now();
+
+        let stalled: Vec<_> = self.clients.iter()
+            .filter(|client| !client.out.is_empty() && now - client.progress >= Duration::from_secs(STALL_SECS))
+            .map(|client| client.token)
+            .collect();
+
+        for token in stalled {
+            println!("subscriber stalled, evicting; queued={}; token={:?}", self.clients[token].out.len(), token);
+            self.evict(token);
+            self.dirty.push(token);
+        }
+
+        self.flush(event_loop);
+
+        if self.published != self.reported {
+            println!("published={}; delivered={}; evicted={}; clients={}", self.published, self.delivered, self.evicted, self.clients.count());
+            self.reported = self.published;
+        }
+
+        event_loop.timeout_ms((), SWEEP_MS).unwrap();
+    }
+}
+
+impl mio::Handler for Broker {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Broker>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ => {
+                // The client may have been evicted while handling an event
+                // for another one
+                if self.clients.contains(token) {
+                    self.client_ready(token, events);
+                }
+
+                self.flush(event_loop);
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Broker>, _: ()) {
+        self.sweep(event_loop);
+    }
+}
+
+struct Client {
+    socket: TcpStream,
+    token: mio::Token,
+    // Bytes of an incomplete line
+    buf: Rope,
+    // Messages and replies waiting to be written, shared with the other
+    // subscribers getting them
+    out: Rope,
+    // The last time the socket took some of the output, or the output
+    // started queuing up
+    progress: Instant,
+    filters: HashSet<String>,
+    closed: bool,
+}
+
+impl acceptor::Connection for Client {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Client {
+    fn new(socket: TcpStream, token: mio::Token) -> Client {
+        Client {
+            socket: socket,
+            token: token,
+            buf: Rope::new(),
+            out: Rope::new(),
+            progress: Instant::now(),
+            filters: HashSet::new(),
+            closed: false,
+        }
+    }
+
+    fn push(&mut self, data: Rope, now: Instant) {
+        if self.out.is_empty() {
+            self.progress = now;
+        }
+
+        self.out.append(data);
+    }
+
+    // Returns the complete lines read, without their line endings. A line
+    // is a slice of the chunk it was read in, unless it was split over
+    // several reads.
+    fn read(&mut self) -> Vec<Chunk> {
+        let mut chunk = [0; 4_096];
+        let mut lines = vec![];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(Some(n)) => self.buf.push(&chunk[..n]),
+                Ok(None) => break,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        while let Some(pos) = self.buf.find(b'\n') {
+            let line = self.buf.split_to(pos + 1).into_chunk();
+            let mut end = pos;
+
+            while end > 0 && line[end - 1] == b'\r' {
+                end -= 1;
+            }
+
+            lines.push(line.slice(0, end));
+        }
+
+        if self.buf.len() > MAX_LINE {
+            println!("line too long, closing connection; token={:?}", self.token);
+            self.closed = true;
+        }
+
+        lines
+    }
+
+    // Writes the queued messages a batch at a time, each in a single
+    // `writev`
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.out.write_to(&self.socket) {
+                Ok(Some(_)) => self.progress = Instant::now(),
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:4222".to_string())
+        .parse().unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, Client::new as Factory<Client>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+    event_loop.timeout_ms((), SWEEP_MS).unwrap();
+
+    let mut broker = Broker::new(acceptor);
+
+    println!("running pub/sub broker; addr={:?}", address);
+    event_loop.run(&mut broker).unwrap();
+}
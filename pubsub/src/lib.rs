@@ -0,0 +1,198 @@
+// The parts of the pub/sub protocol shared by the broker and the benchmark
+// client: parsing the commands clients send, and matching topics against
+// the filters subscribers gave. Neither does any I/O.
+//
+// The protocol is line based. Clients send:
+//
+//     SUB <filter>
+//     UNSUB <filter>
+//     PUB <topic> <payload>
+//     PING
+//
+// and the broker answers `OK` to `SUB` and `UNSUB`, `PONG` to `PING`, and
+// `ERR <reason>` to anything it can't make sense of. `PUB` gets no answer
+// unless it fails, publishers can send as fast as they like without
+// waiting. Messages are delivered as:
+//
+//     MSG <topic> <payload>
+//
+// Topics are dot separated, like `prices.eu.mio`. In a filter, `*` matches
+// any one level, and `>`, only allowed last, one or more of them:
+// `prices.*.mio` and `prices.>` both match the topic above.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+// Longer lines are refused
+pub const MAX_LINE: usize = 64 * 1_024;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    Sub(&'a str),
+    Unsub(&'a str),
+    Pub(&'a str, &'a str),
+    Ping,
+}
+
+// Parses a line, without its newline
+pub fn parse<'a>(line: &'a str) -> Result<Command<'a>, &'static str> {
+    let mut parts = line.splitn(2, ' ');
+    let name = parts.next().unwrap_or("");
+    let rest = parts.next().unwrap_or("");
+
+    match name {
+        "SUB" | "UNSUB" => {
+            if !is_valid_filter(rest) {
+                return Err("invalid filter");
+            }
+
+            Ok(if name == "SUB" { Command::Sub(rest) } else { Command::Unsub(rest) })
+        }
+        "PUB" => {
+            let mut parts = rest.splitn(2, ' ');
+            let topic = parts.next().unwrap_or("");
+
+            if !is_valid_topic(topic) {
+                return Err("invalid topic");
+            }
+
+            Ok(Command::Pub(topic, parts.next().unwrap_or("")))
+        }
+        "PING" => Ok(Command::Ping),
+        _ => Err("unknown command"),
+    }
+}
+
+pub fn is_valid_topic(topic: &str) -> bool {
+    !topic.is_empty() && topic.split('.').all(|level| !level.is_empty() && !level.contains(['*', '>']))
+}
+
+pub fn is_valid_filter(filter: &str) -> bool {
+    let levels: Vec<&str> = filter.split('.').collect();
+
+    !filter.is_empty() && levels.iter().enumerate().all(|(i, level)| {
+        match *level {
+            "*" => true,
+            ">" => i == levels.len() - 1,
+            level => !level.is_empty() && !level.contains(['*', '>']),
+        }
+    })
+}
+
+/*
+ *
+ * ===== Subscriptions =====
+ *
+ */
+
+// The subscribers of every filter, arranged by level. Finding the
+// subscribers of a topic walks down the levels of the topic, following the
+// exact level and the wildcards at each step. The cost depends on how deep
+// the topic is and how many wildcard filters are on its way, not on how
+// many filters there are in total.
+//
+//     prices ─┬─ eu ── mio    {a}
+//             ├─ *  ── mio    {b}
+//             └─ >            {c}
+//
+// Nodes are removed once no filter goes through them anymore.
+pub struct Trie<T> {
+    root: Node<T>,
+}
+
+struct Node<T> {
+    children: HashMap<String, Node<T>>,
+    // Subscribed with a filter ending at this node
+    subscribers: HashSet<T>,
+}
+
+impl<T: Copy + Eq + Hash> Node<T> {
+    fn new() -> Node<T> {
+        Node {
+            children: HashMap::new(),
+            subscribers: HashSet::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.children.is_empty() && self.subscribers.is_empty()
+    }
+
+    fn remove(&mut self, levels: &[&str], subscriber: T) -> bool {
+        let (level, rest) = match levels.split_first() {
+            Some(split) => split,
+            None => return self.subscribers.remove(&subscriber),
+        };
+
+        let (removed, empty) = match self.children.get_mut(*level) {
+            Some(child) => {
+                let removed = child.remove(rest, subscriber);
+                (removed, child.is_empty())
+            }
+            None => return false,
+        };
+
+        if empty {
+            self.children.remove(*level);
+        }
+
+        removed
+    }
+
+    fn collect(&self, levels: &[&str], out: &mut HashSet<T>) {
+        let (level, rest) = match levels.split_first() {
+            Some(split) => split,
+            None => {
+                out.extend(self.subscribers.iter().cloned());
+                return;
+            }
+        };
+
+        if let Some(child) = self.children.get(*level) {
+            child.collect(rest, out);
+        }
+
+        if let Some(child) = self.children.get("*") {
+            child.collect(rest, out);
+        }
+
+        // There is at least this level left, `>` matches it and the rest
+        if let Some(child) = self.children.get(">") {
+            out.extend(child.subscribers.iter().cloned());
+        }
+    }
+}
+
+impl<T: Copy + Eq + Hash> Trie<T> {
+    pub fn new() -> Trie<T> {
+        Trie { root: Node::new() }
+    }
+
+    // Returns false if the subscriber was already subscribed with the filter
+    pub fn insert(&mut self, filter: &str, subscriber: T) -> bool {
+        let node = filter.split('.').fold(&mut self.root, |node, level| {
+            node.children.entry(level.to_string()).or_insert_with(Node::new)
+        });
+
+        node.subscribers.insert(subscriber)
+    }
+
+    // Returns false if the subscriber wasn't subscribed with the filter
+    pub fn remove(&mut self, filter: &str, subscriber: T) -> bool {
+        let levels: Vec<&str> = filter.split('.').collect();
+        self.root.remove(&levels, subscriber)
+    }
+
+    // Adds the subscribers of the topic to `out`. A subscriber matching
+    // through several filters is only added once.
+    pub fn matches(&self, topic: &str, out: &mut HashSet<T>) {
+        let levels: Vec<&str> = topic.split('.').collect();
+        self.root.collect(&levels, out);
+    }
+}
+
+impl<T: Copy + Eq + Hash> Default for Trie<T> {
+    fn default() -> Trie<T> {
+        Trie::new()
+    }
+}
@@ -0,0 +1,220 @@
+// A pipe relaying bytes both ways between two sockets: what a proxy is
+// left with once it is done setting up a connection, whatever it took to
+// get there. Each direction has its own buffer, and reading from one side
+// stops while `MAX_BUFFERED` is waiting to be written to the other, so a
+// side that isn't keeping up slows the other down rather than filling the
+// proxy's memory.
+//
+// Either side can close its end while the other still has something to
+// say. That is passed on with a `shutdown` of the other socket, once what
+// was read before it has been written, and the pipe is done once both
+// sides are.
+//
+// The pipe does the reads and the writes, the caller owns the event loop:
+// it registers both sockets, with tokens of its choosing, and calls `read`
+// and `write` for the side an event is for. What is read from one side
+// waits to be written to the other, and a side reading again depends on
+// what the other has written, so after every event both sockets are
+// reregistered for what `interest` says each waits on. A side's closing
+// is passed on by `read` when nothing is left to write to the other, by
+// `write` once it is written otherwise.
+//
+// Errors are returned, for the caller to log and drop the pipe on. `read`
+// takes the most to read, for callers capping the transfer rate, and
+// `read_with` hands what it reads to a closure, for callers looking at
+// the bytes going through. The bytes written each way are counted.
+
+extern crate mio;
+
+use mio::{EventSet, TryRead, TryWrite};
+use mio::tcp::*;
+use std::{cmp, io};
+
+// Reading from one side stops while this much is waiting to be written to
+// the other
+pub const MAX_BUFFERED: usize = 64 * 1_024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Side {
+    A,
+    B,
+}
+
+impl Side {
+    pub fn other(self) -> Side {
+        match self {
+            Side::A => Side::B,
+            Side::B => Side::A,
+        }
+    }
+}
+
+struct End {
+    socket: TcpStream,
+    // To be written to the socket
+    out: Vec<u8>,
+    // Set once the peer has closed its end, and once the other side's
+    // closing was passed on to it
+    eof: bool,
+    shut: bool,
+    // Bytes written to the socket
+    written: u64,
+}
+
+pub struct Pipe {
+    a: End,
+    b: End,
+}
+
+impl Pipe {
+    pub fn new(a: TcpStream, b: TcpStream) -> Pipe {
+        Pipe {
+            a: End::new(a),
+            b: End::new(b),
+        }
+    }
+
+    pub fn socket(&self, side: Side) -> &TcpStream {
+        &self.end(side).socket
+    }
+
+    // Queues bytes to be written to a side, ahead of what is read from the
+    // other from now on. For what the proxy says itself, or what it read
+    // before there was a pipe.
+    pub fn push(&mut self, side: Side, data: &[u8]) {
+        self.end_mut(side).out.extend_from_slice(data);
+    }
+
+    // Reads what a side sent, at most `max` bytes, and queues it to be
+    // written to the other. Returns how much was read.
+    pub fn read(&mut self, side: Side, max: usize) -> io::Result<usize> {
+        self.read_with(side, max, |_| {})
+    }
+
+    // Same as `read`, handing every chunk read to `f` before it is queued
+    pub fn read_with<F>(&mut self, side: Side, max: usize, mut f: F) -> io::Result<usize>
+        where F: FnMut(&[u8]),
+    {
+        let (from, to) = self.ends(side);
+        let mut chunk = [0; 4_096];
+        let mut read = 0;
+
+        // The sockets are registered as edge triggered, drain it. Or at least
+        // until enough is waiting for the other side, or `max` was read.
+        while !from.eof && to.out.len() < MAX_BUFFERED && read < max {
+            let len = cmp::min(chunk.len(), max - read);
+
+            match from.socket.try_read(&mut chunk[..len])? {
+                Some(0) => from.eof = true,
+                Some(n) => {
+                    f(&chunk[..n]);
+                    to.out.extend_from_slice(&chunk[..n]);
+                    read += n;
+                }
+                None => break,
+            }
+        }
+
+        if from.eof {
+            to.pass_eof();
+        }
+
+        Ok(read)
+    }
+
+    // Writes what is waiting for a side, and passes the other side's
+    // closing on once all it sent is written
+    pub fn write(&mut self, side: Side) -> io::Result<()> {
+        let (to, from) = self.ends(side);
+
+        while !to.out.is_empty() {
+            match to.socket.try_write(&to.out)? {
+                Some(n) => {
+                    to.out.drain(..n);
+                    to.written += n as u64;
+                }
+                None => return Ok(()),
+            }
+        }
+
+        if from.eof {
+            to.pass_eof();
+        }
+
+        Ok(())
+    }
+
+    // What a side's socket waits on
+    pub fn interest(&self, side: Side) -> EventSet {
+        let end = self.end(side);
+        let mut interest = EventSet::none();
+
+        if !end.eof && self.end(side.other()).out.len() < MAX_BUFFERED {
+            interest = interest | EventSet::readable();
+        }
+
+        if !end.out.is_empty() {
+            interest = interest | EventSet::writable();
+        }
+
+        interest
+    }
+
+    // Whether a side has closed its end
+    pub fn is_closed(&self, side: Side) -> bool {
+        self.end(side).eof
+    }
+
+    // Both sides have closed their end, and it was passed on to the other
+    pub fn is_done(&self) -> bool {
+        self.a.shut && self.b.shut
+    }
+
+    // Bytes written to a side, pushed ones included
+    pub fn written(&self, side: Side) -> u64 {
+        self.end(side).written
+    }
+
+    fn end(&self, side: Side) -> &End {
+        match side {
+            Side::A => &self.a,
+            Side::B => &self.b,
+        }
+    }
+
+    fn end_mut(&mut self, side: Side) -> &mut End {
+        match side {
+            Side::A => &mut self.a,
+            Side::B => &mut self.b,
+        }
+    }
+
+    // The side's end, and the other one
+    fn ends(&mut self, side: Side) -> (&mut End, &mut End) {
+        match side {
+            Side::A => (&mut self.a, &mut self.b),
+            Side::B => (&mut self.b, &mut self.a),
+        }
+    }
+}
+
+impl End {
+    fn new(socket: TcpStream) -> End {
+        End {
+            socket: socket,
+            out: vec![],
+            eof: false,
+            shut: false,
+            written: 0,
+        }
+    }
+
+    // Shuts the socket's write half down, the other side having closed its
+    // end, once nothing is left to write to it
+    fn pass_eof(&mut self) {
+        if self.out.is_empty() && !self.shut {
+            let _ = self.socket.shutdown(Shutdown::Write);
+            self.shut = true;
+        }
+    }
+}
@@ -0,0 +1,469 @@
+extern crate acceptor;
+extern crate mio;
+extern crate rand;
+extern crate reconnect;
+
+use acceptor::{Acceptor, Factory};
+use mio::TryRead;
+use mio::tcp::*;
+use mio::util::Slab;
+use reconnect::{Backoff, Client, Event};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::{env, process};
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Tokens 1 to n are the connections to the peers, by node id, plus one.
+// Connections from the peers come after.
+const PEERS: usize = 1;
+
+const MAX_INBOUND: usize = 64;
+
+const MAX_LINE: usize = 1_024;
+
+// A follower not hearing from a leader for this long starts an election.
+// The timeout is picked at random in the range, again for every election,
+// so that usually one node times out first and wins before the others
+// start competing. Far longer than the heartbeat, so a few lost heartbeats
+// don't unseat a leader.
+const MIN_ELECTION_MS: u64 = 1_500;
+const MAX_ELECTION_MS: u64 = 3_000;
+
+const HEARTBEAT_MS: u64 = 500;
+
+// A peer that is down is retried up to this often
+const INITIAL_BACKOFF_MS: u64 = 250;
+const MAX_BACKOFF_MS: u64 = 2_000;
+
+#[derive(Clone, Copy)]
+enum Timer {
+    Election,
+    Heartbeat,
+    Reconnect(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+// The messages of Raft's leader election, without the log. Every message
+// carries the sender's term, and the id of the sender.
+//
+//     VOTE_REQ <term> <candidate>
+//     VOTE <term> <voter> <granted, 0 or 1>
+//     HEARTBEAT <term> <leader>
+//     HEARTBEAT_ACK <term> <follower>
+//
+// Messages go one way: a node sends on the connection it made to the peer,
+// and the peer answers on the connection it made back. Connections from
+// peers are only read from.
+#[derive(Debug, Clone, Copy)]
+enum Message {
+    VoteRequest(u64, usize),
+    Vote(u64, usize, bool),
+    Heartbeat(u64, usize),
+    HeartbeatAck(u64, usize),
+}
+
+impl Message {
+    fn term(&self) -> u64 {
+        match *self {
+            Message::VoteRequest(term, _) |
+            Message::Vote(term, _, _) |
+            Message::Heartbeat(term, _) |
+            Message::HeartbeatAck(term, _) => term,
+        }
+    }
+
+    fn sender(&self) -> usize {
+        match *self {
+            Message::VoteRequest(_, id) |
+            Message::Vote(_, id, _) |
+            Message::Heartbeat(_, id) |
+            Message::HeartbeatAck(_, id) => id,
+        }
+    }
+
+    fn encode(&self) -> String {
+        match *self {
+            Message::VoteRequest(term, id) => format!("VOTE_REQ {} {}\n", term, id),
+            Message::Vote(term, id, granted) => format!("VOTE {} {} {}\n", term, id, granted as u8),
+            Message::Heartbeat(term, id) => format!("HEARTBEAT {} {}\n", term, id),
+            Message::HeartbeatAck(term, id) => format!("HEARTBEAT_ACK {} {}\n", term, id),
+        }
+    }
+
+    fn decode(line: &str) -> Option<Message> {
+        let parts: Vec<&str> = line.split(' ').collect();
+
+        let term = parts.get(1)?.parse().ok()?;
+        let id = parts.get(2)?.parse().ok()?;
+
+        match (parts[0], parts.get(3), parts.len()) {
+            ("VOTE_REQ", None, 3) => Some(Message::VoteRequest(term, id)),
+            ("VOTE", Some(&"1"), 4) => Some(Message::Vote(term, id, true)),
+            ("VOTE", Some(&"0"), 4) => Some(Message::Vote(term, id, false)),
+            ("HEARTBEAT", None, 3) => Some(Message::Heartbeat(term, id)),
+            ("HEARTBEAT_ACK", None, 3) => Some(Message::HeartbeatAck(term, id)),
+            _ => None,
+        }
+    }
+}
+
+struct Node {
+    id: usize,
+    acceptor: Acceptor<Factory<Inbound>>,
+    // The connection to every other node, by id. `None` for this one.
+    peers: Vec<Option<Client<Timer>>>,
+    inbound: Slab<Inbound>,
+    role: Role,
+    // Terms only go up. A node learning of a later term, from any message,
+    // moves to it and becomes a follower.
+    term: u64,
+    // Who this node voted for in the current term. One vote per term, so
+    // there is at most one leader per term.
+    voted_for: Option<usize>,
+    votes: HashSet<usize>,
+    leader: Option<usize>,
+    election: Option<mio::Timeout>,
+    heartbeat: Option<mio::Timeout>,
+}
+
+impl Node {
+    fn cluster_size(&self) -> usize {
+        self.peers.len()
+    }
+
+    fn send(&mut self, event_loop: &mut mio::EventLoop<Node>, to: usize, msg: Message) {
+        // Raft messages are repeated by the timers when they get no answer,
+        // there is no point keeping one for a peer that is down
+        if let Some(ref mut client) = self.peers[to] {
+            client.send(msg.encode().as_bytes());
+            client.flush(event_loop);
+        }
+    }
+
+    fn broadcast(&mut self, event_loop: &mut mio::EventLoop<Node>, msg: Message) {
+        for to in 0..self.cluster_size() {
+            self.send(event_loop, to, msg);
+        }
+    }
+
+    fn reset_election_timer(&mut self, event_loop: &mut mio::EventLoop<Node>) {
+        if let Some(timeout) = self.election.take() {
+            event_loop.clear_timeout(timeout);
+        }
+
+        let ms = MIN_ELECTION_MS + rand::random::<u64>() % (MAX_ELECTION_MS - MIN_ELECTION_MS);
+        self.election = Some(event_loop.timeout_ms(Timer::Election, ms).unwrap());
+    }
+
+    fn start_election(&mut self, event_loop: &mut mio::EventLoop<Node>) {
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.voted_for = Some(self.id);
+        self.votes.clear();
+        self.votes.insert(self.id);
+
+        println!("election timeout, standing for election; term={}", self.term);
+
+        // With no majority by the next timeout, a split vote for one, the
+        // candidate tries again in a later term
+        self.reset_election_timer(event_loop);
+
+        let msg = Message::VoteRequest(self.term, self.id);
+        self.broadcast(event_loop, msg);
+        self.count_votes(event_loop);
+    }
+
+    fn count_votes(&mut self, event_loop: &mut mio::EventLoop<Node>) {
+        if self.role != Role::Candidate || self.votes.len() <= self.cluster_size() / 2 {
+            return;
+        }
+
+        println!("won the election, leading; term={}; votes={}/{}", self.term, self.votes.len(), self.cluster_size());
+
+        self.role = Role::Leader;
+        self.leader = Some(self.id);
+
+        // A leader doesn't time out, it is the one keeping the others from
+        // timing out
+        if let Some(timeout) = self.election.take() {
+            event_loop.clear_timeout(timeout);
+        }
+
+        self.heartbeat(event_loop);
+    }
+
+    fn heartbeat(&mut self, event_loop: &mut mio::EventLoop<Node>) {
+        if self.role != Role::Leader {
+            return;
+        }
+
+        let msg = Message::Heartbeat(self.term, self.id);
+        self.broadcast(event_loop, msg);
+
+        // A leader stepping down and winning again before its last timer
+        // fired would otherwise have two going
+        if let Some(timeout) = self.heartbeat.take() {
+            event_loop.clear_timeout(timeout);
+        }
+
+        self.heartbeat = Some(event_loop.timeout_ms(Timer::Heartbeat, HEARTBEAT_MS).unwrap());
+    }
+
+    fn handle(&mut self, event_loop: &mut mio::EventLoop<Node>, msg: Message) {
+        if msg.sender() >= self.cluster_size() || msg.sender() == self.id {
+            println!("ignoring message from unknown node; msg={:?}", msg);
+            return;
+        }
+
+        if msg.term() > self.term {
+            if self.role != Role::Follower {
+                println!("later term seen, stepping down; term={}; was={:?}", msg.term(), self.role);
+            }
+
+            self.term = msg.term();
+            self.role = Role::Follower;
+            self.voted_for = None;
+            self.leader = None;
+            self.reset_election_timer(event_loop);
+        }
+
+        match msg {
+            Message::VoteRequest(term, candidate) => {
+                // Without a log, any candidate in the current term is as good
+                // as any other, the first one to ask gets the vote
+                let granted = term == self.term && self.voted_for.map(|id| id == candidate).unwrap_or(true);
+
+                if granted {
+                    println!("voting; term={}; candidate={}", term, candidate);
+
+                    self.voted_for = Some(candidate);
+                    self.reset_election_timer(event_loop);
+                }
+
+                let reply = Message::Vote(self.term, self.id, granted);
+                self.send(event_loop, candidate, reply);
+            }
+            Message::Vote(term, voter, granted) => {
+                if granted && term == self.term && self.role == Role::Candidate {
+                    self.votes.insert(voter);
+                    self.count_votes(event_loop);
+                }
+            }
+            Message::Heartbeat(term, leader) => {
+                if term == self.term {
+                    // A candidate hearing from the leader of its term lost
+                    self.role = Role::Follower;
+
+                    if self.leader != Some(leader) {
+                        println!("following; term={}; leader={}", term, leader);
+                        self.leader = Some(leader);
+                    }
+
+                    self.reset_election_timer(event_loop);
+                }
+
+                // An old leader learns of the later term from the answer
+                let reply = Message::HeartbeatAck(self.term, self.id);
+                self.send(event_loop, leader, reply);
+            }
+            // Only for the term, handled above
+            Message::HeartbeatAck(..) => {}
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Node>) {
+        let (_, res) = self.acceptor.accept(event_loop, &mut self.inbound);
+
+        if let Err(e) = res {
+            println!("encountered error while accepting connection; err={:?}", e);
+            event_loop.shutdown();
+        }
+    }
+
+    fn inbound_ready(&mut self, event_loop: &mut mio::EventLoop<Node>, token: mio::Token) {
+        for line in self.inbound[token].read() {
+            match Message::decode(&line) {
+                Some(msg) => self.handle(event_loop, msg),
+                None => println!("ignoring invalid message; line={:?}", line),
+            }
+        }
+
+        if self.inbound[token].closed {
+            self.inbound.remove(token);
+            return;
+        }
+
+        event_loop.reregister(&self.inbound[token].socket, token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn peer_ready(&mut self, event_loop: &mut mio::EventLoop<Node>, peer: usize, events: mio::EventSet) {
+        let client = match self.peers[peer] {
+            Some(ref mut client) => client,
+            None => return,
+        };
+
+        // Peers never write on this connection, anything read is dropped
+        let mut buf = vec![];
+
+        match client.ready(event_loop, events, &mut buf) {
+            Some(Event::Connected) => println!("connected to peer; id={}", peer),
+            Some(Event::Disconnected) => println!("lost connection to peer; id={}", peer),
+            None => {}
+        }
+
+        client.flush(event_loop);
+    }
+}
+
+impl mio::Handler for Node {
+    type Timeout = Timer;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Node>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            mio::Token(i) if i < PEERS + self.cluster_size() => self.peer_ready(event_loop, i - PEERS, events),
+            _ => self.inbound_ready(event_loop, token),
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Node>, timer: Timer) {
+        match timer {
+            Timer::Election => {
+                self.election = None;
+                self.start_election(event_loop);
+            }
+            Timer::Heartbeat => {
+                self.heartbeat = None;
+                self.heartbeat(event_loop);
+            }
+            Timer::Reconnect(peer) => {
+                if let Some(ref mut client) = self.peers[peer] {
+                    client.connect(event_loop);
+                }
+            }
+        }
+    }
+}
+
+// A connection from a peer, read from only
+struct Inbound {
+    socket: TcpStream,
+    // Bytes of an incomplete line
+    buf: Vec<u8>,
+    closed: bool,
+}
+
+impl acceptor::Connection for Inbound {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Inbound {
+    fn new(socket: TcpStream) -> Inbound {
+        Inbound {
+            socket: socket,
+            buf: vec![],
+            closed: false,
+        }
+    }
+
+    // Returns the complete lines read
+    fn read(&mut self) -> Vec<String> {
+        let mut chunk = [0; 1_024];
+        let mut lines = vec![];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(Some(n)) => self.buf.extend(&chunk[..n]),
+                Ok(None) => break,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..pos + 1).take(pos).collect();
+            lines.push(String::from_utf8_lossy(&line).into_owned());
+        }
+
+        if self.buf.len() > MAX_LINE {
+            self.closed = true;
+        }
+
+        lines
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    // The id of this node, and the address of every node, this one included,
+    // in the order of their ids
+    let id: usize = match args.first().and_then(|id| id.parse().ok()) {
+        Some(id) if id + 1 < args.len() => id,
+        _ => {
+            println!("usage: raft_election <id> <node 0 addr> <node 1 addr> ...");
+            process::exit(1);
+        }
+    };
+
+    let addrs = &args[1..];
+    let address: SocketAddr = addrs[id].parse().unwrap();
+    let acceptor = Acceptor::bind(&address, SERVER, (|socket, _| Inbound::new(socket)) as Factory<Inbound>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let peers = addrs.iter().enumerate().map(|(peer, addr)| {
+        if peer == id {
+            return None;
+        }
+
+        let backoff = Backoff::new(INITIAL_BACKOFF_MS, MAX_BACKOFF_MS);
+        Some(Client::new(addr, mio::Token(PEERS + peer), Timer::Reconnect(peer), backoff, 0))
+    }).collect();
+
+    let mut node = Node {
+        id: id,
+        acceptor: acceptor,
+        peers: peers,
+        inbound: Slab::new_starting_at(mio::Token(PEERS + addrs.len()), MAX_INBOUND),
+        role: Role::Follower,
+        term: 0,
+        voted_for: None,
+        votes: HashSet::new(),
+        leader: None,
+        election: None,
+        heartbeat: None,
+    };
+
+    for peer in node.peers.iter_mut() {
+        if let Some(ref mut client) = *peer {
+            client.connect(&mut event_loop);
+        }
+    }
+
+    node.reset_election_timer(&mut event_loop);
+
+    println!("running raft node; id={}; addr={}; cluster={}", id, address, addrs.len());
+    event_loop.run(&mut node).unwrap();
+}
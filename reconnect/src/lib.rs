@@ -0,0 +1,374 @@
+// A TCP client connection that outlives the connections it is made of.
+// When the server goes away, the socket is dropped and a reconnect is
+// scheduled on the event loop's timer, further away after every failed
+// attempt, until the server is back.
+//
+// What is written to the connection comes in two kinds:
+//
+// * `send` is for the current connection only, like a protocol handshake
+//   or a reply to the server. It is dropped with the connection, or when
+//   there is no connection.
+//
+// * `queue` is for messages that have to get through. They are kept
+//   across connections, and written out once `replay` is called on the new
+//   one, after its handshake if the protocol has one.
+//
+// A queued message is only forgotten once it has been written out in
+// full. One written in part when the connection was lost is written again
+// in full on the next one, so the server may see it twice. Being written
+// isn't being received either: whatever the server hadn't read yet when
+// the connection was lost is gone. Knowing for sure takes acknowledgements
+// from the server, which are up to the protocol.
+
+extern crate mio;
+extern crate rand;
+
+use mio::{EventLoop, Handler, TryRead, TryWrite};
+use mio::tcp::TcpStream;
+use std::cmp;
+use std::collections::VecDeque;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+// A connection staying up for this long is considered a success, and the
+// backoff starts over. A server accepting connections and closing them
+// right away still gets an increasing delay between attempts.
+const STABLE_SECS: u64 = 10;
+
+// How long to wait between attempts. The delay doubles after every
+// attempt, up to the maximum.
+//
+// Every client of a server that restarts loses its connection at the same
+// time. With the same delays, they would all reconnect at the same time
+// too, again and again. So half of each delay is random, which spreads
+// them out after a couple of attempts.
+pub struct Backoff {
+    initial_ms: u64,
+    max_ms: u64,
+    delay_ms: u64,
+}
+
+impl Backoff {
+    pub fn new(initial_ms: u64, max_ms: u64) -> Backoff {
+        Backoff {
+            initial_ms: initial_ms,
+            max_ms: max_ms,
+            delay_ms: initial_ms,
+        }
+    }
+
+    // The delay before the next attempt
+    pub fn next_delay(&mut self) -> u64 {
+        let delay = self.delay_ms;
+        self.delay_ms = cmp::min(delay * 2, self.max_ms);
+
+        delay / 2 + rand::random::<u64>() % (delay - delay / 2 + 1)
+    }
+
+    pub fn reset(&mut self) {
+        self.delay_ms = self.initial_ms;
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    // The connection is up. Anything sent on the previous one is gone, the
+    // handshake should be sent again.
+    Connected,
+    // The connection is lost, and a reconnect is scheduled
+    Disconnected,
+}
+
+// `T` is the event loop timeout the client sets to be woken up when it is
+// time to reconnect. The handler should call `connect` when it fires.
+pub struct Client<T> {
+    addr: String,
+    token: mio::Token,
+    reconnect: T,
+    backoff: Backoff,
+    socket: Option<TcpStream>,
+    // Set once the non-blocking connect has completed
+    connected: Option<Instant>,
+    // Bytes sent on the current connection
+    out: Vec<u8>,
+    // Messages queued to get through, oldest first
+    pending: VecDeque<Vec<u8>>,
+    // How much of the oldest message has been written
+    written: usize,
+    max_pending: usize,
+    // Queued messages dropped for going over the maximum
+    dropped: u64,
+    replaying: bool,
+}
+
+impl<T: Clone> Client<T> {
+    // `addr` is resolved again on every attempt, a server that moved is
+    // found at its new address. At most `max_pending` messages are kept
+    // while disconnected, the oldest ones are dropped to make room.
+    pub fn new(addr: &str, token: mio::Token, reconnect: T, backoff: Backoff, max_pending: usize) -> Client<T> {
+        Client {
+            addr: addr.to_string(),
+            token: token,
+            reconnect: reconnect,
+            backoff: backoff,
+            socket: None,
+            connected: None,
+            out: vec![],
+            pending: VecDeque::new(),
+            written: 0,
+            max_pending: max_pending,
+            dropped: 0,
+            replaying: false,
+        }
+    }
+
+    // Starts a connection attempt. If it fails right away, the next one is
+    // scheduled.
+    pub fn connect<H: Handler<Timeout = T>>(&mut self, event_loop: &mut EventLoop<H>) {
+        if self.socket.is_some() {
+            return;
+        }
+
+        // Resolving the hostname blocks the event loop. It is only done
+        // while disconnected, when there is little else to do.
+        let addr = match resolve(&self.addr) {
+            Some(addr) => addr,
+            None => {
+                println!("failed to resolve server; addr={}", self.addr);
+                self.schedule(event_loop);
+                return;
+            }
+        };
+
+        println!("connecting; addr={:?}", addr);
+
+        let socket = match TcpStream::connect(&addr) {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("failed to connect; err={:?}", e);
+                self.schedule(event_loop);
+                return;
+            }
+        };
+
+        // The connect is non-blocking. The socket becomes writable once the
+        // connection is established (or has failed).
+        event_loop.register_opt(&socket, self.token, mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        self.socket = Some(socket);
+    }
+
+    // Drops the connection, and schedules a reconnect. Does nothing if
+    // there is no connection.
+    pub fn disconnect<H: Handler<Timeout = T>>(&mut self, event_loop: &mut EventLoop<H>) {
+        // Dropping the socket closes it, which also removes it from the
+        // event loop.
+        if self.socket.take().is_none() {
+            return;
+        }
+
+        let stable = self.connected.take()
+            .map(|at| at.elapsed() >= Duration::from_secs(STABLE_SECS))
+            .unwrap_or(false);
+
+        if stable {
+            self.backoff.reset();
+        }
+
+        self.out.clear();
+        self.written = 0;
+        self.replaying = false;
+
+        self.schedule(event_loop);
+    }
+
+    // Handles the socket being ready. Bytes read are appended to `buf`.
+    // The caller should handle them, send and queue what it has to, and
+    // call `flush`.
+    pub fn ready<H: Handler<Timeout = T>>(&mut self, event_loop: &mut EventLoop<H>, events: mio::EventSet, buf: &mut Vec<u8>) -> Option<Event> {
+        // The event may be for a connection dropped already
+        let socket = self.socket.as_ref()?;
+        let mut event = None;
+
+        if self.connected.is_none() {
+            if events.is_error() || events.is_hup() {
+                println!("connection failed; err={:?}", socket.take_socket_error());
+                self.disconnect(event_loop);
+                return Some(Event::Disconnected);
+            }
+
+            if events.is_writable() {
+                println!("connected; addr={}", self.addr);
+                self.connected = Some(Instant::now());
+                event = Some(Event::Connected);
+            }
+        }
+
+        if events.is_readable() && !self.read(buf) {
+            self.disconnect(event_loop);
+            return Some(Event::Disconnected);
+        }
+
+        event
+    }
+
+    // Writes to the current connection only
+    pub fn send(&mut self, data: &[u8]) {
+        if self.is_connected() {
+            self.out.extend(data);
+        }
+    }
+
+    // Writes the message once connected, and once `replay` has been called
+    // for the connection.
+    pub fn queue(&mut self, msg: Vec<u8>) {
+        if self.pending.len() == self.max_pending {
+            self.dropped += 1;
+
+            // The oldest message may be partly written already. Dropping it
+            // would leave the connection in the middle of it, the oldest
+            // one not started goes instead, or the new one if there is none.
+            if self.written == 0 {
+                self.pending.pop_front();
+            } else if self.pending.len() > 1 {
+                self.pending.remove(1);
+            } else {
+                return;
+            }
+        }
+
+        self.pending.push_back(msg);
+    }
+
+    // Starts writing queued messages on the current connection
+    pub fn replay(&mut self) {
+        if self.is_connected() && !self.replaying {
+            if !self.pending.is_empty() {
+                println!("replaying queued messages; count={}", self.pending.len());
+            }
+
+            self.replaying = true;
+        }
+    }
+
+    // Writes what it can, and registers for what is left to do
+    pub fn flush<H: Handler<Timeout = T>>(&mut self, event_loop: &mut EventLoop<H>) {
+        if !self.is_connected() {
+            return;
+        }
+
+        if !self.write() {
+            self.disconnect(event_loop);
+            return;
+        }
+
+        let interest = if self.is_flushed() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        };
+
+        let socket = self.socket.as_ref().unwrap();
+
+        event_loop.reregister(socket, self.token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.is_some()
+    }
+
+    // Whether everything that can be written on the current connection has
+    // been
+    pub fn is_flushed(&self) -> bool {
+        self.out.is_empty() && (!self.replaying || self.pending.is_empty())
+    }
+
+    // The number of queued messages not yet written out
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn schedule<H: Handler<Timeout = T>>(&mut self, event_loop: &mut EventLoop<H>) {
+        let delay = self.backoff.next_delay();
+
+        println!("reconnecting in {}ms", delay);
+        event_loop.timeout_ms(self.reconnect.clone(), delay).unwrap();
+    }
+
+    // Returns false if the connection has been closed or failed
+    fn read(&mut self, buf: &mut Vec<u8>) -> bool {
+        let socket = self.socket.as_mut().unwrap();
+        let mut chunk = [0; 4096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    println!("server closed the connection");
+                    return false;
+                }
+                Ok(Some(n)) => buf.extend(&chunk[..n]),
+                Ok(None) => return true,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    // Returns false if the connection failed
+    fn write(&mut self) -> bool {
+        let socket = self.socket.as_mut().unwrap();
+
+        // The current connection's bytes go first, the handshake has to
+        // come before anything queued.
+        while !self.out.is_empty() {
+            match socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return true,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    return false;
+                }
+            }
+        }
+
+        while self.replaying {
+            let msg = match self.pending.front() {
+                Some(msg) => msg,
+                None => break,
+            };
+
+            match socket.try_write(&msg[self.written..]) {
+                Ok(Some(n)) => {
+                    self.written += n;
+
+                    if self.written == msg.len() {
+                        self.pending.pop_front();
+                        self.written = 0;
+                    }
+                }
+                Ok(None) => return true,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+fn resolve(addr: &str) -> Option<SocketAddr> {
+    addr.to_socket_addrs().ok().and_then(|mut addrs| addrs.next())
+}
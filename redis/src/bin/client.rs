@@ -0,0 +1,288 @@
+extern crate mio;
+extern crate bytes;
+extern crate redis;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use redis::resp::{self, Value};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use std::env;
+
+const CLIENT: mio::Token = mio::Token(0);
+
+// How often progress is reported while the benchmark runs
+const REPORT_MS: u64 = 1_000;
+
+// A command that has been sent and is waiting for its reply. Redis answers
+// the commands on a connection in the order it received them, so replies
+// are matched to requests by position alone: the next reply is always for
+// the oldest request in flight.
+#[derive(Debug)]
+struct Request {
+    sent: Instant,
+    // The reply the command should get
+    expect: Value,
+}
+
+struct Client {
+    socket: TcpStream,
+    connected: bool,
+    // Bytes read but not yet decoded
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Requests sent and waiting for a reply, oldest first
+    in_flight: VecDeque<Request>,
+    // The number of requests the benchmark sends in total, and how many
+    // may be in flight at once.
+    total: usize,
+    depth: usize,
+    sent: usize,
+    stats: Stats,
+}
+
+#[derive(Debug)]
+struct Stats {
+    started: Instant,
+    completed: usize,
+    // Replies that didn't match what was expected
+    mismatched: usize,
+    latency_total: Duration,
+    latency_max: Duration,
+}
+
+impl Client {
+    fn new(socket: TcpStream, total: usize, depth: usize) -> Client {
+        Client {
+            socket: socket,
+            connected: false,
+            buf: vec![],
+            out: vec![],
+            in_flight: VecDeque::with_capacity(depth),
+            total: total,
+            depth: depth,
+            sent: 0,
+            stats: Stats {
+                started: Instant::now(),
+                completed: 0,
+                mismatched: 0,
+                latency_total: Duration::from_secs(0),
+                latency_max: Duration::from_secs(0),
+            },
+        }
+    }
+
+    // Queues requests until the pipeline is full. Each key is first set,
+    // then read back, so every other reply can be checked against the value
+    // written.
+    fn fill(&mut self) {
+        while self.in_flight.len() < self.depth && self.sent < self.total {
+            let n = self.sent / 2;
+            let key = format!("key:{}", n);
+            let value = format!("value:{}", n);
+
+            let (command, expect) = if self.sent.is_multiple_of(2) {
+                (Value::command(&[b"SET", key.as_bytes(), value.as_bytes()]), Value::ok())
+            } else {
+                (Value::command(&[b"GET", key.as_bytes()]), Value::Bulk(value.into_bytes()))
+            };
+
+            command.encode(&mut self.out);
+
+            self.in_flight.push_back(Request {
+                sent: Instant::now(),
+                expect: expect,
+            });
+
+            self.sent += 1;
+        }
+    }
+
+    // Returns false if the connection is closed or failed
+    fn read(&mut self) -> bool {
+        let mut buf = [0; 16 * 1_024];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    println!("server closed the connection");
+                    return false;
+                }
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    if let Err(e) = self.decode() {
+                        println!("invalid reply; err={:?}", e);
+                        return false;
+                    }
+                }
+                Ok(None) => return true,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    // Matches every complete reply in the buffer with its request
+    fn decode(&mut self) -> Result<(), resp::Error> {
+        let mut pos = 0;
+
+        while let Some((value, len)) = resp::decode(&self.buf[pos..])? {
+            pos += len;
+
+            let req = match self.in_flight.pop_front() {
+                Some(req) => req,
+                None => return Err(resp::Error("reply without a request")),
+            };
+
+            let latency = req.sent.elapsed();
+
+            self.stats.completed += 1;
+            self.stats.latency_total += latency;
+
+            if latency > self.stats.latency_max {
+                self.stats.latency_max = latency;
+            }
+
+            if value != req.expect {
+                if self.stats.mismatched == 0 {
+                    println!("unexpected reply; expected={:?}; got={:?}", req.expect, value);
+                }
+
+                self.stats.mismatched += 1;
+            }
+        }
+
+        self.buf.drain(..pos);
+        Ok(())
+    }
+
+    fn write(&mut self) -> bool {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return true,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    fn done(&self) -> bool {
+        self.stats.completed == self.total
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+
+    fn report(&self) {
+        let elapsed = self.stats.started.elapsed();
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        let completed = self.stats.completed.max(1) as u32;
+
+        println!("requests={}; elapsed={:.2}s; throughput={:.0} req/s; latency_avg={:?}; latency_max={:?}; mismatched={}",
+                 self.stats.completed,
+                 secs,
+                 self.stats.completed as f64 / secs,
+                 self.stats.latency_total / completed,
+                 self.stats.latency_max,
+                 self.stats.mismatched);
+    }
+}
+
+impl mio::Handler for Client {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Client>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, CLIENT);
+
+        if !self.connected {
+            // The connect is non-blocking, the socket becoming writable
+            // means it completed, successfully or not.
+            if let Err(e) = self.socket.take_socket_error() {
+                println!("failed to connect; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+
+            println!("connected; requests={}; pipeline depth={}", self.total, self.depth);
+
+            self.connected = true;
+            self.stats.started = Instant::now();
+            self.fill();
+        }
+
+        if events.is_readable() {
+            if !self.read() {
+                event_loop.shutdown();
+                return;
+            }
+
+            // Replies free up room in the pipeline
+            self.fill();
+        }
+
+        if !self.write() {
+            event_loop.shutdown();
+            return;
+        }
+
+        if self.done() {
+            self.report();
+            event_loop.shutdown();
+            return;
+        }
+
+        event_loop.reregister(&self.socket, CLIENT, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Client>, _: ()) {
+        if self.connected {
+            self.report();
+        }
+
+        event_loop.timeout_ms((), REPORT_MS).unwrap();
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("127.0.0.1:6379".to_string()).parse().unwrap();
+    let total: usize = args.next().map(|s| s.parse().unwrap()).unwrap_or(1_000_000);
+    let depth: usize = args.next().map(|s| s.parse().unwrap()).unwrap_or(128);
+
+    let socket = TcpStream::connect(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+
+    // The socket becomes writable once connected
+    event_loop.register_opt(
+        &socket,
+        CLIENT,
+        mio::EventSet::writable(),
+        mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+
+    event_loop.timeout_ms((), REPORT_MS).unwrap();
+
+    let mut client = Client::new(socket, total, depth);
+
+    event_loop.run(&mut client).unwrap();
+}
@@ -0,0 +1,441 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+extern crate redis;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use redis::resp::{self, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Inline commands (typed by hand over telnet) are limited to this length
+const MAX_INLINE: usize = 64 * 1_024;
+
+// Every key with a time to live holds a timeout, the event loop's timer
+// is sized for this many. Past it, setting a time to live fails.
+const MAX_EXPIRING: usize = 256 * 1_024;
+
+struct Entry {
+    value: Vec<u8>,
+    // When the key has a time to live, the timer that removes it, and when
+    // it fires.
+    expiry: Option<(mio::Timeout, Instant)>,
+}
+
+struct Redis {
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+    data: HashMap<Vec<u8>, Entry>,
+}
+
+impl Redis {
+    fn new(acceptor: Acceptor<Factory<Connection>>) -> Redis {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Redis {
+            acceptor: acceptor,
+            connections: slab,
+            data: HashMap::new(),
+        }
+    }
+
+    fn execute(&mut self, event_loop: &mut mio::EventLoop<Redis>, args: Vec<Vec<u8>>) -> Value {
+        let name = String::from_utf8_lossy(&args[0]).to_lowercase();
+
+        let arity = match &name[..] {
+            "ping" => 1..3,
+            "get" | "incr" | "ttl" => 2..3,
+            "set" => 3..6,
+            "del" => 2..usize::MAX,
+            "expire" => 3..4,
+            _ => return Value::error(&format!("unknown command '{}'", name)),
+        };
+
+        if !arity.contains(&args.len()) {
+            return Value::error(&format!("wrong number of arguments for '{}' command", name));
+        }
+
+        match &name[..] {
+            "ping" => {
+                match args.get(1) {
+                    Some(msg) => Value::Bulk(msg.clone()),
+                    None => Value::Simple("PONG".to_string()),
+                }
+            }
+            "get" => {
+                match self.data.get(&args[1]) {
+                    Some(entry) => Value::Bulk(entry.value.clone()),
+                    None => Value::Nil,
+                }
+            }
+            "set" => {
+                // SET key value [EX seconds | PX milliseconds]
+                let ttl = match args.len() {
+                    3 => None,
+                    5 => {
+                        let n = match parse_int(&args[4]) {
+                            Some(n) if n > 0 => n,
+                            _ => return Value::error("invalid expire time in 'set' command"),
+                        };
+
+                        let ms = match &String::from_utf8_lossy(&args[3]).to_lowercase()[..] {
+                            "ex" => n.checked_mul(1_000),
+                            "px" => Some(n),
+                            _ => return Value::error("syntax error"),
+                        };
+
+                        match ms {
+                            Some(ms) => Some(ms as u64),
+                            None => return Value::error("invalid expire time in 'set' command"),
+                        }
+                    }
+                    _ => return Value::error("syntax error"),
+                };
+
+                let mut args = args.into_iter().skip(1);
+                let key = args.next().unwrap();
+                let value = args.next().unwrap();
+
+                // Setting a key discards any time to live it had
+                self.remove(event_loop, &key);
+                self.data.insert(key.clone(), Entry { value: value, expiry: None });
+
+                // The value without its time to live would be a different
+                // command, it isn't kept
+                if let Some(ms) = ttl {
+                    if !self.expire(event_loop, key.clone(), ms) {
+                        self.remove(event_loop, &key);
+                        return Value::error("too many keys with a time to live");
+                    }
+                }
+
+                Value::ok()
+            }
+            "del" => {
+                let removed = args[1..].iter()
+                    .filter(|key| self.remove(event_loop, key))
+                    .count();
+
+                Value::Integer(removed as i64)
+            }
+            "incr" => {
+                let entry = self.data.entry(args[1].clone()).or_insert(Entry {
+                    value: b"0".to_vec(),
+                    expiry: None,
+                });
+
+                // The value is stored as a string, like every other value,
+                // and keeps its time to live.
+                let n = match parse_int(&entry.value).and_then(|n| n.checked_add(1)) {
+                    Some(n) => n,
+                    None => return Value::error("value is not an integer or out of range"),
+                };
+
+                entry.value = n.to_string().into_bytes();
+                Value::Integer(n)
+            }
+            "expire" => {
+                let secs = match parse_int(&args[2]) {
+                    Some(secs) => secs,
+                    None => return Value::error("value is not an integer or out of range"),
+                };
+
+                if !self.data.contains_key(&args[1]) {
+                    return Value::Integer(0);
+                }
+
+                // A time to live that is already over deletes the key
+                if secs <= 0 {
+                    self.remove(event_loop, &args[1]);
+                    return Value::Integer(1);
+                }
+
+                let ms = match secs.checked_mul(1_000) {
+                    Some(ms) => ms as u64,
+                    None => return Value::error("invalid expire time in 'expire' command"),
+                };
+
+                // The key keeps the time to live it had
+                if !self.expire(event_loop, args[1].clone(), ms) {
+                    return Value::error("too many keys with a time to live");
+                }
+
+                Value::Integer(1)
+            }
+            "ttl" => {
+                match self.data.get(&args[1]) {
+                    Some(&Entry { expiry: Some((_, at)), .. }) => {
+                        let left = at.saturating_duration_since(Instant::now());
+                        Value::Integer(left.as_millis().div_ceil(1_000) as i64)
+                    }
+                    Some(_) => Value::Integer(-1),
+                    None => Value::Integer(-2),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Sets a key's time to live. The event loop's timer removes the key
+    // when it is up, replacing any timer set before. Returns false, leaving
+    // the key as it was, if the timer is full.
+    fn expire(&mut self, event_loop: &mut mio::EventLoop<Redis>, key: Vec<u8>, ms: u64) -> bool {
+        let timeout = match event_loop.timeout_ms(key.clone(), ms) {
+            Ok(timeout) => timeout,
+            Err(_) => return false,
+        };
+
+        let at = Instant::now() + Duration::from_millis(ms);
+
+        let entry = self.data.get_mut(&key).unwrap();
+
+        if let Some((old, _)) = entry.expiry.take() {
+            event_loop.clear_timeout(old);
+        }
+
+        entry.expiry = Some((timeout, at));
+        true
+    }
+
+    // Removes a key along with its timer. Returns whether the key existed.
+    fn remove(&mut self, event_loop: &mut mio::EventLoop<Redis>, key: &[u8]) -> bool {
+        match self.data.remove(key) {
+            Some(entry) => {
+                if let Some((timeout, _)) = entry.expiry {
+                    event_loop.clear_timeout(timeout);
+                }
+
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl mio::Handler for Redis {
+    // The key to expire
+    type Timeout = Vec<u8>;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Redis>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
+                }
+            }
+            _ => {
+                if events.is_readable() {
+                    // Clients may pipeline commands, the replies are queued
+                    // in the same order.
+                    for args in self.connections[token].read() {
+                        let reply = self.execute(event_loop, args);
+                        reply.encode(&mut self.connections[token].out);
+                    }
+                }
+
+                self.connections[token].write();
+
+                if self.connections[token].closed {
+                    let _ = self.connections.remove(token);
+                } else {
+                    self.connections[token].reregister(event_loop);
+                }
+            }
+        }
+    }
+
+    fn timeout(&mut self, _: &mut mio::EventLoop<Redis>, key: Vec<u8>) {
+        // Timers are cleared whenever a key is removed or its time to live
+        // replaced, so a timer that fires is always the key's current one.
+        self.data.remove(&key);
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Bytes read but not yet decoded
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            buf: vec![],
+            out: vec![],
+            closed: false,
+        }
+    }
+
+    // Returns the complete commands read from the socket
+    fn read(&mut self) -> Vec<Vec<Vec<u8>>> {
+        let mut buf = [0; 4096];
+        let mut commands = vec![];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    if let Err(e) = self.decode(&mut commands) {
+                        // The protocol can't be resynchronized after an
+                        // error, the client is told and disconnected.
+                        println!("protocol error; err={:?}", e);
+                        Value::error(&format!("Protocol error: {}", e.0)).encode(&mut self.out);
+                        self.closed = true;
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        commands
+    }
+
+    fn decode(&mut self, commands: &mut Vec<Vec<Vec<u8>>>) -> Result<(), resp::Error> {
+        loop {
+            if self.buf.is_empty() {
+                return Ok(());
+            }
+
+            // Commands are normally sent as arrays of bulk strings. Anything
+            // else is an "inline" command: a line of space separated words.
+            if self.buf[0] != b'*' {
+                let pos = match self.buf.iter().position(|b| *b == b'\n') {
+                    Some(pos) => pos,
+                    None if self.buf.len() > MAX_INLINE => return Err(resp::Error("too big inline request")),
+                    None => return Ok(()),
+                };
+
+                let args: Vec<Vec<u8>> = self.buf[..pos]
+                    .split(|b| *b == b' ' || *b == b'\r')
+                    .filter(|arg| !arg.is_empty())
+                    .map(|arg| arg.to_vec())
+                    .collect();
+
+                self.buf.drain(..pos + 1);
+
+                if !args.is_empty() {
+                    commands.push(args);
+                }
+
+                continue;
+            }
+
+            let (value, len) = match resp::decode(&self.buf)? {
+                Some(value) => value,
+                None => return Ok(()),
+            };
+
+            self.buf.drain(..len);
+
+            let args = match value {
+                Value::Array(values) => values,
+                _ => return Err(resp::Error("expected an array")),
+            };
+
+            let mut command = vec![];
+
+            for arg in args {
+                match arg {
+                    Value::Bulk(data) => command.push(data),
+                    _ => return Err(resp::Error("expected a bulk string")),
+                }
+            }
+
+            if !command.is_empty() {
+                commands.push(command);
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn reregister(&self, event_loop: &mut mio::EventLoop<Redis>) {
+        let interest = if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        };
+
+        event_loop.reregister(&self.socket, self.token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+fn parse_int(data: &[u8]) -> Option<i64> {
+    String::from_utf8_lossy(data).parse().ok()
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:6379".to_string())
+        .parse().unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    // The default timer holds 65,536 timeouts
+    let config = mio::EventLoopConfig {
+        timer_capacity: MAX_EXPIRING,
+        ..mio::EventLoopConfig::default()
+    };
+
+    let mut event_loop = mio::EventLoop::configured(config).unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mut redis = Redis::new(acceptor);
+
+    println!("running Redis server; addr={:?}", address);
+    event_loop.run(&mut redis).unwrap();
+}
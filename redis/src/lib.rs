@@ -0,0 +1,5 @@
+// The Redis serialization protocol (RESP), shared by the server and the
+// client in `src/bin`. Like the rest of the examples' protocol code, it
+// does no I/O and works on buffers filled and drained by the event loop.
+
+pub mod resp;
@@ -0,0 +1,298 @@
+// RESP values are prefixed with a type byte and terminated by CRLF:
+//
+// +OK\r\n                     simple string
+// -ERR message\r\n            error
+// :42\r\n                     integer
+// $5\r\nhello\r\n             bulk string, `$-1\r\n` is nil
+// *2\r\n$3\r\nGET\r\n$1\r\nk\r\n  array of values, `*-1\r\n` is nil
+//
+// Bulk strings carry their length up front, so they are binary safe: the
+// payload may contain CRLF, and is never scanned.
+//
+// Everything a peer says about sizes is checked before it is trusted: the
+// length of a bulk string or of an array, how deep arrays nest, and how
+// much of a value may be buffered before it is complete. Arrays grow as
+// their elements come in, never from the length in their header.
+
+use std::str;
+
+// Bulk strings larger than this are refused
+pub const MAX_BULK: usize = 16 * 1_024 * 1_024;
+
+// Arrays with more elements than this are refused, the limit Redis has on
+// the arguments of a command
+pub const MAX_ARRAY: usize = 1_024 * 1_024;
+
+// Arrays nested deeper than this are refused
+pub const MAX_DEPTH: usize = 32;
+
+// A value still incomplete once this much of it is buffered is refused
+pub const MAX_VALUE: usize = 2 * MAX_BULK;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Array(Vec<Value>),
+    Nil,
+}
+
+impl Value {
+    pub fn ok() -> Value {
+        Value::Simple("OK".to_string())
+    }
+
+    pub fn error(msg: &str) -> Value {
+        Value::Error(format!("ERR {}", msg))
+    }
+
+    // A command is sent as an array of bulk strings
+    pub fn command(args: &[&[u8]]) -> Value {
+        Value::Array(args.iter().map(|arg| Value::Bulk(arg.to_vec())).collect())
+    }
+
+    pub fn encode(&self, dst: &mut Vec<u8>) {
+        match *self {
+            Value::Simple(ref s) => {
+                dst.push(b'+');
+                dst.extend(s.as_bytes());
+            }
+            Value::Error(ref s) => {
+                dst.push(b'-');
+                dst.extend(s.as_bytes());
+            }
+            Value::Integer(n) => {
+                dst.extend(format!(":{}", n).as_bytes());
+            }
+            Value::Bulk(ref data) => {
+                dst.extend(format!("${}\r\n", data.len()).as_bytes());
+                dst.extend(data);
+            }
+            Value::Array(ref values) => {
+                dst.extend(format!("*{}\r\n", values.len()).as_bytes());
+
+                // Every element ends with its own CRLF
+                for value in values {
+                    value.encode(dst);
+                }
+
+                return;
+            }
+            Value::Nil => {
+                dst.extend(b"$-1");
+            }
+        }
+
+        dst.extend(b"\r\n");
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Error(pub &'static str);
+
+// Decodes a value from the start of `buf`. Returns the value and the number
+// of bytes it used, or `None` if the value hasn't been fully received.
+pub fn decode(buf: &[u8]) -> Result<Option<(Value, usize)>, Error> {
+    match decode_at(buf, 0, 0)? {
+        None if buf.len() > MAX_VALUE => Err(Error("value too large")),
+        decoded => Ok(decoded),
+    }
+}
+
+// Decodes the value at `start`, inside `depth` arrays
+fn decode_at(buf: &[u8], start: usize, depth: usize) -> Result<Option<(Value, usize)>, Error> {
+    let (line, mut pos) = match read_line(buf, start) {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    if line.is_empty() {
+        return Err(Error("empty line"));
+    }
+
+    let text = match str::from_utf8(&line[1..]) {
+        Ok(text) => text,
+        Err(_) => return Err(Error("invalid header")),
+    };
+
+    let value = match line[0] {
+        b'+' => Value::Simple(text.to_string()),
+        b'-' => Value::Error(text.to_string()),
+        b':' => Value::Integer(parse_int(text)?),
+        b'$' => {
+            let len = parse_int(text)?;
+
+            if len < 0 {
+                return Ok(Some((Value::Nil, pos)));
+            }
+
+            let len = len as usize;
+
+            if len > MAX_BULK {
+                return Err(Error("bulk string too large"));
+            }
+
+            // The payload is followed by CRLF
+            if buf.len() < pos + len + 2 {
+                return Ok(None);
+            }
+
+            if &buf[pos + len..pos + len + 2] != b"\r\n" {
+                return Err(Error("bulk string not terminated"));
+            }
+
+            let data = buf[pos..pos + len].to_vec();
+            pos += len + 2;
+
+            Value::Bulk(data)
+        }
+        b'*' => {
+            let len = parse_int(text)?;
+
+            if len < 0 {
+                return Ok(Some((Value::Nil, pos)));
+            }
+
+            if len as usize > MAX_ARRAY {
+                return Err(Error("array too large"));
+            }
+
+            if depth == MAX_DEPTH {
+                return Err(Error("arrays nested too deep"));
+            }
+
+            let mut values = vec![];
+
+            for _ in 0..len {
+                match decode_at(buf, pos, depth + 1)? {
+                    Some((value, next)) => {
+                        values.push(value);
+                        pos = next;
+                    }
+                    None => return Ok(None),
+                }
+            }
+
+            Value::Array(values)
+        }
+        _ => return Err(Error("unknown type")),
+    };
+
+    Ok(Some((value, pos)))
+}
+
+// Finds the CRLF terminated line starting at `start`. Returns the line,
+// without the CRLF, and the position following it.
+fn read_line(buf: &[u8], start: usize) -> Option<(&[u8], usize)> {
+    buf[start..].windows(2)
+        .position(|w| w == b"\r\n")
+        .map(|pos| (&buf[start..start + pos], start + pos + 2))
+}
+
+fn parse_int(text: &str) -> Result<i64, Error> {
+    text.parse().map_err(|_| Error("invalid integer"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encoded(value: &Value) -> Vec<u8> {
+        let mut buf = vec![];
+        value.encode(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn decodes_values() {
+        let buf = b"+OK\r\n-ERR no\r\n:-42\r\n$5\r\nhe\r\nl\r\n$-1\r\n*-1\r\n";
+        let mut pos = 0;
+        let mut values = vec![];
+
+        while let Some((value, len)) = decode(&buf[pos..]).unwrap() {
+            values.push(value);
+            pos += len;
+        }
+
+        assert_eq!(pos, buf.len());
+        assert_eq!(values, vec![
+            Value::ok(),
+            Value::Error("ERR no".to_string()),
+            Value::Integer(-42),
+            Value::Bulk(b"he\r\nl".to_vec()),
+            Value::Nil,
+            Value::Nil,
+        ]);
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let value = Value::Array(vec![
+            Value::command(&[b"SET", b"k", b"v"]),
+            Value::Integer(7),
+            Value::Array(vec![]),
+        ]);
+
+        let buf = encoded(&value);
+        assert_eq!(decode(&buf), Ok(Some((value, buf.len()))));
+    }
+
+    #[test]
+    fn waits_for_truncated_values() {
+        let buf = encoded(&Value::command(&[b"GET", b"key"]));
+
+        for len in 0..buf.len() {
+            assert_eq!(decode(&buf[..len]), Ok(None), "len={}", len);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_values() {
+        assert_eq!(decode(b"\r\n"), Err(Error("empty line")));
+        assert_eq!(decode(b"?x\r\n"), Err(Error("unknown type")));
+        assert_eq!(decode(b":4x\r\n"), Err(Error("invalid integer")));
+        assert_eq!(decode(b"$3\r\nabcd\r\n"), Err(Error("bulk string not terminated")));
+        assert_eq!(decode(b"*2\r\n:1\r\n?\r\n"), Err(Error("unknown type")));
+    }
+
+    #[test]
+    fn rejects_oversize_lengths() {
+        let bulk = format!("${}\r\n", MAX_BULK + 1);
+        let array = format!("*{}\r\n", MAX_ARRAY + 1);
+
+        assert_eq!(decode(bulk.as_bytes()), Err(Error("bulk string too large")));
+        assert_eq!(decode(array.as_bytes()), Err(Error("array too large")));
+        // The largest array allowed only takes what its elements use
+        assert_eq!(decode(format!("*{}\r\n:1\r\n", MAX_ARRAY).as_bytes()), Ok(None));
+    }
+
+    #[test]
+    fn rejects_incomplete_value_over_max() {
+        // Each bulk string is within the limit, the array isn't
+        let header = format!("${}\r\n", MAX_BULK).into_bytes();
+        let mut buf = b"*3\r\n".to_vec();
+
+        buf.extend_from_slice(&header);
+        buf.resize(buf.len() + MAX_BULK, b'a');
+        buf.extend_from_slice(b"\r\n");
+        buf.extend_from_slice(&header);
+        buf.resize(MAX_VALUE, b'a');
+
+        assert_eq!(decode(&buf), Ok(None));
+        buf.push(b'a');
+        assert_eq!(decode(&buf), Err(Error("value too large")));
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let mut buf = b"*1\r\n".repeat(MAX_DEPTH);
+        buf.extend_from_slice(b":1\r\n");
+        assert!(decode(&buf).unwrap().is_some());
+
+        // Refused without recursing any deeper, however deep it goes
+        let buf = b"*1\r\n".repeat(100_000);
+        assert_eq!(decode(&buf), Err(Error("arrays nested too deep")));
+    }
+}
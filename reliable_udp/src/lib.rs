@@ -0,0 +1,236 @@
+// Reliable, ordered delivery of a byte stream over UDP, using stop-and-wait
+// ARQ (automatic repeat request). The stream is cut into segments, each
+// with a sequence number. Only one segment is in flight at a time: it is
+// sent again until the peer acknowledges it, then the next one is sent.
+//
+// Every packet starts with its type and a big endian u32 sequence number:
+//
+// +------+----------------+-----------------------+
+// | type | seq (4 bytes)  | payload (DATA only)   |
+// +------+----------------+-----------------------+
+//
+// FIN ends the stream. It takes a sequence number and is acknowledged like
+// a DATA segment, so the sender knows everything arrived.
+//
+// Like the rest of the examples' protocol code, the endpoint does no I/O.
+// The event loop feeds it the datagrams it receives and tells it when the
+// retransmission timer fires, and sends the packets it produces.
+
+use std::collections::VecDeque;
+
+const DATA: u8 = 0;
+const ACK: u8 = 1;
+const FIN: u8 = 2;
+
+const HEADER: usize = 5;
+
+// Small enough for a segment to fit in a datagram on any path without IP
+// fragmentation.
+pub const MAX_SEGMENT: usize = 1_024;
+
+// The retransmission timeout doubles with every retransmission of the same
+// segment, up to this many of them. The peer is given up on after 51
+// seconds without an ACK.
+pub const INITIAL_RTO_MS: u64 = 100;
+pub const MAX_RETRANSMITS: u32 = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    // The peer didn't acknowledge a segment, however often it was sent
+    TimedOut,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Stats {
+    // Segments sent, not counting retransmissions
+    pub segments: u64,
+    pub retransmits: u64,
+    // Segments received again because their ACK was lost
+    pub duplicates: u64,
+}
+
+// A sent segment waiting for its ACK
+struct Unacked {
+    seq: u32,
+    packet: Vec<u8>,
+    retransmits: u32,
+}
+
+#[derive(Default)]
+pub struct Endpoint {
+    // Written but not yet sent
+    outgoing: VecDeque<u8>,
+    next_seq: u32,
+    unacked: Option<Unacked>,
+    // Set once the application is done writing. The FIN is sent after the
+    // rest of the stream.
+    closing: bool,
+    fin_sent: bool,
+    fin_acked: bool,
+    // The sequence number of the next segment to deliver
+    expected: u32,
+    // Delivered, in order, but not yet read by the application
+    incoming: Vec<u8>,
+    fin_received: bool,
+    // Packets for the event loop to send
+    pending: VecDeque<Vec<u8>>,
+    pub stats: Stats,
+}
+
+impl Endpoint {
+    pub fn new() -> Endpoint {
+        Endpoint::default()
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        assert!(!self.closing, "write after close");
+
+        self.outgoing.extend(data);
+        self.fill();
+    }
+
+    // Ends the stream once everything written so far has been delivered
+    pub fn close(&mut self) {
+        self.closing = true;
+        self.fill();
+    }
+
+    // Takes the bytes delivered so far
+    pub fn read(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.incoming)
+    }
+
+    // The peer has closed the stream, and all of it has been read
+    pub fn is_eof(&self) -> bool {
+        self.fin_received && self.incoming.is_empty()
+    }
+
+    // Everything written has been acknowledged, FIN included
+    pub fn is_done(&self) -> bool {
+        self.fin_acked
+    }
+
+    // The next packet to send
+    pub fn poll_packet(&mut self) -> Option<Vec<u8>> {
+        self.pending.pop_front()
+    }
+
+    // The segment the retransmission timer should be running for, if any
+    pub fn unacked(&self) -> Option<u32> {
+        self.unacked.as_ref().map(|unacked| unacked.seq)
+    }
+
+    // How long to wait for the unacknowledged segment's ACK
+    pub fn rto_ms(&self) -> u64 {
+        let retransmits = self.unacked.as_ref().map(|unacked| unacked.retransmits).unwrap_or(0);
+        INITIAL_RTO_MS << retransmits
+    }
+
+    // The retransmission timer for `seq` fired. The timer may be stale, if
+    // the ACK arrived just before it fired.
+    pub fn retransmit(&mut self, seq: u32) -> Result<(), Error> {
+        let unacked = match self.unacked {
+            Some(ref mut unacked) if unacked.seq == seq => unacked,
+            _ => return Ok(()),
+        };
+
+        if unacked.retransmits == MAX_RETRANSMITS {
+            return Err(Error::TimedOut);
+        }
+
+        unacked.retransmits += 1;
+        self.stats.retransmits += 1;
+        self.pending.push_back(unacked.packet.clone());
+
+        Ok(())
+    }
+
+    // Handles a datagram from the peer. Malformed ones are ignored, as if
+    // they had been lost.
+    pub fn receive(&mut self, packet: &[u8]) {
+        if packet.len() < HEADER {
+            return;
+        }
+
+        let seq = packet[1..HEADER].iter().fold(0, |n, b| n << 8 | *b as u32);
+
+        match packet[0] {
+            DATA | FIN => {
+                if seq == self.expected {
+                    if packet[0] == FIN {
+                        self.fin_received = true;
+                    } else {
+                        self.incoming.extend(&packet[HEADER..]);
+                    }
+
+                    self.expected += 1;
+                } else if seq < self.expected {
+                    // Already delivered, the ACK must have been lost. It is
+                    // sent again, or the peer would keep retransmitting.
+                    self.stats.duplicates += 1;
+                } else {
+                    // Stop-and-wait never sends ahead of what was ACKed
+                    return;
+                }
+
+                self.pending.push_back(encode(ACK, seq, &[]));
+            }
+            ACK => {
+                let acked = match self.unacked {
+                    Some(ref unacked) => unacked.seq == seq,
+                    None => false,
+                };
+
+                if acked {
+                    self.unacked = None;
+
+                    if self.fin_sent && self.outgoing.is_empty() {
+                        self.fin_acked = true;
+                    }
+
+                    self.fill();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Sends the next segment, unless one is already waiting for its ACK
+    fn fill(&mut self) {
+        if self.unacked.is_some() || self.fin_sent {
+            return;
+        }
+
+        let packet = if !self.outgoing.is_empty() {
+            let n = std::cmp::min(MAX_SEGMENT, self.outgoing.len());
+            let payload: Vec<u8> = self.outgoing.drain(..n).collect();
+
+            encode(DATA, self.next_seq, &payload)
+        } else if self.closing {
+            self.fin_sent = true;
+            encode(FIN, self.next_seq, &[])
+        } else {
+            return;
+        };
+
+        self.unacked = Some(Unacked {
+            seq: self.next_seq,
+            packet: packet.clone(),
+            retransmits: 0,
+        });
+
+        self.next_seq += 1;
+        self.stats.segments += 1;
+        self.pending.push_back(packet);
+    }
+}
+
+fn encode(kind: u8, seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER + payload.len());
+
+    packet.push(kind);
+    packet.extend(&[(seq >> 24) as u8, (seq >> 16) as u8, (seq >> 8) as u8, seq as u8]);
+    packet.extend(payload);
+
+    packet
+}
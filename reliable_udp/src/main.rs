@@ -0,0 +1,265 @@
+extern crate mio;
+extern crate bytes;
+extern crate rand;
+extern crate reliable_udp;
+
+use mio::udp::*;
+use bytes::SliceBuf;
+use reliable_udp::Endpoint;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::time::Instant;
+use std::{env, process};
+
+const SOCKET: mio::Token = mio::Token(0);
+
+const MAX_DATAGRAM: usize = 2_048;
+
+// The receiver stays around after the stream ends, in case its ACK of the
+// FIN is lost and the sender retransmits it.
+const LINGER_MS: u64 = 2_000;
+
+enum Timeout {
+    // The segment with this sequence number wasn't acknowledged in time
+    Retransmit(u32),
+    Linger,
+}
+
+// Drops datagrams at random, in both directions, to show that the endpoint
+// recovers from loss
+struct Loss {
+    rate: f64,
+    dropped: u64,
+}
+
+impl Loss {
+    fn new(rate: f64) -> Loss {
+        Loss {
+            rate: rate,
+            dropped: 0,
+        }
+    }
+
+    fn drop(&mut self) -> bool {
+        let dropped = rand::random::<f64>() < self.rate;
+
+        if dropped {
+            self.dropped += 1;
+        }
+
+        dropped
+    }
+}
+
+struct Demo {
+    socket: UdpSocket,
+    // The sender knows its peer from the start, the receiver talks to
+    // whoever sends the first datagram.
+    peer: Option<SocketAddr>,
+    endpoint: Endpoint,
+    // Where the receiver writes the stream
+    output: Option<File>,
+    loss: Loss,
+    // The segment the retransmission timer is running for
+    timer: Option<(u32, mio::Timeout)>,
+    lingering: bool,
+    started: Instant,
+    bytes: u64,
+}
+
+impl Demo {
+    fn new(socket: UdpSocket, peer: Option<SocketAddr>, output: Option<File>, loss: f64) -> Demo {
+        Demo {
+            socket: socket,
+            peer: peer,
+            endpoint: Endpoint::new(),
+            output: output,
+            loss: Loss::new(loss),
+            timer: None,
+            lingering: false,
+            started: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    fn receive(&mut self, event_loop: &mut mio::EventLoop<Demo>) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event.
+        loop {
+            let mut buf = Vec::with_capacity(MAX_DATAGRAM);
+
+            let addr = match self.socket.recv_from(&mut buf) {
+                Ok(Some(addr)) => addr,
+                Ok(None) => break,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            if *self.peer.get_or_insert(addr) != addr {
+                println!("ignoring datagram from another peer; addr={:?}", addr);
+                continue;
+            }
+
+            if self.loss.drop() {
+                continue;
+            }
+
+            self.endpoint.receive(&buf);
+        }
+
+        if let Some(ref mut output) = self.output {
+            let data = self.endpoint.read();
+            self.bytes += data.len() as u64;
+            output.write_all(&data).unwrap();
+        }
+
+        self.flush(event_loop);
+    }
+
+    // Sends what the endpoint has queued up, and keeps the retransmission
+    // timer running for the segment waiting to be acknowledged.
+    fn flush(&mut self, event_loop: &mut mio::EventLoop<Demo>) {
+        let peer = self.peer.unwrap();
+
+        while let Some(packet) = self.endpoint.poll_packet() {
+            if self.loss.drop() {
+                continue;
+            }
+
+            // A datagram that can't be sent right away is as good as lost,
+            // the endpoint retransmits it.
+            match self.socket.send_to(&mut SliceBuf::wrap(&packet), &peer) {
+                Ok(Some(())) => {}
+                Ok(None) => println!("the socket wasn't actually ready, dropping datagram"),
+                Err(e) => println!("failed to send datagram; addr={:?}; err={:?}", peer, e),
+            }
+        }
+
+        let unacked = self.endpoint.unacked();
+
+        if self.timer.map(|(seq, _)| seq) != unacked {
+            if let Some((_, timeout)) = self.timer.take() {
+                event_loop.clear_timeout(timeout);
+            }
+
+            if let Some(seq) = unacked {
+                let timeout = event_loop.timeout_ms(Timeout::Retransmit(seq), self.endpoint.rto_ms()).unwrap();
+                self.timer = Some((seq, timeout));
+            }
+        }
+
+        if self.endpoint.is_done() {
+            println!("stream delivered");
+            self.summary();
+            event_loop.shutdown();
+        }
+
+        if self.endpoint.is_eof() && !self.lingering {
+            println!("stream received");
+            self.summary();
+
+            self.lingering = true;
+            event_loop.timeout_ms(Timeout::Linger, LINGER_MS).unwrap();
+        }
+    }
+
+    fn summary(&self) {
+        let elapsed = self.started.elapsed();
+        let stats = self.endpoint.stats;
+
+        if self.output.is_some() {
+            println!("bytes:       {}", self.bytes);
+        }
+
+        println!("elapsed:     {}.{:03}s", elapsed.as_secs(), elapsed.subsec_millis());
+        println!("segments:    {}", stats.segments);
+        println!("retransmits: {}", stats.retransmits);
+        println!("duplicates:  {}", stats.duplicates);
+        println!("dropped:     {}", self.loss.dropped);
+    }
+}
+
+impl mio::Handler for Demo {
+    type Timeout = Timeout;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Demo>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, SOCKET);
+        assert!(events.is_readable(), "unexpected events; events={:?}", events);
+
+        self.receive(event_loop);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Demo>, timeout: Timeout) {
+        match timeout {
+            Timeout::Retransmit(seq) => {
+                self.timer = None;
+
+                if let Err(e) = self.endpoint.retransmit(seq) {
+                    println!("giving up, the peer isn't responding; err={:?}", e);
+                    self.summary();
+                    event_loop.shutdown();
+                    return;
+                }
+
+                self.flush(event_loop);
+            }
+            Timeout::Linger => event_loop.shutdown(),
+        }
+    }
+}
+
+fn usage() -> ! {
+    println!("usage: reliable_udp send <file> [addr] [loss]");
+    println!("       reliable_udp receive <file> [addr] [loss]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let mode = args.next().unwrap_or_else(|| usage());
+    let path = args.next().unwrap_or_else(|| usage());
+    let address: SocketAddr = args.next().unwrap_or("127.0.0.1:9700".to_string()).parse().unwrap();
+
+    // The share of datagrams to drop, from 0 to 1
+    let loss: f64 = args.next().map(|s| s.parse().unwrap()).unwrap_or(0.0);
+
+    // The default timer tick is 100ms, too coarse for retransmission
+    // timeouts starting at 100ms
+    let config = mio::EventLoopConfig {
+        timer_tick_ms: 10,
+        ..mio::EventLoopConfig::default()
+    };
+
+    let mut event_loop = mio::EventLoop::configured(config).unwrap();
+
+    let mut demo = match &mode[..] {
+        "send" => {
+            let mut data = vec![];
+            File::open(&path).unwrap().read_to_end(&mut data).unwrap();
+
+            let socket = UdpSocket::bound(&"0.0.0.0:0".parse().unwrap()).unwrap();
+            let mut demo = Demo::new(socket, Some(address), None, loss);
+
+            println!("sending; file={:?}; bytes={}; addr={:?}; loss={}", path, data.len(), address, loss);
+
+            demo.endpoint.write(&data);
+            demo.endpoint.close();
+            demo.flush(&mut event_loop);
+            demo
+        }
+        "receive" => {
+            let socket = UdpSocket::bound(&address).unwrap();
+            let output = File::create(&path).unwrap();
+
+            println!("receiving; file={:?}; addr={:?}; loss={}", path, address, loss);
+
+            Demo::new(socket, None, Some(output), loss)
+        }
+        _ => usage(),
+    };
+
+    event_loop.register_opt(&demo.socket, SOCKET, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+    event_loop.run(&mut demo).unwrap();
+}
@@ -0,0 +1,385 @@
+extern crate acceptor;
+extern crate mio;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::time::Instant;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 1_024;
+
+const MAX_LINE: usize = 64 * 1_024;
+
+// Past this much output queued for a replica, entries wait in the log until
+// it has read some
+const MAX_OUT: usize = 256 * 1_024;
+
+const STATS_MS: u64 = 1_000;
+
+struct Entry {
+    // When it was appended, to tell how far behind a replica is in time
+    at: Instant,
+    data: String,
+}
+
+// Accepts writes and appends them to a log, kept in memory, and streams the
+// log to the replicas. Writers and replicas connect to the same port, and
+// tell which they are by what they send:
+//
+//     WRITE <data>                 -> OK <offset>
+//     REPLICATE <offset> <name>    -> ENTRY <offset> <data>, one per
+//                                     entry, from the offset on
+//
+// A replica acknowledges with `ACK <offset>`, the offset being the next
+// entry it doesn't have yet. What it acknowledged is how far behind it is.
+struct Primary {
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+    log: Vec<Entry>,
+    // Connections that had output queued, or were closed, while handling the
+    // current event. They are reregistered (or removed) once it is done.
+    dirty: Vec<mio::Token>,
+}
+
+struct Replica {
+    name: String,
+    // The next entry to send
+    next: u64,
+    // The next entry it doesn't have, according to its last ACK
+    acked: u64,
+}
+
+impl Primary {
+    fn new(acceptor: Acceptor<Factory<Connection>>) -> Primary {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS);
+
+        Primary {
+            acceptor: acceptor,
+            connections: slab,
+            log: vec![],
+            dirty: vec![],
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Primary>) {
+        let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+        if let Err(e) = res {
+            println!("encountered error while accepting connection; err={:?}", e);
+            event_loop.shutdown();
+        }
+    }
+
+    fn connection_ready(&mut self, token: mio::Token, events: mio::EventSet) {
+        let written = self.log.len();
+
+        if events.is_readable() {
+            for line in self.connections[token].read() {
+                self.handle(token, &line);
+            }
+        }
+
+        if events.is_writable() {
+            self.connections[token].write();
+        }
+
+        // New entries go to every replica, an ACK or a write makes room for
+        // more on this one
+        if self.log.len() > written {
+            let replicas: Vec<mio::Token> = self.connections.iter()
+                .filter(|conn| conn.replica.is_some())
+                .map(|conn| conn.token)
+                .collect();
+
+            for token in replicas {
+                self.stream(token);
+            }
+        } else {
+            self.stream(token);
+        }
+    }
+
+    fn handle(&mut self, token: mio::Token, line: &str) {
+        let mut parts = line.splitn(2, ' ');
+        let command = parts.next().unwrap_or("");
+        let rest = parts.next().unwrap_or("");
+
+        let is_replica = self.connections[token].replica.is_some();
+
+        match command {
+            "WRITE" if !is_replica => {
+                self.log.push(Entry {
+                    at: Instant::now(),
+                    data: rest.to_string(),
+                });
+
+                let offset = self.log.len() - 1;
+                self.reply(token, &format!("OK {}", offset));
+            }
+            "REPLICATE" if !is_replica => {
+                let mut parts = rest.split(' ');
+
+                let (offset, name) = match (parts.next().map(|offset| offset.parse::<u64>()), parts.next()) {
+                    (Some(Ok(offset)), Some(name)) => (offset, name),
+                    _ => return self.reply(token, "ERR expected an offset and a name"),
+                };
+
+                // A replica ahead of the primary has entries the primary
+                // lost, it can't follow this log
+                if offset > self.log.len() as u64 {
+                    self.reply(token, &format!("ERR offset past the end of the log; end={}", self.log.len()));
+                    self.connections[token].closing = true;
+                    return;
+                }
+
+                println!("replica connected; name={}; offset={}; behind={}", name, offset, self.log.len() as u64 - offset);
+
+                self.connections[token].replica = Some(Replica {
+                    name: name.to_string(),
+                    next: offset,
+                    acked: offset,
+                });
+            }
+            "ACK" if is_replica => {
+                let acked = match rest.parse::<u64>() {
+                    Ok(acked) => acked,
+                    Err(_) => return self.reply(token, "ERR invalid offset"),
+                };
+
+                let replica = self.connections[token].replica.as_mut().unwrap();
+
+                // Only what was sent can be acknowledged, and acknowledgements
+                // only go forward
+                if acked <= replica.next && acked > replica.acked {
+                    replica.acked = acked;
+                }
+            }
+            _ => self.reply(token, "ERR unknown command"),
+        }
+    }
+
+    // Sends a replica the entries it doesn't have, as many as fit
+    fn stream(&mut self, token: mio::Token) {
+        let conn = match self.connections.get_mut(token) {
+            Some(conn) => conn,
+            None => return,
+        };
+
+        {
+            let replica = match conn.replica {
+                Some(ref mut replica) => replica,
+                None => return,
+            };
+
+            while (replica.next as usize) < self.log.len() && conn.out.len() < MAX_OUT {
+                let entry = &self.log[replica.next as usize];
+
+                conn.out.extend(format!("ENTRY {} {}\n", replica.next, entry.data).as_bytes());
+                replica.next += 1;
+            }
+        }
+
+        self.dirty.push(token);
+    }
+
+    fn reply(&mut self, token: mio::Token, line: &str) {
+        let conn = &mut self.connections[token];
+
+        conn.out.extend(line.as_bytes());
+        conn.out.push(b'\n');
+
+        self.dirty.push(token);
+    }
+
+    // Reregisters every connection touched while handling an event, and
+    // removes the ones that are done.
+    fn flush(&mut self, event_loop: &mut mio::EventLoop<Primary>) {
+        while let Some(token) = self.dirty.pop() {
+            let done = match self.connections.get_mut(token) {
+                Some(conn) => {
+                    conn.write();
+                    conn.closed || (conn.closing && conn.out.is_empty())
+                }
+                None => continue,
+            };
+
+            if done {
+                let conn = self.connections.remove(token).unwrap();
+
+                if let Some(replica) = conn.replica {
+                    println!("replica disconnected; name={}; acked={}", replica.name, replica.acked);
+                }
+
+                continue;
+            }
+
+            let conn = &self.connections[token];
+
+            event_loop.reregister(&conn.socket, token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    // How far behind each replica is: in entries, the ones it hasn't
+    // acknowledged, and in time, how long ago the oldest of them was
+    // written. A replica that acknowledges everything it gets still lags
+    // by the round trip, and a replica that can't keep up, or isn't
+    // reading, lags more and more.
+    fn stats(&self) {
+        let end = self.log.len() as u64;
+
+        println!("primary; entries={}", end);
+
+        for conn in self.connections.iter() {
+            let replica = match conn.replica {
+                Some(ref replica) => replica,
+                None => continue,
+            };
+
+            let lag_ms = match self.log.get(replica.acked as usize) {
+                Some(entry) => {
+                    let elapsed = entry.at.elapsed();
+                    elapsed.as_secs() * 1_000 + elapsed.subsec_nanos() as u64 / 1_000_000
+                }
+                None => 0,
+            };
+
+            println!("  replica {}; acked={}; in flight={}; lag={} entries, {}ms",
+                     replica.name, replica.acked, replica.next - replica.acked, end - replica.acked, lag_ms);
+        }
+    }
+}
+
+impl mio::Handler for Primary {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Primary>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ => {
+                self.connection_ready(token, events);
+                self.flush(event_loop);
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Primary>, _: ()) {
+        self.stats();
+        event_loop.timeout_ms((), STATS_MS).unwrap();
+    }
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Bytes of an incomplete line
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Set by REPLICATE
+    replica: Option<Replica>,
+    // Set when the connection should be closed once `out` is flushed
+    closing: bool,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            buf: vec![],
+            out: vec![],
+            replica: None,
+            closing: false,
+            closed: false,
+        }
+    }
+
+    // Returns the complete lines read
+    fn read(&mut self) -> Vec<String> {
+        let mut chunk = [0; 4_096];
+        let mut lines = vec![];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(Some(n)) => self.buf.extend(&chunk[..n]),
+                Ok(None) => break,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..pos + 1).take(pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            lines.push(line.trim_end_matches('\r').to_string());
+        }
+
+        if self.buf.len() > MAX_LINE {
+            println!("line too long, closing connection; token={:?}", self.token);
+            self.closed = true;
+        }
+
+        lines
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1).unwrap_or("0.0.0.0:9900".to_string()).parse().unwrap();
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+    event_loop.timeout_ms((), STATS_MS).unwrap();
+
+    println!("running primary; addr={:?}", address);
+
+    let mut primary = Primary::new(acceptor);
+    event_loop.run(&mut primary).unwrap();
+}
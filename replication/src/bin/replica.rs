@@ -0,0 +1,157 @@
+extern crate mio;
+extern crate reconnect;
+
+use reconnect::{Backoff, Client, Event};
+use std::env;
+
+const PRIMARY: mio::Token = mio::Token(0);
+
+const INITIAL_BACKOFF_MS: u64 = 500;
+const MAX_BACKOFF_MS: u64 = 10_000;
+
+const STATS_MS: u64 = 1_000;
+
+#[derive(Clone, Copy)]
+enum Timer {
+    Reconnect,
+    Ack,
+    Stats,
+}
+
+// Follows the primary's log, keeping a copy in memory. On every
+// connection, it asks for the entries from the end of its copy, so a
+// replica coming back after losing the connection only gets what it
+// missed.
+struct Replica {
+    client: Client<Timer>,
+    name: String,
+    log: Vec<String>,
+    // Bytes of an incomplete line
+    buf: Vec<u8>,
+    // Acknowledge on a timer instead of as soon as entries arrive, to play
+    // a replica that falls behind. 0 acknowledges right away.
+    ack_ms: u64,
+    acked: usize,
+}
+
+impl Replica {
+    fn primary_ready(&mut self, event_loop: &mut mio::EventLoop<Replica>, events: mio::EventSet) {
+        let mut buf = std::mem::take(&mut self.buf);
+
+        match self.client.ready(event_loop, events, &mut buf) {
+            Some(Event::Connected) => {
+                let line = format!("REPLICATE {} {}\n", self.log.len(), self.name);
+                self.client.send(line.as_bytes());
+                self.acked = self.log.len();
+            }
+            Some(Event::Disconnected) => {
+                buf.clear();
+            }
+            None => {}
+        }
+
+        while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buf.drain(..pos + 1).take(pos).collect();
+
+            if !self.apply(&String::from_utf8_lossy(&line)) {
+                // Resuming from the end of the copy is the only way out of a
+                // gap, and it starts with a new connection
+                self.client.disconnect(event_loop);
+                buf.clear();
+                break;
+            }
+        }
+
+        self.buf = buf;
+
+        if self.ack_ms == 0 {
+            self.ack();
+        }
+
+        self.client.flush(event_loop);
+    }
+
+    // Returns false if the primary and the copy don't agree
+    fn apply(&mut self, line: &str) -> bool {
+        let mut parts = line.splitn(3, ' ');
+
+        match (parts.next(), parts.next().map(|offset| offset.parse::<usize>())) {
+            (Some("ENTRY"), Some(Ok(offset))) if offset == self.log.len() => {
+                self.log.push(parts.next().unwrap_or("").to_string());
+                true
+            }
+            (Some("ENTRY"), Some(Ok(offset))) => {
+                println!("unexpected entry; offset={}; expected={}", offset, self.log.len());
+                false
+            }
+            _ => {
+                println!("primary says; line={:?}", line);
+                false
+            }
+        }
+    }
+
+    fn ack(&mut self) {
+        if self.acked == self.log.len() || !self.client.is_connected() {
+            return;
+        }
+
+        self.client.send(format!("ACK {}\n", self.log.len()).as_bytes());
+        self.acked = self.log.len();
+    }
+}
+
+impl mio::Handler for Replica {
+    type Timeout = Timer;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Replica>, _: mio::Token, events: mio::EventSet) {
+        self.primary_ready(event_loop, events);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Replica>, timer: Timer) {
+        match timer {
+            Timer::Reconnect => self.client.connect(event_loop),
+            Timer::Ack => {
+                self.ack();
+                self.client.flush(event_loop);
+                event_loop.timeout_ms(Timer::Ack, self.ack_ms).unwrap();
+            }
+            Timer::Stats => {
+                println!("replica; name={}; entries={}; connected={}", self.name, self.log.len(), self.client.is_connected());
+                event_loop.timeout_ms(Timer::Stats, STATS_MS).unwrap();
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let primary = args.next().unwrap_or("127.0.0.1:9900".to_string());
+    let name = args.next().unwrap_or("replica".to_string());
+    let ack_ms: u64 = args.next().map(|ms| ms.parse().unwrap()).unwrap_or(0);
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+
+    let backoff = Backoff::new(INITIAL_BACKOFF_MS, MAX_BACKOFF_MS);
+
+    let mut replica = Replica {
+        client: Client::new(&primary, PRIMARY, Timer::Reconnect, backoff, 0),
+        name: name,
+        log: vec![],
+        buf: vec![],
+        ack_ms: ack_ms,
+        acked: 0,
+    };
+
+    replica.client.connect(&mut event_loop);
+    event_loop.timeout_ms(Timer::Stats, STATS_MS).unwrap();
+
+    if ack_ms > 0 {
+        event_loop.timeout_ms(Timer::Ack, ack_ms).unwrap();
+    }
+
+    println!("running replica; primary={}; name={}", primary, replica.name);
+    event_loop.run(&mut replica).unwrap();
+}
@@ -0,0 +1,289 @@
+// Hostname resolution that doesn't block the event loop. The system's
+// resolver, `getaddrinfo`, only comes as a blocking call, which may take
+// seconds when a DNS server is slow or gone. So the lookups are made on a
+// few threads of their own, and each answer is sent back through the
+// event loop's channel, the way the offload example sends back its
+// results.
+//
+// The handler plugs it in at three places:
+//
+// * `resolve` starts a query, or answers it right away from the cache.
+//   A pending query is answered later, with the tag it was started with;
+// * `answer`, from `Handler::notify`, with the `Answer` the lookup thread
+//   sent. It returns the queries it completes;
+// * `expire`, from `Handler::timeout`, with the `Deadline` of a query
+//   taking too long. Every query has its own.
+//
+// The event loop's message type has to be built from an `Answer`, and its
+// timeout type from a `Deadline`, which is what the `From` bounds are
+// for.
+//
+// Queries for the same name share the lookup in flight. A query timing out
+// doesn't stop its lookup, there is no stopping `getaddrinfo`, but its
+// answer still goes to the cache for the next query.
+
+extern crate mio;
+
+use mio::{EventLoop, Handler};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fmt, thread};
+
+// How long answers are kept. `getaddrinfo` doesn't tell the TTL of the
+// records, these stand in for it. Failures are kept for less, but kept, so
+// that a crawler with many links to a dead host doesn't look it up again
+// for each one.
+const TTL_SECS: u64 = 60;
+const FAILURE_TTL_SECS: u64 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    // The lookup failed, or found no address
+    Failed(String),
+    // No answer within the query's timeout
+    TimedOut,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Failed(ref err) => write!(f, "{}", err),
+            Error::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+pub enum Resolve<T> {
+    // From the cache, or the name was an address already. The tag comes
+    // back with the result.
+    Done(T, Result<SocketAddr, Error>),
+    // The answer comes later, through `answer` or `expire`
+    Pending(Query),
+}
+
+// A pending query, to cancel it
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Query(u64);
+
+// The timeout of a pending query, to be handed to `expire`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Deadline(u64);
+
+// The result of a lookup, sent by a lookup thread to the event loop
+pub struct Answer {
+    host: String,
+    port: u16,
+    result: Result<SocketAddr, Error>,
+}
+
+struct Entry {
+    result: Result<SocketAddr, Error>,
+    expires: Instant,
+}
+
+struct Waiting<T> {
+    key: (String, u16),
+    tag: T,
+    timeout: mio::Timeout,
+}
+
+// `T` tags the queries, like the token of the connection waiting for the
+// address
+pub struct Resolver<T> {
+    lookups: Sender<(String, u16)>,
+    cache: HashMap<(String, u16), Entry>,
+    capacity: usize,
+    // The lookups in flight, and the queries waiting for each
+    in_flight: HashMap<(String, u16), Vec<u64>>,
+    queries: HashMap<u64, Waiting<T>>,
+    next: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl<T> Resolver<T> {
+    // Starts `threads` lookup threads, sending their answers to `results`.
+    // The cache keeps at most `capacity` names.
+    pub fn new<M>(threads: usize, results: mio::Sender<M>, capacity: usize) -> Resolver<T>
+        where M: From<Answer> + Send + 'static
+    {
+        let (tx, rx) = mpsc::channel();
+        let rx = Arc::new(Mutex::new(rx));
+
+        for _ in 0..threads {
+            let rx = rx.clone();
+            let results = results.clone();
+
+            thread::spawn(move || lookup(&rx, &results));
+        }
+
+        Resolver {
+            lookups: tx,
+            cache: HashMap::new(),
+            capacity: capacity,
+            in_flight: HashMap::new(),
+            queries: HashMap::new(),
+            next: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    // Resolves `host`, a name or an address, IPv6 ones in brackets or not
+    pub fn resolve<H>(&mut self, event_loop: &mut EventLoop<H>, host: &str, port: u16, timeout_ms: u64, tag: T) -> Resolve<T>
+        where H: Handler,
+              H::Timeout: From<Deadline>
+    {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Resolve::Done(tag, Ok(SocketAddr::new(ip, port)));
+        }
+
+        // Names aren't case sensitive
+        let key = (host.to_ascii_lowercase(), port);
+
+        if let Some(entry) = self.cache.get(&key) {
+            if entry.expires > Instant::now() {
+                self.hits += 1;
+                return Resolve::Done(tag, entry.result.clone());
+            }
+        }
+
+        self.misses += 1;
+
+        self.next += 1;
+        let id = self.next;
+
+        // A lookup may be in flight for the name already, for an earlier
+        // query
+        if !self.in_flight.contains_key(&key) {
+            // The lookup threads only stop with the resolver
+            self.lookups.send(key.clone()).unwrap();
+        }
+
+        self.in_flight.entry(key.clone()).or_default().push(id);
+
+        self.queries.insert(id, Waiting {
+            key: key,
+            tag: tag,
+            timeout: event_loop.timeout_ms(Deadline(id).into(), timeout_ms).unwrap(),
+        });
+
+        Resolve::Pending(Query(id))
+    }
+
+    // Caches the answer, and returns the queries it completes
+    pub fn answer<H: Handler>(&mut self, event_loop: &mut EventLoop<H>, answer: Answer) -> Vec<(T, Result<SocketAddr, Error>)> {
+        let key = (answer.host, answer.port);
+
+        let ttl = if answer.result.is_ok() { TTL_SECS } else { FAILURE_TTL_SECS };
+        self.insert(key.clone(), answer.result.clone(), Duration::from_secs(ttl));
+
+        let mut done = vec![];
+
+        for id in self.in_flight.remove(&key).unwrap_or_default() {
+            if let Some(query) = self.queries.remove(&id) {
+                event_loop.clear_timeout(query.timeout);
+                done.push((query.tag, answer.result.clone()));
+            }
+        }
+
+        done
+    }
+
+    // Fails the query whose timeout it is, unless it was answered or
+    // cancelled in the meantime
+    pub fn expire(&mut self, deadline: Deadline) -> Option<(T, Error)> {
+        self.remove(deadline.0).map(|query| (query.tag, Error::TimedOut))
+    }
+
+    // Forgets a pending query, its tag is never returned
+    pub fn cancel<H: Handler>(&mut self, event_loop: &mut EventLoop<H>, query: Query) {
+        if let Some(query) = self.remove(query.0) {
+            event_loop.clear_timeout(query.timeout);
+        }
+    }
+
+    // Queries answered from the cache, and not
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    fn remove(&mut self, id: u64) -> Option<Waiting<T>> {
+        let query = self.queries.remove(&id)?;
+
+        // The lookup stays in flight, with no one waiting for it, so that a
+        // query for the same name meanwhile doesn't start another one
+        if let Some(waiting) = self.in_flight.get_mut(&query.key) {
+            waiting.retain(|&other| other != id);
+        }
+
+        Some(query)
+    }
+
+    fn insert(&mut self, key: (String, u16), result: Result<SocketAddr, Error>, ttl: Duration) {
+        let now = Instant::now();
+
+        if self.cache.len() >= self.capacity && !self.cache.contains_key(&key) {
+            self.cache.retain(|_, entry| entry.expires > now);
+        }
+
+        // Still full, the entry closest to expiring goes
+        if self.cache.len() >= self.capacity && !self.cache.contains_key(&key) {
+            let oldest = self.cache.iter().min_by_key(|&(_, entry)| entry.expires).map(|(key, _)| key.clone());
+
+            if let Some(oldest) = oldest {
+                self.cache.remove(&oldest);
+            }
+        }
+
+        if self.capacity > 0 {
+            self.cache.insert(key, Entry {
+                result: result,
+                expires: now + ttl,
+            });
+        }
+    }
+}
+
+fn lookup<M: From<Answer> + Send>(lookups: &Mutex<Receiver<(String, u16)>>, results: &mio::Sender<M>) {
+    loop {
+        // The lock is only held while waiting for a name, not while looking
+        // it up
+        let (host, port) = match lookups.lock().unwrap().recv() {
+            Ok(lookup) => lookup,
+            Err(_) => return,
+        };
+
+        let result = match (&host[..], port).to_socket_addrs() {
+            Ok(mut addrs) => addrs.next().ok_or_else(|| Error::Failed("no address found".to_string())),
+            Err(e) => Err(Error::Failed(e.to_string())),
+        };
+
+        let mut answer = M::from(Answer {
+            host: host,
+            port: port,
+            result: result,
+        });
+
+        // A full channel means the loop is behind, the answer waits for it
+        loop {
+            match results.send(answer) {
+                Ok(()) => break,
+                Err(mio::NotifyError::Full(returned)) => {
+                    answer = returned;
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Err(_) => return,
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+// Maps names sent by clients onto the directory a server serves. Anything
+// that could leave it is refused: `..`, an absolute path once the leading
+// slashes are gone, or a Windows prefix. Symlinks inside the root are
+// followed, they were put there by whoever runs the server.
+
+use std::path::{Component, Path, PathBuf};
+
+// Maps a name onto a path under `root`. Names containing `..` or other
+// components that could escape the root are rejected, and so are names,
+// like an empty one, that come down to the root itself.
+pub fn resolve(root: &Path, name: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+
+    for component in Path::new(name.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    if path == root {
+        return None;
+    }
+
+    Some(path)
+}
+
+// Like `resolve`, for servers with no directories: the path has to be a
+// file right in the root.
+pub fn resolve_flat(root: &Path, name: &str) -> Option<PathBuf> {
+    let path = resolve(root, name)?;
+
+    if path.parent() != Some(root) {
+        return None;
+    }
+
+    Some(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn resolve(name: &str) -> Option<PathBuf> {
+        super::resolve(Path::new("files"), name)
+    }
+
+    fn resolve_flat(name: &str) -> Option<PathBuf> {
+        super::resolve_flat(Path::new("files"), name)
+    }
+
+    #[test]
+    fn resolves_names_under_the_root() {
+        assert_eq!(resolve("a.txt"), Some(PathBuf::from("files/a.txt")));
+        assert_eq!(resolve("/a.txt"), Some(PathBuf::from("files/a.txt")));
+        assert_eq!(resolve("//dir/./a.txt"), Some(PathBuf::from("files/dir/a.txt")));
+        assert_eq!(resolve("dir/"), Some(PathBuf::from("files/dir")));
+    }
+
+    #[test]
+    fn rejects_escaping_names() {
+        assert_eq!(resolve(".."), None);
+        assert_eq!(resolve("../a.txt"), None);
+        assert_eq!(resolve("dir/../../a.txt"), None);
+        assert_eq!(resolve("/dir/.."), None);
+    }
+
+    #[test]
+    fn rejects_the_root() {
+        assert_eq!(resolve(""), None);
+        assert_eq!(resolve("/"), None);
+        assert_eq!(resolve("./"), None);
+    }
+
+    #[test]
+    fn flat_rejects_directories() {
+        assert_eq!(resolve_flat("/a.txt"), Some(PathBuf::from("files/a.txt")));
+        assert_eq!(resolve_flat("./a.txt"), Some(PathBuf::from("files/a.txt")));
+        assert_eq!(resolve_flat("dir/a.txt"), None);
+        assert_eq!(resolve_flat(".."), None);
+        assert_eq!(resolve_flat(""), None);
+    }
+}
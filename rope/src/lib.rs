@@ -0,0 +1,252 @@
+// A buffer made of a list of byte chunks, for queuing output without
+// copying it. A `Chunk` is a slice of a shared, immutable allocation:
+// cloning or slicing it copies no bytes, only the pointer and the bounds.
+// A `Rope` is a queue of chunks:
+//
+// * pushing a chunk, or appending another rope, copies nothing;
+// * `split_to` takes the first bytes off, a complete frame say, slicing
+//   the chunk the split falls in rather than copying it;
+// * `write_to` writes out as many chunks as the socket takes, in a single
+//   `writev`, and drops what was written.
+//
+// The same chunk can be in many ropes at once, which is the point: a
+// message fanned out to a thousand connections is a thousand queued
+// pointers to one allocation. A chunk keeps its whole allocation alive,
+// though, not just its slice of it.
+//
+// Chunks are `Rc`s, for use within a single event loop.
+
+extern crate libc;
+
+use std::collections::VecDeque;
+use std::io;
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::rc::Rc;
+use std::{cmp, fmt};
+
+// The most chunks written in one `writev`. Linux allows 1024, the rest
+// are written by the next call.
+const MAX_IOVECS: usize = 64;
+
+#[derive(Clone)]
+pub struct Chunk {
+    data: Rc<Vec<u8>>,
+    start: usize,
+    end: usize,
+}
+
+impl Chunk {
+    pub fn new(data: Vec<u8>) -> Chunk {
+        let end = data.len();
+
+        Chunk {
+            data: Rc::new(data),
+            start: 0,
+            end: end,
+        }
+    }
+
+    // The bytes from `start` to `end` of this chunk, sharing its
+    // allocation
+    pub fn slice(&self, start: usize, end: usize) -> Chunk {
+        assert!(start <= end && end <= self.len(), "slice out of bounds; start={}; end={}; len={}", start, end, self.len());
+
+        Chunk {
+            data: self.data.clone(),
+            start: self.start + start,
+            end: self.start + end,
+        }
+    }
+}
+
+impl Deref for Chunk {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[self.start..self.end]
+    }
+}
+
+impl From<Vec<u8>> for Chunk {
+    fn from(data: Vec<u8>) -> Chunk {
+        Chunk::new(data)
+    }
+}
+
+impl<'a> From<&'a [u8]> for Chunk {
+    fn from(data: &'a [u8]) -> Chunk {
+        Chunk::new(data.to_vec())
+    }
+}
+
+impl fmt::Debug for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Chunk({:?})", String::from_utf8_lossy(self))
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Rope {
+    chunks: VecDeque<Chunk>,
+    // In bytes, over all the chunks
+    len: usize,
+}
+
+impl Rope {
+    pub fn new() -> Rope {
+        Rope::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks.iter()
+    }
+
+    // Empty chunks are skipped, there is no point writing them
+    pub fn push<C: Into<Chunk>>(&mut self, chunk: C) {
+        let chunk = chunk.into();
+
+        if !chunk.is_empty() {
+            self.len += chunk.len();
+            self.chunks.push_back(chunk);
+        }
+    }
+
+    pub fn append(&mut self, other: Rope) {
+        self.len += other.len;
+        self.chunks.extend(other.chunks);
+    }
+
+    // The position of the first `byte`, over all the chunks
+    pub fn find(&self, byte: u8) -> Option<usize> {
+        let mut pos = 0;
+
+        for chunk in &self.chunks {
+            if let Some(i) = chunk.iter().position(|&b| b == byte) {
+                return Some(pos + i);
+            }
+
+            pos += chunk.len();
+        }
+
+        None
+    }
+
+    // Takes the first `n` bytes off, and returns them. A chunk straddling
+    // the split is sliced in two, both halves sharing it.
+    pub fn split_to(&mut self, n: usize) -> Rope {
+        assert!(n <= self.len, "split past the end; n={}; len={}", n, self.len);
+
+        let mut head = Rope::new();
+
+        while head.len < n {
+            let chunk = self.chunks.pop_front().unwrap();
+            let want = n - head.len;
+
+            if chunk.len() > want {
+                self.chunks.push_front(chunk.slice(want, chunk.len()));
+                head.push(chunk.slice(0, want));
+            } else {
+                head.push(chunk);
+            }
+        }
+
+        self.len -= n;
+        head
+    }
+
+    // Drops the first `n` bytes
+    pub fn advance(&mut self, n: usize) {
+        self.split_to(n);
+    }
+
+    // The bytes as a single chunk. That is the one chunk there is, shared,
+    // if there is one, only several are copied into a new one.
+    pub fn into_chunk(mut self) -> Chunk {
+        if self.chunks.len() == 1 {
+            return self.chunks.pop_front().unwrap();
+        }
+
+        let mut data = Vec::with_capacity(self.len);
+
+        for chunk in &self.chunks {
+            data.extend_from_slice(chunk);
+        }
+
+        Chunk::new(data)
+    }
+
+    // Writes as much as the socket takes, in a single `writev` of the first
+    // chunks, and drops what was written. Returns `None` if the socket
+    // wasn't ready, like `TryWrite`.
+    pub fn write_to<S: AsRawFd>(&mut self, socket: &S) -> io::Result<Option<usize>> {
+        let len = self.len;
+        self.write_prefix_to(socket, len)
+    }
+
+    // Like `write_to`, writing no more than the first `max` bytes
+    pub fn write_prefix_to<S: AsRawFd>(&mut self, socket: &S, max: usize) -> io::Result<Option<usize>> {
+        let mut iovecs = Vec::with_capacity(cmp::min(self.chunks.len(), MAX_IOVECS));
+        let mut left = max;
+
+        for chunk in self.chunks.iter().take(MAX_IOVECS) {
+            if left == 0 {
+                break;
+            }
+
+            let len = cmp::min(chunk.len(), left);
+
+            iovecs.push(libc::iovec {
+                iov_base: chunk.as_ptr() as *mut libc::c_void,
+                iov_len: len,
+            });
+
+            left -= len;
+        }
+
+        if iovecs.is_empty() {
+            return Ok(Some(0));
+        }
+
+        let res = unsafe { libc::writev(socket.as_raw_fd(), iovecs.as_ptr(), iovecs.len() as libc::c_int) };
+
+        if res < 0 {
+            let err = io::Error::last_os_error();
+
+            return match err.kind() {
+                io::ErrorKind::WouldBlock => Ok(None),
+                _ => Err(err),
+            };
+        }
+
+        let n = res as usize;
+        self.advance(n);
+
+        Ok(Some(n))
+    }
+}
+
+impl<C: Into<Chunk>> From<C> for Rope {
+    fn from(chunk: C) -> Rope {
+        let mut rope = Rope::new();
+        rope.push(chunk);
+        rope
+    }
+}
+
+impl fmt::Debug for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Rope")
+            .field("len", &self.len)
+            .field("chunks", &self.chunks.len())
+            .finish()
+    }
+}
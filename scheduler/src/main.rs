@@ -0,0 +1,215 @@
+extern crate mio;
+extern crate libc;
+
+mod schedule;
+
+use schedule::Schedule;
+use std::cmp::{self, Reverse};
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process::{self, Child, Command};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::env;
+
+// The longest the scheduler sleeps. The event loop's timer follows the
+// monotonic clock, while schedules follow the wall clock, which can be set
+// backward or forward. Waking up every so often keeps the two from
+// drifting too far apart.
+const MAX_SLEEP_MS: u64 = 60_000;
+
+// How often a running job is checked on
+const REAP_MS: u64 = 1_000;
+
+struct Job {
+    // Where the job is in the jobs file, to tell it apart in the logs
+    line: usize,
+    schedule: Schedule,
+    command: String,
+    // The last run, if it hasn't been seen to exit yet
+    running: Option<Child>,
+    runs: u64,
+}
+
+// Runs shell commands on a schedule. The time each job is due next is kept
+// in a priority queue, and the event loop has a single timeout set, for the
+// earliest of them. For every job run, the next one is pushed onto the
+// queue, and the timeout is set again.
+struct Scheduler {
+    jobs: Vec<Job>,
+    // When each job is due next, by job index. Earliest first.
+    queue: BinaryHeap<Reverse<(u64, usize)>>,
+}
+
+impl Scheduler {
+    fn new(jobs: Vec<Job>) -> Scheduler {
+        let now = now_ms();
+
+        let queue = jobs.iter().enumerate()
+            .map(|(i, job)| Reverse((job.schedule.next(now), i)))
+            .collect();
+
+        Scheduler {
+            jobs: jobs,
+            queue: queue,
+        }
+    }
+
+    // Runs the jobs that are due, and sets the timeout for the next one
+    fn wake(&mut self, event_loop: &mut mio::EventLoop<Scheduler>) {
+        self.reap();
+
+        let now = now_ms();
+
+        while let Some(&Reverse((due, i))) = self.queue.peek() {
+            if due > now {
+                break;
+            }
+
+            self.queue.pop();
+            self.run(i);
+
+            // Runs missed while the scheduler wasn't, or while the clock was
+            // set forward, are skipped
+            let schedule = self.jobs[i].schedule;
+            let mut next = schedule.next(due);
+
+            while next <= now {
+                next = schedule.next(next);
+            }
+
+            self.queue.push(Reverse((next, i)));
+        }
+
+        let mut delay = match self.queue.peek() {
+            Some(&Reverse((due, _))) => cmp::min(due - now, MAX_SLEEP_MS),
+            None => MAX_SLEEP_MS,
+        };
+
+        if self.jobs.iter().any(|job| job.running.is_some()) {
+            delay = cmp::min(delay, REAP_MS);
+        }
+
+        event_loop.timeout_ms((), delay).unwrap();
+    }
+
+    fn run(&mut self, i: usize) {
+        let job = &mut self.jobs[i];
+
+        // Like cron, a job that is still running isn't started again. Unlike
+        // cron, the run is skipped instead of running twice at once.
+        if job.running.is_some() {
+            println!("still running, skipping; line={}; command={:?}", job.line, job.command);
+            return;
+        }
+
+        job.runs += 1;
+
+        // The job shares the scheduler's stdout and stderr. It isn't waited
+        // for, the event loop keeps going while it runs.
+        match Command::new("sh").arg("-c").arg(&job.command).spawn() {
+            Ok(child) => {
+                println!("running job; line={}; run={}; pid={}; command={:?}", job.line, job.runs, child.id(), job.command);
+                job.running = Some(child);
+            }
+            Err(e) => println!("failed to run job; line={}; err={:?}", job.line, e),
+        }
+    }
+
+    fn reap(&mut self) {
+        for job in &mut self.jobs {
+            let status = match job.running.as_mut().map(|child| child.try_wait()) {
+                Some(Ok(Some(status))) => status,
+                Some(Ok(None)) | None => continue,
+                Some(Err(e)) => panic!("failed to wait for job; line={}; err={:?}", job.line, e),
+            };
+
+            job.running = None;
+
+            if !status.success() {
+                println!("job failed; line={}; status={}", job.line, status);
+            }
+        }
+    }
+}
+
+impl mio::Handler for Scheduler {
+    type Timeout = ();
+    type Message = ();
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Scheduler>, _: ()) {
+        self.wake(event_loop);
+    }
+}
+
+fn now_ms() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    now.as_secs() * 1_000 + now.subsec_millis() as u64
+}
+
+// Every line of the jobs file is a schedule followed by a shell command:
+//
+//     every 10s date
+//     at 03:00 ./backup.sh
+//
+// Empty lines and lines starting with `#` are ignored.
+fn load(path: &str) -> Result<Vec<Job>, String> {
+    let file = File::open(path).map_err(|e| format!("failed to open {:?}; err={:?}", path, e))?;
+    let mut jobs = vec![];
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("failed to read {:?}; err={:?}", path, e))?;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.splitn(3, char::is_whitespace);
+
+        let (kind, value, command) = match (parts.next(), parts.next(), parts.next().map(str::trim)) {
+            (Some(kind), Some(value), Some(command)) if !command.is_empty() => (kind, value, command),
+            _ => return Err(format!("line {}: expected a schedule and a command", i + 1)),
+        };
+
+        let schedule = Schedule::parse(kind, value).map_err(|e| format!("line {}: {}", i + 1, e))?;
+
+        jobs.push(Job {
+            line: i + 1,
+            schedule: schedule,
+            command: command.to_string(),
+            running: None,
+            runs: 0,
+        });
+    }
+
+    Ok(jobs)
+}
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            println!("usage: scheduler <jobs file>");
+            process::exit(1);
+        }
+    };
+
+    let jobs = match load(&path) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            println!("invalid jobs file; {}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    let mut scheduler = Scheduler::new(jobs);
+
+    for job in &scheduler.jobs {
+        println!("scheduled job; line={}; schedule={:?}; command={:?}", job.line, job.schedule, job.command);
+    }
+
+    scheduler.wake(&mut event_loop);
+    event_loop.run(&mut scheduler).unwrap();
+}
@@ -0,0 +1,89 @@
+// When a job runs. Two kinds of schedules are supported:
+//
+// every 30s     every 30 seconds, also `m` for minutes and `h` for hours
+// at 09:30      every day at 9:30, local time
+//
+// Times are in milliseconds since the Unix epoch.
+
+use libc;
+use std::mem;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Schedule {
+    Every(u64),
+    At { hour: u32, minute: u32 },
+}
+
+impl Schedule {
+    pub fn parse(kind: &str, value: &str) -> Result<Schedule, String> {
+        match kind {
+            "every" => {
+                let (n, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+
+                let n: u64 = n.parse().map_err(|_| format!("invalid interval {:?}", value))?;
+
+                let unit_ms = match unit {
+                    "s" => 1_000,
+                    "m" => 60_000,
+                    "h" => 3_600_000,
+                    _ => return Err(format!("invalid interval unit {:?}, expected s, m or h", unit)),
+                };
+
+                if n == 0 {
+                    return Err("the interval can't be 0".to_string());
+                }
+
+                Ok(Schedule::Every(n * unit_ms))
+            }
+            "at" => {
+                let mut parts = value.splitn(2, ':');
+
+                match (parts.next().and_then(|h| h.parse().ok()), parts.next().and_then(|m| m.parse().ok())) {
+                    (Some(hour), Some(minute)) if hour < 24 && minute < 60 => Ok(Schedule::At { hour: hour, minute: minute }),
+                    _ => Err(format!("invalid time {:?}, expected HH:MM", value)),
+                }
+            }
+            _ => Err(format!("unknown schedule {:?}, expected `every` or `at`", kind)),
+        }
+    }
+
+    // The first time the job is due after `after`. For `every`, that is one
+    // interval later: passed the time the job was due last, rather than the
+    // time it actually ran, late runs don't push back the ones after it.
+    pub fn next(&self, after: u64) -> u64 {
+        match *self {
+            Schedule::Every(interval) => after + interval,
+            Schedule::At { hour, minute } => next_local_time(after, hour, minute),
+        }
+    }
+}
+
+// The libc functions know about the local time zone, daylight saving time
+// included: the day is moved forward in the broken down time, and `mktime`
+// works out what that means.
+fn next_local_time(after: u64, hour: u32, minute: u32) -> u64 {
+    let secs = (after / 1_000) as libc::time_t;
+
+    unsafe {
+        let mut tm: libc::tm = mem::zeroed();
+        libc::localtime_r(&secs, &mut tm);
+
+        tm.tm_hour = hour as libc::c_int;
+        tm.tm_min = minute as libc::c_int;
+        tm.tm_sec = 0;
+        // Let `mktime` figure out whether daylight saving time is in effect
+        tm.tm_isdst = -1;
+
+        let mut at = libc::mktime(&mut tm);
+
+        if at <= secs {
+            tm.tm_mday += 1;
+            tm.tm_hour = hour as libc::c_int;
+            tm.tm_min = minute as libc::c_int;
+            tm.tm_isdst = -1;
+            at = libc::mktime(&mut tm);
+        }
+
+        at as u64 * 1_000
+    }
+}
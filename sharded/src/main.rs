@@ -0,0 +1,374 @@
+extern crate mio;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::Instant;
+use std::{env, process, thread};
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Per shard
+const MAX_CONNECTIONS: usize = 16_384;
+
+// Lines longer than this close the connection
+const MAX_LINE: usize = 1_024;
+
+// How often the dispatcher prints the shards' stats, and how often they
+// forget the clients gone for longer than `CLIENT_TTL_SECS`
+const REPORT_MS: u64 = 10_000;
+const SWEEP_MS: u64 = 60_000;
+const CLIENT_TTL_SECS: u64 = 600;
+
+// What each shard counts, read by the dispatcher for its reports. Only
+// the shard writes them.
+#[derive(Default)]
+struct ShardStats {
+    connections: AtomicUsize,
+    accepted: AtomicUsize,
+    clients: AtomicUsize,
+    lines: AtomicUsize,
+}
+
+// Accepts the connections, and hands each to a shard, picked by hashing
+// the client's IP address. All the connections from a client end up on
+// the same shard, whatever their port: it can keep what it knows about
+// the client in plain memory of its own, without locks, and find it again
+// on the client's next connection. Handing connections out round robin
+// spreads them more evenly, but any shard may get any client.
+struct Dispatcher {
+    server: TcpListener,
+    shards: Vec<mio::Sender<TcpStream>>,
+    stats: Vec<Arc<ShardStats>>,
+    // Connections dropped because the shard's queue was full
+    dropped: usize,
+}
+
+impl Dispatcher {
+    fn accept(&mut self) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("failed to accept connection; err={:?}", e);
+                return;
+            }
+        };
+
+        let ip = match socket.peer_addr() {
+            Ok(addr) => addr.ip(),
+            Err(_) => return,
+        };
+
+        let shard = shard(&ip, self.shards.len());
+
+        // The socket isn't registered with the dispatcher's event loop,
+        // it moves to the shard's as it is
+        match self.shards[shard].send(socket) {
+            Ok(()) => {}
+            Err(mio::NotifyError::Full(_)) => {
+                self.dropped += 1;
+                println!("shard queue full, dropping client; shard={}; ip={}", shard, ip);
+            }
+            Err(e) => panic!("shard is gone; shard={}; err={:?}", shard, e),
+        }
+    }
+
+    fn report(&self) {
+        let total: usize = self.stats.iter().map(|stats| stats.connections.load(Ordering::Relaxed)).sum();
+
+        println!("connections={}; dropped={}", total, self.dropped);
+
+        for (i, stats) in self.stats.iter().enumerate() {
+            println!("  shard {}; connections={}; accepted={}; clients={}; lines={}",
+                     i,
+                     stats.connections.load(Ordering::Relaxed),
+                     stats.accepted.load(Ordering::Relaxed),
+                     stats.clients.load(Ordering::Relaxed),
+                     stats.lines.load(Ordering::Relaxed));
+        }
+    }
+}
+
+impl mio::Handler for Dispatcher {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, _: &mut mio::EventLoop<Dispatcher>, token: mio::Token, _: mio::EventSet) {
+        assert_eq!(token, SERVER);
+        self.accept();
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Dispatcher>, _: ()) {
+        self.report();
+        event_loop.timeout_ms((), REPORT_MS).unwrap();
+    }
+}
+
+// The same address always hashes to the same shard: `DefaultHasher::new`
+// has fixed keys. A client can't pick its shard without picking its
+// address.
+fn shard(ip: &IpAddr, shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    ip.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+// What a shard knows about a client, across its connections
+struct Client {
+    lines: u64,
+    // Open connections
+    connections: usize,
+    last_seen: Instant,
+}
+
+struct Connection {
+    socket: TcpStream,
+    ip: IpAddr,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    eof: bool,
+    closed: bool,
+}
+
+// A worker event loop, on its own thread, with the connections handed to
+// it. It answers each line with which shard it is, and how many lines the
+// client sent so far, over all its connections.
+struct Shard {
+    id: usize,
+    connections: Slab<Connection>,
+    clients: HashMap<IpAddr, Client>,
+    stats: Arc<ShardStats>,
+}
+
+impl Shard {
+    fn add(&mut self, event_loop: &mut mio::EventLoop<Shard>, socket: TcpStream) {
+        let ip = match socket.peer_addr() {
+            Ok(addr) => addr.ip(),
+            Err(_) => return,
+        };
+
+        let conn = Connection {
+            socket: socket,
+            ip: ip,
+            buf: vec![],
+            out: vec![],
+            eof: false,
+            closed: false,
+        };
+
+        let token = match self.connections.insert(conn) {
+            Ok(token) => token,
+            Err(_) => {
+                println!("connection limit reached, dropping client; shard={}", self.id);
+                return;
+            }
+        };
+
+        event_loop.register_opt(&self.connections[token].socket, token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        let client = self.clients.entry(ip).or_insert(Client {
+            lines: 0,
+            connections: 0,
+            last_seen: Instant::now(),
+        });
+
+        client.connections += 1;
+        client.last_seen = Instant::now();
+
+        self.stats.accepted.fetch_add(1, Ordering::Relaxed);
+        self.stats.connections.store(self.connections.count(), Ordering::Relaxed);
+        self.stats.clients.store(self.clients.len(), Ordering::Relaxed);
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<Shard>, token: mio::Token, events: mio::EventSet) {
+        let Shard { id, ref mut connections, ref mut clients, ref stats } = *self;
+        let conn = &mut connections[token];
+
+        if events.is_readable() {
+            conn.read();
+        }
+
+        // A line at a time, each answered with what the shard knows of the
+        // client
+        while let Some(pos) = conn.buf.iter().position(|&b| b == b'\n') {
+            conn.buf.drain(..pos + 1);
+
+            let client = clients.get_mut(&conn.ip).unwrap();
+            client.lines += 1;
+            client.last_seen = Instant::now();
+
+            stats.lines.fetch_add(1, Ordering::Relaxed);
+
+            conn.out.extend_from_slice(format!("shard={}; client={}; lines={}\n", id, conn.ip, client.lines).as_bytes());
+        }
+
+        if conn.buf.len() > MAX_LINE {
+            conn.closed = true;
+        }
+
+        conn.write();
+
+        if conn.closed || conn.eof && conn.out.is_empty() {
+            let ip = conn.ip;
+            self.remove(token, ip);
+            return;
+        }
+
+        let mut interest = mio::EventSet::none();
+
+        if !conn.eof {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !conn.out.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        event_loop.reregister(&conn.socket, token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn remove(&mut self, token: mio::Token, ip: IpAddr) {
+        self.connections.remove(token);
+
+        // The client is kept after its last connection closes, for when it
+        // comes back, until the sweep forgets it
+        if let Some(client) = self.clients.get_mut(&ip) {
+            client.connections -= 1;
+            client.last_seen = Instant::now();
+        }
+
+        self.stats.connections.store(self.connections.count(), Ordering::Relaxed);
+    }
+
+    fn sweep(&mut self) {
+        self.clients.retain(|_, client| client.connections > 0 || client.last_seen.elapsed().as_secs() < CLIENT_TTL_SECS);
+        self.stats.clients.store(self.clients.len(), Ordering::Relaxed);
+    }
+}
+
+impl mio::Handler for Shard {
+    type Timeout = ();
+    type Message = TcpStream;
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Shard>, token: mio::Token, events: mio::EventSet) {
+        self.connection_ready(event_loop, token, events);
+    }
+
+    // A connection from the dispatcher
+    fn notify(&mut self, event_loop: &mut mio::EventLoop<Shard>, socket: TcpStream) {
+        self.add(event_loop, socket);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Shard>, _: ()) {
+        self.sweep();
+        event_loop.timeout_ms((), SWEEP_MS).unwrap();
+    }
+}
+
+impl Connection {
+    fn read(&mut self) {
+        let mut chunk = [0; 4_096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.eof = true;
+                    return;
+                }
+                Ok(Some(n)) => self.buf.extend_from_slice(&chunk[..n]),
+                Ok(None) => return,
+                Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Starts a shard on its own thread, and returns the channel to hand it
+// connections
+fn spawn(id: usize, stats: Arc<ShardStats>) -> mio::Sender<TcpStream> {
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut event_loop = mio::EventLoop::new().unwrap();
+        event_loop.timeout_ms((), SWEEP_MS).unwrap();
+        tx.send(event_loop.channel()).unwrap();
+
+        let mut shard = Shard {
+            id: id,
+            connections: Slab::new(MAX_CONNECTIONS),
+            clients: HashMap::new(),
+            stats: stats,
+        };
+
+        event_loop.run(&mut shard).unwrap();
+    });
+
+    rx.recv().unwrap()
+}
+
+fn usage() -> ! {
+    println!("usage: sharded [addr] [shards]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:9600".to_string()).parse().unwrap_or_else(|_| usage());
+    let count: usize = args.next().map(|s| s.parse().unwrap_or_else(|_| usage())).unwrap_or(4);
+
+    if count == 0 {
+        usage();
+    }
+
+    let stats: Vec<Arc<ShardStats>> = (0..count).map(|_| Arc::new(ShardStats::default())).collect();
+    let shards = stats.iter().enumerate().map(|(i, stats)| spawn(i, stats.clone())).collect();
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+    event_loop.timeout_ms((), REPORT_MS).unwrap();
+
+    println!("running sharded server; addr={:?}; shards={}", address, count);
+
+    let mut dispatcher = Dispatcher {
+        server: server,
+        shards: shards,
+        stats: stats,
+        dropped: 0,
+    };
+
+    event_loop.run(&mut dispatcher).unwrap();
+}
@@ -0,0 +1,141 @@
+// The load generator of the benchmark: connections sending `incr`
+// commands, pipelined, as fast as the server answers them.
+
+use mio::{self, TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+// Commands in flight per connection
+const DEPTH: usize = 16;
+
+// The commands are spread over this many keys
+pub const KEYS: u64 = 100;
+
+struct Client {
+    socket: TcpStream,
+    out: Vec<u8>,
+    // Commands sent and not answered yet
+    in_flight: usize,
+}
+
+struct Load {
+    clients: Slab<Client>,
+    // Commands sent so far, also what picks the next key
+    sent: u64,
+    answered: u64,
+    // Set once the time is up: no more commands are sent, the ones in
+    // flight are waited for, so that every command sent is answered
+    stopping: bool,
+    stopped: Option<Instant>,
+}
+
+impl Load {
+    fn send(&mut self, token: mio::Token) {
+        let client = &mut self.clients[token];
+
+        while !self.stopping && client.in_flight < DEPTH {
+            // Keys are picked in a scattered order, the same for every run
+            let key = self.sent.wrapping_mul(7_919) % KEYS;
+            client.out.extend_from_slice(format!("incr key{}\n", key).as_bytes());
+            client.in_flight += 1;
+            self.sent += 1;
+        }
+
+        while !client.out.is_empty() {
+            match client.socket.try_write(&client.out) {
+                Ok(Some(n)) => {
+                    client.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => panic!("failed to write; err={:?}", e),
+            }
+        }
+    }
+
+    fn in_flight(&self) -> usize {
+        (0..self.clients.count()).filter_map(|i| self.clients.get(mio::Token(i))).map(|client| client.in_flight).sum()
+    }
+}
+
+impl mio::Handler for Load {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Load>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            let mut buf = [0; 16 * 1_024];
+
+            // The socket is registered as edge triggered, drain it. Each
+            // line is an answer.
+            loop {
+                let client = &mut self.clients[token];
+
+                match client.socket.try_read(&mut buf) {
+                    Ok(Some(0)) => panic!("the server closed the connection"),
+                    Ok(Some(n)) => {
+                        let answers = buf[..n].iter().filter(|&&b| b == b'\n').count();
+                        client.in_flight -= answers;
+                        self.answered += answers as u64;
+                    }
+                    Ok(None) => break,
+                    Err(e) => panic!("failed to read; err={:?}", e),
+                }
+            }
+        }
+
+        self.send(token);
+
+        if self.stopping && self.in_flight() == 0 {
+            event_loop.shutdown();
+        }
+    }
+
+    // The time is up
+    fn timeout(&mut self, _: &mut mio::EventLoop<Load>, _: ()) {
+        self.stopping = true;
+        self.stopped = Some(Instant::now());
+    }
+}
+
+// Runs the load for `secs` seconds. Returns the number of commands sent,
+// all of them answered, and the time it took to send them.
+pub fn run(addr: &SocketAddr, connections: usize, secs: u64) -> (u64, f64) {
+    let mut event_loop = mio::EventLoop::new().unwrap();
+
+    let mut load = Load {
+        clients: Slab::new(connections),
+        sent: 0,
+        answered: 0,
+        stopping: false,
+        stopped: None,
+    };
+
+    for _ in 0..connections {
+        let socket = TcpStream::connect(addr).unwrap();
+        socket.set_nodelay(true).unwrap();
+
+        let client = Client {
+            socket: socket,
+            out: vec![],
+            in_flight: 0,
+        };
+
+        let token = load.clients.insert(client).ok().unwrap();
+
+        // The socket becomes writable once connected, and the first
+        // commands are sent then
+        event_loop.register_opt(&load.clients[token].socket, token, mio::EventSet::readable() | mio::EventSet::writable(), mio::PollOpt::edge())
+            .unwrap();
+    }
+
+    let started = Instant::now();
+    event_loop.timeout_ms((), secs * 1_000).unwrap();
+    event_loop.run(&mut load).unwrap();
+
+    let elapsed = load.stopped.unwrap().duration_since(started);
+    assert_eq!(load.sent, load.answered);
+
+    (load.sent, elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9)
+}
@@ -0,0 +1,344 @@
+extern crate mio;
+
+mod load;
+mod state;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use state::State;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{self, SocketAddr};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use std::{env, process};
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 1_024;
+
+// How often the loops keeping their own counts merge them into the shared
+// ones. Reads on one loop lag behind the other loops' writes by up to
+// twice as much.
+const MERGE_MS: u64 = 100;
+
+#[derive(Copy, Clone, Debug)]
+enum Strategy {
+    Single,
+    Mutex,
+    Sharded,
+}
+
+const STRATEGIES: [Strategy; 3] = [Strategy::Single, Strategy::Mutex, Strategy::Sharded];
+
+struct Connection {
+    socket: TcpStream,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    closed: bool,
+}
+
+// An event loop of the counter service, on a thread of its own. The
+// protocol is a command per line, answered with a line:
+//
+//     incr <key>   -> the new count
+//     get <key>    -> the count, 0 for a key never incremented
+//
+// All the loops accept connections, from clones of the same listener:
+// every loop is woken up by a new connection, and the first to accept it
+// gets it.
+struct Node {
+    server: TcpListener,
+    connections: Slab<Connection>,
+    state: State,
+}
+
+impl Node {
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Node>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough. Another loop may have taken the connection.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("failed to accept connection; err={:?}", e);
+                return;
+            }
+        };
+
+        socket.set_nodelay(true).unwrap();
+
+        let conn = Connection {
+            socket: socket,
+            buf: vec![],
+            out: vec![],
+            closed: false,
+        };
+
+        let token = match self.connections.insert(conn) {
+            Ok(token) => token,
+            Err(_) => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        event_loop.register_opt(&self.connections[token].socket, token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<Node>, token: mio::Token, events: mio::EventSet) {
+        let Node { ref mut connections, ref mut state, .. } = *self;
+        let conn = &mut connections[token];
+
+        if events.is_readable() {
+            conn.read();
+        }
+
+        while let Some(pos) = conn.buf.iter().position(|&b| b == b'\n') {
+            let answer = {
+                let line = String::from_utf8_lossy(&conn.buf[..pos]);
+                let mut words = line.split_whitespace();
+
+                match (words.next(), words.next(), words.next()) {
+                    (Some("incr"), Some(key), None) => state.incr(key).to_string(),
+                    (Some("get"), Some(key), None) => state.get(key).to_string(),
+                    _ => "ERR usage: incr <key> | get <key>".to_string(),
+                }
+            };
+
+            conn.buf.drain(..pos + 1);
+            conn.out.extend_from_slice(answer.as_bytes());
+            conn.out.push(b'\n');
+        }
+
+        conn.write();
+
+        if conn.closed && conn.out.is_empty() || conn.buf.len() > 1_024 {
+            connections.remove(token);
+            return;
+        }
+
+        let mut interest = mio::EventSet::readable();
+
+        if !conn.out.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        event_loop.reregister(&conn.socket, token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+impl mio::Handler for Node {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Node>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ => self.connection_ready(event_loop, token, events),
+        }
+    }
+
+    // Only the sharded loops have a timer
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Node>, _: ()) {
+        self.state.merge();
+        event_loop.timeout_ms((), MERGE_MS).unwrap();
+    }
+
+    // Time to stop
+    fn notify(&mut self, event_loop: &mut mio::EventLoop<Node>, _: ()) {
+        self.state.merge();
+        event_loop.shutdown();
+    }
+}
+
+impl Connection {
+    fn read(&mut self) {
+        let mut chunk = [0; 4_096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) | Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => self.buf.extend_from_slice(&chunk[..n]),
+                Ok(None) => return,
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(_) => {
+                    self.closed = true;
+                    self.out.clear();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// The running loops of a strategy
+struct Service {
+    loops: Vec<(mio::Sender<()>, JoinHandle<()>)>,
+}
+
+impl Service {
+    fn start(strategy: Strategy, server: &TcpListener, loops: usize) -> Service {
+        let shared = Arc::new(Mutex::new(state::Counters::new()));
+
+        let loops = match strategy {
+            Strategy::Single => 1,
+            _ => loops,
+        };
+
+        let loops = (0..loops).map(|_| {
+            let state = match strategy {
+                Strategy::Single => State::Owned(state::Counters::new()),
+                Strategy::Mutex => State::Locked(shared.clone()),
+                Strategy::Sharded => State::merged(shared.clone()),
+            };
+
+            let server = server.try_clone().unwrap();
+            let (tx, rx) = mpsc::channel();
+
+            let handle = thread::spawn(move || {
+                let mut event_loop = mio::EventLoop::new().unwrap();
+                event_loop.register(&server, SERVER).unwrap();
+                tx.send(event_loop.channel()).unwrap();
+
+                if let State::Merged { .. } = state {
+                    event_loop.timeout_ms((), MERGE_MS).unwrap();
+                }
+
+                // Token `0` is reserved for the server socket. Tokens 1+
+                // are used for client connections.
+                let mut node = Node {
+                    server: server,
+                    connections: Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS),
+                    state: state,
+                };
+
+                event_loop.run(&mut node).unwrap();
+            });
+
+            (rx.recv().unwrap(), handle)
+        }).collect();
+
+        Service { loops: loops }
+    }
+
+    fn stop(self) {
+        for (stop, handle) in self.loops {
+            stop.send(()).unwrap();
+            handle.join().unwrap();
+        }
+    }
+}
+
+// Runs the load against each strategy in turn, on a port of its own, and
+// checks that the counts add up to the commands sent
+fn bench(loops: usize, connections: usize, secs: u64) {
+    println!("counter service; loops={}; connections={}; {}s per strategy", loops, connections, secs);
+    println!();
+    println!("{:<10} {:>14} {:>10}", "strategy", "commands/s", "counted");
+
+    for &strategy in STRATEGIES.iter() {
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let server = TcpListener::bind(&addr).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let service = Service::start(strategy, &server, loops);
+        let (sent, elapsed) = load::run(&addr, connections, secs);
+
+        // Every loop's counts merged, and every loop's snapshot of them
+        // taken after
+        thread::sleep(Duration::from_millis(MERGE_MS * 3));
+
+        let counted = total(&addr);
+
+        println!("{:<10} {:>14.0} {:>10}", format!("{:?}", strategy).to_lowercase(), sent as f64 / elapsed,
+                 if counted == sent { "ok".to_string() } else { format!("{} != {}", counted, sent) });
+
+        service.stop();
+    }
+}
+
+// The sum of all the counts, asked for with a blocking connection
+fn total(addr: &SocketAddr) -> u64 {
+    let socket = net::TcpStream::connect(addr).unwrap();
+    let mut reader = BufReader::new(socket.try_clone().unwrap());
+    let mut socket = socket;
+
+    (0..load::KEYS).map(|key| {
+        writeln!(socket, "get key{}", key).unwrap();
+
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        line.trim().parse::<u64>().unwrap()
+    }).sum()
+}
+
+fn usage() -> ! {
+    println!("usage: shared_state serve <single|mutex|sharded> [addr] [loops]");
+    println!("       shared_state bench [loops] [connections] [seconds]");
+    process::exit(1);
+}
+
+// The argument at `i`, or the default if there are fewer
+fn arg<T: FromStr>(args: &[String], i: usize, default: T) -> T {
+    args.get(i).map(|s| s.parse().unwrap_or_else(|_| usage())).unwrap_or(default)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(|s| &s[..]) {
+        Some("bench") => {
+            let loops = arg(&args, 1, 4);
+            let connections = arg(&args, 2, 32);
+            let secs = arg(&args, 3, 3);
+
+            if loops == 0 || connections == 0 || secs == 0 {
+                usage();
+            }
+
+            bench(loops, connections, secs);
+        }
+        Some("serve") => {
+            let strategy = match args.get(1).map(|s| &s[..]) {
+                Some("single") => Strategy::Single,
+                Some("mutex") => Strategy::Mutex,
+                Some("sharded") => Strategy::Sharded,
+                _ => usage(),
+            };
+
+            let address: SocketAddr = arg(&args, 2, "0.0.0.0:9800".parse().unwrap());
+            let loops = arg(&args, 3, 4);
+
+            let server = TcpListener::bind(&address).unwrap();
+
+            println!("running counter service; addr={:?}; strategy={:?}; loops={}", address, strategy, loops);
+
+            // Runs until the process exits
+            let service = Service::start(strategy, &server, loops);
+
+            for (_, handle) in service.loops {
+                handle.join().unwrap();
+            }
+        }
+        _ => usage(),
+    }
+}
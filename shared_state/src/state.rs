@@ -0,0 +1,79 @@
+// The counters, kept the three ways being compared. Each event loop has
+// a `State`, and how much each shares with the others is the difference.
+// This does no I/O.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub type Counters = HashMap<String, u64>;
+
+pub enum State {
+    // The only loop's, no sharing at all
+    Owned(Counters),
+    // All the loops' counters, behind a lock taken for every command
+    Locked(Arc<Mutex<Counters>>),
+    // Each loop counts on its own, and adds its counts to the shared
+    // counters now and then. Reads see the shared counters as of the last
+    // merge, and the loop's own counts since.
+    Merged {
+        shared: Arc<Mutex<Counters>>,
+        // The shared counters as of the last merge
+        snapshot: Counters,
+        // Counted since the last merge
+        delta: Counters,
+    },
+}
+
+impl State {
+    pub fn merged(shared: Arc<Mutex<Counters>>) -> State {
+        State::Merged {
+            shared: shared,
+            snapshot: Counters::new(),
+            delta: Counters::new(),
+        }
+    }
+
+    pub fn incr(&mut self, key: &str) -> u64 {
+        match *self {
+            State::Owned(ref mut counters) => incr(counters, key),
+            State::Locked(ref counters) => incr(&mut counters.lock().unwrap(), key),
+            State::Merged { ref snapshot, ref mut delta, .. } => snapshot.get(key).cloned().unwrap_or(0) + incr(delta, key),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> u64 {
+        match *self {
+            State::Owned(ref counters) => counters.get(key).cloned().unwrap_or(0),
+            State::Locked(ref counters) => counters.lock().unwrap().get(key).cloned().unwrap_or(0),
+            State::Merged { ref snapshot, ref delta, .. } => {
+                snapshot.get(key).cloned().unwrap_or(0) + delta.get(key).cloned().unwrap_or(0)
+            }
+        }
+    }
+
+    // Adds the counts since the last merge to the shared counters, and
+    // takes a new snapshot of them. The lock is taken once per merge, not
+    // once per command. Nothing to do for the other strategies.
+    pub fn merge(&mut self) {
+        if let State::Merged { ref shared, ref mut snapshot, ref mut delta } = *self {
+            let mut shared = shared.lock().unwrap();
+
+            for (key, count) in delta.drain() {
+                *shared.entry(key).or_insert(0) += count;
+            }
+
+            snapshot.clone_from(&shared);
+        }
+    }
+}
+
+// Doesn't allocate the key unless it is new
+fn incr(counters: &mut Counters, key: &str) -> u64 {
+    if let Some(count) = counters.get_mut(key) {
+        *count += 1;
+        return *count;
+    }
+
+    counters.insert(key.to_string(), 1);
+    1
+}
@@ -0,0 +1,418 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::fs::{self, File};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const HOSTNAME: &str = "mio.smtp";
+
+// RFC 5321 limits a line, including the CRLF, to 1000 bytes
+const MAX_LINE: usize = 1_000;
+
+// Messages larger than this are rejected
+const MAX_MESSAGE: usize = 10 * 1_024 * 1_024;
+
+// Where received messages are written
+struct Mailbox {
+    dir: PathBuf,
+    count: usize,
+}
+
+impl Mailbox {
+    // Writes a message to its own file and returns the file name. The
+    // envelope is recorded in headers prepended to the message, since it
+    // isn't otherwise part of the data.
+    //
+    // Writing a file blocks the event loop. It is fast enough for a local
+    // mail catcher, a real server would hand this off to another thread.
+    fn deliver(&mut self, from: &str, to: &[String], data: &[u8]) -> std::io::Result<String> {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+        self.count += 1;
+        let name = format!("{}-{}.eml", secs, self.count);
+
+        let mut file = File::create(self.dir.join(&name))?;
+
+        write!(file, "X-Envelope-From: <{}>\r\n", from)?;
+
+        for rcpt in to {
+            write!(file, "X-Envelope-To: <{}>\r\n", rcpt)?;
+        }
+
+        file.write_all(data)?;
+
+        Ok(name)
+    }
+}
+
+struct Sink {
+    acceptor: Acceptor<Factory<Connection>>,
+    mailbox: Mailbox,
+    connections: Slab<Connection>,
+}
+
+impl Sink {
+    fn new(acceptor: Acceptor<Factory<Connection>>, mailbox: Mailbox) -> Sink {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Sink {
+            acceptor: acceptor,
+            mailbox: mailbox,
+            connections: slab,
+        }
+    }
+}
+
+impl mio::Handler for Sink {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Sink>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                let (tokens, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+                for token in tokens {
+                    println!("accepted a new client socket; token={:?}", token);
+                }
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
+                }
+            }
+            _ => {
+                self.connections[token].ready(event_loop, events, &mut self.mailbox);
+
+                if self.connections[token].closed {
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+}
+
+// SMTP is line based, except while a message is being received. The data
+// phase ends with a line containing a single period, which may arrive
+// split across any number of reads.
+#[derive(Debug, PartialEq)]
+enum State {
+    Command,
+    Data,
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    state: State,
+    // Bytes read but not yet processed, at most one incomplete line
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // The envelope and contents of the message in progress
+    from: Option<String>,
+    to: Vec<String>,
+    data: Vec<u8>,
+    // Set once the message has grown too large. The rest of it is read and
+    // thrown away.
+    oversized: bool,
+    // Set when the connection should be closed once `out` is flushed
+    quit: bool,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        Connection::interest(self)
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        let mut conn = Connection {
+            socket: socket,
+            token: token,
+            state: State::Command,
+            buf: Vec::with_capacity(MAX_LINE),
+            out: vec![],
+            from: None,
+            to: vec![],
+            data: vec![],
+            oversized: false,
+            quit: false,
+            closed: false,
+        };
+
+        conn.reply(&format!("220 {} mio SMTP sink ready", HOSTNAME));
+        conn
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Sink>, events: mio::EventSet, mailbox: &mut Mailbox) {
+        if events.is_readable() {
+            self.read(mailbox);
+        }
+
+        if events.is_writable() || !self.out.is_empty() {
+            self.write();
+        }
+
+        if !self.closed {
+            event_loop.reregister(&self.socket, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn read(&mut self, mailbox: &mut Mailbox) {
+        let mut buf = [0; 4096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    // Clients may pipeline commands, so a single read can
+                    // hold many lines, or the end of a message followed by
+                    // the next command.
+                    while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+                        let line: Vec<u8> = self.buf.drain(..pos + 1).collect();
+
+                        if self.quit {
+                            continue;
+                        }
+
+                        match self.state {
+                            State::Command => self.command(&line),
+                            State::Data => self.data(&line, mailbox),
+                        }
+                    }
+
+                    if self.buf.len() > MAX_LINE {
+                        println!("line too long, closing connection");
+                        self.reply("500 Line too long");
+                        self.quit = true;
+                        self.buf.clear();
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn command(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end();
+
+        println!("received command; line={:?}", line);
+
+        let (verb, arg) = match line.find(' ') {
+            Some(pos) => (&line[..pos], line[pos + 1..].trim()),
+            None => (line, ""),
+        };
+
+        match &verb.to_uppercase()[..] {
+            "HELO" | "EHLO" => {
+                self.reset();
+                self.reply(&format!("250 {} Hello {}", HOSTNAME, arg));
+            }
+            "MAIL" => {
+                if self.from.is_some() {
+                    return self.reply("503 Sender already given");
+                }
+
+                match address(arg, "FROM:") {
+                    Some(addr) => {
+                        self.from = Some(addr);
+                        self.reply("250 OK");
+                    }
+                    None => self.reply("501 Syntax: MAIL FROM:<address>"),
+                }
+            }
+            "RCPT" => {
+                if self.from.is_none() {
+                    return self.reply("503 Need MAIL before RCPT");
+                }
+
+                match address(arg, "TO:") {
+                    Some(addr) => {
+                        self.to.push(addr);
+                        self.reply("250 OK");
+                    }
+                    None => self.reply("501 Syntax: RCPT TO:<address>"),
+                }
+            }
+            "DATA" => {
+                if self.to.is_empty() {
+                    return self.reply("503 Need RCPT before DATA");
+                }
+
+                self.state = State::Data;
+                self.reply("354 End data with <CR><LF>.<CR><LF>");
+            }
+            "RSET" => {
+                self.reset();
+                self.reply("250 OK");
+            }
+            "NOOP" => self.reply("250 OK"),
+            "QUIT" => {
+                self.reply(&format!("221 {} closing connection", HOSTNAME));
+                self.quit = true;
+            }
+            _ => self.reply("500 Command not recognized"),
+        }
+    }
+
+    // Handles a line of the message. The message ends at a line containing
+    // only a period. Lines of the message that start with a period have had
+    // another one added by the client ("dot-stuffing"), which is removed.
+    fn data(&mut self, line: &[u8], mailbox: &mut Mailbox) {
+        if line == b".\r\n" || line == b".\n" {
+            self.finish(mailbox);
+            return;
+        }
+
+        let line = if line.starts_with(b".") { &line[1..] } else { line };
+
+        if self.data.len() + line.len() > MAX_MESSAGE {
+            self.oversized = true;
+        }
+
+        if !self.oversized {
+            self.data.extend(line);
+        }
+    }
+
+    fn finish(&mut self, mailbox: &mut Mailbox) {
+        self.state = State::Command;
+
+        if self.oversized {
+            self.reset();
+            return self.reply("552 Message exceeds maximum size");
+        }
+
+        let from = self.from.take().unwrap_or_default();
+
+        match mailbox.deliver(&from, &self.to, &self.data) {
+            Ok(name) => {
+                println!("received message; from={:?}; to={:?}; size={}; file={}", from, self.to, self.data.len(), name);
+                self.reply(&format!("250 OK: queued as {}", name));
+            }
+            Err(e) => {
+                println!("failed to write message; err={:?}", e);
+                self.reply("451 Requested action aborted: local error in processing");
+            }
+        }
+
+        self.reset();
+    }
+
+    // Forgets the message in progress
+    fn reset(&mut self) {
+        self.from = None;
+        self.to.clear();
+        self.data.clear();
+        self.oversized = false;
+    }
+
+    fn reply(&mut self, line: &str) {
+        self.out.extend(line.as_bytes());
+        self.out.extend(b"\r\n");
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        if self.quit {
+            self.closed = true;
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+// Extracts the address from a `FROM:<addr>` or `TO:<addr>` argument. The
+// null sender `<>` is allowed, as bounces use it.
+fn address(arg: &str, prefix: &str) -> Option<String> {
+    if arg.len() < prefix.len() || !arg[..prefix.len()].eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+
+    let rest = arg[prefix.len()..].trim_start();
+
+    if !rest.starts_with('<') {
+        return None;
+    }
+
+    // Anything after the closing bracket are ESMTP parameters, e.g. SIZE=
+    rest.find('>').map(|end| rest[1..end].to_string())
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let dir = PathBuf::from(args.next().unwrap_or("mail".to_string()));
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:2525".to_string()).parse().unwrap();
+
+    fs::create_dir_all(&dir).unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mailbox = Mailbox {
+        dir: dir,
+        count: 0,
+    };
+
+    let mut sink = Sink::new(acceptor, mailbox);
+
+    println!("running SMTP sink; addr={:?}; dir={:?}", address, sink.mailbox.dir);
+    event_loop.run(&mut sink).unwrap();
+}
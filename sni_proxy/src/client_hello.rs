@@ -0,0 +1,314 @@
+// Reads the server name a TLS client asks for (SNI, RFC 6066) off its
+// ClientHello, the first message of the handshake, without taking part
+// in the handshake. The ClientHello is sent in the clear, in one or more
+// handshake records:
+//
+// +------------+-----------------+-----------------+
+// | type (22)  | version (3, x)  | length (16 bits)|  record header
+// +------------+-----------------+-----------------+
+// | type (1)   | length (24 bits)                  |  handshake header
+// +------------+-----------------------------------+
+// | version, random, session id, cipher suites,    |
+// | compression methods, extensions...             |
+// +------------------------------------------------+
+//
+// The server name is one of the extensions. Like the rest of the
+// examples' protocol code, the parser does no I/O: it is given what was
+// read so far, and says whether that is enough.
+
+use std::str;
+
+// A ClientHello larger than this is rejected. They are usually well under
+// 2KB.
+pub const MAX_HELLO: usize = 16 * 1_024;
+
+const CONTENT_HANDSHAKE: u8 = 22;
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+const EXTENSION_SERVER_NAME: u16 = 0;
+const NAME_TYPE_HOST_NAME: u8 = 0;
+
+// A record's payload is at most 2^14 bytes
+const MAX_RECORD: usize = 16 * 1_024;
+
+#[derive(Debug)]
+pub struct ClientHello {
+    // Lowercased, without a trailing dot. `None` if the client didn't say,
+    // as when connecting to an IP address.
+    pub server_name: Option<String>,
+}
+
+// Parses the ClientHello at the start of `buf`, reassembling it from the
+// records it came in. Returns `None` if it isn't complete yet. Nothing is
+// consumed: the bytes are all to be passed on as they are.
+pub fn parse(buf: &[u8]) -> Result<Option<ClientHello>, &'static str> {
+    let mut message = vec![];
+    let mut pos = 0;
+
+    loop {
+        if buf.len() < pos + 5 {
+            return Ok(None);
+        }
+
+        if buf[pos] != CONTENT_HANDSHAKE {
+            return Err("not a TLS handshake");
+        }
+
+        if buf[pos + 1] != 3 {
+            return Err("unsupported TLS version");
+        }
+
+        let len = read_u16(&buf[pos + 3..]) as usize;
+
+        if len == 0 || len > MAX_RECORD {
+            return Err("invalid record length");
+        }
+
+        if buf.len() < pos + 5 + len {
+            return Ok(None);
+        }
+
+        message.extend(&buf[pos + 5..pos + 5 + len]);
+        pos += 5 + len;
+
+        if message.len() < 4 {
+            continue;
+        }
+
+        if message[0] != HANDSHAKE_CLIENT_HELLO {
+            return Err("expected a ClientHello");
+        }
+
+        let len = read_u24(&message[1..]);
+
+        if len > MAX_HELLO {
+            return Err("ClientHello too large");
+        }
+
+        if message.len() >= 4 + len {
+            return hello(&message[4..4 + len]).map(Some);
+        }
+    }
+}
+
+fn hello(body: &[u8]) -> Result<ClientHello, &'static str> {
+    let mut hello = Reader { buf: body };
+
+    // The version and random, then the session id, cipher suites and
+    // compression methods: nothing of interest
+    hello.take(2 + 32)?;
+    hello.vec8()?;
+    hello.vec16()?;
+    hello.vec8()?;
+
+    // Extensions are optional, although anything recent sends some
+    if hello.buf.is_empty() {
+        return Ok(ClientHello { server_name: None });
+    }
+
+    let mut extensions = Reader { buf: hello.vec16()? };
+
+    while !extensions.buf.is_empty() {
+        let kind = extensions.u16()?;
+        let data = extensions.vec16()?;
+
+        if kind == EXTENSION_SERVER_NAME {
+            return server_name(data).map(|name| ClientHello { server_name: Some(name) });
+        }
+    }
+
+    Ok(ClientHello { server_name: None })
+}
+
+// The extension is a list of names, of which there is only one type, and
+// a client may send only one of each
+fn server_name(data: &[u8]) -> Result<String, &'static str> {
+    let mut names = Reader { buf: Reader { buf: data }.vec16()? };
+
+    while !names.buf.is_empty() {
+        let kind = names.u8()?;
+        let name = names.vec16()?;
+
+        if kind != NAME_TYPE_HOST_NAME {
+            continue;
+        }
+
+        // International names are sent in their ASCII form
+        let name = match str::from_utf8(name) {
+            Ok(name) if !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.') => name,
+            _ => return Err("invalid server name"),
+        };
+
+        return Ok(name.trim_end_matches('.').to_ascii_lowercase());
+    }
+
+    Err("no host name in the server name extension")
+}
+
+// Takes fields off the front of a slice, failing if it is too short
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        if self.buf.len() < len {
+            return Err("truncated ClientHello");
+        }
+
+        let (field, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(field)
+    }
+
+    fn u8(&mut self) -> Result<u8, &'static str> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, &'static str> {
+        self.take(2).map(read_u16)
+    }
+
+    // A field prefixed by its one byte length
+    fn vec8(&mut self) -> Result<&'a [u8], &'static str> {
+        let len = self.u8()? as usize;
+        self.take(len)
+    }
+
+    // A field prefixed by its two byte length
+    fn vec16(&mut self) -> Result<&'a [u8], &'static str> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+}
+
+fn read_u16(buf: &[u8]) -> u16 {
+    (buf[0] as u16) << 8 | buf[1] as u16
+}
+
+fn read_u24(buf: &[u8]) -> usize {
+    buf[..3].iter().fold(0, |n, b| n << 8 | *b as usize)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn vec16(data: &[u8]) -> Vec<u8> {
+        let mut vec = vec![(data.len() >> 8) as u8, data.len() as u8];
+        vec.extend(data);
+        vec
+    }
+
+    // A ClientHello body, with the given extensions
+    fn body(extensions: Option<Vec<u8>>) -> Vec<u8> {
+        let mut body = vec![3, 3];
+        body.extend(&[0; 32]);
+        // No session id, one cipher suite, the null compression method
+        body.extend(&[0, 0, 2, 0x13, 0x01, 1, 0]);
+
+        if let Some(extensions) = extensions {
+            body.extend(vec16(&extensions));
+        }
+
+        body
+    }
+
+    fn sni(name: &[u8]) -> Vec<u8> {
+        let mut entry = vec![NAME_TYPE_HOST_NAME];
+        entry.extend(vec16(name));
+
+        let mut extension = vec![0, 0];
+        extension.extend(vec16(&vec16(&entry)));
+        extension
+    }
+
+    // The handshake message, split in records of at most `split` bytes
+    fn records(body: &[u8], split: usize) -> Vec<u8> {
+        let mut message = vec![HANDSHAKE_CLIENT_HELLO, 0, (body.len() >> 8) as u8, body.len() as u8];
+        message.extend(body);
+
+        let mut buf = vec![];
+
+        for chunk in message.chunks(split) {
+            buf.extend(&[CONTENT_HANDSHAKE, 3, 1]);
+            buf.extend(vec16(chunk));
+        }
+
+        buf
+    }
+
+    fn server_name(buf: &[u8]) -> Result<Option<Option<String>>, &'static str> {
+        parse(buf).map(|hello| hello.map(|hello| hello.server_name))
+    }
+
+    #[test]
+    fn parses_server_name() {
+        let buf = records(&body(Some(sni(b"Example.COM."))), MAX_RECORD);
+        assert_eq!(server_name(&buf), Ok(Some(Some("example.com".to_string()))));
+
+        // Another extension first
+        let mut extensions = vec![0, 10, 0, 2, 0, 29];
+        extensions.extend(sni(b"example.com"));
+        let buf = records(&body(Some(extensions)), MAX_RECORD);
+        assert_eq!(server_name(&buf), Ok(Some(Some("example.com".to_string()))));
+    }
+
+    #[test]
+    fn parses_hello_without_server_name() {
+        assert_eq!(server_name(&records(&body(None), MAX_RECORD)), Ok(Some(None)));
+        assert_eq!(server_name(&records(&body(Some(vec![0, 10, 0, 0])), MAX_RECORD)), Ok(Some(None)));
+    }
+
+    #[test]
+    fn reassembles_records() {
+        let buf = records(&body(Some(sni(b"example.com"))), 3);
+        assert_eq!(server_name(&buf), Ok(Some(Some("example.com".to_string()))));
+    }
+
+    #[test]
+    fn waits_for_truncated_hello() {
+        let buf = records(&body(Some(sni(b"example.com"))), 16);
+
+        for len in 0..buf.len() {
+            assert_eq!(server_name(&buf[..len]), Ok(None));
+        }
+    }
+
+    #[test]
+    fn rejects_other_protocols() {
+        assert_eq!(server_name(b"GET / HTTP/1.1\r\n"), Err("not a TLS handshake"));
+        assert_eq!(server_name(&[CONTENT_HANDSHAKE, 2, 0, 0, 1]), Err("unsupported TLS version"));
+
+        let mut buf = records(&body(None), MAX_RECORD);
+        buf[5] = 2;
+        assert_eq!(server_name(&buf), Err("expected a ClientHello"));
+    }
+
+    #[test]
+    fn rejects_oversize_lengths() {
+        assert_eq!(server_name(&[CONTENT_HANDSHAKE, 3, 1, 0, 0]), Err("invalid record length"));
+        assert_eq!(server_name(&[CONTENT_HANDSHAKE, 3, 1, 0x40, 1]), Err("invalid record length"));
+
+        // Refused on the handshake header, before the rest arrives
+        let buf = [CONTENT_HANDSHAKE, 3, 1, 0, 4, HANDSHAKE_CLIENT_HELLO, 0xff, 0xff, 0xff];
+        assert_eq!(server_name(&buf), Err("ClientHello too large"));
+    }
+
+    #[test]
+    fn rejects_malformed_hello() {
+        // A session id running past the end of the message
+        let mut hello = body(None);
+        hello[34] = 32;
+        assert_eq!(server_name(&records(&hello, MAX_RECORD)), Err("truncated ClientHello"));
+
+        // An extension running past the end of the extensions
+        let buf = records(&body(Some(vec![0, 0, 0, 9, 0])), MAX_RECORD);
+        assert_eq!(server_name(&buf), Err("truncated ClientHello"));
+
+        let buf = records(&body(Some(sni(b"exa mple.com"))), MAX_RECORD);
+        assert_eq!(server_name(&buf), Err("invalid server name"));
+
+        let buf = records(&body(Some(sni(b""))), MAX_RECORD);
+        assert_eq!(server_name(&buf), Err("invalid server name"));
+    }
+}
@@ -0,0 +1,524 @@
+extern crate mio;
+extern crate pump;
+
+mod client_hello;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use pump::{Pipe, Side};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::{env, process};
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Tokens `1..=MAX_CONNECTIONS` are used for client connections, and the
+// connection to a client's backend is its token plus `MAX_CONNECTIONS`.
+const MAX_CONNECTIONS: usize = 1_024;
+
+// A client not done sending its ClientHello within this long is dropped,
+// and so is one whose backend doesn't accept the connection in time
+const TIMEOUT_MS: u64 = 10_000;
+
+// TLS alerts, sent to the client before closing the connection when it
+// can't be routed
+const ALERT_DECODE_ERROR: u8 = 50;
+const ALERT_INTERNAL_ERROR: u8 = 80;
+const ALERT_UNRECOGNIZED_NAME: u8 = 112;
+
+// Which backend each server name goes to
+struct Routes {
+    hosts: HashMap<String, SocketAddr>,
+    // For the names not listed, and the clients that don't send one
+    default: Option<SocketAddr>,
+}
+
+impl Routes {
+    fn route(&self, name: Option<&str>) -> Option<SocketAddr> {
+        name.and_then(|name| self.hosts.get(name)).cloned().or(self.default)
+    }
+}
+
+// Routes TLS connections to a backend by the server name the client asks
+// for, without terminating TLS: the proxy holds no certificate, and can't
+// read what goes through. The client's first message, the ClientHello, is
+// sent in the clear, and says which host it wants. The proxy reads it,
+// connects to the backend for that host, and passes the ClientHello on as
+// it came, before relaying everything else both ways. The backend takes
+// it from there, and the handshake is between it and the client.
+struct Proxy {
+    server: TcpListener,
+    routes: Routes,
+    connections: Slab<Connection>,
+}
+
+impl Proxy {
+    fn new(server: TcpListener, routes: Routes) -> Proxy {
+        // Token `0` is reserved for the server socket
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS);
+
+        Proxy {
+            server: server,
+            routes: routes,
+            connections: slab,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Proxy>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        let token = match self.connections.insert_with(|token| Connection::new(socket, token)) {
+            Some(token) => token,
+            None => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        let conn = &mut self.connections[token];
+        conn.timeout = Some(event_loop.timeout_ms(token, TIMEOUT_MS).unwrap());
+
+        event_loop.register_opt(conn.client(), token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn client_ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            self.connections[token].read_client();
+        }
+
+        if self.connections[token].state == State::Hello {
+            self.hello(event_loop, token);
+        }
+
+        if events.is_writable() {
+            self.connections[token].write_client();
+        }
+
+        self.update(event_loop, token);
+    }
+
+    fn backend_ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        if self.connections[token].state == State::Connecting {
+            self.connected(event_loop, token, events);
+        }
+
+        {
+            let conn = &mut self.connections[token];
+
+            if conn.state == State::Relaying {
+                if events.is_readable() {
+                    conn.read_backend();
+                }
+
+                if events.is_writable() {
+                    conn.write_backend();
+                }
+            }
+        }
+
+        self.update(event_loop, token);
+    }
+
+    // Picks the backend, once the ClientHello is all in, and starts
+    // connecting to it. The ClientHello stays buffered, to be the first
+    // thing the backend gets.
+    fn hello(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token) {
+        let hello = match client_hello::parse(&self.connections[token].buf) {
+            Ok(Some(hello)) => hello,
+            Ok(None) if self.connections[token].client_eof => {
+                println!("client closed before its ClientHello was complete; token={:?}", token);
+                return self.connections[token].closed = true;
+            }
+            Ok(None) => return,
+            Err(reason) => {
+                println!("invalid ClientHello; token={:?}; reason={}", token, reason);
+                return self.connections[token].fail(ALERT_DECODE_ERROR);
+            }
+        };
+
+        let addr = match self.routes.route(hello.server_name.as_ref().map(|name| &name[..])) {
+            Some(addr) => addr,
+            None => {
+                println!("no backend for server name; token={:?}; name={:?}", token, hello.server_name);
+                return self.connections[token].fail(ALERT_UNRECOGNIZED_NAME);
+            }
+        };
+
+        let socket = match TcpStream::connect(&addr) {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("failed to connect to backend; addr={}; err={:?}", addr, e);
+                return self.connections[token].fail(ALERT_INTERNAL_ERROR);
+            }
+        };
+
+        println!("routing; token={:?}; name={:?}; backend={}", token, hello.server_name, addr);
+
+        // Writable once connected
+        event_loop.register_opt(&socket, backend_token(token), mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        let conn = &mut self.connections[token];
+        event_loop.clear_timeout(conn.timeout.take().unwrap());
+
+        conn.backend = Some(socket);
+        conn.timeout = Some(event_loop.timeout_ms(token, TIMEOUT_MS).unwrap());
+        conn.state = State::Connecting;
+    }
+
+    fn connected(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        let conn = &mut self.connections[token];
+
+        if events.is_error() || events.is_hup() {
+            println!("failed to connect to backend; token={:?}; err={:?}", token, conn.backend().unwrap().take_socket_error());
+            event_loop.clear_timeout(conn.timeout.take().unwrap());
+            return conn.fail(ALERT_INTERNAL_ERROR);
+        }
+
+        if !events.is_writable() {
+            return;
+        }
+
+        event_loop.clear_timeout(conn.timeout.take().unwrap());
+
+        conn.establish();
+    }
+
+    // Writes what it can, then reregisters both sockets of the connection,
+    // or removes it once it is done
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token) {
+        {
+            let conn = &mut self.connections[token];
+
+            conn.write_client();
+
+            if conn.state == State::Relaying {
+                conn.write_backend();
+            }
+        }
+
+        if self.connections[token].is_done() {
+            let conn = self.connections.remove(token).unwrap();
+
+            if let Some(timeout) = conn.timeout {
+                event_loop.clear_timeout(timeout);
+            }
+
+            let (sent, received) = conn.transferred();
+            println!("connection closed; token={:?}; sent={}; received={}", token, sent, received);
+            return;
+        }
+
+        let conn = &self.connections[token];
+
+        event_loop.reregister(conn.client(), token, conn.client_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        if let Some(backend) = conn.backend() {
+            event_loop.reregister(backend, backend_token(token), conn.backend_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+}
+
+impl mio::Handler for Proxy {
+    // The connection whose ClientHello, or backend, is taking too long
+    type Timeout = mio::Token;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ if token.as_usize() <= MAX_CONNECTIONS => self.client_ready(event_loop, token, events),
+            _ => {
+                let token = mio::Token(token.as_usize() - MAX_CONNECTIONS);
+
+                // The backend's events may still come in after it is gone,
+                // or even the connection
+                if self.connections.get(token).map(|conn| conn.backend().is_some()).unwrap_or(false) {
+                    self.backend_ready(event_loop, token, events);
+                }
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token) {
+        println!("timed out; token={:?}; state={:?}", token, self.connections[token].state);
+
+        self.connections[token].timeout = None;
+        self.connections[token].closed = true;
+        self.update(event_loop, token);
+    }
+}
+
+fn backend_token(token: mio::Token) -> mio::Token {
+    mio::Token(token.as_usize() + MAX_CONNECTIONS)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    // Reading the ClientHello
+    Hello,
+    // Waiting for the backend to accept the connection
+    Connecting,
+    // Passing bytes both ways
+    Relaying,
+    // Writing an alert, then closing
+    Failed,
+}
+
+struct Connection {
+    token: mio::Token,
+    state: State,
+    // The client, and the backend once there is one, until they are
+    // handed over to the pipe
+    client: Option<TcpStream>,
+    backend: Option<TcpStream>,
+    pipe: Option<Pipe>,
+    timeout: Option<mio::Timeout>,
+    // Read from the client before the backend is connected: the
+    // ClientHello until it is complete, then what follows it, all to be
+    // written to the backend first
+    buf: Vec<u8>,
+    // The alert, once the connection failed
+    alert: Vec<u8>,
+    client_eof: bool,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(client: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            token: token,
+            state: State::Hello,
+            client: Some(client),
+            backend: None,
+            pipe: None,
+            timeout: None,
+            buf: vec![],
+            alert: vec![],
+            client_eof: false,
+            closed: false,
+        }
+    }
+
+    fn client(&self) -> &TcpStream {
+        match self.pipe {
+            Some(ref pipe) => pipe.socket(Side::A),
+            None => self.client.as_ref().unwrap(),
+        }
+    }
+
+    fn backend(&self) -> Option<&TcpStream> {
+        match self.pipe {
+            Some(ref pipe) => Some(pipe.socket(Side::B)),
+            None => self.backend.as_ref(),
+        }
+    }
+
+    // The client gets a fatal alert, the way a server refusing the
+    // handshake would answer
+    fn fail(&mut self, alert: u8) {
+        self.alert = vec![21, 3, 1, 0, 2, 2, alert];
+        self.buf.clear();
+        self.backend = None;
+        self.state = State::Failed;
+    }
+
+    // Hands both sockets over to the pipe, the ClientHello, and whatever
+    // the client sent after it, to be written to the backend first
+    fn establish(&mut self) {
+        let mut pipe = Pipe::new(self.client.take().unwrap(), self.backend.take().unwrap());
+        pipe.push(Side::B, &self.buf);
+
+        self.buf.clear();
+        self.pipe = Some(pipe);
+        self.state = State::Relaying;
+    }
+
+    fn read_client(&mut self) {
+        if self.state == State::Relaying {
+            return self.relay(Side::A);
+        }
+
+        let mut chunk = [0; 4_096];
+        let client = self.client.as_mut().unwrap();
+
+        // The socket is registered as edge triggered, drain it. Or at least
+        // until enough is waiting for the backend. The ClientHello is
+        // smaller than that, or it is refused.
+        while self.buf.len() < pump::MAX_BUFFERED {
+            match client.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.client_eof = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    if self.state != State::Failed {
+                        self.buf.extend(&chunk[..n]);
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read from the client; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn read_backend(&mut self) {
+        self.relay(Side::B);
+    }
+
+    fn relay(&mut self, side: Side) {
+        if let Err(e) = self.pipe.as_mut().unwrap().read(side, usize::MAX) {
+            println!("got an error trying to read from the {}; token={:?}; err={:?}", name(side), self.token, e);
+            self.closed = true;
+        }
+    }
+
+    fn write_client(&mut self) {
+        if self.state == State::Relaying {
+            return self.write(Side::A);
+        }
+
+        let client = self.client.as_mut().unwrap();
+
+        while !self.alert.is_empty() {
+            match client.try_write(&self.alert) {
+                Ok(Some(n)) => {
+                    self.alert.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write to the client; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write_backend(&mut self) {
+        self.write(Side::B);
+    }
+
+    fn write(&mut self, side: Side) {
+        if let Err(e) = self.pipe.as_mut().unwrap().write(side) {
+            println!("got an error trying to write to the {}; token={:?}; err={:?}", name(side), self.token, e);
+            self.closed = true;
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        if self.closed {
+            return true;
+        }
+
+        match self.state {
+            State::Hello | State::Connecting => false,
+            State::Relaying => self.pipe.as_ref().unwrap().is_done(),
+            State::Failed => self.alert.is_empty(),
+        }
+    }
+
+    // Bytes written to the backend, and to the client
+    fn transferred(&self) -> (u64, u64) {
+        match self.pipe {
+            Some(ref pipe) => (pipe.written(Side::B), pipe.written(Side::A)),
+            None => (0, 0),
+        }
+    }
+
+    fn client_interest(&self) -> mio::EventSet {
+        if let Some(ref pipe) = self.pipe {
+            return pipe.interest(Side::A);
+        }
+
+        let mut interest = mio::EventSet::none();
+
+        if !self.client_eof && self.buf.len() < pump::MAX_BUFFERED {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.alert.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        interest
+    }
+
+    fn backend_interest(&self) -> mio::EventSet {
+        match self.pipe {
+            Some(ref pipe) => pipe.interest(Side::B),
+            // Writable once connected
+            None => mio::EventSet::writable(),
+        }
+    }
+}
+
+fn name(side: Side) -> &'static str {
+    match side {
+        Side::A => "client",
+        Side::B => "backend",
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:9443".to_string()).parse().unwrap();
+
+    let mut routes = Routes {
+        hosts: HashMap::new(),
+        default: None,
+    };
+
+    // Routes are given as `name=backend`, with `*` for the default
+    for arg in args {
+        let mut parts = arg.splitn(2, '=');
+
+        let (name, backend) = match (parts.next(), parts.next().and_then(|addr| addr.parse::<SocketAddr>().ok())) {
+            (Some(name), Some(backend)) => (name.trim_end_matches('.').to_ascii_lowercase(), backend),
+            _ => {
+                println!("invalid route, expected name=ip:port; route={:?}", arg);
+                process::exit(1);
+            }
+        };
+
+        if name == "*" {
+            routes.default = Some(backend);
+        } else {
+            routes.hosts.insert(name, backend);
+        }
+    }
+
+    if routes.hosts.is_empty() && routes.default.is_none() {
+        println!("usage: sni_proxy <addr> <name=ip:port>...");
+        process::exit(1);
+    }
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    println!("running SNI proxy; addr={:?}; routes={:?}; default={:?}", address, routes.hosts, routes.default);
+
+    let mut proxy = Proxy::new(server, routes);
+    event_loop.run(&mut proxy).unwrap();
+}
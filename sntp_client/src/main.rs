@@ -0,0 +1,213 @@
+extern crate mio;
+extern crate bytes;
+
+use mio::udp::*;
+use bytes::SliceBuf;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::env;
+
+const SOCKET: mio::Token = mio::Token(0);
+
+// How long to wait for a response before sending the request again
+const RETRY_MS: u64 = 2_000;
+
+// How many requests are sent before giving up
+const MAX_ATTEMPTS: usize = 3;
+
+// Seconds between the NTP epoch (1900) and the Unix epoch (1970)
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+const PACKET_LEN: usize = 48;
+
+struct Client {
+    socket: UdpSocket,
+    server: SocketAddr,
+    // The transmit timestamp of the most recent request. The server copies
+    // it into the "originate" field of its response, which is used to match
+    // the response with the request and to discard stale responses.
+    sent: Timestamp,
+    attempts: usize,
+    timeout: Option<mio::Timeout>,
+}
+
+impl Client {
+    fn new(socket: UdpSocket, server: SocketAddr) -> Client {
+        Client {
+            socket: socket,
+            server: server,
+            sent: Timestamp(0),
+            attempts: 0,
+            timeout: None,
+        }
+    }
+
+    fn send(&mut self, event_loop: &mut mio::EventLoop<Client>) {
+        self.attempts += 1;
+        self.sent = Timestamp::now();
+
+        println!("sending request; server={:?}; attempt={}", self.server, self.attempts);
+
+        let mut packet = [0; PACKET_LEN];
+
+        // LI = 0 (no warning), VN = 4, Mode = 3 (client)
+        packet[0] = 4 << 3 | 3;
+        self.sent.write(&mut packet[40..48]);
+
+        match self.socket.send_to(&mut SliceBuf::wrap(&packet), &self.server) {
+            Ok(Some(())) => {}
+            Ok(None) => println!("the socket wasn't actually ready, will retry"),
+            Err(e) => panic!("got an error trying to send; err={:?}", e),
+        }
+
+        self.timeout = Some(event_loop.timeout_ms((), RETRY_MS).unwrap());
+    }
+
+    fn receive(&mut self, event_loop: &mut mio::EventLoop<Client>) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event.
+        loop {
+            let mut buf = Vec::with_capacity(PACKET_LEN);
+
+            let addr = match self.socket.recv_from(&mut buf) {
+                Ok(Some(addr)) => addr,
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            // Capture the arrival time as close to the read as possible
+            let t4 = Timestamp::now();
+
+            if addr != self.server || buf.len() < PACKET_LEN {
+                println!("ignoring unexpected datagram; addr={:?}; len={}", addr, buf.len());
+                continue;
+            }
+
+            let mode = buf[0] & 0x7;
+            let stratum = buf[1];
+            let originate = Timestamp::read(&buf[24..32]);
+
+            if mode != 4 || originate != self.sent {
+                println!("ignoring response that doesn't match the last request");
+                continue;
+            }
+
+            if stratum == 0 {
+                // A "kiss-o'-death" packet, the reason is in the reference ID
+                let code = String::from_utf8_lossy(&buf[12..16]).into_owned();
+                println!("server refused the request; code={}", code);
+            } else {
+                report(self.sent, Timestamp::read(&buf[32..40]), Timestamp::read(&buf[40..48]), t4, stratum);
+            }
+
+            if let Some(timeout) = self.timeout.take() {
+                event_loop.clear_timeout(timeout);
+            }
+
+            event_loop.shutdown();
+            return;
+        }
+    }
+}
+
+impl mio::Handler for Client {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Client>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, SOCKET);
+        assert!(events.is_readable(), "unexpected events; events={:?}", events);
+
+        self.receive(event_loop);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Client>, _: ()) {
+        if self.attempts < MAX_ATTEMPTS {
+            self.send(event_loop);
+            return;
+        }
+
+        println!("no response after {} attempts", self.attempts);
+        event_loop.shutdown();
+    }
+}
+
+// Prints the clock offset and round trip delay computed from the four
+// timestamps of an exchange:
+//
+// t1: request sent (client clock)
+// t2: request received (server clock)
+// t3: response sent (server clock)
+// t4: response received (client clock)
+fn report(t1: Timestamp, t2: Timestamp, t3: Timestamp, t4: Timestamp, stratum: u8) {
+    let (t1, t2, t3, t4) = (t1.secs(), t2.secs(), t3.secs(), t4.secs());
+
+    let offset = ((t2 - t1) + (t3 - t4)) / 2.0;
+    let delay = (t4 - t1) - (t3 - t2);
+
+    println!("stratum: {}", stratum);
+    println!("offset:  {:+.6}s", offset);
+    println!("delay:   {:.6}s", delay);
+}
+
+/*
+ *
+ * ===== NTP timestamps =====
+ *
+ */
+
+// A 64 bit NTP timestamp: seconds since 1900 in the upper 32 bits and the
+// fraction of a second in the lower 32 bits.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Timestamp(u64);
+
+impl Timestamp {
+    fn now() -> Timestamp {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+        let secs = now.as_secs() + NTP_UNIX_OFFSET;
+        let frac = ((now.subsec_nanos() as u64) << 32) / 1_000_000_000;
+
+        Timestamp(secs << 32 | frac)
+    }
+
+    fn read(buf: &[u8]) -> Timestamp {
+        let mut val = 0;
+
+        for b in &buf[..8] {
+            val = val << 8 | *b as u64;
+        }
+
+        Timestamp(val)
+    }
+
+    fn write(&self, buf: &mut [u8]) {
+        for (i, b) in buf[..8].iter_mut().enumerate() {
+            *b = (self.0 >> (56 - i * 8)) as u8;
+        }
+    }
+
+    fn secs(&self) -> f64 {
+        (self.0 >> 32) as f64 + (self.0 & 0xffff_ffff) as f64 / 4_294_967_296.0
+    }
+}
+
+fn main() {
+    let server = env::args().nth(1).unwrap_or("pool.ntp.org:123".to_string());
+
+    // Resolving the hostname blocks, which is fine since the event loop
+    // isn't running yet.
+    let server = server.to_socket_addrs().unwrap()
+        .find(|addr| addr.is_ipv4())
+        .expect("no IPv4 address for server");
+
+    let socket = UdpSocket::bound(&"0.0.0.0:0".parse().unwrap()).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&socket, SOCKET, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+
+    let mut client = Client::new(socket, server);
+    client.send(&mut event_loop);
+
+    event_loop.run(&mut client).unwrap();
+}
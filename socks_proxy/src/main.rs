@@ -0,0 +1,607 @@
+extern crate mio;
+extern crate pump;
+extern crate resolver;
+
+mod socks;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use pump::{Pipe, Side};
+use resolver::{Answer, Deadline, Resolve, Resolver};
+use socks::Target;
+use std::env;
+use std::io;
+use std::net::SocketAddr;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Tokens `1..=MAX_TUNNELS` are used for client connections, and the
+// connection to a client's target is its token plus `MAX_TUNNELS`.
+const MAX_TUNNELS: usize = 1_024;
+
+// A target not accepting the connection within this long, or whose name
+// takes this long to resolve, is reported unreachable
+const CONNECT_TIMEOUT_MS: u64 = 10_000;
+const RESOLVE_TIMEOUT_MS: u64 = 5_000;
+
+// The names of the targets are resolved on threads of their own, this
+// many, and the last ones are cached
+const RESOLVER_THREADS: usize = 4;
+const RESOLVER_CACHE: usize = 1_024;
+
+#[derive(Clone, Copy)]
+enum Timer {
+    // The tunnel whose target is taking too long to connect
+    Connect(mio::Token),
+    // A target's name is taking too long to resolve
+    Resolve(Deadline),
+}
+
+impl From<Deadline> for Timer {
+    fn from(deadline: Deadline) -> Timer {
+        Timer::Resolve(deadline)
+    }
+}
+
+// A SOCKS5 proxy, for the CONNECT command, without authentication. The
+// client says hello, asks for a target by address or by name, and once the
+// proxy has connected to it, the connection is a tunnel relaying bytes
+// both ways without looking at them. Each tunnel goes through five states:
+// the greeting, the request, resolving the target's name when it was given
+// one, connecting to it, and relaying. The name is resolved without
+// blocking the event loop, by the resolver's threads, which send the
+// address back through the loop's channel.
+struct Proxy {
+    server: TcpListener,
+    tunnels: Slab<Tunnel>,
+    resolver: Resolver<mio::Token>,
+}
+
+impl Proxy {
+    fn new(server: TcpListener, resolver: Resolver<mio::Token>) -> Proxy {
+        // Token `0` is reserved for the server socket
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_TUNNELS);
+
+        Proxy {
+            server: server,
+            tunnels: slab,
+            resolver: resolver,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Proxy>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        let token = match self.tunnels.insert_with(|token| Tunnel::new(socket, token)) {
+            Some(token) => token,
+            None => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        event_loop.register_opt(self.tunnels[token].client(), token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn client_ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            self.tunnels[token].read_client();
+        }
+
+        if self.tunnels[token].state == State::Greeting {
+            self.tunnels[token].take_greeting();
+        }
+
+        if self.tunnels[token].state == State::Request {
+            self.request(event_loop, token);
+        }
+
+        if events.is_writable() {
+            self.tunnels[token].write_client();
+        }
+
+        self.update(event_loop, token);
+    }
+
+    fn target_ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        if self.tunnels[token].state == State::Connecting {
+            self.connected(event_loop, token, events);
+        }
+
+        {
+            let tunnel = &mut self.tunnels[token];
+
+            if tunnel.state == State::Relaying {
+                if events.is_readable() {
+                    tunnel.read_target();
+                }
+
+                if events.is_writable() {
+                    tunnel.write_target();
+                }
+            }
+        }
+
+        self.update(event_loop, token);
+    }
+
+    // Parses the request, once it is all in, and connects to the target
+    // right away when it was given by address, or starts resolving its name
+    fn request(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token) {
+        let target = match self.tunnels[token].take_request() {
+            Ok(Some(target)) => target,
+            Ok(None) => return,
+            Err((code, reason)) => {
+                println!("refusing request; token={:?}; reason={}", token, reason);
+                return self.tunnels[token].fail(code);
+            }
+        };
+
+        let (host, port) = match target {
+            Target::Addr(addr) => {
+                self.tunnels[token].target_name = addr.to_string();
+                return self.connect(event_loop, token, addr);
+            }
+            Target::Name(host, port) => (host, port),
+        };
+
+        self.tunnels[token].target_name = format!("{}:{}", host, port);
+
+        match self.resolver.resolve(event_loop, &host, port, RESOLVE_TIMEOUT_MS, token) {
+            Resolve::Done(token, result) => self.resolved(event_loop, token, result),
+            Resolve::Pending(query) => {
+                let tunnel = &mut self.tunnels[token];
+                tunnel.query = Some(query);
+                tunnel.state = State::Resolving;
+            }
+        }
+    }
+
+    fn resolved(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, result: Result<SocketAddr, resolver::Error>) {
+        self.tunnels[token].query = None;
+
+        let addr = match result {
+            Ok(addr) => addr,
+            Err(e) => {
+                println!("failed to resolve target; token={:?}; target={}; err={}", token, self.tunnels[token].target_name, e);
+                return self.tunnels[token].fail(socks::HOST_UNREACHABLE);
+            }
+        };
+
+        self.connect(event_loop, token, addr);
+    }
+
+    fn connect(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, addr: SocketAddr) {
+        let socket = match TcpStream::connect(&addr) {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("failed to connect to target; target={}; err={:?}", self.tunnels[token].target_name, e);
+                return self.tunnels[token].fail(socks::GENERAL_FAILURE);
+            }
+        };
+
+        println!("connecting; token={:?}; target={}; addr={}", token, self.tunnels[token].target_name, addr);
+
+        // Writable once connected
+        event_loop.register_opt(&socket, target_token(token), mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        let tunnel = &mut self.tunnels[token];
+        tunnel.target = Some(socket);
+        tunnel.timeout = Some(event_loop.timeout_ms(Timer::Connect(token), CONNECT_TIMEOUT_MS).unwrap());
+        tunnel.state = State::Connecting;
+    }
+
+    fn connected(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        let tunnel = &mut self.tunnels[token];
+
+        if events.is_error() || events.is_hup() {
+            let err = tunnel.target().unwrap().take_socket_error();
+            println!("failed to connect to target; token={:?}; err={:?}", token, err);
+            event_loop.clear_timeout(tunnel.timeout.take().unwrap());
+
+            return match err {
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => tunnel.fail(socks::CONNECTION_REFUSED),
+                _ => tunnel.fail(socks::HOST_UNREACHABLE),
+            };
+        }
+
+        if !events.is_writable() {
+            return;
+        }
+
+        println!("tunnel established; token={:?}", token);
+        event_loop.clear_timeout(tunnel.timeout.take().unwrap());
+
+        tunnel.establish();
+    }
+
+    // Writes what it can, then reregisters both sockets of the tunnel, or
+    // removes it once it is done
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token) {
+        {
+            let tunnel = &mut self.tunnels[token];
+
+            tunnel.write_client();
+
+            if tunnel.state == State::Relaying {
+                tunnel.write_target();
+            }
+        }
+
+        if self.tunnels[token].is_done() {
+            let tunnel = self.tunnels.remove(token).unwrap();
+
+            if let Some(timeout) = tunnel.timeout {
+                event_loop.clear_timeout(timeout);
+            }
+
+            // The client may leave while the name is being resolved
+            if let Some(query) = tunnel.query {
+                self.resolver.cancel(event_loop, query);
+            }
+
+            let (sent, received) = tunnel.transferred();
+            println!("tunnel closed; token={:?}; sent={}; received={}", token, sent, received);
+            return;
+        }
+
+        let tunnel = &self.tunnels[token];
+
+        event_loop.reregister(tunnel.client(), token, tunnel.client_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        if let Some(target) = tunnel.target() {
+            event_loop.reregister(target, target_token(token), tunnel.target_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+}
+
+impl mio::Handler for Proxy {
+    type Timeout = Timer;
+    type Message = Answer;
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ if token.as_usize() <= MAX_TUNNELS => self.client_ready(event_loop, token, events),
+            _ => {
+                let token = mio::Token(token.as_usize() - MAX_TUNNELS);
+
+                // The target's events may still come in after it is gone,
+                // or even the tunnel
+                if self.tunnels.get(token).map(|tunnel| tunnel.target().is_some()).unwrap_or(false) {
+                    self.target_ready(event_loop, token, events);
+                }
+            }
+        }
+    }
+
+    // A name is resolved, for all the tunnels waiting for it
+    fn notify(&mut self, event_loop: &mut mio::EventLoop<Proxy>, answer: Answer) {
+        for (token, result) in self.resolver.answer(event_loop, answer) {
+            self.resolved(event_loop, token, result);
+            self.update(event_loop, token);
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Proxy>, timer: Timer) {
+        let token = match timer {
+            Timer::Connect(token) => token,
+            Timer::Resolve(deadline) => {
+                if let Some((token, err)) = self.resolver.expire(deadline) {
+                    self.resolved(event_loop, token, Err(err));
+                    self.update(event_loop, token);
+                }
+
+                return;
+            }
+        };
+
+        println!("target didn't accept the connection in time; token={:?}", token);
+
+        self.tunnels[token].timeout = None;
+        self.tunnels[token].fail(socks::HOST_UNREACHABLE);
+        self.update(event_loop, token);
+    }
+}
+
+fn target_token(token: mio::Token) -> mio::Token {
+    mio::Token(token.as_usize() + MAX_TUNNELS)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    // Reading the methods the client can authenticate with
+    Greeting,
+    // Reading the target the client asks for
+    Request,
+    // Waiting for the target's address
+    Resolving,
+    // Waiting for the target to accept the connection
+    Connecting,
+    // Passing bytes both ways
+    Relaying,
+    // Writing the refusal, then closing
+    Failed,
+}
+
+struct Tunnel {
+    token: mio::Token,
+    state: State,
+    // The target, as the client asked for it
+    target_name: String,
+    // The client, and the target once there is one, until the tunnel is
+    // established and they are handed over to the pipe
+    client: Option<TcpStream>,
+    target: Option<TcpStream>,
+    pipe: Option<Pipe>,
+    // The target's name being resolved
+    query: Option<resolver::Query>,
+    timeout: Option<mio::Timeout>,
+    // Read from the client before the tunnel is established: the greeting
+    // and the request until they are complete, then anything sent without
+    // waiting for the answer, to be written to the target first
+    buf: Vec<u8>,
+    // To be written to the client before the tunnel is established: the
+    // answer to the greeting, and the refusal once the tunnel failed
+    response: Vec<u8>,
+    client_eof: bool,
+    closed: bool,
+}
+
+impl Tunnel {
+    fn new(client: TcpStream, token: mio::Token) -> Tunnel {
+        Tunnel {
+            token: token,
+            state: State::Greeting,
+            target_name: String::new(),
+            client: Some(client),
+            target: None,
+            pipe: None,
+            query: None,
+            timeout: None,
+            buf: vec![],
+            response: vec![],
+            client_eof: false,
+            closed: false,
+        }
+    }
+
+    fn client(&self) -> &TcpStream {
+        match self.pipe {
+            Some(ref pipe) => pipe.socket(Side::A),
+            None => self.client.as_ref().unwrap(),
+        }
+    }
+
+    fn target(&self) -> Option<&TcpStream> {
+        match self.pipe {
+            Some(ref pipe) => Some(pipe.socket(Side::B)),
+            None => self.target.as_ref(),
+        }
+    }
+
+    // Answers the greeting, once it is all in, moving on to the request if
+    // the client can go without authenticating
+    fn take_greeting(&mut self) {
+        let (len, no_auth) = match socks::parse_greeting(&self.buf) {
+            Ok(Some(greeting)) => greeting,
+            Ok(None) => return,
+            Err(reason) => {
+                println!("refusing client; token={:?}; reason={}", self.token, reason);
+                self.closed = true;
+                return;
+            }
+        };
+
+        self.buf.drain(..len);
+        self.response.extend_from_slice(&socks::method(no_auth));
+
+        if !no_auth {
+            println!("refusing client; token={:?}; reason=authentication required", self.token);
+            self.buf.clear();
+            self.state = State::Failed;
+            return;
+        }
+
+        self.state = State::Request;
+    }
+
+    // Returns the target, once the request is in, or the reply code to
+    // refuse the request with
+    fn take_request(&mut self) -> Result<Option<Target>, (u8, &'static str)> {
+        match socks::parse_request(&self.buf)? {
+            Some((len, target)) => {
+                self.buf.drain(..len);
+                Ok(Some(target))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn fail(&mut self, code: u8) {
+        self.response.extend_from_slice(&socks::reply(code, None));
+        self.buf.clear();
+        self.target = None;
+        self.state = State::Failed;
+    }
+
+    // Hands both sockets over to the pipe, with what is left of the answer
+    // to the greeting and the reply to the request first for the client,
+    // and what it sent since for the target
+    fn establish(&mut self) {
+        let target = self.target.take().unwrap();
+        let reply = socks::reply(socks::SUCCEEDED, target.local_addr().ok());
+
+        let mut pipe = Pipe::new(self.client.take().unwrap(), target);
+
+        pipe.push(Side::A, &self.response);
+        pipe.push(Side::A, &reply);
+        pipe.push(Side::B, &self.buf);
+
+        self.response.clear();
+        self.buf.clear();
+        self.pipe = Some(pipe);
+        self.state = State::Relaying;
+    }
+
+    fn read_client(&mut self) {
+        if self.state == State::Relaying {
+            return self.relay(Side::A);
+        }
+
+        let mut chunk = [0; 4_096];
+        let client = self.client.as_mut().unwrap();
+
+        // The socket is registered as edge triggered, drain it. Or at least
+        // until enough is waiting for the target.
+        while self.buf.len() < pump::MAX_BUFFERED {
+            match client.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.client_eof = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    if self.state != State::Failed {
+                        self.buf.extend(&chunk[..n]);
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read from the client; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn read_target(&mut self) {
+        self.relay(Side::B);
+    }
+
+    // Reads from a side of the established tunnel
+    fn relay(&mut self, side: Side) {
+        if let Err(e) = self.pipe.as_mut().unwrap().read(side, usize::MAX) {
+            println!("got an error trying to read from the {}; token={:?}; err={:?}", name(side), self.token, e);
+            self.closed = true;
+        }
+    }
+
+    fn write_client(&mut self) {
+        if self.state == State::Relaying {
+            return self.write(Side::A);
+        }
+
+        let client = self.client.as_mut().unwrap();
+
+        while !self.response.is_empty() {
+            match client.try_write(&self.response) {
+                Ok(Some(n)) => {
+                    self.response.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write to the client; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write_target(&mut self) {
+        self.write(Side::B);
+    }
+
+    fn write(&mut self, side: Side) {
+        if let Err(e) = self.pipe.as_mut().unwrap().write(side) {
+            println!("got an error trying to write to the {}; token={:?}; err={:?}", name(side), self.token, e);
+            self.closed = true;
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        if self.closed {
+            return true;
+        }
+
+        match self.state {
+            State::Greeting | State::Request => self.client_eof,
+            State::Resolving | State::Connecting => false,
+            State::Relaying => self.pipe.as_ref().unwrap().is_done(),
+            State::Failed => self.response.is_empty(),
+        }
+    }
+
+    // Bytes written to the target, and to the client
+    fn transferred(&self) -> (u64, u64) {
+        match self.pipe {
+            Some(ref pipe) => (pipe.written(Side::B), pipe.written(Side::A)),
+            None => (0, 0),
+        }
+    }
+
+    fn client_interest(&self) -> mio::EventSet {
+        if let Some(ref pipe) = self.pipe {
+            return pipe.interest(Side::A);
+        }
+
+        let mut interest = mio::EventSet::none();
+
+        if !self.client_eof && self.buf.len() < pump::MAX_BUFFERED {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.response.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        interest
+    }
+
+    fn target_interest(&self) -> mio::EventSet {
+        match self.pipe {
+            Some(ref pipe) => pipe.interest(Side::B),
+            // Writable once connected
+            None => mio::EventSet::writable(),
+        }
+    }
+}
+
+fn name(side: Side) -> &'static str {
+    match side {
+        Side::A => "client",
+        Side::B => "target",
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1).unwrap_or("127.0.0.1:1080".to_string()).parse().unwrap();
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    println!("running SOCKS proxy; addr={:?}", address);
+
+    let resolver = Resolver::new(RESOLVER_THREADS, event_loop.channel(), RESOLVER_CACHE);
+
+    let mut proxy = Proxy::new(server, resolver);
+    event_loop.run(&mut proxy).unwrap();
+}
@@ -0,0 +1,159 @@
+// The two messages a SOCKS5 client (RFC 1928) sends before its connection
+// becomes a tunnel, and the answers to them. First the client says which
+// ways of authenticating it knows:
+//
+// +-----+----------+------------------+
+// | VER | NMETHODS | METHODS          |
+// +-----+----------+------------------+
+// |  5  |    1     | 1 to 255 bytes   |
+// +-----+----------+------------------+
+//
+// The proxy picks one, and then the client asks for a target:
+//
+// +-----+-----+-----+------+----------+----------+
+// | VER | CMD | RSV | ATYP | DST.ADDR | DST.PORT |
+// +-----+-----+-----+------+----------+----------+
+// |  5  |  1  |  0  |  1   | variable |    2     |
+// +-----+-----+-----+------+----------+----------+
+//
+// The address is an IPv4 address, an IPv6 one, or a name preceded by its
+// length. Only the `CONNECT` command, and only clients asking for no
+// authentication at all, are supported. Like the rest of the examples'
+// protocol code, the parser does no I/O: it is given what was read so
+// far, and says whether that is enough.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str;
+
+const VERSION: u8 = 5;
+
+const METHOD_NO_AUTH: u8 = 0;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+
+const CMD_CONNECT: u8 = 1;
+
+const ATYP_IPV4: u8 = 1;
+const ATYP_NAME: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+// The reply codes
+pub const SUCCEEDED: u8 = 0;
+pub const GENERAL_FAILURE: u8 = 1;
+pub const HOST_UNREACHABLE: u8 = 4;
+pub const CONNECTION_REFUSED: u8 = 5;
+pub const COMMAND_NOT_SUPPORTED: u8 = 7;
+pub const ADDRESS_TYPE_NOT_SUPPORTED: u8 = 8;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Target {
+    Addr(SocketAddr),
+    // A name, to be resolved by the proxy
+    Name(String, u16),
+}
+
+// Parses the greeting at the start of `buf`. Returns its length, and
+// whether the client can go without authenticating, or `None` if it isn't
+// complete yet.
+pub fn parse_greeting(buf: &[u8]) -> Result<Option<(usize, bool)>, &'static str> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+
+    if buf[0] != VERSION {
+        return Err("not a SOCKS5 client");
+    }
+
+    let len = 2 + buf[1] as usize;
+
+    if buf.len() < len {
+        return Ok(None);
+    }
+
+    Ok(Some((len, buf[2..len].contains(&METHOD_NO_AUTH))))
+}
+
+// The answer to the greeting
+pub fn method(no_auth: bool) -> [u8; 2] {
+    if no_auth {
+        [VERSION, METHOD_NO_AUTH]
+    } else {
+        [VERSION, METHOD_NONE_ACCEPTABLE]
+    }
+}
+
+// Parses the request at the start of `buf`. Returns its length and the
+// target, `None` if it isn't complete yet, or the reply code to refuse
+// it with, and why.
+pub fn parse_request(buf: &[u8]) -> Result<Option<(usize, Target)>, (u8, &'static str)> {
+    if buf.len() < 4 {
+        return Ok(None);
+    }
+
+    if buf[0] != VERSION {
+        return Err((GENERAL_FAILURE, "not a SOCKS5 request"));
+    }
+
+    if buf[1] != CMD_CONNECT {
+        return Err((COMMAND_NOT_SUPPORTED, "command not supported"));
+    }
+
+    let (addr_len, start) = match buf[3] {
+        ATYP_IPV4 => (4, 4),
+        ATYP_IPV6 => (16, 4),
+        ATYP_NAME if buf.len() < 5 => return Ok(None),
+        ATYP_NAME => (buf[4] as usize, 5),
+        _ => return Err((ADDRESS_TYPE_NOT_SUPPORTED, "address type not supported")),
+    };
+
+    let len = start + addr_len + 2;
+
+    if buf.len() < len {
+        return Ok(None);
+    }
+
+    let addr = &buf[start..start + addr_len];
+    let port = (buf[len - 2] as u16) << 8 | buf[len - 1] as u16;
+
+    let target = match buf[3] {
+        ATYP_IPV4 => {
+            let ip = Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]);
+            Target::Addr(SocketAddr::new(IpAddr::V4(ip), port))
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(addr);
+            Target::Addr(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+        }
+        _ => {
+            match str::from_utf8(addr) {
+                Ok(name) if !name.is_empty() => Target::Name(name.to_string(), port),
+                _ => return Err((HOST_UNREACHABLE, "invalid target name")),
+            }
+        }
+    };
+
+    Ok(Some((len, target)))
+}
+
+// The answer to the request, with the address the proxy connected to the
+// target from when it succeeded
+pub fn reply(code: u8, bound: Option<SocketAddr>) -> Vec<u8> {
+    let mut reply = vec![VERSION, code, 0];
+
+    match bound.unwrap_or(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0)) {
+        SocketAddr::V4(addr) => {
+            reply.push(ATYP_IPV4);
+            reply.extend_from_slice(&addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            reply.push(ATYP_IPV6);
+            reply.extend_from_slice(&addr.ip().octets());
+        }
+    }
+
+    let port = bound.map(|addr| addr.port()).unwrap_or(0);
+    reply.push((port >> 8) as u8);
+    reply.push(port as u8);
+
+    reply
+}
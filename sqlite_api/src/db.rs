@@ -0,0 +1,158 @@
+// The database side: a thread of its own owns the SQLite connection, and
+// runs the queries the event loop sends it, in the order they come. The
+// results go back through the event loop's channel, tagged with the id
+// the loop gave the query.
+//
+// Queries waiting when the thread gets to them are run as a batch, in one
+// transaction, so that a burst of writes costs one sync to disk rather
+// than one each. A query failing in the batch doesn't undo the others.
+
+use mio;
+use rusqlite::{self, params, Connection, OptionalExtension};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The most queries run in one transaction
+const MAX_BATCH: usize = 64;
+
+pub enum Query {
+    List,
+    Get(i64),
+    Create(String),
+    Update(i64, String),
+    Delete(i64),
+}
+
+pub struct Note {
+    pub id: i64,
+    pub text: String,
+}
+
+pub enum Reply {
+    Notes(Vec<Note>),
+    Note(Option<Note>),
+    Created(i64),
+    // Whether there was a note to update or delete
+    Changed(bool),
+}
+
+pub struct Job {
+    // The correlation id, for the loop to find the request again
+    pub id: u64,
+    pub query: Query,
+}
+
+pub struct Done {
+    pub id: u64,
+    pub result: Result<Reply, String>,
+    // How long the query's batch took, waiting for the lock included
+    pub ms: u64,
+}
+
+pub fn open(path: &str) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+
+    // A query finding the database locked, by another process, waits a
+    // moment rather than fail right away
+    conn.busy_timeout(Duration::from_secs(2))?;
+    conn.execute_batch("PRAGMA journal_mode = WAL;
+                        CREATE TABLE IF NOT EXISTS notes (id INTEGER PRIMARY KEY, text TEXT NOT NULL);")?;
+
+    Ok(conn)
+}
+
+// Starts the thread. It stops once the returned sender is dropped, or the
+// loop is gone.
+pub fn start(conn: Connection, results: mio::Sender<Done>) -> Sender<Job> {
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || run(conn, &rx, &results));
+
+    tx
+}
+
+fn run(mut conn: Connection, jobs: &Receiver<Job>, results: &mio::Sender<Done>) {
+    // Blocks for the first query of a batch, then takes whatever else is
+    // waiting already
+    while let Ok(first) = jobs.recv() {
+        let mut batch = vec![first];
+
+        while batch.len() < MAX_BATCH {
+            match jobs.try_recv() {
+                Ok(job) => batch.push(job),
+                Err(_) => break,
+            }
+        }
+
+        let started = Instant::now();
+        let mut replies = Vec::with_capacity(batch.len());
+
+        let committed = conn.transaction().and_then(|tx| {
+            for job in &batch {
+                replies.push(execute(&tx, &job.query).map_err(|e| e.to_string()));
+            }
+
+            tx.commit()
+        });
+
+        let elapsed = started.elapsed();
+        let ms = elapsed.as_secs() * 1_000 + elapsed.subsec_millis() as u64;
+
+        // Nothing was written if the transaction failed, whatever the
+        // queries returned
+        if let Err(e) = committed {
+            replies = batch.iter().map(|_| Err(e.to_string())).collect();
+        }
+
+        for (job, result) in batch.into_iter().zip(replies) {
+            let mut done = Done {
+                id: job.id,
+                result: result,
+                ms: ms,
+            };
+
+            // A full channel means the loop is behind, the result waits for
+            // it
+            loop {
+                match results.send(done) {
+                    Ok(()) => break,
+                    Err(mio::NotifyError::Full(returned)) => {
+                        done = returned;
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    Err(_) => return,
+                }
+            }
+        }
+    }
+}
+
+fn execute(conn: &Connection, query: &Query) -> rusqlite::Result<Reply> {
+    match *query {
+        Query::List => {
+            let mut stmt = conn.prepare_cached("SELECT id, text FROM notes ORDER BY id")?;
+            let notes = stmt.query_map([], |row| Ok(Note { id: row.get(0)?, text: row.get(1)? }))?;
+
+            Ok(Reply::Notes(notes.collect::<rusqlite::Result<_>>()?))
+        }
+        Query::Get(id) => {
+            let mut stmt = conn.prepare_cached("SELECT id, text FROM notes WHERE id = ?1")?;
+            let note = stmt.query_row(params![id], |row| Ok(Note { id: row.get(0)?, text: row.get(1)? })).optional()?;
+
+            Ok(Reply::Note(note))
+        }
+        Query::Create(ref text) => {
+            conn.prepare_cached("INSERT INTO notes (text) VALUES (?1)")?.execute(params![text])?;
+            Ok(Reply::Created(conn.last_insert_rowid()))
+        }
+        Query::Update(id, ref text) => {
+            let changed = conn.prepare_cached("UPDATE notes SET text = ?2 WHERE id = ?1")?.execute(params![id, text])?;
+            Ok(Reply::Changed(changed > 0))
+        }
+        Query::Delete(id) => {
+            let changed = conn.prepare_cached("DELETE FROM notes WHERE id = ?1")?.execute(params![id])?;
+            Ok(Reply::Changed(changed > 0))
+        }
+    }
+}
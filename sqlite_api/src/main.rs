@@ -0,0 +1,379 @@
+extern crate acceptor;
+extern crate http_server;
+extern crate mio;
+extern crate rusqlite;
+
+mod db;
+
+use acceptor::{Acceptor, Factory};
+use db::{Done, Job, Query, Reply};
+use http_server::request::{self, Request};
+use http_server::Response;
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::mpsc::Sender;
+use std::{env, process};
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_CONNECTIONS: usize = 1_024;
+
+// Queries sent to the database and not answered yet, past that requests
+// get a 503: the database can't keep up, and queueing more only makes
+// every answer later
+const MAX_QUEUED: usize = 1_024;
+
+// A query not answered within this long gets its request a 503. It still
+// runs, there is no taking it back from the database thread, but its
+// result is dropped.
+const QUERY_TIMEOUT_MS: u64 = 5_000;
+
+const MAX_NOTE: usize = 4_096;
+
+// A notes API, kept in SQLite:
+//
+//     GET /notes               -> every note, one per line, after its id
+//     POST /notes              -> the id of the new note, the body
+//     GET /notes/<id>          -> the note's text
+//     PUT /notes/<id>          -> replaces the note's text with the body
+//     DELETE /notes/<id>       -> removes the note
+//
+// SQLite's API is synchronous: a query blocks the thread running it, on
+// the disk, or on a lock held by another process. So the queries don't
+// run on the event loop, but on a database thread. A request needing one
+// is parked: the query is sent to the thread with an id, the correlation
+// id, and the loop goes on with the other connections. The result comes
+// back through the loop's channel with the same id, which leads back to
+// the parked request, and its response is written.
+struct Api {
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+    db: Sender<Job>,
+    // The parked requests, by correlation id
+    queries: HashMap<u64, mio::Token>,
+    // The id the next query gets
+    next_id: u64,
+}
+
+impl Api {
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Api>) {
+        let (_, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+        if let Err(e) = res {
+            println!("encountered error while accepting connection; err={:?}", e);
+            event_loop.shutdown();
+        }
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<Api>, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            self.connections[token].read();
+        }
+
+        self.process(event_loop, token);
+        self.update(event_loop, token);
+    }
+
+    // Answers the complete requests read so far, until one is parked. The
+    // ones after it wait for it to be answered, so that responses go out in
+    // the order of the requests.
+    fn process(&mut self, event_loop: &mut mio::EventLoop<Api>, token: mio::Token) {
+        while !self.connections[token].closing && self.connections[token].parked.is_none() {
+            let request = match request::parse(&self.connections[token].buf) {
+                Ok(Some((request, len))) => {
+                    self.connections[token].buf.drain(..len);
+                    request
+                }
+                Ok(None) => return,
+                Err(reason) => {
+                    let conn = &mut self.connections[token];
+                    Response::text(400, reason).encode(false, &mut conn.out);
+                    conn.closing = true;
+                    return;
+                }
+            };
+
+            match route(&request) {
+                Ok(query) => self.query(event_loop, token, request, query),
+                Err(response) => self.respond(token, &request, response),
+            }
+        }
+    }
+
+    // Sends the query to the database thread, and parks the request until
+    // the result comes back
+    fn query(&mut self, event_loop: &mut mio::EventLoop<Api>, token: mio::Token, request: Request, query: Query) {
+        if self.queries.len() >= MAX_QUEUED {
+            return self.respond(token, &request, Response::text(503, "too many queries waiting, try again later"));
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        // The thread only stops once the sender is dropped
+        self.db.send(Job { id: id, query: query }).unwrap();
+        self.queries.insert(id, token);
+
+        self.connections[token].parked = Some(Parked {
+            id: id,
+            request: request,
+            timeout: event_loop.timeout_ms(id, QUERY_TIMEOUT_MS).unwrap(),
+        });
+    }
+
+    // Answers the parked request, and goes on with the ones behind it
+    fn complete(&mut self, event_loop: &mut mio::EventLoop<Api>, token: mio::Token, response: Response) {
+        let parked = self.connections[token].parked.take().unwrap();
+        self.queries.remove(&parked.id);
+
+        println!("{} {}; status={}", parked.request.method, parked.request.path, response.status);
+
+        self.respond(token, &parked.request, response);
+        self.process(event_loop, token);
+        self.update(event_loop, token);
+    }
+
+    fn respond(&mut self, token: mio::Token, request: &Request, response: Response) {
+        let conn = &mut self.connections[token];
+        let keep_alive = request.keep_alive();
+
+        response.encode(keep_alive, &mut conn.out);
+        conn.closing = !keep_alive;
+    }
+
+    // Writes what it can, then reregisters the connection, or removes it
+    // once it is done
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Api>, token: mio::Token) {
+        let conn = &mut self.connections[token];
+        conn.write();
+
+        if conn.closed || conn.closing && conn.out.is_empty() {
+            // A client may go away with its query still running, the result
+            // is dropped when it comes
+            if let Some(parked) = conn.parked.take() {
+                event_loop.clear_timeout(parked.timeout);
+                self.queries.remove(&parked.id);
+            }
+
+            self.connections.remove(token);
+            return;
+        }
+
+        event_loop.reregister(&conn.socket, token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+impl mio::Handler for Api {
+    // The correlation id of the query taking too long
+    type Timeout = u64;
+    type Message = Done;
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Api>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ => self.connection_ready(event_loop, token, events),
+        }
+    }
+
+    // A query is done
+    fn notify(&mut self, event_loop: &mut mio::EventLoop<Api>, done: Done) {
+        // Unless its request timed out, or its client went away
+        let token = match self.queries.get(&done.id) {
+            Some(&token) => token,
+            None => return,
+        };
+
+        event_loop.clear_timeout(self.connections[token].parked.as_ref().unwrap().timeout);
+
+        let response = match done.result {
+            Ok(reply) => respond(reply),
+            Err(e) => {
+                println!("query failed; id={}; err={}", done.id, e);
+                Response::text(500, "query failed")
+            }
+        };
+
+        if done.ms > 100 {
+            println!("slow query; id={}; time={}ms", done.id, done.ms);
+        }
+
+        self.complete(event_loop, token, response);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Api>, id: u64) {
+        let token = match self.queries.get(&id) {
+            Some(&token) => token,
+            None => return,
+        };
+
+        println!("query timed out; id={}", id);
+        self.complete(event_loop, token, Response::text(503, "the database is taking too long"));
+    }
+}
+
+// The query a request needs, or the response when it needs none
+fn route(request: &Request) -> Result<Query, Response> {
+    let mut segments = request.path[1..].split('/');
+
+    let id = match (segments.next(), segments.next(), segments.next()) {
+        (Some("notes"), None, None) => None,
+        (Some("notes"), Some(id), None) => match id.parse() {
+            Ok(id) => Some(id),
+            Err(_) => return Err(Response::text(404, "Not Found")),
+        },
+        _ => return Err(Response::text(404, "Not Found")),
+    };
+
+    let text = || match String::from_utf8(request.body.clone()) {
+        Ok(ref text) if text.trim().is_empty() => Err(Response::text(400, "the note is empty")),
+        Ok(ref text) if text.len() > MAX_NOTE => Err(Response::text(413, "the note is too long")),
+        Ok(text) => Ok(text.trim().to_string()),
+        Err(_) => Err(Response::text(400, "the note is not valid UTF-8")),
+    };
+
+    match (&request.method[..], id) {
+        ("GET", None) => Ok(Query::List),
+        ("POST", None) => Ok(Query::Create(text()?)),
+        (_, None) => Err(Response::text(405, "Method Not Allowed").header("Allow", "GET, POST")),
+        ("GET", Some(id)) => Ok(Query::Get(id)),
+        ("PUT", Some(id)) => Ok(Query::Update(id, text()?)),
+        ("DELETE", Some(id)) => Ok(Query::Delete(id)),
+        (_, Some(_)) => Err(Response::text(405, "Method Not Allowed").header("Allow", "GET, PUT, DELETE")),
+    }
+}
+
+fn respond(reply: Reply) -> Response {
+    match reply {
+        Reply::Notes(notes) => {
+            let lines: Vec<String> = notes.iter().map(|note| format!("{} {}", note.id, note.text)).collect();
+            Response::text(200, &lines.join("\n"))
+        }
+        Reply::Note(Some(note)) => Response::text(200, &note.text),
+        Reply::Note(None) | Reply::Changed(false) => Response::text(404, "no such note"),
+        Reply::Created(id) => Response::text(201, &id.to_string()),
+        Reply::Changed(true) => Response::new(204),
+    }
+}
+
+// A request waiting for its query
+struct Parked {
+    id: u64,
+    request: Request,
+    timeout: mio::Timeout,
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Read, and not yet parsed into requests
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    parked: Option<Parked>,
+    // Set once the connection should be closed after writing `out`
+    closing: bool,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            buf: vec![],
+            out: vec![],
+            parked: None,
+            closing: false,
+            closed: false,
+        }
+    }
+
+    fn read(&mut self) {
+        let mut chunk = [0; 4_096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut chunk) {
+                Ok(Some(0)) => {
+                    // What was asked already is still answered
+                    self.closing = true;
+                    return;
+                }
+                Ok(Some(n)) => self.buf.extend(&chunk[..n]),
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:8083".to_string()).parse().unwrap();
+    let path = args.next().unwrap_or("notes.db".to_string());
+
+    let conn = match db::open(&path) {
+        Ok(conn) => conn,
+        Err(e) => {
+            println!("failed to open the database; path={:?}; err={}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    println!("running notes API; addr={:?}; db={:?}", address, path);
+
+    // Token `0` is reserved for the server socket. Tokens 1+ are used for
+    // client connections.
+    let mut api = Api {
+        acceptor: acceptor,
+        connections: Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS),
+        db: db::start(conn, event_loop.channel()),
+        queries: HashMap::new(),
+        next_id: 1,
+    };
+
+    event_loop.run(&mut api).unwrap();
+}
@@ -0,0 +1,256 @@
+extern crate mio;
+extern crate bytes;
+extern crate libc;
+extern crate rand;
+
+use mio::IpAddr;
+use mio::udp::*;
+use bytes::SliceBuf;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::unix::io::AsRawFd;
+use std::{env, io, mem, str};
+
+const SOCKET: mio::Token = mio::Token(0);
+
+const SSDP_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const SSDP_PORT: u16 = 1900;
+
+// UPnP caps the MX header at 5 seconds, larger values are treated as 5.
+const MAX_MX: u64 = 5;
+
+const DEVICE_TYPE: &str = "urn:schemas-upnp-org:device:Basic:1";
+
+const MAX_MESSAGE: usize = 2_048;
+
+// Replies waiting for their delay at once, at most. Searches past this are
+// ignored: a legitimate control point searches a few times, not thousands,
+// and anyone on the network can send M-SEARCHes.
+const MAX_PENDING: usize = 1_024;
+
+// A response waiting for its randomized delay to elapse. The event loop
+// timeout carries everything needed to send it.
+#[derive(Debug)]
+struct Reply {
+    addr: SocketAddr,
+    st: String,
+}
+
+struct Responder {
+    socket: UdpSocket,
+    uuid: String,
+    location: String,
+    // Replies waiting for their delay
+    pending: usize,
+}
+
+impl Responder {
+    fn new(socket: UdpSocket, uuid: String, location: String) -> Responder {
+        Responder {
+            socket: socket,
+            uuid: uuid,
+            location: location,
+            pending: 0,
+        }
+    }
+
+    fn receive(&mut self, event_loop: &mut mio::EventLoop<Responder>) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event.
+        loop {
+            let mut buf = Vec::with_capacity(MAX_MESSAGE);
+
+            let addr = match self.socket.recv_from(&mut buf) {
+                Ok(Some(addr)) => addr,
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            let search = match parse_search(&buf) {
+                Some(search) => search,
+                // NOTIFY messages from other devices and anything malformed
+                None => continue,
+            };
+
+            if !self.matches(&search.st) {
+                continue;
+            }
+
+            if self.pending == MAX_PENDING {
+                println!("too many replies pending, ignoring M-SEARCH; addr={:?}", addr);
+                continue;
+            }
+
+            // Devices must wait a random amount of time between 0 and MX
+            // seconds before responding so that a control point isn't
+            // flooded by every device on the network at once.
+            let delay = rand::random::<u64>() % (search.mx * 1_000 + 1);
+
+            println!("received M-SEARCH; addr={:?}; st={}; delay={}ms", addr, search.st, delay);
+
+            let reply = Reply {
+                addr: addr,
+                st: search.st,
+            };
+
+            match event_loop.timeout_ms(reply, delay) {
+                Ok(_) => self.pending += 1,
+                Err(_) => println!("timer full, ignoring M-SEARCH; addr={:?}", addr),
+            }
+        }
+    }
+
+    fn matches(&self, st: &str) -> bool {
+        st == "ssdp:all" ||
+            st == "upnp:rootdevice" ||
+            st == DEVICE_TYPE ||
+            st == self.uuid
+    }
+
+    fn reply(&mut self, reply: Reply) {
+        // An `ssdp:all` search is answered with the root device type
+        let st = if reply.st == "ssdp:all" { "upnp:rootdevice" } else { &reply.st[..] };
+
+        let usn = if st == self.uuid {
+            self.uuid.clone()
+        } else {
+            format!("{}::{}", self.uuid, st)
+        };
+
+        let response = format!(
+            "HTTP/1.1 200 OK\r\n\
+             CACHE-CONTROL: max-age=1800\r\n\
+             EXT:\r\n\
+             LOCATION: {}\r\n\
+             SERVER: Linux UPnP/1.1 mio-ssdp/0.1\r\n\
+             ST: {}\r\n\
+             USN: {}\r\n\
+             \r\n",
+            self.location, st, usn);
+
+        println!("sending response; addr={:?}; st={}", reply.addr, st);
+
+        // Responses are unicast back to the address the search came from
+        match self.socket.send_to(&mut SliceBuf::wrap(response.as_bytes()), &reply.addr) {
+            Ok(Some(())) => {}
+            Ok(None) => println!("the socket wasn't actually ready, dropping response"),
+            Err(e) => println!("failed to send response; addr={:?}; err={:?}", reply.addr, e),
+        }
+    }
+}
+
+impl mio::Handler for Responder {
+    type Timeout = Reply;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Responder>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, SOCKET);
+        assert!(events.is_readable(), "unexpected events; events={:?}", events);
+
+        self.receive(event_loop);
+    }
+
+    fn timeout(&mut self, _: &mut mio::EventLoop<Responder>, reply: Reply) {
+        self.pending -= 1;
+        self.reply(reply);
+    }
+}
+
+/*
+ *
+ * ===== M-SEARCH =====
+ *
+ */
+
+struct Search {
+    st: String,
+    mx: u64,
+}
+
+// SSDP messages are HTTP requests sent over UDP, one per datagram.
+fn parse_search(packet: &[u8]) -> Option<Search> {
+    let packet = str::from_utf8(packet).ok()?;
+    let mut lines = packet.split("\r\n");
+
+    if lines.next()? != "M-SEARCH * HTTP/1.1" {
+        return None;
+    }
+
+    let mut man = None;
+    let mut mx = None;
+    let mut st = None;
+
+    for line in lines {
+        if line.is_empty() {
+            break;
+        }
+
+        let colon = line.find(':')?;
+        let name = line[..colon].trim().to_lowercase();
+        let value = line[colon + 1..].trim();
+
+        match &name[..] {
+            "man" => man = Some(value.trim_matches('"').to_string()),
+            "mx" => mx = value.parse::<u64>().ok(),
+            "st" => st = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    if man.as_ref().map(|s| &s[..]) != Some("ssdp:discover") {
+        return None;
+    }
+
+    Some(Search {
+        st: st?,
+        // A search without MX is a unicast search, answered right away
+        mx: std::cmp::min(mx.unwrap_or(0), MAX_MX),
+    })
+}
+
+// Other UPnP stacks (minissdpd, media servers) are most likely already bound
+// to port 1900. Setting SO_REUSEADDR before binding lets us share it.
+fn bind_shared(port: u16) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::v4()?;
+
+    let on: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_REUSEADDR,
+            &on as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    socket.bind(&SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port)))?;
+    Ok(socket)
+}
+
+fn main() {
+    let location = env::args().nth(1)
+        .unwrap_or("http://127.0.0.1:8080/description.xml".to_string());
+
+    // Every device is identified by a UUID that should stay the same across
+    // restarts. A random one is fine for a demo.
+    let uuid = format!("uuid:{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+                       rand::random::<u32>(),
+                       rand::random::<u16>(),
+                       rand::random::<u16>(),
+                       rand::random::<u16>(),
+                       rand::random::<u64>() & 0xffff_ffff_ffff);
+
+    let socket = bind_shared(SSDP_PORT).unwrap();
+    socket.join_multicast(&IpAddr::V4(SSDP_GROUP)).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&socket, SOCKET, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+
+    let mut responder = Responder::new(socket, uuid, location);
+
+    println!("running ssdp responder; usn={}", responder.uuid);
+    event_loop.run(&mut responder).unwrap();
+}
@@ -0,0 +1,476 @@
+extern crate mio;
+extern crate openssl;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use openssl::ssl::{self, ErrorCode, HandshakeError, MidHandshakeSslStream, SslAcceptor, SslFiletype, SslMethod, SslStream};
+use std::net::SocketAddr;
+use std::{env, mem};
+
+const SERVER: mio::Token = mio::Token(0);
+
+const HOSTNAME: &str = "mio.starttls";
+
+// RFC 5321 limits a line, including the CRLF, to 1000 bytes
+const MAX_LINE: usize = 1_000;
+
+// An SMTP-like server, speaking just the part of the protocol around
+// STARTTLS (RFC 3207). The connection starts in plaintext, and the client
+// asks for it to be upgraded:
+//
+//     S: 220 mio.starttls ESMTP ready
+//     C: EHLO client
+//     S: 250-mio.starttls Hello client
+//     S: 250 STARTTLS
+//     C: STARTTLS
+//     S: 220 Ready to start TLS
+//
+// and from then on, it carries on over TLS, starting over with an EHLO.
+// The socket stays registered as it is, with the same token: only what
+// reads and writes it changes, from the socket itself to OpenSSL.
+struct Server {
+    server: TcpListener,
+    acceptor: SslAcceptor,
+    connections: Slab<Connection>,
+}
+
+impl Server {
+    fn new(server: TcpListener, acceptor: SslAcceptor) -> Server {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Server {
+            server: server,
+            acceptor: acceptor,
+            connections: slab,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Server>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        let token = match self.connections.insert_with(|token| Connection::new(socket, token)) {
+            Some(token) => token,
+            None => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        let conn = &self.connections[token];
+
+        event_loop.register_opt(conn.stream.socket(), token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+impl mio::Handler for Server {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, _: mio::EventSet) {
+        if token == SERVER {
+            return self.accept(event_loop);
+        }
+
+        let conn = &mut self.connections[token];
+        conn.ready(&self.acceptor);
+
+        if conn.closed {
+            println!("connection closed; token={:?}", token);
+            self.connections.remove(token);
+            return;
+        }
+
+        // The same socket as ever, whether it is read by OpenSSL or not
+        event_loop.reregister(conn.stream.socket(), token, conn.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+// What the connection is read and written through
+enum Stream {
+    Plain(TcpStream),
+    Handshaking(MidHandshakeSslStream<TcpStream>),
+    Tls(SslStream<TcpStream>),
+    // The handshake failed, the connection is gone
+    Failed,
+}
+
+impl Stream {
+    fn socket(&self) -> &TcpStream {
+        match *self {
+            Stream::Plain(ref socket) => socket,
+            Stream::Handshaking(ref stream) => stream.get_ref(),
+            Stream::Tls(ref stream) => stream.get_ref(),
+            Stream::Failed => panic!("no socket after a failed handshake"),
+        }
+    }
+}
+
+struct Connection {
+    stream: Stream,
+    token: mio::Token,
+    // Bytes read but not yet processed, at most one incomplete line
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    // Set once the client asked for STARTTLS, until the reply is written
+    // and the handshake can start
+    upgrading: bool,
+    // Set once the client said EHLO, STARTTLS has to come after it
+    greeted: bool,
+    // The readiness OpenSSL waits for, to go on with the handshake, a read
+    // or a write. Plain sockets read when readable, and write when
+    // writable.
+    handshake_wants: mio::EventSet,
+    read_wants: mio::EventSet,
+    write_wants: mio::EventSet,
+    // Set when the connection should be closed once `out` is flushed
+    quit: bool,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        let mut conn = Connection {
+            stream: Stream::Plain(socket),
+            token: token,
+            buf: Vec::with_capacity(MAX_LINE),
+            out: vec![],
+            upgrading: false,
+            greeted: false,
+            handshake_wants: mio::EventSet::readable(),
+            read_wants: mio::EventSet::readable(),
+            write_wants: mio::EventSet::writable(),
+            quit: false,
+            closed: false,
+        };
+
+        conn.reply(&format!("220 {} ESMTP ready", HOSTNAME));
+        conn
+    }
+
+    fn ready(&mut self, acceptor: &SslAcceptor) {
+        // Goes around a second time when the reply to STARTTLS was written
+        // right away: the handshake starts, and once it is done, the client
+        // may already have sent its next command along with its last
+        // handshake message.
+        loop {
+            self.handshake(acceptor);
+
+            if self.closed || self.is_handshaking() {
+                return;
+            }
+
+            // A read or a write may each be waiting for either readiness,
+            // both are tried whatever the socket is ready for
+            self.read();
+            self.write();
+
+            if self.closed || !self.upgrading || !self.out.is_empty() {
+                return;
+            }
+        }
+    }
+
+    // Starts the handshake once the reply to STARTTLS is written, and goes
+    // on with it as far as the socket allows
+    fn handshake(&mut self, acceptor: &SslAcceptor) {
+        let result = match mem::replace(&mut self.stream, Stream::Failed) {
+            Stream::Plain(socket) if self.upgrading && self.out.is_empty() => {
+                self.upgrading = false;
+                acceptor.accept(socket)
+            }
+            Stream::Handshaking(stream) => stream.handshake(),
+            stream => {
+                self.stream = stream;
+                return;
+            }
+        };
+
+        match result {
+            Ok(stream) => {
+                println!("upgraded to TLS; token={:?}; version={}", self.token, stream.ssl().version_str());
+                self.stream = Stream::Tls(stream);
+            }
+            Err(HandshakeError::WouldBlock(stream)) => {
+                self.handshake_wants = wants(stream.error());
+                self.stream = Stream::Handshaking(stream);
+            }
+            Err(HandshakeError::Failure(stream)) => {
+                println!("handshake failed; token={:?}; err={}", self.token, stream.error());
+                self.closed = true;
+            }
+            Err(HandshakeError::SetupFailure(e)) => {
+                println!("failed to set up the handshake; token={:?}; err={}", self.token, e);
+                self.closed = true;
+            }
+        }
+    }
+
+    fn is_handshaking(&self) -> bool {
+        matches!(self.stream, Stream::Handshaking(_))
+    }
+
+    fn is_tls(&self) -> bool {
+        matches!(self.stream, Stream::Tls(_))
+    }
+
+    fn read(&mut self) {
+        let mut chunk = [0; 4_096];
+
+        // The socket is registered as edge triggered, drain it, or OpenSSL
+        // until it needs more from the socket. Nothing is read while the
+        // reply to STARTTLS is being written: what follows is the client's
+        // handshake, for OpenSSL to read.
+        while !self.upgrading && !self.closed {
+            let n = match self.read_some(&mut chunk) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => n,
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; token={:?}; err={}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            };
+
+            self.buf.extend(&chunk[..n]);
+
+            while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.buf.drain(..pos + 1).collect();
+
+                if !self.quit {
+                    self.command(&line);
+                }
+
+                // Anything the client sent after STARTTLS, before the
+                // handshake, was sent in plaintext and could have been
+                // injected by anyone on the way. It is thrown away rather
+                // than taken as having come over TLS.
+                if self.upgrading {
+                    if !self.buf.is_empty() {
+                        println!("discarding plaintext sent after STARTTLS; token={:?}; len={}", self.token, self.buf.len());
+                    }
+
+                    self.buf.clear();
+                    return;
+                }
+            }
+
+            if self.buf.len() > MAX_LINE {
+                println!("line too long, closing connection");
+                self.reply("500 Line too long");
+                self.quit = true;
+                self.buf.clear();
+            }
+        }
+    }
+
+    // Reads from the socket, or through OpenSSL once upgraded. `None` if
+    // it would block.
+    fn read_some(&mut self, chunk: &mut [u8]) -> Result<Option<usize>, String> {
+        match self.stream {
+            Stream::Plain(ref mut socket) => socket.try_read(chunk).map_err(|e| format!("{:?}", e)),
+            Stream::Tls(ref mut stream) => {
+                self.read_wants = mio::EventSet::readable();
+
+                match stream.ssl_read(chunk) {
+                    Ok(n) => Ok(Some(n)),
+                    // The client sent its close_notify
+                    Err(ref e) if e.code() == ErrorCode::ZERO_RETURN => Ok(Some(0)),
+                    Err(ref e) if e.code() == ErrorCode::WANT_READ || e.code() == ErrorCode::WANT_WRITE => {
+                        self.read_wants = wants(e);
+                        Ok(None)
+                    }
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn command(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end();
+
+        println!("received command; token={:?}; tls={}; line={:?}", self.token, self.is_tls(), line);
+
+        let (verb, arg) = match line.find(' ') {
+            Some(pos) => (&line[..pos], line[pos + 1..].trim()),
+            None => (line, ""),
+        };
+
+        match &verb.to_uppercase()[..] {
+            "EHLO" => {
+                self.greeted = true;
+
+                // STARTTLS is only offered until it is done
+                if self.is_tls() {
+                    self.reply(&format!("250 {} Hello {}", HOSTNAME, arg));
+                } else {
+                    self.reply(&format!("250-{} Hello {}", HOSTNAME, arg));
+                    self.reply("250 STARTTLS");
+                }
+            }
+            "HELO" => {
+                self.greeted = true;
+                self.reply(&format!("250 {} Hello {}", HOSTNAME, arg));
+            }
+            "STARTTLS" => {
+                if self.is_tls() {
+                    return self.reply("503 TLS already active");
+                }
+
+                if !self.greeted {
+                    return self.reply("503 Send EHLO first");
+                }
+
+                if !arg.is_empty() {
+                    return self.reply("501 Syntax: STARTTLS");
+                }
+
+                self.reply("220 Ready to start TLS");
+
+                // The client starts over once the connection is upgraded,
+                // nothing it said before is known anymore
+                self.greeted = false;
+                self.upgrading = true;
+            }
+            "RSET" | "NOOP" => self.reply("250 OK"),
+            "QUIT" => {
+                self.reply(&format!("221 {} closing connection", HOSTNAME));
+                self.quit = true;
+            }
+            // Mail is only taken over TLS. Which it is just an example of,
+            // taking it is for the SMTP sink.
+            "MAIL" | "RCPT" | "DATA" if !self.is_tls() => self.reply("530 Must issue a STARTTLS command first"),
+            "MAIL" | "RCPT" | "DATA" => self.reply("502 Command not implemented"),
+            _ => self.reply("500 Command not recognized"),
+        }
+    }
+
+    fn reply(&mut self, line: &str) {
+        self.out.extend(line.as_bytes());
+        self.out.extend(b"\r\n");
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.write_some() {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; token={:?}; err={}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        if self.quit {
+            // A client expects a close_notify before the connection is
+            // closed, without it the end of the session could have been
+            // forged. Whether it is written out or not, the connection is
+            // closed.
+            if let Stream::Tls(ref mut stream) = self.stream {
+                let _ = stream.shutdown();
+            }
+
+            self.closed = true;
+        }
+    }
+
+    // Writes to the socket, or through OpenSSL once upgraded. `None` if it
+    // would block. A TLS write that would block has to be retried with the
+    // same data, which it is, as nothing is taken off `out` until written.
+    fn write_some(&mut self) -> Result<Option<usize>, String> {
+        match self.stream {
+            Stream::Plain(ref mut socket) => socket.try_write(&self.out).map_err(|e| format!("{:?}", e)),
+            Stream::Tls(ref mut stream) => {
+                self.write_wants = mio::EventSet::writable();
+
+                match stream.ssl_write(&self.out) {
+                    Ok(n) => Ok(Some(n)),
+                    Err(ref e) if e.code() == ErrorCode::WANT_READ || e.code() == ErrorCode::WANT_WRITE => {
+                        self.write_wants = wants(e);
+                        Ok(None)
+                    }
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.is_handshaking() {
+            return self.handshake_wants;
+        }
+
+        // Only the reply to STARTTLS is left to write, then the handshake
+        // starts
+        if self.upgrading {
+            return mio::EventSet::writable();
+        }
+
+        if self.out.is_empty() {
+            self.read_wants
+        } else {
+            self.read_wants | self.write_wants
+        }
+    }
+}
+
+// The readiness OpenSSL is waiting for, when it can't go on
+fn wants(e: &ssl::Error) -> mio::EventSet {
+    if e.code() == ErrorCode::WANT_WRITE {
+        mio::EventSet::writable()
+    } else {
+        mio::EventSet::readable()
+    }
+}
+
+fn acceptor(cert: &str, key: &str) -> SslAcceptor {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    builder.set_certificate_chain_file(cert).unwrap();
+    builder.set_private_key_file(key, SslFiletype::PEM).unwrap();
+    builder.check_private_key().unwrap();
+    builder.build()
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:2587".to_string()).parse().unwrap();
+    let cert = args.next().unwrap_or("cert.pem".to_string());
+    let key = args.next().unwrap_or("key.pem".to_string());
+
+    let acceptor = acceptor(&cert, &key);
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    println!("running STARTTLS server; addr={:?}; cert={}", address, cert);
+
+    let mut server = Server::new(server, acceptor);
+    event_loop.run(&mut server).unwrap();
+}
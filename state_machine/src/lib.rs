@@ -0,0 +1,121 @@
+// A macro writing the state enum a protocol example keeps per connection,
+// from a description of its states and the events moving it between them.
+// Every example repeats the same code around such an enum: accessors
+// panicking when the connection is in another state, the
+// `mem::replace` dance to move a buffer from one state to the next, and a
+// match giving the readiness each state waits on. The macro writes all of
+// it, leaving only what the protocol does:
+//
+//     state_machine! {
+//         #[derive(Debug)]
+//         enum State {
+//             Reading(Vec<u8>) as read_buf: readable,
+//             Writing(Cursor<Vec<u8>>) as write_buf: writable,
+//             Closed: none,
+//         }
+//
+//         placeholder Closed;
+//
+//         events {
+//             fn line_read(&mut self) {
+//                 State::Reading(buf) => State::Writing(Cursor::new(buf)),
+//             }
+//         }
+//     }
+//
+// For each state, the variant, with its data if it has any, and the
+// readiness it waits on: `readable`, `writable`, `none`, or several of
+// them joined with `|`. `interest` returns it, for registering the socket,
+// and `name` the state's name, for logging. A state with data can name an
+// accessor with `as`, giving a mutable reference to the data and panicking
+// in any other state.
+//
+// Each event is a method taking the state by value: the arms match the
+// state the machine is in, binding its data, guards included, and
+// evaluate to the next state. An arm can evaluate to different states depending on the data,
+// the same one included, and call other events on a state it built. An
+// event arriving in a state none of its arms match panics, leaving the
+// machine where it was.
+//
+// While an event runs, and if it panics, the machine is in the
+// placeholder state, which has no data.
+
+pub extern crate mio;
+
+#[macro_export]
+macro_rules! state_machine {
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $name:ident {
+            $(
+                $(#[$state_attr:meta])*
+                $state:ident $(($data:ty) $(as $get:ident)?)? : $($interest:ident)|+
+            ),+ $(,)?
+        }
+
+        placeholder $placeholder:ident;
+
+        events {
+            $(
+                $(#[$event_attr:meta])*
+                fn $event:ident(&mut self $(, $arg:ident: $arg_ty:ty)*) {
+                    $($from:pat $(if $guard:expr)? => $to:expr),+ $(,)?
+                }
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        $vis enum $name {
+            $(
+                $(#[$state_attr])*
+                $state $(($data))?,
+            )+
+        }
+
+        #[allow(dead_code)]
+        impl $name {
+            // The readiness the connection waits on in this state
+            pub fn interest(&self) -> $crate::mio::EventSet {
+                match *self {
+                    $(
+                        $name::$state { .. } => $($crate::mio::EventSet::$interest())|+,
+                    )+
+                }
+            }
+
+            pub fn name(&self) -> &'static str {
+                match *self {
+                    $(
+                        $name::$state { .. } => stringify!($state),
+                    )+
+                }
+            }
+
+            $($($(
+                pub fn $get(&mut self) -> &mut $data {
+                    match *self {
+                        $name::$state(ref mut data) => data,
+                        ref state => panic!(concat!("not in the ", stringify!($state), " state; state={}"), state.name()),
+                    }
+                }
+            )?)?)+
+
+            $(
+                $(#[$event_attr])*
+                pub fn $event(&mut self $(, $arg: $arg_ty)*) {
+                    #[allow(unreachable_patterns)]
+                    let next = match ::std::mem::replace(self, $name::$placeholder) {
+                        $($from $(if $guard)? => $to,)+
+                        state => {
+                            let was = state.name();
+                            *self = state;
+                            panic!(concat!("unexpected event; event=", stringify!($event), "; state={}"), was);
+                        }
+                    };
+
+                    *self = next;
+                }
+            )*
+        }
+    };
+}
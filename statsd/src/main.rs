@@ -0,0 +1,200 @@
+extern crate mio;
+extern crate bytes;
+
+use mio::udp::*;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const FLUSH_MS: u64 = 10_000;
+
+// Large enough for any datagram that isn't fragmented on a typical network
+const MAX_DATAGRAM: usize = 1_500;
+
+// The most datagrams handled per readiness event. Under a constant flood
+// the socket never runs dry, and reading until it does would keep the
+// event loop from ever getting to the flush timer.
+const MAX_BATCH: usize = 256;
+
+// The aggregates for the current flush interval
+#[derive(Default)]
+struct Metrics {
+    counters: HashMap<String, f64>,
+    // Gauges keep their value across flushes
+    gauges: HashMap<String, f64>,
+    timers: HashMap<String, Vec<f64>>,
+    sets: HashMap<String, HashSet<String>>,
+    packets: u64,
+    invalid: u64,
+}
+
+impl Metrics {
+    // Records a single metric line:
+    //
+    // <name>:<value>|<type>[|@<sample rate>]
+    //
+    // where the type is `c` (counter), `g` (gauge), `ms` (timer) or `s`
+    // (set). Returns false if the line is malformed.
+    fn record(&mut self, line: &str) -> bool {
+        let (name, rest) = match line.find(':') {
+            Some(pos) => (&line[..pos], &line[pos + 1..]),
+            None => return false,
+        };
+
+        let mut fields = rest.split('|');
+        let value = fields.next().unwrap_or("");
+        let kind = fields.next().unwrap_or("");
+
+        // A counter sampled at 0.1 was only sent for one event in ten
+        let rate = match fields.next() {
+            Some(rate) if rate.starts_with('@') => match rate[1..].parse::<f64>() {
+                Ok(rate) if rate > 0.0 && rate <= 1.0 => rate,
+                _ => return false,
+            },
+            Some(_) => return false,
+            None => 1.0,
+        };
+
+        if name.is_empty() {
+            return false;
+        }
+
+        if kind == "s" {
+            self.sets.entry(name.to_string()).or_default().insert(value.to_string());
+            return true;
+        }
+
+        // `NaN` and `inf` parse, but can't be summed or sorted into anything
+        // meaningful
+        let n = match value.parse::<f64>() {
+            Ok(n) if n.is_finite() => n,
+            _ => return false,
+        };
+
+        match kind {
+            "c" => *self.counters.entry(name.to_string()).or_insert(0.0) += n / rate,
+            "g" => {
+                let gauge = self.gauges.entry(name.to_string()).or_insert(0.0);
+
+                // A leading sign adjusts the gauge instead of setting it
+                if value.starts_with('+') || value.starts_with('-') {
+                    *gauge += n;
+                } else {
+                    *gauge = n;
+                }
+            }
+            "ms" => self.timers.entry(name.to_string()).or_default().push(n),
+            _ => return false,
+        }
+
+        true
+    }
+
+    // Prints a summary of the interval and starts the next one
+    fn flush(&mut self) {
+        let secs = FLUSH_MS as f64 / 1_000.0;
+
+        println!("flush; packets={}; invalid={}", self.packets, self.invalid);
+
+        for (name, value) in sorted(&self.counters) {
+            println!("  counter {} = {} ({:.2}/s)", name, value, value / secs);
+        }
+
+        for (name, value) in sorted(&self.gauges) {
+            println!("  gauge {} = {}", name, value);
+        }
+
+        for (name, values) in sorted(&self.timers) {
+            let mut values = values.clone();
+            values.sort_by(|a, b| a.total_cmp(b));
+
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            let p90 = values[(values.len() * 9 / 10).min(values.len() - 1)];
+
+            println!("  timer {} count={} min={} max={} mean={:.2} p90={}",
+                     name, values.len(), values[0], values[values.len() - 1], mean, p90);
+        }
+
+        for (name, values) in sorted(&self.sets) {
+            println!("  set {} unique={}", name, values.len());
+        }
+
+        self.counters.clear();
+        self.timers.clear();
+        self.sets.clear();
+        self.packets = 0;
+        self.invalid = 0;
+    }
+}
+
+fn sorted<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+struct Statsd {
+    socket: UdpSocket,
+    // Reused for every datagram, so receiving doesn't allocate
+    buf: Vec<u8>,
+    metrics: Metrics,
+}
+
+impl mio::Handler for Statsd {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, _: &mut mio::EventLoop<Statsd>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, SERVER);
+        assert!(events.is_readable(), "unexpected events; events={:?}", events);
+
+        // The socket is registered as level triggered, whatever is left
+        // after this batch is picked up on the next loop iteration.
+        for _ in 0..MAX_BATCH {
+            self.buf.clear();
+
+            match self.socket.recv_from(&mut self.buf) {
+                Ok(Some(_)) => {}
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            }
+
+            self.metrics.packets += 1;
+
+            // Clients batch several metrics per datagram, one per line
+            for line in String::from_utf8_lossy(&self.buf).lines() {
+                if !line.is_empty() && !self.metrics.record(line) {
+                    self.metrics.invalid += 1;
+                }
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Statsd>, _: ()) {
+        self.metrics.flush();
+        event_loop.timeout_ms((), FLUSH_MS).unwrap();
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:8125".to_string())
+        .parse().unwrap();
+
+    let socket = UdpSocket::bound(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&socket, SERVER, mio::EventSet::readable(), mio::PollOpt::level()).unwrap();
+    event_loop.timeout_ms((), FLUSH_MS).unwrap();
+
+    let mut statsd = Statsd {
+        socket: socket,
+        buf: Vec::with_capacity(MAX_DATAGRAM),
+        metrics: Metrics::default(),
+    };
+
+    println!("running statsd server; addr={:?}; flush interval={}ms", address, FLUSH_MS);
+    event_loop.run(&mut statsd).unwrap();
+}
@@ -0,0 +1,606 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::{env, mem};
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Limits on the size of a frame's command and headers, and on its body
+const MAX_HEADERS: usize = 8 * 1_024;
+const MAX_BODY: usize = 1_024 * 1_024;
+
+// A client that doesn't read fast enough is disconnected once this much
+// output is queued for it, rather than letting its queue grow forever.
+const MAX_QUEUED: usize = 4 * 1_024 * 1_024;
+
+struct Stomp {
+    acceptor: Acceptor<Factory<Client>>,
+    clients: Slab<Client>,
+    // Destination -> (client, subscription id) of every subscription to it.
+    // A client may subscribe to the same destination more than once, with
+    // different ids, and gets each message once per subscription.
+    destinations: HashMap<String, HashSet<(mio::Token, String)>>,
+    // Clients that had output queued, or were closed, while handling the
+    // current event. They are reregistered (or removed) once it is done.
+    dirty: Vec<mio::Token>,
+    // Used for the `message-id` header, which must be unique
+    next_message_id: u64,
+}
+
+impl Stomp {
+    fn new(acceptor: Acceptor<Factory<Client>>) -> Stomp {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Stomp {
+            acceptor: acceptor,
+            clients: slab,
+            destinations: HashMap::new(),
+            dirty: vec![],
+            next_message_id: 0,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Stomp>) {
+        let (_, res) = self.acceptor.accept(event_loop, &mut self.clients);
+
+        if let Err(e) = res {
+            println!("encountered error while accepting connection; err={:?}", e);
+            event_loop.shutdown();
+        }
+    }
+
+    fn client_ready(&mut self, token: mio::Token, events: mio::EventSet) {
+        if events.is_readable() {
+            match self.clients[token].read() {
+                Ok(frames) => {
+                    for frame in frames {
+                        // Anything sent after DISCONNECT, or an error, is
+                        // ignored
+                        if self.clients[token].quit {
+                            break;
+                        }
+
+                        self.handle(token, frame);
+                    }
+                }
+                Err(Some(e)) => self.error(token, e),
+                Err(None) => self.clients[token].closed = true,
+            }
+        }
+
+        if events.is_writable() {
+            self.clients[token].write();
+        }
+
+        self.dirty.push(token);
+    }
+
+    // Reregisters every client touched while handling an event, and
+    // removes the ones that are done.
+    fn flush(&mut self, event_loop: &mut mio::EventLoop<Stomp>) {
+        while let Some(token) = self.dirty.pop() {
+            let (closed, overflow) = match self.clients.get(token) {
+                Some(client) => (client.closed, client.out.len() > MAX_QUEUED),
+                None => continue,
+            };
+
+            if overflow && !closed {
+                println!("client is not keeping up, disconnecting; token={:?}", token);
+                self.clients[token].out.clear();
+                self.clients[token].closed = true;
+            }
+
+            if self.clients[token].closed {
+                self.remove(token);
+                continue;
+            }
+
+            let client = &self.clients[token];
+
+            event_loop.reregister(&client.socket, token, client.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn remove(&mut self, token: mio::Token) {
+        let mut client = self.clients.remove(token).unwrap();
+
+        for (id, destination) in mem::take(&mut client.subscriptions) {
+            self.unsubscribe(token, id, &destination);
+        }
+
+        println!("client disconnected; token={:?}", token);
+    }
+
+    // Reacts to a single frame received from a client
+    fn handle(&mut self, token: mio::Token, frame: Frame) {
+        println!("received frame; command={}; token={:?}", frame.command, token);
+
+        let connected = self.clients[token].connected;
+
+        match &frame.command[..] {
+            // STOMP is the 1.2 name for CONNECT, both are accepted
+            "CONNECT" | "STOMP" if !connected => return self.connect(token, &frame),
+            "CONNECT" | "STOMP" => return self.error(token, "already connected"),
+            _ if !connected => return self.error(token, "expected CONNECT"),
+            "SEND" => {
+                if let Err(e) = self.send(&frame) {
+                    return self.error(token, e);
+                }
+            }
+            "SUBSCRIBE" => {
+                if let Err(e) = self.subscribe(token, &frame) {
+                    return self.error(token, e);
+                }
+            }
+            "DISCONNECT" => {
+                // The client waits for the receipt to know that everything
+                // it sent was handled, then closes the connection. If it
+                // didn't ask for one, there's no reason to wait.
+                self.clients[token].quit = true;
+
+                if frame.header("receipt").is_none() {
+                    self.clients[token].closed = true;
+                }
+            }
+            _ => return self.error(token, "unknown command"),
+        }
+
+        // Any frame but CONNECT may ask for a receipt
+        if let Some(receipt) = frame.header("receipt") {
+            let mut out = vec![];
+            encode("RECEIPT", &[("receipt-id", receipt)], b"", &mut out);
+            self.queue(token, &out);
+        }
+    }
+
+    fn connect(&mut self, token: mio::Token, frame: &Frame) {
+        // Clients list every version they speak. A client that doesn't list
+        // any only speaks 1.0.
+        let ok = frame.header("accept-version")
+            .map(|versions| versions.split(',').any(|v| v == "1.2"))
+            .unwrap_or(false);
+
+        if !ok {
+            let mut out = vec![];
+            encode("ERROR", &[("version", "1.2"), ("message", "unsupported protocol version")],
+                   b"Supported protocol versions are 1.2", &mut out);
+
+            self.queue(token, &out);
+            self.clients[token].quit = true;
+            return;
+        }
+
+        // Heart-beating isn't supported, which is what `0,0` says
+        let mut out = vec![];
+        encode("CONNECTED", &[("version", "1.2"), ("server", "mio-stomp"), ("heart-beat", "0,0")], b"", &mut out);
+
+        self.clients[token].connected = true;
+        self.queue(token, &out);
+    }
+
+    // Routes the body of a SEND frame to every subscription to its
+    // destination.
+    fn send(&mut self, frame: &Frame) -> Result<(), &'static str> {
+        let destination = match frame.header("destination") {
+            Some(destination) => destination,
+            None => return Err("SEND without a destination"),
+        };
+
+        let subscribers: Vec<(mio::Token, String)> = match self.destinations.get(destination) {
+            Some(subscribers) => subscribers.iter().cloned().collect(),
+            None => vec![],
+        };
+
+        println!("send; destination={:?}; len={}; subscribers={}", destination, frame.body.len(), subscribers.len());
+
+        self.next_message_id += 1;
+        let message_id = self.next_message_id.to_string();
+
+        // Application headers are passed along. The ones only meaningful
+        // between the sender and the server are not, and `content-length`
+        // is recomputed by `encode`.
+        let passed: Vec<(&str, &str)> = frame.headers.iter()
+            .filter(|&(name, _)| match &name[..] {
+                "destination" | "receipt" | "transaction" | "content-length" => false,
+                _ => true,
+            })
+            .map(|(name, value)| (&name[..], &value[..]))
+            .collect();
+
+        for (token, id) in subscribers {
+            let mut headers = vec![("destination", destination), ("message-id", &message_id[..]), ("subscription", &id[..])];
+            headers.extend(passed.iter().cloned());
+
+            let mut out = vec![];
+            encode("MESSAGE", &headers, &frame.body, &mut out);
+            self.queue(token, &out);
+        }
+
+        Ok(())
+    }
+
+    fn subscribe(&mut self, token: mio::Token, frame: &Frame) -> Result<(), &'static str> {
+        let (destination, id) = match (frame.header("destination"), frame.header("id")) {
+            (Some(destination), Some(id)) => (destination, id),
+            _ => return Err("SUBSCRIBE requires destination and id"),
+        };
+
+        // The id is the client's handle on the subscription, so it must be
+        // unique on the connection.
+        if self.clients[token].subscriptions.contains_key(id) {
+            return Err("duplicate subscription id");
+        }
+
+        println!("subscribe; destination={:?}; id={:?}; token={:?}", destination, id, token);
+
+        self.clients[token].subscriptions.insert(id.to_string(), destination.to_string());
+
+        self.destinations.entry(destination.to_string())
+            .or_default()
+            .insert((token, id.to_string()));
+
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, token: mio::Token, id: String, destination: &str) {
+        let empty = match self.destinations.get_mut(destination) {
+            Some(subscribers) => {
+                subscribers.remove(&(token, id));
+                subscribers.is_empty()
+            }
+            None => false,
+        };
+
+        if empty {
+            self.destinations.remove(destination);
+        }
+    }
+
+    // Sends an ERROR frame. The protocol requires closing the connection
+    // after an error, which happens once the frame is flushed.
+    fn error(&mut self, token: mio::Token, message: &str) {
+        println!("client error, closing connection; err={}; token={:?}", message, token);
+
+        let mut out = vec![];
+        encode("ERROR", &[("message", message)], b"", &mut out);
+
+        self.queue(token, &out);
+        self.clients[token].quit = true;
+    }
+
+    fn queue(&mut self, token: mio::Token, frame: &[u8]) {
+        self.clients[token].out.extend(frame);
+        self.dirty.push(token);
+    }
+}
+
+impl mio::Handler for Stomp {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Stomp>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+                self.accept(event_loop);
+            }
+            _ => {
+                self.client_ready(token, events);
+                self.flush(event_loop);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Client {
+    socket: TcpStream,
+    // Bytes of an incomplete frame
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    connected: bool,
+    // Subscription id -> destination
+    subscriptions: HashMap<String, String>,
+    // Set after DISCONNECT or an error. The connection is closed after the
+    // remaining output is flushed.
+    quit: bool,
+    closed: bool,
+}
+
+impl acceptor::Connection for Client {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Client {
+    fn new(socket: TcpStream) -> Client {
+        Client {
+            socket: socket,
+            buf: vec![],
+            out: vec![],
+            connected: false,
+            subscriptions: HashMap::new(),
+            quit: false,
+            closed: false,
+        }
+    }
+
+    // Returns the complete frames read. Fails with the reason if the client
+    // sent something that isn't a valid frame, or with None if the
+    // connection is closed.
+    fn read(&mut self) -> Result<Vec<Frame>, Option<&'static str>> {
+        let mut buf = [0; 4096];
+        let mut frames = vec![];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => return Err(None),
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    while let Some((frame, len)) = decode(&self.buf).map_err(Some)? {
+                        self.buf.drain(..len);
+                        frames.push(frame);
+                    }
+                }
+                Ok(None) => return Ok(frames),
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    return Err(None);
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        if self.quit {
+            self.closed = true;
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        let mut interest = mio::EventSet::none();
+
+        if !self.quit {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.out.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        interest
+    }
+}
+
+/*
+ *
+ * ===== STOMP frames =====
+ *
+ */
+
+// A frame is a command line, header lines, a blank line, then the body
+// terminated by a NUL byte:
+//
+// SEND
+// destination:/queue/a
+//
+// hello^@
+//
+// Lines end with LF or CRLF.
+#[derive(Debug)]
+struct Frame {
+    command: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Frame {
+    // When a header is repeated, only the first value counts
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|&(n, _)| n == name)
+            .map(|(_, value)| &value[..])
+    }
+}
+
+// Decodes the frame at the start of `buf`. Returns the frame and the number
+// of bytes it used, or None if the frame is not complete yet.
+fn decode(buf: &[u8]) -> Result<Option<(Frame, usize)>, &'static str> {
+    // Clients may send empty lines between frames, as heart-beats
+    let start = buf.iter().position(|b| *b != b'\n' && *b != b'\r').unwrap_or(buf.len());
+
+    let mut lines = vec![];
+    let mut pos = start;
+
+    // The command and headers, up to the blank line
+    loop {
+        let end = match buf[pos..].iter().position(|b| *b == b'\n') {
+            Some(end) => pos + end,
+            None if buf.len() - start > MAX_HEADERS => return Err("headers too long"),
+            None => return Ok(None),
+        };
+
+        let line = &buf[pos..end];
+        let line = if line.ends_with(b"\r") { &line[..line.len() - 1] } else { line };
+
+        pos = end + 1;
+
+        if line.is_empty() {
+            break;
+        }
+
+        match std::str::from_utf8(line) {
+            Ok(line) => lines.push(line),
+            Err(_) => return Err("invalid UTF-8"),
+        }
+    }
+
+    if pos - start > MAX_HEADERS {
+        return Err("headers too long");
+    }
+
+    let command = lines[0].to_string();
+
+    // Header values in CONNECT frames aren't escaped, for compatibility
+    // with older versions.
+    let escaped = command != "CONNECT";
+
+    let mut headers = vec![];
+
+    for line in &lines[1..] {
+        let colon = match line.find(':') {
+            Some(colon) => colon,
+            None => return Err("invalid header"),
+        };
+
+        let (name, value) = (&line[..colon], &line[colon + 1..]);
+
+        if escaped {
+            headers.push((unescape(name)?, unescape(value)?));
+        } else {
+            headers.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    let frame = Frame {
+        command: command,
+        headers: headers,
+        body: vec![],
+    };
+
+    // With a content-length, the body may contain NUL bytes and is found
+    // by counting. Without one, it ends at the first NUL.
+    let len = match frame.header("content-length") {
+        Some(len) => match len.parse::<usize>() {
+            Ok(len) if len <= MAX_BODY => {
+                if buf.len() < pos + len + 1 {
+                    return Ok(None);
+                }
+
+                if buf[pos + len] != 0 {
+                    return Err("body not terminated by NUL");
+                }
+
+                len
+            }
+            _ => return Err("invalid content-length"),
+        },
+        None => match buf[pos..].iter().position(|b| *b == 0) {
+            Some(len) => len,
+            None if buf.len() - pos > MAX_BODY => return Err("body too large"),
+            None => return Ok(None),
+        },
+    };
+
+    let frame = Frame { body: buf[pos..pos + len].to_vec(), ..frame };
+
+    Ok(Some((frame, pos + len + 1)))
+}
+
+// Writes a frame to `dst`. A content-length header is added to frames with
+// a body.
+fn encode(command: &str, headers: &[(&str, &str)], body: &[u8], dst: &mut Vec<u8>) {
+    dst.extend(command.as_bytes());
+    dst.push(b'\n');
+
+    for &(name, value) in headers {
+        // The same exception as for CONNECT applies to CONNECTED
+        if command == "CONNECTED" {
+            dst.extend(format!("{}:{}\n", name, value).as_bytes());
+        } else {
+            dst.extend(format!("{}:{}\n", escape(name), escape(value)).as_bytes());
+        }
+    }
+
+    if !body.is_empty() {
+        dst.extend(format!("content-length:{}\n", body.len()).as_bytes());
+    }
+
+    dst.push(b'\n');
+    dst.extend(body);
+    dst.push(0);
+}
+
+// Header names and values can't contain a CR, LF or colon, or those would
+// be taken as framing, so they are backslash escaped.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '\r' => out.push_str("\\r"),
+            '\n' => out.push_str("\\n"),
+            ':' => out.push_str("\\c"),
+            '\\' => out.push_str("\\\\"),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+fn unescape(s: &str) -> Result<String, &'static str> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some('c') => out.push(':'),
+            Some('\\') => out.push('\\'),
+            // Any other escape is an error
+            _ => return Err("invalid escape in header"),
+        }
+    }
+
+    Ok(out)
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:61613".to_string())
+        .parse().unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, (|socket, _| Client::new(socket)) as Factory<Client>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mut stomp = Stomp::new(acceptor);
+
+    println!("running STOMP server; addr={:?}", address);
+    event_loop.run(&mut stomp).unwrap();
+}
@@ -0,0 +1,162 @@
+extern crate mio;
+extern crate bytes;
+extern crate stun;
+
+use mio::udp::*;
+use bytes::SliceBuf;
+use stun::Message;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Instant;
+use std::env;
+
+const SOCKET: mio::Token = mio::Token(0);
+
+// The retransmission timer starts at the RTO and doubles after every
+// transmission. After the last one, the client waits a while longer for a
+// late response before giving up. These are the defaults of RFC 5389,
+// giving up after 39.5 seconds.
+const RTO_MS: u64 = 500;
+const MAX_TRANSMISSIONS: usize = 7;
+const FINAL_WAIT_MS: u64 = 16 * RTO_MS;
+
+const MAX_DATAGRAM: usize = 1_500;
+
+struct Client {
+    socket: UdpSocket,
+    server: SocketAddr,
+    // Every transmission is the same request, with the same transaction ID:
+    // a response to any of them completes the transaction.
+    request: Message,
+    transmissions: usize,
+    rto: u64,
+    started: Instant,
+}
+
+impl Client {
+    fn new(socket: UdpSocket, server: SocketAddr) -> Client {
+        Client {
+            socket: socket,
+            server: server,
+            request: Message::binding_request(),
+            transmissions: 0,
+            rto: RTO_MS,
+            started: Instant::now(),
+        }
+    }
+
+    fn send(&mut self, event_loop: &mut mio::EventLoop<Client>) {
+        self.transmissions += 1;
+
+        println!("sending binding request; server={:?}; attempt={}", self.server, self.transmissions);
+
+        match self.socket.send_to(&mut SliceBuf::wrap(&self.request.encode()), &self.server) {
+            Ok(Some(())) => {}
+            Ok(None) => println!("the socket wasn't actually ready, will retry"),
+            Err(e) => panic!("got an error trying to send; err={:?}", e),
+        }
+
+        if self.transmissions < MAX_TRANSMISSIONS {
+            event_loop.timeout_ms((), self.rto).unwrap();
+            self.rto *= 2;
+        } else {
+            event_loop.timeout_ms((), FINAL_WAIT_MS).unwrap();
+        }
+    }
+
+    fn receive(&mut self, event_loop: &mut mio::EventLoop<Client>) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event.
+        loop {
+            let mut buf = Vec::with_capacity(MAX_DATAGRAM);
+
+            let addr = match self.socket.recv_from(&mut buf) {
+                Ok(Some(addr)) => addr,
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            let response = match Message::decode(&buf) {
+                Ok(response) => response,
+                Err(e) => {
+                    println!("ignoring invalid datagram; addr={:?}; err={:?}", addr, e);
+                    continue;
+                }
+            };
+
+            // A response to an earlier run, or a forgery, is ignored. The
+            // real response may still be on its way.
+            if response.transaction != self.request.transaction {
+                println!("ignoring response to another transaction; addr={:?}", addr);
+                continue;
+            }
+
+            let elapsed = self.started.elapsed();
+            let ms = elapsed.as_secs() * 1_000 + elapsed.subsec_nanos() as u64 / 1_000_000;
+
+            match response.kind {
+                stun::BINDING_SUCCESS => {
+                    // Servers predating RFC 5389 only send MAPPED-ADDRESS
+                    match response.xor_mapped_address().or_else(|| response.mapped_address()) {
+                        Some(mapped) => println!("public address: {}; elapsed_ms={}", mapped, ms),
+                        None => println!("response has no mapped address"),
+                    }
+                }
+                stun::BINDING_ERROR => {
+                    match response.error_code() {
+                        Some((code, reason)) => println!("server refused the request; code={}; reason={:?}", code, reason),
+                        None => println!("server refused the request"),
+                    }
+                }
+                kind => {
+                    println!("ignoring unexpected message; type={:#06x}", kind);
+                    continue;
+                }
+            }
+
+            event_loop.shutdown();
+            return;
+        }
+    }
+}
+
+impl mio::Handler for Client {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Client>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, SOCKET);
+        assert!(events.is_readable(), "unexpected events; events={:?}", events);
+
+        self.receive(event_loop);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Client>, _: ()) {
+        if self.transmissions < MAX_TRANSMISSIONS {
+            self.send(event_loop);
+            return;
+        }
+
+        println!("no response after {} attempts", self.transmissions);
+        event_loop.shutdown();
+    }
+}
+
+fn main() {
+    let server = env::args().nth(1).unwrap_or("stun.l.google.com:19302".to_string());
+
+    // Resolving the hostname blocks, which is fine since the event loop
+    // isn't running yet.
+    let server = server.to_socket_addrs().unwrap()
+        .find(|addr| addr.is_ipv4())
+        .expect("no IPv4 address for server");
+
+    let socket = UdpSocket::bound(&"0.0.0.0:0".parse().unwrap()).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&socket, SOCKET, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+
+    let mut client = Client::new(socket, server);
+    client.send(&mut event_loop);
+
+    event_loop.run(&mut client).unwrap();
+}
@@ -0,0 +1,124 @@
+extern crate mio;
+extern crate bytes;
+extern crate stun;
+
+use mio::udp::*;
+use bytes::SliceBuf;
+use stun::Message;
+use std::net::SocketAddr;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// The largest datagram accepted, enough for any request a client sends
+const MAX_DATAGRAM: usize = 1_500;
+
+// The class bits are spread over the message type, around the method bits
+const CLASS_MASK: u16 = 0x0110;
+const CLASS_REQUEST: u16 = 0x0000;
+const CLASS_ERROR: u16 = 0x0110;
+
+const SOFTWARE: &str = "mio-examples stun_server";
+
+struct StunServer {
+    socket: UdpSocket,
+}
+
+impl StunServer {
+    fn receive(&mut self) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event.
+        loop {
+            let mut buf = Vec::with_capacity(MAX_DATAGRAM);
+
+            let addr = match self.socket.recv_from(&mut buf) {
+                Ok(Some(addr)) => addr,
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            // Anything that isn't a well formed request goes unanswered,
+            // responses to a garbled datagram would only confuse the sender.
+            let request = match Message::decode(&buf) {
+                Ok(message) => message,
+                Err(e) => {
+                    println!("ignoring invalid datagram; addr={:?}; err={:?}", addr, e);
+                    continue;
+                }
+            };
+
+            if request.kind & CLASS_MASK != CLASS_REQUEST {
+                continue;
+            }
+
+            let response = respond(&request, addr);
+
+            match self.socket.send_to(&mut SliceBuf::wrap(&response.encode()), &addr) {
+                Ok(Some(())) => {}
+                Ok(None) => println!("the socket wasn't actually ready, dropping reply"),
+                Err(e) => println!("failed to send reply; addr={:?}; err={:?}", addr, e),
+            }
+        }
+    }
+}
+
+impl mio::Handler for StunServer {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, _: &mut mio::EventLoop<StunServer>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, SERVER);
+        assert!(events.is_readable());
+
+        self.receive();
+    }
+}
+
+// The response echoes the transaction ID, which is how the client matches
+// it up with its request.
+fn respond(request: &Message, addr: SocketAddr) -> Message {
+    let method = request.kind & !CLASS_MASK;
+
+    let mut response = if request.kind != stun::BINDING_REQUEST {
+        println!("unknown method; addr={:?}; method={:#06x}", addr, method);
+
+        let mut response = Message::new(method | CLASS_ERROR, request.transaction);
+        response.add_error_code(400, "Bad Request");
+        response
+    } else {
+        let unknown = request.unknown_required(&[]);
+
+        if !unknown.is_empty() {
+            // The client is told which attributes weren't understood
+            let mut response = Message::new(stun::BINDING_ERROR, request.transaction);
+            response.add_error_code(420, "Unknown Attribute");
+            response.add(stun::UNKNOWN_ATTRIBUTES, unknown.iter().flat_map(|kind| vec![(kind >> 8) as u8, *kind as u8]).collect());
+            response
+        } else {
+            println!("binding request; addr={:?}", addr);
+
+            // The address the datagram came from is the one a NAT mapped
+            // the client to.
+            let mut response = Message::new(stun::BINDING_SUCCESS, request.transaction);
+            response.add_xor_mapped_address(addr);
+            response
+        }
+    };
+
+    response.add(stun::SOFTWARE, SOFTWARE.as_bytes().to_vec());
+    response
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:3478".to_string())
+        .parse().unwrap();
+
+    let socket = UdpSocket::bound(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&socket, SERVER, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+
+    println!("running STUN server; addr={:?}", address);
+    event_loop.run(&mut StunServer { socket: socket }).unwrap();
+}
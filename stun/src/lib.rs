@@ -0,0 +1,401 @@
+// STUN (RFC 5389) messages. A message is a 20 byte header followed by
+// attributes:
+//
+// +--+--+----------------+-------------------------------+
+// |00|  type (14 bits)  |       length (16 bits)        |
+// +--+--+----------------+-------------------------------+
+// |                 magic cookie (32 bits)               |
+// +------------------------------------------------------+
+// |             transaction ID (96 bits)                 |
+// +------------------------------------------------------+
+//
+// The length counts the attributes only. Each attribute is a type, a
+// length and a value padded to a multiple of 4 bytes.
+//
+// Only what a Binding exchange needs is implemented: no authentication,
+// and no FINGERPRINT. Like the rest of the examples' protocol code, this
+// does no I/O, the server and client in `src/bin` own the sockets.
+
+extern crate rand;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+// Binding is the only method, a request and its two kinds of response
+pub const BINDING_REQUEST: u16 = 0x0001;
+pub const BINDING_SUCCESS: u16 = 0x0101;
+pub const BINDING_ERROR: u16 = 0x0111;
+
+pub const MAPPED_ADDRESS: u16 = 0x0001;
+pub const ERROR_CODE: u16 = 0x0009;
+pub const UNKNOWN_ATTRIBUTES: u16 = 0x000a;
+pub const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+pub const SOFTWARE: u16 = 0x8022;
+
+// Sets RFC 5389 messages apart from those of the older RFC 3489, and from
+// other protocols sharing the port.
+pub const MAGIC_COOKIE: u32 = 0x2112_a442;
+
+const HEADER: usize = 20;
+
+const FAMILY_IPV4: u8 = 0x01;
+const FAMILY_IPV6: u8 = 0x02;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    // Shorter than the header, or than the length in the header says
+    Truncated,
+    // The first two bits aren't zero or the magic cookie is missing
+    NotStun,
+    // An attribute runs past the end of the message
+    BadAttribute,
+}
+
+pub type TransactionId = [u8; 12];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub kind: u16,
+    pub value: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub kind: u16,
+    pub transaction: TransactionId,
+    pub attributes: Vec<Attribute>,
+}
+
+impl Message {
+    pub fn new(kind: u16, transaction: TransactionId) -> Message {
+        Message {
+            kind: kind,
+            transaction: transaction,
+            attributes: vec![],
+        }
+    }
+
+    // A Binding Request with a fresh transaction ID
+    pub fn binding_request() -> Message {
+        Message::new(BINDING_REQUEST, transaction_id())
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Message, Error> {
+        if buf.len() < HEADER {
+            return Err(Error::Truncated);
+        }
+
+        if buf[0] & 0xc0 != 0 || read_u32(&buf[4..8]) != MAGIC_COOKIE {
+            return Err(Error::NotStun);
+        }
+
+        let kind = read_u16(&buf[0..2]);
+        let len = read_u16(&buf[2..4]) as usize;
+
+        if buf.len() < HEADER + len {
+            return Err(Error::Truncated);
+        }
+
+        let mut message = Message::new(kind, [0; 12]);
+        message.transaction.copy_from_slice(&buf[8..20]);
+
+        let mut attrs = &buf[HEADER..HEADER + len];
+
+        while !attrs.is_empty() {
+            if attrs.len() < 4 {
+                return Err(Error::BadAttribute);
+            }
+
+            let kind = read_u16(&attrs[0..2]);
+            let len = read_u16(&attrs[2..4]) as usize;
+            let padded = (len + 3) & !3;
+
+            if attrs.len() < 4 + len {
+                return Err(Error::BadAttribute);
+            }
+
+            message.attributes.push(Attribute {
+                kind: kind,
+                value: attrs[4..4 + len].to_vec(),
+            });
+
+            // The padding of the last attribute may be missing
+            attrs = &attrs[std::cmp::min(4 + padded, attrs.len())..];
+        }
+
+        Ok(message)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut attrs = vec![];
+
+        for attr in &self.attributes {
+            write_u16(&mut attrs, attr.kind);
+            write_u16(&mut attrs, attr.value.len() as u16);
+            attrs.extend(&attr.value);
+
+            while attrs.len() % 4 != 0 {
+                attrs.push(0);
+            }
+        }
+
+        let mut buf = Vec::with_capacity(HEADER + attrs.len());
+
+        write_u16(&mut buf, self.kind);
+        write_u16(&mut buf, attrs.len() as u16);
+        write_u32(&mut buf, MAGIC_COOKIE);
+        buf.extend(&self.transaction);
+        buf.extend(attrs);
+
+        buf
+    }
+
+    pub fn get(&self, kind: u16) -> Option<&[u8]> {
+        self.attributes.iter().find(|attr| attr.kind == kind).map(|attr| &attr.value[..])
+    }
+
+    pub fn add(&mut self, kind: u16, value: Vec<u8>) {
+        self.attributes.push(Attribute {
+            kind: kind,
+            value: value,
+        });
+    }
+
+    // The address and port a request was seen coming from. They are XORed
+    // with the magic cookie, and for IPv6 with the transaction ID too, so
+    // that NATs rewriting addresses they find in packets leave them alone.
+    pub fn xor_mapped_address(&self) -> Option<SocketAddr> {
+        let mask = self.mask();
+        let mut addr = decode_address(self.get(XOR_MAPPED_ADDRESS)?)?;
+
+        addr.set_port(addr.port() ^ (MAGIC_COOKIE >> 16) as u16);
+        addr.set_ip(xor_ip(addr.ip(), &mask));
+
+        Some(addr)
+    }
+
+    pub fn add_xor_mapped_address(&mut self, addr: SocketAddr) {
+        let mask = self.mask();
+        let ip = xor_ip(addr.ip(), &mask);
+        let port = addr.port() ^ (MAGIC_COOKIE >> 16) as u16;
+
+        self.add(XOR_MAPPED_ADDRESS, encode_address(&SocketAddr::new(ip, port)));
+    }
+
+    // The same address, unobfuscated. Servers predating RFC 5389 only send
+    // this one.
+    pub fn mapped_address(&self) -> Option<SocketAddr> {
+        decode_address(self.get(MAPPED_ADDRESS)?)
+    }
+
+    // The error code of an error response and its reason phrase
+    pub fn error_code(&self) -> Option<(u16, String)> {
+        let value = self.get(ERROR_CODE)?;
+
+        if value.len() < 4 {
+            return None;
+        }
+
+        let code = (value[2] & 0x7) as u16 * 100 + value[3] as u16;
+        Some((code, String::from_utf8_lossy(&value[4..]).into_owned()))
+    }
+
+    pub fn add_error_code(&mut self, code: u16, reason: &str) {
+        let mut value = vec![0, 0, (code / 100) as u8, (code % 100) as u8];
+        value.extend(reason.as_bytes());
+
+        self.add(ERROR_CODE, value);
+    }
+
+    // Attributes in the range 0x0000-0x7fff must be understood. A request
+    // carrying one the server doesn't know has to be refused.
+    pub fn unknown_required(&self, known: &[u16]) -> Vec<u16> {
+        self.attributes.iter()
+            .map(|attr| attr.kind)
+            .filter(|kind| *kind < 0x8000 && !known.contains(kind))
+            .collect()
+    }
+
+    fn mask(&self) -> [u8; 16] {
+        let mut mask = [0; 16];
+
+        mask[..4].copy_from_slice(&[0x21, 0x12, 0xa4, 0x42]);
+        mask[4..].copy_from_slice(&self.transaction);
+
+        mask
+    }
+}
+
+// Transaction IDs have to be hard to guess, so that an off-path attacker
+// can't forge a response
+pub fn transaction_id() -> TransactionId {
+    rand::random::<TransactionId>()
+}
+
+// MAPPED-ADDRESS and XOR-MAPPED-ADDRESS share a layout: a reserved byte,
+// the address family, the port and the address.
+fn decode_address(value: &[u8]) -> Option<SocketAddr> {
+    if value.len() < 4 {
+        return None;
+    }
+
+    let port = read_u16(&value[2..4]);
+
+    let ip = match (value[1], value.len()) {
+        (FAMILY_IPV4, 8) => IpAddr::V4(Ipv4Addr::new(value[4], value[5], value[6], value[7])),
+        (FAMILY_IPV6, 20) => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&value[4..20]);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+
+    Some(SocketAddr::new(ip, port))
+}
+
+fn encode_address(addr: &SocketAddr) -> Vec<u8> {
+    let mut value = vec![0];
+
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            value.push(FAMILY_IPV4);
+            write_u16(&mut value, addr.port());
+            value.extend(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            value.push(FAMILY_IPV6);
+            write_u16(&mut value, addr.port());
+            value.extend(&ip.octets());
+        }
+    }
+
+    value
+}
+
+fn xor_ip(ip: IpAddr, mask: &[u8; 16]) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let mut octets = ip.octets();
+
+            for (b, m) in octets.iter_mut().zip(mask.iter()) {
+                *b ^= *m;
+            }
+
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        IpAddr::V6(ip) => {
+            let mut octets = ip.octets();
+
+            for (b, m) in octets.iter_mut().zip(mask.iter()) {
+                *b ^= *m;
+            }
+
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+    }
+}
+
+fn read_u16(buf: &[u8]) -> u16 {
+    (buf[0] as u16) << 8 | buf[1] as u16
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    buf[..4].iter().fold(0, |n, b| n << 8 | *b as u32)
+}
+
+fn write_u16(buf: &mut Vec<u8>, val: u16) {
+    buf.push((val >> 8) as u8);
+    buf.push(val as u8);
+}
+
+fn write_u32(buf: &mut Vec<u8>, val: u32) {
+    write_u16(buf, (val >> 16) as u16);
+    write_u16(buf, val as u16);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response() -> Message {
+        let mut message = Message::new(BINDING_SUCCESS, [7; 12]);
+        message.add_xor_mapped_address("192.0.2.1:32853".parse().unwrap());
+        message.add(SOFTWARE, b"mio".to_vec());
+        message
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let message = response();
+        let decoded = Message::decode(&message.encode()).unwrap();
+
+        assert_eq!(decoded, message);
+        assert_eq!(decoded.xor_mapped_address(), Some("192.0.2.1:32853".parse().unwrap()));
+        assert_eq!(decoded.get(SOFTWARE), Some(&b"mio"[..]));
+
+        let mut message = Message::new(BINDING_ERROR, [1; 12]);
+        message.add_xor_mapped_address("[2001:db8::1]:3478".parse().unwrap());
+        message.add_error_code(420, "Unknown Attribute");
+
+        let decoded = Message::decode(&message.encode()).unwrap();
+        assert_eq!(decoded.xor_mapped_address(), Some("[2001:db8::1]:3478".parse().unwrap()));
+        assert_eq!(decoded.error_code(), Some((420, "Unknown Attribute".to_string())));
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        let buf = response().encode();
+
+        for len in 0..buf.len() {
+            assert_eq!(Message::decode(&buf[..len]), Err(Error::Truncated));
+        }
+    }
+
+    #[test]
+    fn rejects_other_protocols() {
+        let mut buf = response().encode();
+        buf[4] ^= 0xff;
+        assert_eq!(Message::decode(&buf), Err(Error::NotStun));
+
+        let mut buf = response().encode();
+        buf[0] |= 0x80;
+        assert_eq!(Message::decode(&buf), Err(Error::NotStun));
+    }
+
+    #[test]
+    fn rejects_oversize_attributes() {
+        let mut buf = Message::new(BINDING_REQUEST, [0; 12]).encode();
+
+        // An attribute claiming more than the message holds
+        buf.extend(&[0x80, 0x22, 0xff, 0xff, b'm', b'i', b'o', 0]);
+        buf[3] = 8;
+        assert_eq!(Message::decode(&buf), Err(Error::BadAttribute));
+
+        // Half an attribute header
+        buf.truncate(HEADER + 2);
+        buf[3] = 2;
+        assert_eq!(Message::decode(&buf), Err(Error::BadAttribute));
+    }
+
+    #[test]
+    fn accepts_missing_final_padding() {
+        let mut buf = Message::new(BINDING_REQUEST, [0; 12]).encode();
+        buf.extend(&[0x80, 0x22, 0, 3, b'm', b'i', b'o']);
+        buf[3] = 7;
+
+        assert_eq!(Message::decode(&buf).unwrap().get(SOFTWARE), Some(&b"mio"[..]));
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        let mut message = Message::new(BINDING_SUCCESS, [0; 12]);
+        message.add(MAPPED_ADDRESS, vec![0, FAMILY_IPV4, 0, 80, 127, 0, 0]);
+        message.add(XOR_MAPPED_ADDRESS, vec![0, 3, 0, 80, 127, 0, 0, 1]);
+        message.add(ERROR_CODE, vec![0, 0, 4]);
+
+        let decoded = Message::decode(&message.encode()).unwrap();
+        assert_eq!(decoded.mapped_address(), None);
+        assert_eq!(decoded.xor_mapped_address(), None);
+        assert_eq!(decoded.error_code(), None);
+    }
+}
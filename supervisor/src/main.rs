@@ -0,0 +1,294 @@
+extern crate mio;
+extern crate libc;
+
+use mio::TryRead;
+use mio::unix::*;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{self, Command, ExitStatus, Stdio};
+use std::time::Instant;
+use std::{cmp, env, io};
+
+// How often exited children are looked for. Waiting for SIGCHLD would mean
+// signal handling, polling is simpler.
+const REAP_MS: u64 = 100;
+
+// A child that keeps exiting is restarted with a delay, doubled every time
+// it exits again soon after starting, up to the maximum.
+const INITIAL_BACKOFF_MS: u64 = 500;
+const MAX_BACKOFF_MS: u64 = 30_000;
+
+// A child that ran this long is considered healthy, its backoff starts over
+const STABLE_SECS: u64 = 10;
+
+enum Timeout {
+    Reap,
+    Restart(usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+// One of a child's output pipes. A line is only printed once it is
+// complete, so the output of different children doesn't get mixed up
+// mid-line.
+struct Output {
+    pipe: PipeReader,
+    partial: Vec<u8>,
+}
+
+struct Child {
+    name: String,
+    command: String,
+    // Not running while waiting to be restarted
+    process: Option<process::Child>,
+    stdout: Option<Output>,
+    stderr: Option<Output>,
+    started: Instant,
+    restarts: u64,
+    backoff_ms: u64,
+}
+
+// Runs a set of commands, prints their output tagged with their name, and
+// restarts them when they exit. Every child gets two tokens, one per
+// output pipe: `2 * i` and `2 * i + 1`, for the child at index `i`.
+struct Supervisor {
+    children: Vec<Child>,
+}
+
+impl Supervisor {
+    fn spawn(&mut self, event_loop: &mut mio::EventLoop<Supervisor>, i: usize) {
+        let child = &mut self.children[i];
+
+        // Output still buffered for the previous run goes with it
+        child.stdout = None;
+        child.stderr = None;
+
+        let mut process = match Command::new("sh").arg("-c").arg(&child.command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(process) => process,
+            Err(e) => {
+                println!("failed to spawn; name={}; err={:?}", child.name, e);
+                event_loop.timeout_ms(Timeout::Restart(i), child.backoff_ms).unwrap();
+                return;
+            }
+        };
+
+        let stdout = process.stdout.take().unwrap().into_raw_fd();
+        let stderr = process.stderr.take().unwrap().into_raw_fd();
+
+        child.stdout = Some(Output::new(event_loop, stdout, token(i, Stream::Stdout)));
+        child.stderr = Some(Output::new(event_loop, stderr, token(i, Stream::Stderr)));
+
+        println!("started; name={}; pid={}", child.name, process.id());
+
+        child.process = Some(process);
+        child.started = Instant::now();
+    }
+
+    fn output(&mut self, token: mio::Token) {
+        let (i, stream) = match token.as_usize() % 2 {
+            0 => (token.as_usize() / 2, Stream::Stdout),
+            _ => (token.as_usize() / 2, Stream::Stderr),
+        };
+
+        let child = match self.children.get_mut(i) {
+            Some(child) => child,
+            None => return,
+        };
+
+        let output = match stream {
+            Stream::Stdout => &mut child.stdout,
+            Stream::Stderr => &mut child.stderr,
+        };
+
+        // The pipe may have been dropped by a restart, with its event
+        // already on the way
+        let open = match *output {
+            Some(ref mut output) => output.read(&child.name, stream),
+            None => return,
+        };
+
+        if !open {
+            *output = None;
+        }
+    }
+
+    fn reap(&mut self, event_loop: &mut mio::EventLoop<Supervisor>) {
+        for (i, child) in self.children.iter_mut().enumerate() {
+            let status = match child.process.as_mut().map(|process| process.try_wait()) {
+                Some(Ok(Some(status))) => status,
+                Some(Ok(None)) | None => continue,
+                Some(Err(e)) => panic!("failed to wait for child; name={}; err={:?}", child.name, e),
+            };
+
+            let uptime = child.started.elapsed().as_secs();
+
+            child.process = None;
+            child.restarts += 1;
+
+            if uptime >= STABLE_SECS {
+                child.backoff_ms = INITIAL_BACKOFF_MS;
+            }
+
+            let delay = child.backoff_ms;
+            child.backoff_ms = cmp::min(delay * 2, MAX_BACKOFF_MS);
+
+            println!("exited; name={}; status={}; uptime={}s; restarting in {}ms",
+                     child.name, describe(status), uptime, delay);
+
+            event_loop.timeout_ms(Timeout::Restart(i), delay).unwrap();
+        }
+
+        event_loop.timeout_ms(Timeout::Reap, REAP_MS).unwrap();
+    }
+}
+
+impl mio::Handler for Supervisor {
+    type Timeout = Timeout;
+    type Message = ();
+
+    fn ready(&mut self, _: &mut mio::EventLoop<Supervisor>, token: mio::Token, _: mio::EventSet) {
+        // Readable or hung up, either way the pipe is read until it would
+        // block, or until the end of it.
+        self.output(token);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Supervisor>, timeout: Timeout) {
+        match timeout {
+            Timeout::Reap => self.reap(event_loop),
+            Timeout::Restart(i) => {
+                println!("restarting; name={}; restarts={}", self.children[i].name, self.children[i].restarts);
+                self.spawn(event_loop, i);
+            }
+        }
+    }
+}
+
+impl Output {
+    fn new(event_loop: &mut mio::EventLoop<Supervisor>, fd: libc::c_int, token: mio::Token) -> Output {
+        let pipe = unsafe { PipeReader::from_raw_fd(fd) };
+
+        // The standard library creates the pipes blocking
+        set_nonblocking(&pipe).unwrap();
+
+        event_loop.register_opt(&pipe, token, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+
+        Output {
+            pipe: pipe,
+            partial: vec![],
+        }
+    }
+
+    // Prints the complete lines read so far. Returns false once the child
+    // has closed the pipe.
+    fn read(&mut self, name: &str, stream: Stream) -> bool {
+        let mut buf = [0; 4096];
+
+        // The pipe is registered as edge triggered, drain it
+        let open = loop {
+            match self.pipe.try_read(&mut buf) {
+                Ok(Some(0)) => break false,
+                Ok(Some(n)) => self.partial.extend(&buf[..n]),
+                Ok(None) => break true,
+                Err(e) => {
+                    println!("got an error trying to read output; name={}; err={:?}", name, e);
+                    break false;
+                }
+            }
+        };
+
+        while let Some(pos) = self.partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..pos + 1).collect();
+            print_line(name, stream, &line[..pos]);
+        }
+
+        // The last line may not have been terminated
+        if !open && !self.partial.is_empty() {
+            print_line(name, stream, &self.partial);
+            self.partial.clear();
+        }
+
+        open
+    }
+}
+
+fn print_line(name: &str, stream: Stream, line: &[u8]) {
+    let tag = match stream {
+        Stream::Stdout => "out",
+        Stream::Stderr => "err",
+    };
+
+    println!("[{} {}] {}", name, tag, String::from_utf8_lossy(line));
+}
+
+fn token(i: usize, stream: Stream) -> mio::Token {
+    match stream {
+        Stream::Stdout => mio::Token(2 * i),
+        Stream::Stderr => mio::Token(2 * i + 1),
+    }
+}
+
+fn describe(status: ExitStatus) -> String {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("exit code {}", code),
+        (None, Some(signal)) => format!("killed by signal {}", signal),
+        _ => "unknown".to_string(),
+    }
+}
+
+fn set_nonblocking<T: AsRawFd>(fd: &T) -> io::Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd.as_raw_fd(), libc::F_GETFL);
+
+        if flags < 0 || libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    // Each argument is a command, run with `sh -c`, optionally named with a
+    // `name=` prefix. Without one, the command's first word names it.
+    let children: Vec<Child> = env::args().skip(1).map(|arg| {
+        let (name, command) = match arg.find('=') {
+            Some(pos) if !arg[..pos].contains(char::is_whitespace) => (arg[..pos].to_string(), arg[pos + 1..].to_string()),
+            _ => (arg.split_whitespace().next().unwrap_or("").to_string(), arg.clone()),
+        };
+
+        Child {
+            name: name,
+            command: command,
+            process: None,
+            stdout: None,
+            stderr: None,
+            started: Instant::now(),
+            restarts: 0,
+            backoff_ms: INITIAL_BACKOFF_MS,
+        }
+    }).collect();
+
+    if children.is_empty() {
+        println!("usage: supervisor [name=]<command> ...");
+        process::exit(1);
+    }
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    let mut supervisor = Supervisor { children: children };
+
+    for i in 0..supervisor.children.len() {
+        supervisor.spawn(&mut event_loop, i);
+    }
+
+    event_loop.timeout_ms(Timeout::Reap, REAP_MS).unwrap();
+    event_loop.run(&mut supervisor).unwrap();
+}
@@ -0,0 +1,380 @@
+extern crate mio;
+extern crate bytes;
+
+use mio::TryRead;
+use mio::tcp::*;
+use mio::udp::*;
+use mio::util::Slab;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::env;
+
+const UDP_SERVER: mio::Token = mio::Token(0);
+const TCP_SERVER: mio::Token = mio::Token(1);
+
+// RFC 5424 requires receivers to accept messages of at least 2048 bytes
+const MAX_MESSAGE: usize = 8 * 1_024;
+
+// The log file is rotated when it reaches this size. The previous files
+// are kept as `<name>.1` (the most recent) to `<name>.<KEEP>`.
+const MAX_LOG_SIZE: u64 = 1_024 * 1_024;
+const KEEP: usize = 5;
+
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news",
+    "uucp", "cron", "authpriv", "ftp", "ntp", "audit", "alert", "clock",
+    "local0", "local1", "local2", "local3", "local4", "local5", "local6", "local7"];
+
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// The destination of every message, whichever transport it came in on.
+//
+// Writing to the file blocks the event loop. A syslog line is small, and a
+// local disk absorbs the writes in its page cache, so this is acceptable
+// for the example.
+struct Log {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl Log {
+    fn open(path: PathBuf) -> Log {
+        let file = OpenOptions::new().create(true).append(true).open(&path).unwrap();
+        let size = file.metadata().unwrap().len();
+
+        Log {
+            path: path,
+            file: file,
+            size: size,
+        }
+    }
+
+    fn write(&mut self, line: &str) {
+        if self.size + line.len() as u64 > MAX_LOG_SIZE {
+            self.rotate();
+        }
+
+        match self.file.write_all(line.as_bytes()) {
+            Ok(()) => self.size += line.len() as u64,
+            Err(e) => println!("failed to write log; err={:?}", e),
+        }
+    }
+
+    // Shifts every old file up by one, dropping the oldest, and starts a
+    // fresh file.
+    fn rotate(&mut self) {
+        let name = |i: usize| PathBuf::from(format!("{}.{}", self.path.display(), i));
+
+        for i in (1..KEEP).rev() {
+            let _ = fs::rename(name(i), name(i + 1));
+        }
+
+        let _ = fs::rename(&self.path, name(1));
+
+        println!("rotated log; path={:?}", self.path);
+
+        *self = Log::open(self.path.clone());
+    }
+}
+
+struct Syslog {
+    udp: UdpSocket,
+    tcp: TcpListener,
+    connections: Slab<Connection>,
+    log: Log,
+}
+
+impl Syslog {
+    fn new(udp: UdpSocket, tcp: TcpListener, log: Log) -> Syslog {
+        // Tokens `0` and `1` are reserved for the server sockets. The slab
+        // is initialized to return Tokens starting at 2.
+        let slab = Slab::new_starting_at(mio::Token(2), 1024);
+
+        Syslog {
+            udp: udp,
+            tcp: tcp,
+            connections: slab,
+            log: log,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Syslog>) {
+        match self.tcp.accept() {
+            Ok(Some(socket)) => {
+                let peer = match socket.peer_addr() {
+                    Ok(addr) => addr.ip(),
+                    Err(_) => return,
+                };
+
+                let token = match self.connections.insert_with(|token| Connection::new(socket, token, peer)) {
+                    Some(token) => token,
+                    None => {
+                        println!("connection limit reached, dropping sender");
+                        return;
+                    }
+                };
+
+                event_loop.register_opt(
+                    &self.connections[token].socket,
+                    token,
+                    mio::EventSet::readable(),
+                    mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+            }
+            Ok(None) => {
+                println!("the server socket wasn't actually ready");
+            }
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+            }
+        }
+    }
+
+    fn receive(&mut self) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event. Each
+        // datagram is exactly one message.
+        loop {
+            let mut buf = Vec::with_capacity(MAX_MESSAGE);
+
+            let addr = match self.udp.recv_from(&mut buf) {
+                Ok(Some(addr)) => addr,
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            self.log.write(&normalize(&buf, addr.ip()));
+        }
+    }
+}
+
+impl mio::Handler for Syslog {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Syslog>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            UDP_SERVER => {
+                assert!(events.is_readable());
+                self.receive();
+            }
+            TCP_SERVER => {
+                assert!(events.is_readable());
+                self.accept(event_loop);
+            }
+            _ => {
+                let mut messages = vec![];
+
+                let conn = &mut self.connections[token];
+                conn.read(&mut messages);
+
+                for msg in messages {
+                    self.log.write(&normalize(&msg, conn.peer));
+                }
+
+                if conn.closed {
+                    let _ = self.connections.remove(token);
+                } else {
+                    event_loop.reregister(&conn.socket, token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                        .unwrap();
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    peer: IpAddr,
+    // Bytes of an incomplete message
+    buf: Vec<u8>,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token, peer: IpAddr) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            peer: peer,
+            buf: vec![],
+            closed: false,
+        }
+    }
+
+    fn read(&mut self, messages: &mut Vec<Vec<u8>>) {
+        let mut buf = [0; 4096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+
+                    if let Err(e) = self.decode(messages) {
+                        // There is no finding the start of the next message
+                        // once the framing is lost.
+                        println!("closing connection; err={}; token={:?}", e, self.token);
+                        self.closed = true;
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    // Splits the stream into messages. RFC 6587 describes two framings, and
+    // senders use either:
+    //
+    // * octet counting: `<length> SP <message>`
+    // * non-transparent framing: `<message> LF`
+    //
+    // A message always starts with `<`, so a leading digit means the frame
+    // is octet counted. Each frame is checked on its own.
+    fn decode(&mut self, messages: &mut Vec<Vec<u8>>) -> Result<(), &'static str> {
+        while !self.buf.is_empty() {
+            if self.buf[0].is_ascii_digit() {
+                let space = match self.buf.iter().take(6).position(|b| *b == b' ') {
+                    Some(pos) => pos,
+                    None if self.buf.len() >= 6 => return Err("invalid frame length"),
+                    None => return Ok(()),
+                };
+
+                let len = std::str::from_utf8(&self.buf[..space]).ok().and_then(|len| len.parse::<usize>().ok());
+
+                let len = match len {
+                    Some(len) if len <= MAX_MESSAGE => len,
+                    _ => return Err("invalid frame length"),
+                };
+
+                if self.buf.len() < space + 1 + len {
+                    return Ok(());
+                }
+
+                let frame: Vec<u8> = self.buf.drain(..space + 1 + len).skip(space + 1).collect();
+                messages.push(frame);
+            } else {
+                let pos = match self.buf.iter().position(|b| *b == b'\n') {
+                    Some(pos) => pos,
+                    None if self.buf.len() > MAX_MESSAGE => return Err("message too long"),
+                    None => return Ok(()),
+                };
+
+                let frame: Vec<u8> = self.buf.drain(..pos + 1).take(pos).collect();
+
+                if !frame.is_empty() {
+                    messages.push(frame);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Turns a message into a line of the log file:
+//
+// <received time> <host> <facility>.<severity> <message>
+//
+// An RFC 3164 message looks like:
+//
+// <34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick
+//
+// but senders are sloppy, so every part after the priority is optional.
+// Without a hostname, the address the message came from is used.
+fn normalize(msg: &[u8], peer: IpAddr) -> String {
+    let msg = String::from_utf8_lossy(msg);
+    let mut rest = msg.trim_end();
+
+    // Messages without a priority are user.notice
+    let mut pri = 13;
+
+    if rest.starts_with('<') {
+        if let Some(end) = rest[1..].find('>').map(|end| end + 1) {
+            if let Ok(n) = rest[1..end].parse::<usize>() {
+                if n < FACILITIES.len() * 8 {
+                    pri = n;
+                    rest = &rest[end + 1..];
+                }
+            }
+        }
+    }
+
+    let mut host = peer.to_string();
+
+    // The timestamp has a fixed width: "Mmm dd hh:mm:ss"
+    if rest.len() > 16 && rest.is_char_boundary(16) && MONTHS.contains(&&rest[..3]) && &rest[15..16] == " " {
+        rest = &rest[16..];
+
+        // The hostname follows the timestamp
+        if let Some(end) = rest.find(' ') {
+            host = rest[..end].to_string();
+            rest = &rest[end + 1..];
+        }
+    }
+
+    format!("{} {} {}.{} {}\n", now(), host, FACILITIES[pri / 8], SEVERITIES[pri % 8], rest)
+}
+
+// Formats the current UTC time as "2015-10-11T22:14:15Z"
+fn now() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+
+    // Converts days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year, month, day, rem / 3_600, rem % 3_600 / 60, rem % 60)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    // The well known port is 514, which requires root. Both transports
+    // listen on the same port number.
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:5514".to_string()).parse().unwrap();
+    let path = PathBuf::from(args.next().unwrap_or("syslog.log".to_string()));
+
+    let udp = UdpSocket::bound(&address).unwrap();
+    let tcp = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&udp, UDP_SERVER, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+    event_loop.register(&tcp, TCP_SERVER).unwrap();
+
+    let mut syslog = Syslog::new(udp, tcp, Log::open(path));
+
+    println!("running syslog receiver; addr={:?}; log={:?}", address, syslog.log.path);
+    event_loop.run(&mut syslog).unwrap();
+}
@@ -0,0 +1,29 @@
+// The capture file of a connection: everything relayed, both ways, in
+// the order it was read, for picking apart offline. Each read is a
+// record:
+//
+// +--------------+-------------------+-------------------+----------+
+// | direction(8) | timestamp (64)    | length (32)       | data     |
+// +--------------+-------------------+-------------------+----------+
+//
+// The direction is `0` for client to server and `1` for server to
+// client. The timestamp is in microseconds since the connection was
+// accepted. All fields are big endian.
+//
+// This does no I/O.
+
+use std::time::Duration;
+
+pub const CLIENT_TO_SERVER: u8 = 0;
+pub const SERVER_TO_CLIENT: u8 = 1;
+
+pub fn record(direction: u8, elapsed: Duration, data: &[u8]) -> Vec<u8> {
+    let micros = elapsed.as_secs() * 1_000_000 + elapsed.subsec_micros() as u64;
+    let mut record = Vec::with_capacity(13 + data.len());
+
+    record.push(direction);
+    record.extend_from_slice(&micros.to_be_bytes());
+    record.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    record.extend_from_slice(data);
+    record
+}
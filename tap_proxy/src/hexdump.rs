@@ -0,0 +1,40 @@
+// Formats bytes the way `hexdump -C` does: sixteen bytes a line, the
+// offset, the bytes in hex, then the bytes that are printable as they
+// are, and dots for the rest.
+//
+// 00000000  47 45 54 20 2f 20 48 54  54 50 2f 31 2e 31 0d 0a  |GET / HTTP/1.1..|
+//
+// This does no I/O.
+
+use std::fmt::Write;
+
+// Dumps `buf`, found at `offset` in the stream it was read from, so that
+// the dumps of consecutive reads line up
+pub fn hexdump(buf: &[u8], offset: u64) -> String {
+    let mut out = String::new();
+
+    for (i, line) in buf.chunks(16).enumerate() {
+        write!(out, "{:08x}  ", offset + i as u64 * 16).unwrap();
+
+        for j in 0..16 {
+            match line.get(j) {
+                Some(b) => write!(out, "{:02x} ", b).unwrap(),
+                None => out.push_str("   "),
+            }
+
+            if j == 7 {
+                out.push(' ');
+            }
+        }
+
+        out.push_str(" |");
+
+        for &b in line {
+            out.push(if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' });
+        }
+
+        out.push_str("|\n");
+    }
+
+    out
+}
@@ -0,0 +1,360 @@
+extern crate mio;
+extern crate pump;
+
+mod capture;
+mod hexdump;
+
+use mio::tcp::*;
+use mio::util::Slab;
+use pump::{Pipe, Side};
+use std::fs::{self, File};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Instant;
+use std::{env, process};
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Tokens `1..=MAX_CONNECTIONS` are used for client connections, and the
+// connection to the upstream server for a client is its token plus
+// `MAX_CONNECTIONS`.
+const MAX_CONNECTIONS: usize = 1_024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Direction {
+    // Client to server
+    Up,
+    // Server to client
+    Down,
+}
+
+impl Direction {
+    fn arrow(self) -> &'static str {
+        match self {
+            Direction::Up => "client -> server",
+            Direction::Down => "server -> client",
+        }
+    }
+
+    // The side of the pipe the direction reads from
+    fn side(self) -> Side {
+        match self {
+            Direction::Up => Side::A,
+            Direction::Down => Side::B,
+        }
+    }
+}
+
+// Relays TCP connections to an upstream server, printing everything that
+// goes through, both ways, as a hexdump. Each dump is stamped with the
+// time since the proxy started, and the id of its connection: tokens are
+// reused, ids aren't. With a capture directory, everything is also
+// written to a file per connection, as it was read, see `capture`. The
+// bytes are relayed by a pipe, with the client as its side A and the
+// upstream server as side B, which hands each chunk it reads over to be
+// dumped on the way.
+//
+// The dumps and the capture files are written with blocking I/O, from
+// the event loop. It's a debugging tool, the traffic it sees is expected
+// to be light.
+struct Proxy {
+    server: TcpListener,
+    upstream: SocketAddr,
+    capture: Option<PathBuf>,
+    connections: Slab<Connection>,
+    started: Instant,
+    next_id: u64,
+}
+
+impl Proxy {
+    fn new(server: TcpListener, upstream: SocketAddr, capture: Option<PathBuf>) -> Proxy {
+        // Token `0` is reserved for the server socket
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS);
+
+        Proxy {
+            server: server,
+            upstream: upstream,
+            capture: capture,
+            connections: slab,
+            started: Instant::now(),
+            next_id: 1,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Proxy>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        let upstream = match TcpStream::connect(&self.upstream) {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                println!("failed to connect upstream; err={:?}", e);
+                return;
+            }
+        };
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let file = match self.capture {
+            Some(ref dir) => {
+                let path = dir.join(format!("{}.cap", id));
+
+                match File::create(&path) {
+                    Ok(file) => Some(file),
+                    Err(e) => {
+                        println!("failed to create capture file; path={:?}; err={:?}", path, e);
+                        return;
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let peer = socket.peer_addr().ok();
+
+        let token = match self.connections.insert_with(|token| Connection::new(socket, upstream, token, id, file)) {
+            Some(token) => token,
+            None => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        println!("[{}] #{} accepted; peer={:?}", self.timestamp(), id, peer);
+
+        let conn = &self.connections[token];
+
+        // Nothing is read from the client until the upstream connection is
+        // up, which is when it is writable
+        event_loop.register_opt(conn.pipe.socket(Side::A), token, mio::EventSet::none(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+        event_loop.register_opt(conn.pipe.socket(Side::B), upstream_token(token), mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, direction: Direction, events: mio::EventSet) {
+        let timestamp = self.timestamp();
+        let conn = &mut self.connections[token];
+
+        if !conn.connected {
+            if events.is_error() || events.is_hup() {
+                println!("[{}] #{} failed to connect upstream; err={:?}", timestamp, conn.id, conn.pipe.socket(Side::B).take_socket_error());
+                conn.closed = true;
+            } else if events.is_writable() {
+                println!("[{}] #{} connected upstream", timestamp, conn.id);
+                conn.connected = true;
+            }
+        }
+
+        if conn.connected && events.is_readable() {
+            conn.read(direction, self.started);
+        }
+
+        self.update(event_loop, token);
+    }
+
+    // Writes what it can, then reregisters both sockets of the connection,
+    // or removes it once it is done
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token) {
+        {
+            let conn = &mut self.connections[token];
+
+            if conn.connected && !conn.closed {
+                conn.write(Direction::Up);
+                conn.write(Direction::Down);
+            }
+        }
+
+        if self.connections[token].is_done() {
+            let conn = self.connections.remove(token).unwrap();
+            println!("[{}] #{} closed; sent={}; received={}", self.timestamp(), conn.id, conn.pipe.written(Side::B), conn.pipe.written(Side::A));
+            return;
+        }
+
+        let conn = &self.connections[token];
+
+        event_loop.reregister(conn.pipe.socket(Side::A), token, conn.client_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+        event_loop.reregister(conn.pipe.socket(Side::B), upstream_token(token), conn.upstream_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn timestamp(&self) -> String {
+        format_timestamp(self.started)
+    }
+}
+
+impl mio::Handler for Proxy {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ if token.as_usize() <= MAX_CONNECTIONS => self.connection_ready(event_loop, token, Direction::Up, events),
+            _ => {
+                let token = mio::Token(token.as_usize() - MAX_CONNECTIONS);
+
+                // The upstream socket's events may still come in after the
+                // connection is gone
+                if self.connections.contains(token) {
+                    self.connection_ready(event_loop, token, Direction::Down, events);
+                }
+            }
+        }
+    }
+}
+
+fn upstream_token(token: mio::Token) -> mio::Token {
+    mio::Token(token.as_usize() + MAX_CONNECTIONS)
+}
+
+// Seconds since `started`, with milliseconds
+fn format_timestamp(started: Instant) -> String {
+    let elapsed = started.elapsed();
+    format!("{:5}.{:03}", elapsed.as_secs(), elapsed.subsec_millis())
+}
+
+struct Connection {
+    pipe: Pipe,
+    token: mio::Token,
+    id: u64,
+    capture: Option<File>,
+    accepted: Instant,
+    connected: bool,
+    // Bytes read so far, client to server and server to client, the
+    // offsets of the next hexdumps
+    up: u64,
+    down: u64,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(client: TcpStream, upstream: TcpStream, token: mio::Token, id: u64, capture: Option<File>) -> Connection {
+        Connection {
+            pipe: Pipe::new(client, upstream),
+            token: token,
+            id: id,
+            capture: capture,
+            accepted: Instant::now(),
+            connected: false,
+            up: 0,
+            down: 0,
+            closed: false,
+        }
+    }
+
+    // Reads from the side `direction` starts at, dumping what it read
+    fn read(&mut self, direction: Direction, started: Instant) {
+        let side = direction.side();
+        let was_closed = self.pipe.is_closed(side);
+
+        let id = self.id;
+        let accepted = self.accepted;
+        let capture = &mut self.capture;
+
+        let offset = match direction {
+            Direction::Up => &mut self.up,
+            Direction::Down => &mut self.down,
+        };
+
+        let res = self.pipe.read_with(side, usize::MAX, |data| {
+            println!("[{}] #{} {}; len={}", format_timestamp(started), id, direction.arrow(), data.len());
+            print!("{}", hexdump::hexdump(data, *offset));
+
+            *offset += data.len() as u64;
+
+            if let Some(ref mut file) = *capture {
+                let kind = match direction {
+                    Direction::Up => capture::CLIENT_TO_SERVER,
+                    Direction::Down => capture::SERVER_TO_CLIENT,
+                };
+
+                // Losing the capture is no reason to drop the connection,
+                // but it is no use going on writing to it
+                if let Err(e) = file.write_all(&capture::record(kind, accepted.elapsed(), data)) {
+                    println!("failed to write capture file; id={}; err={:?}", id, e);
+                    *capture = None;
+                }
+            }
+        });
+
+        if let Err(e) = res {
+            println!("got an error trying to read; token={:?}; direction={:?}; err={:?}", self.token, direction, e);
+            self.closed = true;
+            return;
+        }
+
+        if !was_closed && self.pipe.is_closed(side) {
+            println!("[{}] #{} {} closed", format_timestamp(started), self.id, direction.arrow());
+        }
+    }
+
+    // Writes what was read from the other side to the side `direction`
+    // ends at
+    fn write(&mut self, direction: Direction) {
+        if let Err(e) = self.pipe.write(direction.side().other()) {
+            println!("got an error trying to write; token={:?}; direction={:?}; err={:?}", self.token, direction, e);
+            self.closed = true;
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.closed || self.pipe.is_done()
+    }
+
+    // Nothing is read from the client until the upstream connection is up
+    fn client_interest(&self) -> mio::EventSet {
+        if !self.connected {
+            return mio::EventSet::none();
+        }
+
+        self.pipe.interest(Side::A)
+    }
+
+    fn upstream_interest(&self) -> mio::EventSet {
+        if !self.connected {
+            return mio::EventSet::writable();
+        }
+
+        self.pipe.interest(Side::B)
+    }
+}
+
+fn usage() -> ! {
+    println!("usage: tap_proxy <addr> <upstream> [capture dir]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().and_then(|addr| addr.parse().ok()).unwrap_or_else(|| usage());
+    let upstream: SocketAddr = args.next().and_then(|addr| addr.parse().ok()).unwrap_or_else(|| usage());
+    let capture = args.next().map(PathBuf::from);
+
+    if let Some(ref dir) = capture {
+        fs::create_dir_all(dir).unwrap();
+    }
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    println!("running tap proxy; addr={:?}; upstream={:?}; capture={:?}", address, upstream, capture);
+
+    let mut proxy = Proxy::new(server, upstream, capture);
+    event_loop.run(&mut proxy).unwrap();
+}
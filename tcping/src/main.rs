@@ -0,0 +1,195 @@
+extern crate mio;
+extern crate bytes;
+
+use mio::tcp::*;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Instant;
+use std::{env, process};
+
+const CLIENT: mio::Token = mio::Token(0);
+
+// How long to wait after a probe completes before starting the next one
+const INTERVAL_MS: u64 = 1_000;
+
+// How long a handshake may take before the probe is given up on
+const PROBE_TIMEOUT_MS: u64 = 2_000;
+
+enum Timeout {
+    // Time to start the next probe
+    Next,
+    // The probe in flight took too long
+    Deadline,
+}
+
+// A connect in flight. Every probe uses a new socket, registered with the
+// same token: it is closed before the next one is opened.
+struct Probe {
+    socket: TcpStream,
+    started: Instant,
+    deadline: mio::Timeout,
+}
+
+struct Tcping {
+    addr: SocketAddr,
+    count: u64,
+    // Probes started so far
+    seq: u64,
+    probe: Option<Probe>,
+    // Handshake times of the successful probes, in milliseconds
+    rtts: Vec<f64>,
+    failed: u64,
+}
+
+impl Tcping {
+    fn new(addr: SocketAddr, count: u64) -> Tcping {
+        Tcping {
+            addr: addr,
+            count: count,
+            seq: 0,
+            probe: None,
+            rtts: vec![],
+            failed: 0,
+        }
+    }
+
+    fn start(&mut self, event_loop: &mut mio::EventLoop<Tcping>) {
+        self.seq += 1;
+
+        // The clock starts before the connect: the SYN is sent before the
+        // call returns.
+        let started = Instant::now();
+
+        let socket = match TcpStream::connect(&self.addr) {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("failed to connect to {}: seq={} err={}", self.addr, self.seq, e);
+                self.failed += 1;
+                return self.next(event_loop);
+            }
+        };
+
+        // The socket becomes writable once the handshake completes, or fails
+        event_loop.register_opt(&socket, CLIENT, mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        self.probe = Some(Probe {
+            socket: socket,
+            started: started,
+            deadline: event_loop.timeout_ms(Timeout::Deadline, PROBE_TIMEOUT_MS).unwrap(),
+        });
+    }
+
+    // Waits for the interval, or prints the summary if that was the last
+    // probe.
+    fn next(&mut self, event_loop: &mut mio::EventLoop<Tcping>) {
+        if self.seq == self.count {
+            self.summary();
+            event_loop.shutdown();
+            return;
+        }
+
+        event_loop.timeout_ms(Timeout::Next, INTERVAL_MS).unwrap();
+    }
+
+    fn summary(&self) {
+        let connected = self.rtts.len() as u64;
+
+        println!();
+        println!("--- {} tcping statistics ---", self.addr);
+        println!("{} probes, {} connected, {:.0}% failed", self.seq, connected, self.failed as f64 * 100.0 / self.seq as f64);
+
+        if connected == 0 {
+            return;
+        }
+
+        let min = self.rtts.iter().cloned().fold(f64::MAX, f64::min);
+        let max = self.rtts.iter().cloned().fold(0.0, f64::max);
+        let avg = self.rtts.iter().sum::<f64>() / connected as f64;
+        let variance = self.rtts.iter().map(|rtt| (rtt - avg) * (rtt - avg)).sum::<f64>() / connected as f64;
+
+        println!("rtt min/avg/max/stddev = {:.3}/{:.3}/{:.3}/{:.3} ms", min, avg, max, variance.sqrt());
+    }
+}
+
+impl mio::Handler for Tcping {
+    type Timeout = Timeout;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Tcping>, token: mio::Token, _: mio::EventSet) {
+        assert_eq!(token, CLIENT);
+
+        let probe = match self.probe.take() {
+            Some(probe) => probe,
+            None => return,
+        };
+
+        let elapsed = probe.started.elapsed();
+        let ms = elapsed.as_secs() as f64 * 1_000.0 + elapsed.subsec_nanos() as f64 / 1e6;
+
+        event_loop.clear_timeout(probe.deadline);
+
+        // A failed handshake is reported as writable too, the socket's
+        // pending error tells the two apart.
+        match probe.socket.take_socket_error() {
+            Ok(()) => {
+                println!("connected to {}: seq={} time={:.3} ms", self.addr, self.seq, ms);
+                self.rtts.push(ms);
+            }
+            Err(e) => {
+                println!("failed to connect to {}: seq={} err={}", self.addr, self.seq, e);
+                self.failed += 1;
+            }
+        }
+
+        // Dropping the probe closes the connection, the handshake was all
+        // that was needed.
+        drop(probe);
+        self.next(event_loop);
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Tcping>, timeout: Timeout) {
+        match timeout {
+            Timeout::Next => self.start(event_loop),
+            Timeout::Deadline => {
+                println!("no response from {}: seq={}", self.addr, self.seq);
+
+                self.probe = None;
+                self.failed += 1;
+                self.next(event_loop);
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    // Host names are resolved once, with the blocking resolver, before the
+    // event loop starts.
+    let addr = match args.next().and_then(|target| target.to_socket_addrs().ok()).and_then(|mut addrs| addrs.next()) {
+        Some(addr) => addr,
+        None => {
+            println!("usage: tcping <host:port> [count]");
+            process::exit(1);
+        }
+    };
+
+    let count: u64 = args.next().map(|s| s.parse().unwrap()).unwrap_or(4);
+
+    if count == 0 {
+        println!("count must be at least 1");
+        process::exit(1);
+    }
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    let mut tcping = Tcping::new(addr, count);
+
+    println!("TCPING {}", addr);
+
+    tcping.start(&mut event_loop);
+
+    // A single probe may have failed right away, leaving nothing to wait for
+    if tcping.probe.is_some() || tcping.seq < count {
+        event_loop.run(&mut tcping).unwrap();
+    }
+}
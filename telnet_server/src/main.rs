@@ -0,0 +1,376 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+const MAX_LINE: usize = 256;
+
+// Telnet commands (RFC 854)
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+// Telnet options
+const OPT_ECHO: u8 = 1;
+const OPT_SGA: u8 = 3;
+
+const PROMPT: &[u8] = b"> ";
+
+struct Telnet {
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+}
+
+impl Telnet {
+    fn new(acceptor: Acceptor<Factory<Connection>>) -> Telnet {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Telnet {
+            acceptor: acceptor,
+            connections: slab,
+        }
+    }
+}
+
+impl mio::Handler for Telnet {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Telnet>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                let (tokens, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+                for token in tokens {
+                    println!("accepted a new client socket; token={:?}", token);
+                }
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
+                }
+            }
+            _ => {
+                self.connections[token].ready(event_loop, events);
+
+                if self.connections[token].closed {
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+}
+
+// Where the parser is in the byte stream. Telnet commands are embedded in
+// the data, and a read can stop anywhere, including in the middle of a
+// command, so the parser's position must survive between reads.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Parse {
+    Data,
+    // Received IAC
+    Iac,
+    // Received IAC followed by WILL, WONT, DO or DONT. Waiting for the option.
+    Negotiate(u8),
+    // Inside a subnegotiation (IAC SB ... IAC SE), which is skipped
+    Subneg,
+    // Received IAC inside a subnegotiation
+    SubnegIac,
+    // Received CR, which is followed by either LF or NUL
+    Cr,
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    parse: Parse,
+    // Whether the server is echoing input back to the client. The client's
+    // terminal stops doing local echo when the server takes this on.
+    echo: bool,
+    // Set while the server's own `WILL ECHO` is waiting for an answer
+    echo_requested: bool,
+    line: Vec<u8>,
+    out: Vec<u8>,
+    // Set when the connection should be closed once `out` is flushed
+    quit: bool,
+    closed: bool,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        Connection::interest(self)
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        let mut conn = Connection {
+            socket: socket,
+            token: token,
+            parse: Parse::Data,
+            echo: false,
+            echo_requested: true,
+            line: Vec::with_capacity(MAX_LINE),
+            out: vec![],
+            quit: false,
+            closed: false,
+        };
+
+        // Ask to switch the client to "character at a time" mode: the server
+        // echoes and neither side sends go-aheads.
+        conn.out.extend(&[IAC, WILL, OPT_ECHO, IAC, WILL, OPT_SGA]);
+        conn.out.extend(b"Welcome to the mio telnet server. Type `help` for a list of commands.\r\n");
+        conn.out.extend(PROMPT);
+
+        conn
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Telnet>, events: mio::EventSet) {
+        if events.is_readable() {
+            self.read();
+        }
+
+        if events.is_writable() || !self.out.is_empty() {
+            self.write();
+        }
+
+        if !self.closed {
+            event_loop.reregister(&self.socket, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn read(&mut self) {
+        let mut buf = [0; 1024];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    for &b in &buf[..n] {
+                        self.parse(b);
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    // Feeds a single byte through the parser. Control sequences are handled
+    // and dropped, anything else is passed on to the line editor.
+    fn parse(&mut self, b: u8) {
+        self.parse = match (self.parse, b) {
+            (Parse::Data, IAC) => Parse::Iac,
+            (Parse::Data, b'\r') => Parse::Cr,
+            (Parse::Data, b) => {
+                self.input(b);
+                Parse::Data
+            }
+            // IAC IAC is an escaped 255 data byte
+            (Parse::Iac, IAC) => {
+                self.input(IAC);
+                Parse::Data
+            }
+            (Parse::Iac, WILL) | (Parse::Iac, WONT) | (Parse::Iac, DO) | (Parse::Iac, DONT) => Parse::Negotiate(b),
+            (Parse::Iac, SB) => Parse::Subneg,
+            // Any other command (NOP, AYT, GA, ...) is ignored
+            (Parse::Iac, _) => Parse::Data,
+            (Parse::Negotiate(cmd), opt) => {
+                self.negotiate(cmd, opt);
+                Parse::Data
+            }
+            (Parse::Subneg, IAC) => Parse::SubnegIac,
+            (Parse::Subneg, _) => Parse::Subneg,
+            (Parse::SubnegIac, SE) => Parse::Data,
+            (Parse::SubnegIac, _) => Parse::Subneg,
+            // CR LF and CR NUL both mean "end of line"
+            (Parse::Cr, b'\n') | (Parse::Cr, 0) => {
+                self.input(b'\n');
+                Parse::Data
+            }
+            (Parse::Cr, IAC) => {
+                self.input(b'\n');
+                Parse::Iac
+            }
+            (Parse::Cr, b) => {
+                self.input(b'\n');
+                self.input(b);
+                Parse::Data
+            }
+        };
+    }
+
+    // Responds to an option negotiation request. A side must only reply when
+    // the request changes the state of the option, otherwise two peers can
+    // end up acknowledging each other forever.
+    fn negotiate(&mut self, cmd: u8, opt: u8) {
+        match (cmd, opt) {
+            (DO, OPT_ECHO) => {
+                if self.echo_requested {
+                    // The client accepted the server's offer
+                    self.echo_requested = false;
+                    self.echo = true;
+                } else if !self.echo {
+                    self.echo = true;
+                    self.out.extend(&[IAC, WILL, OPT_ECHO]);
+                }
+            }
+            (DONT, OPT_ECHO) => {
+                if self.echo_requested {
+                    // The client refused the server's offer
+                    self.echo_requested = false;
+                } else if self.echo {
+                    self.echo = false;
+                    self.out.extend(&[IAC, WONT, OPT_ECHO]);
+                }
+            }
+            (DO, OPT_SGA) | (DONT, OPT_SGA) => {
+                // The server never sends go-aheads either way
+            }
+            (WILL, OPT_SGA) | (WONT, _) => {
+                // The client may suppress go-aheads, and refusing an option
+                // needs no reply.
+            }
+            (DO, opt) => self.out.extend(&[IAC, WONT, opt]),
+            (WILL, opt) => self.out.extend(&[IAC, DONT, opt]),
+            _ => {}
+        }
+    }
+
+    // The line editor. Handles backspace and runs a command at the end of
+    // each line.
+    fn input(&mut self, b: u8) {
+        match b {
+            b'\n' => {
+                if self.echo {
+                    self.out.extend(b"\r\n");
+                }
+
+                let line = String::from_utf8_lossy(&self.line).into_owned();
+                self.line.clear();
+
+                self.command(line.trim());
+
+                if !self.quit {
+                    self.out.extend(PROMPT);
+                }
+            }
+            // Backspace and DEL
+            0x08 | 0x7f => {
+                let erased = self.line.pop().is_some();
+
+                if erased && self.echo {
+                    self.out.extend(b"\x08 \x08");
+                }
+            }
+            b if b >= 0x20 && self.line.len() < MAX_LINE => {
+                self.line.push(b);
+
+                if self.echo && b == IAC {
+                    // Data bytes equal to IAC must be escaped on the wire
+                    self.out.extend(&[IAC, IAC]);
+                } else if self.echo {
+                    self.out.push(b);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn command(&mut self, line: &str) {
+        let mut parts = line.splitn(2, ' ');
+        let cmd = parts.next().unwrap_or("");
+        let arg = parts.next().unwrap_or("").trim();
+
+        println!("running command; line={:?}", line);
+
+        let response = match cmd {
+            "" => return,
+            "help" => "commands: help, echo <text>, rev <text>, echo-mode, quit".to_string(),
+            "echo" => arg.to_string(),
+            "rev" => arg.chars().rev().collect(),
+            "echo-mode" => format!("server echo is {}", if self.echo { "on" } else { "off" }),
+            "quit" => {
+                self.quit = true;
+                "bye".to_string()
+            }
+            _ => format!("unknown command: {}", cmd),
+        };
+
+        self.out.extend(response.as_bytes());
+        self.out.extend(b"\r\n");
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        if self.quit {
+            self.closed = true;
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:2323".to_string())
+        .parse().unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mut telnet = Telnet::new(acceptor);
+
+    println!("running telnet server; addr={:?}", address);
+    event_loop.run(&mut telnet).unwrap();
+}
@@ -0,0 +1,571 @@
+extern crate mio;
+extern crate bytes;
+extern crate root_dir;
+
+use mio::udp::*;
+use bytes::SliceBuf;
+use root_dir::resolve;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Opcodes
+const RRQ: u16 = 1;
+const WRQ: u16 = 2;
+const DATA: u16 = 3;
+const ACK: u16 = 4;
+const ERROR: u16 = 5;
+
+// Error codes
+const NOT_DEFINED: u16 = 0;
+const FILE_NOT_FOUND: u16 = 1;
+const ACCESS_VIOLATION: u16 = 2;
+const DISK_FULL: u16 = 3;
+const ILLEGAL_OPERATION: u16 = 4;
+const UNKNOWN_TRANSFER: u16 = 5;
+const FILE_EXISTS: u16 = 6;
+
+// Every DATA packet carries a full block except the last one, which is
+// how the receiver knows the transfer is over.
+const BLOCK_SIZE: usize = 512;
+
+// Opcode, block number and a block
+const MAX_PACKET: usize = 4 + BLOCK_SIZE;
+
+// How long to wait for the client before resending the last packet
+const RETRANSMIT_MS: u64 = 1_000;
+
+// How many times a packet is resent before giving up on the transfer
+const MAX_RETRIES: u32 = 5;
+
+// Transfers at once, at most. A request is a single datagram, from an
+// address anyone can forge, and every transfer holds a file and a timer.
+const MAX_TRANSFERS: usize = 1_024;
+
+struct Server {
+    socket: UdpSocket,
+    root: PathBuf,
+    // RFC 1350 has the server pick a new port for every transfer. Replying
+    // from the server port works with real clients too, and lets the one
+    // socket serve every transfer, keyed by the client's address.
+    transfers: HashMap<SocketAddr, Transfer>,
+}
+
+struct Transfer {
+    direction: Direction,
+    // The last block sent, when reading, or received, when writing
+    block: u16,
+    // The last packet sent, resent if the client doesn't answer in time
+    last: Vec<u8>,
+    // Set once the final, short, block has been sent or received
+    done: bool,
+    retries: u32,
+    timeout: mio::Timeout,
+}
+
+enum Direction {
+    // The client is reading the file
+    Read(File),
+    // The client is writing the file at the path
+    Write(File, PathBuf),
+}
+
+impl Server {
+    fn new(socket: UdpSocket, root: PathBuf) -> Server {
+        Server {
+            socket: socket,
+            root: root,
+            transfers: HashMap::new(),
+        }
+    }
+
+    fn packet(&mut self, event_loop: &mut mio::EventLoop<Server>, addr: SocketAddr, packet: &[u8]) {
+        match Packet::parse(packet) {
+            Some(Packet::Read(filename, mode)) => self.request(event_loop, addr, filename, mode, false),
+            Some(Packet::Write(filename, mode)) => self.request(event_loop, addr, filename, mode, true),
+            Some(Packet::Data(block, data)) => self.data(event_loop, addr, block, data),
+            Some(Packet::Ack(block)) => self.ack(event_loop, addr, block),
+            Some(Packet::Error(code, message)) => {
+                println!("client aborted transfer; code={}; message={:?}; addr={:?}", code, message, addr);
+
+                if let Some(transfer) = self.transfers.remove(&addr) {
+                    abort(event_loop, transfer);
+                }
+            }
+            None => {
+                println!("malformed packet; addr={:?}", addr);
+                self.fail(event_loop, addr, error(ILLEGAL_OPERATION, "malformed packet"));
+            }
+        }
+    }
+
+    fn request(&mut self, event_loop: &mut mio::EventLoop<Server>, addr: SocketAddr, filename: &str, mode: &str, write: bool) {
+        // A repeated request means the reply to the first one was lost
+        if let Some(transfer) = self.transfers.get(&addr) {
+            send(&self.socket, &transfer.last, &addr);
+            return;
+        }
+
+        // Netascii would mean translating line endings, in blocks that can
+        // split a CR LF pair.
+        if !mode.eq_ignore_ascii_case("octet") {
+            println!("refusing transfer; mode={:?}; addr={:?}", mode, addr);
+            send(&self.socket, &error(NOT_DEFINED, "only octet mode is supported"), &addr);
+            return;
+        }
+
+        if self.transfers.len() == MAX_TRANSFERS {
+            println!("too many transfers, refusing transfer; addr={:?}", addr);
+            send(&self.socket, &error(NOT_DEFINED, "server busy, try again later"), &addr);
+            return;
+        }
+
+        let path = match resolve(&self.root, filename) {
+            Some(path) => path,
+            None => {
+                println!("refusing transfer outside of the root; file={:?}; addr={:?}", filename, addr);
+                send(&self.socket, &error(ACCESS_VIOLATION, "invalid file name"), &addr);
+                return;
+            }
+        };
+
+        // Taken before the file is opened, or created, so that a full timer
+        // leaves no file behind
+        let timeout = match event_loop.timeout_ms(addr, RETRANSMIT_MS) {
+            Ok(timeout) => timeout,
+            Err(_) => {
+                println!("timer full, refusing transfer; addr={:?}", addr);
+                send(&self.socket, &error(NOT_DEFINED, "server busy, try again later"), &addr);
+                return;
+            }
+        };
+
+        let started = if write {
+            start_write(path)
+        } else {
+            start_read(path)
+        };
+
+        let (direction, last, done) = match started {
+            Ok(started) => started,
+            Err(e) => {
+                println!("refusing transfer; file={:?}; err={:?}; addr={:?}", filename, e, addr);
+                send(&self.socket, &io_error(&e), &addr);
+                event_loop.clear_timeout(timeout);
+                return;
+            }
+        };
+
+        println!("starting transfer; file={:?}; write={}; addr={:?}", filename, write, addr);
+
+        send(&self.socket, &last, &addr);
+
+        self.transfers.insert(addr, Transfer {
+            direction: direction,
+            block: if write { 0 } else { 1 },
+            last: last,
+            done: done,
+            retries: 0,
+            timeout: timeout,
+        });
+    }
+
+    // The client received a block of the file it is reading
+    fn ack(&mut self, event_loop: &mut mio::EventLoop<Server>, addr: SocketAddr, block: u16) {
+        let mut transfer = match self.transfers.remove(&addr) {
+            Some(transfer) => transfer,
+            None => {
+                send(&self.socket, &error(UNKNOWN_TRANSFER, "unknown transfer"), &addr);
+                return;
+            }
+        };
+
+        let next = match transfer.direction {
+            Direction::Read(ref mut file) => {
+                // A duplicate ACK for the previous block is ignored. Answering
+                // it would send every following block twice (the "Sorcerer's
+                // Apprentice" bug), the timer takes care of lost packets.
+                if block != transfer.block || transfer.done {
+                    None
+                } else {
+                    Some(read_block(file))
+                }
+            }
+            Direction::Write(..) => {
+                send(&self.socket, &error(ILLEGAL_OPERATION, "unexpected ACK"), &addr);
+                return abort(event_loop, transfer);
+            }
+        };
+
+        match next {
+            Some(Ok(data)) => {
+                transfer.block = transfer.block.wrapping_add(1);
+                transfer.last = data_packet(transfer.block, &data);
+                transfer.done = data.len() < BLOCK_SIZE;
+                self.resend(event_loop, addr, transfer);
+            }
+            Some(Err(e)) => {
+                println!("failed to read file; err={:?}; addr={:?}", e, addr);
+                send(&self.socket, &io_error(&e), &addr);
+                abort(event_loop, transfer);
+            }
+            None if transfer.done && block == transfer.block => {
+                println!("transfer complete; blocks={}; addr={:?}", transfer.block, addr);
+                event_loop.clear_timeout(transfer.timeout);
+            }
+            None => {
+                self.transfers.insert(addr, transfer);
+            }
+        }
+    }
+
+    // The client sent a block of the file it is writing
+    fn data(&mut self, event_loop: &mut mio::EventLoop<Server>, addr: SocketAddr, block: u16, data: &[u8]) {
+        let mut transfer = match self.transfers.remove(&addr) {
+            Some(transfer) => transfer,
+            None => {
+                send(&self.socket, &error(UNKNOWN_TRANSFER, "unknown transfer"), &addr);
+                return;
+            }
+        };
+
+        let written = match transfer.direction {
+            Direction::Write(ref mut file, _) => {
+                if block == transfer.block.wrapping_add(1) && !transfer.done {
+                    Some(file.write_all(data))
+                } else {
+                    None
+                }
+            }
+            Direction::Read(..) => {
+                send(&self.socket, &error(ILLEGAL_OPERATION, "unexpected DATA"), &addr);
+                return abort(event_loop, transfer);
+            }
+        };
+
+        match written {
+            Some(Ok(())) => {
+                transfer.block = block;
+                transfer.last = ack_packet(block);
+                transfer.done = data.len() < BLOCK_SIZE;
+
+                if transfer.done {
+                    println!("file received; blocks={}; addr={:?}", block, addr);
+                }
+
+                self.resend(event_loop, addr, transfer);
+            }
+            Some(Err(e)) => {
+                println!("failed to write file; err={:?}; addr={:?}", e, addr);
+                send(&self.socket, &io_error(&e), &addr);
+                abort(event_loop, transfer);
+            }
+            None => {
+                // The client didn't get the ACK for the block, and sent it
+                // again.
+                if block == transfer.block {
+                    send(&self.socket, &transfer.last, &addr);
+                }
+
+                self.transfers.insert(addr, transfer);
+            }
+        }
+    }
+
+    // Sends the transfer's last packet and restarts its timer
+    fn resend(&mut self, event_loop: &mut mio::EventLoop<Server>, addr: SocketAddr, transfer: Transfer) {
+        send(&self.socket, &transfer.last, &addr);
+
+        event_loop.clear_timeout(transfer.timeout);
+        self.rearm(event_loop, addr, transfer);
+    }
+
+    // Sets the transfer's timer and keeps it, or ends it with an error if
+    // the timer is full: a transfer that can't resend can't go on
+    fn rearm(&mut self, event_loop: &mut mio::EventLoop<Server>, addr: SocketAddr, mut transfer: Transfer) {
+        match event_loop.timeout_ms(addr, RETRANSMIT_MS) {
+            Ok(timeout) => {
+                transfer.timeout = timeout;
+                self.transfers.insert(addr, transfer);
+            }
+            Err(_) => {
+                println!("timer full, abandoning transfer; addr={:?}", addr);
+                send(&self.socket, &error(NOT_DEFINED, "server busy, try again later"), &addr);
+                abort(event_loop, transfer);
+            }
+        }
+    }
+
+    // Sends an error, ending the client's transfer if it has one
+    fn fail(&mut self, event_loop: &mut mio::EventLoop<Server>, addr: SocketAddr, packet: Vec<u8>) {
+        send(&self.socket, &packet, &addr);
+
+        if let Some(transfer) = self.transfers.remove(&addr) {
+            abort(event_loop, transfer);
+        }
+    }
+}
+
+impl mio::Handler for Server {
+    type Timeout = SocketAddr;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, events: mio::EventSet) {
+        assert_eq!(token, SERVER);
+        assert!(events.is_readable(), "unexpected events; events={:?}", events);
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            let mut buf = Vec::with_capacity(MAX_PACKET);
+
+            match self.socket.recv_from(&mut buf) {
+                Ok(Some(addr)) => self.packet(event_loop, addr, &buf),
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Server>, addr: SocketAddr) {
+        let mut transfer = match self.transfers.remove(&addr) {
+            Some(transfer) => transfer,
+            None => return,
+        };
+
+        // The final ACK of a write isn't acknowledged. The transfer was kept
+        // around for a timeout in case the client resent the final block,
+        // which would mean the ACK was lost.
+        if let Direction::Write(..) = transfer.direction {
+            if transfer.done {
+                return;
+            }
+        }
+
+        if transfer.retries == MAX_RETRIES {
+            println!("client stopped responding, abandoning transfer; addr={:?}", addr);
+            return abort(event_loop, transfer);
+        }
+
+        transfer.retries += 1;
+        send(&self.socket, &transfer.last, &addr);
+
+        self.rearm(event_loop, addr, transfer);
+    }
+}
+
+// Ends a transfer that didn't complete. A partially written file is
+// removed rather than left truncated.
+fn abort(event_loop: &mut mio::EventLoop<Server>, transfer: Transfer) {
+    event_loop.clear_timeout(transfer.timeout);
+
+    if let Direction::Write(file, path) = transfer.direction {
+        drop(file);
+        let _ = fs::remove_file(path);
+    }
+}
+
+// Sends a datagram. If the socket isn't ready the datagram is dropped, and
+// either side will resend its last packet once its timer fires.
+fn send(socket: &UdpSocket, packet: &[u8], addr: &SocketAddr) {
+    match socket.send_to(&mut SliceBuf::wrap(packet), addr) {
+        Ok(Some(())) => {}
+        Ok(None) => println!("the socket wasn't actually ready, dropping datagram; addr={:?}", addr),
+        Err(e) => println!("failed to send datagram; addr={:?}; err={:?}", addr, e),
+    }
+}
+
+/*
+ *
+ * ===== Files =====
+ *
+ */
+
+// Opens the file and reads the first block. Returns the packet to send and
+// whether it is also the last.
+fn start_read(path: PathBuf) -> io::Result<(Direction, Vec<u8>, bool)> {
+    if !fs::metadata(&path)?.is_file() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "not a file"));
+    }
+
+    let mut file = File::open(&path)?;
+    let data = read_block(&mut file)?;
+
+    Ok((Direction::Read(file), data_packet(1, &data), data.len() < BLOCK_SIZE))
+}
+
+// Creates the file, and returns the ACK inviting the first block. Existing
+// files are never overwritten.
+fn start_write(path: PathBuf) -> io::Result<(Direction, Vec<u8>, bool)> {
+    let file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+
+    Ok((Direction::Write(file, path), ack_packet(0), false))
+}
+
+// Reads the next block. It is only short at the end of the file: a file
+// whose length is a multiple of the block size ends with an empty block.
+fn read_block(file: &mut File) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(BLOCK_SIZE);
+    file.take(BLOCK_SIZE as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/*
+ *
+ * ===== Packets =====
+ *
+ */
+
+#[derive(Debug, PartialEq)]
+enum Packet<'a> {
+    Read(&'a str, &'a str),
+    Write(&'a str, &'a str),
+    Data(u16, &'a [u8]),
+    Ack(u16),
+    Error(u16, String),
+}
+
+impl<'a> Packet<'a> {
+    fn parse(packet: &'a [u8]) -> Option<Packet<'a>> {
+        match read_u16(packet, 0)? {
+            op @ RRQ | op @ WRQ => {
+                // The file name and mode are NUL terminated. Options (RFC
+                // 2347) may follow, they are ignored, which tells the
+                // client to go without.
+                let mut fields = packet[2..].split(|b| *b == 0);
+                let filename = str::from_utf8(fields.next()?).ok()?;
+                let mode = str::from_utf8(fields.next()?).ok()?;
+
+                // The mode's terminator was missing
+                fields.next()?;
+
+                if op == RRQ {
+                    Some(Packet::Read(filename, mode))
+                } else {
+                    Some(Packet::Write(filename, mode))
+                }
+            }
+            DATA => Some(Packet::Data(read_u16(packet, 2)?, &packet[4..])),
+            ACK => Some(Packet::Ack(read_u16(packet, 2)?)),
+            ERROR => {
+                let code = read_u16(packet, 2)?;
+                let message = packet[4..].split(|b| *b == 0).next()?;
+                Some(Packet::Error(code, String::from_utf8_lossy(message).into_owned()))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn data_packet(block: u16, data: &[u8]) -> Vec<u8> {
+    let mut packet = header(DATA, block);
+    packet.extend(data);
+    packet
+}
+
+fn ack_packet(block: u16) -> Vec<u8> {
+    header(ACK, block)
+}
+
+fn error(code: u16, message: &str) -> Vec<u8> {
+    let mut packet = header(ERROR, code);
+    packet.extend(message.as_bytes());
+    packet.push(0);
+    packet
+}
+
+// Maps a file system error onto the closest TFTP error
+fn io_error(e: &io::Error) -> Vec<u8> {
+    let code = match e.kind() {
+        io::ErrorKind::NotFound => FILE_NOT_FOUND,
+        io::ErrorKind::PermissionDenied => ACCESS_VIOLATION,
+        io::ErrorKind::StorageFull => DISK_FULL,
+        io::ErrorKind::AlreadyExists => FILE_EXISTS,
+        _ => NOT_DEFINED,
+    };
+
+    error(code, &e.to_string())
+}
+
+// Every packet but requests starts with the opcode and a second u16, the
+// block number or the error code.
+fn header(opcode: u16, n: u16) -> Vec<u8> {
+    vec![(opcode >> 8) as u8, opcode as u8, (n >> 8) as u8, n as u8]
+}
+
+fn read_u16(packet: &[u8], pos: usize) -> Option<u16> {
+    let b = packet.get(pos..pos + 2)?;
+    Some((b[0] as u16) << 8 | b[1] as u16)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let root = PathBuf::from(args.next().unwrap_or("files".to_string()));
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:6969".to_string()).parse().unwrap();
+
+    fs::create_dir_all(&root).unwrap();
+
+    let socket = UdpSocket::bound(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register_opt(&socket, SERVER, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+
+    let mut server = Server::new(socket, root.clone());
+
+    println!("running tftp server; addr={:?}; root={:?}", address, root);
+    event_loop.run(&mut server).unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_packets() {
+        assert_eq!(Packet::parse(b"\0\x01notes.txt\0octet\0"), Some(Packet::Read("notes.txt", "octet")));
+        assert_eq!(Packet::parse(b"\0\x02notes.txt\0OCTET\0"), Some(Packet::Write("notes.txt", "OCTET")));
+        assert_eq!(Packet::parse(b"\0\x03\0\x07abc"), Some(Packet::Data(7, b"abc")));
+        assert_eq!(Packet::parse(b"\0\x03\0\x07"), Some(Packet::Data(7, b"")));
+        assert_eq!(Packet::parse(b"\0\x04\x01\x02"), Some(Packet::Ack(0x0102)));
+        assert_eq!(Packet::parse(b"\0\x05\0\x01not found\0"), Some(Packet::Error(1, "not found".to_string())));
+    }
+
+    #[test]
+    fn ignores_request_options() {
+        let packet = [&b"\0\x01notes.txt\0octet\0"[..], b"blksize\0", b"1428\0"].concat();
+        assert_eq!(Packet::parse(&packet), Some(Packet::Read("notes.txt", "octet")));
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        let request = b"\0\x01notes.txt\0octet\0";
+
+        for len in 0..request.len() {
+            assert_eq!(Packet::parse(&request[..len]), None);
+        }
+
+        assert_eq!(Packet::parse(b"\0\x03\0"), None);
+        assert_eq!(Packet::parse(b"\0\x04\0"), None);
+        assert_eq!(Packet::parse(b"\0\x05\0"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_packets() {
+        assert_eq!(Packet::parse(b"\0\x06\0\x01"), None);
+        assert_eq!(Packet::parse(b"\x01\x01notes.txt\0octet\0"), None);
+        assert_eq!(Packet::parse(b"\0\x01\xffnotes\0octet\0"), None);
+    }
+
+    #[test]
+    fn builds_packets() {
+        assert_eq!(data_packet(2, b"abc"), b"\0\x03\0\x02abc");
+        assert_eq!(ack_packet(0x0102), b"\0\x04\x01\x02");
+        assert_eq!(error(FILE_EXISTS, "exists"), b"\0\x05\0\x06exists\0");
+    }
+}
@@ -0,0 +1,198 @@
+extern crate mio;
+extern crate bytes;
+
+use mio::TryWrite;
+use mio::tcp::*;
+use mio::udp::*;
+use mio::util::Slab;
+use bytes::SliceBuf;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::env;
+
+const TCP_SERVER: mio::Token = mio::Token(0);
+const UDP_SERVER: mio::Token = mio::Token(1);
+
+// Seconds between the time protocol epoch (1900) and the Unix epoch (1970)
+const EPOCH_OFFSET: u64 = 2_208_988_800;
+
+struct TimeServer {
+    tcp: TcpListener,
+    udp: UdpSocket,
+    connections: Slab<Connection>,
+}
+
+impl TimeServer {
+    fn new(tcp: TcpListener, udp: UdpSocket) -> TimeServer {
+        // Tokens `0` and `1` are reserved for the server sockets. The slab
+        // is initialized to return Tokens starting at 2.
+        let slab = Slab::new_starting_at(mio::Token(2), 1024);
+
+        TimeServer {
+            tcp: tcp,
+            udp: udp,
+            connections: slab,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<TimeServer>) {
+        match self.tcp.accept() {
+            Ok(Some(socket)) => {
+                // The time is captured when the connection is accepted, not
+                // when the socket becomes writable.
+                let conn = Connection::new(socket, now());
+
+                let token = match self.connections.insert(conn) {
+                    Ok(token) => token,
+                    Err(_) => {
+                        // Dropping the connection closes it
+                        println!("connection limit reached, dropping client");
+                        return;
+                    }
+                };
+
+                // The server never reads from the client, only wait for the
+                // socket to become writable.
+                event_loop.register_opt(
+                    &self.connections[token].socket,
+                    token,
+                    mio::EventSet::writable(),
+                    mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+            }
+            Ok(None) => {
+                println!("the server socket wasn't actually ready");
+            }
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+            }
+        }
+    }
+
+    fn reply(&mut self) {
+        // The socket is registered as edge triggered, so all available
+        // datagrams must be read before waiting for the next event. The
+        // contents of the datagram don't matter.
+        loop {
+            let mut buf = Vec::with_capacity(512);
+
+            let addr = match self.udp.recv_from(&mut buf) {
+                Ok(Some(addr)) => addr,
+                Ok(None) => return,
+                Err(e) => panic!("got an error trying to receive; err={:?}", e),
+            };
+
+            println!("replying to datagram; addr={:?}", addr);
+
+            match self.udp.send_to(&mut SliceBuf::wrap(&now()), &addr) {
+                Ok(Some(())) => {}
+                Ok(None) => println!("the socket wasn't actually ready, dropping reply"),
+                Err(e) => println!("failed to send reply; addr={:?}; err={:?}", addr, e),
+            }
+        }
+    }
+}
+
+impl mio::Handler for TimeServer {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<TimeServer>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            TCP_SERVER => {
+                assert!(events.is_readable());
+                self.accept(event_loop);
+            }
+            UDP_SERVER => {
+                assert!(events.is_readable());
+                self.reply();
+            }
+            _ => {
+                self.connections[token].write(event_loop, token);
+
+                // Once the time has been written the connection is closed by
+                // removing it from the slab, which drops the socket.
+                if self.connections[token].is_done() {
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    buf: [u8; 4],
+    // Number of bytes of `buf` written so far
+    pos: usize,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, time: [u8; 4]) -> Connection {
+        Connection {
+            socket: socket,
+            buf: time,
+            pos: 0,
+            closed: false,
+        }
+    }
+
+    fn write(&mut self, event_loop: &mut mio::EventLoop<TimeServer>, token: mio::Token) {
+        match self.socket.try_write(&self.buf[self.pos..]) {
+            Ok(Some(n)) => {
+                self.pos += n;
+
+                // A four byte write is all but guaranteed to complete at
+                // once, but it is still possible for it to be partial.
+                if !self.is_done() {
+                    self.reregister(event_loop, token);
+                }
+            }
+            Ok(None) => {
+                self.reregister(event_loop, token);
+            }
+            Err(e) => {
+                println!("failed to write time; err={:?}", e);
+                self.closed = true;
+            }
+        }
+    }
+
+    fn reregister(&self, event_loop: &mut mio::EventLoop<TimeServer>, token: mio::Token) {
+        event_loop.reregister(&self.socket, token, mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn is_done(&self) -> bool {
+        self.closed || self.pos == self.buf.len()
+    }
+}
+
+// The current time as the number of seconds since 1900, big endian.
+fn now() -> [u8; 4] {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let secs = (secs + EPOCH_OFFSET) as u32;
+
+    [(secs >> 24) as u8, (secs >> 16) as u8, (secs >> 8) as u8, secs as u8]
+}
+
+fn main() {
+    // The protocol's well known port is 37, which requires root
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:3737".to_string())
+        .parse().unwrap();
+
+    let tcp = TcpListener::bind(&address).unwrap();
+    let udp = UdpSocket::bound(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&tcp, TCP_SERVER).unwrap();
+    event_loop.register_opt(&udp, UDP_SERVER, mio::EventSet::readable(), mio::PollOpt::edge()).unwrap();
+
+    let mut server = TimeServer::new(tcp, udp);
+
+    println!("running time server; addr={:?}", address);
+    event_loop.run(&mut server).unwrap();
+}
@@ -0,0 +1,132 @@
+// A deadline per connection, such as an idle timeout, kept by token on
+// behalf of the handler. A connection has at most one: setting it again
+// replaces the one it had, pushing it back or bringing it forward.
+//
+// An idle timeout is reset on every event the connection gets. Clearing
+// the event loop timeout and setting a new one each time is two trips
+// through mio's timer for a deadline that almost never fires. Here,
+// pushing a deadline back only writes down the new time: the event loop
+// timeout set for the old one is left alone, and when it fires, it is set
+// again for what is left. A connection that is never idle for long costs
+// a timeout firing per timeout period, however many events it has. Only
+// bringing a deadline forward clears the timeout to set an earlier one.
+//
+// Expirations come through `Handler::timeout`, as the connection's token,
+// which the handler hands back to find out whether the deadline has
+// actually passed:
+//
+//     fn timeout(&mut self, event_loop: &mut EventLoop<Server>, token: mio::Token) {
+//         if self.timeouts.expired(event_loop, token) {
+//             self.close(event_loop, token);
+//         }
+//     }
+//
+// A handler with timeouts of its own implements `From<mio::Token>` for its
+// `Timeout` type, and passes the tokens it gets back on to `expired`.
+//
+// Deadlines are kept in a slot per token, the way the connections are
+// kept in their slab, so that every operation is constant time.
+
+extern crate mio;
+
+use mio::{EventLoop, Handler, Token};
+use std::time::{Duration, Instant};
+
+struct Entry {
+    deadline: Instant,
+    // The event loop timeout, and when it was set to fire: never after
+    // the deadline, before it if the deadline was pushed back since
+    timeout: mio::Timeout,
+    fires: Instant,
+}
+
+pub struct Timeouts {
+    entries: Vec<Option<Entry>>,
+}
+
+impl Timeouts {
+    pub fn new() -> Timeouts {
+        Timeouts { entries: vec![] }
+    }
+
+    // Sets the token's deadline `ms` from now, in place of the one it had
+    pub fn set<H>(&mut self, event_loop: &mut EventLoop<H>, token: Token, ms: u64)
+        where H: Handler,
+              H::Timeout: From<Token>,
+    {
+        let deadline = Instant::now() + Duration::from_millis(ms);
+        let index = token.as_usize();
+
+        if index >= self.entries.len() {
+            self.entries.resize_with(index + 1, || None);
+        }
+
+        match self.entries[index] {
+            // The timeout already set fires first, and is set again then
+            Some(ref mut entry) if entry.fires <= deadline => {
+                entry.deadline = deadline;
+                return;
+            }
+            Some(ref entry) => {
+                event_loop.clear_timeout(entry.timeout);
+            }
+            None => {}
+        }
+
+        let timeout = event_loop.timeout_ms(H::Timeout::from(token), ms).unwrap();
+
+        self.entries[index] = Some(Entry {
+            deadline: deadline,
+            timeout: timeout,
+            fires: deadline,
+        });
+    }
+
+    // Removes the token's deadline, if it has one. This must be done
+    // before the token is reused, or the timeout fires for the next
+    // connection.
+    pub fn clear<H: Handler>(&mut self, event_loop: &mut EventLoop<H>, token: Token) {
+        if let Some(entry) = self.entries.get_mut(token.as_usize()).and_then(Option::take) {
+            event_loop.clear_timeout(entry.timeout);
+        }
+    }
+
+    // Called with a token whose timeout fired. Returns whether its deadline
+    // has passed, removing it. If the deadline was pushed back in the
+    // meantime, the timeout is set again for what is left.
+    pub fn expired<H>(&mut self, event_loop: &mut EventLoop<H>, token: Token) -> bool
+        where H: Handler,
+              H::Timeout: From<Token>,
+    {
+        let now = Instant::now();
+
+        let slot = match self.entries.get_mut(token.as_usize()) {
+            Some(slot) => slot,
+            None => return false,
+        };
+
+        match *slot {
+            Some(ref mut entry) if entry.deadline > now => {
+                // Rounded up, firing a millisecond late is better than a
+                // spurious wake up just before the deadline
+                let left = entry.deadline - now;
+                let ms = (left.as_micros() as u64).div_ceil(1_000);
+
+                entry.timeout = event_loop.timeout_ms(H::Timeout::from(token), ms).unwrap();
+                entry.fires = entry.deadline;
+                false
+            }
+            Some(_) => {
+                *slot = None;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts::new()
+    }
+}
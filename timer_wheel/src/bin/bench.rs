@@ -0,0 +1,99 @@
+extern crate mio;
+extern crate timer_wheel;
+
+use std::time::{Duration, Instant};
+use std::env;
+use timer_wheel::Wheel;
+
+// The idle server's timeout, 30 seconds of 100ms ticks
+const IDLE_TICKS: u64 = 300;
+const TICK_MS: u64 = 100;
+
+// Compares the timer wheel with giving every connection its own event loop
+// timeout. The cost measured is the one a busy server pays all the time:
+// every time a connection sees some activity, its idle deadline is pushed
+// back, by removing it and setting a new one.
+struct Naive;
+
+impl mio::Handler for Naive {
+    type Timeout = usize;
+    type Message = ();
+}
+
+fn wheel(connections: usize, rounds: usize) {
+    let mut wheel = Wheel::new();
+
+    let start = Instant::now();
+    let mut keys: Vec<_> = (0..connections).map(|i| wheel.insert(IDLE_TICKS, i)).collect();
+    report("wheel", "insert", start.elapsed(), connections);
+
+    let start = Instant::now();
+
+    for round in 0..rounds {
+        for (i, key) in keys.iter_mut().enumerate() {
+            wheel.remove(*key);
+            *key = wheel.insert(IDLE_TICKS, i);
+        }
+
+        // Time goes by between rounds, some of it while the deadlines are
+        // on the levels above the first one
+        if round % 10 == 0 {
+            wheel.tick();
+        }
+    }
+
+    report("wheel", "reset", start.elapsed(), connections * rounds);
+
+    let start = Instant::now();
+    let mut expired = 0;
+
+    while !wheel.is_empty() {
+        expired += wheel.tick().len();
+    }
+
+    report("wheel", "expire", start.elapsed(), expired);
+}
+
+fn naive(connections: usize, rounds: usize) {
+    // The timer needs room for every connection's timeout
+    let config = mio::EventLoopConfig {
+        timer_capacity: connections * 2,
+        ..mio::EventLoopConfig::default()
+    };
+
+    let mut event_loop: mio::EventLoop<Naive> = mio::EventLoop::configured(config).unwrap();
+
+    let start = Instant::now();
+    let mut timeouts: Vec<_> = (0..connections).map(|i| event_loop.timeout_ms(i, IDLE_TICKS * TICK_MS).unwrap()).collect();
+    report("naive", "insert", start.elapsed(), connections);
+
+    let start = Instant::now();
+
+    for _ in 0..rounds {
+        for (i, timeout) in timeouts.iter_mut().enumerate() {
+            event_loop.clear_timeout(*timeout);
+            *timeout = event_loop.timeout_ms(i, IDLE_TICKS * TICK_MS).unwrap();
+        }
+    }
+
+    report("naive", "reset", start.elapsed(), connections * rounds);
+}
+
+fn report(name: &str, operation: &str, elapsed: Duration, count: usize) {
+    let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+
+    println!("{:<6} {:<7} {:>10} ops in {:>5}ms, {:>6}ns/op",
+             name, operation, count, nanos / 1_000_000, nanos / std::cmp::max(count as u64, 1));
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let connections: usize = args.next().map(|s| s.parse().unwrap()).unwrap_or(10_000);
+    let rounds: usize = args.next().map(|s| s.parse().unwrap()).unwrap_or(100);
+
+    println!("connections={}; rounds={}", connections, rounds);
+
+    wheel(connections, rounds);
+    naive(connections, rounds);
+}
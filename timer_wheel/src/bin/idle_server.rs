@@ -0,0 +1,240 @@
+extern crate mio;
+extern crate timer_wheel;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::env;
+use timer_wheel::{Key, Wheel};
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Idle timeouts don't need to be precise, a tenth of a second is plenty
+const TICK_MS: u64 = 100;
+
+// Room for 10k connections and then some. The process needs as many file
+// descriptors, see `ulimit -n`.
+const MAX_CONNECTIONS: usize = 16_384;
+
+// An echo server closing connections that stay idle for too long. Every
+// connection's deadline is kept in the timer wheel, and pushed back every
+// time the client sends something. The event loop only has a single
+// timeout, firing every tick, to move the wheel forward.
+struct IdleServer {
+    server: TcpListener,
+    connections: Slab<Connection>,
+    wheel: Wheel<mio::Token>,
+    idle_ticks: u64,
+    // The number of connections last reported
+    reported: usize,
+}
+
+impl IdleServer {
+    fn new(server: TcpListener, idle_ticks: u64) -> IdleServer {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_CONNECTIONS);
+
+        IdleServer {
+            server: server,
+            connections: slab,
+            wheel: Wheel::new(),
+            idle_ticks: idle_ticks,
+            reported: 0,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<IdleServer>) {
+        // The server socket is level triggered, accepting one connection
+        // per event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => {
+                println!("the server socket wasn't actually ready");
+                return;
+            }
+            Err(e) => {
+                // Likely out of file descriptors. The connection stays in
+                // the backlog, and is accepted once some have been freed.
+                println!("encountered error while accepting connection; err={:?}", e);
+                return;
+            }
+        };
+
+        let wheel = &mut self.wheel;
+        let idle_ticks = self.idle_ticks;
+
+        let token = match self.connections.insert_with(|token| Connection::new(socket, token, wheel.insert(idle_ticks, token))) {
+            Some(token) => token,
+            None => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        event_loop.register_opt(
+            &self.connections[token].socket,
+            token,
+            mio::EventSet::readable(),
+            mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+    }
+
+    fn connection_ready(&mut self, event_loop: &mut mio::EventLoop<IdleServer>, token: mio::Token, events: mio::EventSet) {
+        let active = self.connections[token].ready(event_loop, events);
+
+        if self.connections[token].closed {
+            let connection = self.connections.remove(token).unwrap();
+            self.wheel.remove(connection.idle);
+        } else if active {
+            // Push the deadline back
+            let connection = &mut self.connections[token];
+            self.wheel.remove(connection.idle);
+            connection.idle = self.wheel.insert(self.idle_ticks, token);
+        }
+    }
+}
+
+impl mio::Handler for IdleServer {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<IdleServer>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+                self.accept(event_loop);
+            }
+            _ => self.connection_ready(event_loop, token, events),
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<IdleServer>, _: ()) {
+        let expired = self.wheel.tick();
+
+        if !expired.is_empty() {
+            // Closed connections have their deadline removed, every token
+            // still refers to the connection it was inserted for.
+            for token in &expired {
+                let _ = self.connections.remove(*token);
+            }
+
+            println!("closed idle connections; count={}", expired.len());
+        }
+
+        // With thousands of clients, connections aren't logged one by one
+        if self.connections.count() != self.reported {
+            self.reported = self.connections.count();
+            println!("connections open; count={}", self.reported);
+        }
+
+        event_loop.timeout_ms((), TICK_MS).unwrap();
+    }
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    // Read but not yet echoed back
+    buf: Vec<u8>,
+    // The connection's deadline in the wheel
+    idle: Key,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token, idle: Key) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            buf: vec![],
+            idle: idle,
+            closed: false,
+        }
+    }
+
+    // Returns true if the client sent something
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<IdleServer>, events: mio::EventSet) -> bool {
+        let mut active = false;
+
+        if events.is_readable() {
+            active = self.read();
+        }
+
+        if !self.closed {
+            self.write();
+        }
+
+        if !self.closed {
+            let interest = if self.buf.is_empty() {
+                mio::EventSet::readable()
+            } else {
+                mio::EventSet::writable()
+            };
+
+            event_loop.reregister(&self.socket, self.token, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+
+        active
+    }
+
+    fn read(&mut self) -> bool {
+        let mut buf = [0; 1024];
+        let mut active = false;
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.closed = true;
+                    return active;
+                }
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+                    active = true;
+                }
+                Ok(None) => return active,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return active;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.buf.is_empty() {
+            match self.socket.try_write(&self.buf) {
+                Ok(Some(n)) => {
+                    self.buf.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:9600".to_string()).parse().unwrap();
+    let idle_secs: u64 = args.next().map(|s| s.parse().unwrap()).unwrap_or(30);
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+    event_loop.timeout_ms((), TICK_MS).unwrap();
+
+    let mut server = IdleServer::new(server, idle_secs * 1_000 / TICK_MS);
+
+    println!("running idle server; addr={:?}; idle timeout={}s", address, idle_secs);
+    event_loop.run(&mut server).unwrap();
+}
@@ -0,0 +1,109 @@
+extern crate mio;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use std::net::SocketAddr;
+use std::env;
+
+const REPORT_MS: u64 = 1_000;
+
+// Opens a lot of connections to the idle server and keeps some of them
+// busy. The active ones send a line every second, the others send nothing
+// and should be closed by the server once its idle timeout is up.
+struct Load {
+    connections: Slab<Client>,
+    active: usize,
+    // Connections the server closed, by kind
+    closed_active: usize,
+    closed_idle: usize,
+}
+
+struct Client {
+    socket: TcpStream,
+    active: bool,
+}
+
+impl Load {
+    fn report(&self) {
+        println!("open={}; closed idle={}; closed active={}", self.connections.count(), self.closed_idle, self.closed_active);
+    }
+}
+
+impl mio::Handler for Load {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Load>, token: mio::Token, events: mio::EventSet) {
+        let mut buf = [0; 1024];
+        let mut closed = events.is_hup() || events.is_error();
+
+        // The echoes are only read to notice the server closing the
+        // connection. The socket is registered as edge triggered, drain it.
+        while !closed {
+            match self.connections[token].socket.try_read(&mut buf) {
+                Ok(Some(0)) | Err(_) => closed = true,
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+            }
+        }
+
+        if closed {
+            let client = self.connections.remove(token).unwrap();
+            event_loop.deregister(&client.socket).unwrap();
+
+            if client.active {
+                self.closed_active += 1;
+            } else {
+                self.closed_idle += 1;
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Load>, _: ()) {
+        for i in 0..self.active {
+            // A line is short enough to always fit in the socket's buffer
+            if let Some(client) = self.connections.get_mut(mio::Token(i)) {
+                let _ = client.socket.try_write(b"ping\n");
+            }
+        }
+
+        self.report();
+        event_loop.timeout_ms((), REPORT_MS).unwrap();
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("127.0.0.1:9600".to_string()).parse().unwrap();
+    let count: usize = args.next().map(|s| s.parse().unwrap()).unwrap_or(10_000);
+    let active: usize = args.next().map(|s| s.parse().unwrap()).unwrap_or(1_000);
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+
+    // The first `active` tokens are the active connections
+    let mut load = Load {
+        connections: Slab::new(count),
+        active: active,
+        closed_active: 0,
+        closed_idle: 0,
+    };
+
+    for i in 0..count {
+        let socket = TcpStream::connect(&address).unwrap();
+
+        let token = load.connections.insert(Client {
+            socket: socket,
+            active: i < active,
+        }).ok().unwrap();
+
+        event_loop.register_opt(&load.connections[token].socket, token, mio::EventSet::readable(), mio::PollOpt::edge())
+            .unwrap();
+    }
+
+    println!("connecting; addr={:?}; connections={}; active={}", address, count, active);
+
+    event_loop.timeout_ms((), REPORT_MS).unwrap();
+    event_loop.run(&mut load).unwrap();
+}
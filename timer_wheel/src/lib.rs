@@ -0,0 +1,214 @@
+// A hierarchical timer wheel, for keeping track of a large number of
+// deadlines that keep getting pushed back, like idle timeouts.
+//
+// Time moves in ticks, driven by the caller: a single event loop timeout
+// firing every tick is enough, however many deadlines there are. The wheel
+// has several levels of 64 slots each. A slot on the first level holds the
+// deadlines falling on one tick, a slot on the second level those falling
+// within 64 ticks, on the third within 64 * 64 ticks, and so on:
+//
+// level 0:  [0][1][2] ... [63]    1 tick per slot
+// level 1:  [0][1][2] ... [63]    64 ticks per slot
+// level 2:  [0][1][2] ... [63]    4,096 ticks per slot
+// level 3:  [0][1][2] ... [63]    262,144 ticks per slot
+//
+// A deadline goes in the lowest level that reaches far enough. Whenever
+// the first level wraps around, the next slot of the level above is
+// emptied into the levels below, where they are now close enough to go.
+// Every deadline is moved at most once per level, and only those on the
+// current tick's slot are looked at.
+//
+// Inserting and removing is constant time. Removed deadlines are not
+// looked for in their slot, they are left behind and skipped when the
+// slot comes up, so resetting a deadline is cheap.
+//
+// Like the rest of the examples' protocol code, the wheel does no I/O and
+// doesn't look at the clock. It only knows about the ticks it is given.
+
+use std::mem;
+
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const LEVELS: usize = 4;
+
+// Deadlines further away than this are kept on the last level, and moved
+// back onto it when their slot comes up, until they are close enough.
+pub const MAX_TICKS: u64 = 1 << (SLOT_BITS * LEVELS as u32);
+
+// Identifies a deadline in the wheel, to remove it. The generation tells
+// the deadline apart from the ones that took its place after it expired or
+// was removed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Key {
+    index: usize,
+    generation: u64,
+}
+
+struct Entry<T> {
+    // The tick it expires on
+    deadline: u64,
+    generation: u64,
+    value: Option<T>,
+}
+
+pub struct Wheel<T> {
+    // The current tick
+    now: u64,
+    levels: Vec<Vec<Vec<Key>>>,
+    entries: Vec<Entry<T>>,
+    // Entries not holding a deadline, ready for reuse
+    free: Vec<usize>,
+    len: usize,
+}
+
+impl<T> Wheel<T> {
+    pub fn new() -> Wheel<T> {
+        Wheel {
+            now: 0,
+            levels: (0..LEVELS).map(|_| (0..SLOTS).map(|_| vec![]).collect()).collect(),
+            entries: vec![],
+            free: vec![],
+            len: 0,
+        }
+    }
+
+    // Adds a deadline `ticks` ticks from now. It expires on the tick
+    // `ticks` calls to `tick` away, or on the next one for 0.
+    pub fn insert(&mut self, ticks: u64, value: T) -> Key {
+        let deadline = self.now + std::cmp::max(ticks, 1);
+
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.entries.push(Entry {
+                    deadline: 0,
+                    generation: 0,
+                    value: None,
+                });
+
+                self.entries.len() - 1
+            }
+        };
+
+        let entry = &mut self.entries[index];
+        entry.deadline = deadline;
+        entry.generation += 1;
+        entry.value = Some(value);
+
+        let key = Key {
+            index: index,
+            generation: entry.generation,
+        };
+
+        self.len += 1;
+        self.place(key, deadline);
+
+        key
+    }
+
+    // Removes a deadline before it expires. Returns `None` if it already
+    // has, or was removed already.
+    pub fn remove(&mut self, key: Key) -> Option<T> {
+        let value = match self.entries.get_mut(key.index) {
+            Some(entry) if entry.generation == key.generation => entry.value.take(),
+            _ => None,
+        };
+
+        // The key stays in its slot until the slot comes up, the entry can
+        // only be reused once it is gone from there.
+        if value.is_some() {
+            self.len -= 1;
+        }
+
+        value
+    }
+
+    // Moves time forward by a tick, and returns the values of the
+    // deadlines expiring on it.
+    pub fn tick(&mut self) -> Vec<T> {
+        self.now += 1;
+
+        // Moves deadlines down from the higher levels, starting with the
+        // highest, as they may end up on the levels below it.
+        for level in (1..LEVELS).rev() {
+            let width = level as u32 * SLOT_BITS;
+
+            if self.now & ((1 << width) - 1) == 0 {
+                let slot = (self.now >> width) as usize & (SLOTS - 1);
+                let keys = mem::take(&mut self.levels[level][slot]);
+
+                for key in keys {
+                    let deadline = self.entries[key.index].deadline;
+
+                    if self.is_live(key) {
+                        self.place(key, deadline);
+                    } else {
+                        self.release(key);
+                    }
+                }
+            }
+        }
+
+        let slot = self.now as usize & (SLOTS - 1);
+        let keys = mem::take(&mut self.levels[0][slot]);
+        let mut expired = vec![];
+
+        for key in keys {
+            if self.is_live(key) {
+                if let Some(value) = self.entries[key.index].value.take() {
+                    self.len -= 1;
+                    expired.push(value);
+                }
+            }
+
+            self.release(key);
+        }
+
+        expired
+    }
+
+    // The current tick, 0 when the wheel was created
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    // The number of deadlines waiting to expire
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn place(&mut self, key: Key, deadline: u64) {
+        let delta = deadline - self.now;
+
+        // The lowest level reaching the deadline, or the last one
+        let level = (0..LEVELS)
+            .find(|level| delta < 1 << ((level + 1) as u32 * SLOT_BITS))
+            .unwrap_or(LEVELS - 1);
+
+        let slot = (deadline >> (level as u32 * SLOT_BITS)) as usize & (SLOTS - 1);
+        self.levels[level][slot].push(key);
+    }
+
+    fn is_live(&self, key: Key) -> bool {
+        let entry = &self.entries[key.index];
+        entry.generation == key.generation && entry.value.is_some()
+    }
+
+    // The key has left its slot. Unless it is still live, the entry can be
+    // reused.
+    fn release(&mut self, key: Key) {
+        if !self.is_live(key) && self.entries[key.index].generation == key.generation {
+            self.free.push(key.index);
+        }
+    }
+}
+
+impl<T> Default for Wheel<T> {
+    fn default() -> Wheel<T> {
+        Wheel::new()
+    }
+}
@@ -0,0 +1,589 @@
+extern crate mio;
+extern crate openssl;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use openssl::ssl::{self, ErrorCode, HandshakeError, MidHandshakeSslStream, SslAcceptor, SslFiletype, SslMethod, SslStream};
+use std::net::SocketAddr;
+use std::{env, mem};
+
+const SERVER: mio::Token = mio::Token(0);
+
+// Tokens `1..=MAX_SESSIONS` are used for client connections, and the
+// connection to the backend for a client is its token plus
+// `MAX_SESSIONS`.
+const MAX_SESSIONS: usize = 1_024;
+
+// Reading from one side stops while this much is waiting to be written to
+// the other
+const MAX_BUFFERED: usize = 64 * 1_024;
+
+// A client not done with the handshake within this long is dropped, and
+// so is one whose backend doesn't accept the connection in time
+const TIMEOUT_MS: u64 = 10_000;
+
+// Terminates TLS in front of a backend that only speaks plaintext. Clients
+// connect with TLS, and once the handshake is done, the proxy connects to
+// the backend and relays between the two: what it decrypts from the client
+// goes to the backend, what the backend answers is encrypted back to the
+// client. Nothing is known of the protocol inside.
+//
+// OpenSSL stands between the client's socket and the relay, and doesn't
+// map onto readiness as simply as a socket does. A read may need the socket
+// to be writable, while a handshake message or a key update goes out, and
+// a write may need it to be readable. And the socket having nothing more
+// to read doesn't mean OpenSSL has nothing more to give: it may hold
+// decrypted data it already read from the socket, that no readiness event
+// will ever tell about.
+struct Proxy {
+    server: TcpListener,
+    acceptor: SslAcceptor,
+    backend: SocketAddr,
+    sessions: Slab<Session>,
+}
+
+impl Proxy {
+    fn new(server: TcpListener, acceptor: SslAcceptor, backend: SocketAddr) -> Proxy {
+        // Token `0` is reserved for the server socket
+        let slab = Slab::new_starting_at(mio::Token(1), MAX_SESSIONS);
+
+        Proxy {
+            server: server,
+            acceptor: acceptor,
+            backend: backend,
+            sessions: slab,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Proxy>) {
+        // The server socket is registered as level triggered, one accept per
+        // event is enough.
+        let socket = match self.server.accept() {
+            Ok(Some(socket)) => socket,
+            Ok(None) => return,
+            Err(e) => {
+                println!("encountered error while accepting connection; err={:?}", e);
+                event_loop.shutdown();
+                return;
+            }
+        };
+
+        let token = match self.sessions.insert_with(|token| Session::new(socket, token)) {
+            Some(token) => token,
+            None => {
+                println!("connection limit reached, dropping client");
+                return;
+            }
+        };
+
+        let session = &mut self.sessions[token];
+        session.timeout = Some(event_loop.timeout_ms(token, TIMEOUT_MS).unwrap());
+
+        // The handshake starts once the client's hello is in
+        event_loop.register_opt(session.tls.socket(), token, mio::EventSet::readable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+
+    fn client_ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token) {
+        if self.sessions[token].state == State::Handshake && self.sessions[token].handshake(&self.acceptor) {
+            self.established(event_loop, token);
+        }
+
+        // A read or a write may each be waiting for either readiness, any
+        // event on the socket retries both. Right after the handshake, the
+        // client may have sent data along with its last handshake message.
+        if self.sessions[token].state != State::Handshake {
+            self.sessions[token].read_client();
+        }
+
+        self.update(event_loop, token);
+    }
+
+    fn backend_ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        if self.sessions[token].state == State::Connecting {
+            self.connected(event_loop, token, events);
+        }
+
+        {
+            let session = &mut self.sessions[token];
+
+            if session.state == State::Relaying {
+                if events.is_readable() {
+                    session.read_backend();
+                }
+
+                if events.is_writable() {
+                    session.write_backend();
+                }
+            }
+        }
+
+        self.update(event_loop, token);
+    }
+
+    // The handshake is done, connects to the backend
+    fn established(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token) {
+        let session = &mut self.sessions[token];
+        event_loop.clear_timeout(session.timeout.take().unwrap());
+
+        if let Tls::Established(ref stream) = session.tls {
+            let cipher = stream.ssl().current_cipher().map(|cipher| cipher.name()).unwrap_or("none");
+            println!("handshake done; token={:?}; version={}; cipher={}", token, stream.ssl().version_str(), cipher);
+        }
+
+        let socket = match TcpStream::connect(&self.backend) {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("failed to connect to backend; err={:?}", e);
+                session.closed = true;
+                return;
+            }
+        };
+
+        // Writable once connected
+        event_loop.register_opt(&socket, backend_token(token), mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        session.backend = Some(socket);
+        session.timeout = Some(event_loop.timeout_ms(token, TIMEOUT_MS).unwrap());
+        session.state = State::Connecting;
+    }
+
+    fn connected(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        let session = &mut self.sessions[token];
+
+        if events.is_error() || events.is_hup() {
+            println!("failed to connect to backend; token={:?}; err={:?}", token, session.backend.as_ref().unwrap().take_socket_error());
+            session.closed = true;
+            return;
+        }
+
+        if !events.is_writable() {
+            return;
+        }
+
+        event_loop.clear_timeout(session.timeout.take().unwrap());
+
+        // What the client sent while the backend was being connected to is
+        // already waiting to be written to it
+        session.state = State::Relaying;
+    }
+
+    // Writes what it can, then reregisters both sockets of the session, or
+    // removes it once it is done
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token) {
+        {
+            let session = &mut self.sessions[token];
+
+            session.write_client();
+
+            if session.state == State::Relaying {
+                session.write_backend();
+
+                // Reading from the client may have stopped with decrypted
+                // data left in OpenSSL, for the backend to catch up. The
+                // socket won't be readable for it, it is read from here.
+                if session.pending() && session.to_backend.len() < MAX_BUFFERED {
+                    session.read_client();
+                    session.write_backend();
+                }
+            }
+        }
+
+        if self.sessions[token].is_done() {
+            let session = self.sessions.remove(token).unwrap();
+
+            if let Some(timeout) = session.timeout {
+                event_loop.clear_timeout(timeout);
+            }
+
+            println!("session closed; token={:?}; sent={}; received={}", token, session.sent, session.received);
+            return;
+        }
+
+        let session = &self.sessions[token];
+
+        event_loop.reregister(session.tls.socket(), token, session.client_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        if let Some(ref backend) = session.backend {
+            event_loop.reregister(backend, backend_token(token), session.backend_interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+}
+
+impl mio::Handler for Proxy {
+    // The session whose handshake, or connection to the backend, is taking
+    // too long
+    type Timeout = mio::Token;
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => self.accept(event_loop),
+            _ if token.as_usize() <= MAX_SESSIONS => self.client_ready(event_loop, token),
+            _ => {
+                let token = mio::Token(token.as_usize() - MAX_SESSIONS);
+
+                // The backend's events may still come in after the session
+                // is gone
+                if self.sessions.get(token).map(|session| session.backend.is_some()).unwrap_or(false) {
+                    self.backend_ready(event_loop, token, events);
+                }
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Proxy>, token: mio::Token) {
+        println!("timed out; token={:?}; state={:?}", token, self.sessions[token].state);
+
+        self.sessions[token].timeout = None;
+        self.sessions[token].closed = true;
+        self.update(event_loop, token);
+    }
+}
+
+fn backend_token(token: mio::Token) -> mio::Token {
+    mio::Token(token.as_usize() + MAX_SESSIONS)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    // Going through the TLS handshake with the client
+    Handshake,
+    // Waiting for the backend to accept the connection
+    Connecting,
+    // Passing data both ways
+    Relaying,
+}
+
+// The client's connection, as far as the handshake went
+enum Tls {
+    // The handshake has not started, the client's hello isn't in yet
+    Accepting(TcpStream),
+    Handshaking(MidHandshakeSslStream<TcpStream>),
+    Established(SslStream<TcpStream>),
+    // The handshake failed, the connection is gone
+    Failed,
+}
+
+impl Tls {
+    fn socket(&self) -> &TcpStream {
+        match *self {
+            Tls::Accepting(ref socket) => socket,
+            Tls::Handshaking(ref stream) => stream.get_ref(),
+            Tls::Established(ref stream) => stream.get_ref(),
+            Tls::Failed => panic!("no socket after a failed handshake"),
+        }
+    }
+}
+
+struct Session {
+    tls: Tls,
+    token: mio::Token,
+    state: State,
+    backend: Option<TcpStream>,
+    timeout: Option<mio::Timeout>,
+    // Decrypted from the client, and not yet written to the backend
+    to_backend: Vec<u8>,
+    // Read from the backend, and not yet encrypted to the client
+    to_client: Vec<u8>,
+    // The readiness OpenSSL waits for, on the client's socket, to go on with
+    // the handshake, a read, or a write. Usually the obvious one, but not
+    // always.
+    handshake_wants: mio::EventSet,
+    read_wants: mio::EventSet,
+    write_wants: mio::EventSet,
+    // Set once a side has closed its end, and once that was passed on to
+    // the other side
+    client_eof: bool,
+    backend_eof: bool,
+    backend_shut: bool,
+    client_shut: bool,
+    closed: bool,
+    sent: u64,
+    received: u64,
+}
+
+impl Session {
+    fn new(socket: TcpStream, token: mio::Token) -> Session {
+        Session {
+            tls: Tls::Accepting(socket),
+            token: token,
+            state: State::Handshake,
+            backend: None,
+            timeout: None,
+            to_backend: vec![],
+            to_client: vec![],
+            handshake_wants: mio::EventSet::readable(),
+            read_wants: mio::EventSet::readable(),
+            write_wants: mio::EventSet::writable(),
+            client_eof: false,
+            backend_eof: false,
+            backend_shut: false,
+            client_shut: false,
+            closed: false,
+            sent: 0,
+            received: 0,
+        }
+    }
+
+    // Goes on with the handshake as far as the socket allows. Returns true
+    // once it is done.
+    fn handshake(&mut self, acceptor: &SslAcceptor) -> bool {
+        let result = match mem::replace(&mut self.tls, Tls::Failed) {
+            Tls::Accepting(socket) => acceptor.accept(socket),
+            Tls::Handshaking(stream) => stream.handshake(),
+            tls => {
+                self.tls = tls;
+                return false;
+            }
+        };
+
+        match result {
+            Ok(stream) => {
+                self.tls = Tls::Established(stream);
+                true
+            }
+            Err(HandshakeError::WouldBlock(stream)) => {
+                self.handshake_wants = wants(stream.error());
+                self.tls = Tls::Handshaking(stream);
+                false
+            }
+            Err(HandshakeError::Failure(stream)) => {
+                println!("handshake failed; token={:?}; err={}", self.token, stream.error());
+                self.closed = true;
+                false
+            }
+            Err(HandshakeError::SetupFailure(e)) => {
+                println!("failed to set up the handshake; token={:?}; err={}", self.token, e);
+                self.closed = true;
+                false
+            }
+        }
+    }
+
+    // Whether OpenSSL holds decrypted data not read yet
+    fn pending(&self) -> bool {
+        match self.tls {
+            Tls::Established(ref stream) => stream.ssl().pending() > 0,
+            _ => false,
+        }
+    }
+
+    fn read_client(&mut self) {
+        // A TLS record holds up to 16KB
+        let mut chunk = [0; 16 * 1_024];
+
+        let stream = match self.tls {
+            Tls::Established(ref mut stream) => stream,
+            _ => return,
+        };
+
+        self.read_wants = mio::EventSet::readable();
+
+        // The socket is registered as edge triggered, drain it, until
+        // OpenSSL needs more from the socket. Or at least until enough is
+        // waiting for the backend.
+        while !self.client_eof && self.to_backend.len() < MAX_BUFFERED {
+            match stream.ssl_read(&mut chunk) {
+                Ok(n) => self.to_backend.extend(&chunk[..n]),
+                // The client sent its close_notify
+                Err(ref e) if e.code() == ErrorCode::ZERO_RETURN => self.client_eof = true,
+                Err(ref e) if e.code() == ErrorCode::WANT_READ || e.code() == ErrorCode::WANT_WRITE => {
+                    self.read_wants = wants(e);
+                    return;
+                }
+                Err(e) => {
+                    println!("got an error trying to read from the client; token={:?}; err={}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn read_backend(&mut self) {
+        let mut chunk = [0; 16 * 1_024];
+
+        while self.to_client.len() < MAX_BUFFERED {
+            let result = match self.backend {
+                Some(ref mut backend) => backend.try_read(&mut chunk),
+                None => return,
+            };
+
+            match result {
+                Ok(Some(0)) => {
+                    self.backend_eof = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    self.to_client.extend(&chunk[..n]);
+                    self.received += n as u64;
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read from the backend; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn write_client(&mut self) {
+        let stream = match self.tls {
+            Tls::Established(ref mut stream) => stream,
+            _ => return,
+        };
+
+        self.write_wants = mio::EventSet::writable();
+
+        // A write that can't go on has to be retried with the same data,
+        // which it is, as nothing is taken off `to_client` until written
+        while !self.to_client.is_empty() {
+            match stream.ssl_write(&self.to_client) {
+                Ok(n) => {
+                    self.to_client.drain(..n);
+                }
+                Err(ref e) if e.code() == ErrorCode::WANT_READ || e.code() == ErrorCode::WANT_WRITE => {
+                    self.write_wants = wants(e);
+                    return;
+                }
+                Err(e) => {
+                    println!("got an error trying to write to the client; token={:?}; err={}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        // The backend is done, and all it said was passed on. The client is
+        // told with a close_notify, before the connection is shut down.
+        if self.backend_eof && !self.client_shut {
+            match stream.shutdown() {
+                Err(ref e) if e.code() == ErrorCode::WANT_WRITE => {
+                    self.write_wants = mio::EventSet::writable();
+                    return;
+                }
+                _ => {}
+            }
+
+            let _ = stream.get_ref().shutdown(Shutdown::Write);
+            self.client_shut = true;
+        }
+    }
+
+    fn write_backend(&mut self) {
+        let backend = match self.backend {
+            Some(ref mut backend) => backend,
+            None => return,
+        };
+
+        while !self.to_backend.is_empty() {
+            match backend.try_write(&self.to_backend) {
+                Ok(Some(n)) => {
+                    self.to_backend.drain(..n);
+                    self.sent += n as u64;
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write to the backend; token={:?}; err={:?}", self.token, e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        if self.client_eof && !self.backend_shut {
+            let _ = backend.shutdown(Shutdown::Write);
+            self.backend_shut = true;
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        if self.closed {
+            return true;
+        }
+
+        match self.state {
+            State::Handshake | State::Connecting => false,
+            State::Relaying => self.client_shut && self.backend_shut,
+        }
+    }
+
+    fn client_interest(&self) -> mio::EventSet {
+        if self.state == State::Handshake {
+            return self.handshake_wants;
+        }
+
+        let mut interest = mio::EventSet::none();
+
+        if !self.client_eof && self.to_backend.len() < MAX_BUFFERED {
+            interest = interest | self.read_wants;
+        }
+
+        if !self.to_client.is_empty() || self.backend_eof && !self.client_shut {
+            interest = interest | self.write_wants;
+        }
+
+        interest
+    }
+
+    fn backend_interest(&self) -> mio::EventSet {
+        if self.state == State::Connecting {
+            return mio::EventSet::writable();
+        }
+
+        let mut interest = mio::EventSet::none();
+
+        if !self.backend_eof && self.to_client.len() < MAX_BUFFERED {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        if !self.to_backend.is_empty() || self.client_eof && !self.backend_shut {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        interest
+    }
+}
+
+// The readiness OpenSSL is waiting for, when it can't go on
+fn wants(e: &ssl::Error) -> mio::EventSet {
+    if e.code() == ErrorCode::WANT_WRITE {
+        mio::EventSet::writable()
+    } else {
+        mio::EventSet::readable()
+    }
+}
+
+fn acceptor(cert: &str, key: &str) -> SslAcceptor {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    builder.set_certificate_chain_file(cert).unwrap();
+    builder.set_private_key_file(key, SslFiletype::PEM).unwrap();
+    builder.check_private_key().unwrap();
+    builder.build()
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let address: SocketAddr = args.next().unwrap_or("127.0.0.1:8443".to_string()).parse().unwrap();
+    let backend: SocketAddr = args.next().unwrap_or("127.0.0.1:8080".to_string()).parse().unwrap();
+    let cert = args.next().unwrap_or("cert.pem".to_string());
+    let key = args.next().unwrap_or("key.pem".to_string());
+
+    let acceptor = acceptor(&cert, &key);
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    println!("running TLS proxy; addr={:?}; backend={:?}; cert={}", address, backend, cert);
+
+    let mut proxy = Proxy::new(server, acceptor, backend);
+    event_loop.run(&mut proxy).unwrap();
+}
@@ -0,0 +1,132 @@
+extern crate mio;
+extern crate codec;
+extern crate unix_rpc;
+
+use codec::connection::Connection;
+use mio::TryRead;
+use mio::unix::*;
+use std::env;
+use unix_rpc::{Client, ClientCodec};
+
+const SOCKET: mio::Token = mio::Token(0);
+const STDIN: mio::Token = mio::Token(1);
+
+const DEFAULT_PATH: &str = "/tmp/mio-unix-rpc.sock";
+
+// Reads calls from stdin, one per line, and prints the responses as they
+// arrive. Calls are sent as soon as they are typed, without waiting for the
+// previous ones to complete.
+struct Repl {
+    connection: Connection<UnixStream, ClientCodec>,
+    client: Client,
+    stdin: mio::Io,
+    // Typed but not yet terminated by a newline
+    line: Vec<u8>,
+    stdin_closed: bool,
+}
+
+impl Repl {
+    // Sends a call for every complete line typed so far
+    fn read_stdin(&mut self) {
+        let mut buf = [0; 1_024];
+
+        match self.stdin.try_read(&mut buf) {
+            Ok(Some(0)) => self.stdin_closed = true,
+            Ok(Some(n)) => self.line.extend(&buf[..n]),
+            Ok(None) => {}
+            Err(e) => {
+                println!("got an error trying to read stdin; err={:?}", e);
+                self.stdin_closed = true;
+            }
+        }
+
+        while let Some(pos) = self.line.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.line.drain(..pos + 1).collect();
+            let line = String::from_utf8_lossy(&line);
+            let mut parts = line.trim().splitn(2, ' ');
+
+            if let Some(method) = parts.next().filter(|method| !method.is_empty()) {
+                let request = self.client.call(method, parts.next().unwrap_or(""));
+                self.connection.send(request);
+            }
+        }
+    }
+
+    fn read_socket(&mut self) {
+        for response in self.connection.read() {
+            let method = match self.client.complete(&response) {
+                Some(method) => method,
+                None => {
+                    println!("ignoring response to no call; id={}", response.id);
+                    continue;
+                }
+            };
+
+            match response.result {
+                Ok(result) => println!("{} -> {}", method, result),
+                Err(message) => println!("{} failed: {}", method, message),
+            }
+        }
+    }
+}
+
+impl mio::Handler for Repl {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Repl>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SOCKET => {
+                if events.is_readable() {
+                    self.read_socket();
+                }
+            }
+            STDIN => {
+                // Stdin is registered as level triggered, one read per event
+                // is enough.
+                self.read_stdin();
+
+                if self.stdin_closed {
+                    event_loop.deregister(&self.stdin).unwrap();
+                }
+            }
+            _ => panic!("unexpected token"),
+        }
+
+        if self.connection.closed {
+            println!("the server closed the connection");
+            event_loop.shutdown();
+            return;
+        }
+
+        self.connection.flush(event_loop);
+
+        // Done once every call has been answered
+        if self.stdin_closed && self.client.pending() == 0 {
+            event_loop.shutdown();
+        }
+    }
+}
+
+fn main() {
+    let path = env::args().nth(1).unwrap_or(DEFAULT_PATH.to_string());
+
+    let socket = UnixStream::connect(&path).unwrap();
+    let stdin = mio::Io::from_raw_fd(0);
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+
+    let mut repl = Repl {
+        connection: Connection::new(socket, SOCKET, ClientCodec::new()),
+        client: Client::new(),
+        stdin: stdin,
+        line: vec![],
+        stdin_closed: false,
+    };
+
+    repl.connection.register(&mut event_loop);
+    event_loop.register_opt(&repl.stdin, STDIN, mio::EventSet::readable(), mio::PollOpt::level()).unwrap();
+
+    println!("connected; path={:?}; type calls like `add 1 2`, ctrl-d to quit", path);
+    event_loop.run(&mut repl).unwrap();
+}
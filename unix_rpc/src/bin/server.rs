@@ -0,0 +1,148 @@
+extern crate mio;
+extern crate codec;
+extern crate unix_rpc;
+
+use codec::connection::Connection;
+use mio::unix::*;
+use mio::util::Slab;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs, io};
+use unix_rpc::{Request, Response, ServerCodec};
+
+const SERVER: mio::Token = mio::Token(0);
+
+const DEFAULT_PATH: &str = "/tmp/mio-unix-rpc.sock";
+
+struct RpcServer {
+    server: UnixListener,
+    connections: Slab<Connection<UnixStream, ServerCodec>>,
+    // Calls handled since the server started, across all connections
+    calls: u64,
+}
+
+impl RpcServer {
+    fn new(server: UnixListener) -> RpcServer {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        RpcServer {
+            server: server,
+            connections: slab,
+            calls: 0,
+        }
+    }
+
+    // The methods the server exposes:
+    //
+    // echo <text>        returns the text
+    // add <n> <n> ...    returns the sum of the integers
+    // upper <text>       returns the text in upper case
+    // time               returns the seconds since the Unix epoch
+    // stats              returns the number of calls handled
+    fn call(&mut self, request: &Request) -> Response {
+        self.calls += 1;
+
+        let result = match &request.method[..] {
+            "echo" => Ok(request.params.clone()),
+            "add" => {
+                request.params.split_whitespace()
+                    .map(|n| n.parse::<i64>().map_err(|_| format!("not an integer: {:?}", n)))
+                    .sum::<Result<i64, String>>()
+                    .map(|sum| sum.to_string())
+            }
+            "upper" => Ok(request.params.to_uppercase()),
+            "time" => Ok(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().to_string()),
+            "stats" => Ok(format!("calls={}; connections={}", self.calls, self.connections.count())),
+            _ => Err(format!("unknown method {:?}", request.method)),
+        };
+
+        Response {
+            id: request.id,
+            result: result,
+        }
+    }
+}
+
+impl mio::Handler for RpcServer {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<RpcServer>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                match self.server.accept() {
+                    Ok(Some(socket)) => {
+                        let token = match self.connections.insert_with(|token| Connection::new(socket, token, ServerCodec::new())) {
+                            Some(token) => token,
+                            None => {
+                                println!("connection limit reached, dropping client");
+                                return;
+                            }
+                        };
+
+                        println!("client connected; token={:?}", token);
+                        self.connections[token].register(event_loop);
+                    }
+                    Ok(None) => {
+                        println!("the server socket wasn't actually ready");
+                    }
+                    Err(e) => {
+                        println!("encountered error while accepting connection; err={:?}", e);
+                        event_loop.shutdown();
+                    }
+                }
+            }
+            _ => {
+                if events.is_readable() {
+                    let requests = self.connections[token].read();
+
+                    for request in requests {
+                        let response = self.call(&request);
+                        self.connections[token].send(response);
+                    }
+                }
+
+                self.connections[token].flush(event_loop);
+
+                if self.connections[token].closed {
+                    println!("client disconnected; token={:?}", token);
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+}
+
+// A socket file left behind by a server that didn't shut down cleanly
+// makes the bind fail. If connecting to it is refused, no one is listening
+// on it anymore, and it is removed.
+fn bind(path: &str) -> io::Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AddrInUse, "another server is listening on the path"));
+    }
+
+    match fs::remove_file(path) {
+        Ok(()) => println!("removed stale socket; path={:?}", path),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    UnixListener::bind(path)
+}
+
+fn main() {
+    let path = env::args().nth(1).unwrap_or(DEFAULT_PATH.to_string());
+
+    let server = bind(&path).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    let mut server = RpcServer::new(server);
+
+    println!("running rpc server; path={:?}", path);
+    event_loop.run(&mut server).unwrap();
+}
@@ -0,0 +1,229 @@
+// Request/response RPC between processes on the same host, shared by the
+// server and the client in `src/bin`.
+//
+// Messages are text, carried in length delimited frames:
+//
+// <id> <method> <params>    request
+// <id> OK <result>          response to a successful call
+// <id> ERR <message>        response to a failed call
+//
+// The client picks the id of each request and the server copies it into
+// the response. A client may send several requests without waiting, the
+// ids tell the responses apart.
+//
+// Like the rest of the examples' protocol code, this does no I/O. The
+// codecs sit on top of the length delimited one from the `codec` crate,
+// and `codec::connection::Connection` moves the frames over the socket.
+
+extern crate codec;
+
+use codec::{Decoder, Encoder};
+use codec::length_delimited::{self, LengthDelimited};
+use std::collections::HashMap;
+
+// The largest message either side accepts
+pub const MAX_MESSAGE: usize = 64 * 1_024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub id: u32,
+    pub method: String,
+    // Whatever follows the method, the method decides what it means
+    pub params: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub id: u32,
+    pub result: Result<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Frame(length_delimited::Error),
+    // The frame doesn't hold a valid message
+    Invalid,
+}
+
+impl Request {
+    pub fn encode(&self) -> Vec<u8> {
+        format!("{} {} {}", self.id, self.method, self.params).into_bytes()
+    }
+
+    pub fn decode(frame: &[u8]) -> Result<Request, Error> {
+        let (id, method, params) = split(frame)?;
+
+        if method.is_empty() {
+            return Err(Error::Invalid);
+        }
+
+        Ok(Request {
+            id: id,
+            method: method.to_string(),
+            params: params.to_string(),
+        })
+    }
+}
+
+impl Response {
+    pub fn encode(&self) -> Vec<u8> {
+        match self.result {
+            Ok(ref result) => format!("{} OK {}", self.id, result).into_bytes(),
+            Err(ref message) => format!("{} ERR {}", self.id, message).into_bytes(),
+        }
+    }
+
+    pub fn decode(frame: &[u8]) -> Result<Response, Error> {
+        let (id, status, rest) = split(frame)?;
+
+        let result = match status {
+            "OK" => Ok(rest.to_string()),
+            "ERR" => Err(rest.to_string()),
+            _ => return Err(Error::Invalid),
+        };
+
+        Ok(Response {
+            id: id,
+            result: result,
+        })
+    }
+}
+
+// Splits a message into its id, the word after it, and the rest
+fn split(frame: &[u8]) -> Result<(u32, &str, &str), Error> {
+    let text = std::str::from_utf8(frame).map_err(|_| Error::Invalid)?;
+    let mut parts = text.splitn(3, ' ');
+
+    let id = parts.next().and_then(|id| id.parse().ok()).ok_or(Error::Invalid)?;
+    let word = parts.next().ok_or(Error::Invalid)?;
+
+    Ok((id, word, parts.next().unwrap_or("")))
+}
+
+/*
+ *
+ * ===== Codecs =====
+ *
+ */
+
+// Decodes requests and encodes responses
+#[derive(Debug)]
+pub struct ServerCodec {
+    frames: LengthDelimited,
+}
+
+impl ServerCodec {
+    pub fn new() -> ServerCodec {
+        ServerCodec { frames: LengthDelimited::new(MAX_MESSAGE) }
+    }
+}
+
+impl Default for ServerCodec {
+    fn default() -> ServerCodec {
+        ServerCodec::new()
+    }
+}
+
+impl Decoder for ServerCodec {
+    type Item = Request;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Request>, Error> {
+        match self.frames.decode(buf).map_err(Error::Frame)? {
+            Some(frame) => Request::decode(&frame).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder for ServerCodec {
+    type Item = Response;
+
+    fn encode(&mut self, response: Response, dst: &mut Vec<u8>) {
+        self.frames.encode(response.encode(), dst);
+    }
+}
+
+// Decodes responses and encodes requests
+#[derive(Debug)]
+pub struct ClientCodec {
+    frames: LengthDelimited,
+}
+
+impl ClientCodec {
+    pub fn new() -> ClientCodec {
+        ClientCodec { frames: LengthDelimited::new(MAX_MESSAGE) }
+    }
+}
+
+impl Default for ClientCodec {
+    fn default() -> ClientCodec {
+        ClientCodec::new()
+    }
+}
+
+impl Decoder for ClientCodec {
+    type Item = Response;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Response>, Error> {
+        match self.frames.decode(buf).map_err(Error::Frame)? {
+            Some(frame) => Response::decode(&frame).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder for ClientCodec {
+    type Item = Request;
+
+    fn encode(&mut self, request: Request, dst: &mut Vec<u8>) {
+        self.frames.encode(request.encode(), dst);
+    }
+}
+
+/*
+ *
+ * ===== Client =====
+ *
+ */
+
+// Keeps track of the calls waiting for a response. The caller sends the
+// requests it makes, and hands it the responses it receives.
+#[derive(Debug, Default)]
+pub struct Client {
+    next_id: u32,
+    // The method of each outstanding call, by id
+    pending: HashMap<u32, String>,
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client::default()
+    }
+
+    // Builds the request for a call
+    pub fn call(&mut self, method: &str, params: &str) -> Request {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        self.pending.insert(id, method.to_string());
+
+        Request {
+            id: id,
+            method: method.to_string(),
+            params: params.to_string(),
+        }
+    }
+
+    // Matches a response with its call, returning the call's method. A
+    // response to no outstanding call returns `None`.
+    pub fn complete(&mut self, response: &Response) -> Option<String> {
+        self.pending.remove(&response.id)
+    }
+
+    // The number of calls still waiting for a response
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
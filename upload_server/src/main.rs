@@ -0,0 +1,386 @@
+extern crate mio;
+extern crate bytes;
+extern crate root_dir;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use root_dir::resolve_flat;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// The line naming the file is at most this long
+const MAX_HEADER: usize = 256;
+
+// Reading stops once this much is buffered, and resumes once the buffer is
+// back down to the low water mark.
+const HIGH_WATER: usize = 1_024 * 1_024;
+const LOW_WATER: usize = 256 * 1_024;
+
+// How much is written to disk at a time
+const DISK_CHUNK: usize = 64 * 1_024;
+
+struct Upload {
+    server: TcpListener,
+    dir: PathBuf,
+    connections: Slab<Connection>,
+    accepted: u64,
+}
+
+impl Upload {
+    fn new(server: TcpListener, dir: PathBuf) -> Upload {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Upload {
+            server: server,
+            dir: dir,
+            connections: slab,
+            accepted: 0,
+        }
+    }
+}
+
+impl mio::Handler for Upload {
+    type Timeout = ();
+    // A connection with buffered data to write to disk. The id tells a
+    // message for a closed connection apart from one for a connection that
+    // has since been given the same token.
+    type Message = (mio::Token, u64);
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Upload>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                match self.server.accept() {
+                    Ok(Some(socket)) => {
+                        println!("accepted a new client socket");
+
+                        self.accepted += 1;
+                        let id = self.accepted;
+
+                        let token = match self.connections.insert_with(|token| Connection::new(socket, token, id)) {
+                            Some(token) => token,
+                            None => {
+                                println!("connection limit reached, dropping client");
+                                return;
+                            }
+                        };
+
+                        let conn = &self.connections[token];
+
+                        event_loop.register_opt(
+                            &conn.socket,
+                            token,
+                            conn.interest(),
+                            mio::PollOpt::edge() | mio::PollOpt::oneshot()).unwrap();
+                    }
+                    Ok(None) => {
+                        println!("the server socket wasn't actually ready");
+                    }
+                    Err(e) => {
+                        println!("encountered error while accepting connection; err={:?}", e);
+                        event_loop.shutdown();
+                    }
+                }
+            }
+            _ => {
+                self.connections[token].ready(event_loop, events, &self.dir);
+
+                if self.connections[token].closed {
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+
+    fn notify(&mut self, event_loop: &mut mio::EventLoop<Upload>, (token, id): (mio::Token, u64)) {
+        match self.connections.get_mut(token) {
+            Some(conn) if conn.id == id => conn.flush(event_loop),
+            _ => return,
+        }
+
+        if self.connections[token].closed {
+            let _ = self.connections.remove(token);
+        }
+    }
+}
+
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    id: u64,
+    // Opened once the header line naming it has been read
+    file: Option<File>,
+    name: String,
+    // Bytes read but not yet written to disk
+    buf: Vec<u8>,
+    written: u64,
+    // Set while reading is stopped because the buffer is full
+    paused: bool,
+    pauses: u64,
+    // Set when a message asking for the next chunk to be written is on its
+    // way, so that there is only ever one.
+    flushing: bool,
+    // The client has sent the whole file
+    eof: bool,
+    out: Vec<u8>,
+    // Set when the connection should be closed once `out` is flushed
+    quit: bool,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token, id: u64) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            id: id,
+            file: None,
+            name: String::new(),
+            buf: vec![],
+            written: 0,
+            paused: false,
+            pauses: 0,
+            flushing: false,
+            eof: false,
+            out: vec![],
+            quit: false,
+            closed: false,
+        }
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Upload>, events: mio::EventSet, dir: &Path) {
+        // Reads may be paused with the client still sending, only the
+        // connection failing is reported then.
+        if events.is_error() {
+            println!("connection failed; file={:?}", self.name);
+            self.closed = true;
+            return;
+        }
+
+        if events.is_readable() {
+            self.read();
+
+            if self.file.is_none() && !self.quit {
+                self.header(dir);
+            }
+
+            if self.paused {
+                println!("buffer full, pausing reads; file={:?}; buffered={}", self.name, self.buf.len());
+            }
+        }
+
+        self.schedule(event_loop);
+        self.finish();
+        self.write();
+
+        if !self.closed {
+            self.reregister(event_loop);
+        }
+    }
+
+    fn read(&mut self) {
+        let mut buf = [0; 16 * 1_024];
+
+        // The socket is registered as edge triggered, keep reading until
+        // the socket is drained or there is no room left to buffer data.
+        while self.buf.len() < HIGH_WATER {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.eof = true;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    self.buf.extend(&buf[..n]);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        // The rest of the data stays in the socket's receive buffer. Once
+        // that fills up the client's writes block, which slows it down to
+        // the speed of the disk.
+        self.paused = true;
+        self.pauses += 1;
+    }
+
+    // Opens the file named by the first line
+    fn header(&mut self, dir: &Path) {
+        let pos = match self.buf.iter().take(MAX_HEADER).position(|b| *b == b'\n') {
+            Some(pos) => pos,
+            None if self.buf.len() < MAX_HEADER && !self.eof => return,
+            None => return self.fail("ERR expected a file name"),
+        };
+
+        let line = self.buf.drain(..pos + 1).collect::<Vec<u8>>();
+        self.name = String::from_utf8_lossy(&line).trim().to_string();
+
+        let path = match resolve_flat(dir, &self.name) {
+            Some(path) => path,
+            None => return self.fail("ERR invalid file name"),
+        };
+
+        // Existing files are never overwritten
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => {
+                println!("receiving file; file={:?}", self.name);
+                self.file = Some(file);
+            }
+            Err(e) => {
+                println!("failed to create file; file={:?}; err={:?}", self.name, e);
+                self.fail(&format!("ERR {}", e));
+            }
+        }
+    }
+
+    // Asks the event loop to call back once it has handled the events that
+    // are ready, to write the next chunk to disk. Writing to a file blocks
+    // and there is no readiness to wait on, so the writes are spread out,
+    // one chunk per turn of the event loop, to keep other connections
+    // responsive.
+    fn schedule(&mut self, event_loop: &mut mio::EventLoop<Upload>) {
+        if self.flushing || self.file.is_none() || self.buf.is_empty() {
+            return;
+        }
+
+        self.flushing = event_loop.channel().send((self.token, self.id)).is_ok();
+    }
+
+    // Writes the next chunk to disk
+    fn flush(&mut self, event_loop: &mut mio::EventLoop<Upload>) {
+        self.flushing = false;
+
+        let n = std::cmp::min(DISK_CHUNK, self.buf.len());
+
+        let res = match self.file {
+            Some(ref mut file) => file.write_all(&self.buf[..n]),
+            None => return,
+        };
+
+        if let Err(e) = res {
+            println!("failed to write file; file={:?}; err={:?}", self.name, e);
+            self.fail(&format!("ERR {}", e));
+        } else {
+            self.buf.drain(..n);
+            self.written += n as u64;
+
+            if self.paused && self.buf.len() <= LOW_WATER {
+                println!("buffer drained, resuming reads; file={:?}; buffered={}", self.name, self.buf.len());
+                self.paused = false;
+            }
+
+            self.schedule(event_loop);
+            self.finish();
+        }
+
+        self.write();
+
+        if !self.closed {
+            self.reregister(event_loop);
+        }
+    }
+
+    // Once the client is done and everything is on disk, the client is told
+    // how much was stored.
+    fn finish(&mut self) {
+        if !self.eof || self.quit || !self.buf.is_empty() {
+            return;
+        }
+
+        // Dropping the file closes it
+        self.file = None;
+
+        println!("upload complete; file={:?}; bytes={}; pauses={}", self.name, self.written, self.pauses);
+
+        let reply = format!("OK {}", self.written);
+        self.reply(&reply);
+        self.quit = true;
+    }
+
+    // Refuses the upload. A partially written file is left as it is.
+    fn fail(&mut self, line: &str) {
+        self.reply(line);
+        self.file = None;
+        self.buf.clear();
+        self.quit = true;
+    }
+
+    fn reply(&mut self, line: &str) {
+        self.out.extend(line.as_bytes());
+        self.out.push(b'\n');
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        if self.quit {
+            self.closed = true;
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        let mut interest = mio::EventSet::none();
+
+        if !self.out.is_empty() {
+            interest = interest | mio::EventSet::writable();
+        }
+
+        // Dropping readable interest is the backpressure: the event loop
+        // stops reporting the socket, and nothing more is read until the
+        // disk has caught up.
+        if !self.paused && !self.eof && !self.quit {
+            interest = interest | mio::EventSet::readable();
+        }
+
+        interest
+    }
+
+    fn reregister(&self, event_loop: &mut mio::EventLoop<Upload>) {
+        event_loop.reregister(&self.socket, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let dir = PathBuf::from(args.next().unwrap_or("uploads".to_string()));
+    let address: SocketAddr = args.next().unwrap_or("0.0.0.0:9400".to_string()).parse().unwrap();
+
+    fs::create_dir_all(&dir).unwrap();
+
+    let server = TcpListener::bind(&address).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    event_loop.register(&server, SERVER).unwrap();
+
+    let mut upload = Upload::new(server, dir);
+
+    println!("running upload server; addr={:?}; dir={:?}", address, upload.dir);
+    event_loop.run(&mut upload).unwrap();
+}
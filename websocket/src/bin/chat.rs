@@ -0,0 +1,368 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+extern crate websocket;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use websocket::frame::{self, Message, Opcode};
+use websocket::handshake;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+// The page served to browsers that request it over plain HTTP
+const PAGE: &str = include_str!("chat.html");
+
+// The most frames that can be waiting to be written to a client. A browser
+// that falls this far behind (a backgrounded tab, a slow network) is
+// disconnected rather than letting its queue grow without bound.
+const MAX_QUEUED: usize = 256;
+
+const MAX_TEXT: usize = 4 * 1_024;
+
+struct Chat {
+    acceptor: Acceptor<Factory<Client>>,
+    clients: Slab<Client>,
+}
+
+impl Chat {
+    fn new(acceptor: Acceptor<Factory<Client>>) -> Chat {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Chat {
+            acceptor: acceptor,
+            clients: slab,
+        }
+    }
+
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Chat>) {
+        let (_, res) = self.acceptor.accept(event_loop, &mut self.clients);
+
+        if let Err(e) = res {
+            println!("encountered error while accepting connection; err={:?}", e);
+            event_loop.shutdown();
+        }
+    }
+
+    fn client_ready(&mut self, event_loop: &mut mio::EventLoop<Chat>, token: mio::Token, events: mio::EventSet) {
+        let mut texts = vec![];
+        let was_open = self.clients[token].state == State::Open;
+
+        if events.is_readable() {
+            self.clients[token].read(&mut texts);
+        }
+
+        let name = self.clients[token].name.clone();
+
+        if !was_open && self.clients[token].state == State::Open {
+            self.broadcast(event_loop, &format!("* {} joined", name));
+        }
+
+        for text in texts {
+            self.broadcast(event_loop, &format!("{}: {}", name, text));
+        }
+
+        if events.is_writable() {
+            self.clients[token].write();
+        }
+
+        self.reregister(event_loop, token);
+    }
+
+    // Queues a text message for every connected client. The frame is
+    // encoded once and shared by all the queues it ends up in.
+    fn broadcast(&mut self, event_loop: &mut mio::EventLoop<Chat>, text: &str) {
+        println!("broadcast; msg={:?}", text);
+
+        let mut buf = vec![];
+        frame::encode(Opcode::Text, text.as_bytes(), &mut buf);
+
+        let buf = Rc::new(buf);
+        let mut tokens = vec![];
+
+        for client in self.clients.iter_mut() {
+            if client.state == State::Open {
+                client.queue(buf.clone());
+                tokens.push(client.token);
+            }
+        }
+
+        // Make sure every client is interested in writable events now that
+        // it has something to write.
+        for token in tokens {
+            self.reregister(event_loop, token);
+        }
+    }
+
+    // Reregisters a client, or removes it if it is done. A client that left
+    // after joining the chat is announced to the others.
+    fn reregister(&mut self, event_loop: &mut mio::EventLoop<Chat>, token: mio::Token) {
+        if self.clients[token].state != State::Closed {
+            let client = &self.clients[token];
+
+            event_loop.reregister(&client.socket, token, client.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+
+            return;
+        }
+
+        let client = self.clients.remove(token).unwrap();
+
+        if client.joined {
+            self.broadcast(event_loop, &format!("* {} left", client.name));
+        }
+    }
+}
+
+impl mio::Handler for Chat {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Chat>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+                self.accept(event_loop);
+            }
+            _ => {
+                // The client may have been removed while handling an event
+                // for another client earlier in this iteration.
+                if self.clients.contains(token) {
+                    self.client_ready(event_loop, token, events);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    // Waiting for an HTTP request, either for the page or to upgrade
+    Http,
+    // A WebSocket participating in the chat
+    Open,
+    // Done, the connection is closed once the queue is written
+    Closing,
+    Closed,
+}
+
+#[derive(Debug)]
+struct Client {
+    socket: TcpStream,
+    token: mio::Token,
+    state: State,
+    name: String,
+    // Whether the client was ever announced as joining
+    joined: bool,
+    // Bytes read but not yet decoded
+    buf: Vec<u8>,
+    reader: frame::Reader,
+    // Frames waiting to be written, and how much of the first one has been
+    // written already.
+    out: VecDeque<Rc<Vec<u8>>>,
+    pos: usize,
+}
+
+impl acceptor::Connection for Client {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Client {
+    fn new(socket: TcpStream, token: mio::Token) -> Client {
+        Client {
+            socket: socket,
+            token: token,
+            state: State::Http,
+            name: format!("guest{}", token.as_usize()),
+            joined: false,
+            buf: vec![],
+            reader: frame::Reader::new(),
+            out: VecDeque::new(),
+            pos: 0,
+        }
+    }
+
+    // Text messages received from the client are pushed onto `texts`.
+    fn read(&mut self, texts: &mut Vec<String>) {
+        let mut buf = [0; 4096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.state = State::Closed;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    if self.state != State::Closing {
+                        self.buf.extend(&buf[..n]);
+                        self.process(texts);
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.state = State::Closed;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn process(&mut self, texts: &mut Vec<String>) {
+        if self.state == State::Http {
+            let request = match handshake::parse(&self.buf) {
+                Ok(Some((request, len))) => {
+                    self.buf.drain(..len);
+                    request
+                }
+                Ok(None) => return,
+                Err(_) => return self.respond(handshake::bad_request()),
+            };
+
+            println!("received request; method={}; path={}; upgrade={}", request.method, request.path, request.is_upgrade());
+
+            // The page and the WebSocket are served on the same port, one
+            // connection at a time is either a browser fetching the page or
+            // the page's script connecting back.
+            if request.is_upgrade() {
+                match handshake::accept(&request) {
+                    Some(response) => {
+                        self.queue(Rc::new(response));
+                        self.state = State::Open;
+                        self.joined = true;
+                    }
+                    None => return self.respond(handshake::bad_request()),
+                }
+            } else if request.method == "GET" && request.path == "/" {
+                return self.respond(page());
+            } else {
+                return self.respond(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec());
+            }
+        }
+
+        while self.state == State::Open {
+            let frame = match frame::decode(&mut self.buf) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return,
+                Err(e) => return self.fail(e.close_code()),
+            };
+
+            let msg = match self.reader.push(frame) {
+                Ok(Some(msg)) => msg,
+                Ok(None) => continue,
+                Err(e) => return self.fail(e.close_code()),
+            };
+
+            match msg {
+                Message::Text(ref text) if text.len() > MAX_TEXT => self.fail(1009),
+                Message::Text(text) => texts.push(text),
+                // 1003: the chat only deals in text
+                Message::Binary(..) => self.fail(1003),
+                Message::Ping(data) => {
+                    let mut buf = vec![];
+                    frame::encode(Opcode::Pong, &data, &mut buf);
+                    self.queue(Rc::new(buf));
+                }
+                Message::Pong(..) => {}
+                Message::Close(code) => self.fail(code.unwrap_or(1000)),
+            }
+        }
+    }
+
+    // Sends a plain HTTP response, then closes the connection
+    fn respond(&mut self, response: Vec<u8>) {
+        self.queue(Rc::new(response));
+        self.state = State::Closing;
+    }
+
+    // Sends a close frame, then closes the connection
+    fn fail(&mut self, code: u16) {
+        let mut buf = vec![];
+        frame::encode_close(Some(code), &mut buf);
+
+        self.queue(Rc::new(buf));
+        self.state = State::Closing;
+    }
+
+    fn queue(&mut self, buf: Rc<Vec<u8>>) {
+        if self.out.len() >= MAX_QUEUED {
+            println!("client is not keeping up, disconnecting; name={}", self.name);
+            self.state = State::Closed;
+            return;
+        }
+
+        self.out.push_back(buf);
+    }
+
+    fn write(&mut self) {
+        while let Some(buf) = self.out.front().cloned() {
+            match self.socket.try_write(&buf[self.pos..]) {
+                Ok(Some(n)) => {
+                    self.pos += n;
+
+                    if self.pos == buf.len() {
+                        self.out.pop_front();
+                        self.pos = 0;
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.state = State::Closed;
+                    return;
+                }
+            }
+        }
+
+        if self.state == State::Closing {
+            self.state = State::Closed;
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+fn page() -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/html; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        PAGE.len()).into_bytes();
+
+    response.extend(PAGE.as_bytes());
+    response
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:9002".to_string())
+        .parse().unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, Client::new as Factory<Client>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mut chat = Chat::new(acceptor);
+
+    println!("running WebSocket chat server; open http://{}/ in a browser", address);
+    event_loop.run(&mut chat).unwrap();
+}
@@ -0,0 +1,261 @@
+extern crate acceptor;
+extern crate mio;
+extern crate bytes;
+extern crate websocket;
+
+use acceptor::{Acceptor, Factory};
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use mio::util::Slab;
+use websocket::frame::{self, Message, Opcode};
+use websocket::handshake;
+use std::net::SocketAddr;
+use std::env;
+
+const SERVER: mio::Token = mio::Token(0);
+
+struct Echo {
+    acceptor: Acceptor<Factory<Connection>>,
+    connections: Slab<Connection>,
+}
+
+impl Echo {
+    fn new(acceptor: Acceptor<Factory<Connection>>) -> Echo {
+        // Token `0` is reserved for the server socket. Tokens 1+ are used for
+        // client connections.
+        let slab = Slab::new_starting_at(mio::Token(1), 1024);
+
+        Echo {
+            acceptor: acceptor,
+            connections: slab,
+        }
+    }
+}
+
+impl mio::Handler for Echo {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Echo>, token: mio::Token, events: mio::EventSet) {
+        match token {
+            SERVER => {
+                assert!(events.is_readable());
+
+                let (tokens, res) = self.acceptor.accept(event_loop, &mut self.connections);
+
+                for token in tokens {
+                    println!("accepted a new client socket; token={:?}", token);
+                }
+
+                if let Err(e) = res {
+                    println!("encountered error while accepting connection; err={:?}", e);
+                    event_loop.shutdown();
+                }
+            }
+            _ => {
+                self.connections[token].ready(event_loop, events);
+
+                if self.connections[token].state == State::Closed {
+                    let _ = self.connections.remove(token);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    // Waiting for the HTTP upgrade request
+    Handshake,
+    // Exchanging frames
+    Open,
+    // A close frame (or an HTTP error) has been queued, the connection is
+    // closed once it is written. Anything else the client sends is ignored.
+    Closing,
+    Closed,
+}
+
+#[derive(Debug)]
+struct Connection {
+    socket: TcpStream,
+    token: mio::Token,
+    state: State,
+    // Bytes read but not yet decoded
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    reader: frame::Reader,
+}
+
+impl acceptor::Connection for Connection {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+}
+
+impl Connection {
+    fn new(socket: TcpStream, token: mio::Token) -> Connection {
+        Connection {
+            socket: socket,
+            token: token,
+            state: State::Handshake,
+            buf: vec![],
+            out: vec![],
+            reader: frame::Reader::new(),
+        }
+    }
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Echo>, events: mio::EventSet) {
+        if events.is_readable() {
+            self.read();
+        }
+
+        if events.is_writable() || !self.out.is_empty() {
+            self.write();
+        }
+
+        if self.state != State::Closed {
+            event_loop.reregister(&self.socket, self.token, self.interest(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                .unwrap();
+        }
+    }
+
+    fn read(&mut self) {
+        let mut buf = [0; 4096];
+
+        // The socket is registered as edge triggered, drain it
+        loop {
+            match self.socket.try_read(&mut buf) {
+                Ok(Some(0)) => {
+                    self.state = State::Closed;
+                    return;
+                }
+                Ok(Some(n)) => {
+                    if self.state != State::Closing {
+                        self.buf.extend(&buf[..n]);
+                        self.process();
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to read; err={:?}", e);
+                    self.state = State::Closed;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn process(&mut self) {
+        if self.state == State::Handshake {
+            match handshake::parse(&self.buf) {
+                Ok(Some((request, len))) => {
+                    self.buf.drain(..len);
+
+                    match handshake::accept(&request) {
+                        Some(response) => {
+                            println!("upgraded connection; path={}", request.path);
+                            self.out.extend(response);
+                            self.state = State::Open;
+                        }
+                        None => {
+                            self.out.extend(handshake::bad_request());
+                            self.state = State::Closing;
+                        }
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("invalid handshake; err={}", e);
+                    self.out.extend(handshake::bad_request());
+                    self.state = State::Closing;
+                }
+            }
+        }
+
+        // The client may send its first frames right behind the handshake,
+        // so decoding continues with whatever is left in the buffer.
+        while self.state == State::Open {
+            let frame = match frame::decode(&mut self.buf) {
+                Ok(Some(frame)) => frame,
+                // Wait for the rest of the frame
+                Ok(None) => return,
+                Err(e) => return self.fail(e),
+            };
+
+            match self.reader.push(frame) {
+                Ok(Some(msg)) => self.handle(msg),
+                // A fragment of a larger message
+                Ok(None) => {}
+                Err(e) => return self.fail(e),
+            }
+        }
+    }
+
+    // Sends a close frame with the status code matching the error, after
+    // which the connection is closed.
+    fn fail(&mut self, err: frame::Error) {
+        println!("failing connection; err={:?}", err);
+        frame::encode_close(Some(err.close_code()), &mut self.out);
+        self.state = State::Closing;
+    }
+
+    fn handle(&mut self, msg: Message) {
+        match msg {
+            Message::Text(text) => frame::encode(Opcode::Text, text.as_bytes(), &mut self.out),
+            Message::Binary(data) => frame::encode(Opcode::Binary, &data, &mut self.out),
+            Message::Ping(data) => frame::encode(Opcode::Pong, &data, &mut self.out),
+            Message::Pong(..) => {}
+            Message::Close(code) => {
+                // Reply with a close frame echoing the status code, which
+                // completes the closing handshake.
+                println!("client closed the connection; code={:?}", code);
+                frame::encode_close(code, &mut self.out);
+                self.state = State::Closing;
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        while !self.out.is_empty() {
+            match self.socket.try_write(&self.out) {
+                Ok(Some(n)) => {
+                    self.out.drain(..n);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    println!("got an error trying to write; err={:?}", e);
+                    self.state = State::Closed;
+                    return;
+                }
+            }
+        }
+
+        if self.state == State::Closing {
+            self.state = State::Closed;
+        }
+    }
+
+    fn interest(&self) -> mio::EventSet {
+        if self.out.is_empty() {
+            mio::EventSet::readable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+}
+
+fn main() {
+    let address: SocketAddr = env::args().nth(1)
+        .unwrap_or("0.0.0.0:9001".to_string())
+        .parse().unwrap();
+
+    let acceptor = Acceptor::bind(&address, SERVER, Connection::new as Factory<Connection>).unwrap();
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    acceptor.register(&mut event_loop).unwrap();
+
+    let mut echo = Echo::new(acceptor);
+
+    println!("running WebSocket echo server; addr={:?}", address);
+    event_loop.run(&mut echo).unwrap();
+}
@@ -0,0 +1,411 @@
+// WebSocket framing. Every frame starts with a 2 byte header:
+//
+//  0                   1
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5
+// +-+-+-+-+-------+-+-------------+
+// |F|R|R|R| opcode|M| payload len |
+// |I|S|S|S|  (4)  |A|     (7)     |
+// |N|V|V|V|       |S|             |
+// | |1|2|3|       |K|             |
+// +-+-+-+-+-------+-+-------------+
+//
+// followed by a 16 or 64 bit extended length when `payload len` is 126 or
+// 127, a 4 byte masking key when `MASK` is set, and the payload.
+
+// Messages larger than this, whether in one frame or many, are refused
+pub const MAX_MESSAGE: usize = 1_024 * 1_024;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(op: u8) -> Option<Opcode> {
+        match op {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(&self) -> u8 {
+        match *self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    fn is_control(&self) -> bool {
+        self.as_u8() & 0x8 != 0
+    }
+}
+
+#[derive(Debug)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    // Already unmasked
+    pub payload: Vec<u8>,
+}
+
+// What the application sees once fragmented messages have been put back
+// together.
+#[derive(Debug)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    // The status code, if the peer sent one
+    Close(Option<u16>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    Protocol(&'static str),
+    InvalidUtf8,
+    TooLarge,
+}
+
+impl Error {
+    // The status code to send in the close frame when failing the
+    // connection because of this error.
+    pub fn close_code(&self) -> u16 {
+        match *self {
+            Error::Protocol(..) => 1002,
+            Error::InvalidUtf8 => 1007,
+            Error::TooLarge => 1009,
+        }
+    }
+}
+
+// Decodes a frame sent by a client from the start of `buf`, removing its
+// bytes. Returns `None` if the frame hasn't been fully received yet, in
+// which case `buf` is left alone.
+pub fn decode(buf: &mut Vec<u8>) -> Result<Option<Frame>, Error> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+
+    if buf[0] & 0x70 != 0 {
+        // No extensions are negotiated, so the reserved bits must be unset
+        return Err(Error::Protocol("reserved bits set"));
+    }
+
+    let fin = buf[0] & 0x80 != 0;
+
+    let opcode = match Opcode::from_u8(buf[0] & 0x0F) {
+        Some(opcode) => opcode,
+        None => return Err(Error::Protocol("unknown opcode")),
+    };
+
+    // Clients must mask every frame they send
+    if buf[1] & 0x80 == 0 {
+        return Err(Error::Protocol("client frame is not masked"));
+    }
+
+    let (len, mut pos) = match buf[1] & 0x7F {
+        126 => {
+            if buf.len() < 4 {
+                return Ok(None);
+            }
+
+            (read_uint(&buf[2..4]), 4)
+        }
+        127 => {
+            if buf.len() < 10 {
+                return Ok(None);
+            }
+
+            (read_uint(&buf[2..10]), 10)
+        }
+        len => (len as u64, 2),
+    };
+
+    // Control frames may be sent in between the fragments of a message, so
+    // they can't be fragmented themselves.
+    if opcode.is_control() && (!fin || len > 125) {
+        return Err(Error::Protocol("invalid control frame"));
+    }
+
+    if len > MAX_MESSAGE as u64 {
+        return Err(Error::TooLarge);
+    }
+
+    let len = len as usize;
+
+    if buf.len() < pos + 4 + len {
+        return Ok(None);
+    }
+
+    let mask = [buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]];
+    pos += 4;
+
+    let payload = buf[pos..pos + len].iter()
+        .enumerate()
+        .map(|(i, b)| b ^ mask[i % 4])
+        .collect();
+
+    buf.drain(..pos + len);
+
+    Ok(Some(Frame {
+        fin: fin,
+        opcode: opcode,
+        payload: payload,
+    }))
+}
+
+// Encodes a single, unfragmented frame. Frames sent by a server are not
+// masked.
+pub fn encode(opcode: Opcode, payload: &[u8], dst: &mut Vec<u8>) {
+    dst.push(0x80 | opcode.as_u8());
+
+    let len = payload.len();
+
+    if len < 126 {
+        dst.push(len as u8);
+    } else if len <= 0xFFFF {
+        dst.push(126);
+        dst.extend(&[(len >> 8) as u8, len as u8]);
+    } else {
+        dst.push(127);
+
+        for i in (0..8).rev() {
+            dst.push((len as u64 >> (i * 8)) as u8);
+        }
+    }
+
+    dst.extend(payload);
+}
+
+// Encodes a close frame. The payload, if any, starts with the status code.
+pub fn encode_close(code: Option<u16>, dst: &mut Vec<u8>) {
+    match code {
+        Some(code) => encode(Opcode::Close, &[(code >> 8) as u8, code as u8], dst),
+        None => encode(Opcode::Close, &[], dst),
+    }
+}
+
+// Puts fragmented messages back together. Control frames can arrive in
+// between the fragments and are passed through as they come.
+#[derive(Debug, Clone)]
+pub struct Reader {
+    // The opcode and data of the fragmented message received so far
+    partial: Option<(Opcode, Vec<u8>)>,
+}
+
+impl Default for Reader {
+    fn default() -> Reader {
+        Reader::new()
+    }
+}
+
+impl Reader {
+    pub fn new() -> Reader {
+        Reader { partial: None }
+    }
+
+    // Feeds a frame to the reader. Returns a message once one is complete.
+    pub fn push(&mut self, frame: Frame) -> Result<Option<Message>, Error> {
+        match frame.opcode {
+            Opcode::Ping => Ok(Some(Message::Ping(frame.payload))),
+            Opcode::Pong => Ok(Some(Message::Pong(frame.payload))),
+            Opcode::Close => {
+                match frame.payload.len() {
+                    0 => Ok(Some(Message::Close(None))),
+                    1 => Err(Error::Protocol("invalid close frame")),
+                    _ => Ok(Some(Message::Close(Some(read_uint(&frame.payload[..2]) as u16)))),
+                }
+            }
+            Opcode::Text | Opcode::Binary => {
+                if self.partial.is_some() {
+                    return Err(Error::Protocol("expected a continuation frame"));
+                }
+
+                if frame.fin {
+                    return message(frame.opcode, frame.payload).map(Some);
+                }
+
+                self.partial = Some((frame.opcode, frame.payload));
+                Ok(None)
+            }
+            Opcode::Continuation => {
+                let (opcode, mut data) = match self.partial.take() {
+                    Some(partial) => partial,
+                    None => return Err(Error::Protocol("unexpected continuation frame")),
+                };
+
+                if data.len() + frame.payload.len() > MAX_MESSAGE {
+                    return Err(Error::TooLarge);
+                }
+
+                data.extend(frame.payload);
+
+                if frame.fin {
+                    return message(opcode, data).map(Some);
+                }
+
+                self.partial = Some((opcode, data));
+                Ok(None)
+            }
+        }
+    }
+}
+
+fn message(opcode: Opcode, data: Vec<u8>) -> Result<Message, Error> {
+    match opcode {
+        Opcode::Text => String::from_utf8(data).map(Message::Text).map_err(|_| Error::InvalidUtf8),
+        Opcode::Binary => Ok(Message::Binary(data)),
+        _ => unreachable!(),
+    }
+}
+
+// Reads a big endian unsigned integer
+fn read_uint(buf: &[u8]) -> u64 {
+    buf.iter().fold(0, |n, &b| n << 8 | b as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A frame as a client sends it, masked
+    fn client_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![];
+
+        encode(Opcode::Text, payload, &mut frame);
+        frame[0] = first;
+        frame[1] |= 0x80;
+
+        let pos = frame.len() - payload.len();
+        frame.splice(pos..pos, mask.iter().cloned());
+
+        for (i, b) in frame[pos + 4..].iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+
+        frame
+    }
+
+    fn frame(fin: bool, opcode: Opcode, payload: &[u8]) -> Frame {
+        Frame {
+            fin: fin,
+            opcode: opcode,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn decodes_frames() {
+        let long = vec![b'a'; 300];
+        let mut buf = client_frame(0x81, b"hello");
+        buf.extend(client_frame(0x02, &long));
+
+        let first = decode(&mut buf).unwrap().unwrap();
+        assert!(first.fin);
+        assert_eq!(first.opcode, Opcode::Text);
+        assert_eq!(first.payload, b"hello");
+
+        let second = decode(&mut buf).unwrap().unwrap();
+        assert!(!second.fin);
+        assert_eq!(second.opcode, Opcode::Binary);
+        assert_eq!(second.payload, long);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn waits_for_truncated_frames() {
+        let frame = client_frame(0x82, &[7; 300]);
+
+        for len in 0..frame.len() {
+            let mut buf = frame[..len].to_vec();
+
+            assert!(decode(&mut buf).unwrap().is_none());
+            assert_eq!(buf, &frame[..len]);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        let reserved = Err(Error::Protocol("reserved bits set"));
+        let opcode = Err(Error::Protocol("unknown opcode"));
+        let unmasked = Err(Error::Protocol("client frame is not masked"));
+        let control = Err(Error::Protocol("invalid control frame"));
+
+        assert_eq!(decode(&mut client_frame(0xc1, b"a")).map(|f| f.is_some()), reserved);
+        assert_eq!(decode(&mut client_frame(0x83, b"a")).map(|f| f.is_some()), opcode);
+        assert_eq!(decode(&mut vec![0x81, 0x01, b'a']).map(|f| f.is_some()), unmasked);
+        assert_eq!(decode(&mut client_frame(0x09, b"a")).map(|f| f.is_some()), control);
+        assert_eq!(decode(&mut client_frame(0x89, &[0; 126])).map(|f| f.is_some()), control);
+    }
+
+    #[test]
+    fn rejects_oversize_lengths() {
+        // Only the header has to arrive for the length to be refused
+        let mut buf = vec![0x82, 0xff, 0x80, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(decode(&mut buf).map(|f| f.is_some()), Err(Error::TooLarge));
+
+        let len = MAX_MESSAGE as u64 + 1;
+        let mut buf = vec![0x82, 0xff];
+        buf.extend((0..8).rev().map(|i| (len >> (i * 8)) as u8));
+        assert_eq!(decode(&mut buf).map(|f| f.is_some()), Err(Error::TooLarge));
+    }
+
+    #[test]
+    fn reassembles_fragments() {
+        let mut reader = Reader::new();
+
+        assert!(reader.push(frame(false, Opcode::Text, b"hel")).unwrap().is_none());
+
+        match reader.push(frame(true, Opcode::Ping, b"ping")).unwrap() {
+            Some(Message::Ping(payload)) => assert_eq!(payload, b"ping"),
+            res => panic!("unexpected result; res={:?}", res),
+        }
+
+        match reader.push(frame(true, Opcode::Continuation, b"lo")).unwrap() {
+            Some(Message::Text(text)) => assert_eq!(text, "hello"),
+            res => panic!("unexpected result; res={:?}", res),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_fragments() {
+        let mut reader = Reader::new();
+        let res = reader.push(frame(true, Opcode::Continuation, b"a"));
+        assert_eq!(res.map(|m| m.is_some()), Err(Error::Protocol("unexpected continuation frame")));
+
+        let mut reader = Reader::new();
+        reader.push(frame(false, Opcode::Text, b"a")).unwrap();
+        let res = reader.push(frame(true, Opcode::Text, b"b"));
+        assert_eq!(res.map(|m| m.is_some()), Err(Error::Protocol("expected a continuation frame")));
+
+        let mut reader = Reader::new();
+        let res = reader.push(frame(true, Opcode::Text, &[0xff]));
+        assert_eq!(res.map(|m| m.is_some()), Err(Error::InvalidUtf8));
+    }
+
+    #[test]
+    fn rejects_oversize_messages() {
+        let mut reader = Reader::new();
+        let half = vec![0; MAX_MESSAGE / 2 + 1];
+
+        reader.push(frame(false, Opcode::Binary, &half)).unwrap();
+        let res = reader.push(frame(true, Opcode::Continuation, &half));
+        assert_eq!(res.map(|m| m.is_some()), Err(Error::TooLarge));
+    }
+}
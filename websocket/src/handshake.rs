@@ -0,0 +1,122 @@
+use rustc_serialize::base64::{self, ToBase64};
+use sha1::Sha1;
+use std::str;
+
+// Requests with a larger head are rejected
+pub const MAX_REQUEST: usize = 8 * 1_024;
+
+// Appended to the client's key before hashing, as required by the RFC
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// The head of an HTTP request. The body, if any, is not needed by anything
+// here.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    // Returns the value of a header. Header names are case insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|&(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &value[..])
+    }
+
+    // Whether the client is asking to switch to the WebSocket protocol. A
+    // plain HTTP request is not an error, a server may serve both on the
+    // same port.
+    pub fn is_upgrade(&self) -> bool {
+        let has_token = |name, token: &str| {
+            self.header(name)
+                .map(|value| value.split(',').any(|v| v.trim().eq_ignore_ascii_case(token)))
+                .unwrap_or(false)
+        };
+
+        has_token("Upgrade", "websocket") && has_token("Connection", "upgrade")
+    }
+}
+
+// Parses a request head from the start of `buf`. Returns the request and
+// the number of bytes it used, or `None` if the head isn't complete yet.
+pub fn parse(buf: &[u8]) -> Result<Option<(Request, usize)>, &'static str> {
+    let end = match buf.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => pos + 4,
+        None if buf.len() > MAX_REQUEST => return Err("request too large"),
+        None => return Ok(None),
+    };
+
+    let head = match str::from_utf8(&buf[..end]) {
+        Ok(head) => head,
+        Err(_) => return Err("request is not valid UTF-8"),
+    };
+
+    let mut lines = head.split("\r\n");
+
+    // GET /path HTTP/1.1
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+
+    let (method, path, version) = match (request_line.next(), request_line.next(), request_line.next()) {
+        (Some(method), Some(path), Some(version)) => (method, path, version),
+        _ => return Err("malformed request line"),
+    };
+
+    if !version.starts_with("HTTP/") {
+        return Err("malformed request line");
+    }
+
+    let mut headers = vec![];
+
+    for line in lines.filter(|line| !line.is_empty()) {
+        match line.find(':') {
+            Some(pos) => headers.push((line[..pos].trim().to_string(), line[pos + 1..].trim().to_string())),
+            None => return Err("malformed header"),
+        }
+    }
+
+    let request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        headers: headers,
+    };
+
+    Ok(Some((request, end)))
+}
+
+// Builds the `101 Switching Protocols` response completing the handshake,
+// or `None` if the request is not a valid WebSocket upgrade.
+pub fn accept(request: &Request) -> Option<Vec<u8>> {
+    if request.method != "GET" || !request.is_upgrade() || request.header("Sec-WebSocket-Version") != Some("13") {
+        return None;
+    }
+
+    let key = request.header("Sec-WebSocket-Key")?;
+
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key));
+
+    Some(response.into_bytes())
+}
+
+// The response to anything that can't be handled
+pub fn bad_request() -> Vec<u8> {
+    b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec()
+}
+
+// Computes the `Sec-WebSocket-Accept` value for a client's key: the base64
+// encoded SHA-1 of the key followed by the protocol's GUID. It proves to
+// the client that the server understood the handshake.
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+
+    sha1.digest().bytes().to_base64(base64::STANDARD)
+}
@@ -0,0 +1,10 @@
+// The parts of the WebSocket protocol (RFC 6455) shared by the servers in
+// `src/bin`: the HTTP upgrade handshake and the framing that follows it.
+// Neither does any I/O, they work on buffers filled and drained by the
+// event loop.
+
+extern crate rustc_serialize;
+extern crate sha1;
+
+pub mod frame;
+pub mod handshake;
@@ -0,0 +1,241 @@
+extern crate mio;
+
+mod referral;
+
+use mio::{TryRead, TryWrite};
+use mio::tcp::*;
+use referral::Server;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::{env, process};
+
+const CLIENT: mio::Token = mio::Token(0);
+
+// Where queries start: IANA knows which registry is in charge of every
+// TLD and every IP range, and answers with a referral to it
+const ROOT: &str = "whois.iana.org";
+
+// How long a server has to answer, from the connect to the end of its
+// response
+const TIMEOUT_MS: u64 = 10_000;
+
+// Referrals are followed this many times at most
+const MAX_HOPS: usize = 5;
+
+// Responses are cut off past this size
+const MAX_RESPONSE: usize = 1_024 * 1_024;
+
+// The query in flight, to one server. Each hop uses a new socket,
+// registered with the same token: the previous one is closed before the
+// next one is opened.
+struct Hop {
+    server: Server,
+    socket: TcpStream,
+    // The query, then what is left of it to write
+    query: Vec<u8>,
+    response: Vec<u8>,
+    timeout: mio::Timeout,
+}
+
+// Asks the root server, then whichever server it refers to, and so on,
+// printing each response. The servers close the connection once they are
+// done answering, which is how the end of a response is known.
+struct Whois {
+    query: String,
+    hop: Option<Hop>,
+    // The servers asked so far, to not go round in circles
+    visited: Vec<Server>,
+    failed: bool,
+}
+
+impl Whois {
+    fn new(query: String) -> Whois {
+        Whois {
+            query: query,
+            hop: None,
+            visited: vec![],
+            failed: false,
+        }
+    }
+
+    fn connect(&mut self, event_loop: &mut mio::EventLoop<Whois>, server: Server) {
+        self.visited.push(server.clone());
+
+        // Resolving blocks, but there is nothing else to do meanwhile
+        let addr: SocketAddr = match (&server.host[..], server.port).to_socket_addrs().ok().and_then(|mut addrs| addrs.next()) {
+            Some(addr) => addr,
+            None => return self.fail(event_loop, &server, "failed to resolve host"),
+        };
+
+        let socket = match TcpStream::connect(&addr) {
+            Ok(socket) => socket,
+            Err(e) => return self.fail(event_loop, &server, &e.to_string()),
+        };
+
+        println!("% querying {}:{} ({})", server.host, server.port, addr);
+
+        // The socket becomes writable once connected, and the query is
+        // written then
+        event_loop.register_opt(&socket, CLIENT, mio::EventSet::writable(), mio::PollOpt::edge() | mio::PollOpt::oneshot())
+            .unwrap();
+
+        self.hop = Some(Hop {
+            server: server,
+            socket: socket,
+            query: format!("{}\r\n", self.query).into_bytes(),
+            response: vec![],
+            timeout: event_loop.timeout_ms((), TIMEOUT_MS).unwrap(),
+        });
+    }
+
+    fn fail(&mut self, event_loop: &mut mio::EventLoop<Whois>, server: &Server, err: &str) {
+        println!("% failed to query {}:{}; err={}", server.host, server.port, err);
+        self.failed = true;
+        event_loop.shutdown();
+    }
+
+    // The server closed the connection, the response is complete
+    fn done(&mut self, event_loop: &mut mio::EventLoop<Whois>) {
+        let hop = self.hop.take().unwrap();
+        event_loop.clear_timeout(hop.timeout);
+
+        // Most registries answer in ASCII, some in UTF-8, a few in Latin-1
+        let response = String::from_utf8_lossy(&hop.response);
+
+        println!("{}", response.trim_end());
+        println!();
+
+        let next = match referral::referral(&response) {
+            Some(next) => next,
+            None => return event_loop.shutdown(),
+        };
+
+        // A server refers to itself when it has the answer
+        if self.visited.contains(&next) {
+            return event_loop.shutdown();
+        }
+
+        if self.visited.len() == MAX_HOPS {
+            println!("% not following referral to {}, too many hops", next.host);
+            return event_loop.shutdown();
+        }
+
+        self.connect(event_loop, next);
+    }
+}
+
+impl mio::Handler for Whois {
+    type Timeout = ();
+    type Message = ();
+
+    fn ready(&mut self, event_loop: &mut mio::EventLoop<Whois>, _: mio::Token, events: mio::EventSet) {
+        let mut err = None;
+        let mut eof = false;
+
+        {
+            let hop = match self.hop.as_mut() {
+                Some(hop) => hop,
+                None => return,
+            };
+
+            if events.is_error() || events.is_hup() && hop.response.is_empty() && !hop.query.is_empty() {
+                err = Some(hop.socket.take_socket_error().err().map(|e| e.to_string()).unwrap_or_else(|| "connection failed".to_string()));
+            }
+
+            if err.is_none() && events.is_writable() {
+                while !hop.query.is_empty() {
+                    match hop.socket.try_write(&hop.query) {
+                        Ok(Some(n)) => {
+                            hop.query.drain(..n);
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            err = Some(e.to_string());
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if err.is_none() && events.is_readable() {
+                let mut buf = [0; 4_096];
+
+                // The socket is registered as edge triggered, drain it
+                loop {
+                    match hop.socket.try_read(&mut buf) {
+                        Ok(Some(0)) => {
+                            eof = true;
+                            break;
+                        }
+                        Ok(Some(n)) => {
+                            let len = n.min(MAX_RESPONSE - hop.response.len());
+                            hop.response.extend_from_slice(&buf[..len]);
+
+                            if hop.response.len() == MAX_RESPONSE {
+                                eof = true;
+                                break;
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            err = Some(e.to_string());
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if err.is_none() && !eof {
+                // Once the query is written, only reading is left
+                let interest = if hop.query.is_empty() {
+                    mio::EventSet::readable()
+                } else {
+                    mio::EventSet::writable()
+                };
+
+                event_loop.reregister(&hop.socket, CLIENT, interest, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+                    .unwrap();
+            }
+        }
+
+        if let Some(err) = err {
+            let hop = self.hop.take().unwrap();
+            event_loop.clear_timeout(hop.timeout);
+            return self.fail(event_loop, &hop.server, &err);
+        }
+
+        if eof {
+            self.done(event_loop);
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Whois>, _: ()) {
+        let hop = self.hop.take().unwrap();
+        self.fail(event_loop, &hop.server, "timed out");
+    }
+}
+
+fn usage() -> ! {
+    println!("usage: whois <query> [server[:port]]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let query = args.next().unwrap_or_else(|| usage());
+    let server = Server::parse(&args.next().unwrap_or_else(|| ROOT.to_string())).unwrap_or_else(|| usage());
+
+    let mut event_loop = mio::EventLoop::new().unwrap();
+    let mut whois = Whois::new(query);
+
+    whois.connect(&mut event_loop, server);
+
+    // Connecting may have failed already
+    if whois.hop.is_some() {
+        event_loop.run(&mut whois).unwrap();
+    }
+
+    if whois.failed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,69 @@
+// Finds where a whois response says to ask next. Whois (RFC 3912) has
+// no structure to speak of, a response is text for humans, but the
+// registries that hand queries down to others say so in a few known
+// ways:
+//
+// refer:        whois.verisign-grs.com                (IANA)
+// Registrar WHOIS Server: whois.markmonitor.com       (thin registries)
+// ReferralServer:  whois://whois.ripe.net             (ARIN)
+//
+// This does no I/O.
+
+pub const DEFAULT_PORT: u16 = 43;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Server {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Server {
+    // Parses `host` or `host:port`
+    pub fn parse(s: &str) -> Option<Server> {
+        let mut parts = s.splitn(2, ':');
+        let host = parts.next().unwrap().trim().to_ascii_lowercase();
+
+        let port = match parts.next() {
+            Some(port) => port.parse().ok()?,
+            None => DEFAULT_PORT,
+        };
+
+        if host.is_empty() || !host.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.') {
+            return None;
+        }
+
+        Some(Server {
+            host: host,
+            port: port,
+        })
+    }
+}
+
+// The first referral in the response, if any
+pub fn referral(response: &str) -> Option<Server> {
+    for line in response.lines() {
+        let mut parts = line.splitn(2, ':');
+
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key.trim().to_ascii_lowercase(), value.trim()),
+            _ => continue,
+        };
+
+        let server = match &key[..] {
+            "refer" | "whois" | "registrar whois server" => value,
+            // Only whois referrals are followed, ARIN also refers to
+            // rwhois servers, which speak another protocol
+            "referralserver" if value.starts_with("whois://") => &value["whois://".len()..],
+            _ => continue,
+        };
+
+        // Some registrars write a URL where the host name should be
+        let server = server.trim_end_matches('/');
+
+        if let Some(server) = Server::parse(server) {
+            return Some(server);
+        }
+    }
+
+    None
+}